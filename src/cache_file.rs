@@ -0,0 +1,854 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use crate::conditionals::{CondExpr, ConditionalUse};
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::events::{self, EventSink, NoEvents, ScanProgress};
+use crate::pas_parse::{Section, UnitName};
+use crate::unit_cache::{self, Prefetched, SourceOptions, UnitCache, UnitFileInfo};
+use crate::uses_include::Includes;
+use crate::warnings::Warnings;
+
+pub const DEFAULT_CACHE_FILE_NAME: &str = ".fixdpr-cache";
+const FORMAT_HEADER: &str = "fixdpr-unit-cache 9";
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    size: u64,
+    mtime: u128,
+    name: String,
+    conditional_uses: Vec<ConditionalUse>,
+    interface_uses: usize,
+    name_from_stem: bool,
+    warnings: Vec<Diagnostic>,
+    /// Size and modification time of each file the unit pulled in through `{$I}`.
+    includes: Vec<(PathBuf, u64, u128)>,
+}
+
+impl CacheEntry {
+    /// Whether the unit and every include it read still have the recorded stamps.
+    fn is_current(&self, size: u64, mtime: u128) -> bool {
+        self.size == size
+            && self.mtime == mtime
+            && self.includes.iter().all(|(path, size, mtime)| {
+                file_stamp(path).is_ok_and(|stamp| stamp == (*size, *mtime))
+            })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CacheFile {
+    path: PathBuf,
+    entries: HashMap<PathBuf, CacheEntry>,
+    dpr_results: HashMap<PathBuf, (u64, u64)>,
+    unit_fingerprint: u64,
+    pub hits: usize,
+    pub misses: usize,
+    pub last_hits: usize,
+    pub last_misses: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct CacheVerifyReport {
+    pub checked: usize,
+    pub stale: Vec<PathBuf>,
+    pub drifted: Vec<PathBuf>,
+}
+
+impl CacheFile {
+    pub fn load(path: &Path, warnings: &mut Warnings) -> Self {
+        let mut cache = CacheFile {
+            path: path.to_path_buf(),
+            ..CacheFile::default()
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return cache,
+            Err(err) => {
+                warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!("failed to read cache file {}: {err}", path.display()),
+                    )
+                    .at(path),
+                );
+                return cache;
+            }
+        };
+        match parse_cache_file(&contents) {
+            Some((entries, dpr_results, last_hits, last_misses)) => {
+                cache.entries = entries;
+                cache.dpr_results = dpr_results;
+                cache.last_hits = last_hits;
+                cache.last_misses = last_misses;
+            }
+            None => warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::CacheInvalid,
+                    format!("ignoring outdated or corrupt cache file {}", path.display()),
+                )
+                .at(path),
+            ),
+        }
+        cache
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn unit_fingerprint(&self) -> u64 {
+        self.unit_fingerprint
+    }
+
+    /// Size and modification time of the entries a unit cache build with `source` would reuse.
+    pub fn unit_stamps(&self, source: SourceOptions) -> HashMap<PathBuf, (u64, u128)> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| !entry.name_from_stem || source.stem_fallback)
+            .map(|(path, entry)| (path.clone(), (entry.size, entry.mtime)))
+            .collect()
+    }
+
+    pub fn is_dpr_unchanged(&self, path: &Path, content_hash: u64, params_hash: u64) -> bool {
+        self.dpr_results.get(path) == Some(&(content_hash, params_hash))
+    }
+
+    pub fn record_dpr_unchanged(&mut self, path: &Path, content_hash: u64, params_hash: u64) {
+        self.dpr_results
+            .insert(path.to_path_buf(), (content_hash, params_hash));
+    }
+
+    pub fn verify(&self, sample: Option<usize>, source: SourceOptions) -> CacheVerifyReport {
+        let mut paths: Vec<&PathBuf> = self.entries.keys().collect();
+        paths.sort();
+        if let Some(sample) = sample {
+            if sample > 0 && sample < paths.len() {
+                let step = paths.len() / sample;
+                paths = paths.into_iter().step_by(step).take(sample).collect();
+            }
+        }
+
+        let mut report = CacheVerifyReport::default();
+        for path in paths {
+            let entry = &self.entries[path];
+            report.checked += 1;
+            match file_stamp(path) {
+                Ok((size, mtime)) if entry.is_current(size, mtime) => {}
+                _ => {
+                    report.stale.push(path.clone());
+                    continue;
+                }
+            }
+            let mut warnings = Warnings::new();
+            let matches = match unit_cache::load_unit_file_with(path, source, &mut warnings) {
+                Ok(Some(info)) => {
+                    info.name == entry.name && info.conditional_uses == entry.conditional_uses
+                }
+                _ => false,
+            };
+            if !matches {
+                report.drifted.push(path.clone());
+            }
+        }
+        report
+    }
+
+    pub fn build_unit_cache(
+        &mut self,
+        paths: &[PathBuf],
+        warnings: &mut Warnings,
+    ) -> io::Result<UnitCache> {
+        self.build_unit_cache_with_events(
+            paths,
+            SourceOptions::default(),
+            Includes::default(),
+            &Prefetched::default(),
+            warnings,
+            &NoEvents,
+        )
+    }
+
+    pub fn build_unit_cache_with_events(
+        &mut self,
+        paths: &[PathBuf],
+        source: SourceOptions,
+        includes: Includes,
+        prefetched: &Prefetched,
+        warnings: &mut Warnings,
+        events: &dyn EventSink,
+    ) -> io::Result<UnitCache> {
+        let mut cache = UnitCache {
+            source,
+            includes: Rc::new(includes),
+            ..UnitCache::default()
+        };
+        let mut entries = HashMap::new();
+        let mut forwarded = warnings.len();
+
+        for (index, path) in paths.iter().enumerate() {
+            events::forward_warnings(events, warnings, &mut forwarded);
+            if index > 0 && events::should_report(index, paths.len()) {
+                events.on_scan_progress(ScanProgress::ParsingUnits {
+                    done: index,
+                    total: paths.len(),
+                });
+            }
+            let canonical = cache.canonical.canonicalize(path);
+            if cache.by_path.contains_key(&canonical) || entries.contains_key(&canonical) {
+                continue;
+            }
+            let (size, mtime) = file_stamp(&canonical)?;
+            let entry = match self.entries.remove(&canonical) {
+                Some(entry)
+                    if entry.is_current(size, mtime)
+                        && (!entry.name_from_stem || source.stem_fallback) =>
+                {
+                    self.hits += 1;
+                    entry
+                }
+                _ => {
+                    self.misses += 1;
+                    let mut unit_warnings = Warnings::new();
+                    let (loaded, include_paths) = cache.includes.track(|| {
+                        unit_cache::load_unit_file_prefetched(
+                            &canonical,
+                            &cache,
+                            prefetched,
+                            &mut unit_warnings,
+                        )
+                    });
+                    let Some(info) = loaded? else {
+                        warnings.extend(unit_warnings);
+                        continue;
+                    };
+                    let includes = include_paths
+                        .into_iter()
+                        .filter_map(|path| {
+                            let (size, mtime) = file_stamp(&path).ok()?;
+                            Some((path, size, mtime))
+                        })
+                        .collect();
+                    CacheEntry {
+                        size,
+                        mtime,
+                        name: info.name,
+                        conditional_uses: info.conditional_uses,
+                        interface_uses: info.interface_uses,
+                        name_from_stem: info.name_from_stem,
+                        warnings: unit_warnings.into_vec(),
+                        includes,
+                    }
+                }
+            };
+            warnings.extend(entry.warnings.iter().cloned());
+            let uses = unit_cache::uses_refs(&entry.conditional_uses, entry.interface_uses);
+            unit_cache::insert_unit(
+                &mut cache,
+                canonical.clone(),
+                UnitFileInfo {
+                    name: entry.name.clone(),
+                    path: canonical.clone(),
+                    uses,
+                    conditional_uses: entry.conditional_uses.clone(),
+                    interface_uses: entry.interface_uses,
+                    name_from_stem: entry.name_from_stem,
+                },
+            );
+            entries.insert(canonical, entry);
+        }
+        events::forward_warnings(events, warnings, &mut forwarded);
+        if !paths.is_empty() {
+            events.on_scan_progress(ScanProgress::ParsingUnits {
+                done: paths.len(),
+                total: paths.len(),
+            });
+        }
+
+        let mut stamps: Vec<String> = entries
+            .iter()
+            .map(|(path, entry)| {
+                let mut stamp = format!("{}|{}|{}", path.display(), entry.size, entry.mtime);
+                for (include, size, mtime) in &entry.includes {
+                    stamp.push_str(&format!("|{}|{size}|{mtime}", include.display()));
+                }
+                stamp
+            })
+            .collect();
+        stamps.sort();
+        self.unit_fingerprint = content_hash(stamps.join("\n").as_bytes());
+
+        self.entries.retain(|path, _| path.is_file());
+        self.entries.extend(entries);
+        prefetched.clear();
+        Ok(cache)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut paths: Vec<&PathBuf> = self.entries.keys().collect();
+        paths.sort();
+
+        let mut out = String::new();
+        out.push_str(FORMAT_HEADER);
+        out.push('\n');
+        out.push_str(&format!("stats\t{}\t{}\n", self.hits, self.misses));
+        for path in paths {
+            let entry = &self.entries[path];
+            out.push_str(&format!(
+                "unit\t{}\t{}\t{}\t{}\t{}\n",
+                escape_field(&path.to_string_lossy()),
+                entry.size,
+                entry.mtime,
+                escape_field(&entry.name),
+                u8::from(entry.name_from_stem)
+            ));
+            for (include, size, mtime) in &entry.includes {
+                out.push_str(&format!(
+                    "inc\t{}\t{size}\t{mtime}\n",
+                    escape_field(&include.to_string_lossy())
+                ));
+            }
+            for (index, conditional_use) in entry.conditional_uses.iter().enumerate() {
+                let section = if index < entry.interface_uses {
+                    Section::Interface
+                } else {
+                    Section::Implementation
+                };
+                let in_path = match &conditional_use.in_path {
+                    Some(value) => format!("+{}", escape_field(value)),
+                    None => "-".to_string(),
+                };
+                out.push_str(&format!(
+                    "use\t{}\t{}\t{}\t{}\n",
+                    escape_field(conditional_use.unit_name.as_str()),
+                    in_path,
+                    section.label(),
+                    escape_field(&encode_condition(&conditional_use.condition))
+                ));
+            }
+            for warning in &entry.warnings {
+                let path = match &warning.path {
+                    Some(path) => format!("+{}", escape_field(&path.to_string_lossy())),
+                    None => "-".to_string(),
+                };
+                let offset = match warning.offset {
+                    Some(offset) => offset.to_string(),
+                    None => "-".to_string(),
+                };
+                let referenced_by = match &warning.referenced_by {
+                    Some(path) => format!("+{}", escape_field(&path.to_string_lossy())),
+                    None => "-".to_string(),
+                };
+                out.push_str(&format!(
+                    "warn\t{}\t{path}\t{offset}\t{referenced_by}\t{}\n",
+                    warning.code,
+                    escape_field(&warning.message)
+                ));
+            }
+        }
+        let mut dpr_paths: Vec<&PathBuf> = self.dpr_results.keys().collect();
+        dpr_paths.sort();
+        for path in dpr_paths {
+            let (content, params) = self.dpr_results[path];
+            out.push_str(&format!(
+                "dpr\t{}\t{content}\t{params}\n",
+                escape_field(&path.to_string_lossy())
+            ));
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, out)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+pub(crate) fn file_stamp(path: &Path) -> io::Result<(u64, u128)> {
+    let metadata = fs::metadata(path)?;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    Ok((metadata.len(), mtime))
+}
+
+type ParsedCacheFile = (
+    HashMap<PathBuf, CacheEntry>,
+    HashMap<PathBuf, (u64, u64)>,
+    usize,
+    usize,
+);
+
+fn parse_cache_file(contents: &str) -> Option<ParsedCacheFile> {
+    let mut lines = contents.lines();
+    if lines.next()? != FORMAT_HEADER {
+        return None;
+    }
+    let (hits, misses) = match lines.next()?.split('\t').collect::<Vec<_>>().as_slice() {
+        ["stats", hits, misses] => (hits.parse().ok()?, misses.parse().ok()?),
+        _ => return None,
+    };
+
+    let mut entries = HashMap::new();
+    let mut dpr_results = HashMap::new();
+    let mut current: Option<(PathBuf, CacheEntry)> = None;
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["unit", path, size, mtime, name, name_from_stem] => {
+                if let Some((path, entry)) = current.take() {
+                    entries.insert(path, entry);
+                }
+                current = Some((
+                    PathBuf::from(unescape_field(path)?),
+                    CacheEntry {
+                        size: size.parse().ok()?,
+                        mtime: mtime.parse().ok()?,
+                        name: unescape_field(name)?,
+                        conditional_uses: Vec::new(),
+                        interface_uses: 0,
+                        name_from_stem: match *name_from_stem {
+                            "0" => false,
+                            "1" => true,
+                            _ => return None,
+                        },
+                        warnings: Vec::new(),
+                        includes: Vec::new(),
+                    },
+                ));
+            }
+            ["inc", path, size, mtime] => {
+                let (_, entry) = current.as_mut()?;
+                entry.includes.push((
+                    PathBuf::from(unescape_field(path)?),
+                    size.parse().ok()?,
+                    mtime.parse().ok()?,
+                ));
+            }
+            ["use", unit_name, in_path, section, condition] => {
+                let (_, entry) = current.as_mut()?;
+                // Interface uses are written first, so they always form a prefix.
+                match *section {
+                    "interface" if entry.interface_uses == entry.conditional_uses.len() => {
+                        entry.interface_uses += 1;
+                    }
+                    "implementation" => {}
+                    _ => return None,
+                }
+                let in_path = match *in_path {
+                    "-" => None,
+                    value => Some(unescape_field(value.strip_prefix('+')?)?),
+                };
+                let condition = unescape_field(condition)?;
+                let mut pos = 0;
+                let condition = decode_condition(condition.as_bytes(), &mut pos)?;
+                entry.conditional_uses.push(ConditionalUse {
+                    unit_name: UnitName::new(&unescape_field(unit_name)?),
+                    in_path,
+                    condition,
+                });
+            }
+            ["warn", code, path, offset, referenced_by, message] => {
+                let (_, entry) = current.as_mut()?;
+                let mut warning = Diagnostic::warning(code.parse().ok()?, unescape_field(message)?);
+                if *path != "-" {
+                    warning.path = Some(PathBuf::from(unescape_field(path.strip_prefix('+')?)?));
+                }
+                if *offset != "-" {
+                    warning.offset = Some(offset.parse().ok()?);
+                }
+                if *referenced_by != "-" {
+                    warning.referenced_by = Some(PathBuf::from(unescape_field(
+                        referenced_by.strip_prefix('+')?,
+                    )?));
+                }
+                entry.warnings.push(warning);
+            }
+            ["dpr", path, content, params] => {
+                dpr_results.insert(
+                    PathBuf::from(unescape_field(path)?),
+                    (content.parse().ok()?, params.parse().ok()?),
+                );
+            }
+            _ => return None,
+        }
+    }
+    if let Some((path, entry)) = current.take() {
+        entries.insert(path, entry);
+    }
+    Some((entries, dpr_results, hits, misses))
+}
+
+fn escape_field(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+fn unescape_field(value: &str) -> Option<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => out.push('\\'),
+            't' => out.push('\t'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+fn encode_condition(expr: &CondExpr) -> String {
+    match expr {
+        CondExpr::True => "T".to_string(),
+        CondExpr::False => "F".to_string(),
+        CondExpr::Symbol(value) => format!("S{}:{value}", value.len()),
+        CondExpr::IfOpt(value) => format!("O{}:{value}", value.len()),
+        CondExpr::Unknown(value) => format!("U{}:{value}", value.len()),
+        CondExpr::Not(inner) => format!("N{}", encode_condition(inner)),
+        CondExpr::And(parts) => encode_condition_list('A', parts),
+        CondExpr::Or(parts) => encode_condition_list('R', parts),
+    }
+}
+
+fn encode_condition_list(tag: char, parts: &[CondExpr]) -> String {
+    let mut out = format!("{tag}{}:", parts.len());
+    for part in parts {
+        out.push_str(&encode_condition(part));
+    }
+    out
+}
+
+fn decode_condition(bytes: &[u8], pos: &mut usize) -> Option<CondExpr> {
+    let tag = *bytes.get(*pos)?;
+    *pos += 1;
+    match tag {
+        b'T' => Some(CondExpr::True),
+        b'F' => Some(CondExpr::False),
+        b'S' => decode_text(bytes, pos).map(CondExpr::Symbol),
+        b'O' => decode_text(bytes, pos).map(CondExpr::IfOpt),
+        b'U' => decode_text(bytes, pos).map(CondExpr::Unknown),
+        b'N' => decode_condition(bytes, pos).map(|inner| CondExpr::Not(Box::new(inner))),
+        b'A' | b'R' => {
+            let count = decode_length(bytes, pos)?;
+            let mut parts = Vec::with_capacity(count);
+            for _ in 0..count {
+                parts.push(decode_condition(bytes, pos)?);
+            }
+            Some(if tag == b'A' {
+                CondExpr::And(parts)
+            } else {
+                CondExpr::Or(parts)
+            })
+        }
+        _ => None,
+    }
+}
+
+fn decode_length(bytes: &[u8], pos: &mut usize) -> Option<usize> {
+    let start = *pos;
+    while *pos < bytes.len() && bytes[*pos].is_ascii_digit() {
+        *pos += 1;
+    }
+    let length = std::str::from_utf8(&bytes[start..*pos])
+        .ok()?
+        .parse()
+        .ok()?;
+    if bytes.get(*pos) != Some(&b':') {
+        return None;
+    }
+    *pos += 1;
+    Some(length)
+}
+
+fn decode_text(bytes: &[u8], pos: &mut usize) -> Option<String> {
+    let length = decode_length(bytes, pos)?;
+    let end = pos.checked_add(length)?;
+    let text = std::str::from_utf8(bytes.get(*pos..end)?).ok()?.to_string();
+    *pos = end;
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pas_parse::UsesRef;
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_dir() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = env::temp_dir().join(format!("fixdpr_cache_file_test_{nanos}"));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn condition_encoding_round_trips() {
+        let expr = CondExpr::And(vec![
+            CondExpr::Symbol("DEBUG".to_string()),
+            CondExpr::Not(Box::new(CondExpr::Or(vec![
+                CondExpr::IfOpt("R+".to_string()),
+                CondExpr::Unknown("IF DECLARED(X:Y)".to_string()),
+            ]))),
+            CondExpr::True,
+        ]);
+        let encoded = encode_condition(&expr);
+        let mut pos = 0;
+        assert_eq!(decode_condition(encoded.as_bytes(), &mut pos), Some(expr));
+        assert_eq!(pos, encoded.len());
+    }
+
+    #[test]
+    fn reuses_unchanged_entries_and_reparses_modified_files() {
+        let root = temp_dir();
+        let unit_a = root.join("UnitA.pas");
+        let unit_b = root.join("UnitB.pas");
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses {$IFDEF DEBUG} UnitB {$ENDIF};\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&unit_b, "unit UnitB;\ninterface\nimplementation\nend.\n").unwrap();
+        let cache_path = root.join(DEFAULT_CACHE_FILE_NAME);
+        let paths = vec![unit_a.clone(), unit_b.clone()];
+
+        let mut warnings = Warnings::new();
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        let first = cache_file.build_unit_cache(&paths, &mut warnings).unwrap();
+        assert_eq!((cache_file.hits, cache_file.misses), (0, 2));
+        cache_file.save().unwrap();
+
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        assert_eq!((cache_file.last_hits, cache_file.last_misses), (0, 2));
+        let second = cache_file.build_unit_cache(&paths, &mut warnings).unwrap();
+        assert_eq!((cache_file.hits, cache_file.misses), (2, 0));
+        let canonical_a = fs::canonicalize(&unit_a).unwrap();
+        assert_eq!(
+            first.by_path[&canonical_a].conditional_uses,
+            second.by_path[&canonical_a].conditional_uses
+        );
+
+        fs::write(
+            &unit_b,
+            "unit UnitB;\ninterface\nuses UnitA;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        let third = cache_file.build_unit_cache(&paths, &mut warnings).unwrap();
+        assert_eq!((cache_file.hits, cache_file.misses), (1, 1));
+        let canonical_b = fs::canonicalize(&unit_b).unwrap();
+        assert_eq!(
+            third.by_path[&canonical_b].uses,
+            vec![UsesRef::new("UnitA", Section::Interface)]
+        );
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn ignores_cache_file_with_other_format_version() {
+        let root = temp_dir();
+        let cache_path = root.join(DEFAULT_CACHE_FILE_NAME);
+        fs::write(&cache_path, "fixdpr-unit-cache 4\nunit\tx\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache_file = CacheFile::load(&cache_path, &mut warnings);
+        assert!(cache_file.entries.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings
+                .iter()
+                .next()
+                .unwrap()
+                .message
+                .contains("outdated or corrupt"),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn verify_reports_stale_and_drifted_entries() {
+        let root = temp_dir();
+        let unit_a = root.join("UnitA.pas");
+        let unit_b = root.join("UnitB.pas");
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(&unit_b, "unit UnitB;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let mut cache_file = CacheFile::load(&root.join(DEFAULT_CACHE_FILE_NAME), &mut warnings);
+        cache_file
+            .build_unit_cache(&[unit_a.clone(), unit_b.clone()], &mut warnings)
+            .unwrap();
+        let report = cache_file.verify(None, SourceOptions::default());
+        assert_eq!(report.checked, 2);
+        assert!(report.stale.is_empty() && report.drifted.is_empty());
+
+        let canonical_a = fs::canonicalize(&unit_a).unwrap();
+        cache_file.entries.get_mut(&canonical_a).unwrap().name = "Renamed".to_string();
+        fs::write(
+            &unit_b,
+            "unit UnitB;\ninterface\nuses UnitA;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        let report = cache_file.verify(None, SourceOptions::default());
+        assert_eq!(report.drifted, vec![canonical_a]);
+        assert_eq!(report.stale, vec![fs::canonicalize(&unit_b).unwrap()]);
+        assert_eq!(
+            cache_file.verify(Some(1), SourceOptions::default()).checked,
+            1
+        );
+    }
+
+    #[test]
+    fn dpr_results_round_trip_and_unit_changes_alter_fingerprint() {
+        let root = temp_dir();
+        let unit_a = root.join("UnitA.pas");
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        let cache_path = root.join(DEFAULT_CACHE_FILE_NAME);
+        let dpr_path = root.join("App.dpr");
+
+        let mut warnings = Warnings::new();
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        cache_file
+            .build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings)
+            .unwrap();
+        let fingerprint = cache_file.unit_fingerprint();
+        cache_file.record_dpr_unchanged(&dpr_path, 11, 22);
+        cache_file.save().unwrap();
+
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        assert!(cache_file.is_dpr_unchanged(&dpr_path, 11, 22));
+        assert!(!cache_file.is_dpr_unchanged(&dpr_path, 11, 23));
+        assert!(!cache_file.is_dpr_unchanged(&dpr_path, 12, 22));
+
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses SysUtils;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        cache_file
+            .build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings)
+            .unwrap();
+        assert_ne!(cache_file.unit_fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn entries_are_parsed_again_when_an_include_changes() {
+        let root = temp_dir();
+        let unit_a = root.join("UnitA.pas");
+        let deps = root.join("deps.inc");
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses {$I deps.inc};\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&deps, "UnitB").unwrap();
+        let cache_path = root.join(DEFAULT_CACHE_FILE_NAME);
+        let uses = |cache: &UnitCache| {
+            cache.by_path.values().next().unwrap().conditional_uses[0]
+                .unit_name
+                .to_string()
+        };
+
+        let mut warnings = Warnings::new();
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        let cache = cache_file
+            .build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings)
+            .unwrap();
+        assert_eq!(uses(&cache), "UnitB");
+        let fingerprint = cache_file.unit_fingerprint();
+        cache_file.save().unwrap();
+
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        cache_file
+            .build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings)
+            .unwrap();
+        assert_eq!((cache_file.hits, cache_file.misses), (1, 0));
+        assert_eq!(cache_file.unit_fingerprint(), fingerprint);
+
+        fs::write(&deps, "UnitCee").unwrap();
+        assert_eq!(
+            cache_file.verify(None, SourceOptions::default()).stale,
+            vec![fs::canonicalize(&unit_a).unwrap()]
+        );
+        let cache = cache_file
+            .build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings)
+            .unwrap();
+        assert_eq!(uses(&cache), "UnitCee");
+        assert_eq!((cache_file.hits, cache_file.misses), (1, 1));
+        assert_ne!(cache_file.unit_fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn stem_named_entries_are_dropped_without_the_stem_fallback() {
+        let root = temp_dir();
+        let scratch = root.join("Scratch.pas");
+        fs::write(&scratch, "const X = 1;\n").unwrap();
+        let cache_path = root.join(DEFAULT_CACHE_FILE_NAME);
+
+        let mut warnings = Warnings::new();
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        let cache = cache_file
+            .build_unit_cache(std::slice::from_ref(&scratch), &mut warnings)
+            .unwrap();
+        assert!(cache.by_path.values().all(|info| info.name_from_stem));
+        cache_file.save().unwrap();
+
+        let mut cache_file = CacheFile::load(&cache_path, &mut warnings);
+        let source = SourceOptions {
+            stem_fallback: false,
+            ..SourceOptions::default()
+        };
+        let cache = cache_file.build_unit_cache_with_events(
+            std::slice::from_ref(&scratch),
+            source,
+            Includes::default(),
+            &Prefetched::default(),
+            &mut warnings,
+            &NoEvents,
+        );
+        assert!(cache.unwrap().by_name.is_empty());
+        assert_eq!(cache_file.hits, 0);
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
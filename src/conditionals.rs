@@ -3,9 +3,12 @@ use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 
-use crate::pas_lex::{self, CompilerDirective};
-use crate::unit_cache::{self, UnitCache};
-use crate::uses_include;
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::pas_lex::{self, CompilerDirective, Skipped};
+use crate::pas_parse::{self, Section, UnitName};
+use crate::unit_cache::{self, LazyUnitCache, UnitCache, UsesSections};
+use crate::uses_include::{self, Includes};
+use crate::warnings::Warnings;
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum CondExpr {
@@ -21,7 +24,7 @@ pub enum CondExpr {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ConditionalUse {
-    pub unit_name: String,
+    pub unit_name: UnitName,
     pub in_path: Option<String>,
     pub condition: CondExpr,
 }
@@ -36,6 +39,7 @@ pub enum AssumedValue {
 #[derive(Clone, Debug, Default)]
 pub struct Assumptions {
     values: HashMap<String, AssumedValue>,
+    uses_sections: UsesSections,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -59,21 +63,14 @@ pub struct ConditionBuckets {
     pub complex: Vec<(String, String)>,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum Section {
-    None,
-    Interface,
-    Implementation,
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 struct CondFrame {
     active_branch: CondExpr,
     remaining_branch: CondExpr,
     seen_else: bool,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 struct ConditionState {
     frames: Vec<CondFrame>,
     sticky_unknowns: Vec<CondExpr>,
@@ -94,6 +91,8 @@ enum ResolutionSource {
 
 enum ResolveByName {
     NotFound,
+    /// Listed in the known units; resolves without a path.
+    Known,
     Unique {
         path: PathBuf,
         source: ResolutionSource,
@@ -175,7 +174,10 @@ pub fn render_condition(expr: &CondExpr) -> String {
     render_condition_with_precedence(expr, 0)
 }
 
-pub fn flatten_conditional_uses(uses: &[ConditionalUse], assumptions: &Assumptions) -> Vec<String> {
+pub fn flatten_conditional_uses(
+    uses: &[ConditionalUse],
+    assumptions: &Assumptions,
+) -> Vec<UnitName> {
     let mut flattened = Vec::new();
     for entry in uses {
         if evaluate_condition(&entry.condition, assumptions) == EvalResult::Never {
@@ -255,6 +257,22 @@ impl Assumptions {
             .get(&symbol.trim().to_ascii_uppercase())
             .copied()
     }
+
+    pub fn set_uses_sections(&mut self, sections: UsesSections) {
+        self.uses_sections = sections;
+    }
+
+    pub fn uses_sections(&self) -> UsesSections {
+        self.uses_sections
+    }
+
+    /// The assumed symbols in name order and the uses sections, as text, for
+    /// callers that skip work done with the same assumptions.
+    pub fn outcome_key(&self) -> String {
+        let mut values: Vec<(&String, &AssumedValue)> = self.values.iter().collect();
+        values.sort_unstable_by_key(|(symbol, _)| *symbol);
+        format!("{values:?} {:?}", self.uses_sections)
+    }
 }
 
 impl ConditionState {
@@ -275,7 +293,8 @@ impl ConditionState {
         &mut self,
         directive: CompilerDirective,
         source_path: &Path,
-        warnings: &mut Vec<String>,
+        offset: usize,
+        warnings: &mut Warnings,
     ) {
         match directive {
             CompilerDirective::IfDef(symbol) => self.push_branch(CondExpr::Symbol(symbol)),
@@ -283,18 +302,18 @@ impl ConditionState {
                 CondExpr::Not(Box::new(CondExpr::Symbol(symbol))),
             )),
             CompilerDirective::IfExpr(expr) => {
-                let parsed = self.parse_if_expr("IF", &expr, source_path, warnings);
+                let parsed = self.parse_if_expr("IF", &expr, source_path, offset, warnings);
                 self.push_branch(parsed);
             }
             CompilerDirective::IfOpt(option) => {
                 self.push_branch(CondExpr::IfOpt(option));
             }
             CompilerDirective::ElseIfExpr(expr) => {
-                let parsed = self.parse_if_expr("ELSEIF", &expr, source_path, warnings);
-                self.enter_elseif(parsed, source_path, warnings);
+                let parsed = self.parse_if_expr("ELSEIF", &expr, source_path, offset, warnings);
+                self.enter_elseif(parsed, source_path, offset, warnings);
             }
-            CompilerDirective::Else => self.enter_else(source_path, warnings),
-            CompilerDirective::EndIf => self.end_if(source_path, warnings),
+            CompilerDirective::Else => self.enter_else(source_path, offset, warnings),
+            CompilerDirective::EndIf => self.end_if(source_path, offset, warnings),
             _ => {}
         }
     }
@@ -303,16 +322,23 @@ impl ConditionState {
         &mut self,
         name: &str,
         source_path: &Path,
-        warnings: &mut Vec<String>,
+        offset: usize,
+        warnings: &mut Warnings,
     ) {
         let upper = name.trim().to_ascii_uppercase();
         let warning_key = format!("{}|{}", source_path.display(), upper);
         if self.warned_unsupported.insert(warning_key) {
-            warnings.push(format!(
-                "warning: unsupported compiler directive {} in conditional uses context for {}",
-                upper,
-                source_path.display()
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnsupportedDirective,
+                    format!(
+                        "unsupported compiler directive {} in conditional uses context for {}",
+                        upper,
+                        source_path.display()
+                    ),
+                )
+                .at_offset(source_path, Some(offset)),
+            );
         }
         let unknown = CondExpr::Unknown(upper);
         if !self.sticky_unknowns.contains(&unknown) {
@@ -325,7 +351,8 @@ impl ConditionState {
         kind: &str,
         expr: &str,
         source_path: &Path,
-        warnings: &mut Vec<String>,
+        offset: usize,
+        warnings: &mut Warnings,
     ) {
         let rendered = expr.trim();
         let warning_key = format!(
@@ -335,12 +362,18 @@ impl ConditionState {
             rendered.to_ascii_uppercase()
         );
         if self.warned_unsupported.insert(warning_key) {
-            warnings.push(format!(
-                "warning: unsupported {} expression {} in conditional uses context for {}",
-                kind.trim().to_ascii_uppercase(),
-                rendered,
-                source_path.display()
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnsupportedDirective,
+                    format!(
+                        "unsupported {} expression {} in conditional uses context for {}",
+                        kind.trim().to_ascii_uppercase(),
+                        rendered,
+                        source_path.display()
+                    ),
+                )
+                .at_offset(source_path, Some(offset)),
+            );
         }
     }
 
@@ -349,12 +382,13 @@ impl ConditionState {
         kind: &str,
         expr: &str,
         source_path: &Path,
-        warnings: &mut Vec<String>,
+        offset: usize,
+        warnings: &mut Warnings,
     ) -> CondExpr {
         if let Some(parsed) = parse_if_expression(expr) {
             parsed
         } else {
-            self.note_unsupported_expression(kind, expr, source_path, warnings);
+            self.note_unsupported_expression(kind, expr, source_path, offset, warnings);
             CondExpr::Unknown(format!(
                 "{}: {}",
                 kind.trim().to_ascii_uppercase(),
@@ -376,20 +410,27 @@ impl ConditionState {
         &mut self,
         condition: CondExpr,
         source_path: &Path,
-        warnings: &mut Vec<String>,
+        offset: usize,
+        warnings: &mut Warnings,
     ) {
         let Some(frame) = self.frames.last_mut() else {
-            warnings.push(format!(
-                "warning: unmatched ELSEIF in {}",
-                source_path.display()
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnbalancedConditional,
+                    format!("unmatched ELSEIF in {}", source_path.display()),
+                )
+                .at_offset(source_path, Some(offset)),
+            );
             return;
         };
         if frame.seen_else {
-            warnings.push(format!(
-                "warning: ELSEIF after ELSE in {}",
-                source_path.display()
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnbalancedConditional,
+                    format!("ELSEIF after ELSE in {}", source_path.display()),
+                )
+                .at_offset(source_path, Some(offset)),
+            );
             return;
         }
 
@@ -401,19 +442,25 @@ impl ConditionState {
             normalize_condition(CondExpr::And(vec![remaining, other_not(condition)]));
     }
 
-    fn enter_else(&mut self, source_path: &Path, warnings: &mut Vec<String>) {
+    fn enter_else(&mut self, source_path: &Path, offset: usize, warnings: &mut Warnings) {
         let Some(frame) = self.frames.last_mut() else {
-            warnings.push(format!(
-                "warning: unmatched ELSE in {}",
-                source_path.display()
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnbalancedConditional,
+                    format!("unmatched ELSE in {}", source_path.display()),
+                )
+                .at_offset(source_path, Some(offset)),
+            );
             return;
         };
         if frame.seen_else {
-            warnings.push(format!(
-                "warning: duplicate ELSE in {}",
-                source_path.display()
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnbalancedConditional,
+                    format!("duplicate ELSE in {}", source_path.display()),
+                )
+                .at_offset(source_path, Some(offset)),
+            );
             return;
         }
 
@@ -422,12 +469,15 @@ impl ConditionState {
         frame.seen_else = true;
     }
 
-    fn end_if(&mut self, source_path: &Path, warnings: &mut Vec<String>) {
+    fn end_if(&mut self, source_path: &Path, offset: usize, warnings: &mut Warnings) {
         if self.frames.pop().is_none() {
-            warnings.push(format!(
-                "warning: unmatched ENDIF in {}",
-                source_path.display()
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnbalancedConditional,
+                    format!("unmatched ENDIF in {}", source_path.display()),
+                )
+                .at_offset(source_path, Some(offset)),
+            );
         }
     }
 }
@@ -563,12 +613,10 @@ fn tokenize_if_expression(expr: &str) -> Option<Vec<IfExprToken>> {
                 tokens.push(IfExprToken::RParen);
                 i += 1;
             }
-            byte if byte.is_ascii_alphabetic() || byte == b'_' => {
+            byte if pas_lex::is_ident_start(byte) => {
                 let start = i;
                 i += 1;
-                while i < bytes.len()
-                    && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'.')
-                {
+                while i < bytes.len() && pas_lex::is_ident_continue(bytes[i]) {
                     i += 1;
                 }
                 tokens.push(IfExprToken::Ident(
@@ -700,13 +748,24 @@ impl IfExprParser {
     }
 }
 
+#[cfg(test)]
 pub fn parse_unit_conditional_uses(
     path: &Path,
     bytes: &[u8],
-    warnings: &mut Vec<String>,
+    warnings: &mut Warnings,
 ) -> Vec<ConditionalUse> {
+    parse_unit_conditional_uses_by_section(path, bytes, &Includes::default(), warnings).0
+}
+
+pub fn parse_unit_conditional_uses_by_section(
+    path: &Path,
+    bytes: &[u8],
+    includes: &Includes,
+    warnings: &mut Warnings,
+) -> (Vec<ConditionalUse>, usize) {
     let mut entries = Vec::new();
-    let mut i = 0;
+    let mut interface_uses = None;
+    let mut i = pas_lex::skip_bom(bytes);
     let mut section = Section::None;
     let mut include_stack = vec![unit_cache::canonicalize_if_exists(path)];
     let mut condition_state = ConditionState::default();
@@ -715,20 +774,21 @@ pub fn parse_unit_conditional_uses(
         match bytes[i] {
             b'{' | b'(' => {
                 if let Some((directive, end)) = pas_lex::parse_compiler_directive(bytes, i) {
-                    handle_scan_directive(directive, path, warnings, &mut condition_state);
+                    handle_scan_directive(directive, path, i, warnings, &mut condition_state);
                     i = end;
                     continue;
                 }
-                i = skip_non_directive_comment(bytes, i);
+                i = skip_non_directive_comment(path, bytes, i, warnings);
             }
             b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            b'\'' => i = skip_string(path, bytes, i, warnings),
             byte if pas_lex::is_ident_start(byte) => {
                 let (token, next) = pas_lex::read_ident(bytes, i);
-                if token.eq_ignore_ascii_case("interface") {
-                    section = Section::Interface;
-                } else if token.eq_ignore_ascii_case("implementation") {
-                    section = Section::Implementation;
+                if let Some(keyword) = pas_parse::section_keyword(&token) {
+                    section = keyword;
+                    if section == Section::Implementation {
+                        interface_uses = Some(entries.len());
+                    }
                 } else if token.eq_ignore_ascii_case("uses") && section != Section::None {
                     let (next_i, _) = parse_uses_fragment(
                         path,
@@ -736,11 +796,18 @@ pub fn parse_unit_conditional_uses(
                         next,
                         warnings,
                         &mut entries,
+                        includes,
                         &mut include_stack,
                         &mut condition_state,
                     );
+                    // An `{$ELSE}` branch may still hold another implementation uses clause.
+                    if section == Section::Implementation && condition_state.frames.is_empty() {
+                        break;
+                    }
                     i = next_i;
                     continue;
+                } else if section == Section::Implementation && condition_state.frames.is_empty() {
+                    break;
                 }
                 i = next;
             }
@@ -748,16 +815,18 @@ pub fn parse_unit_conditional_uses(
         }
     }
 
-    entries
+    let interface_uses = interface_uses.unwrap_or(entries.len());
+    (entries, interface_uses)
 }
 
 pub fn parse_dpr_conditional_uses(
     path: &Path,
     bytes: &[u8],
-    warnings: &mut Vec<String>,
+    includes: &Includes,
+    warnings: &mut Warnings,
 ) -> Option<Vec<ConditionalUse>> {
     let mut entries = Vec::new();
-    let mut i = 0;
+    let mut i = pas_lex::skip_bom(bytes);
     let mut include_stack = vec![unit_cache::canonicalize_if_exists(path)];
     let mut condition_state = ConditionState::default();
 
@@ -765,14 +834,14 @@ pub fn parse_dpr_conditional_uses(
         match bytes[i] {
             b'{' | b'(' => {
                 if let Some((directive, end)) = pas_lex::parse_compiler_directive(bytes, i) {
-                    handle_scan_directive(directive, path, warnings, &mut condition_state);
+                    handle_scan_directive(directive, path, i, warnings, &mut condition_state);
                     i = end;
                     continue;
                 }
-                i = skip_non_directive_comment(bytes, i);
+                i = skip_non_directive_comment(path, bytes, i, warnings);
             }
             b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            b'\'' => i = skip_string(path, bytes, i, warnings),
             byte if pas_lex::is_ident_start(byte) => {
                 let (token, next) = pas_lex::read_ident(bytes, i);
                 if token.eq_ignore_ascii_case("uses") {
@@ -782,6 +851,7 @@ pub fn parse_dpr_conditional_uses(
                         next,
                         warnings,
                         &mut entries,
+                        includes,
                         &mut include_stack,
                         &mut condition_state,
                     );
@@ -806,17 +876,19 @@ pub fn parse_dpr_conditional_uses(
 pub fn collect_dpr_conditional_units(
     dpr_path: &Path,
     project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
+    delphi_cache: Option<&LazyUnitCache>,
     assumptions: &Assumptions,
-    warnings: &mut Vec<String>,
+    warnings: &mut Warnings,
 ) -> io::Result<Option<Vec<AggregatedConditionalUnit>>> {
     let bytes = fs::read(dpr_path)?;
-    let Some(root_entries) = parse_dpr_conditional_uses(dpr_path, &bytes, warnings) else {
+    let Some(root_entries) =
+        parse_dpr_conditional_uses(dpr_path, &bytes, &project_cache.includes, warnings)
+    else {
         return Ok(None);
     };
 
-    let mut conditions_by_name: HashMap<String, (String, CondExpr)> = HashMap::new();
-    let mut conditions_by_path: HashMap<PathBuf, (String, CondExpr)> = HashMap::new();
+    let mut conditions_by_name: HashMap<String, (UnitName, CondExpr)> = HashMap::new();
+    let mut conditions_by_path: HashMap<PathBuf, (UnitName, CondExpr)> = HashMap::new();
     let mut queue = VecDeque::new();
 
     for entry in root_entries {
@@ -839,11 +911,11 @@ pub fn collect_dpr_conditional_units(
             warnings,
             true,
         ) {
-            let canonical = unit_cache::canonicalize_if_exists(&path);
+            let canonical = project_cache.canonical.canonicalize(&path);
             if merge_path_condition(
                 &mut conditions_by_path,
                 canonical.clone(),
-                entry.unit_name.clone(),
+                entry.unit_name,
                 root_condition,
             ) {
                 queue.push_back(canonical);
@@ -859,11 +931,16 @@ pub fn collect_dpr_conditional_units(
             continue;
         }
 
-        let conditional_uses =
-            match load_conditional_uses(project_cache, delphi_cache, &unit_path, warnings)? {
-                Some(uses) => uses,
-                None => continue,
-            };
+        let conditional_uses = match load_conditional_uses(
+            project_cache,
+            delphi_cache,
+            &unit_path,
+            assumptions.uses_sections(),
+            warnings,
+        )? {
+            Some(uses) => uses,
+            None => continue,
+        };
 
         for dep in conditional_uses {
             let next_condition = normalize_condition(CondExpr::And(vec![
@@ -889,11 +966,11 @@ pub fn collect_dpr_conditional_units(
                 warnings,
                 false,
             ) {
-                let canonical = unit_cache::canonicalize_if_exists(&dep_path);
+                let canonical = project_cache.canonical.canonicalize(&dep_path);
                 if merge_path_condition(
                     &mut conditions_by_path,
                     canonical.clone(),
-                    dep.unit_name.clone(),
+                    dep.unit_name,
                     next_condition,
                 ) {
                     queue.push_back(canonical);
@@ -904,7 +981,10 @@ pub fn collect_dpr_conditional_units(
 
     let mut units: Vec<AggregatedConditionalUnit> = conditions_by_name
         .into_values()
-        .map(|(name, condition)| AggregatedConditionalUnit { name, condition })
+        .map(|(name, condition)| AggregatedConditionalUnit {
+            name: name.to_string(),
+            condition,
+        })
         .collect();
     units.sort_by(|left, right| {
         left.name
@@ -916,12 +996,14 @@ pub fn collect_dpr_conditional_units(
     Ok(Some(units))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn parse_uses_fragment(
     source_path: &Path,
     bytes: &[u8],
     mut i: usize,
-    warnings: &mut Vec<String>,
+    warnings: &mut Warnings,
     entries: &mut Vec<ConditionalUse>,
+    includes: &Includes,
     include_stack: &mut Vec<PathBuf>,
     condition_state: &mut ConditionState,
 ) -> (usize, bool) {
@@ -932,6 +1014,7 @@ fn parse_uses_fragment(
             i,
             warnings,
             entries,
+            includes,
             include_stack,
             condition_state,
         );
@@ -972,11 +1055,12 @@ fn parse_uses_fragment(
             bytes,
             i,
             warnings,
+            includes,
             include_stack,
             condition_state,
         );
         entries.push(ConditionalUse {
-            unit_name,
+            unit_name: UnitName::new(&unit_name),
             in_path,
             condition,
         });
@@ -991,12 +1075,14 @@ fn parse_uses_fragment(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn skip_noise_and_includes(
     source_path: &Path,
     bytes: &[u8],
     mut i: usize,
-    warnings: &mut Vec<String>,
+    warnings: &mut Warnings,
     entries: &mut Vec<ConditionalUse>,
+    includes: &Includes,
     include_stack: &mut Vec<PathBuf>,
     condition_state: &mut ConditionState,
 ) -> (usize, bool) {
@@ -1010,7 +1096,9 @@ fn skip_noise_and_includes(
                             let result = parse_include_entries(
                                 include_name.as_str(),
                                 source_path,
+                                i,
                                 warnings,
+                                includes,
                                 include_stack,
                                 condition_state,
                             );
@@ -1027,6 +1115,7 @@ fn skip_noise_and_includes(
                             condition_state.note_unsupported_directive(
                                 name.as_str(),
                                 source_path,
+                                i,
                                 warnings,
                             );
                             i = end;
@@ -1037,16 +1126,16 @@ fn skip_noise_and_includes(
                             continue;
                         }
                         _ => {
-                            condition_state.apply_directive(directive, source_path, warnings);
+                            condition_state.apply_directive(directive, source_path, i, warnings);
                             i = end;
                             continue;
                         }
                     }
                 }
-                i = skip_non_directive_comment(bytes, i);
+                i = skip_non_directive_comment(source_path, bytes, i, warnings);
             }
             b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            b'\'' => i = skip_string(source_path, bytes, i, warnings),
             _ => break,
         }
     }
@@ -1057,7 +1146,7 @@ fn skip_noise_no_include(
     source_path: &Path,
     bytes: &[u8],
     mut i: usize,
-    warnings: &mut Vec<String>,
+    warnings: &mut Warnings,
     condition_state: &mut ConditionState,
 ) -> usize {
     while i < bytes.len() {
@@ -1070,6 +1159,7 @@ fn skip_noise_no_include(
                             condition_state.note_unsupported_directive(
                                 name.as_str(),
                                 source_path,
+                                i,
                                 warnings,
                             );
                             i = end;
@@ -1080,13 +1170,13 @@ fn skip_noise_no_include(
                             continue;
                         }
                         _ => {
-                            condition_state.apply_directive(directive, source_path, warnings);
+                            condition_state.apply_directive(directive, source_path, i, warnings);
                             i = end;
                             continue;
                         }
                     }
                 }
-                i = skip_non_directive_comment(bytes, i);
+                i = skip_non_directive_comment(source_path, bytes, i, warnings);
             }
             b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
             _ => break,
@@ -1099,7 +1189,8 @@ fn scan_to_delimiter(
     source_path: &Path,
     bytes: &[u8],
     mut i: usize,
-    warnings: &mut Vec<String>,
+    warnings: &mut Warnings,
+    includes: &Includes,
     include_stack: &mut Vec<PathBuf>,
     condition_state: &mut ConditionState,
 ) -> (usize, Option<u8>, Vec<ConditionalUse>) {
@@ -1114,7 +1205,9 @@ fn scan_to_delimiter(
                             let result = parse_include_entries(
                                 include_name.as_str(),
                                 source_path,
+                                i,
                                 warnings,
+                                includes,
                                 include_stack,
                                 condition_state,
                             );
@@ -1131,6 +1224,7 @@ fn scan_to_delimiter(
                             condition_state.note_unsupported_directive(
                                 name.as_str(),
                                 source_path,
+                                i,
                                 warnings,
                             );
                             i = end;
@@ -1141,16 +1235,16 @@ fn scan_to_delimiter(
                             continue;
                         }
                         _ => {
-                            condition_state.apply_directive(directive, source_path, warnings);
+                            condition_state.apply_directive(directive, source_path, i, warnings);
                             i = end;
                             continue;
                         }
                     }
                 }
-                i = skip_non_directive_comment(bytes, i);
+                i = skip_non_directive_comment(source_path, bytes, i, warnings);
             }
             b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            b'\'' => i = skip_string(source_path, bytes, i, warnings),
             _ => i += 1,
         }
     }
@@ -1161,36 +1255,52 @@ fn scan_to_delimiter(
 fn parse_include_entries(
     include_name: &str,
     source_path: &Path,
-    warnings: &mut Vec<String>,
+    offset: usize,
+    warnings: &mut Warnings,
+    includes: &Includes,
     include_stack: &mut Vec<PathBuf>,
     condition_state: &mut ConditionState,
 ) -> IncludeParseResult {
-    uses_include::with_include_bytes(
+    // The entries' conditions build on the includer's state, so it keys the memo.
+    let parsed = uses_include::with_memoized_include(
         include_name,
         source_path,
+        offset,
+        includes,
         warnings,
         include_stack,
+        "conditional-uses",
+        condition_state.clone(),
         |include_path, bytes, warnings, include_stack| {
+            let mut state = condition_state.clone();
             let mut entries = Vec::new();
             let (_, ended) = parse_uses_fragment(
                 include_path,
                 bytes,
-                0,
+                pas_lex::skip_bom(bytes),
                 warnings,
                 &mut entries,
+                includes,
                 include_stack,
-                condition_state,
+                &mut state,
             );
-            IncludeParseResult { entries, ended }
+            (IncludeParseResult { entries, ended }, state)
         },
-    )
-    .unwrap_or_default()
+    );
+    match parsed {
+        Some((result, state)) => {
+            *condition_state = state;
+            result
+        }
+        None => IncludeParseResult::default(),
+    }
 }
 
 fn handle_scan_directive(
     directive: CompilerDirective,
     source_path: &Path,
-    warnings: &mut Vec<String>,
+    offset: usize,
+    warnings: &mut Warnings,
     condition_state: &mut ConditionState,
 ) {
     match directive {
@@ -1201,10 +1311,15 @@ fn handle_scan_directive(
         | CompilerDirective::ElseIfExpr(_)
         | CompilerDirective::Else
         | CompilerDirective::EndIf => {
-            condition_state.apply_directive(directive, source_path, warnings)
+            condition_state.apply_directive(directive, source_path, offset, warnings)
         }
         CompilerDirective::UnsupportedAffecting(name) => {
-            condition_state.note_unsupported_directive(name.as_str(), source_path, warnings);
+            condition_state.note_unsupported_directive(
+                name.as_str(),
+                source_path,
+                offset,
+                warnings,
+            );
         }
         CompilerDirective::Include(_) | CompilerDirective::Other(_) => {}
     }
@@ -1215,8 +1330,8 @@ fn resolve_conditional_use_path(
     source_path: &Path,
     conditional_use: &ConditionalUse,
     project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    warnings: &mut Vec<String>,
+    delphi_cache: Option<&LazyUnitCache>,
+    warnings: &mut Warnings,
     is_root: bool,
 ) -> Option<PathBuf> {
     if let Some(raw_path) = conditional_use.in_path.as_ref() {
@@ -1225,68 +1340,105 @@ fn resolve_conditional_use_path(
             return Some(resolved);
         }
 
-        warnings.push(format!(
-            "warning: uses path not found for unit {} in {}: {}",
-            conditional_use.unit_name,
-            owner_path.display(),
-            resolved.display()
-        ));
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::BrokenUsesPath,
+                format!(
+                    "uses path not found for unit {} in {}: {}",
+                    conditional_use.unit_name,
+                    owner_path.display(),
+                    resolved.display()
+                ),
+            )
+            .at(owner_path),
+        );
     }
 
     match resolve_by_name(project_cache, delphi_cache, &conditional_use.unit_name) {
         ResolveByName::Unique { path, source } => {
             if conditional_use.in_path.is_none() && source == ResolutionSource::Project {
                 let label = if is_root { "dpr" } else { "unit" };
-                warnings.push(format!(
-                    "warning: missing in-path for {} {} in {} (resolved via scan)",
-                    label,
-                    conditional_use.unit_name,
-                    owner_path.display()
-                ));
+                warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::MissingInPath,
+                        format!(
+                            "missing in-path for {} {} in {} (resolved via scan)",
+                            label,
+                            conditional_use.unit_name,
+                            owner_path.display()
+                        ),
+                    )
+                    .at(owner_path),
+                );
             }
             Some(path)
         }
         ResolveByName::Ambiguous { count, source } => {
-            warnings.push(format!(
-                "warning: ambiguous unit {} referenced by {} ({} {} matches)",
-                conditional_use.unit_name,
-                owner_path.display(),
-                count,
-                source_label(source)
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::AmbiguousUnit,
+                    format!(
+                        "ambiguous unit {} referenced by {} ({} {} matches)",
+                        conditional_use.unit_name,
+                        owner_path.display(),
+                        count,
+                        source_label(source)
+                    ),
+                )
+                .at(owner_path),
+            );
             None
         }
-        ResolveByName::NotFound => None,
+        ResolveByName::Known | ResolveByName::NotFound => None,
     }
 }
 
 fn load_conditional_uses(
     project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
+    delphi_cache: Option<&LazyUnitCache>,
     unit_path: &Path,
-    warnings: &mut Vec<String>,
+    sections: UsesSections,
+    warnings: &mut Warnings,
 ) -> io::Result<Option<Vec<ConditionalUse>>> {
-    let canonical = unit_cache::canonicalize_if_exists(unit_path);
+    let canonical = project_cache.canonical.canonicalize(unit_path);
     if let Some(info) = project_cache.by_path.get(&canonical) {
-        return Ok(Some(info.conditional_uses.clone()));
+        return Ok(Some(info.section_uses(sections).to_vec()));
     }
     if let Some(delphi_cache) = delphi_cache {
-        if let Some(info) = delphi_cache.by_path.get(&canonical) {
-            return Ok(Some(info.conditional_uses.clone()));
+        if let Some(info) = delphi_cache.unit(&canonical) {
+            return Ok(Some(info.section_uses(sections).to_vec()));
         }
     }
     if !canonical.is_file() {
-        warnings.push(format!(
-            "warning: failed to read unit at {}",
-            canonical.display()
-        ));
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::ReadFailed,
+                format!("failed to read unit at {}", canonical.display()),
+            )
+            .at(&canonical),
+        );
         return Ok(None);
     }
 
-    let bytes = fs::read(&canonical)?;
-    Ok(Some(parse_unit_conditional_uses(
-        &canonical, &bytes, warnings,
-    )))
+    let Some(bytes) =
+        unit_cache::read_source_file(&canonical, project_cache.source.max_file_size, warnings)?
+    else {
+        return Ok(None);
+    };
+    let (mut entries, interface_uses) = parse_unit_conditional_uses_by_section(
+        &canonical,
+        &bytes,
+        &project_cache.includes,
+        warnings,
+    );
+    match sections {
+        UsesSections::Interface => entries.truncate(interface_uses),
+        UsesSections::Implementation => {
+            entries.drain(..interface_uses);
+        }
+        UsesSections::Both => {}
+    }
+    Ok(Some(entries))
 }
 
 fn resolve_relative_unit_path(source_path: &Path, raw_path: &str) -> PathBuf {
@@ -1303,11 +1455,13 @@ fn resolve_relative_unit_path(source_path: &Path, raw_path: &str) -> PathBuf {
 
 fn resolve_by_name(
     project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    unit_name: &str,
+    delphi_cache: Option<&LazyUnitCache>,
+    unit_name: &UnitName,
 ) -> ResolveByName {
-    let key = unit_name.to_ascii_lowercase();
-    if let Some(paths) = project_cache.by_name.get(&key) {
+    if project_cache.known_units.contains_name(unit_name) {
+        return ResolveByName::Known;
+    }
+    if let Some(paths) = project_cache.by_name.get(unit_name.key()) {
         if paths.len() > 1 {
             return ResolveByName::Ambiguous {
                 count: paths.len(),
@@ -1321,7 +1475,8 @@ fn resolve_by_name(
     }
 
     if let Some(delphi_cache) = delphi_cache {
-        if let Some(paths) = delphi_cache.by_name.get(&key) {
+        let paths = delphi_cache.lookup_name(unit_name.as_str());
+        if !paths.is_empty() {
             if paths.len() > 1 {
                 return ResolveByName::Ambiguous {
                     count: paths.len(),
@@ -1346,25 +1501,24 @@ fn source_label(source: ResolutionSource) -> &'static str {
 }
 
 fn merge_name_condition(
-    conditions: &mut HashMap<String, (String, CondExpr)>,
-    name: String,
+    conditions: &mut HashMap<String, (UnitName, CondExpr)>,
+    name: UnitName,
     condition: CondExpr,
 ) {
-    let key = name.to_ascii_lowercase();
-    match conditions.get_mut(&key) {
+    match conditions.get_mut(name.key()) {
         Some((_, existing)) => {
             *existing = normalize_condition(CondExpr::Or(vec![existing.clone(), condition]));
         }
         None => {
-            conditions.insert(key, (name, condition));
+            conditions.insert(name.key().to_string(), (name, condition));
         }
     }
 }
 
 fn merge_path_condition(
-    conditions: &mut HashMap<PathBuf, (String, CondExpr)>,
+    conditions: &mut HashMap<PathBuf, (UnitName, CondExpr)>,
     path: PathBuf,
-    name: String,
+    name: UnitName,
     condition: CondExpr,
 ) -> bool {
     match conditions.get_mut(&path) {
@@ -1392,14 +1546,48 @@ fn peek_ident(bytes: &[u8], i: usize) -> Option<(String, usize)> {
     None
 }
 
-fn skip_non_directive_comment(bytes: &[u8], i: usize) -> usize {
-    if bytes.get(i) == Some(&b'{') {
-        pas_lex::skip_brace_comment(bytes, i + 1)
-    } else if bytes.get(i) == Some(&b'(') && bytes.get(i + 1) == Some(&b'*') {
-        pas_lex::skip_paren_comment(bytes, i + 2)
-    } else {
-        i + 1
+fn skip_non_directive_comment(
+    source_path: &Path,
+    bytes: &[u8],
+    i: usize,
+    warnings: &mut Warnings,
+) -> usize {
+    let is_comment = bytes.get(i) == Some(&b'{')
+        || (bytes.get(i) == Some(&b'(') && bytes.get(i + 1) == Some(&b'*'));
+    if !is_comment {
+        return i + 1;
+    }
+    let skipped = pas_lex::skip_comment_or_recover(bytes, i);
+    if let Skipped::Unterminated(_) = skipped {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::UnterminatedComment,
+                format!(
+                    "comment in {} is never closed; resuming at the next line",
+                    source_path.display()
+                ),
+            )
+            .at_offset(source_path, Some(i)),
+        );
+    }
+    skipped.next()
+}
+
+fn skip_string(source_path: &Path, bytes: &[u8], i: usize, warnings: &mut Warnings) -> usize {
+    let skipped = pas_lex::skip_string_or_recover(bytes, i);
+    if let Skipped::Unterminated(_) = skipped {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::UnterminatedString,
+                format!(
+                    "string literal in {} is not closed on its line; resuming at the next line",
+                    source_path.display()
+                ),
+            )
+            .at_offset(source_path, Some(i)),
+        );
     }
+    skipped.next()
 }
 
 #[cfg(test)]
@@ -1428,6 +1616,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn assumptions_outcome_key_ignores_the_order_symbols_were_set_in() {
+        let mut first = Assumptions::default();
+        first.set("debug", AssumedValue::On);
+        first.set("TRACE", AssumedValue::Off);
+        let mut second = Assumptions::default();
+        second.set("trace", AssumedValue::Off);
+        second.set("DEBUG", AssumedValue::On);
+        assert_eq!(first.outcome_key(), second.outcome_key());
+
+        second.set_uses_sections(UsesSections::Interface);
+        assert_ne!(first.outcome_key(), second.outcome_key());
+        first.set("TRACE", AssumedValue::On);
+        assert_ne!(first.outcome_key(), Assumptions::default().outcome_key());
+    }
+
     #[test]
     fn evaluate_condition_supports_unknown_symbols() {
         let expr = normalize_condition(CondExpr::And(vec![
@@ -1463,7 +1667,7 @@ implementation
 end.
 "#;
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
         assert_eq!(entries.len(), 3);
         assert_eq!(entries[0].unit_name, "Foo");
@@ -1477,6 +1681,179 @@ end.
         assert_eq!(render_condition(&entries[2].condition), "NOT DEBUG");
     }
 
+    #[test]
+    fn parse_unit_conditional_uses_by_section_splits_and_stops_after_implementation_uses() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let src = br#"
+unit Demo;
+interface
+uses Foo, Bar;
+implementation
+uses Baz;
+procedure Run;
+begin
+  {$IFDEF NEVER_CLOSED
+end;
+uses Late;
+end.
+"#;
+
+        let mut warnings = Warnings::new();
+        let (entries, interface_uses) = parse_unit_conditional_uses_by_section(
+            &unit_path,
+            src,
+            &Includes::default(),
+            &mut warnings,
+        );
+        let names: Vec<&str> = entries.iter().map(|e| e.unit_name.as_str()).collect();
+        assert_eq!(names, vec!["Foo", "Bar", "Baz"]);
+        assert_eq!(interface_uses, 2);
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn parse_unit_conditional_uses_by_section_reads_every_branch_of_implementation_uses() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let src = br#"
+unit Demo;
+interface
+uses Foo;
+implementation
+{$IFDEF MSWINDOWS}
+uses WinApi;
+{$ELSE}
+uses Posix;
+{$ENDIF}
+procedure Run;
+begin
+  {$IFDEF NEVER_CLOSED
+end;
+end.
+"#;
+
+        let mut warnings = Warnings::new();
+        let (entries, interface_uses) = parse_unit_conditional_uses_by_section(
+            &unit_path,
+            src,
+            &Includes::default(),
+            &mut warnings,
+        );
+        let names: Vec<&str> = entries.iter().map(|e| e.unit_name.as_str()).collect();
+        assert_eq!(names, vec!["Foo", "WinApi", "Posix"]);
+        assert_eq!(interface_uses, 1);
+        assert_eq!(render_condition(&entries[1].condition), "MSWINDOWS");
+        assert_eq!(render_condition(&entries[2].condition), "NOT MSWINDOWS");
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn parse_unit_conditional_uses_by_section_stops_when_implementation_has_no_uses() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let src = b"unit Demo;\ninterface\nuses Foo;\nimplementation\nprocedure Run;\nbegin\nend;\nuses Late;\nend.\n";
+
+        let mut warnings = Warnings::new();
+        let (entries, interface_uses) = parse_unit_conditional_uses_by_section(
+            &unit_path,
+            src,
+            &Includes::default(),
+            &mut warnings,
+        );
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].unit_name, "Foo");
+        assert_eq!(interface_uses, 1);
+    }
+
+    #[test]
+    fn parse_unit_conditional_uses_locates_include_and_directive_warnings() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let src = b"unit Demo;\ninterface\nuses Foo,\n  {$I Missing.inc}\n  {$ENDIF}\n  Bar;\nimplementation\nend.\n";
+
+        let mut warnings = Warnings::new();
+        let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
+        assert_eq!(entries.len(), 2);
+
+        let include = warnings
+            .iter()
+            .find(|warning| warning.code == DiagnosticCode::IncludeNotFound)
+            .expect("include warning");
+        assert_eq!(include.path.as_deref(), Some(unit_path.as_path()));
+        assert_eq!(include.offset, Some(33));
+
+        let endif = warnings
+            .iter()
+            .find(|warning| warning.code == DiagnosticCode::UnbalancedConditional)
+            .expect("endif warning");
+        assert_eq!(endif.offset, Some(52));
+    }
+
+    #[test]
+    fn parse_unit_conditional_uses_recovers_from_unterminated_brace_comment() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let src = b"unit Demo;\n{ stray\ninterface\nuses Foo, Bar;\nimplementation\nend.\n";
+
+        let mut warnings = Warnings::new();
+        let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
+        let names: Vec<&str> = entries.iter().map(|e| e.unit_name.as_str()).collect();
+        assert_eq!(names, vec!["Foo", "Bar"]);
+        let warning = warnings
+            .iter()
+            .find(|warning| warning.code == DiagnosticCode::UnterminatedComment)
+            .expect("unterminated comment warning");
+        assert_eq!(warning.offset, Some(11));
+    }
+
+    #[test]
+    fn parse_unit_conditional_uses_recovers_from_unterminated_paren_comment() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let src = b"unit Demo;\ninterface\nuses Foo, (* stray\n  Bar;\nimplementation\nend.\n";
+
+        let mut warnings = Warnings::new();
+        let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
+        let names: Vec<&str> = entries.iter().map(|e| e.unit_name.as_str()).collect();
+        assert_eq!(names, vec!["Foo", "Bar"]);
+        let warning = warnings
+            .iter()
+            .find(|warning| warning.code == DiagnosticCode::UnterminatedComment)
+            .expect("unterminated comment warning");
+        assert_eq!(warning.offset, Some(31));
+    }
+
+    #[test]
+    fn parse_unit_conditional_uses_recovers_from_string_crossing_a_line() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let src = b"unit Demo;\nconst Title = 'Demo;\ninterface\nuses Foo;\nimplementation\nend.\n";
+
+        let mut warnings = Warnings::new();
+        let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].unit_name, "Foo");
+        let warning = warnings
+            .iter()
+            .find(|warning| warning.code == DiagnosticCode::UnterminatedString)
+            .expect("unterminated string warning");
+        assert_eq!(warning.offset, Some(25));
+    }
+
+    #[test]
+    fn parse_unit_conditional_uses_accepts_well_formed_strings_and_comments() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let src = b"unit Demo;\n{ multi\n  line }\n(* also\n  multi *)\nconst A = 'it''s';\nconst B = '''\nuses Hidden;\n''';\ninterface\nuses Foo;\nimplementation\nend.\n";
+
+        let mut warnings = Warnings::new();
+        let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].unit_name, "Foo");
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
     #[test]
     fn parse_dpr_conditional_uses_tracks_root_conditions() {
         let root = temp_dir();
@@ -1493,8 +1870,10 @@ begin
 end.
 "#;
 
-        let mut warnings = Vec::new();
-        let entries = parse_dpr_conditional_uses(&dpr_path, src, &mut warnings).expect("entries");
+        let mut warnings = Warnings::new();
+        let entries =
+            parse_dpr_conditional_uses(&dpr_path, src, &Includes::default(), &mut warnings)
+                .expect("entries");
         assert_eq!(entries.len(), 2);
         assert_eq!(render_condition(&entries[0].condition), "DEBUG");
         assert_eq!(render_condition(&entries[1].condition), "NOT DEBUG");
@@ -1514,7 +1893,7 @@ implementation
 end.
 "#;
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
         assert_eq!(entries.len(), 1);
         assert_eq!(
@@ -1542,7 +1921,7 @@ implementation
 end.
 "#;
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
         assert_eq!(entries.len(), 3);
         assert_eq!(render_condition(&entries[0].condition), "A");
@@ -1567,7 +1946,7 @@ implementation
 end.
 "#;
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
         assert_eq!(entries.len(), 2);
         assert_eq!(render_condition(&entries[0].condition), "IFOPT(N+)");
@@ -1591,7 +1970,7 @@ implementation
 end.
 "#;
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
         assert_eq!(entries.len(), 2);
         assert_eq!(
@@ -1603,7 +1982,12 @@ end.
             "NOT UNKNOWN(IF: RTLVERSION >= 14)"
         );
         assert_eq!(warnings.len(), 1);
-        assert!(warnings[0].contains("unsupported IF expression RTLVersion >= 14"));
+        assert!(warnings
+            .iter()
+            .next()
+            .unwrap()
+            .message
+            .contains("unsupported IF expression RTLVersion >= 14"));
     }
 
     #[test]
@@ -1624,7 +2008,7 @@ implementation
 end.
 "#;
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
         assert_eq!(entries.len(), 3);
         assert_eq!(render_condition(&entries[0].condition), "DEBUG");
@@ -1645,12 +2029,17 @@ implementation
 end.
 "#;
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let entries = parse_unit_conditional_uses(&unit_path, src, &mut warnings);
         assert_eq!(entries.len(), 1);
         assert_eq!(render_condition(&entries[0].condition), "UNKNOWN(DEFINE)");
         assert_eq!(warnings.len(), 1);
-        assert!(warnings[0].contains("unsupported compiler directive DEFINE"));
+        assert!(warnings
+            .iter()
+            .next()
+            .unwrap()
+            .message
+            .contains("unsupported compiler directive DEFINE"));
     }
 
     #[test]
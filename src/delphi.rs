@@ -1,33 +1,230 @@
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 
+use crate::error::FixdprError;
+use crate::fs_walk;
+
 const SOURCE_DIR_NAME: &str = "source";
 
-pub fn resolve_source_roots(raw_versions: &[String]) -> Result<Vec<PathBuf>, String> {
+#[cfg(windows)]
+const REGISTRY_BASES: [&str; 3] = [
+    r"HKCU\Software\Embarcadero\BDS",
+    r"HKLM\Software\Embarcadero\BDS",
+    r"HKLM\Software\WOW6432Node\Embarcadero\BDS",
+];
+
+const LIBRARY_PLATFORM: &str = "Win32";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceRootsResolution {
+    pub roots: Vec<PathBuf>,
+    /// Versions picked by `auto` or `latest`, lowest first.
+    pub discovered_versions: Vec<String>,
+    /// Existing folders from each version's Win32 library search path.
+    pub library_roots: Vec<PathBuf>,
+    /// Requested source subfolders that do not exist under a source root.
+    pub missing_source_dirs: Vec<PathBuf>,
+}
+
+/// Options for [`resolve_source_roots`].
+#[derive(Clone, Debug, Default)]
+pub struct SourceRootsOptions {
+    library_paths: bool,
+    bds_roots: Vec<PathBuf>,
+    roots_are_source: bool,
+    source_dirs: Vec<String>,
+}
+
+impl SourceRootsOptions {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    /// Also resolve the registry library search path of each version.
+    pub fn library_paths(mut self, value: bool) -> Self {
+        self.library_paths = value;
+        self
+    }
+
+    /// A BDS root given directly; its `source` folder is used like a version's.
+    pub fn bds_root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.bds_roots.push(path.into());
+        self
+    }
+
+    /// Use the `bds_root` paths as source folders without appending `source`.
+    pub fn roots_are_source(mut self, value: bool) -> Self {
+        self.roots_are_source = value;
+        self
+    }
+
+    /// Scan only this subfolder of each source root, e.g. `vcl/imaging`.
+    pub fn source_dir(mut self, relative: impl Into<String>) -> Self {
+        self.source_dirs.push(relative.into());
+        self
+    }
+
+    pub fn build(self) -> Self {
+        self
+    }
+}
+
+/// Resolves `--delphi-version` values and `--delphi-root` folders to
+/// `<BDS Root>\source` folders.
+///
+/// `auto` selects every installed version with a `source` folder and `latest`
+/// only the highest of them. Explicit roots work on every platform and are
+/// deduplicated against the versions.
+pub fn resolve_source_roots(
+    raw_versions: &[String],
+    options: &SourceRootsOptions,
+) -> Result<SourceRootsResolution, FixdprError> {
+    let mut resolution = resolve_registry_versions(raw_versions, options.library_paths)?;
+    add_bds_roots(
+        &mut resolution,
+        &options.bds_roots,
+        options.roots_are_source,
+    )?;
+    restrict_to_source_dirs(&mut resolution, &options.source_dirs);
+    Ok(resolution)
+}
+
+fn resolve_registry_versions(
+    raw_versions: &[String],
+    library_paths: bool,
+) -> Result<SourceRootsResolution, FixdprError> {
     #[cfg(windows)]
     {
-        resolve_source_roots_with_lookup(raw_versions, lookup_bds_root_from_registry)
+        resolve_source_roots_with_lookup(
+            raw_versions,
+            lookup_bds_root_from_registry,
+            enumerate_bds_versions_from_registry,
+            |version| {
+                if library_paths {
+                    lookup_library_search_path_from_registry(version)
+                } else {
+                    Ok(None)
+                }
+            },
+        )
     }
 
     #[cfg(not(windows))]
     {
+        let _ = library_paths;
         let has_any = raw_versions.iter().any(|value| !value.trim().is_empty());
         if has_any {
-            return Err("--delphi-version is only supported on Windows".to_string());
+            return Err(FixdprError::invalid_argument(
+                "--delphi-version is only supported on Windows",
+            ));
         }
-        Ok(Vec::new())
+        Ok(SourceRootsResolution::default())
     }
 }
 
-fn resolve_source_roots_with_lookup<F>(
+fn add_bds_roots(
+    resolution: &mut SourceRootsResolution,
+    bds_roots: &[PathBuf],
+    roots_are_source: bool,
+) -> Result<(), FixdprError> {
+    let mut seen = resolution
+        .roots
+        .iter()
+        .chain(&resolution.library_roots)
+        .map(|path| normalize_for_dedupe(path))
+        .collect::<HashSet<_>>();
+    for bds_root in bds_roots {
+        let source_root = if roots_are_source {
+            bds_root.clone()
+        } else {
+            bds_root.join(SOURCE_DIR_NAME)
+        };
+        let flag = format!(
+            "Delphi source path for --delphi-root {}",
+            bds_root.display()
+        );
+        let canonical = checked_source_root(source_root, flag)?;
+        if seen.insert(normalize_for_dedupe(&canonical)) {
+            resolution.roots.push(canonical);
+        }
+    }
+    resolution
+        .roots
+        .sort_by_key(|path| normalize_for_dedupe(path.as_path()));
+    Ok(())
+}
+
+/// Replaces each source root with its `source_dirs` subfolders, matched
+/// case-insensitively. Library roots are kept as they are.
+fn restrict_to_source_dirs(resolution: &mut SourceRootsResolution, source_dirs: &[String]) {
+    let relative_dirs = source_dirs
+        .iter()
+        .map(|dir| dir.trim().trim_matches(['/', '\\']))
+        .filter(|dir| !dir.is_empty())
+        .collect::<Vec<_>>();
+    if relative_dirs.is_empty() {
+        return;
+    }
+
+    let mut roots = Vec::new();
+    let mut seen = HashSet::new();
+    for source_root in &resolution.roots {
+        for relative in &relative_dirs {
+            let direct = source_root.join(relative.replace('\\', "/"));
+            let found = if direct.is_dir() {
+                Some(direct)
+            } else {
+                fs_walk::find_path_ignoring_case(source_root, relative).filter(|path| path.is_dir())
+            };
+            let Some(found) = found else {
+                resolution
+                    .missing_source_dirs
+                    .push(source_root.join(relative));
+                continue;
+            };
+            let canonical = canonicalize_if_exists(&found);
+            if seen.insert(normalize_for_dedupe(&canonical)) {
+                roots.push(canonical);
+            }
+        }
+    }
+    roots.sort_by_key(|path| normalize_for_dedupe(path.as_path()));
+    resolution.roots = roots;
+}
+
+fn checked_source_root(source_root: PathBuf, flag: String) -> Result<PathBuf, FixdprError> {
+    if !source_root.exists() {
+        return Err(FixdprError::PathNotFound {
+            flag,
+            path: source_root,
+        });
+    }
+    if !source_root.is_dir() {
+        return Err(FixdprError::NotADirectory {
+            flag,
+            path: source_root,
+        });
+    }
+    Ok(canonicalize_if_exists(&source_root))
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn resolve_source_roots_with_lookup<F, E, L>(
     raw_versions: &[String],
     mut lookup_bds_root: F,
-) -> Result<Vec<PathBuf>, String>
+    mut enumerate_versions: E,
+    mut lookup_library_search_path: L,
+) -> Result<SourceRootsResolution, FixdprError>
 where
-    F: FnMut(&str) -> Result<Option<PathBuf>, String>,
+    F: FnMut(&str) -> Result<Option<PathBuf>, FixdprError>,
+    E: FnMut() -> Result<Vec<String>, FixdprError>,
+    L: FnMut(&str) -> Result<Option<String>, FixdprError>,
 {
     let mut roots = Vec::new();
     let mut seen = HashSet::new();
+    let mut discovered_versions = Vec::new();
+    let mut bds_roots = Vec::new();
+    let mut installed: Option<Vec<(String, PathBuf)>> = None;
 
     for raw in raw_versions {
         let version = raw.trim();
@@ -35,71 +232,373 @@ where
             continue;
         }
 
+        let latest = version.eq_ignore_ascii_case("latest");
+        if latest || version.eq_ignore_ascii_case("auto") {
+            if installed.is_none() {
+                installed = Some(installed_source_roots(
+                    &mut lookup_bds_root,
+                    &mut enumerate_versions,
+                )?);
+            }
+            let installed = installed.as_deref().unwrap_or_default();
+            let picked = if latest {
+                installed
+                    .last()
+                    .map(std::slice::from_ref)
+                    .unwrap_or_default()
+            } else {
+                installed
+            };
+            if picked.is_empty() {
+                return Err(FixdprError::DelphiVersionNotFound(version.to_string()));
+            }
+            for (name, source_root) in picked {
+                if !discovered_versions.contains(name) {
+                    discovered_versions.push(name.clone());
+                    let bds_root = source_root.parent().unwrap_or(source_root);
+                    bds_roots.push((name.clone(), bds_root.to_path_buf()));
+                }
+                let canonical = canonicalize_if_exists(source_root);
+                if seen.insert(normalize_for_dedupe(&canonical)) {
+                    roots.push(canonical);
+                }
+            }
+            continue;
+        }
+
         let bds_root = match lookup_bds_root(version)? {
             Some(path) => path,
             None => {
-                return Err(format!("--delphi-version not found in registry: {version}"));
+                return Err(FixdprError::DelphiVersionNotFound(version.to_string()));
             }
         };
 
-        let source_root = bds_root.join(SOURCE_DIR_NAME);
-        if !source_root.exists() {
-            return Err(format!(
-                "Delphi source path not found for --delphi-version {version}: {}",
-                source_root.display()
-            ));
-        }
-        if !source_root.is_dir() {
-            return Err(format!(
-                "Delphi source path is not a directory for --delphi-version {version}: {}",
-                source_root.display()
-            ));
-        }
-
-        let canonical = canonicalize_if_exists(&source_root);
+        let canonical = checked_source_root(
+            bds_root.join(SOURCE_DIR_NAME),
+            format!("Delphi source path for --delphi-version {version}"),
+        )?;
         let dedupe_key = normalize_for_dedupe(&canonical);
         if seen.insert(dedupe_key) {
             roots.push(canonical);
         }
+        bds_roots.push((version.to_string(), bds_root));
+    }
+
+    let mut library_roots = Vec::new();
+    for (version, bds_root) in &bds_roots {
+        let Some(search_path) = lookup_library_search_path(version)? else {
+            continue;
+        };
+        for dir in expand_library_search_path(&search_path, bds_root) {
+            if seen.insert(normalize_for_dedupe(&dir)) {
+                library_roots.push(dir);
+            }
+        }
     }
 
     roots.sort_by_key(|path| normalize_for_dedupe(path.as_path()));
-    Ok(roots)
+    discovered_versions.sort_by_key(|version| version_key(version));
+    Ok(SourceRootsResolution {
+        roots,
+        discovered_versions,
+        library_roots,
+        missing_source_dirs: Vec::new(),
+    })
 }
 
-#[cfg(windows)]
-fn lookup_bds_root_from_registry(version: &str) -> Result<Option<PathBuf>, String> {
-    let candidates = version_candidates(version);
-    if candidates.is_empty() {
-        return Ok(None);
+/// Splits a `;` separated library search path and keeps the existing folders.
+///
+/// `$(BDS)`, `$(BDSLIB)`, and `$(Platform)` are expanded; entries with other
+/// variables are dropped unless they happen to exist.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn expand_library_search_path(search_path: &str, bds_root: &Path) -> Vec<PathBuf> {
+    let bds = bds_root.to_string_lossy();
+    let bds = bds.trim_end_matches(['\\', '/']);
+    let bds_lib = format!(r"{bds}\lib");
+    let mut dirs = Vec::new();
+    for entry in search_path.split(';') {
+        let entry = entry.trim().trim_matches('"');
+        if entry.is_empty() {
+            continue;
+        }
+        let expanded = replace_ignore_case(entry, "$(BDSLIB)", &bds_lib);
+        let expanded = replace_ignore_case(&expanded, "$(BDS)", bds);
+        let expanded = replace_ignore_case(&expanded, "$(Platform)", LIBRARY_PLATFORM);
+        let dir = PathBuf::from(expanded.replace('\\', std::path::MAIN_SEPARATOR_STR));
+        if dir.is_dir() {
+            dirs.push(canonicalize_if_exists(&dir));
+        }
+    }
+    dirs
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn replace_ignore_case(value: &str, pattern: &str, replacement: &str) -> String {
+    let lower = value.to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+    let mut replaced = String::with_capacity(value.len());
+    let mut last = 0;
+    for (start, _) in lower.match_indices(&pattern) {
+        replaced.push_str(&value[last..start]);
+        replaced.push_str(replacement);
+        last = start + pattern.len();
     }
+    replaced.push_str(&value[last..]);
+    replaced
+}
 
-    let registry_bases = [
-        r"HKCU\Software\Embarcadero\BDS",
-        r"HKLM\Software\Embarcadero\BDS",
-        r"HKLM\Software\WOW6432Node\Embarcadero\BDS",
-    ];
+/// Installed versions that have a `source` folder, sorted lowest first.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn installed_source_roots<F, E>(
+    lookup_bds_root: &mut F,
+    enumerate_versions: &mut E,
+) -> Result<Vec<(String, PathBuf)>, FixdprError>
+where
+    F: FnMut(&str) -> Result<Option<PathBuf>, FixdprError>,
+    E: FnMut() -> Result<Vec<String>, FixdprError>,
+{
+    let mut versions = enumerate_versions()?;
+    versions.retain(|version| version_key(version).is_some());
+    versions.sort_by_key(|version| version_key(version));
+    versions.dedup_by(|a, b| version_key(a) == version_key(b));
 
-    for candidate in candidates {
-        for base in registry_bases {
-            let key_path = format!(r"{base}\{candidate}");
-            let root_dir = query_registry_value(&key_path, "RootDir")
-                .map_err(|err| format!("failed to query registry key {key_path}: {err}"))?;
-            let Some(root_dir) = root_dir else {
-                continue;
+    let mut installed = Vec::new();
+    for version in versions {
+        let Some(bds_root) = lookup_bds_root(&version)? else {
+            continue;
+        };
+        let source_root = bds_root.join(SOURCE_DIR_NAME);
+        if source_root.is_dir() {
+            installed.push((version, source_root));
+        }
+    }
+    Ok(installed)
+}
+
+/// Numeric ordering key for registry version names such as `22.0`.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn version_key(version: &str) -> Option<Vec<u32>> {
+    let mut key = version
+        .trim()
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    while key.len() > 1 && key.last() == Some(&0) {
+        key.pop();
+    }
+    Some(key)
+}
+
+/// A registry string value; `Expandable` may hold `%NAME%` references.
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum RegistryString {
+    Plain(String),
+    Expandable(String),
+}
+
+#[cfg(windows)]
+fn lookup_bds_root_from_registry(version: &str) -> Result<Option<PathBuf>, FixdprError> {
+    lookup_bds_root_with(version, &REGISTRY_BASES, read_registry_string, |name| {
+        std::env::var(name).ok()
+    })
+}
+
+#[cfg(windows)]
+fn lookup_library_search_path_from_registry(version: &str) -> Result<Option<String>, FixdprError> {
+    let sub_key = format!(r"Library\{LIBRARY_PLATFORM}");
+    lookup_registry_string(
+        version,
+        &REGISTRY_BASES,
+        (sub_key.as_str(), "Search Path"),
+        read_registry_string,
+        |name| std::env::var(name).ok(),
+    )
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn lookup_bds_root_with<R, V>(
+    version: &str,
+    bases: &[&str],
+    read_value: R,
+    env_var: V,
+) -> Result<Option<PathBuf>, FixdprError>
+where
+    R: FnMut(&str, &str) -> std::io::Result<Option<RegistryString>>,
+    V: Fn(&str) -> Option<String>,
+{
+    Ok(
+        lookup_registry_string(version, bases, ("", "RootDir"), read_value, env_var)?
+            .map(PathBuf::from),
+    )
+}
+
+/// Reads `value_name` of the `sub_key` below each base for each form of
+/// `version`; the first non-empty value wins, so earlier bases take precedence.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn lookup_registry_string<R, V>(
+    version: &str,
+    bases: &[&str],
+    (sub_key, value_name): (&str, &str),
+    mut read_value: R,
+    env_var: V,
+) -> Result<Option<String>, FixdprError>
+where
+    R: FnMut(&str, &str) -> std::io::Result<Option<RegistryString>>,
+    V: Fn(&str) -> Option<String>,
+{
+    for candidate in version_candidates(version) {
+        for base in bases {
+            let mut key_path = format!(r"{base}\{candidate}");
+            if !sub_key.is_empty() {
+                key_path.push('\\');
+                key_path.push_str(sub_key);
+            }
+            let value = read_value(&key_path, value_name).map_err(|source| {
+                FixdprError::RegistryLookup {
+                    key: key_path.clone(),
+                    source,
+                }
+            })?;
+            let root_dir = match value {
+                Some(RegistryString::Plain(value)) => value,
+                Some(RegistryString::Expandable(value)) => {
+                    expand_environment_strings(&value, &env_var)
+                }
+                None => continue,
             };
             let trimmed = root_dir.trim().trim_matches('"');
             if trimmed.is_empty() {
                 continue;
             }
-            return Ok(Some(PathBuf::from(trimmed)));
+            return Ok(Some(trimmed.to_string()));
         }
     }
 
     Ok(None)
 }
 
+/// Replaces `%NAME%` references like `ExpandEnvironmentStrings`; unknown
+/// names are left as written.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn expand_environment_strings(value: &str, env_var: impl Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('%') else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = &after[..end];
+        match env_var(name).filter(|_| !name.is_empty()) {
+            Some(replacement) => expanded.push_str(&replacement),
+            None => {
+                expanded.push('%');
+                expanded.push_str(name);
+                expanded.push('%');
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(windows)]
+fn read_registry_string(
+    key_path: &str,
+    value_name: &str,
+) -> std::io::Result<Option<RegistryString>> {
+    use winreg::enums::{REG_EXPAND_SZ, REG_SZ};
+    use winreg::types::FromRegValue;
+
+    let value = match open_registry_key(key_path)? {
+        Some(key) => match key.get_raw_value(value_name) {
+            Ok(raw) if raw.vtype == REG_SZ || raw.vtype == REG_EXPAND_SZ => {
+                let text = String::from_reg_value(&raw)?;
+                Some(if raw.vtype == REG_EXPAND_SZ {
+                    RegistryString::Expandable(text)
+                } else {
+                    RegistryString::Plain(text)
+                })
+            }
+            Ok(_) => None,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        },
+        None => None,
+    };
+
+    #[cfg(feature = "reg-query-fallback")]
+    if value.is_none() {
+        return Ok(query_registry_value(key_path, value_name)?.map(RegistryString::Plain));
+    }
+    Ok(value)
+}
+
+#[cfg(windows)]
+fn open_registry_key(key_path: &str) -> std::io::Result<Option<winreg::RegKey>> {
+    use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+
+    let (hive, sub_key) = key_path.split_once('\\').unwrap_or((key_path, ""));
+    let hive = match hive {
+        "HKCU" => HKEY_CURRENT_USER,
+        "HKLM" => HKEY_LOCAL_MACHINE,
+        _ => return Ok(None),
+    };
+    match winreg::RegKey::predef(hive).open_subkey(sub_key) {
+        Ok(key) => Ok(Some(key)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
 #[cfg(windows)]
+fn enumerate_bds_versions_from_registry() -> Result<Vec<String>, FixdprError> {
+    let mut versions = Vec::new();
+    for base in REGISTRY_BASES {
+        let to_error = |source| FixdprError::RegistryLookup {
+            key: base.to_string(),
+            source,
+        };
+        let Some(key) = open_registry_key(base).map_err(to_error)? else {
+            continue;
+        };
+        for name in key.enum_keys() {
+            let name = name.map_err(to_error)?;
+            if !versions.contains(&name) {
+                versions.push(name);
+            }
+        }
+    }
+
+    #[cfg(feature = "reg-query-fallback")]
+    if versions.is_empty() {
+        for base in REGISTRY_BASES {
+            let output = std::process::Command::new("reg")
+                .args(["query", base])
+                .output()
+                .map_err(|source| FixdprError::RegistryLookup {
+                    key: base.to_string(),
+                    source,
+                })?;
+            if !output.status.success() {
+                continue;
+            }
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            for version in parse_reg_query_subkeys(&stdout) {
+                if !versions.contains(&version) {
+                    versions.push(version);
+                }
+            }
+        }
+    }
+    Ok(versions)
+}
+
+#[cfg(all(windows, feature = "reg-query-fallback"))]
 fn query_registry_value(key_path: &str, value_name: &str) -> std::io::Result<Option<String>> {
     let output = std::process::Command::new("reg")
         .args(["query", key_path, "/v", value_name])
@@ -113,6 +612,8 @@ fn query_registry_value(key_path: &str, value_name: &str) -> std::io::Result<Opt
     Ok(parse_reg_query_value(&stdout, value_name))
 }
 
+#[cfg(any(test, feature = "reg-query-fallback"))]
+#[cfg_attr(not(windows), allow(dead_code))]
 fn parse_reg_query_value(output: &str, value_name: &str) -> Option<String> {
     for line in output.lines() {
         let trimmed = line.trim();
@@ -120,26 +621,92 @@ fn parse_reg_query_value(output: &str, value_name: &str) -> Option<String> {
             continue;
         }
 
-        let mut parts = trimmed.split_whitespace();
-        let Some(name) = parts.next() else {
+        let Some(name) = trimmed.get(..value_name.len()) else {
             continue;
         };
-        if !name.eq_ignore_ascii_case(value_name) {
+        let rest = &trimmed[value_name.len()..];
+        if !name.eq_ignore_ascii_case(value_name) || !rest.starts_with(char::is_whitespace) {
             continue;
         }
 
-        let _value_type = parts.next()?;
-        let value = parts.collect::<Vec<_>>().join(" ");
-        if value.is_empty() {
+        let Some((value_type, value)) = rest.trim_start().split_once(char::is_whitespace) else {
+            continue;
+        };
+        let value = value.trim();
+        if !value_type.starts_with("REG_") || value.is_empty() {
             continue;
         }
 
-        return Some(value);
+        return Some(value.to_string());
     }
 
     None
 }
 
+/// Last path component of each key line printed by `reg query KEY`.
+#[cfg(any(test, feature = "reg-query-fallback"))]
+#[cfg_attr(not(windows), allow(dead_code))]
+fn parse_reg_query_subkeys(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("HKEY_"))
+        .filter_map(|key| key.rsplit('\\').next())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Product names and numbers with the BDS registry version they install as.
+const PRODUCT_VERSIONS: &[(&str, &str)] = &[
+    ("xe", "8.0"),
+    ("xe2", "9.0"),
+    ("xe3", "10.0"),
+    ("xe4", "11.0"),
+    ("xe5", "12.0"),
+    ("xe6", "14.0"),
+    ("xe7", "15.0"),
+    ("xe8", "16.0"),
+    ("10", "17.0"),
+    ("seattle", "17.0"),
+    ("10.1", "18.0"),
+    ("berlin", "18.0"),
+    ("10.2", "19.0"),
+    ("tokyo", "19.0"),
+    ("10.3", "20.0"),
+    ("rio", "20.0"),
+    ("10.4", "21.0"),
+    ("sydney", "21.0"),
+    ("11", "22.0"),
+    ("alexandria", "22.0"),
+    ("12", "23.0"),
+    ("athens", "23.0"),
+    ("13", "37.0"),
+    ("florence", "37.0"),
+];
+
+/// Maps names like `XE8`, `Sydney`, or `Delphi 11 Alexandria` to a BDS
+/// version. Every word must be known and all of them must agree.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn product_version(version: &str) -> Option<&'static str> {
+    let mut mapped = None;
+    for word in version.split_whitespace() {
+        let word = word.to_ascii_lowercase();
+        if matches!(word.as_str(), "delphi" | "rad" | "studio") {
+            continue;
+        }
+        let (_, bds) = PRODUCT_VERSIONS.iter().find(|(name, _)| *name == word)?;
+        if mapped.is_some_and(|mapped| mapped != *bds) {
+            return None;
+        }
+        mapped = Some(*bds);
+    }
+    mapped
+}
+
+/// Registry key names to try for `version`, in order. A product name or
+/// number comes first, so `11` means Delphi 11 (BDS 22.0) before BDS 11.0.
+#[cfg_attr(not(windows), allow(dead_code))]
 fn version_candidates(version: &str) -> Vec<String> {
     let trimmed = version.trim();
     if trimmed.is_empty() {
@@ -147,6 +714,10 @@ fn version_candidates(version: &str) -> Vec<String> {
     }
 
     let mut candidates = Vec::new();
+    if let Some(bds) = product_version(trimmed) {
+        candidates.push(bds.to_string());
+        candidates.push(bds.trim_end_matches(".0").to_string());
+    }
     candidates.push(trimmed.to_string());
 
     if !trimmed.contains('.') {
@@ -165,7 +736,7 @@ fn version_candidates(version: &str) -> Vec<String> {
 }
 
 fn canonicalize_if_exists(path: &Path) -> PathBuf {
-    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    crate::unit_cache::canonicalize_if_exists(path)
 }
 
 fn normalize_for_dedupe(path: &Path) -> String {
@@ -193,8 +764,101 @@ mod tests {
         assert_eq!(version_candidates("22.0"), vec!["22.0", "22"]);
     }
 
+    #[test]
+    fn version_candidates_map_product_names() {
+        assert_eq!(
+            version_candidates("XE8"),
+            vec!["16.0", "16", "XE8", "XE8.0"]
+        );
+        assert_eq!(version_candidates("sydney")[0], "21.0");
+        assert_eq!(version_candidates("10.4"), vec!["21.0", "21", "10.4"]);
+        assert_eq!(version_candidates("Alexandria")[0], "22.0");
+        assert_eq!(version_candidates("Delphi 11 Alexandria")[0], "22.0");
+        assert_eq!(version_candidates("ATHENS")[0], "23.0");
+    }
+
+    #[test]
+    fn version_candidates_try_product_numbers_before_raw_bds_numbers() {
+        assert_eq!(version_candidates("11"), vec!["22.0", "22", "11", "11.0"]);
+        assert_eq!(version_candidates("12"), vec!["23.0", "23", "12", "12.0"]);
+        assert_eq!(version_candidates("10"), vec!["17.0", "17", "10", "10.0"]);
+        // Raw BDS numbers that are no product number are left alone.
+        assert_eq!(version_candidates("21.0"), vec!["21.0", "21"]);
+        // Words that disagree or are unknown fall through unchanged.
+        assert_eq!(
+            version_candidates("11 Athens"),
+            vec!["11 Athens", "11 Athens.0"]
+        );
+        assert_eq!(
+            version_candidates("Delphi 7"),
+            vec!["Delphi 7", "Delphi 7.0"]
+        );
+    }
+
+    #[test]
+    fn lookup_bds_root_with_expands_environment_variables() {
+        let bases = [r"HKCU\BDS", r"HKLM\BDS"];
+        let env_var =
+            |name: &str| (name == "ProgramFiles").then(|| r"C:\Program Files".to_string());
+        let read = |key_path: &str, _value_name: &str| {
+            Ok(match key_path {
+                r"HKLM\BDS\22.0" => Some(RegistryString::Expandable(
+                    r"%ProgramFiles%\Studio\22.0\".to_string(),
+                )),
+                r"HKLM\BDS\23.0" => Some(RegistryString::Plain(r"%ProgramFiles%\23".to_string())),
+                _ => None,
+            })
+        };
+
+        let root = lookup_bds_root_with("22", &bases, read, env_var).expect("lookup");
+        assert_eq!(root, Some(PathBuf::from(r"C:\Program Files\Studio\22.0\")));
+        let root = lookup_bds_root_with("23.0", &bases, read, env_var).expect("lookup");
+        assert_eq!(root, Some(PathBuf::from(r"%ProgramFiles%\23")));
+        assert_eq!(
+            expand_environment_strings("%Unknown%\\%ProgramFiles%\\50%", env_var),
+            r"%Unknown%\C:\Program Files\50%"
+        );
+    }
+
+    #[test]
+    fn lookup_bds_root_with_prefers_earlier_bases() {
+        let bases = [r"HKCU\BDS", r"HKLM\BDS"];
+        let read = |key_path: &str, _value_name: &str| {
+            Ok(match key_path {
+                r"HKCU\BDS\22.0" => Some(RegistryString::Plain("  ".to_string())),
+                r"HKLM\BDS\22.0" => Some(RegistryString::Plain(r#""D:\hklm""#.to_string())),
+                r"HKLM\BDS\22" => Some(RegistryString::Plain(r"D:\short".to_string())),
+                _ => None,
+            })
+        };
+        let root = lookup_bds_root_with("22.0", &bases, read, |_| None).expect("lookup");
+        assert_eq!(root, Some(PathBuf::from(r"D:\hklm")));
+
+        let err = lookup_bds_root_with(
+            "22",
+            &bases,
+            |_, _| Err(std::io::Error::other("access denied")),
+            |_| None,
+        )
+        .expect_err("expected registry error");
+        assert_eq!(
+            err.to_string(),
+            r"failed to query registry key HKCU\BDS\22: access denied"
+        );
+    }
+
     #[test]
     fn parse_reg_query_value_extracts_root_dir() {
+        let output = r#"
+HKEY_CURRENT_USER\Software\Embarcadero\BDS\22.0\Library\Win32
+    Search Path    REG_SZ    $(BDSLIB)\$(Platform)\release;C:\Components  Two
+"#;
+        assert_eq!(
+            parse_reg_query_value(output, "search path").as_deref(),
+            Some(r"$(BDSLIB)\$(Platform)\release;C:\Components  Two")
+        );
+        assert_eq!(parse_reg_query_value(output, "Search"), None);
+
         let output = r#"
 HKEY_CURRENT_USER\Software\Embarcadero\BDS\22.0
     RootDir    REG_SZ    C:\Program Files (x86)\Embarcadero\Studio\22.0\
@@ -219,9 +883,14 @@ HKEY_CURRENT_USER\Software\Embarcadero\BDS\22.0
         lookup.insert("23.0".to_string(), v23.clone());
 
         let versions = vec!["22".to_string(), "23.0".to_string()];
-        let roots =
-            resolve_source_roots_with_lookup(&versions, |version| Ok(lookup.get(version).cloned()))
-                .expect("resolve roots");
+        let roots = resolve_source_roots_with_lookup(
+            &versions,
+            |version| Ok(lookup.get(version).cloned()),
+            || Ok(Vec::new()),
+            |_| Ok(None),
+        )
+        .expect("resolve roots")
+        .roots;
         let bds22_source = PathBuf::from("bds22").join(SOURCE_DIR_NAME);
         let bds23_source = PathBuf::from("bds23").join(SOURCE_DIR_NAME);
 
@@ -237,9 +906,157 @@ HKEY_CURRENT_USER\Software\Embarcadero\BDS\22.0
         fs::create_dir_all(&v22).expect("create bds22 root");
 
         let versions = vec!["22".to_string()];
-        let err = resolve_source_roots_with_lookup(&versions, |_version| Ok(Some(v22.clone())))
-            .expect_err("expected missing source error");
-        assert!(err.contains("Delphi source path not found"), "{err}");
+        let err = resolve_source_roots_with_lookup(
+            &versions,
+            |_version| Ok(Some(v22.clone())),
+            || Ok(Vec::new()),
+            |_| Ok(None),
+        )
+        .expect_err("expected missing source error");
+        assert!(
+            err.to_string()
+                .contains("Delphi source path for --delphi-version 22 does not exist"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn resolve_source_roots_with_lookup_discovers_installed_versions() {
+        let root = temp_dir("fixdpr_delphi_resolve_auto_");
+        let mut lookup = HashMap::new();
+        for (version, folder, has_source) in [
+            ("9.0", "bds9", true),
+            ("22.0", "bds22", true),
+            ("23.0", "bds23", false),
+        ] {
+            let bds_root = root.join(folder);
+            if has_source {
+                fs::create_dir_all(bds_root.join(SOURCE_DIR_NAME)).expect("create source");
+            }
+            lookup.insert(version.to_string(), bds_root);
+        }
+        let enumerate = || {
+            Ok(vec![
+                "22.0".to_string(),
+                "Globals".to_string(),
+                "9.0".to_string(),
+                "23.0".to_string(),
+            ])
+        };
+        let resolve = |raw: &str| {
+            resolve_source_roots_with_lookup(
+                &[raw.to_string()],
+                |version| Ok(lookup.get(version).cloned()),
+                enumerate,
+                |_| Ok(None),
+            )
+        };
+
+        let auto = resolve("auto").expect("resolve auto");
+        assert_eq!(auto.discovered_versions, vec!["9.0", "22.0"]);
+        assert_eq!(auto.roots.len(), 2);
+
+        let latest = resolve("Latest").expect("resolve latest");
+        assert_eq!(latest.discovered_versions, vec!["22.0"]);
+        assert_eq!(latest.roots.len(), 1);
+        assert!(latest.roots[0].ends_with(PathBuf::from("bds22").join(SOURCE_DIR_NAME)));
+
+        let err = resolve_source_roots_with_lookup(
+            &["auto".to_string()],
+            |_version| Ok(None),
+            enumerate,
+            |_| Ok(None),
+        )
+        .expect_err("expected no installed version");
+        assert_eq!(
+            err.to_string(),
+            "--delphi-version not found in registry: auto"
+        );
+    }
+
+    #[test]
+    fn resolve_source_roots_with_lookup_adds_library_search_path() {
+        let root = temp_dir("fixdpr_delphi_resolve_library_");
+        let bds_root = root.join("bds22");
+        for dir in ["source/rtl", "lib/Win32/release", "imports"] {
+            fs::create_dir_all(bds_root.join(dir)).expect("create bds folder");
+        }
+        let search_path = r#"$(BDS)\source;$(bds)\source\rtl; "$(BDSLIB)\$(Platform)\release" ;$(BDS)\missing;;$(BDSUSERDIR)\imports"#;
+
+        let resolution = resolve_source_roots_with_lookup(
+            &["22".to_string()],
+            |_version| Ok(Some(bds_root.clone())),
+            || Ok(Vec::new()),
+            |version| {
+                assert_eq!(version, "22");
+                Ok(Some(search_path.to_string()))
+            },
+        )
+        .expect("resolve roots");
+
+        assert_eq!(resolution.roots.len(), 1);
+        assert_eq!(resolution.library_roots.len(), 2, "{resolution:?}");
+        assert!(resolution.library_roots[0].ends_with(Path::new("source").join("rtl")));
+        assert!(
+            resolution.library_roots[1].ends_with(Path::new("lib").join("Win32").join("release"))
+        );
+    }
+
+    #[test]
+    fn resolve_source_roots_merges_and_dedupes_explicit_roots() {
+        let root = temp_dir("fixdpr_delphi_resolve_explicit_");
+        let bds_root = root.join("bds");
+        fs::create_dir_all(bds_root.join(SOURCE_DIR_NAME)).expect("create source");
+
+        let options = SourceRootsOptions::builder()
+            .bds_root(&bds_root)
+            .bds_root(bds_root.join(SOURCE_DIR_NAME))
+            .build();
+        let err = resolve_source_roots(&[], &options).expect_err("expected missing nested source");
+        assert!(err.to_string().contains("does not exist"), "{err}");
+
+        let options = SourceRootsOptions::builder()
+            .bds_root(bds_root.join(SOURCE_DIR_NAME))
+            .bds_root(bds_root.join(SOURCE_DIR_NAME).join("."))
+            .roots_are_source(true)
+            .build();
+        let resolution = resolve_source_roots(&[], &options).expect("resolve roots");
+        assert_eq!(resolution.roots.len(), 1);
+        assert!(resolution.roots[0].ends_with(Path::new("bds").join(SOURCE_DIR_NAME)));
+    }
+
+    #[test]
+    fn resolve_source_roots_restricts_to_source_dirs() {
+        let root = temp_dir("fixdpr_delphi_source_dirs_");
+        let source = root.join("bds").join(SOURCE_DIR_NAME);
+        for dir in ["rtl/common", "VCL/Imaging", "fmx"] {
+            fs::create_dir_all(source.join(dir)).expect("create source dir");
+        }
+
+        let options = SourceRootsOptions::builder()
+            .bds_root(root.join("bds"))
+            .source_dir("rtl")
+            .source_dir("vcl/imaging")
+            .source_dir("\\RTL\\")
+            .source_dir("db")
+            .build();
+        let resolution = resolve_source_roots(&[], &options).expect("resolve roots");
+
+        assert_eq!(resolution.roots.len(), 2, "{resolution:?}");
+        assert!(resolution.roots[0].ends_with("rtl"));
+        assert!(resolution.roots[1].ends_with(Path::new("VCL").join("Imaging")));
+        assert_eq!(resolution.missing_source_dirs.len(), 1);
+        assert!(resolution.missing_source_dirs[0].ends_with("db"));
+    }
+
+    #[test]
+    fn parse_reg_query_subkeys_returns_key_names() {
+        let output = r#"
+HKEY_CURRENT_USER\Software\Embarcadero\BDS
+HKEY_CURRENT_USER\Software\Embarcadero\BDS\22.0
+HKEY_CURRENT_USER\Software\Embarcadero\BDS\23.0
+"#;
+        assert_eq!(parse_reg_query_subkeys(output), vec!["BDS", "22.0", "23.0"]);
     }
 
     fn temp_dir(prefix: &str) -> PathBuf {
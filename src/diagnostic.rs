@@ -0,0 +1,324 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DiagnosticCode {
+    ReadFailed,
+    WriteFailed,
+    NoUsesList,
+    UsesParseFailed,
+    MissingInPath,
+    AmbiguousUnit,
+    BrokenUsesPath,
+    UnresolvedUnit,
+    DuplicateUnit,
+    UnitNameFallback,
+    IncludeNotFound,
+    IncludeCycle,
+    IncludeAmbiguous,
+    IncludeSeparator,
+    IncludeFragmentEntry,
+    UnsupportedDirective,
+    UnbalancedConditional,
+    ScanFailed,
+    FixFailed,
+    CacheInvalid,
+    UnmatchedSearchPath,
+    BrokenSymlink,
+    ScanDepthLimited,
+    PermissionDenied,
+    UnreadableEntry,
+    CrossDrivePath,
+    MissingSourceDir,
+    MismatchedUnitPath,
+    UnterminatedComment,
+    UnterminatedString,
+    FileTooLarge,
+    BinaryFile,
+    UnitNotFound,
+    DprLocked,
+    ConflictingUnitPath,
+    DeepRelativePath,
+    DriveRelativePath,
+    HookFailed,
+    UsesListExists,
+    EntryNotRemoved,
+    DependencyCycle,
+    OutsideSearchRoots,
+}
+
+const ALL_CODES: &[DiagnosticCode] = &[
+    DiagnosticCode::ReadFailed,
+    DiagnosticCode::WriteFailed,
+    DiagnosticCode::NoUsesList,
+    DiagnosticCode::UsesParseFailed,
+    DiagnosticCode::MissingInPath,
+    DiagnosticCode::AmbiguousUnit,
+    DiagnosticCode::BrokenUsesPath,
+    DiagnosticCode::UnresolvedUnit,
+    DiagnosticCode::DuplicateUnit,
+    DiagnosticCode::UnitNameFallback,
+    DiagnosticCode::IncludeNotFound,
+    DiagnosticCode::IncludeCycle,
+    DiagnosticCode::IncludeAmbiguous,
+    DiagnosticCode::IncludeSeparator,
+    DiagnosticCode::IncludeFragmentEntry,
+    DiagnosticCode::UnsupportedDirective,
+    DiagnosticCode::UnbalancedConditional,
+    DiagnosticCode::ScanFailed,
+    DiagnosticCode::FixFailed,
+    DiagnosticCode::CacheInvalid,
+    DiagnosticCode::UnmatchedSearchPath,
+    DiagnosticCode::BrokenSymlink,
+    DiagnosticCode::ScanDepthLimited,
+    DiagnosticCode::PermissionDenied,
+    DiagnosticCode::UnreadableEntry,
+    DiagnosticCode::CrossDrivePath,
+    DiagnosticCode::MissingSourceDir,
+    DiagnosticCode::MismatchedUnitPath,
+    DiagnosticCode::UnterminatedComment,
+    DiagnosticCode::UnterminatedString,
+    DiagnosticCode::FileTooLarge,
+    DiagnosticCode::BinaryFile,
+    DiagnosticCode::UnitNotFound,
+    DiagnosticCode::DprLocked,
+    DiagnosticCode::ConflictingUnitPath,
+    DiagnosticCode::DeepRelativePath,
+    DiagnosticCode::DriveRelativePath,
+    DiagnosticCode::HookFailed,
+    DiagnosticCode::UsesListExists,
+    DiagnosticCode::EntryNotRemoved,
+    DiagnosticCode::DependencyCycle,
+    DiagnosticCode::OutsideSearchRoots,
+];
+
+impl DiagnosticCode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ReadFailed => "ReadFailed",
+            Self::WriteFailed => "WriteFailed",
+            Self::NoUsesList => "NoUsesList",
+            Self::UsesParseFailed => "UsesParseFailed",
+            Self::MissingInPath => "MissingInPath",
+            Self::AmbiguousUnit => "AmbiguousUnit",
+            Self::BrokenUsesPath => "BrokenUsesPath",
+            Self::UnresolvedUnit => "UnresolvedUnit",
+            Self::DuplicateUnit => "DuplicateUnit",
+            Self::UnitNameFallback => "UnitNameFallback",
+            Self::IncludeNotFound => "IncludeNotFound",
+            Self::IncludeCycle => "IncludeCycle",
+            Self::IncludeAmbiguous => "IncludeAmbiguous",
+            Self::IncludeSeparator => "IncludeSeparator",
+            Self::IncludeFragmentEntry => "IncludeFragmentEntry",
+            Self::UnsupportedDirective => "UnsupportedDirective",
+            Self::UnbalancedConditional => "UnbalancedConditional",
+            Self::ScanFailed => "ScanFailed",
+            Self::FixFailed => "FixFailed",
+            Self::CacheInvalid => "CacheInvalid",
+            Self::UnmatchedSearchPath => "UnmatchedSearchPath",
+            Self::BrokenSymlink => "BrokenSymlink",
+            Self::ScanDepthLimited => "ScanDepthLimited",
+            Self::PermissionDenied => "PermissionDenied",
+            Self::UnreadableEntry => "UnreadableEntry",
+            Self::CrossDrivePath => "CrossDrivePath",
+            Self::MissingSourceDir => "MissingSourceDir",
+            Self::MismatchedUnitPath => "MismatchedUnitPath",
+            Self::UnterminatedComment => "UnterminatedComment",
+            Self::UnterminatedString => "UnterminatedString",
+            Self::FileTooLarge => "FileTooLarge",
+            Self::BinaryFile => "BinaryFile",
+            Self::UnitNotFound => "UnitNotFound",
+            Self::DprLocked => "DprLocked",
+            Self::ConflictingUnitPath => "ConflictingUnitPath",
+            Self::DeepRelativePath => "DeepRelativePath",
+            Self::DriveRelativePath => "DriveRelativePath",
+            Self::HookFailed => "HookFailed",
+            Self::UsesListExists => "UsesListExists",
+            Self::EntryNotRemoved => "EntryNotRemoved",
+            Self::DependencyCycle => "DependencyCycle",
+            Self::OutsideSearchRoots => "OutsideSearchRoots",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DiagnosticCode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let wanted: String = value
+            .trim()
+            .chars()
+            .filter(|ch| *ch != '-' && *ch != '_')
+            .collect();
+        ALL_CODES
+            .iter()
+            .copied()
+            .find(|code| code.name().eq_ignore_ascii_case(&wanted))
+            .ok_or_else(|| format!("unknown diagnostic code '{}'", value.trim()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+}
+
+impl Severity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub severity: Severity,
+    pub path: Option<PathBuf>,
+    pub offset: Option<usize>,
+    pub message: String,
+    /// The file whose reference raised a warning about another file, such as a missing include.
+    pub referenced_by: Option<PathBuf>,
+}
+
+impl Diagnostic {
+    pub fn warning(code: DiagnosticCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            severity: Severity::Warning,
+            path: None,
+            offset: None,
+            message: message.into(),
+            referenced_by: None,
+        }
+    }
+
+    /// Marks a warning raised by the reference at `offset` in `path`; identical
+    /// warnings from several referencers are then grouped into one.
+    pub fn referenced_from(mut self, path: &Path, offset: Option<usize>) -> Self {
+        self.referenced_by = Some(path.to_path_buf());
+        self.at_offset(path, offset)
+    }
+
+    pub fn at(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+
+    pub fn at_offset(mut self, path: &Path, offset: Option<usize>) -> Self {
+        self.path = Some(path.to_path_buf());
+        self.offset = offset;
+        self
+    }
+
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        let bytes = fs::read(self.path.as_ref()?).ok()?;
+        line_col_at(&bytes, self.offset?)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity.label(), self.message)?;
+        if let Some(referencer) = &self.referenced_by {
+            write!(f, " (referenced by {}", referencer.display())?;
+            if self.path.as_ref() == Some(referencer) {
+                if let Some((line, column)) = self.line_col() {
+                    write!(f, ":{line}:{column}")?;
+                }
+            }
+            return write!(f, ")");
+        }
+        if let (Some(path), Some((line, column))) = (&self.path, self.line_col()) {
+            let name = path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy();
+            write!(f, " ({name}:{line}:{column})")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn line_col_at(bytes: &[u8], offset: usize) -> Option<(usize, usize)> {
+    if offset > bytes.len() {
+        return None;
+    }
+    let before = &bytes[..offset];
+    let line = before.iter().filter(|&&byte| byte == b'\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map(|pos| pos + 1)
+        .unwrap_or(0);
+    Some((line, offset - line_start + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_parses_camel_and_kebab_case() {
+        assert_eq!(
+            "MissingInPath".parse::<DiagnosticCode>(),
+            Ok(DiagnosticCode::MissingInPath)
+        );
+        assert_eq!(
+            "missing-in-path".parse::<DiagnosticCode>(),
+            Ok(DiagnosticCode::MissingInPath)
+        );
+        assert_eq!(
+            "AMBIGUOUS_UNIT".parse::<DiagnosticCode>(),
+            Ok(DiagnosticCode::AmbiguousUnit)
+        );
+        assert!("NotACode".parse::<DiagnosticCode>().is_err());
+    }
+
+    #[test]
+    fn display_reproduces_text_report_format() {
+        let diagnostic =
+            Diagnostic::warning(DiagnosticCode::NoUsesList, "no uses list found in App.dpr");
+        assert_eq!(
+            diagnostic.to_string(),
+            "warning: no uses list found in App.dpr"
+        );
+    }
+
+    #[test]
+    fn display_appends_file_line_and_column_when_offset_is_known() {
+        let root = std::env::temp_dir().join(format!("fixdpr_diagnostic_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let dpr_path = root.join("App.dpr");
+        fs::write(&dpr_path, "program App;\nuses\n  Foo;\nbegin\nend.\n").unwrap();
+
+        let diagnostic = Diagnostic::warning(DiagnosticCode::MissingInPath, "missing in-path")
+            .at_offset(&dpr_path, Some(20));
+        assert_eq!(diagnostic.line_col(), Some((3, 3)));
+        assert_eq!(
+            diagnostic.to_string(),
+            "warning: missing in-path (App.dpr:3:3)"
+        );
+
+        let without_offset =
+            Diagnostic::warning(DiagnosticCode::MissingInPath, "missing in-path").at(&dpr_path);
+        assert_eq!(without_offset.to_string(), "warning: missing in-path");
+    }
+
+    #[test]
+    fn line_col_at_counts_from_one() {
+        let src = b"program App;\nuses\n  Foo;\n";
+        assert_eq!(line_col_at(src, 0), Some((1, 1)));
+        assert_eq!(line_col_at(src, 20), Some((3, 3)));
+        assert_eq!(line_col_at(src, 100), None);
+    }
+}
@@ -1,2594 +1,9165 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use crate::conditionals::{self, Assumptions, EvalResult};
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::dpr_lock::{self, DprLock};
+use crate::events::EventSink;
+use crate::fs_walk;
+use crate::graph::{self, UnitEdges, UnitGraph};
 use crate::pas_lex;
-use crate::unit_cache::{self, UnitCache, UnitFileInfo};
-use crate::uses_include;
+use crate::pas_parse::{Section, UnitName};
+use crate::unit_cache::{self, CanonicalPaths, LazyUnitCache, UnitCache, UnitFileInfo};
+use crate::uses_include::{self, Includes};
+use crate::warnings::Warnings;
 
 #[derive(Debug)]
 pub struct DprUpdateSummary {
     pub scanned: usize,
     pub updated: usize,
     pub updated_paths: Vec<PathBuf>,
-    pub warnings: Vec<String>,
+    pub warnings: Warnings,
+    /// Notes that are not warnings, such as a placement fallback.
+    pub infos: Vec<String>,
+    /// Delphi-cache units left out because of [`FixOptions::no_delphi_inserts`].
+    pub suppressed_delphi_inserts: usize,
+    /// Names found in no unit cache, with [`FixOptions::report_unresolved`]; sorted by name.
+    pub unresolved: Vec<UnresolvedReference>,
     pub failures: usize,
+    pub failed_paths: Vec<PathBuf>,
+    pub details: Vec<DprDetail>,
+    /// Time spent walking unit dependency graphs, summed over all dprs.
+    pub graph_time: Duration,
+    /// Time spent writing dpr files, summed over all dprs.
+    pub write_time: Duration,
+    /// In a dry run, the contents each changed dpr would have been written with.
+    pub staged: HashMap<PathBuf, Vec<u8>>,
+    open_detail: Option<(usize, Instant)>,
+    events: EventHandle,
 }
 
-#[derive(Debug)]
-struct UsesEntry {
-    name: String,
-    in_path: Option<String>,
-    start: usize,
-    delimiter: Option<u8>,
-    delimiter_pos: Option<usize>,
-    from_include: bool,
+#[derive(Clone, Default)]
+struct EventHandle(Option<Rc<dyn EventSink>>);
+
+impl fmt::Debug for EventHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "EventHandle(Some)"
+        } else {
+            "EventHandle(None)"
+        })
+    }
 }
 
-#[derive(Debug)]
-struct UsesList {
-    entries: Vec<UsesEntry>,
-    semicolon: usize,
-    multiline: bool,
-    indent: String,
-    has_backslash: bool,
-    has_slash: bool,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DprStatus {
+    Updated,
+    /// Updated, but [`FixOptions::max_inserts`] left missing units out.
+    Partial,
+    Unchanged,
+    Failed,
+    Ignored,
+    Filtered,
 }
 
-pub fn update_dpr_files(
-    dpr_paths: &[PathBuf],
-    project_cache: &mut UnitCache,
-    mut delphi_cache: Option<&mut UnitCache>,
-    new_unit: &UnitFileInfo,
-    add_introduced_dependencies: bool,
-    assumptions: &Assumptions,
-) -> io::Result<DprUpdateSummary> {
-    let mut summary = DprUpdateSummary {
-        scanned: 0,
-        updated: 0,
-        updated_paths: Vec::new(),
-        warnings: Vec::new(),
-        failures: 0,
-    };
+impl DprStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Updated => "updated",
+            Self::Partial => "partial",
+            Self::Unchanged => "unchanged",
+            Self::Failed => "failed",
+            Self::Ignored => "ignored",
+            Self::Filtered => "filtered",
+        }
+    }
 
-    'dpr_loop: for path in dpr_paths {
-        summary.scanned += 1;
-        let bytes = match fs::read(path) {
-            Ok(data) => data,
-            Err(err) => {
-                summary.warnings.push(format!(
-                    "warning: failed to read dpr {}: {err}",
-                    path.display()
-                ));
-                summary.failures += 1;
-                continue;
+    /// The label for a run that only staged the changes, such as a dry run:
+    /// a dpr that would have been written is `would-update`.
+    pub fn staged_label(self) -> &'static str {
+        match self {
+            Self::Updated | Self::Partial => "would-update",
+            other => other.label(),
+        }
+    }
+}
+
+/// Where a dpr stood with the new dependency of [`update_dpr_files_with`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DependencyOutcome {
+    /// The new dependency was inserted.
+    Inserted,
+    /// The dpr already listed the new dependency.
+    AlreadyPresent,
+    /// No unit listed by the dpr uses the new dependency.
+    NotNeeded,
+}
+
+impl DependencyOutcome {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Inserted => "inserted",
+            Self::AlreadyPresent => "already present",
+            Self::NotNeeded => "not needed",
+        }
+    }
+}
+
+/// A uses reference that resolves to no file in either unit cache.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnresolvedReference {
+    pub name: String,
+    /// The dpr and units whose uses clauses name it, sorted.
+    pub referenced_by: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InsertedUnit {
+    pub name: String,
+    pub in_path: Option<String>,
+    /// The unit's name was guessed from its file stem.
+    pub name_from_stem: bool,
+    /// The unit cache the unit was found in; `None` for entries copied from another dpr.
+    pub source: Option<ResolutionSource>,
+    /// The file the entry points at; `None` for entries copied from another dpr.
+    pub path: Option<PathBuf>,
+    /// For `fix-dpr`, the units from a dpr entry down to the one using this
+    /// unit, in that order. Empty for other modes.
+    pub chain: Vec<String>,
+}
+
+/// A dpr entry whose in-path names no file and whose unit resolves in no
+/// unit cache, found by [`fix_dpr_file_with`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingEntry {
+    pub name: String,
+    pub in_path: String,
+    /// Removed from the dpr, with [`FixOptions::remove_missing`].
+    pub removed: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct DprDetail {
+    pub path: PathBuf,
+    pub status: DprStatus,
+    pub inserted: Vec<InsertedUnit>,
+    pub warnings: Vec<Diagnostic>,
+    /// Missing units not inserted because of [`FixOptions::max_inserts`].
+    pub not_inserted: usize,
+    /// Entries pointing at unit files that no longer exist, in list order.
+    pub missing_entries: Vec<MissingEntry>,
+    /// Set by [`update_dpr_files_with`] once it knows whether the dpr needs
+    /// the new dependency; `None` for other modes and for dprs that failed first.
+    pub dependency: Option<DependencyOutcome>,
+    /// Wall-clock time spent processing this dpr.
+    pub elapsed: Duration,
+}
+
+impl DprDetail {
+    pub fn new(path: &Path, status: DprStatus) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            status,
+            inserted: Vec::new(),
+            warnings: Vec::new(),
+            not_inserted: 0,
+            missing_entries: Vec::new(),
+            dependency: None,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl DprUpdateSummary {
+    pub(crate) fn new() -> Self {
+        Self {
+            scanned: 0,
+            updated: 0,
+            updated_paths: Vec::new(),
+            warnings: Warnings::new(),
+            infos: Vec::new(),
+            suppressed_delphi_inserts: 0,
+            unresolved: Vec::new(),
+            failures: 0,
+            failed_paths: Vec::new(),
+            details: Vec::new(),
+            graph_time: Duration::ZERO,
+            write_time: Duration::ZERO,
+            staged: HashMap::new(),
+            open_detail: None,
+            events: EventHandle::default(),
+        }
+    }
+
+    fn record_failure(&mut self, path: &Path) {
+        self.failures += 1;
+        if !self.failed_paths.iter().any(|failed| failed == path) {
+            self.failed_paths.push(path.to_path_buf());
+        }
+    }
+
+    fn record_unresolved(&mut self, name: &str, path: &Path) {
+        let key = name.to_ascii_lowercase();
+        let index = match self
+            .unresolved
+            .binary_search_by(|reference| reference.name.to_ascii_lowercase().cmp(&key))
+        {
+            Ok(index) => index,
+            Err(index) => {
+                self.unresolved.insert(
+                    index,
+                    UnresolvedReference {
+                        name: name.to_string(),
+                        referenced_by: Vec::new(),
+                    },
+                );
+                index
             }
         };
-        let Some(list) = parse_dpr_uses(path, &bytes, &mut summary.warnings) else {
-            summary
-                .warnings
-                .push(format!("warning: no uses list found in {}", path.display()));
-            summary.failures += 1;
-            continue;
+        let referenced_by = &mut self.unresolved[index].referenced_by;
+        if let Err(position) = referenced_by.binary_search_by(|known| known.as_path().cmp(path)) {
+            referenced_by.insert(position, path.to_path_buf());
+        }
+    }
+
+    /// Sorts `updated_paths` and `failed_paths`, so reports do not depend on
+    /// the order the dprs were handed in or found on disk.
+    pub fn sort_paths(&mut self) {
+        self.updated_paths.sort();
+        self.failed_paths.sort();
+    }
+
+    fn finish(&mut self) {
+        self.finish_dpr();
+        self.sort_paths();
+    }
+
+    fn record_updated(&mut self, path: &Path) {
+        self.updated += 1;
+        self.updated_paths.push(path.to_path_buf());
+    }
+
+    fn begin_dpr(&mut self, path: &Path) {
+        self.finish_dpr();
+        self.details
+            .push(DprDetail::new(path, DprStatus::Unchanged));
+        self.open_detail = Some((self.warnings.len(), Instant::now()));
+    }
+
+    fn finish_dpr(&mut self) {
+        let Some((warnings_start, started)) = self.open_detail.take() else {
+            return;
         };
-        let mut current_bytes = bytes;
-        let mut current_list = list;
-        let active_root_names = collect_active_dpr_entry_names(
-            path,
-            &current_bytes,
-            assumptions,
-            &mut summary.warnings,
-        );
+        let Some(detail) = self.details.last_mut() else {
+            return;
+        };
+        detail.elapsed = started.elapsed();
+        detail.warnings = self.warnings.iter().skip(warnings_start).cloned().collect();
+        detail.status = if self.failed_paths.contains(&detail.path) {
+            DprStatus::Failed
+        } else if detail.not_inserted > 0 {
+            DprStatus::Partial
+        } else if self.updated_paths.contains(&detail.path) {
+            DprStatus::Updated
+        } else {
+            DprStatus::Unchanged
+        };
+        if let Some(events) = &self.events.0 {
+            for warning in &detail.warnings {
+                events.on_warning(warning);
+            }
+            events.on_dpr_finished(detail);
+        }
+    }
 
-        let project_map = build_project_map(
-            path,
-            &current_list,
-            project_cache,
-            delphi_cache.as_deref(),
-            &mut summary.warnings,
+    fn record_inserted(&mut self, list: &UsesList, unit: &UnitFileInfo, source: ResolutionSource) {
+        self.record_inserted_name(
+            list,
+            &unit.name,
+            unit.name_from_stem,
+            Some(source),
+            Some(&unit.path),
         );
-        let has_new_unit = current_list
+    }
+
+    fn record_inserted_name(
+        &mut self,
+        list: &UsesList,
+        name: &str,
+        name_from_stem: bool,
+        source: Option<ResolutionSource>,
+        path: Option<&Path>,
+    ) {
+        let Some(detail) = self.details.last_mut() else {
+            return;
+        };
+        let in_path = list
             .entries
             .iter()
-            .any(|entry| entry.name.eq_ignore_ascii_case(&new_unit.name));
-        let has_active_new_unit = active_root_names.as_ref().map_or(has_new_unit, |names| {
-            names.contains(&new_unit.name.to_ascii_lowercase())
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+            .and_then(|entry| entry.in_path.clone());
+        detail.inserted.push(InsertedUnit {
+            name: name.to_string(),
+            in_path,
+            name_from_stem,
+            source,
+            path: path.map(Path::to_path_buf),
+            chain: Vec::new(),
         });
+    }
 
-        let mut needs_new_unit = false;
-        let mut insert_after = None;
-        if !has_new_unit {
-            if project_map.is_empty() {
-                continue;
-            }
+    fn record_dependency(&mut self, outcome: DependencyOutcome) {
+        if let Some(detail) = self.details.last_mut() {
+            detail.dependency = Some(outcome);
+        }
+    }
 
-            let dependents = compute_project_dependents(
-                project_cache,
-                delphi_cache.as_deref_mut(),
-                &project_map,
-                new_unit,
-                assumptions,
-                &mut summary.warnings,
-            )?;
+    /// Dprs whose [`DprDetail::dependency`] is `outcome`.
+    pub fn dependency_count(&self, outcome: DependencyOutcome) -> usize {
+        self.details
+            .iter()
+            .filter(|detail| detail.dependency == Some(outcome))
+            .count()
+    }
 
-            for entry in &current_list.entries {
-                if !is_active_dpr_entry(active_root_names.as_ref(), entry) {
-                    continue;
-                }
-                let key = entry.name.to_ascii_lowercase();
-                if let Some(path) = project_map.get(&key) {
-                    if let Some(&id) = dependents.id_by_path.get(path) {
-                        if dependents.dependents[id] {
-                            needs_new_unit = true;
-                            break;
-                        }
-                    }
-                }
-            }
+    /// Units inserted across all dprs that were found in the `source` unit cache.
+    pub fn inserted_from(&self, source: ResolutionSource) -> usize {
+        self.details
+            .iter()
+            .flat_map(|detail| &detail.inserted)
+            .filter(|unit| unit.source == Some(source))
+            .count()
+    }
 
-            if !needs_new_unit {
-                continue;
-            }
-            insert_after = find_direct_introducer_index(
-                &current_list,
-                &project_map,
-                &dependents,
-                active_root_names.as_ref(),
-            );
+    pub fn merge_detail(&mut self, detail: DprDetail) {
+        let Some(existing) = self
+            .details
+            .iter_mut()
+            .find(|existing| existing.path == detail.path)
+        else {
+            self.details.push(detail);
+            return;
+        };
+        existing.status = match (existing.status, detail.status) {
+            (DprStatus::Failed, _) | (_, DprStatus::Failed) => DprStatus::Failed,
+            (DprStatus::Partial, _) | (_, DprStatus::Partial) => DprStatus::Partial,
+            (DprStatus::Updated, _) | (_, DprStatus::Updated) => DprStatus::Updated,
+            (status, _) => status,
+        };
+        existing.inserted.extend(detail.inserted);
+        existing.warnings.extend(detail.warnings);
+        existing.not_inserted += detail.not_inserted;
+        existing.missing_entries.extend(detail.missing_entries);
+        existing.dependency = existing.dependency.or(detail.dependency);
+        existing.elapsed += detail.elapsed;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InsertPosition {
+    /// Insert after the entry that introduces the dependency, or at the end.
+    #[default]
+    AfterIntroducer,
+    End,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Reuse the separator already used by the uses list.
+    #[default]
+    MatchList,
+    Backslash,
+    Slash,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CrossDrive {
+    /// Write the unit's absolute path and note it in the warnings.
+    #[default]
+    Absolute,
+    /// Leave the entry out and warn.
+    Skip,
+    /// Fail the dpr.
+    Error,
+}
+
+impl FromStr for CrossDrive {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "absolute" => Ok(Self::Absolute),
+            "skip" => Ok(Self::Skip),
+            "error" => Ok(Self::Error),
+            other => Err(format!(
+                "--cross-drive must be absolute, skip, or error, got '{other}'"
+            )),
         }
+    }
+}
 
-        let mut dpr_updated = false;
-        let mut last_inserted_name = None;
+/// What to do when a relative in-path climbs more parent folders than allowed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeepRelative {
+    /// Write the unit's absolute path and note it in the warnings.
+    #[default]
+    Absolute,
+    /// Leave the entry out and warn.
+    Skip,
+    /// Keep the relative path and warn.
+    Allow,
+}
 
-        if needs_new_unit {
-            let updated = match insert_new_unit(
-                &current_bytes,
-                path,
-                &current_list,
-                new_unit,
-                insert_after,
-            ) {
-                Ok(value) => value,
-                Err(err) => {
-                    summary.warnings.push(format!(
-                        "warning: failed to update dpr {}: {err}",
-                        path.display()
-                    ));
-                    summary.failures += 1;
-                    continue;
-                }
-            };
-            if updated {
-                dpr_updated = true;
-                last_inserted_name = Some(new_unit.name.clone());
-                let reloaded = match reload_dpr_state(path, &mut summary.warnings) {
-                    Ok(Some(value)) => value,
-                    Ok(None) => {
-                        summary
-                            .warnings
-                            .push(format!("warning: no uses list found in {}", path.display()));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                    Err(err) => {
-                        summary.warnings.push(format!(
-                            "warning: failed to read dpr {}: {err}",
-                            path.display()
-                        ));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                };
-                current_bytes = reloaded.0;
-                current_list = reloaded.1;
-            }
+impl FromStr for DeepRelative {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "absolute" => Ok(Self::Absolute),
+            "skip" => Ok(Self::Skip),
+            "allow" => Ok(Self::Allow),
+            other => Err(format!(
+                "--on-deep-relative must be absolute, skip, or allow, got '{other}'"
+            )),
         }
+    }
+}
 
-        if add_introduced_dependencies && (needs_new_unit || has_active_new_unit) {
-            let introduced = collect_introduced_dependencies(
-                project_cache,
-                delphi_cache.as_deref_mut(),
-                &project_map,
-                new_unit,
-                assumptions,
-                &mut summary.warnings,
-            )?;
-            if has_new_unit && last_inserted_name.is_none() {
-                last_inserted_name = Some(new_unit.name.clone());
-            }
+/// Which of the units NEW_DEPENDENCY uses are inserted along with it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IntroducedScope {
+    /// Every unit it uses, directly or through other introduced units.
+    #[default]
+    All,
+    /// Only units of the project cache; Delphi units are neither inserted nor followed.
+    ProjectOnly,
+    /// None; only NEW_DEPENDENCY itself is inserted.
+    None,
+}
 
-            for dep_unit in introduced {
-                if current_list
-                    .entries
-                    .iter()
-                    .any(|entry| entry.name.eq_ignore_ascii_case(&dep_unit.name))
-                {
-                    continue;
-                }
+impl FromStr for IntroducedScope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "project-only" => Ok(Self::ProjectOnly),
+            "none" => Ok(Self::None),
+            other => Err(format!(
+                "--introduced-scope must be all, project-only, or none, got '{other}'"
+            )),
+        }
+    }
+}
 
-                let dep_insert_after = last_inserted_name.as_ref().and_then(|name| {
-                    current_list.entries.iter().position(|entry| {
-                        !entry.from_include && entry.name.eq_ignore_ascii_case(name)
-                    })
-                });
-                let dep_updated = match insert_new_unit(
-                    &current_bytes,
-                    path,
-                    &current_list,
-                    &dep_unit,
-                    dep_insert_after,
-                ) {
-                    Ok(value) => value,
-                    Err(err) => {
-                        summary.warnings.push(format!(
-                            "warning: failed to update dpr {}: {err}",
-                            path.display()
-                        ));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                };
-                if !dep_updated {
-                    continue;
-                }
+/// How often a dpr write is retried when another program briefly holds the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteRetry {
+    /// Tries in total, including the first.
+    pub attempts: u32,
+    /// Wait before the second try; doubled before each later one.
+    pub backoff: Duration,
+}
 
-                dpr_updated = true;
-                last_inserted_name = Some(dep_unit.name);
-                let reloaded = match reload_dpr_state(path, &mut summary.warnings) {
-                    Ok(Some(value)) => value,
-                    Ok(None) => {
-                        summary
-                            .warnings
-                            .push(format!("warning: no uses list found in {}", path.display()));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                    Err(err) => {
-                        summary.warnings.push(format!(
-                            "warning: failed to read dpr {}: {err}",
-                            path.display()
-                        ));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                };
-                current_bytes = reloaded.0;
-                current_list = reloaded.1;
-            }
+impl Default for WriteRetry {
+    /// Five tries spread over about two seconds.
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(125),
         }
+    }
+}
 
-        if dpr_updated {
-            summary.updated += 1;
-            summary.updated_paths.push(path.clone());
+/// Which entry `fix-dpr` keeps when a unit is listed more than once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    KeepFirst,
+    /// The first entry whose path exists, else the first entry.
+    KeepResolving,
+    /// Like `KeepFirst`, but only entries naming the same file as the kept one are removed.
+    RemoveExtra,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "keep-first" => Ok(Self::KeepFirst),
+            "keep-resolving" => Ok(Self::KeepResolving),
+            "remove-extra" => Ok(Self::RemoveExtra),
+            other => Err(format!(
+                "--fix-duplicates must be keep-first, keep-resolving, or remove-extra, got '{other}'"
+            )),
         }
     }
+}
 
-    Ok(summary)
+#[derive(Clone, Copy)]
+enum ListRewrite {
+    Duplicates,
+    MismatchedPaths,
 }
 
-pub fn insert_dependency_files(
-    dpr_paths: &[PathBuf],
-    project_cache: &mut UnitCache,
-    mut delphi_cache: Option<&mut UnitCache>,
-    new_unit: &UnitFileInfo,
-    add_introduced_dependencies: bool,
-    assumptions: &Assumptions,
-) -> io::Result<DprUpdateSummary> {
-    let mut summary = DprUpdateSummary {
-        scanned: 0,
-        updated: 0,
-        updated_paths: Vec::new(),
-        warnings: Vec::new(),
-        failures: 0,
-    };
+pub type ProgressFn = Rc<dyn Fn(usize, usize, &Path)>;
 
-    'dpr_loop: for path in dpr_paths {
-        summary.scanned += 1;
-        let bytes = match fs::read(path) {
-            Ok(data) => data,
-            Err(err) => {
-                summary.warnings.push(format!(
-                    "warning: failed to read dpr {}: {err}",
-                    path.display()
-                ));
-                summary.failures += 1;
-                continue;
-            }
-        };
+#[derive(Clone, Debug, Default)]
+struct DependencyLimits {
+    excluded_units: HashSet<String>,
+    max_depth: Option<usize>,
+    skip_delphi_units: bool,
+    /// Order introduced units so each follows the units it uses.
+    topo_order: bool,
+    introduced_scope: IntroducedScope,
+}
 
-        let mut current_bytes = bytes;
-        let parsed_list = parse_dpr_uses(path, &current_bytes, &mut summary.warnings);
-        let mut current_list = match parsed_list {
-            Some(list) => list,
-            None => {
-                if dpr_has_uses_keyword(&current_bytes) {
-                    summary.warnings.push(format!(
-                        "warning: failed to parse existing uses list in {}",
-                        path.display()
-                    ));
-                    summary.failures += 1;
-                    continue;
-                }
+impl DependencyLimits {
+    fn is_excluded(&self, name: &str) -> bool {
+        self.excluded_units.contains(&name.to_ascii_lowercase())
+    }
 
-                let created =
-                    match create_uses_section(&current_bytes, path, std::slice::from_ref(new_unit))
-                    {
-                        Ok(value) => value,
-                        Err(err) => {
-                            summary.warnings.push(format!(
-                                "warning: failed to create uses section in {}: {err}",
-                                path.display()
-                            ));
-                            summary.failures += 1;
-                            continue;
-                        }
-                    };
-                if !created {
-                    continue;
-                }
+    fn allows_depth(&self, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth <= max_depth)
+    }
 
-                let reloaded = match reload_dpr_state(path, &mut summary.warnings) {
-                    Ok(Some(value)) => value,
-                    Ok(None) => {
-                        summary
-                            .warnings
-                            .push(format!("warning: no uses list found in {}", path.display()));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                    Err(err) => {
-                        summary.warnings.push(format!(
-                            "warning: failed to read dpr {}: {err}",
-                            path.display()
-                        ));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                };
-                current_bytes = reloaded.0;
-                let mut current_list = reloaded.1;
-                let mut dpr_updated = true;
-                let mut last_inserted_name = Some(new_unit.name.clone());
+    fn outcome_key(&self) -> String {
+        let Self {
+            excluded_units,
+            max_depth,
+            skip_delphi_units,
+            topo_order,
+            introduced_scope,
+        } = self;
+        let mut excluded_units: Vec<&String> = excluded_units.iter().collect();
+        excluded_units.sort();
+        format!("{excluded_units:?} {max_depth:?} {skip_delphi_units} {topo_order} {introduced_scope:?}")
+    }
+}
 
-                if add_introduced_dependencies {
-                    let project_map = build_project_map(
-                        path,
-                        &current_list,
-                        project_cache,
-                        delphi_cache.as_deref(),
-                        &mut summary.warnings,
-                    );
-                    let introduced = collect_introduced_dependencies(
-                        project_cache,
-                        delphi_cache.as_deref_mut(),
-                        &project_map,
-                        new_unit,
-                        assumptions,
-                        &mut summary.warnings,
-                    )?;
+/// The default unit scope names of a Delphi VCL application for Win32.
+pub const DEFAULT_NAMESPACE_PREFIXES: &[&str] = &[
+    "Winapi",
+    "System.Win",
+    "Data.Win",
+    "Datasnap.Win",
+    "Web.Win",
+    "Soap.Win",
+    "Xml.Win",
+    "Bde",
+    "System",
+    "Xml",
+    "Data",
+    "Datasnap",
+    "Web",
+    "Soap",
+    "Vcl",
+    "Vcl.Imaging",
+    "Vcl.Touch",
+    "Vcl.Samples",
+    "Vcl.Shell",
+];
+
+/// How dprs are edited and written, shared by [`UpdateOptions`] and [`FixOptions`].
+#[derive(Clone, Default)]
+pub struct EditOptions {
+    dry_run: bool,
+    insert_position: InsertPosition,
+    path_style: PathStyle,
+    cross_drive: CrossDrive,
+    max_parent_dirs: Option<usize>,
+    on_deep_relative: DeepRelative,
+    namespace_prefixes: Option<Vec<String>>,
+    indent: Option<String>,
+    wrap_at: Option<usize>,
+    insert_in_conditional: bool,
+    known_roots: Vec<PathBuf>,
+    max_file_size: Option<u64>,
+    lock_timeout: Option<Duration>,
+    write_retry: WriteRetry,
+    progress: Option<ProgressFn>,
+    events: Option<Rc<dyn EventSink>>,
+}
 
-                    for dep_unit in introduced {
-                        if current_list
-                            .entries
-                            .iter()
-                            .any(|entry| entry.name.eq_ignore_ascii_case(&dep_unit.name))
-                        {
-                            continue;
-                        }
+impl EditOptions {
+    pub fn builder() -> Self {
+        Self::default()
+    }
 
-                        let dep_insert_after = last_inserted_name.as_ref().and_then(|name| {
-                            current_list.entries.iter().position(|entry| {
-                                !entry.from_include && entry.name.eq_ignore_ascii_case(name)
-                            })
-                        });
-                        let dep_updated = match insert_new_unit(
-                            &current_bytes,
-                            path,
-                            &current_list,
-                            &dep_unit,
-                            dep_insert_after,
-                        ) {
-                            Ok(value) => value,
-                            Err(err) => {
-                                summary.warnings.push(format!(
-                                    "warning: failed to update dpr {}: {err}",
-                                    path.display()
-                                ));
-                                summary.failures += 1;
-                                continue 'dpr_loop;
-                            }
-                        };
-                        if !dep_updated {
-                            continue;
-                        }
+    /// Compute the edits without writing any file.
+    pub fn dry_run(mut self, value: bool) -> Self {
+        self.dry_run = value;
+        self
+    }
 
-                        dpr_updated = true;
-                        last_inserted_name = Some(dep_unit.name);
-                        let reloaded = match reload_dpr_state(path, &mut summary.warnings) {
-                            Ok(Some(value)) => value,
-                            Ok(None) => {
-                                summary.warnings.push(format!(
-                                    "warning: no uses list found in {}",
-                                    path.display()
-                                ));
-                                summary.failures += 1;
-                                continue 'dpr_loop;
-                            }
-                            Err(err) => {
-                                summary.warnings.push(format!(
-                                    "warning: failed to read dpr {}: {err}",
-                                    path.display()
-                                ));
-                                summary.failures += 1;
-                                continue 'dpr_loop;
-                            }
-                        };
-                        current_bytes = reloaded.0;
-                        current_list = reloaded.1;
-                    }
-                }
+    pub fn insert_position(mut self, value: InsertPosition) -> Self {
+        self.insert_position = value;
+        self
+    }
 
-                if dpr_updated {
-                    summary.updated += 1;
-                    summary.updated_paths.push(path.clone());
-                }
-                continue;
-            }
-        };
+    pub fn path_style(mut self, value: PathStyle) -> Self {
+        self.path_style = value;
+        self
+    }
 
-        let mut dpr_updated = false;
-        let active_root_names = collect_active_dpr_entry_names(
-            path,
-            &current_bytes,
-            assumptions,
-            &mut summary.warnings,
-        );
-        let has_new_unit = current_list
-            .entries
-            .iter()
-            .any(|entry| entry.name.eq_ignore_ascii_case(&new_unit.name));
-        let has_active_new_unit = active_root_names.as_ref().map_or(has_new_unit, |names| {
-            names.contains(&new_unit.name.to_ascii_lowercase())
-        });
-        let mut last_inserted_name = None;
+    /// What to do when a unit lives on a different drive than the dpr.
+    pub fn cross_drive(mut self, value: CrossDrive) -> Self {
+        self.cross_drive = value;
+        self
+    }
 
-        if !has_new_unit {
-            let updated = match insert_new_unit(&current_bytes, path, &current_list, new_unit, None)
-            {
-                Ok(value) => value,
-                Err(err) => {
-                    summary.warnings.push(format!(
-                        "warning: failed to update dpr {}: {err}",
-                        path.display()
-                    ));
-                    summary.failures += 1;
-                    continue;
-                }
-            };
-            if updated {
-                dpr_updated = true;
-                last_inserted_name = Some(new_unit.name.clone());
-                let reloaded = match reload_dpr_state(path, &mut summary.warnings) {
-                    Ok(Some(value)) => value,
-                    Ok(None) => {
-                        summary
-                            .warnings
-                            .push(format!("warning: no uses list found in {}", path.display()));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                    Err(err) => {
-                        summary.warnings.push(format!(
-                            "warning: failed to read dpr {}: {err}",
-                            path.display()
-                        ));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                };
-                current_bytes = reloaded.0;
-                current_list = reloaded.1;
-            }
-        }
+    /// Apply `policy` to relative in-paths starting with more than `max` `..` folders.
+    pub fn max_parent_dirs(mut self, max: Option<usize>, policy: DeepRelative) -> Self {
+        self.max_parent_dirs = max;
+        self.on_deep_relative = policy;
+        self
+    }
 
-        if add_introduced_dependencies && (dpr_updated || has_active_new_unit) {
-            let project_map = build_project_map(
-                path,
-                &current_list,
-                project_cache,
-                delphi_cache.as_deref(),
-                &mut summary.warnings,
-            );
-            let introduced = collect_introduced_dependencies(
-                project_cache,
-                delphi_cache.as_deref_mut(),
-                &project_map,
-                new_unit,
-                assumptions,
-                &mut summary.warnings,
-            )?;
-            if has_active_new_unit && last_inserted_name.is_none() {
-                last_inserted_name = Some(new_unit.name.clone());
-            }
+    /// Unit scope names, such as `Vcl`, under which `Vcl.Dialogs` and `Dialogs`
+    /// name the same unit. A unit is not inserted when the dpr lists such a
+    /// variant of it. `None` uses [`DEFAULT_NAMESPACE_PREFIXES`].
+    pub fn namespace_prefixes(mut self, prefixes: Option<Vec<String>>) -> Self {
+        self.namespace_prefixes = prefixes;
+        self
+    }
 
-            for dep_unit in introduced {
-                if current_list
-                    .entries
-                    .iter()
-                    .any(|entry| entry.name.eq_ignore_ascii_case(&dep_unit.name))
-                {
-                    continue;
-                }
+    /// Indentation for entries inserted on a line of their own, replacing the
+    /// one inferred from the uses list. `None` keeps the inferred indentation.
+    pub fn indent(mut self, indent: Option<String>) -> Self {
+        self.indent = indent;
+        self
+    }
 
-                let dep_insert_after = last_inserted_name.as_ref().and_then(|name| {
-                    current_list.entries.iter().position(|entry| {
-                        !entry.from_include && entry.name.eq_ignore_ascii_case(name)
-                    })
-                });
-                let dep_updated = match insert_new_unit(
-                    &current_bytes,
-                    path,
-                    &current_list,
-                    &dep_unit,
-                    dep_insert_after,
-                ) {
-                    Ok(value) => value,
-                    Err(err) => {
-                        summary.warnings.push(format!(
-                            "warning: failed to update dpr {}: {err}",
-                            path.display()
-                        ));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                };
-                if !dep_updated {
-                    continue;
-                }
+    /// Rewrite a single-line uses list to one entry per line once an insert
+    /// makes its line longer than this many columns.
+    pub fn wrap_at(mut self, width: Option<usize>) -> Self {
+        self.wrap_at = width;
+        self
+    }
 
-                dpr_updated = true;
-                last_inserted_name = Some(dep_unit.name);
-                let reloaded = match reload_dpr_state(path, &mut summary.warnings) {
-                    Ok(Some(value)) => value,
-                    Ok(None) => {
-                        summary
-                            .warnings
-                            .push(format!("warning: no uses list found in {}", path.display()));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                    Err(err) => {
-                        summary.warnings.push(format!(
-                            "warning: failed to read dpr {}: {err}",
-                            path.display()
-                        ));
-                        summary.failures += 1;
-                        continue 'dpr_loop;
-                    }
-                };
-                current_bytes = reloaded.0;
-                current_list = reloaded.1;
-            }
-        }
+    /// Insert after an anchor entry even when it sits inside an `{$IFDEF}`
+    /// region. By default such units go to the end of the list instead, so
+    /// builds without the symbol still get them.
+    pub fn insert_in_conditional(mut self, value: bool) -> Self {
+        self.insert_in_conditional = value;
+        self
+    }
 
-        if dpr_updated {
-            summary.updated += 1;
-            summary.updated_paths.push(path.clone());
-        }
+    /// Folders that later scans cover, such as the search and Delphi roots.
+    /// Writing an in-path to a unit outside all of them raises an
+    /// `OutsideSearchRoots` warning. Empty skips the check.
+    pub fn known_roots(mut self, roots: Vec<PathBuf>) -> Self {
+        self.known_roots = roots;
+        self
     }
 
-    Ok(summary)
-}
+    /// Skip dprs larger than `bytes`; 0 removes the limit. Units are read with
+    /// the [`UnitCache::source`] of the cache they are looked up in.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
 
-pub fn fix_dpr_file(
-    dpr_path: &Path,
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    assumptions: &Assumptions,
-) -> io::Result<DprUpdateSummary> {
-    let dpr_path = unit_cache::canonicalize_if_exists(dpr_path);
-    let mut summary = DprUpdateSummary {
-        scanned: 1,
-        updated: 0,
-        updated_paths: Vec::new(),
-        warnings: Vec::new(),
-        failures: 0,
-    };
+    /// Wait this long for another process editing a dpr before counting it as failed.
+    pub fn lock_timeout(mut self, value: Duration) -> Self {
+        self.lock_timeout = Some(value);
+        self
+    }
 
-    let bytes = match fs::read(&dpr_path) {
-        Ok(data) => data,
-        Err(err) => {
-            summary.warnings.push(format!(
-                "warning: failed to read dpr {}: {err}",
-                dpr_path.display()
-            ));
-            summary.failures += 1;
-            return Ok(summary);
-        }
-    };
-    let Some(list) = parse_dpr_uses(&dpr_path, &bytes, &mut summary.warnings) else {
-        summary.warnings.push(format!(
-            "warning: no uses list found in {}",
-            dpr_path.display()
-        ));
-        summary.failures += 1;
-        return Ok(summary);
-    };
-    let mut current_bytes = bytes;
-    let mut current_list = list;
-    let existing_names: HashSet<String> = current_list
-        .entries
-        .iter()
-        .map(|entry| entry.name.to_ascii_lowercase())
-        .collect();
+    /// Retry dpr writes that fail because another program briefly holds the file.
+    pub fn write_retry(mut self, value: WriteRetry) -> Self {
+        self.write_retry = value;
+        self
+    }
 
-    let project_map = build_project_map(
-        &dpr_path,
-        &current_list,
-        project_cache,
-        delphi_cache,
-        &mut summary.warnings,
-    );
-    let active_root_names = collect_active_dpr_entry_names(
-        &dpr_path,
-        &current_bytes,
-        assumptions,
-        &mut summary.warnings,
-    );
-    let root_paths = collect_fix_root_paths(
-        &dpr_path,
-        &current_list,
-        &project_map,
-        project_cache,
-        delphi_cache,
-        active_root_names.as_ref(),
-        &mut summary.warnings,
-    );
-    if root_paths.is_empty() {
-        return Ok(summary);
+    /// Called before each dpr with its index, the dpr count, and its path. A
+    /// single dpr is index 0 of 1.
+    pub fn progress(mut self, callback: impl Fn(usize, usize, &Path) + 'static) -> Self {
+        self.progress = Some(Rc::new(callback));
+        self
     }
 
-    let missing_units = collect_missing_dpr_dependencies(
-        &root_paths,
-        &existing_names,
-        project_cache,
-        delphi_cache,
-        assumptions,
-        &mut summary.warnings,
-    )?;
-    if missing_units.is_empty() {
-        return Ok(summary);
+    /// Report dpr start, finish, and warnings to `events`.
+    pub fn events(mut self, events: Rc<dyn EventSink>) -> Self {
+        self.events = Some(events);
+        self
     }
 
-    let mut dpr_updated = false;
-    let mut last_inserted_name = None::<String>;
-    for dep_unit in missing_units {
-        let dep_insert_after = last_inserted_name.as_ref().and_then(|name| {
-            current_list
-                .entries
-                .iter()
-                .position(|entry| !entry.from_include && entry.name.eq_ignore_ascii_case(name))
-        });
-        let dep_updated = match insert_new_unit(
-            &current_bytes,
-            &dpr_path,
-            &current_list,
-            &dep_unit,
-            dep_insert_after,
-        ) {
-            Ok(value) => value,
-            Err(err) => {
-                summary.warnings.push(format!(
-                    "warning: failed to update dpr {}: {err}",
-                    dpr_path.display()
-                ));
-                summary.failures += 1;
-                return Ok(summary);
-            }
-        };
-        if !dep_updated {
-            continue;
-        }
+    pub fn build(self) -> Self {
+        self
+    }
 
-        dpr_updated = true;
-        last_inserted_name = Some(dep_unit.name);
-        let reloaded = match reload_dpr_state(&dpr_path, &mut summary.warnings) {
-            Ok(Some(value)) => value,
-            Ok(None) => {
-                summary.warnings.push(format!(
-                    "warning: no uses list found in {}",
-                    dpr_path.display()
-                ));
-                summary.failures += 1;
-                return Ok(summary);
-            }
-            Err(err) => {
-                summary.warnings.push(format!(
-                    "warning: failed to read dpr {}: {err}",
-                    dpr_path.display()
-                ));
-                summary.failures += 1;
-                return Ok(summary);
-            }
-        };
-        current_bytes = reloaded.0;
-        current_list = reloaded.1;
+    /// The options that can change the edits or warnings of a run, as text.
+    /// Dry runs, lock waits, write retries, and callbacks are left out.
+    fn outcome_key(&self) -> String {
+        let Self {
+            dry_run: _,
+            insert_position,
+            path_style,
+            cross_drive,
+            max_parent_dirs,
+            on_deep_relative,
+            namespace_prefixes,
+            indent,
+            wrap_at,
+            insert_in_conditional,
+            known_roots,
+            max_file_size,
+            lock_timeout: _,
+            write_retry: _,
+            progress: _,
+            events: _,
+        } = self;
+        format!(
+            "{insert_position:?} {path_style:?} {cross_drive:?} {max_parent_dirs:?} \
+             {on_deep_relative:?} {namespace_prefixes:?} {indent:?} {wrap_at:?} \
+             {insert_in_conditional} {known_roots:?} {max_file_size:?}"
+        )
     }
 
-    if dpr_updated {
-        summary.updated += 1;
-        summary.updated_paths.push(dpr_path);
+    /// A writer for one run with these options.
+    fn writer(&self) -> DprWriter {
+        DprWriter::new(
+            self.dry_run,
+            self.path_style,
+            self.cross_drive,
+            self.lock_timeout,
+            self.write_retry,
+        )
+        .limit_parent_dirs(self.max_parent_dirs, self.on_deep_relative)
+        .max_file_size(self.max_file_size)
+        .namespace_prefixes(self.namespace_prefixes.as_deref())
+        .indent(self.indent.as_deref())
+        .wrap_at(self.wrap_at)
+        .insert_in_conditional(self.insert_in_conditional)
+        .known_roots(&self.known_roots)
     }
 
-    Ok(summary)
+    /// Tells `progress` and `events` that the dpr at `index` of `count` starts.
+    fn start_dpr(&self, index: usize, count: usize, path: &Path) {
+        if let Some(progress) = &self.progress {
+            progress(index, count, path);
+        }
+        if let Some(events) = &self.events {
+            events.on_dpr_started(index, count, path);
+        }
+    }
 }
 
-pub fn delete_dependency_files(
-    dpr_paths: &[PathBuf],
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    old_dependency_name: &str,
-    assumptions: &Assumptions,
-) -> io::Result<DprUpdateSummary> {
-    let mut summary = DprUpdateSummary {
-        scanned: 0,
-        updated: 0,
-        updated_paths: Vec::new(),
-        warnings: Vec::new(),
-        failures: 0,
-    };
+/// Options for [`update_dpr_files_with`].
+#[derive(Default)]
+pub struct UpdateOptions {
+    edit: EditOptions,
+    direct_only: bool,
+    retarget_existing: bool,
+    after_unit: Option<String>,
+    limits: DependencyLimits,
+}
 
-    for path in dpr_paths {
-        summary.scanned += 1;
-        let bytes = match fs::read(path) {
-            Ok(data) => data,
-            Err(err) => {
-                summary.warnings.push(format!(
-                    "warning: failed to read dpr {}: {err}",
-                    path.display()
-                ));
-                summary.failures += 1;
-                continue;
-            }
-        };
-        let Some(list) = parse_dpr_uses(path, &bytes, &mut summary.warnings) else {
-            continue;
-        };
-        let active_root_names =
-            collect_active_dpr_entry_names(path, &bytes, assumptions, &mut summary.warnings);
+impl UpdateOptions {
+    pub fn builder() -> Self {
+        Self::default()
+    }
 
-        let removal_set = match collect_cascading_delete_names(
-            path,
-            &list,
-            project_cache,
-            delphi_cache,
-            old_dependency_name,
-            active_root_names.as_ref(),
-            assumptions,
-            &mut summary.warnings,
-        )? {
-            Some(set) => set,
-            None => continue,
-        };
+    /// How the dprs are edited and written.
+    pub fn edit(mut self, value: EditOptions) -> Self {
+        self.edit = value;
+        self
+    }
 
-        if !can_delete_entries(path, &list, &removal_set, &mut summary.warnings) {
-            continue;
-        }
+    /// [`IntroducedScope::All`] when `true`, [`IntroducedScope::None`] otherwise.
+    pub fn add_introduced_dependencies(self, value: bool) -> Self {
+        self.introduced_scope(if value {
+            IntroducedScope::All
+        } else {
+            IntroducedScope::None
+        })
+    }
 
-        let updated = match delete_uses_entries(path, &bytes, &list, &removal_set) {
-            Ok(value) => value,
-            Err(err) => {
-                summary.warnings.push(format!(
-                    "warning: failed to update dpr {}: {err}",
-                    path.display()
-                ));
-                summary.failures += 1;
-                continue;
-            }
-        };
-        if updated {
-            summary.updated += 1;
-            summary.updated_paths.push(path.clone());
-        }
+    /// Which units used by the new dependency are inserted along with it.
+    pub fn introduced_scope(mut self, scope: IntroducedScope) -> Self {
+        self.limits.introduced_scope = scope;
+        self
     }
 
-    Ok(summary)
-}
+    /// Only add the new unit to dprs listing a unit that uses it directly.
+    pub fn direct_only(mut self, value: bool) -> Self {
+        self.direct_only = value;
+        self
+    }
 
-fn can_delete_entries(
-    dpr_path: &Path,
-    list: &UsesList,
-    removal_set: &HashSet<String>,
-    warnings: &mut Vec<String>,
-) -> bool {
-    for entry in &list.entries {
-        let key = entry.name.to_ascii_lowercase();
-        if !removal_set.contains(&key) {
-            continue;
-        }
-        if !entry.from_include {
-            continue;
-        }
-        warnings.push(format!(
-            "warning: cannot remove unit {} from {} because it originates from include fragment",
-            entry.name,
-            dpr_path.display()
-        ));
-        return false;
+    /// Point an entry naming the new unit at another file to the new unit's file.
+    pub fn retarget_existing(mut self, value: bool) -> Self {
+        self.retarget_existing = value;
+        self
     }
-    true
-}
 
-fn delete_uses_entries(
-    dpr_path: &Path,
-    bytes: &[u8],
-    list: &UsesList,
-    removal_set: &HashSet<String>,
-) -> io::Result<bool> {
-    let mut kept = Vec::new();
-    for entry in &list.entries {
-        let key = entry.name.to_ascii_lowercase();
-        if removal_set.contains(&key) {
-            continue;
-        }
-        if entry.from_include {
-            return Ok(false);
-        }
-        kept.push(entry);
+    /// Insert the new unit and its introduced dependencies after this listed unit.
+    pub fn after_unit(mut self, name: Option<String>) -> Self {
+        self.after_unit = name.map(|name| name.trim().to_string());
+        self
     }
 
-    if kept.is_empty() {
-        return Ok(false);
+    /// Never insert this unit as an introduced dependency.
+    pub fn exclude_unit(mut self, name: impl AsRef<str>) -> Self {
+        self.limits
+            .excluded_units
+            .insert(name.as_ref().trim().to_ascii_lowercase());
+        self
     }
 
-    let list_start = list
-        .entries
-        .first()
-        .map(|entry| entry.start)
-        .unwrap_or(list.semicolon);
-    let new_body = render_uses_entries(list, &kept);
+    /// Follow introduced dependencies at most this many uses levels deep.
+    pub fn max_depth(mut self, value: Option<usize>) -> Self {
+        self.limits.max_depth = value;
+        self
+    }
 
-    let mut output = Vec::with_capacity(bytes.len() + new_body.len());
-    output.extend_from_slice(&bytes[..list_start]);
-    output.extend_from_slice(new_body.as_bytes());
-    output.extend_from_slice(&bytes[list.semicolon..]);
-    write_atomic(dpr_path, &output)?;
-    Ok(true)
-}
+    /// Insert introduced dependencies before the units that use them, breaking
+    /// ties by name, instead of by uses depth and name. Units that use each
+    /// other keep that order and get a `DependencyCycle` warning.
+    pub fn topo_order(mut self, value: bool) -> Self {
+        self.limits.topo_order = value;
+        self
+    }
 
-fn render_uses_entries(list: &UsesList, entries: &[&UsesEntry]) -> String {
-    if list.multiline {
-        let mut output = String::new();
-        for (idx, entry) in entries.iter().enumerate() {
-            if idx > 0 {
-                output.push(',');
-                output.push('\n');
-                output.push_str(&list.indent);
-            }
-            output.push_str(&entry.name);
-            if let Some(in_path) = entry.in_path.as_ref() {
-                output.push_str(" in '");
-                output.push_str(in_path);
-                output.push('\'');
-            }
-        }
-        output
-    } else {
-        let mut parts = Vec::new();
-        for entry in entries {
-            if let Some(in_path) = entry.in_path.as_ref() {
-                parts.push(format!("{} in '{}'", entry.name, in_path));
-            } else {
-                parts.push(entry.name.clone());
-            }
-        }
-        parts.join(", ")
+    pub fn build(self) -> Self {
+        self
     }
-}
 
-#[allow(clippy::too_many_arguments)]
-fn collect_cascading_delete_names(
-    dpr_path: &Path,
-    list: &UsesList,
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    old_dependency_name: &str,
-    active_root_names: Option<&HashSet<String>>,
-    assumptions: &Assumptions,
-    warnings: &mut Vec<String>,
-) -> io::Result<Option<HashSet<String>>> {
-    let root_key = old_dependency_name.to_ascii_lowercase();
-    let mut all_present = HashSet::new();
-    for entry in &list.entries {
-        all_present.insert(entry.name.to_ascii_lowercase());
+    /// Whether the dprs are left unwritten.
+    pub(crate) fn is_dry_run(&self) -> bool {
+        self.edit.dry_run
     }
-    if !all_present.contains(&root_key) {
-        return Ok(None);
+
+    /// Every option that can change the edits or warnings of a run, as text,
+    /// for callers that skip dprs already processed with the same options.
+    /// Dry runs, lock waits, write retries, and callbacks are left out.
+    pub fn outcome_key(&self) -> String {
+        let Self {
+            edit,
+            direct_only,
+            retarget_existing,
+            after_unit,
+            limits,
+        } = self;
+        format!(
+            "update {} {direct_only} {retarget_existing} {after_unit:?} {}",
+            edit.outcome_key(),
+            limits.outcome_key()
+        )
     }
-    let mut present = active_root_names
-        .cloned()
-        .unwrap_or_else(|| all_present.clone());
-    present.insert(root_key.clone());
+}
 
-    let project_map = build_project_map(dpr_path, list, project_cache, delphi_cache, warnings);
-    let mut edges: HashMap<String, HashSet<String>> = HashMap::new();
-    let mut incoming: HashMap<String, usize> = HashMap::new();
-    for key in &present {
-        incoming.insert(key.clone(), 0);
+/// Options for [`fix_dpr_file_with`].
+#[derive(Default)]
+pub struct FixOptions {
+    edit: EditOptions,
+    fix_mismatched_paths: bool,
+    fix_duplicates: Option<DuplicatePolicy>,
+    report_unresolved: bool,
+    remove_missing: bool,
+    max_inserts: Option<usize>,
+    limits: DependencyLimits,
+    staged: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl FixOptions {
+    pub fn builder() -> Self {
+        Self::default()
     }
 
-    for key in &present {
-        let Some(unit_path) = project_map.get(key) else {
-            continue;
-        };
-        let uses = match load_unit_uses_readonly(
-            project_cache,
-            delphi_cache,
-            unit_path,
-            warnings,
-            assumptions,
-        )? {
-            Some(value) => value,
-            None => continue,
-        };
-        for dep_name in uses {
-            let dep_key = dep_name.to_ascii_lowercase();
-            if !present.contains(&dep_key) {
-                continue;
-            }
-            let inserted = edges
-                .entry(key.clone())
-                .or_default()
-                .insert(dep_key.clone());
-            if inserted {
-                *incoming.entry(dep_key).or_default() += 1;
-            }
-        }
+    /// How the dpr is edited and written.
+    pub fn edit(mut self, value: EditOptions) -> Self {
+        self.edit = value;
+        self
     }
 
-    let mut removed = HashSet::new();
-    let mut queue = VecDeque::new();
-    removed.insert(root_key.clone());
-    queue.push_back(root_key);
+    /// Options for the fix pass over the dprs an update changed: the edit
+    /// options of `update` without the insert position, progress, and events
+    /// of the update pass.
+    pub fn for_updated_dprs(update: &UpdateOptions) -> Self {
+        Self::builder().edit(EditOptions {
+            insert_position: InsertPosition::default(),
+            progress: None,
+            events: None,
+            ..update.edit.clone()
+        })
+    }
 
-    while let Some(current) = queue.pop_front() {
-        let Some(children) = edges.get(&current) else {
-            continue;
-        };
-        for child in children {
-            if removed.contains(child) {
-                continue;
-            }
-            if let Some(count) = incoming.get_mut(child) {
-                *count = count.saturating_sub(1);
-                if *count == 0 {
-                    removed.insert(child.clone());
-                    queue.push_back(child.clone());
-                }
-            }
-        }
+    /// Switch dry runs on or off for the next fix, as `serve` does per request.
+    pub fn set_dry_run(&mut self, value: bool) {
+        self.edit.dry_run = value;
     }
 
-    Ok(Some(removed))
-}
+    /// Point in-paths that name a file declaring another unit at the entry's own unit.
+    pub fn fix_mismatched_paths(mut self, value: bool) -> Self {
+        self.fix_mismatched_paths = value;
+        self
+    }
 
-fn load_unit_uses_readonly(
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    unit_path: &Path,
-    warnings: &mut Vec<String>,
-    assumptions: &Assumptions,
-) -> io::Result<Option<Vec<String>>> {
-    let canonical = unit_cache::canonicalize_if_exists(unit_path);
-    if let Some(info) = project_cache.by_path.get(&canonical) {
-        return Ok(Some(flatten_unit_uses(info, assumptions)));
+    /// Keep one entry per unit listed more than once, chosen by `value`.
+    pub fn fix_duplicates(mut self, value: Option<DuplicatePolicy>) -> Self {
+        self.fix_duplicates = value;
+        self
     }
-    if let Some(delphi_cache) = delphi_cache {
-        if let Some(info) = delphi_cache.by_path.get(&canonical) {
-            return Ok(Some(flatten_unit_uses(info, assumptions)));
-        }
+
+    /// Collect the uses references of the dpr and its closure that resolve to
+    /// no unit into [`DprUpdateSummary::unresolved`].
+    pub fn report_unresolved(mut self, value: bool) -> Self {
+        self.report_unresolved = value;
+        self
     }
 
-    Ok(unit_cache::load_unit_file(&canonical, warnings)?
-        .map(|info| conditionals::flatten_conditional_uses(&info.conditional_uses, assumptions)))
-}
+    /// Remove the entries listed in [`DprDetail::missing_entries`], except
+    /// pinned ones and those under a conditional or in an include fragment.
+    pub fn remove_missing(mut self, value: bool) -> Self {
+        self.remove_missing = value;
+        self
+    }
 
-fn collect_fix_root_paths(
-    dpr_path: &Path,
-    list: &UsesList,
-    project_map: &HashMap<String, PathBuf>,
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    active_root_names: Option<&HashSet<String>>,
-    warnings: &mut Vec<String>,
-) -> Vec<PathBuf> {
-    let mut roots = Vec::new();
-    let mut seen = HashSet::new();
+    /// Insert at most this many missing units into the dpr. The rest are
+    /// still found and counted in [`DprDetail::not_inserted`].
+    pub fn max_inserts(mut self, value: Option<usize>) -> Self {
+        self.max_inserts = value;
+        self
+    }
 
-    for entry in &list.entries {
-        if !is_active_dpr_entry(active_root_names, entry) {
-            continue;
-        }
-        let key = entry.name.to_ascii_lowercase();
-        let Some(path) = project_map.get(&key) else {
-            continue;
-        };
-        let canonical = unit_cache::canonicalize_if_exists(path);
-        if !has_unit_path(project_cache, delphi_cache, &canonical) {
-            warnings.push(format!(
-                "warning: unit {} in {} resolved outside known unit caches and will be ignored",
-                entry.name,
-                dpr_path.display()
-            ));
-            continue;
-        }
-        if seen.insert(canonical.clone()) {
-            roots.push(canonical);
-        }
+    /// Never insert this unit, even when it is missing.
+    pub fn exclude_unit(mut self, name: impl AsRef<str>) -> Self {
+        self.limits
+            .excluded_units
+            .insert(name.as_ref().trim().to_ascii_lowercase());
+        self
     }
 
-    roots
-}
+    /// Follow uses of the listed units at most this many levels deep.
+    pub fn max_depth(mut self, value: Option<usize>) -> Self {
+        self.limits.max_depth = value;
+        self
+    }
 
-fn collect_missing_dpr_dependencies(
-    root_paths: &[PathBuf],
-    existing_names: &HashSet<String>,
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    assumptions: &Assumptions,
-    warnings: &mut Vec<String>,
-) -> io::Result<Vec<UnitFileInfo>> {
-    let mut queue = VecDeque::new();
-    let mut seen_paths = HashSet::new();
-    let mut missing_names = HashSet::new();
-    let mut missing_units = Vec::new();
+    /// Follow units from the Delphi cache but never insert them.
+    pub fn no_delphi_inserts(mut self, value: bool) -> Self {
+        self.limits.skip_delphi_units = value;
+        self
+    }
 
-    for path in root_paths {
-        if seen_paths.insert(path.clone()) {
-            queue.push_back(path.clone());
-        }
+    /// In a dry run, edit these contents instead of the files on disk, such as
+    /// the [`DprUpdateSummary::staged`] of an earlier dry run.
+    pub fn staged(mut self, contents: HashMap<PathBuf, Vec<u8>>) -> Self {
+        self.staged = contents;
+        self
     }
 
-    while let Some(unit_path) = queue.pop_front() {
-        let uses = match load_unit_uses_readonly(
-            project_cache,
-            delphi_cache,
-            &unit_path,
-            warnings,
-            assumptions,
-        )? {
-            Some(value) => value,
-            None => continue,
-        };
+    pub fn build(self) -> Self {
+        self
+    }
 
-        for dep in uses {
-            let dep_key = dep.to_ascii_lowercase();
-            let dep_path = match resolve_by_name(project_cache, delphi_cache, dep.as_str()) {
-                ResolveByName::Unique { path, .. } => path,
-                ResolveByName::Ambiguous { count, source } => {
-                    warnings.push(format!(
-                        "warning: ambiguous unit {} referenced by {} ({} {} matches)",
-                        dep,
-                        unit_path.display(),
-                        count,
-                        source_label(source)
-                    ));
-                    continue;
-                }
-                ResolveByName::NotFound => continue,
-            };
-            let dep_path = unit_cache::canonicalize_if_exists(&dep_path);
-            if !has_unit_path(project_cache, delphi_cache, &dep_path) {
-                continue;
-            }
-            if seen_paths.insert(dep_path.clone()) {
-                queue.push_back(dep_path.clone());
-            }
+    /// [`UpdateOptions::outcome_key`] for these options. Staged contents are
+    /// left out as well, since only dry runs edit them.
+    pub fn outcome_key(&self) -> String {
+        let Self {
+            edit,
+            fix_mismatched_paths,
+            fix_duplicates,
+            report_unresolved,
+            remove_missing,
+            max_inserts,
+            limits,
+            staged: _,
+        } = self;
+        format!(
+            "fix {} {fix_mismatched_paths} {fix_duplicates:?} {report_unresolved} \
+             {remove_missing} {max_inserts:?} {}",
+            edit.outcome_key(),
+            limits.outcome_key()
+        )
+    }
 
-            if existing_names.contains(&dep_key) {
-                continue;
-            }
-            if !missing_names.insert(dep_key) {
-                continue;
-            }
-            if let Some(dep_info) = lookup_unit_info(project_cache, delphi_cache, &dep_path) {
-                missing_units.push(dep_info.clone());
-            }
+    /// A writer for `dpr_path` that starts from its [`FixOptions::staged`]
+    /// contents in a dry run.
+    fn writer(&self, dpr_path: &Path) -> DprWriter {
+        let mut writer = self.edit.writer();
+        if let Some(contents) = self.staged.get(dpr_path).filter(|_| self.edit.dry_run) {
+            writer
+                .staged
+                .insert(dpr_path.to_path_buf(), contents.clone());
         }
+        writer
     }
-
-    Ok(missing_units)
 }
 
-fn reload_dpr_state(
-    path: &Path,
-    warnings: &mut Vec<String>,
-) -> io::Result<Option<(Vec<u8>, UsesList)>> {
-    let bytes = fs::read(path)?;
-    let list = parse_dpr_uses(path, &bytes, warnings);
-    Ok(list.map(|list| (bytes, list)))
+#[derive(Debug, Default)]
+struct DprWriter {
+    dry_run: bool,
+    path_style: PathStyle,
+    cross_drive: CrossDrive,
+    staged: HashMap<PathBuf, Vec<u8>>,
+    write_time: Duration,
+    lock_timeout: Duration,
+    retry: WriteRetry,
+    max_parent_dirs: Option<usize>,
+    on_deep_relative: DeepRelative,
+    namespace_prefixes: Vec<String>,
+    /// Overrides the inferred indentation of inserted entries.
+    indent: Option<String>,
+    /// Keeps anchors inside conditional regions instead of appending.
+    insert_in_conditional: bool,
+    /// Roots later scans cover; in-paths written outside them are reported.
+    known_roots: Vec<PathBuf>,
+    /// Dprs larger than this many bytes are skipped; 0 removes the limit.
+    max_file_size: u64,
+    /// Splits a single-line uses list over several lines once its line grows past this width.
+    wrap_at: Option<usize>,
+    /// Writes that only succeeded after a retry, and skipped scoped variants.
+    infos: Vec<String>,
+    /// How `{$I}` directives in the dprs resolve.
+    includes: Rc<Includes>,
+    /// Canonical paths of the project, which forget each dpr written.
+    canonical: Rc<CanonicalPaths>,
 }
 
-fn flatten_unit_uses(info: &UnitFileInfo, assumptions: &Assumptions) -> Vec<String> {
-    conditionals::flatten_conditional_uses(&info.conditional_uses, assumptions)
-}
+impl DprWriter {
+    fn new(
+        dry_run: bool,
+        path_style: PathStyle,
+        cross_drive: CrossDrive,
+        lock_timeout: Option<Duration>,
+        retry: WriteRetry,
+    ) -> Self {
+        Self {
+            dry_run,
+            path_style,
+            cross_drive,
+            staged: HashMap::new(),
+            write_time: Duration::ZERO,
+            lock_timeout: lock_timeout.unwrap_or(dpr_lock::DEFAULT_LOCK_TIMEOUT),
+            retry,
+            max_parent_dirs: None,
+            on_deep_relative: DeepRelative::default(),
+            namespace_prefixes: Vec::new(),
+            indent: None,
+            insert_in_conditional: false,
+            known_roots: Vec::new(),
+            max_file_size: unit_cache::DEFAULT_MAX_FILE_SIZE,
+            wrap_at: None,
+            infos: Vec::new(),
+            includes: Rc::default(),
+            canonical: Rc::default(),
+        }
+    }
 
-fn collect_active_dpr_entry_names(
-    dpr_path: &Path,
-    bytes: &[u8],
-    assumptions: &Assumptions,
-    warnings: &mut Vec<String>,
-) -> Option<HashSet<String>> {
-    let entries = conditionals::parse_dpr_conditional_uses(dpr_path, bytes, warnings)?;
-    Some(
-        entries
-            .into_iter()
-            .filter(|entry| evaluate_entry_condition(entry, assumptions))
-            .map(|entry| entry.unit_name.to_ascii_lowercase())
-            .collect(),
-    )
-}
+    /// Resolves includes and canonical paths the way `project_cache` does.
+    fn unit_cache(mut self, project_cache: &UnitCache) -> Self {
+        self.includes = Rc::clone(&project_cache.includes);
+        self.canonical = Rc::clone(&project_cache.canonical);
+        self
+    }
 
-fn evaluate_entry_condition(
-    entry: &conditionals::ConditionalUse,
-    assumptions: &Assumptions,
-) -> bool {
-    conditionals::evaluate_condition(&entry.condition, assumptions) != EvalResult::Never
-}
+    fn limit_parent_dirs(mut self, max: Option<usize>, policy: DeepRelative) -> Self {
+        self.max_parent_dirs = max;
+        self.on_deep_relative = policy;
+        self
+    }
 
-fn is_active_dpr_entry(active_root_names: Option<&HashSet<String>>, entry: &UsesEntry) -> bool {
-    active_root_names
-        .map(|names| names.contains(&entry.name.to_ascii_lowercase()))
-        .unwrap_or(true)
-}
+    fn namespace_prefixes(mut self, prefixes: Option<&[String]>) -> Self {
+        self.namespace_prefixes = match prefixes {
+            Some(prefixes) => prefixes.to_vec(),
+            None => DEFAULT_NAMESPACE_PREFIXES
+                .iter()
+                .map(|prefix| prefix.to_string())
+                .collect(),
+        };
+        self
+    }
 
-fn has_unit_path(project_cache: &UnitCache, delphi_cache: Option<&UnitCache>, path: &Path) -> bool {
-    if project_cache.by_path.contains_key(path) {
-        return true;
+    fn indent(mut self, indent: Option<&str>) -> Self {
+        self.indent = indent.map(str::to_string);
+        self
     }
-    if let Some(delphi_cache) = delphi_cache {
-        if delphi_cache.by_path.contains_key(path) {
-            return true;
+
+    fn insert_in_conditional(mut self, value: bool) -> Self {
+        self.insert_in_conditional = value;
+        self
+    }
+
+    fn wrap_at(mut self, width: Option<usize>) -> Self {
+        self.wrap_at = width;
+        self
+    }
+
+    fn known_roots(mut self, roots: &[PathBuf]) -> Self {
+        self.known_roots = roots.to_vec();
+        self
+    }
+
+    fn max_file_size(mut self, bytes: Option<u64>) -> Self {
+        self.max_file_size = bytes.unwrap_or(unit_cache::DEFAULT_MAX_FILE_SIZE);
+        self
+    }
+
+    /// Reads the dpr at `path`, skipping it with a warning above [`DprWriter::max_file_size`].
+    fn read_source(&self, path: &Path, warnings: &mut Warnings) -> io::Result<Option<Vec<u8>>> {
+        unit_cache::read_source_file(path, self.max_file_size, warnings)
+    }
+
+    /// `bytes` with its uses list written one entry per line when the list is
+    /// on a single line longer than [`DprWriter::wrap_at`] columns.
+    fn wrap_long_list(&self, dpr_path: &Path, bytes: Vec<u8>) -> Vec<u8> {
+        let Some(width) = self.wrap_at else {
+            return bytes;
+        };
+        // The caller reports parse warnings of the edited list.
+        let Some(list) = parse_dpr_uses(dpr_path, &bytes, &self.includes, &mut Warnings::new())
+        else {
+            return bytes;
+        };
+        if list.multiline || line_width_at(&bytes, list.semicolon) <= width {
+            return bytes;
         }
+        let indent = self.indent.as_deref().unwrap_or("  ");
+        wrap_uses_list(&bytes, &list, indent).unwrap_or(bytes)
     }
-    false
-}
 
-fn lookup_unit_info<'a>(
-    project_cache: &'a UnitCache,
-    delphi_cache: Option<&'a UnitCache>,
-    path: &Path,
-) -> Option<&'a UnitFileInfo> {
-    if let Some(unit) = project_cache.by_path.get(path) {
-        return Some(unit);
+    /// `insert_after`, unless that entry sits inside a conditional region,
+    /// where the new entry would vanish from builds without the symbol. The
+    /// end of the list is used then, and an info names the guarded anchor.
+    fn unconditional_anchor(
+        &mut self,
+        dpr_path: &Path,
+        list: &UsesList,
+        insert_after: Option<usize>,
+        name: &str,
+    ) -> Option<usize> {
+        let idx = insert_after?;
+        let anchor = list.entries.get(idx)?;
+        if anchor.cond_depth == 0 || self.insert_in_conditional {
+            return Some(idx);
+        }
+        self.infos.push(format!(
+            "{} in {} is inside a conditional region; {name} was added at the end of the uses list",
+            anchor.name,
+            dpr_path.display()
+        ));
+        None
     }
-    delphi_cache.and_then(|cache| cache.by_path.get(path))
-}
 
-fn find_direct_introducer_index(
-    list: &UsesList,
-    project_map: &HashMap<String, PathBuf>,
-    dependents: &ProjectDependents,
-    active_root_names: Option<&HashSet<String>>,
-) -> Option<usize> {
-    list.entries.iter().enumerate().find_map(|(idx, entry)| {
-        if entry.from_include {
-            return None;
+    /// The entry of `list` naming `name` under another unit scope, such as
+    /// `Vcl.Dialogs` for `Dialogs` or the other way round.
+    fn scoped_variant<'a>(&self, list: &'a UsesList, name: &str) -> Option<&'a UsesEntry> {
+        let is_scoped = |scoped: &str, plain: &str| {
+            self.namespace_prefixes.iter().any(|prefix| {
+                scoped
+                    .get(..prefix.len())
+                    .is_some_and(|head| head.eq_ignore_ascii_case(prefix))
+                    && scoped
+                        .get(prefix.len()..)
+                        .and_then(|rest| rest.strip_prefix('.'))
+                        .is_some_and(|rest| rest.eq_ignore_ascii_case(plain))
+            })
+        };
+        list.entries
+            .iter()
+            .find(|entry| is_scoped(&entry.name, name) || is_scoped(name, &entry.name))
+    }
+
+    /// Locks `path` against other processes until the returned lock is dropped.
+    ///
+    /// Take it before reading the dpr so its edit starts from the content the
+    /// previous holder wrote. Dry runs do not lock.
+    fn lock(&self, path: &Path) -> Result<Option<DprLock>, Diagnostic> {
+        if self.dry_run {
+            return Ok(None);
         }
-        if !is_active_dpr_entry(active_root_names, entry) {
-            return None;
+        match dpr_lock::acquire(path, self.lock_timeout) {
+            Ok(Some(lock)) => Ok(Some(lock)),
+            Ok(None) => Err(Diagnostic::warning(
+                DiagnosticCode::DprLocked,
+                format!(
+                    "dpr {} is locked by another process; gave up after {}s",
+                    path.display(),
+                    self.lock_timeout.as_secs_f64()
+                ),
+            )
+            .at(path)),
+            Err(err) => Err(Diagnostic::warning(
+                DiagnosticCode::WriteFailed,
+                format!("failed to lock dpr {}: {err}", path.display()),
+            )
+            .at(path)),
         }
-        let key = entry.name.to_ascii_lowercase();
-        let path = project_map.get(&key)?;
-        let id = *dependents.id_by_path.get(path)?;
-        if dependents.direct[id] {
-            Some(idx)
+    }
+
+    /// Applies the cross-drive and parent folder policies; `Ok(false)` means leave `unit` out.
+    fn allows_unit(
+        &self,
+        dpr_path: &Path,
+        unit: &UnitFileInfo,
+        warnings: &mut Warnings,
+    ) -> io::Result<bool> {
+        let allowed = if is_cross_drive(&unit.path, dpr_path.parent()) {
+            self.allows_cross_drive(dpr_path, unit, warnings)?
         } else {
-            None
+            self.allows_parent_dirs(dpr_path, unit, warnings)
+        };
+        if allowed {
+            self.check_known_roots(dpr_path, unit, warnings);
         }
-    })
-}
-
-struct ProjectDependents {
-    dependents: Vec<bool>,
-    direct: Vec<bool>,
-    id_by_path: HashMap<PathBuf, usize>,
-}
-
-fn build_project_map(
-    dpr_path: &Path,
-    list: &UsesList,
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    warnings: &mut Vec<String>,
-) -> HashMap<String, PathBuf> {
-    let mut map = HashMap::new();
+        Ok(allowed)
+    }
 
-    for entry in &list.entries {
-        let Some(raw_path) = entry.in_path.as_ref() else {
-            match resolve_by_name(project_cache, delphi_cache, &entry.name) {
-                ResolveByName::NotFound => {}
-                ResolveByName::Unique {
-                    path: fallback,
-                    source,
-                } => {
-                    if source == ResolutionSource::Project {
-                        warnings.push(format!(
-                            "warning: missing in-path for unit {} in {} (resolved via scan)",
-                            entry.name,
-                            dpr_path.display()
-                        ));
-                    }
-                    insert_project_entry(&mut map, entry, fallback, dpr_path, warnings);
-                }
-                ResolveByName::Ambiguous { count, source } => {
-                    warnings.push(format!(
-                        "warning: missing in-path for unit {} in {} ({} {} matches)",
-                        entry.name,
-                        dpr_path.display(),
-                        count,
-                        source_label(source)
-                    ));
-                }
+    fn allows_cross_drive(
+        &self,
+        dpr_path: &Path,
+        unit: &UnitFileInfo,
+        warnings: &mut Warnings,
+    ) -> io::Result<bool> {
+        let unit_path = unit_cache::strip_verbatim_prefix(&unit.path);
+        let action = match self.cross_drive {
+            CrossDrive::Absolute => "wrote an absolute path",
+            CrossDrive::Skip => "skipped it",
+            CrossDrive::Error => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} is on a different drive ({})",
+                        unit.name,
+                        unit_path.display()
+                    ),
+                ))
             }
-            continue;
         };
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::CrossDrivePath,
+                format!(
+                    "{} is on a different drive than {}; {action}",
+                    unit_path.display(),
+                    dpr_path.display()
+                ),
+            )
+            .at(dpr_path),
+        );
+        Ok(self.cross_drive == CrossDrive::Absolute)
+    }
 
-        let resolved = resolve_dpr_unit_path(dpr_path, raw_path);
-        if !resolved.is_file() {
-            warnings.push(format!(
-                "warning: dpr uses path not found for unit {} in {}: {}",
-                entry.name,
-                dpr_path.display(),
-                resolved.display()
-            ));
-            match resolve_by_name(project_cache, delphi_cache, &entry.name) {
-                ResolveByName::Unique { path: fallback, .. } => {
-                    insert_project_entry(&mut map, entry, fallback, dpr_path, warnings);
-                }
-                ResolveByName::Ambiguous { count, source } => {
-                    warnings.push(format!(
-                        "warning: unit {} referenced in {} is ambiguous ({} {} matches)",
-                        entry.name,
-                        dpr_path.display(),
-                        count,
-                        source_label(source)
-                    ));
-                }
-                ResolveByName::NotFound => {}
-            }
-            continue;
+    fn allows_parent_dirs(
+        &self,
+        dpr_path: &Path,
+        unit: &UnitFileInfo,
+        warnings: &mut Warnings,
+    ) -> bool {
+        let Some(max) = self.max_parent_dirs else {
+            return true;
+        };
+        let relative = relative_path(&unit.path, dpr_path.parent());
+        let climbs = leading_parent_dirs(&relative);
+        if climbs <= max {
+            return true;
+        }
+        let action = match self.on_deep_relative {
+            DeepRelative::Absolute => "wrote an absolute path",
+            DeepRelative::Skip => "skipped it",
+            DeepRelative::Allow => "kept it",
+        };
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::DeepRelativePath,
+                format!(
+                    "{} in '{relative}' from {} climbs {climbs} parent folders, more than {max}; {action}",
+                    unit.name,
+                    dpr_path.display()
+                ),
+            )
+            .at(dpr_path),
+        );
+        self.on_deep_relative != DeepRelative::Skip
+    }
+
+    /// Warns when `unit` lies outside every known root, so the next scan
+    /// would not find the file its new in-path points at.
+    fn check_known_roots(&self, dpr_path: &Path, unit: &UnitFileInfo, warnings: &mut Warnings) {
+        if self.known_roots.is_empty() {
+            return;
+        }
+        let path = self.canonical.canonicalize(&unit.path);
+        if self.known_roots.iter().any(|root| path.starts_with(root)) {
+            return;
+        }
+        let roots = self
+            .known_roots
+            .iter()
+            .map(|root| {
+                unit_cache::strip_verbatim_prefix(root)
+                    .display()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::OutsideSearchRoots,
+                format!(
+                    "{} in '{}' from {} is outside every search root ({roots}); later scans will not find it",
+                    unit.name,
+                    relative_path(&unit.path, dpr_path.parent()),
+                    dpr_path.display()
+                ),
+            )
+            .at(dpr_path),
+        );
+    }
+
+    /// The in-path written for `unit_path`, relative to the dpr unless the
+    /// parent folder policy asks for an absolute path.
+    fn unit_path_text(&self, dpr_path: &Path, unit_path: &Path, separator: char) -> String {
+        let mut text = relative_path(unit_path, dpr_path.parent());
+        if self.on_deep_relative == DeepRelative::Absolute
+            && self
+                .max_parent_dirs
+                .is_some_and(|max| leading_parent_dirs(&text) > max)
+        {
+            let absolute = self.canonical.canonicalize(unit_path);
+            text = unit_cache::strip_verbatim_prefix(&absolute)
+                .to_string_lossy()
+                .to_string();
         }
+        text.replace(['\\', '/'], &separator.to_string())
+    }
 
-        insert_project_entry(&mut map, entry, resolved, dpr_path, warnings);
+    fn separator(&self, list: Option<&UsesList>) -> char {
+        match self.path_style {
+            PathStyle::Backslash => '\\',
+            PathStyle::Slash => '/',
+            PathStyle::MatchList => match list {
+                Some(list) if !list.has_backslash && list.has_slash => '/',
+                _ => '\\',
+            },
+        }
     }
 
-    map
-}
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.staged.get(path) {
+            Some(bytes) => Ok(bytes.clone()),
+            None => fs::read(path),
+        }
+    }
 
-fn insert_project_entry(
-    map: &mut HashMap<String, PathBuf>,
-    entry: &UsesEntry,
-    resolved: PathBuf,
-    dpr_path: &Path,
-    warnings: &mut Vec<String>,
-) {
-    let key = entry.name.to_ascii_lowercase();
-    if let Some(existing) = map.get(&key) {
-        if existing != &resolved {
-            warnings.push(format!(
-                "warning: duplicate unit name {} in {} with multiple paths",
-                entry.name,
-                dpr_path.display()
+    fn write(&mut self, path: &Path, contents: Vec<u8>) -> io::Result<()> {
+        if self.dry_run {
+            self.staged.insert(path.to_path_buf(), contents);
+            return Ok(());
+        }
+        let started = Instant::now();
+        let result = write_atomic(&RealFiles, path, &contents, self.retry);
+        self.write_time += started.elapsed();
+        let retries = result?;
+        self.canonical.forget(path);
+        if retries > 0 {
+            self.infos.push(format!(
+                "wrote {} after {retries} retries; another program held the file",
+                path.display()
             ));
         }
-        return;
+        Ok(())
     }
-    map.insert(key, resolved);
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum ResolutionSource {
-    Project,
-    Delphi,
+/// Comment text that keeps a dpr entry when [`copy_uses_with`] syncs the list.
+const PIN_MARKER: &str = "fixdpr:pin";
+
+#[derive(Debug)]
+struct UsesEntry {
+    name: String,
+    in_path: Option<String>,
+    /// Byte range of the quoted in-path literal, for entries written in the dpr itself.
+    in_path_span: Option<(usize, usize)>,
+    start: usize,
+    delimiter: Option<u8>,
+    delimiter_pos: Option<usize>,
+    from_include: bool,
+    /// How many `{$IFDEF}`-style regions enclose the entry's name.
+    cond_depth: usize,
 }
 
-enum ResolveByName {
-    NotFound,
-    Unique {
-        path: PathBuf,
-        source: ResolutionSource,
-    },
-    Ambiguous {
-        count: usize,
-        source: ResolutionSource,
-    },
-}
-
-fn resolve_by_name(
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    unit_name: &str,
-) -> ResolveByName {
-    let key = unit_name.to_ascii_lowercase();
-    if let Some(paths) = project_cache.by_name.get(&key) {
-        if paths.len() > 1 {
-            return ResolveByName::Ambiguous {
-                count: paths.len(),
-                source: ResolutionSource::Project,
-            };
-        }
-        return ResolveByName::Unique {
-            path: paths[0].clone(),
-            source: ResolutionSource::Project,
-        };
-    }
-
-    if let Some(delphi_cache) = delphi_cache {
-        if let Some(paths) = delphi_cache.by_name.get(&key) {
-            if paths.len() > 1 {
-                return ResolveByName::Ambiguous {
-                    count: paths.len(),
-                    source: ResolutionSource::Delphi,
-                };
-            }
-            return ResolveByName::Unique {
-                path: paths[0].clone(),
-                source: ResolutionSource::Delphi,
-            };
-        }
+impl UsesEntry {
+    fn offset(&self) -> Option<usize> {
+        (!self.from_include).then_some(self.start)
     }
-
-    ResolveByName::NotFound
 }
 
-fn source_label(source: ResolutionSource) -> &'static str {
-    match source {
-        ResolutionSource::Project => "project",
-        ResolutionSource::Delphi => "--delphi-path",
-    }
+#[derive(Debug)]
+struct UsesList {
+    entries: Vec<UsesEntry>,
+    /// Offset just past the `uses` keyword.
+    start: usize,
+    semicolon: usize,
+    multiline: bool,
+    /// Single-line list whose commas are followed directly by the next entry.
+    tight_commas: bool,
+    indent: String,
+    has_backslash: bool,
+    has_slash: bool,
 }
 
-fn compute_project_dependents(
+/// Kept for one release; prefer [`update_dpr_files_with`].
+pub fn update_dpr_files(
+    dpr_paths: &[PathBuf],
     project_cache: &mut UnitCache,
-    mut delphi_cache: Option<&mut UnitCache>,
-    project_map: &HashMap<String, PathBuf>,
+    delphi_cache: Option<&LazyUnitCache>,
     new_unit: &UnitFileInfo,
+    add_introduced_dependencies: bool,
     assumptions: &Assumptions,
-    warnings: &mut Vec<String>,
-) -> io::Result<ProjectDependents> {
-    let mut id_by_path = HashMap::new();
-    let mut rev: Vec<Vec<usize>> = Vec::new();
-    let mut direct: Vec<bool> = Vec::new();
-    let mut queue = VecDeque::new();
-
-    for path in project_map.values() {
-        if id_by_path.contains_key(path) {
-            continue;
-        }
-        let id = id_by_path.len();
-        id_by_path.insert(path.clone(), id);
-        rev.push(Vec::new());
-        direct.push(false);
-        queue.push_back(path.clone());
-    }
+) -> io::Result<DprUpdateSummary> {
+    let options = UpdateOptions::builder()
+        .add_introduced_dependencies(add_introduced_dependencies)
+        .build();
+    update_dpr_files_with(
+        dpr_paths,
+        project_cache,
+        delphi_cache,
+        new_unit,
+        assumptions,
+        &options,
+    )
+}
 
-    while let Some(unit_path) = queue.pop_front() {
-        let uses = match load_unit_uses(
-            project_cache,
-            delphi_cache.as_deref_mut(),
-            &unit_path,
-            warnings,
-            assumptions,
-        )? {
-            Some(uses) => uses,
-            None => {
-                warnings.push(format!(
-                    "warning: failed to read unit at {}",
-                    unit_path.display()
-                ));
+pub fn update_dpr_files_with(
+    dpr_paths: &[PathBuf],
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    new_unit: &UnitFileInfo,
+    assumptions: &Assumptions,
+    options: &UpdateOptions,
+) -> io::Result<DprUpdateSummary> {
+    let mut summary = DprUpdateSummary::new();
+    summary.events = EventHandle(options.edit.events.clone());
+    let mut writer = options.edit.writer().unit_cache(project_cache);
+    let limits = &options.limits;
+    let add_introduced_dependencies = limits.introduced_scope != IntroducedScope::None;
+    let mut inserted_paths = Vec::new();
+
+    let dpr_paths = in_processing_order(dpr_paths);
+    'dpr_loop: for (index, path) in dpr_paths.iter().enumerate() {
+        summary.scanned += 1;
+        summary.begin_dpr(path);
+        options.edit.start_dpr(index, dpr_paths.len(), path);
+        let _lock = match writer.lock(path) {
+            Ok(lock) => lock,
+            Err(diagnostic) => {
+                summary.warnings.push(diagnostic);
+                summary.record_failure(path);
+                continue;
+            }
+        };
+        let bytes = match writer.read_source(path, &mut summary.warnings) {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                summary.record_failure(path);
+                continue;
+            }
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!("failed to read dpr {}: {err}", path.display()),
+                    )
+                    .at(path),
+                );
+                summary.record_failure(path);
                 continue;
             }
         };
-        let Some(&source_id) = id_by_path.get(&unit_path) else {
+        let Some(list) = parse_dpr_uses(path, &bytes, &writer.includes, &mut summary.warnings)
+        else {
+            summary.warnings.push(uses_list_failure(path, &bytes));
+            summary.record_failure(path);
             continue;
         };
-
-        for dep in uses {
-            if dep.eq_ignore_ascii_case(&new_unit.name) {
-                direct[source_id] = true;
+        let mut current_bytes = bytes;
+        let mut current_list = list;
+        let retargeted = match retarget_new_unit_entries(
+            path,
+            &current_bytes,
+            &current_list,
+            new_unit,
+            options.retarget_existing,
+            &mut writer,
+            &mut summary.warnings,
+        ) {
+            Ok(value) => value,
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::WriteFailed,
+                        format!("failed to update dpr {}: {err}", path.display()),
+                    )
+                    .at(path),
+                );
+                summary.record_failure(path);
                 continue;
             }
-            let dep_path = resolve_dep_path(
-                project_map,
-                project_cache,
-                delphi_cache.as_deref(),
-                dep.as_str(),
-                unit_path.as_path(),
-                warnings,
-            );
-            let Some(dep_path) = dep_path else {
-                continue;
-            };
-            let target_id = if let Some(&id) = id_by_path.get(&dep_path) {
-                id
-            } else {
-                let id = id_by_path.len();
-                id_by_path.insert(dep_path.clone(), id);
-                rev.push(Vec::new());
-                direct.push(false);
-                queue.push_back(dep_path.clone());
-                id
-            };
-            rev[target_id].push(source_id);
-        }
-    }
-
-    let mut dependents = vec![false; id_by_path.len()];
-    let mut queue = VecDeque::new();
-    for (id, is_direct) in direct.iter().copied().enumerate() {
-        if is_direct {
-            dependents[id] = true;
-            queue.push_back(id);
-        }
-    }
-
-    while let Some(current) = queue.pop_front() {
-        for &next in &rev[current] {
-            if !dependents[next] {
-                dependents[next] = true;
-                queue.push_back(next);
+        };
+        if retargeted {
+            // Parse warnings for this dpr were already reported above.
+            match reload_dpr_state(path, &writer, &mut Warnings::new()) {
+                Ok(Ok((bytes, list))) => {
+                    current_bytes = bytes;
+                    current_list = list;
+                }
+                Ok(Err(diagnostic)) => {
+                    summary.warnings.push(diagnostic);
+                    summary.record_failure(path);
+                    continue;
+                }
+                Err(err) => {
+                    summary.warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::ReadFailed,
+                            format!("failed to read dpr {}: {err}", path.display()),
+                        )
+                        .at(path),
+                    );
+                    summary.record_failure(path);
+                    continue;
+                }
             }
         }
-    }
-
-    Ok(ProjectDependents {
-        dependents,
-        direct,
-        id_by_path,
-    })
-}
-
-fn resolve_dep_path(
-    project_map: &HashMap<String, PathBuf>,
-    project_cache: &UnitCache,
-    delphi_cache: Option<&UnitCache>,
-    dep_name: &str,
-    source_path: &Path,
-    warnings: &mut Vec<String>,
-) -> Option<PathBuf> {
-    let dep_key = dep_name.to_ascii_lowercase();
-    if let Some(path) = project_map.get(&dep_key) {
-        return Some(path.clone());
-    }
-    match resolve_by_name(project_cache, delphi_cache, dep_name) {
-        ResolveByName::Unique { path, .. } => Some(path),
-        ResolveByName::Ambiguous { count, source } => {
-            warnings.push(format!(
-                "warning: ambiguous unit {} referenced by {} ({} {} matches)",
-                dep_name,
-                source_path.display(),
-                count,
-                source_label(source)
-            ));
-            None
-        }
-        ResolveByName::NotFound => None,
-    }
-}
-
-fn load_unit_uses(
-    project_cache: &mut UnitCache,
-    delphi_cache: Option<&mut UnitCache>,
-    unit_path: &Path,
-    warnings: &mut Vec<String>,
-    assumptions: &Assumptions,
-) -> io::Result<Option<Vec<String>>> {
-    let canonical = unit_cache::canonicalize_if_exists(unit_path);
-    if let Some(info) = project_cache.by_path.get(&canonical) {
-        return Ok(Some(flatten_unit_uses(info, assumptions)));
-    }
-
-    if let Some(delphi_cache) = delphi_cache {
-        if let Some(info) = delphi_cache.by_path.get(&canonical) {
-            return Ok(Some(flatten_unit_uses(info, assumptions)));
-        }
-    }
-
-    Ok(unit_cache::load_unit_file(&canonical, warnings)?
-        .map(|info| conditionals::flatten_conditional_uses(&info.conditional_uses, assumptions)))
-}
-
-fn collect_introduced_dependencies(
-    project_cache: &mut UnitCache,
-    mut delphi_cache: Option<&mut UnitCache>,
-    project_map: &HashMap<String, PathBuf>,
-    new_unit: &UnitFileInfo,
-    assumptions: &Assumptions,
-    warnings: &mut Vec<String>,
-) -> io::Result<Vec<UnitFileInfo>> {
-    let mut queue = VecDeque::new();
-    let mut seen_paths = HashSet::new();
-    let mut seen_names = HashSet::new();
-    let mut introduced = Vec::new();
-
-    let root_path = unit_cache::canonicalize_if_exists(&new_unit.path);
-    seen_paths.insert(root_path.clone());
-    queue.push_back(root_path.clone());
+        let active_root_names = collect_active_dpr_entry_names(
+            path,
+            &current_bytes,
+            assumptions,
+            &writer.includes,
+            &mut summary.warnings,
+        );
 
-    while let Some(unit_path) = queue.pop_front() {
-        let uses = match load_unit_uses(
+        let project_map = build_project_map(
+            path,
+            &current_list,
             project_cache,
-            delphi_cache.as_deref_mut(),
-            &unit_path,
-            warnings,
-            assumptions,
-        )? {
-            Some(uses) => uses,
-            None => {
-                warnings.push(format!(
-                    "warning: failed to read unit at {}",
-                    unit_path.display()
-                ));
-                continue;
-            }
-        };
+            delphi_cache,
+            &mut summary.warnings,
+        );
+        let has_new_unit = current_list
+            .entries
+            .iter()
+            .any(|entry| entry.name.eq_ignore_ascii_case(&new_unit.name));
+        let has_active_new_unit = active_root_names.as_ref().map_or(has_new_unit, |names| {
+            names.contains(&new_unit.name.to_ascii_lowercase())
+        });
 
-        for dep in uses {
-            if dep.eq_ignore_ascii_case(&new_unit.name) {
+        let mut needs_new_unit = false;
+        let mut insert_after = None;
+        if has_new_unit {
+            summary.record_dependency(DependencyOutcome::AlreadyPresent);
+        } else {
+            if project_map.is_empty() {
+                summary.record_dependency(DependencyOutcome::NotNeeded);
                 continue;
             }
-            let dep_path = resolve_dep_path(
-                project_map,
+
+            let started = Instant::now();
+            let dependents = compute_project_dependents(
                 project_cache,
-                delphi_cache.as_deref(),
-                dep.as_str(),
-                unit_path.as_path(),
-                warnings,
-            );
-            let Some(dep_path) = dep_path else {
-                continue;
-            };
-            let dep_path = unit_cache::canonicalize_if_exists(&dep_path);
-            if dep_path == root_path {
-                continue;
-            }
-            if seen_paths.insert(dep_path.clone()) {
-                queue.push_back(dep_path.clone());
+                delphi_cache,
+                &project_map,
+                &[new_unit.name.as_str()],
+                assumptions,
+                &mut summary.warnings,
+            )?;
+            summary.graph_time += started.elapsed();
+
+            for entry in &current_list.entries {
+                if !is_active_dpr_entry(active_root_names.as_ref(), entry) {
+                    continue;
+                }
+                let key = entry.name.to_ascii_lowercase();
+                if let Some(path) = project_map.get(&key) {
+                    let uses_new_unit = if options.direct_only {
+                        dependents.uses_target_directly(path)
+                    } else {
+                        dependents.reaches_target(path)
+                    };
+                    if uses_new_unit {
+                        needs_new_unit = true;
+                        break;
+                    }
+                }
             }
 
-            let dep_key = dep.to_ascii_lowercase();
-            if !seen_names.insert(dep_key) {
+            if !needs_new_unit {
+                summary.record_dependency(DependencyOutcome::NotNeeded);
                 continue;
             }
-            introduced.push(UnitFileInfo {
-                name: dep,
-                path: dep_path,
-                uses: Vec::new(),
-                conditional_uses: Vec::new(),
-            });
+            if options.edit.insert_position == InsertPosition::AfterIntroducer {
+                insert_after = find_direct_introducer_index(
+                    &current_list,
+                    &project_map,
+                    &dependents,
+                    active_root_names.as_ref(),
+                );
+            }
+            if let Some(marker) = &options.after_unit {
+                match find_listed_unit_index(&current_list, marker) {
+                    Some(idx) => insert_after = Some(idx),
+                    None => summary.infos.push(format!(
+                        "{marker} is not listed in {}; {} uses the default placement",
+                        path.display(),
+                        new_unit.name
+                    )),
+                }
+            }
         }
-    }
 
-    Ok(introduced)
-}
+        // A retargeted entry names the new unit, so the dpr always gets this far.
+        let mut dpr_updated = retargeted;
+        let mut last_inserted_name = None;
 
-fn resolve_dpr_unit_path(dpr_path: &Path, raw: &str) -> PathBuf {
-    let candidate = PathBuf::from(raw);
-    let resolved = if candidate.is_absolute() {
-        candidate
-    } else {
-        dpr_path
-            .parent()
-            .map(|parent| parent.join(&candidate))
-            .unwrap_or(candidate)
-    };
-    unit_cache::canonicalize_if_exists(&resolved)
-}
+        if needs_new_unit {
+            let updated = match insert_new_unit(
+                &current_bytes,
+                path,
+                &current_list,
+                new_unit,
+                insert_after,
+                &mut writer,
+                &mut summary.warnings,
+            ) {
+                Ok(value) => value,
+                Err(err) => {
+                    summary.warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::WriteFailed,
+                            format!("failed to update dpr {}: {err}", path.display()),
+                        )
+                        .at(path),
+                    );
+                    summary.record_failure(path);
+                    continue;
+                }
+            };
+            if !updated {
+                continue;
+            }
+            summary.record_dependency(DependencyOutcome::Inserted);
+            dpr_updated = true;
+            last_inserted_name = Some(new_unit.name.clone());
+            inserted_paths.push(new_unit.path.clone());
+            let reloaded = match reload_dpr_state(path, &writer, &mut summary.warnings) {
+                Ok(Ok(value)) => value,
+                Ok(Err(diagnostic)) => {
+                    summary.warnings.push(diagnostic);
+                    summary.record_failure(path);
+                    continue 'dpr_loop;
+                }
+                Err(err) => {
+                    summary.warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::ReadFailed,
+                            format!("failed to read dpr {}: {err}", path.display()),
+                        )
+                        .at(path),
+                    );
+                    summary.record_failure(path);
+                    continue 'dpr_loop;
+                }
+            };
+            current_bytes = reloaded.0;
+            current_list = reloaded.1;
+            summary.record_inserted(
+                &current_list,
+                new_unit,
+                resolution_source(project_cache, delphi_cache, &new_unit.path),
+            );
+        }
 
-fn insert_new_unit(
-    bytes: &[u8],
-    dpr_path: &Path,
-    list: &UsesList,
-    new_unit: &UnitFileInfo,
-    insert_after: Option<usize>,
-) -> io::Result<bool> {
-    let separator = if list.has_backslash {
-        '\\'
-    } else if list.has_slash {
-        '/'
-    } else {
-        '\\'
-    };
-    let entry_text = format_unit_entry(dpr_path, new_unit, separator);
+        if add_introduced_dependencies && (needs_new_unit || has_active_new_unit) {
+            let started = Instant::now();
+            let introduced = collect_introduced_dependencies(
+                project_cache,
+                delphi_cache,
+                &project_map,
+                new_unit,
+                assumptions,
+                &mut summary.warnings,
+                limits,
+            )?;
+            summary.graph_time += started.elapsed();
+            if has_new_unit && last_inserted_name.is_none() {
+                last_inserted_name = Some(new_unit.name.clone());
+            }
 
-    if let Some(idx) = insert_after {
-        if let Some((insert_at, insert_bytes)) =
-            build_insertion_after(bytes, list, idx, entry_text.as_bytes())
-        {
-            let mut output = Vec::with_capacity(bytes.len() + insert_bytes.len());
-            output.extend_from_slice(&bytes[..insert_at]);
-            output.extend_from_slice(&insert_bytes);
-            output.extend_from_slice(&bytes[insert_at..]);
-            write_atomic(dpr_path, &output)?;
-            return Ok(true);
-        }
-    }
+            for dep_unit in introduced {
+                if current_list
+                    .entries
+                    .iter()
+                    .any(|entry| entry.name.eq_ignore_ascii_case(&dep_unit.name))
+                {
+                    continue;
+                }
 
-    let line_ending = detect_line_ending(bytes);
-    let last_delim = list.entries.last().and_then(|entry| entry.delimiter);
-    let insertion = if list.multiline {
-        let prefix = if matches!(last_delim, Some(b',')) {
-            ""
-        } else {
-            ","
-        };
-        format!("{prefix}{line_ending}{}{}", list.indent, entry_text)
-    } else {
-        let prefix = if matches!(last_delim, Some(b',')) {
-            " "
-        } else {
-            ", "
-        };
-        format!("{prefix}{entry_text}")
-    };
+                let dep_insert_after = last_inserted_name
+                    .as_ref()
+                    .filter(|_| {
+                        options.edit.insert_position == InsertPosition::AfterIntroducer
+                            || options.after_unit.is_some()
+                    })
+                    .and_then(|name| find_listed_unit_index(&current_list, name));
+                let dep_updated = match insert_new_unit(
+                    &current_bytes,
+                    path,
+                    &current_list,
+                    &dep_unit,
+                    dep_insert_after,
+                    &mut writer,
+                    &mut summary.warnings,
+                ) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        summary.warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::WriteFailed,
+                                format!("failed to update dpr {}: {err}", path.display()),
+                            )
+                            .at(path),
+                        );
+                        summary.record_failure(path);
+                        continue 'dpr_loop;
+                    }
+                };
+                if !dep_updated {
+                    continue;
+                }
 
-    let insert_at = if list.multiline && !matches!(last_delim, Some(b',')) {
-        let mut pos = list.semicolon;
-        while pos > 0 && bytes[pos - 1].is_ascii_whitespace() {
-            pos -= 1;
+                dpr_updated = true;
+                last_inserted_name = Some(dep_unit.name.clone());
+                inserted_paths.push(dep_unit.path.clone());
+                let reloaded = match reload_dpr_state(path, &writer, &mut summary.warnings) {
+                    Ok(Ok(value)) => value,
+                    Ok(Err(diagnostic)) => {
+                        summary.warnings.push(diagnostic);
+                        summary.record_failure(path);
+                        continue 'dpr_loop;
+                    }
+                    Err(err) => {
+                        summary.warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::ReadFailed,
+                                format!("failed to read dpr {}: {err}", path.display()),
+                            )
+                            .at(path),
+                        );
+                        summary.record_failure(path);
+                        continue 'dpr_loop;
+                    }
+                };
+                current_bytes = reloaded.0;
+                current_list = reloaded.1;
+                summary.record_inserted(
+                    &current_list,
+                    &dep_unit,
+                    resolution_source(project_cache, delphi_cache, &dep_unit.path),
+                );
+            }
         }
-        pos
-    } else {
-        list.semicolon
-    };
 
-    let insert_bytes = insertion.as_bytes();
-    let mut output = Vec::with_capacity(bytes.len() + insert_bytes.len());
-    output.extend_from_slice(&bytes[..insert_at]);
-    output.extend_from_slice(insert_bytes);
-    output.extend_from_slice(&bytes[insert_at..]);
+        if dpr_updated {
+            summary.updated += 1;
+            summary.updated_paths.push(path.to_path_buf());
+        }
+    }
 
-    write_atomic(dpr_path, &output)?;
-    Ok(true)
+    summary.write_time = writer.write_time;
+    summary.staged = std::mem::take(&mut writer.staged);
+    summary.infos.append(&mut writer.infos);
+    summary.finish();
+    merge_units_outside_caches(
+        project_cache,
+        delphi_cache,
+        new_unit,
+        &inserted_paths,
+        &mut summary.warnings,
+    )?;
+    Ok(summary)
 }
 
-fn create_uses_section(bytes: &[u8], dpr_path: &Path, units: &[UnitFileInfo]) -> io::Result<bool> {
-    if units.is_empty() {
-        return Ok(false);
-    }
-
-    let header_semicolon = find_dpr_header_semicolon(bytes).ok_or_else(|| {
-        io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!(
-                "unable to locate program/library header in {}",
-                dpr_path.display()
-            ),
-        )
-    })?;
-    let line_ending = detect_line_ending(bytes);
-    let mut block = String::new();
-    block.push_str(line_ending);
-    block.push_str("uses");
-    block.push_str(line_ending);
-    for (idx, unit) in units.iter().enumerate() {
-        block.push_str("  ");
-        block.push_str(&format_unit_entry(dpr_path, unit, '\\'));
-        if idx + 1 == units.len() {
-            block.push(';');
+/// Adds inserted units that neither cache knows to the project cache, so a
+/// follow-up fix pass can treat them as dpr roots.
+fn merge_units_outside_caches(
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    new_unit: &UnitFileInfo,
+    inserted_paths: &[PathBuf],
+    warnings: &mut Warnings,
+) -> io::Result<()> {
+    for path in inserted_paths {
+        let canonical = project_cache.canonical.canonicalize(path);
+        if has_unit_path(project_cache, delphi_cache, &canonical) {
+            continue;
+        }
+        let info = if canonical == project_cache.canonical.canonicalize(&new_unit.path) {
+            Some(new_unit.clone())
         } else {
-            block.push(',');
+            project_cache.load_unit(&canonical, warnings)?
+        };
+        if let Some(info) = info {
+            unit_cache::insert_unit(project_cache, canonical, info);
         }
-        block.push_str(line_ending);
-    }
-
-    let suffix = &bytes[header_semicolon + 1..];
-    let (suffix, removed_line_ending) = strip_one_leading_line_ending(suffix);
-    let mut output = Vec::with_capacity(bytes.len() + block.len() + line_ending.len());
-    output.extend_from_slice(&bytes[..header_semicolon + 1]);
-    output.extend_from_slice(block.as_bytes());
-    if !suffix.is_empty() && !removed_line_ending {
-        output.extend_from_slice(line_ending.as_bytes());
     }
-    output.extend_from_slice(suffix);
-    write_atomic(dpr_path, &output)?;
-    Ok(true)
+    Ok(())
 }
 
-fn format_unit_entry(dpr_path: &Path, unit: &UnitFileInfo, separator: char) -> String {
-    let rel_path = relative_path(&unit.path, dpr_path.parent());
-    let separator_str = separator.to_string();
-    let rel_path = rel_path.replace(['\\', '/'], &separator_str);
-    format!("{} in '{}'", unit.name, rel_path)
+pub fn insert_dependency_files(
+    dpr_paths: &[PathBuf],
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    new_unit: &UnitFileInfo,
+    add_introduced_dependencies: bool,
+    assumptions: &Assumptions,
+) -> io::Result<DprUpdateSummary> {
+    let options = UpdateOptions::builder()
+        .add_introduced_dependencies(add_introduced_dependencies)
+        .build();
+    insert_dependency_files_with(
+        dpr_paths,
+        project_cache,
+        delphi_cache,
+        new_unit,
+        assumptions,
+        &options,
+    )
 }
 
-fn strip_one_leading_line_ending(bytes: &[u8]) -> (&[u8], bool) {
-    if bytes.starts_with(b"\r\n") {
-        (&bytes[2..], true)
-    } else if bytes.first() == Some(&b'\n') || bytes.first() == Some(&b'\r') {
-        (&bytes[1..], true)
-    } else {
-        (bytes, false)
-    }
-}
+/// Like [`update_dpr_files_with`], but inserts into every dpr; progress,
+/// events, and the insert position are not used.
+pub fn insert_dependency_files_with(
+    dpr_paths: &[PathBuf],
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    new_unit: &UnitFileInfo,
+    assumptions: &Assumptions,
+    options: &UpdateOptions,
+) -> io::Result<DprUpdateSummary> {
+    let mut summary = DprUpdateSummary::new();
+    let mut writer = options.edit.writer().unit_cache(project_cache);
+    let limits = &options.limits;
+    let add_introduced_dependencies = limits.introduced_scope != IntroducedScope::None;
 
-fn find_dpr_header_semicolon(bytes: &[u8]) -> Option<usize> {
-    let mut i = 0;
-    while i < bytes.len() {
-        match bytes[i] {
-            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
-            b'(' if bytes.get(i + 1) == Some(&b'*') => {
-                i = pas_lex::skip_paren_comment(bytes, i + 2)
-            }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
-            byte if pas_lex::is_ident_start(byte) => {
-                let (token, next) = pas_lex::read_ident(bytes, i);
-                if token.eq_ignore_ascii_case("program") || token.eq_ignore_ascii_case("library") {
-                    let mut j = next;
-                    while j < bytes.len() {
-                        match bytes[j] {
-                            b';' => return Some(j),
-                            b'{' => j = pas_lex::skip_brace_comment(bytes, j + 1),
-                            b'(' if bytes.get(j + 1) == Some(&b'*') => {
-                                j = pas_lex::skip_paren_comment(bytes, j + 2)
-                            }
-                            b'/' if bytes.get(j + 1) == Some(&b'/') => {
-                                j = pas_lex::skip_line_comment(bytes, j + 2)
-                            }
-                            b'\'' => j = pas_lex::skip_string(bytes, j + 1),
-                            _ => j += 1,
-                        }
-                    }
-                    return None;
-                }
-                i = next;
+    'dpr_loop: for path in in_processing_order(dpr_paths) {
+        summary.scanned += 1;
+        summary.begin_dpr(path);
+        let _lock = match writer.lock(path) {
+            Ok(lock) => lock,
+            Err(diagnostic) => {
+                summary.warnings.push(diagnostic);
+                summary.record_failure(path);
+                continue;
             }
-            _ => i += 1,
-        }
-    }
-    None
-}
-
-fn dpr_has_uses_keyword(bytes: &[u8]) -> bool {
-    let mut i = 0;
-    while i < bytes.len() {
-        match bytes[i] {
-            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
-            b'(' if bytes.get(i + 1) == Some(&b'*') => {
-                i = pas_lex::skip_paren_comment(bytes, i + 2)
+        };
+        let bytes = match writer.read_source(path, &mut summary.warnings) {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                summary.record_failure(path);
+                continue;
             }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
-            byte if pas_lex::is_ident_start(byte) => {
-                let (token, next) = pas_lex::read_ident(bytes, i);
-                if token.eq_ignore_ascii_case("uses") {
-                    return true;
-                }
-                i = next;
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!("failed to read dpr {}: {err}", path.display()),
+                    )
+                    .at(path),
+                );
+                summary.record_failure(path);
+                continue;
             }
-            _ => i += 1,
-        }
-    }
-    false
-}
+        };
 
-fn build_insertion_after(
-    bytes: &[u8],
-    list: &UsesList,
-    insert_after: usize,
-    entry_text: &[u8],
-) -> Option<(usize, Vec<u8>)> {
-    let entry = list.entries.get(insert_after)?;
-    if entry.from_include {
-        return None;
-    }
-    let delimiter_pos = entry.delimiter_pos?;
-    if entry.delimiter != Some(b',') {
-        return None;
-    }
-    let next_entry = list.entries.get(insert_after + 1)?;
-    let next_start = next_entry.start;
-    if delimiter_pos + 1 > next_start || next_start > bytes.len() {
-        return None;
-    }
-
-    let separator_after = &bytes[delimiter_pos + 1..next_start];
-    let separator_before = separator_before_new_entry(bytes, list, separator_after);
+        let mut current_bytes = bytes;
+        let parsed_list = parse_dpr_uses(
+            path,
+            &current_bytes,
+            &writer.includes,
+            &mut summary.warnings,
+        );
+        let mut current_list = match parsed_list {
+            Some(list) => list,
+            None => {
+                if dpr_has_uses_keyword(&current_bytes) {
+                    summary
+                        .warnings
+                        .push(uses_list_failure(path, &current_bytes));
+                    summary.record_failure(path);
+                    continue;
+                }
 
-    let mut insertion = Vec::new();
-    insertion.extend_from_slice(&separator_before);
-    insertion.extend_from_slice(entry_text);
-    insertion.push(b',');
+                let created = match create_uses_section(
+                    &current_bytes,
+                    path,
+                    std::slice::from_ref(new_unit),
+                    &mut writer,
+                    &mut summary.warnings,
+                ) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        summary.warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::WriteFailed,
+                                format!(
+                                    "failed to create uses section in {}: {err}",
+                                    path.display()
+                                ),
+                            )
+                            .at(path),
+                        );
+                        summary.record_failure(path);
+                        continue;
+                    }
+                };
+                if !created {
+                    continue;
+                }
 
-    Some((delimiter_pos + 1, insertion))
-}
+                let reloaded = match reload_dpr_state(path, &writer, &mut summary.warnings) {
+                    Ok(Ok(value)) => value,
+                    Ok(Err(diagnostic)) => {
+                        summary.warnings.push(diagnostic);
+                        summary.record_failure(path);
+                        continue 'dpr_loop;
+                    }
+                    Err(err) => {
+                        summary.warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::ReadFailed,
+                                format!("failed to read dpr {}: {err}", path.display()),
+                            )
+                            .at(path),
+                        );
+                        summary.record_failure(path);
+                        continue 'dpr_loop;
+                    }
+                };
+                current_bytes = reloaded.0;
+                let mut current_list = reloaded.1;
+                summary.record_inserted(
+                    &current_list,
+                    new_unit,
+                    resolution_source(project_cache, delphi_cache, &new_unit.path),
+                );
+                let mut dpr_updated = true;
+                let mut last_inserted_name = Some(new_unit.name.clone());
 
-fn separator_before_new_entry<'a>(
-    bytes: &[u8],
-    list: &UsesList,
-    separator_after: &'a [u8],
-) -> std::borrow::Cow<'a, [u8]> {
-    if separator_after
-        .iter()
-        .all(|byte| byte.is_ascii_whitespace())
-    {
-        return std::borrow::Cow::Borrowed(separator_after);
-    }
+                if add_introduced_dependencies {
+                    let project_map = build_project_map(
+                        path,
+                        &current_list,
+                        project_cache,
+                        delphi_cache,
+                        &mut summary.warnings,
+                    );
+                    let started = Instant::now();
+                    let introduced = collect_introduced_dependencies(
+                        project_cache,
+                        delphi_cache,
+                        &project_map,
+                        new_unit,
+                        assumptions,
+                        &mut summary.warnings,
+                        limits,
+                    )?;
+                    summary.graph_time += started.elapsed();
 
-    let leading_ws_len = separator_after
-        .iter()
-        .take_while(|byte| byte.is_ascii_whitespace())
-        .count();
-    if leading_ws_len > 0 {
-        return std::borrow::Cow::Borrowed(&separator_after[..leading_ws_len]);
-    }
+                    for dep_unit in introduced {
+                        if current_list
+                            .entries
+                            .iter()
+                            .any(|entry| entry.name.eq_ignore_ascii_case(&dep_unit.name))
+                        {
+                            continue;
+                        }
 
-    let line_ending = detect_line_ending(bytes);
-    let fallback = if list.multiline {
-        format!("{line_ending}{}", list.indent)
-    } else {
-        " ".to_string()
-    };
-    std::borrow::Cow::Owned(fallback.into_bytes())
-}
+                        let dep_insert_after = last_inserted_name.as_ref().and_then(|name| {
+                            current_list.entries.iter().position(|entry| {
+                                !entry.from_include && entry.name.eq_ignore_ascii_case(name)
+                            })
+                        });
+                        let dep_updated = match insert_new_unit(
+                            &current_bytes,
+                            path,
+                            &current_list,
+                            &dep_unit,
+                            dep_insert_after,
+                            &mut writer,
+                            &mut summary.warnings,
+                        ) {
+                            Ok(value) => value,
+                            Err(err) => {
+                                summary.warnings.push(
+                                    Diagnostic::warning(
+                                        DiagnosticCode::WriteFailed,
+                                        format!("failed to update dpr {}: {err}", path.display()),
+                                    )
+                                    .at(path),
+                                );
+                                summary.record_failure(path);
+                                continue 'dpr_loop;
+                            }
+                        };
+                        if !dep_updated {
+                            continue;
+                        }
 
-fn relative_path(target: &Path, base: Option<&Path>) -> String {
-    let target = unit_cache::canonicalize_if_exists(target);
-    if let Some(base) = base {
-        let base = unit_cache::canonicalize_if_exists(base);
-        if let Some(diff) = pathdiff::diff_paths(&target, &base) {
-            return diff.to_string_lossy().to_string();
-        }
-    }
-    target.to_string_lossy().to_string()
-}
+                        dpr_updated = true;
+                        last_inserted_name = Some(dep_unit.name.clone());
+                        let reloaded = match reload_dpr_state(path, &writer, &mut summary.warnings)
+                        {
+                            Ok(Ok(value)) => value,
+                            Ok(Err(diagnostic)) => {
+                                summary.warnings.push(diagnostic);
+                                summary.record_failure(path);
+                                continue 'dpr_loop;
+                            }
+                            Err(err) => {
+                                summary.warnings.push(
+                                    Diagnostic::warning(
+                                        DiagnosticCode::ReadFailed,
+                                        format!("failed to read dpr {}: {err}", path.display()),
+                                    )
+                                    .at(path),
+                                );
+                                summary.record_failure(path);
+                                continue 'dpr_loop;
+                            }
+                        };
+                        current_bytes = reloaded.0;
+                        current_list = reloaded.1;
+                        summary.record_inserted(
+                            &current_list,
+                            &dep_unit,
+                            resolution_source(project_cache, delphi_cache, &dep_unit.path),
+                        );
+                    }
+                }
 
-fn parse_dpr_uses(dpr_path: &Path, bytes: &[u8], warnings: &mut Vec<String>) -> Option<UsesList> {
-    let mut i = 0;
-    while i < bytes.len() {
-        match bytes[i] {
-            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
-            b'(' if bytes.get(i + 1) == Some(&b'*') => {
-                i = pas_lex::skip_paren_comment(bytes, i + 2)
+                if dpr_updated {
+                    summary.updated += 1;
+                    summary.updated_paths.push(path.clone());
+                }
+                continue;
             }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
-            byte if pas_lex::is_ident_start(byte) => {
-                let (token, next) = pas_lex::read_ident(bytes, i);
-                if token.eq_ignore_ascii_case("uses") {
-                    return parse_dpr_uses_list(dpr_path, bytes, next, warnings);
+        };
+
+        let mut dpr_updated = false;
+        let active_root_names = collect_active_dpr_entry_names(
+            path,
+            &current_bytes,
+            assumptions,
+            &writer.includes,
+            &mut summary.warnings,
+        );
+        let has_new_unit = current_list
+            .entries
+            .iter()
+            .any(|entry| entry.name.eq_ignore_ascii_case(&new_unit.name));
+        let has_active_new_unit = active_root_names.as_ref().map_or(has_new_unit, |names| {
+            names.contains(&new_unit.name.to_ascii_lowercase())
+        });
+        let mut last_inserted_name = None;
+
+        if !has_new_unit {
+            let updated = match insert_new_unit(
+                &current_bytes,
+                path,
+                &current_list,
+                new_unit,
+                None,
+                &mut writer,
+                &mut summary.warnings,
+            ) {
+                Ok(value) => value,
+                Err(err) => {
+                    summary.warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::WriteFailed,
+                            format!("failed to update dpr {}: {err}", path.display()),
+                        )
+                        .at(path),
+                    );
+                    summary.record_failure(path);
+                    continue;
                 }
-                i = next;
+            };
+            if !updated {
+                continue;
             }
-            _ => i += 1,
+            dpr_updated = true;
+            last_inserted_name = Some(new_unit.name.clone());
+            let reloaded = match reload_dpr_state(path, &writer, &mut summary.warnings) {
+                Ok(Ok(value)) => value,
+                Ok(Err(diagnostic)) => {
+                    summary.warnings.push(diagnostic);
+                    summary.record_failure(path);
+                    continue 'dpr_loop;
+                }
+                Err(err) => {
+                    summary.warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::ReadFailed,
+                            format!("failed to read dpr {}: {err}", path.display()),
+                        )
+                        .at(path),
+                    );
+                    summary.record_failure(path);
+                    continue 'dpr_loop;
+                }
+            };
+            current_bytes = reloaded.0;
+            current_list = reloaded.1;
+            summary.record_inserted(
+                &current_list,
+                new_unit,
+                resolution_source(project_cache, delphi_cache, &new_unit.path),
+            );
         }
-    }
-    None
-}
 
-fn parse_dpr_uses_list(
-    dpr_path: &Path,
-    bytes: &[u8],
-    i: usize,
-    warnings: &mut Vec<String>,
-) -> Option<UsesList> {
-    let list_start = i;
-    let mut entries = Vec::new();
-    let mut has_backslash = false;
-    let mut has_slash = false;
-    let mut include_semicolon = false;
-    let mut include_stack = Vec::new();
-    include_stack.push(unit_cache::canonicalize_if_exists(dpr_path));
-    let mut state = DprParseState {
-        warnings,
-        include_stack: &mut include_stack,
-        has_backslash: &mut has_backslash,
-        has_slash: &mut has_slash,
-        include_semicolon: &mut include_semicolon,
-    };
+        if add_introduced_dependencies && (dpr_updated || has_active_new_unit) {
+            let project_map = build_project_map(
+                path,
+                &current_list,
+                project_cache,
+                delphi_cache,
+                &mut summary.warnings,
+            );
+            let started = Instant::now();
+            let introduced = collect_introduced_dependencies(
+                project_cache,
+                delphi_cache,
+                &project_map,
+                new_unit,
+                assumptions,
+                &mut summary.warnings,
+                limits,
+            )?;
+            summary.graph_time += started.elapsed();
+            if has_active_new_unit && last_inserted_name.is_none() {
+                last_inserted_name = Some(new_unit.name.clone());
+            }
 
-    let semicolon =
-        parse_uses_fragment_for_dpr(bytes, i, dpr_path, &mut entries, &mut state, None)?;
-    if include_semicolon {
-        return None;
-    }
-    if entries.is_empty() {
-        return None;
-    }
-    let multiline = bytes[list_start..semicolon].contains(&b'\n');
-    let indent = if multiline {
-        entries
-            .first()
-            .map(|entry| infer_indent(bytes, entry.start))
-            .unwrap_or_default()
-    } else {
-        String::new()
-    };
-
-    Some(UsesList {
-        entries,
-        semicolon,
-        multiline,
-        indent,
-        has_backslash,
-        has_slash,
-    })
-}
+            for dep_unit in introduced {
+                if current_list
+                    .entries
+                    .iter()
+                    .any(|entry| entry.name.eq_ignore_ascii_case(&dep_unit.name))
+                {
+                    continue;
+                }
 
-struct DprParseState<'a> {
-    warnings: &'a mut Vec<String>,
-    include_stack: &'a mut Vec<PathBuf>,
-    has_backslash: &'a mut bool,
-    has_slash: &'a mut bool,
-    include_semicolon: &'a mut bool,
-}
+                let dep_insert_after = last_inserted_name.as_ref().and_then(|name| {
+                    current_list.entries.iter().position(|entry| {
+                        !entry.from_include && entry.name.eq_ignore_ascii_case(name)
+                    })
+                });
+                let dep_updated = match insert_new_unit(
+                    &current_bytes,
+                    path,
+                    &current_list,
+                    &dep_unit,
+                    dep_insert_after,
+                    &mut writer,
+                    &mut summary.warnings,
+                ) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        summary.warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::WriteFailed,
+                                format!("failed to update dpr {}: {err}", path.display()),
+                            )
+                            .at(path),
+                        );
+                        summary.record_failure(path);
+                        continue 'dpr_loop;
+                    }
+                };
+                if !dep_updated {
+                    continue;
+                }
 
-fn parse_uses_fragment_for_dpr(
-    bytes: &[u8],
-    mut i: usize,
-    source_path: &Path,
-    entries: &mut Vec<UsesEntry>,
-    state: &mut DprParseState<'_>,
-    entry_start_override: Option<usize>,
-) -> Option<usize> {
-    while i < bytes.len() {
-        i = skip_ws_comments_and_includes_dpr(
-            bytes,
-            i,
-            source_path,
-            entries,
-            state,
-            entry_start_override,
-        );
-        if i >= bytes.len() {
-            return None;
-        }
-        if bytes[i] == b';' {
-            if entry_start_override.is_some() {
-                state.warnings.push(format!(
-                    "warning: include file {} contains ';' in uses list",
-                    source_path.display()
-                ));
-                *state.include_semicolon = true;
+                dpr_updated = true;
+                last_inserted_name = Some(dep_unit.name.clone());
+                let reloaded = match reload_dpr_state(path, &writer, &mut summary.warnings) {
+                    Ok(Ok(value)) => value,
+                    Ok(Err(diagnostic)) => {
+                        summary.warnings.push(diagnostic);
+                        summary.record_failure(path);
+                        continue 'dpr_loop;
+                    }
+                    Err(err) => {
+                        summary.warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::ReadFailed,
+                                format!("failed to read dpr {}: {err}", path.display()),
+                            )
+                            .at(path),
+                        );
+                        summary.record_failure(path);
+                        continue 'dpr_loop;
+                    }
+                };
+                current_bytes = reloaded.0;
+                current_list = reloaded.1;
+                summary.record_inserted(
+                    &current_list,
+                    &dep_unit,
+                    resolution_source(project_cache, delphi_cache, &dep_unit.path),
+                );
             }
-            return Some(i);
         }
-        if !pas_lex::is_ident_start(bytes[i]) {
-            i += 1;
-            continue;
+
+        if dpr_updated {
+            summary.updated += 1;
+            summary.updated_paths.push(path.clone());
         }
+    }
 
-        let entry_start = i;
-        let (name, next) = pas_lex::read_ident_with_dots(bytes, i);
-        i = next;
-        i = pas_lex::skip_ws_and_comments(bytes, i);
+    summary.write_time = writer.write_time;
+    summary.staged = std::mem::take(&mut writer.staged);
+    summary.infos.append(&mut writer.infos);
+    summary.finish();
+    Ok(summary)
+}
 
-        let mut in_path = None;
-        if let Some((token, next_token)) = peek_ident(bytes, i) {
-            if token.eq_ignore_ascii_case("in") {
-                i = next_token;
-                i = skip_ws_and_comments_no_strings(bytes, i);
-                if i < bytes.len() && bytes[i] == b'\'' {
-                    if let Some((value, end)) = pas_lex::read_string_literal(bytes, i) {
-                        in_path = Some(value);
-                        i = end;
-                    } else {
-                        i = pas_lex::skip_string(bytes, i + 1);
-                    }
-                }
-            }
-        }
+/// Kept for one release; prefer [`fix_dpr_file_with`].
+pub fn fix_dpr_file(
+    dpr_path: &Path,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    assumptions: &Assumptions,
+) -> io::Result<DprUpdateSummary> {
+    fix_dpr_file_with(
+        dpr_path,
+        project_cache,
+        delphi_cache,
+        assumptions,
+        &FixOptions::default(),
+    )
+}
 
-        update_path_separator_flags(&in_path, state.has_backslash, state.has_slash);
+pub fn fix_dpr_file_with(
+    dpr_path: &Path,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    assumptions: &Assumptions,
+    options: &FixOptions,
+) -> io::Result<DprUpdateSummary> {
+    let dpr_path = unit_cache::canonicalize_if_exists(dpr_path);
+    let mut summary = DprUpdateSummary::new();
+    summary.events = EventHandle(options.edit.events.clone());
+    summary.scanned = 1;
+    summary.begin_dpr(&dpr_path);
+    options.edit.start_dpr(0, 1, &dpr_path);
+    let mut writer = options.writer(&dpr_path).unit_cache(project_cache);
+    match writer.lock(&dpr_path) {
+        Ok(_lock) => fix_dpr_uses(
+            &dpr_path,
+            project_cache,
+            delphi_cache,
+            assumptions,
+            options,
+            &mut writer,
+            &mut summary,
+        )?,
+        Err(diagnostic) => {
+            summary.warnings.push(diagnostic);
+            summary.record_failure(&dpr_path);
+        }
+    }
+    summary.write_time = writer.write_time;
+    summary.staged = std::mem::take(&mut writer.staged);
+    summary.infos.append(&mut writer.infos);
+    summary.finish();
+    Ok(summary)
+}
 
-        let (pos, delim, include_entries) =
-            scan_to_delimiter_with_includes(bytes, i, source_path, state, entry_start_override);
-        let start = entry_start_override.unwrap_or(entry_start);
-        entries.push(UsesEntry {
-            name,
-            in_path,
-            start,
-            delimiter: delim,
-            delimiter_pos: if entry_start_override.is_some() {
-                None
-            } else {
-                delim.map(|_| pos)
+fn fix_dpr_uses(
+    dpr_path: &Path,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    assumptions: &Assumptions,
+    options: &FixOptions,
+    writer: &mut DprWriter,
+    summary: &mut DprUpdateSummary,
+) -> io::Result<()> {
+    let limits = &options.limits;
+    let read = match writer.staged.get(dpr_path) {
+        Some(contents) => Ok(Some(contents.clone())),
+        None => writer.read_source(dpr_path, &mut summary.warnings),
+    };
+    let bytes = match read {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            summary.record_failure(dpr_path);
+            return Ok(());
+        }
+        Err(err) => {
+            summary.warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::ReadFailed,
+                    format!("failed to read dpr {}: {err}", dpr_path.display()),
+                )
+                .at(dpr_path),
+            );
+            summary.record_failure(dpr_path);
+            return Ok(());
+        }
+    };
+    let Some(list) = parse_dpr_uses(dpr_path, &bytes, &writer.includes, &mut summary.warnings)
+    else {
+        summary.warnings.push(uses_list_failure(dpr_path, &bytes));
+        summary.record_failure(dpr_path);
+        return Ok(());
+    };
+    let mut current_bytes = bytes;
+    let mut current_list = list;
+    let mut list_rewritten = false;
+    for step in [ListRewrite::Duplicates, ListRewrite::MismatchedPaths] {
+        let rewritten = match step {
+            ListRewrite::Duplicates => match options.fix_duplicates {
+                Some(policy) => remove_duplicate_entries(
+                    dpr_path,
+                    &current_bytes,
+                    &current_list,
+                    project_cache,
+                    delphi_cache,
+                    policy,
+                    writer,
+                    &mut summary.warnings,
+                ),
+                None => Ok(false),
             },
-            from_include: entry_start_override.is_some(),
-        });
-        if !include_entries.is_empty() {
-            entries.extend(include_entries);
+            ListRewrite::MismatchedPaths if options.fix_mismatched_paths => {
+                rewrite_mismatched_paths(
+                    dpr_path,
+                    &current_bytes,
+                    &current_list,
+                    project_cache,
+                    delphi_cache,
+                    writer,
+                    &mut summary.warnings,
+                )
+            }
+            ListRewrite::MismatchedPaths => Ok(false),
+        };
+        match rewritten {
+            Ok(true) => list_rewritten = true,
+            Ok(false) => continue,
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::WriteFailed,
+                        format!("failed to update dpr {}: {err}", dpr_path.display()),
+                    )
+                    .at(dpr_path),
+                );
+                summary.record_failure(dpr_path);
+                return Ok(());
+            }
         }
-        match delim {
-            Some(b',') => i = pos + 1,
-            Some(b';') => return Some(pos),
-            _ => return None,
+        // Parse warnings for this dpr were already reported above.
+        match reload_dpr_state(dpr_path, writer, &mut Warnings::new())? {
+            Ok((bytes, list)) => {
+                current_bytes = bytes;
+                current_list = list;
+            }
+            Err(diagnostic) => {
+                summary.warnings.push(diagnostic);
+                summary.record_failure(dpr_path);
+                return Ok(());
+            }
         }
     }
-    None
-}
+    let existing_names: HashSet<String> = current_list
+        .entries
+        .iter()
+        .map(|entry| entry.name.to_ascii_lowercase())
+        .collect();
 
-fn skip_ws_comments_and_includes_dpr(
-    bytes: &[u8],
-    mut i: usize,
-    source_path: &Path,
-    entries: &mut Vec<UsesEntry>,
-    state: &mut DprParseState<'_>,
-    entry_start_override: Option<usize>,
-) -> usize {
-    while i < bytes.len() {
-        match bytes[i] {
-            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
-            b'{' | b'(' => {
-                if let Some((include_name, end)) = pas_lex::parse_include_directive(bytes, i) {
-                    let anchor = entry_start_override.unwrap_or(i);
-                    let include_entries = parse_include_entries_for_dpr(
-                        include_name.as_str(),
-                        anchor,
-                        source_path,
-                        state,
-                    );
-                    if !include_entries.is_empty() {
-                        entries.extend(include_entries);
+    let mut missing_entries = Vec::new();
+    let project_map = build_project_map_collecting(
+        dpr_path,
+        &current_list,
+        project_cache,
+        delphi_cache,
+        &mut summary.warnings,
+        Some(&mut missing_entries),
+    );
+    if !missing_entries.is_empty() {
+        match remove_missing_entries(
+            dpr_path,
+            &current_bytes,
+            &current_list,
+            &missing_entries,
+            options.remove_missing,
+            writer,
+            summary,
+        ) {
+            Ok(false) => {}
+            Ok(true) => {
+                list_rewritten = true;
+                // Parse warnings for this dpr were already reported above.
+                match reload_dpr_state(dpr_path, writer, &mut Warnings::new())? {
+                    Ok((bytes, list)) => {
+                        current_bytes = bytes;
+                        current_list = list;
+                    }
+                    Err(diagnostic) => {
+                        summary.warnings.push(diagnostic);
+                        summary.record_failure(dpr_path);
+                        return Ok(());
                     }
-                    i = end;
-                    continue;
                 }
-                i = if bytes[i] == b'{' {
-                    pas_lex::skip_brace_comment(bytes, i + 1)
-                } else if bytes.get(i + 1) == Some(&b'*') {
-                    pas_lex::skip_paren_comment(bytes, i + 2)
-                } else {
-                    i + 1
-                };
             }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
-            _ => break,
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::WriteFailed,
+                        format!("failed to update dpr {}: {err}", dpr_path.display()),
+                    )
+                    .at(dpr_path),
+                );
+                summary.record_failure(dpr_path);
+                return Ok(());
+            }
         }
     }
-    i
-}
+    let active_root_names = collect_active_dpr_entry_names(
+        dpr_path,
+        &current_bytes,
+        assumptions,
+        &writer.includes,
+        &mut summary.warnings,
+    );
+    let root_paths = collect_fix_root_paths(
+        dpr_path,
+        &current_list,
+        &project_map,
+        project_cache,
+        delphi_cache,
+        active_root_names.as_ref(),
+        &mut summary.warnings,
+    );
+    if options.report_unresolved {
+        for entry in &current_list.entries {
+            if is_active_dpr_entry(active_root_names.as_ref(), entry)
+                && !project_map.contains_key(&entry.name.to_ascii_lowercase())
+                && matches!(
+                    resolve_by_name(project_cache, delphi_cache, &UnitName::new(&entry.name)),
+                    ResolveByName::NotFound
+                )
+            {
+                summary.record_unresolved(&entry.name, dpr_path);
+            }
+        }
+    }
+    if root_paths.is_empty() {
+        if list_rewritten {
+            summary.record_updated(dpr_path);
+        }
+        return Ok(());
+    }
 
-fn scan_to_delimiter_with_includes(
-    bytes: &[u8],
-    mut i: usize,
-    source_path: &Path,
-    state: &mut DprParseState<'_>,
-    entry_start_override: Option<usize>,
-) -> (usize, Option<u8>, Vec<UsesEntry>) {
-    let mut include_entries = Vec::new();
-    while i < bytes.len() {
-        match bytes[i] {
-            b',' | b';' => return (i, Some(bytes[i]), include_entries),
-            b'{' | b'(' => {
-                if let Some((include_name, end)) = pas_lex::parse_include_directive(bytes, i) {
-                    let anchor = entry_start_override.unwrap_or(i);
-                    let entries = parse_include_entries_for_dpr(
-                        include_name.as_str(),
-                        anchor,
-                        source_path,
-                        state,
+    let started = Instant::now();
+    let MissingDependencies {
+        units: missing_units,
+        mut chains,
+        suppressed_delphi,
+        unresolved,
+    } = collect_missing_dpr_dependencies(
+        &root_paths,
+        &existing_names,
+        project_cache,
+        delphi_cache,
+        assumptions,
+        &mut summary.warnings,
+        limits,
+    )?;
+    summary.graph_time += started.elapsed();
+    summary.suppressed_delphi_inserts += suppressed_delphi;
+    if options.report_unresolved {
+        for (name, unit_path) in unresolved {
+            summary.record_unresolved(&name, &unit_path);
+        }
+    }
+    if missing_units.is_empty() {
+        if list_rewritten {
+            summary.record_updated(dpr_path);
+        }
+        return Ok(());
+    }
+
+    let mut dpr_updated = list_rewritten;
+    let mut last_inserted_name = None::<String>;
+    let mut inserted = 0;
+    let missing_count = missing_units.len();
+    for (index, dep_unit) in missing_units.into_iter().enumerate() {
+        if options.max_inserts.is_some_and(|max| inserted >= max) {
+            if let Some(detail) = summary.details.last_mut() {
+                detail.not_inserted = missing_count - index;
+            }
+            break;
+        }
+        let dep_insert_after = last_inserted_name
+            .as_ref()
+            .filter(|_| options.edit.insert_position == InsertPosition::AfterIntroducer)
+            .and_then(|name| {
+                current_list
+                    .entries
+                    .iter()
+                    .position(|entry| !entry.from_include && entry.name.eq_ignore_ascii_case(name))
+            });
+        let dep_updated = match insert_new_unit(
+            &current_bytes,
+            dpr_path,
+            &current_list,
+            &dep_unit,
+            dep_insert_after,
+            writer,
+            &mut summary.warnings,
+        ) {
+            Ok(value) => value,
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::WriteFailed,
+                        format!("failed to update dpr {}: {err}", dpr_path.display()),
+                    )
+                    .at(dpr_path),
+                );
+                summary.record_failure(dpr_path);
+                return Ok(());
+            }
+        };
+        if !dep_updated {
+            continue;
+        }
+
+        dpr_updated = true;
+        inserted += 1;
+        last_inserted_name = Some(dep_unit.name.clone());
+        let reloaded = match reload_dpr_state(dpr_path, writer, &mut summary.warnings) {
+            Ok(Ok(value)) => value,
+            Ok(Err(diagnostic)) => {
+                summary.warnings.push(diagnostic);
+                summary.record_failure(dpr_path);
+                return Ok(());
+            }
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!("failed to read dpr {}: {err}", dpr_path.display()),
+                    )
+                    .at(dpr_path),
+                );
+                summary.record_failure(dpr_path);
+                return Ok(());
+            }
+        };
+        current_bytes = reloaded.0;
+        current_list = reloaded.1;
+        summary.record_inserted(
+            &current_list,
+            &dep_unit,
+            resolution_source(project_cache, delphi_cache, &dep_unit.path),
+        );
+        if let Some(inserted) = summary
+            .details
+            .last_mut()
+            .and_then(|detail| detail.inserted.last_mut())
+        {
+            inserted.chain = chains
+                .remove(&dep_unit.name.to_ascii_lowercase())
+                .unwrap_or_default();
+        }
+    }
+
+    if dpr_updated {
+        summary.updated += 1;
+        summary.updated_paths.push(dpr_path.to_path_buf());
+    }
+
+    Ok(())
+}
+
+/// Records the `missing` entries of the dpr and, with `remove`, cuts out the
+/// ones that are not pinned, conditional, or from an include fragment.
+///
+/// Returns whether the dpr was rewritten.
+fn remove_missing_entries(
+    dpr_path: &Path,
+    bytes: &[u8],
+    list: &UsesList,
+    missing: &[usize],
+    remove: bool,
+    writer: &mut DprWriter,
+    summary: &mut DprUpdateSummary,
+) -> io::Result<bool> {
+    let mut removed = HashSet::new();
+    if remove {
+        let conditional_names = conditional_entry_names(dpr_path, bytes, &writer.includes);
+        for &idx in missing {
+            let entry = &list.entries[idx];
+            if entry.from_include {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::IncludeFragmentEntry,
+                        format!(
+                            "cannot remove unit {} from {} because it originates from include fragment",
+                            entry.name,
+                            dpr_path.display()
+                        ),
+                    )
+                    .at(dpr_path),
+                );
+            } else if !is_pinned_entry(bytes, list, idx)
+                && !conditional_names.contains(&entry.name.to_ascii_lowercase())
+            {
+                removed.insert(idx);
+            }
+        }
+    }
+    let ranges = if removed.is_empty() {
+        Vec::new()
+    } else if let Some(ranges) = entry_removal_ranges(bytes, list, &removed) {
+        ranges
+    } else {
+        summary.warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::EntryNotRemoved,
+                format!(
+                    "missing units of {} sit next to comments or directives, or are all of its entries, and were not removed",
+                    dpr_path.display()
+                ),
+            )
+            .at(dpr_path),
+        );
+        removed.clear();
+        Vec::new()
+    };
+    if let Some(detail) = summary.details.last_mut() {
+        detail
+            .missing_entries
+            .extend(missing.iter().map(|&idx| MissingEntry {
+                name: list.entries[idx].name.clone(),
+                in_path: list.entries[idx].in_path.clone().unwrap_or_default(),
+                removed: removed.contains(&idx),
+            }));
+    }
+    if ranges.is_empty() {
+        return Ok(false);
+    }
+    let mut output = bytes.to_vec();
+    for (start, end) in ranges.into_iter().rev() {
+        output.drain(start..end);
+    }
+    writer.write(dpr_path, output)?;
+    Ok(true)
+}
+
+/// Writes a uses clause listing `seed_path` and its resolvable dependency closure.
+///
+/// A missing `dpr_path` is created as an empty program named after its file
+/// stem. A dpr whose uses list already has entries fails with `UsesListExists`
+/// unless `force` is set, in which case the list is replaced. The exclusions,
+/// depth limit, and Delphi insert setting of `options` apply as in
+/// [`fix_dpr_file_with`]; the fix-only list rewrites are not used.
+pub fn init_dpr_file_with(
+    dpr_path: &Path,
+    seed_path: &Path,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    assumptions: &Assumptions,
+    force: bool,
+    options: &FixOptions,
+) -> io::Result<DprUpdateSummary> {
+    let dpr_path = unit_cache::canonicalize_if_exists(dpr_path);
+    let mut summary = DprUpdateSummary::new();
+    summary.events = EventHandle(options.edit.events.clone());
+    summary.scanned = 1;
+    summary.begin_dpr(&dpr_path);
+    if let Some(events) = &options.edit.events {
+        events.on_dpr_started(0, 1, &dpr_path);
+    }
+    let mut writer = options.writer(&dpr_path).unit_cache(project_cache);
+    match writer.lock(&dpr_path) {
+        Ok(_lock) => init_dpr_uses(
+            &dpr_path,
+            seed_path,
+            project_cache,
+            delphi_cache,
+            assumptions,
+            force,
+            options,
+            &mut writer,
+            &mut summary,
+        )?,
+        Err(diagnostic) => {
+            summary.warnings.push(diagnostic);
+            summary.record_failure(&dpr_path);
+        }
+    }
+    summary.write_time = writer.write_time;
+    summary.staged = std::mem::take(&mut writer.staged);
+    summary.infos.append(&mut writer.infos);
+    summary.finish();
+    Ok(summary)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn init_dpr_uses(
+    dpr_path: &Path,
+    seed_path: &Path,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    assumptions: &Assumptions,
+    force: bool,
+    options: &FixOptions,
+    writer: &mut DprWriter,
+    summary: &mut DprUpdateSummary,
+) -> io::Result<()> {
+    let seed_path = project_cache.canonical.canonicalize(seed_path);
+    let seed = match lookup_unit_info(project_cache, delphi_cache, &seed_path) {
+        Some(unit) => unit.clone(),
+        None => project_cache
+            .load_unit(&seed_path, &mut summary.warnings)?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "unable to determine unit name from seed unit: {}",
+                        seed_path.display()
+                    ),
+                )
+            })?,
+    };
+
+    let exists = writer.staged.contains_key(dpr_path) || dpr_path.exists();
+    let bytes = if exists {
+        match writer.read(dpr_path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!("failed to read dpr {}: {err}", dpr_path.display()),
+                    )
+                    .at(dpr_path),
+                );
+                summary.record_failure(dpr_path);
+                return Ok(());
+            }
+        }
+    } else {
+        new_program_source(dpr_path).into_bytes()
+    };
+    let existing = parse_dpr_uses(dpr_path, &bytes, &writer.includes, &mut summary.warnings);
+    match &existing {
+        Some(list) if !force && !list.entries.is_empty() => {
+            summary.warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UsesListExists,
+                    format!(
+                        "{} already has a uses list with {} entries; pass --force to replace it",
+                        dpr_path.display(),
+                        list.entries.len()
+                    ),
+                )
+                .at(dpr_path),
+            );
+            summary.record_failure(dpr_path);
+            return Ok(());
+        }
+        None if dpr_has_uses_keyword(&bytes) => {
+            summary.warnings.push(uses_list_failure(dpr_path, &bytes));
+            summary.record_failure(dpr_path);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let started = Instant::now();
+    let found = collect_missing_dpr_dependencies(
+        std::slice::from_ref(&seed.path),
+        &HashSet::from([seed.name.to_ascii_lowercase()]),
+        project_cache,
+        delphi_cache,
+        assumptions,
+        &mut summary.warnings,
+        &options.limits,
+    )?;
+    summary.graph_time += started.elapsed();
+    summary.suppressed_delphi_inserts += found.suppressed_delphi;
+    let mut units = Vec::new();
+    for unit in std::iter::once(seed).chain(found.units) {
+        match writer.allows_unit(dpr_path, &unit, &mut summary.warnings) {
+            Ok(true) => units.push(unit),
+            Ok(false) => {}
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::WriteFailed,
+                        format!("failed to update dpr {}: {err}", dpr_path.display()),
+                    )
+                    .at(dpr_path),
+                );
+                summary.record_failure(dpr_path);
+                return Ok(());
+            }
+        }
+    }
+    if units.is_empty() {
+        return Ok(());
+    }
+
+    let output = match &existing {
+        Some(list) => {
+            let start = find_uses_keyword(&bytes).expect("a parsed uses list has a uses keyword");
+            let clause = format_uses_clause(
+                dpr_path,
+                &units,
+                writer,
+                writer.separator(Some(list)),
+                detect_line_ending(&bytes),
+            );
+            let mut output = bytes[..start].to_vec();
+            output.extend_from_slice(clause.as_bytes());
+            output.extend_from_slice(&bytes[list.semicolon + 1..]);
+            Ok(output)
+        }
+        None => insert_uses_section(&bytes, dpr_path, &units, writer),
+    };
+    let written = output.and_then(|output| {
+        if exists && output == bytes {
+            return Ok(false);
+        }
+        writer.write(dpr_path, output).map(|()| true)
+    });
+    match written {
+        Ok(true) => {}
+        Ok(false) => return Ok(()),
+        Err(err) => {
+            summary.warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::WriteFailed,
+                    format!("failed to write dpr {}: {err}", dpr_path.display()),
+                )
+                .at(dpr_path),
+            );
+            summary.record_failure(dpr_path);
+            return Ok(());
+        }
+    }
+
+    // Parse warnings for this dpr were already reported above.
+    if let Ok(Ok((_, list))) = reload_dpr_state(dpr_path, writer, &mut Warnings::new()) {
+        for unit in &units {
+            summary.record_inserted(
+                &list,
+                unit,
+                resolution_source(project_cache, delphi_cache, &unit.path),
+            );
+        }
+    }
+    summary.record_updated(dpr_path);
+    Ok(())
+}
+
+/// An empty program named after the file stem of `dpr_path`.
+fn new_program_source(dpr_path: &Path) -> String {
+    let name = dpr_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!("program {name};\r\n\r\nbegin\r\nend.\r\n")
+}
+
+/// Copies the uses entries of `source_path` that `dpr_path` does not list into `dpr_path`.
+///
+/// Entries are compared by unit name. A copied entry goes after the nearest
+/// preceding source entry the target lists, or at the end of the list, with its
+/// in-path rewritten relative to `dpr_path`. With `sync`, target entries the
+/// source does not list are removed unless they are pinned with a `fixdpr:pin`
+/// comment on their line. Entries from include fragments count for the
+/// comparison but are never copied or removed.
+pub fn copy_uses_with(
+    source_path: &Path,
+    dpr_path: &Path,
+    sync: bool,
+    options: &FixOptions,
+) -> io::Result<DprUpdateSummary> {
+    let source_path = unit_cache::canonicalize_if_exists(source_path);
+    let dpr_path = unit_cache::canonicalize_if_exists(dpr_path);
+    let mut summary = DprUpdateSummary::new();
+    summary.events = EventHandle(options.edit.events.clone());
+    summary.scanned = 1;
+    summary.begin_dpr(&dpr_path);
+    if let Some(events) = &options.edit.events {
+        events.on_dpr_started(0, 1, &dpr_path);
+    }
+    let mut writer = options.writer(&dpr_path);
+    match writer.lock(&dpr_path) {
+        Ok(_lock) => copy_dpr_uses(&source_path, &dpr_path, sync, &mut writer, &mut summary)?,
+        Err(diagnostic) => {
+            summary.warnings.push(diagnostic);
+            summary.record_failure(&dpr_path);
+        }
+    }
+    summary.write_time = writer.write_time;
+    summary.staged = std::mem::take(&mut writer.staged);
+    summary.infos.append(&mut writer.infos);
+    summary.finish();
+    Ok(summary)
+}
+
+fn copy_dpr_uses(
+    source_path: &Path,
+    dpr_path: &Path,
+    sync: bool,
+    writer: &mut DprWriter,
+    summary: &mut DprUpdateSummary,
+) -> io::Result<()> {
+    let Some((_, source)) = read_dpr_uses_list(
+        source_path,
+        writer.max_file_size,
+        &writer.includes,
+        &mut summary.warnings,
+    ) else {
+        summary.record_failure(dpr_path);
+        return Ok(());
+    };
+    let (mut bytes, mut list) = match reload_dpr_state(dpr_path, writer, &mut summary.warnings) {
+        Ok(Ok(state)) => state,
+        Ok(Err(diagnostic)) => {
+            summary.warnings.push(diagnostic);
+            summary.record_failure(dpr_path);
+            return Ok(());
+        }
+        Err(err) => {
+            summary.warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::ReadFailed,
+                    format!("failed to read dpr {}: {err}", dpr_path.display()),
+                )
+                .at(dpr_path),
+            );
+            summary.record_failure(dpr_path);
+            return Ok(());
+        }
+    };
+    let original = bytes.clone();
+    let separator = writer.separator(Some(&list));
+
+    let mut copied = Vec::new();
+    for (idx, entry) in source.entries.iter().enumerate() {
+        let listed = list
+            .entries
+            .iter()
+            .any(|listed| listed.name.eq_ignore_ascii_case(&entry.name));
+        if listed {
+            continue;
+        }
+        if entry.from_include {
+            summary.infos.push(format!(
+                "did not copy unit {} to {} because it comes from an include fragment of {}",
+                entry.name,
+                dpr_path.display(),
+                source_path.display()
+            ));
+            continue;
+        }
+        let entry_text = match &entry.in_path {
+            None => entry.name.clone(),
+            Some(raw_path) => {
+                let resolved = resolve_dpr_unit_path(source_path, raw_path);
+                if !resolved.is_file() {
+                    summary.warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::BrokenUsesPath,
+                            format!(
+                                "did not copy unit {} to {} because its path '{}' in {} does not exist",
+                                entry.name,
+                                dpr_path.display(),
+                                raw_path,
+                                source_path.display()
+                            ),
+                        )
+                        .at(source_path),
                     );
-                    if !entries.is_empty() {
-                        include_entries.extend(entries);
-                    }
-                    i = end;
                     continue;
                 }
-                i = if bytes[i] == b'{' {
-                    pas_lex::skip_brace_comment(bytes, i + 1)
-                } else if bytes.get(i + 1) == Some(&b'*') {
-                    pas_lex::skip_paren_comment(bytes, i + 2)
-                } else {
-                    i + 1
+                let unit = UnitFileInfo {
+                    name: entry.name.clone(),
+                    path: resolved,
+                    uses: Vec::new(),
+                    conditional_uses: Vec::new(),
+                    interface_uses: 0,
+                    name_from_stem: false,
                 };
+                match writer.allows_unit(dpr_path, &unit, &mut summary.warnings) {
+                    Ok(true) => format_unit_entry(dpr_path, &unit, writer, separator),
+                    Ok(false) => continue,
+                    Err(err) => {
+                        summary.warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::WriteFailed,
+                                format!("failed to update dpr {}: {err}", dpr_path.display()),
+                            )
+                            .at(dpr_path),
+                        );
+                        summary.record_failure(dpr_path);
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        let insert_after = source.entries[..idx]
+            .iter()
+            .rev()
+            .find_map(|previous| find_listed_unit_index(&list, &previous.name));
+        let insert_after = writer.unconditional_anchor(dpr_path, &list, insert_after, &entry.name);
+        bytes = insert_entry_text(
+            &bytes,
+            &list,
+            &entry_text,
+            insert_after,
+            writer.indent.as_deref(),
+        );
+        bytes = writer.wrap_long_list(dpr_path, bytes);
+        // Parse warnings for this dpr were already reported above.
+        let Some(updated) =
+            parse_dpr_uses(dpr_path, &bytes, &writer.includes, &mut Warnings::new())
+        else {
+            summary.warnings.push(uses_list_failure(dpr_path, &bytes));
+            summary.record_failure(dpr_path);
+            return Ok(());
+        };
+        list = updated;
+        copied.push(entry.name.clone());
+    }
+
+    if sync {
+        let source_names: HashSet<String> = source
+            .entries
+            .iter()
+            .map(|entry| entry.name.to_ascii_lowercase())
+            .collect();
+        let mut removed = HashSet::new();
+        for (idx, entry) in list.entries.iter().enumerate() {
+            if source_names.contains(&entry.name.to_ascii_lowercase())
+                || is_pinned_entry(&bytes, &list, idx)
+            {
+                continue;
+            }
+            if entry.from_include {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::IncludeFragmentEntry,
+                        format!(
+                            "cannot remove unit {} from {} because it originates from include fragment",
+                            entry.name,
+                            dpr_path.display()
+                        ),
+                    )
+                    .at(dpr_path),
+                );
+                continue;
+            }
+            removed.insert(idx);
+        }
+        if !removed.is_empty() {
+            match entry_removal_ranges(&bytes, &list, &removed) {
+                Some(ranges) => {
+                    let mut names: Vec<(usize, &str)> = removed
+                        .iter()
+                        .map(|&idx| (idx, list.entries[idx].name.as_str()))
+                        .collect();
+                    names.sort_unstable();
+                    for (_, name) in names {
+                        summary.infos.push(format!(
+                            "removed unit {name} from {} because {} does not list it",
+                            dpr_path.display(),
+                            source_path.display()
+                        ));
+                    }
+                    let mut output = bytes.clone();
+                    for (start, end) in ranges.into_iter().rev() {
+                        output.drain(start..end);
+                    }
+                    bytes = output;
+                }
+                None => summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::EntryNotRemoved,
+                        format!(
+                            "units of {} that {} does not list sit next to comments or directives, or are all of its entries, and were not removed",
+                            dpr_path.display(),
+                            source_path.display()
+                        ),
+                    )
+                    .at(dpr_path),
+                ),
             }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
-            _ => i += 1,
         }
     }
-    (i, None, include_entries)
+
+    if bytes == original {
+        return Ok(());
+    }
+    if let Err(err) = writer.write(dpr_path, bytes) {
+        summary.warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::WriteFailed,
+                format!("failed to write dpr {}: {err}", dpr_path.display()),
+            )
+            .at(dpr_path),
+        );
+        summary.record_failure(dpr_path);
+        return Ok(());
+    }
+    if let Ok(Ok((_, list))) = reload_dpr_state(dpr_path, writer, &mut Warnings::new()) {
+        for name in &copied {
+            summary.record_inserted_name(&list, name, false, None, None);
+        }
+    }
+    summary.record_updated(dpr_path);
+    Ok(())
+}
+
+/// Whether entry `idx` has a `fixdpr:pin` comment on its line, such as
+/// `TestUtils in 'TestUtils.pas', // fixdpr:pin`.
+fn is_pinned_entry(bytes: &[u8], list: &UsesList, idx: usize) -> bool {
+    let entry = &list.entries[idx];
+    if entry.from_include {
+        return false;
+    }
+    let entry_end = entry.delimiter_pos.unwrap_or(list.semicolon);
+    let mut end = bytes[entry_end..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |pos| entry_end + pos);
+    if let Some(next) = list.entries.get(idx + 1).and_then(UsesEntry::offset) {
+        end = end.min(next);
+    }
+    bytes[entry.start..end]
+        .windows(PIN_MARKER.len())
+        .any(|window| window.eq_ignore_ascii_case(PIN_MARKER.as_bytes()))
+}
+
+pub fn delete_dependency_files(
+    dpr_paths: &[PathBuf],
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    old_dependency_name: &str,
+    assumptions: &Assumptions,
+) -> io::Result<DprUpdateSummary> {
+    delete_dependency_files_with(
+        dpr_paths,
+        project_cache,
+        delphi_cache,
+        old_dependency_name,
+        assumptions,
+        &UpdateOptions::default(),
+    )
 }
 
-fn parse_include_entries_for_dpr(
-    include_name: &str,
-    anchor: usize,
-    source_path: &Path,
-    state: &mut DprParseState<'_>,
-) -> Vec<UsesEntry> {
-    let DprParseState {
-        warnings,
-        include_stack,
-        has_backslash,
-        has_slash,
-        include_semicolon,
-    } = &mut *state;
+/// Like [`delete_dependency_files`]; only the dry run, lock timeout, and write retry of `options` are used.
+pub fn delete_dependency_files_with(
+    dpr_paths: &[PathBuf],
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    old_dependency_name: &str,
+    assumptions: &Assumptions,
+    options: &UpdateOptions,
+) -> io::Result<DprUpdateSummary> {
+    let mut summary = DprUpdateSummary::new();
+    let edit = &options.edit;
+    let mut writer = DprWriter::new(
+        edit.dry_run,
+        PathStyle::default(),
+        CrossDrive::default(),
+        edit.lock_timeout,
+        edit.write_retry,
+    )
+    .limit_parent_dirs(edit.max_parent_dirs, edit.on_deep_relative)
+    .max_file_size(edit.max_file_size)
+    .unit_cache(project_cache);
+
+    for path in in_processing_order(dpr_paths) {
+        summary.scanned += 1;
+        summary.begin_dpr(path);
+        let _lock = match writer.lock(path) {
+            Ok(lock) => lock,
+            Err(diagnostic) => {
+                summary.warnings.push(diagnostic);
+                summary.record_failure(path);
+                continue;
+            }
+        };
+        let bytes = match writer.read_source(path, &mut summary.warnings) {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                summary.record_failure(path);
+                continue;
+            }
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!("failed to read dpr {}: {err}", path.display()),
+                    )
+                    .at(path),
+                );
+                summary.record_failure(path);
+                continue;
+            }
+        };
+        let Some(list) = parse_dpr_uses(path, &bytes, &writer.includes, &mut summary.warnings)
+        else {
+            continue;
+        };
+        let active_root_names = collect_active_dpr_entry_names(
+            path,
+            &bytes,
+            assumptions,
+            &writer.includes,
+            &mut summary.warnings,
+        );
+
+        let started = Instant::now();
+        let removal_set = collect_cascading_delete_names(
+            path,
+            &list,
+            project_cache,
+            delphi_cache,
+            old_dependency_name,
+            active_root_names.as_ref(),
+            assumptions,
+            &mut summary.warnings,
+        )?;
+        summary.graph_time += started.elapsed();
+        let Some(removal_set) = removal_set else {
+            continue;
+        };
+
+        if !can_delete_entries(path, &list, &removal_set, &mut summary.warnings) {
+            continue;
+        }
+
+        let started = Instant::now();
+        let updated = delete_uses_entries(path, &bytes, &list, &removal_set, &mut writer);
+        summary.write_time += started.elapsed();
+        let updated = match updated {
+            Ok(value) => value,
+            Err(err) => {
+                summary.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::WriteFailed,
+                        format!("failed to update dpr {}: {err}", path.display()),
+                    )
+                    .at(path),
+                );
+                summary.record_failure(path);
+                continue;
+            }
+        };
+        if updated {
+            summary.updated += 1;
+            summary.updated_paths.push(path.clone());
+        }
+    }
+
+    summary.staged = std::mem::take(&mut writer.staged);
+    summary.infos.append(&mut writer.infos);
+    summary.finish();
+    Ok(summary)
+}
+
+/// Consumers of one unit found by [`who_uses`].
+#[derive(Debug, Default)]
+pub struct WhoUsesReport {
+    pub scanned: usize,
+    /// Dprs whose uses list names the unit.
+    pub direct_dprs: Vec<PathBuf>,
+    /// Dprs that only reach the unit through units they list.
+    pub transitive_dprs: Vec<PathBuf>,
+    /// Project units whose uses clauses name the unit.
+    pub units: Vec<PathBuf>,
+    pub warnings: Warnings,
+}
+
+impl WhoUsesReport {
+    pub fn is_empty(&self) -> bool {
+        self.direct_dprs.is_empty() && self.transitive_dprs.is_empty() && self.units.is_empty()
+    }
+}
+
+/// Lists the dprs and project units that use `unit_name`.
+///
+/// With `transitive`, dprs reaching the unit through the dependency graph are
+/// reported as well.
+pub fn who_uses(
+    dpr_paths: &[PathBuf],
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    unit_name: &str,
+    assumptions: &Assumptions,
+    transitive: bool,
+) -> io::Result<WhoUsesReport> {
+    let mut report = WhoUsesReport::default();
+
+    for path in dpr_paths {
+        report.scanned += 1;
+        match dpr_reach(
+            path,
+            project_cache,
+            delphi_cache,
+            &[unit_name],
+            assumptions,
+            transitive,
+            &mut report.warnings,
+        )? {
+            Some(Reach::Direct) => report.direct_dprs.push(path.clone()),
+            Some(Reach::Transitive) => report.transitive_dprs.push(path.clone()),
+            None => {}
+        }
+    }
+
+    let mut units: Vec<PathBuf> = project_cache
+        .by_path
+        .iter()
+        .filter(|(_, info)| !info.name.eq_ignore_ascii_case(unit_name))
+        .filter(|(_, info)| {
+            flatten_unit_uses(info, assumptions)
+                .iter()
+                .any(|name| name.as_str().eq_ignore_ascii_case(unit_name))
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+    units.sort();
+    report.units = units;
+
+    Ok(report)
+}
+
+/// Dprs whose dependency closure includes a unit, found by [`impacted_dprs`].
+#[derive(Debug, Default)]
+pub struct ImpactReport {
+    pub scanned: usize,
+    pub affected: Vec<PathBuf>,
+    pub warnings: Warnings,
+}
+
+/// Lists the dprs that list or transitively reach any of `unit_names`.
+pub fn impacted_dprs(
+    dpr_paths: &[PathBuf],
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    unit_names: &[String],
+    assumptions: &Assumptions,
+) -> io::Result<ImpactReport> {
+    let mut report = ImpactReport {
+        scanned: dpr_paths.len(),
+        ..ImpactReport::default()
+    };
+    let targets: Vec<&str> = unit_names.iter().map(String::as_str).collect();
+
+    if !targets.is_empty() {
+        for path in dpr_paths {
+            let reach = dpr_reach(
+                path,
+                project_cache,
+                delphi_cache,
+                &targets,
+                assumptions,
+                true,
+                &mut report.warnings,
+            )?;
+            if reach.is_some() {
+                report.affected.push(path.clone());
+            }
+        }
+    }
+    report.affected.sort();
+
+    Ok(report)
+}
+
+/// One unit that depends on the unit passed to [`unit_dependents`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependentUnit {
+    pub name: String,
+    pub path: PathBuf,
+    /// The unit's own uses clauses name the target unit.
+    pub direct: bool,
+    /// Section of the first uses clause naming the target unit, for direct dependents.
+    pub section: Option<Section>,
+    /// Dprs whose uses list contains this unit.
+    pub dprs: Vec<PathBuf>,
+}
+
+/// Units that depend on one unit, found by [`unit_dependents`].
+#[derive(Debug, Default)]
+pub struct DependentsReport {
+    pub units: Vec<DependentUnit>,
+    pub warnings: Warnings,
+}
+
+/// Lists the units that use `unit_name`, directly or through other units.
+///
+/// With `direct_only`, only units whose own uses clauses name the unit are
+/// reported. Each dependent records the dprs in `dpr_paths` that list it.
+pub fn unit_dependents(
+    dpr_paths: &[PathBuf],
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    unit_name: &str,
+    assumptions: &Assumptions,
+    direct_only: bool,
+) -> io::Result<DependentsReport> {
+    let mut report = DependentsReport::default();
+    let mut roots: Vec<PathBuf> = project_cache.by_path.keys().cloned().collect();
+    roots.sort();
+    let no_project_map = HashMap::new();
+
+    let warnings = &mut report.warnings;
+    let graph = graph::build_unit_graph::<io::Error, _>(roots, |unit_path| {
+        let Some(uses) = load_unit_uses(
+            project_cache,
+            delphi_cache,
+            unit_path,
+            warnings,
+            assumptions,
+        )?
+        else {
+            return Ok(None);
+        };
+        let mut edges = UnitEdges::default();
+        for dep in uses {
+            if dep.as_str().eq_ignore_ascii_case(unit_name) {
+                edges.marked = true;
+            } else if let Some(dep_path) = resolve_dep_path(
+                &no_project_map,
+                project_cache,
+                delphi_cache,
+                &dep,
+                unit_path,
+                warnings,
+            ) {
+                edges.deps.push(dep_path);
+            }
+        }
+        Ok(Some(edges))
+    })?;
+    let flags = if direct_only {
+        graph.marked.clone()
+    } else {
+        graph::compute_dependents(&graph, &graph.marked_ids())
+    };
+
+    let mut index_by_path = HashMap::new();
+    for (path, _) in graph.paths.iter().zip(&flags).filter(|(_, flag)| **flag) {
+        let info = project_cache
+            .by_path
+            .get(path)
+            .or_else(|| delphi_cache.and_then(|cache| cache.unit(path)));
+        let Some(info) = info else {
+            continue;
+        };
+        if info.name.eq_ignore_ascii_case(unit_name) {
+            continue;
+        }
+        let id = graph.id(path).expect("graph path has an id");
+        let direct = graph.marked[id.0];
+        let section = info
+            .uses
+            .iter()
+            .find(|dep| direct && dep.name.as_str().eq_ignore_ascii_case(unit_name))
+            .map(|dep| dep.section);
+        index_by_path.insert(path.clone(), report.units.len());
+        report.units.push(DependentUnit {
+            name: info.name.clone(),
+            path: path.clone(),
+            direct,
+            section,
+            dprs: Vec::new(),
+        });
+    }
+
+    for dpr_path in dpr_paths {
+        let Some((_, list)) = read_dpr_uses_list(
+            dpr_path,
+            project_cache.source.max_file_size,
+            &project_cache.includes,
+            &mut report.warnings,
+        ) else {
+            continue;
+        };
+        let project_map = build_project_map(
+            dpr_path,
+            &list,
+            project_cache,
+            delphi_cache,
+            &mut report.warnings,
+        );
+        for path in project_map.values() {
+            if let Some(&index) = index_by_path.get(path) {
+                let dprs = &mut report.units[index].dprs;
+                if !dprs.contains(dpr_path) {
+                    dprs.push(dpr_path.clone());
+                }
+            }
+        }
+    }
+
+    for unit in &mut report.units {
+        unit.dprs.sort();
+    }
+    report.units.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(report)
+}
+
+/// Dependency counts of one dpr, found by [`dpr_fanout`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DprFanout {
+    pub path: PathBuf,
+    /// Entries in the dpr's uses list.
+    pub direct: usize,
+    /// Units in the dependency closure found in the project cache.
+    pub project_units: usize,
+    /// Units in the dependency closure found only in the Delphi cache.
+    pub delphi_units: usize,
+}
+
+impl DprFanout {
+    pub fn closure(&self) -> usize {
+        self.project_units + self.delphi_units
+    }
+}
+
+/// Per-dpr dependency counts, found by [`dpr_fanout`].
+#[derive(Debug, Default)]
+pub struct FanoutReport {
+    pub scanned: usize,
+    /// Heaviest dprs first.
+    pub dprs: Vec<DprFanout>,
+    pub warnings: Warnings,
+}
+
+/// Counts the direct entries and the dependency closure of every dpr.
+///
+/// The closure holds the active listed units and every unit they reach. Dprs
+/// without a uses list are left out; the rest are sorted by closure size,
+/// largest first.
+pub fn dpr_fanout(
+    dpr_paths: &[PathBuf],
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    assumptions: &Assumptions,
+) -> io::Result<FanoutReport> {
+    let mut report = FanoutReport {
+        scanned: dpr_paths.len(),
+        ..FanoutReport::default()
+    };
+
+    for path in dpr_paths {
+        let warnings = &mut report.warnings;
+        let Some((bytes, list)) = read_dpr_uses_list(
+            path,
+            project_cache.source.max_file_size,
+            &project_cache.includes,
+            warnings,
+        ) else {
+            continue;
+        };
+        let project_map = build_project_map(path, &list, project_cache, delphi_cache, warnings);
+        let active_root_names = collect_active_dpr_entry_names(
+            path,
+            &bytes,
+            assumptions,
+            &project_cache.includes,
+            warnings,
+        );
+        let root_paths = collect_fix_root_paths(
+            path,
+            &list,
+            &project_map,
+            project_cache,
+            delphi_cache,
+            active_root_names.as_ref(),
+            warnings,
+        );
+        let closure = collect_dependency_closure(
+            &root_paths,
+            project_cache,
+            delphi_cache,
+            assumptions,
+            warnings,
+        )?;
+        let project_units = closure
+            .iter()
+            .filter(|unit| project_cache.by_path.contains_key(*unit))
+            .count();
+        report.dprs.push(DprFanout {
+            path: path.clone(),
+            direct: list.entries.len(),
+            project_units,
+            delphi_units: closure.len() - project_units,
+        });
+    }
+
+    report.dprs.sort_by(|a, b| {
+        b.closure()
+            .cmp(&a.closure())
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(report)
+}
+
+/// `root_paths` and every unit they reach through resolvable uses clauses.
+fn collect_dependency_closure(
+    root_paths: &[PathBuf],
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    assumptions: &Assumptions,
+    warnings: &mut Warnings,
+) -> io::Result<HashSet<PathBuf>> {
+    let mut seen: HashSet<PathBuf> = root_paths.iter().cloned().collect();
+    let mut queue: VecDeque<PathBuf> = root_paths.iter().cloned().collect();
+
+    while let Some(unit_path) = queue.pop_front() {
+        let Some(uses) = load_unit_uses_readonly(
+            project_cache,
+            delphi_cache,
+            &unit_path,
+            warnings,
+            assumptions,
+        )?
+        else {
+            continue;
+        };
+        for dep in uses {
+            let ResolveByName::Unique { path, .. } =
+                resolve_by_name(project_cache, delphi_cache, &dep)
+            else {
+                continue;
+            };
+            let dep_path = project_cache.canonical.canonicalize(&path);
+            if has_unit_path(project_cache, delphi_cache, &dep_path)
+                && seen.insert(dep_path.clone())
+            {
+                queue.push_back(dep_path);
+            }
+        }
+    }
+
+    Ok(seen)
+}
+
+enum Reach {
+    Direct,
+    Transitive,
+}
+
+/// How a dpr reaches one of `targets`, or `None` when it does not or cannot be read.
+fn dpr_reach(
+    path: &Path,
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    targets: &[&str],
+    assumptions: &Assumptions,
+    transitive: bool,
+    warnings: &mut Warnings,
+) -> io::Result<Option<Reach>> {
+    let Some((bytes, list)) = read_dpr_uses_list(
+        path,
+        project_cache.source.max_file_size,
+        &project_cache.includes,
+        warnings,
+    ) else {
+        return Ok(None);
+    };
+    if list.entries.iter().any(|entry| {
+        targets
+            .iter()
+            .any(|target| entry.name.eq_ignore_ascii_case(target))
+    }) {
+        return Ok(Some(Reach::Direct));
+    }
+    if !transitive {
+        return Ok(None);
+    }
+
+    let active_root_names = collect_active_dpr_entry_names(
+        path,
+        &bytes,
+        assumptions,
+        &project_cache.includes,
+        warnings,
+    );
+    let project_map = build_project_map(path, &list, project_cache, delphi_cache, warnings);
+    if project_map.is_empty() {
+        return Ok(None);
+    }
+    let dependents = compute_project_dependents(
+        project_cache,
+        delphi_cache,
+        &project_map,
+        targets,
+        assumptions,
+        warnings,
+    )?;
+    let reaches = list.entries.iter().any(|entry| {
+        is_active_dpr_entry(active_root_names.as_ref(), entry)
+            && project_map
+                .get(&entry.name.to_ascii_lowercase())
+                .is_some_and(|path| dependents.reaches_target(path))
+    });
+    Ok(reaches.then_some(Reach::Transitive))
+}
+
+fn read_dpr_uses_list(
+    path: &Path,
+    max_file_size: u64,
+    includes: &Includes,
+    warnings: &mut Warnings,
+) -> Option<(Vec<u8>, UsesList)> {
+    let bytes = match unit_cache::read_source_file(path, max_file_size, warnings) {
+        Ok(Some(data)) => data,
+        Ok(None) => return None,
+        Err(err) => {
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::ReadFailed,
+                    format!("failed to read dpr {}: {err}", path.display()),
+                )
+                .at(path),
+            );
+            return None;
+        }
+    };
+    let Some(list) = parse_dpr_uses(path, &bytes, includes, warnings) else {
+        warnings.push(uses_list_failure(path, &bytes));
+        return None;
+    };
+    Some((bytes, list))
+}
+
+fn can_delete_entries(
+    dpr_path: &Path,
+    list: &UsesList,
+    removal_set: &HashSet<String>,
+    warnings: &mut Warnings,
+) -> bool {
+    for entry in &list.entries {
+        let key = entry.name.to_ascii_lowercase();
+        if !removal_set.contains(&key) {
+            continue;
+        }
+        if !entry.from_include {
+            continue;
+        }
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::IncludeFragmentEntry,
+                format!(
+                    "cannot remove unit {} from {} because it originates from include fragment",
+                    entry.name,
+                    dpr_path.display()
+                ),
+            )
+            .at(dpr_path),
+        );
+        return false;
+    }
+    true
+}
+
+fn delete_uses_entries(
+    dpr_path: &Path,
+    bytes: &[u8],
+    list: &UsesList,
+    removal_set: &HashSet<String>,
+    writer: &mut DprWriter,
+) -> io::Result<bool> {
+    let mut kept = Vec::new();
+    for entry in &list.entries {
+        let key = entry.name.to_ascii_lowercase();
+        if removal_set.contains(&key) {
+            continue;
+        }
+        if entry.from_include {
+            return Ok(false);
+        }
+        kept.push(entry);
+    }
+
+    if kept.is_empty() {
+        return Ok(false);
+    }
+
+    let removed: HashSet<usize> = list
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| removal_set.contains(&entry.name.to_ascii_lowercase()))
+        .map(|(idx, _)| idx)
+        .collect();
+    if let Some(ranges) = entry_removal_ranges(bytes, list, &removed) {
+        let mut output = bytes.to_vec();
+        for (start, end) in ranges.into_iter().rev() {
+            output.drain(start..end);
+        }
+        writer.write(dpr_path, output)?;
+        return Ok(true);
+    }
+
+    let list_start = list
+        .entries
+        .first()
+        .map(|entry| entry.start)
+        .unwrap_or(list.semicolon);
+    let new_body = render_uses_entries(list, &kept);
+
+    let mut output = Vec::with_capacity(bytes.len() + new_body.len());
+    output.extend_from_slice(&bytes[..list_start]);
+    output.extend_from_slice(new_body.as_bytes());
+    output.extend_from_slice(&bytes[list.semicolon..]);
+    writer.write(dpr_path, output)?;
+    Ok(true)
+}
+
+fn render_uses_entries(list: &UsesList, entries: &[&UsesEntry]) -> String {
+    if list.multiline {
+        let mut output = String::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            if idx > 0 {
+                output.push(',');
+                output.push('\n');
+                output.push_str(&list.indent);
+            }
+            output.push_str(&entry.name);
+            if let Some(in_path) = entry.in_path.as_ref() {
+                output.push_str(" in '");
+                output.push_str(in_path);
+                output.push('\'');
+            }
+        }
+        output
+    } else {
+        let mut parts = Vec::new();
+        for entry in entries {
+            if let Some(in_path) = entry.in_path.as_ref() {
+                parts.push(format!("{} in '{}'", entry.name, in_path));
+            } else {
+                parts.push(entry.name.clone());
+            }
+        }
+        parts.join(", ")
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn collect_cascading_delete_names(
+    dpr_path: &Path,
+    list: &UsesList,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    old_dependency_name: &str,
+    active_root_names: Option<&HashSet<String>>,
+    assumptions: &Assumptions,
+    warnings: &mut Warnings,
+) -> io::Result<Option<HashSet<String>>> {
+    let root_key = old_dependency_name.to_ascii_lowercase();
+    let mut all_present = HashSet::new();
+    for entry in &list.entries {
+        all_present.insert(entry.name.to_ascii_lowercase());
+    }
+    if !all_present.contains(&root_key) {
+        return Ok(None);
+    }
+    let mut present = active_root_names
+        .cloned()
+        .unwrap_or_else(|| all_present.clone());
+    present.insert(root_key.clone());
+
+    let project_map = build_project_map(dpr_path, list, project_cache, delphi_cache, warnings);
+    let mut edges: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut incoming: HashMap<String, usize> = HashMap::new();
+    for key in &present {
+        incoming.insert(key.clone(), 0);
+    }
+
+    for key in &present {
+        let Some(unit_path) = project_map.get(key) else {
+            continue;
+        };
+        let uses = match load_unit_uses_readonly(
+            project_cache,
+            delphi_cache,
+            unit_path,
+            warnings,
+            assumptions,
+        )? {
+            Some(value) => value,
+            None => continue,
+        };
+        for dep_name in uses {
+            if !present.contains(dep_name.key()) {
+                continue;
+            }
+            let dep_key = dep_name.key().to_string();
+            let inserted = edges
+                .entry(key.clone())
+                .or_default()
+                .insert(dep_key.clone());
+            if inserted {
+                *incoming.entry(dep_key).or_default() += 1;
+            }
+        }
+    }
+
+    let mut removed = HashSet::new();
+    let mut queue = VecDeque::new();
+    removed.insert(root_key.clone());
+    queue.push_back(root_key);
+
+    while let Some(current) = queue.pop_front() {
+        let Some(children) = edges.get(&current) else {
+            continue;
+        };
+        for child in children {
+            if removed.contains(child) {
+                continue;
+            }
+            if let Some(count) = incoming.get_mut(child) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    removed.insert(child.clone());
+                    queue.push_back(child.clone());
+                }
+            }
+        }
+    }
+
+    Ok(Some(removed))
+}
+
+fn load_unit_uses_readonly(
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    unit_path: &Path,
+    warnings: &mut Warnings,
+    assumptions: &Assumptions,
+) -> io::Result<Option<Vec<UnitName>>> {
+    let canonical = project_cache.canonical.canonicalize(unit_path);
+    if let Some(info) = project_cache.by_path.get(&canonical) {
+        return Ok(Some(flatten_unit_uses(info, assumptions)));
+    }
+    if let Some(delphi_cache) = delphi_cache {
+        if let Some(info) = delphi_cache.unit(&canonical) {
+            return Ok(Some(flatten_unit_uses(info, assumptions)));
+        }
+    }
+
+    Ok(project_cache
+        .load_unit(&canonical, warnings)?
+        .map(|info| flatten_unit_uses(&info, assumptions)))
+}
+
+fn collect_fix_root_paths(
+    dpr_path: &Path,
+    list: &UsesList,
+    project_map: &HashMap<String, PathBuf>,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    active_root_names: Option<&HashSet<String>>,
+    warnings: &mut Warnings,
+) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    let mut seen = HashSet::new();
+
+    for entry in &list.entries {
+        if !is_active_dpr_entry(active_root_names, entry) {
+            continue;
+        }
+        let key = entry.name.to_ascii_lowercase();
+        let Some(path) = project_map.get(&key) else {
+            continue;
+        };
+        let canonical = project_cache.canonical.canonicalize(path);
+        if !has_unit_path(project_cache, delphi_cache, &canonical) {
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::UnresolvedUnit,
+                    format!(
+                        "unit {} in {} resolved outside known unit caches and will be ignored",
+                        entry.name,
+                        dpr_path.display()
+                    ),
+                )
+                .at_offset(dpr_path, entry.offset()),
+            );
+            continue;
+        }
+        if seen.insert(canonical.clone()) {
+            roots.push(canonical);
+        }
+    }
+
+    roots
+}
+
+/// What [`collect_missing_dpr_dependencies`] found in the closure of a dpr.
+#[derive(Default)]
+struct MissingDependencies {
+    /// Units to insert, by uses depth and then name.
+    units: Vec<UnitFileInfo>,
+    /// The units leading from a dpr entry to the user of each unit in
+    /// `units`, keyed by its lowercase name.
+    chains: HashMap<String, Vec<String>>,
+    /// Delphi-cache units left out by [`DependencyLimits::skip_delphi_units`].
+    suppressed_delphi: usize,
+    /// Uses references that resolve to no unit, with the unit naming them.
+    unresolved: Vec<(String, PathBuf)>,
+}
+
+fn collect_missing_dpr_dependencies(
+    root_paths: &[PathBuf],
+    existing_names: &HashSet<String>,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    assumptions: &Assumptions,
+    warnings: &mut Warnings,
+    limits: &DependencyLimits,
+) -> io::Result<MissingDependencies> {
+    let mut queue = VecDeque::new();
+    let mut seen_paths = HashSet::new();
+    let mut missing_names = HashSet::new();
+    let mut found = MissingDependencies::default();
+    let mut units = Vec::new();
+    let mut reached_from: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    for path in root_paths {
+        if seen_paths.insert(path.clone()) {
+            queue.push_back((path.clone(), 0));
+        }
+    }
+
+    while let Some((unit_path, depth)) = queue.pop_front() {
+        if !limits.allows_depth(depth + 1) {
+            continue;
+        }
+        let uses = match load_unit_uses_readonly(
+            project_cache,
+            delphi_cache,
+            &unit_path,
+            warnings,
+            assumptions,
+        )? {
+            Some(value) => value,
+            None => continue,
+        };
+
+        for dep in uses {
+            let dep_key = dep.key().to_string();
+            let (dep_path, dep_source) = match resolve_by_name(project_cache, delphi_cache, &dep) {
+                ResolveByName::Unique { path, source } => (path, source),
+                ResolveByName::Ambiguous { count, source } => {
+                    warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::AmbiguousUnit,
+                            format!(
+                                "ambiguous unit {} referenced by {} ({} {} matches)",
+                                dep,
+                                unit_path.display(),
+                                count,
+                                source_label(source)
+                            ),
+                        )
+                        .at(&unit_path),
+                    );
+                    continue;
+                }
+                ResolveByName::NotFound => {
+                    found.unresolved.push((dep.to_string(), unit_path.clone()));
+                    continue;
+                }
+                ResolveByName::Known => continue,
+            };
+            let dep_path = project_cache.canonical.canonicalize(&dep_path);
+            if !has_unit_path(project_cache, delphi_cache, &dep_path) {
+                continue;
+            }
+            if seen_paths.insert(dep_path.clone()) {
+                queue.push_back((dep_path.clone(), depth + 1));
+                reached_from.insert(dep_path.clone(), unit_path.clone());
+            }
+
+            if existing_names.contains(&dep_key) || limits.is_excluded(&dep_key) {
+                continue;
+            }
+            if !missing_names.insert(dep_key.clone()) {
+                continue;
+            }
+            if limits.skip_delphi_units && dep_source == ResolutionSource::Delphi {
+                found.suppressed_delphi += 1;
+                continue;
+            }
+            if let Some(dep_info) = lookup_unit_info(project_cache, delphi_cache, &dep_path) {
+                units.push((depth + 1, dep_info.clone()));
+                let chain =
+                    introducing_chain(project_cache, delphi_cache, &reached_from, &unit_path);
+                found.chains.insert(dep_key, chain);
+            }
+        }
+    }
+
+    found.units = by_depth_then_name(units);
+    Ok(found)
+}
+
+/// The unit names from a dpr entry down to `unit_path`, following the unit
+/// each one was first reached from.
+fn introducing_chain(
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    reached_from: &HashMap<PathBuf, PathBuf>,
+    unit_path: &Path,
+) -> Vec<String> {
+    let mut chain = Vec::new();
+    let mut current = Some(unit_path);
+    while let Some(path) = current {
+        let name = lookup_unit_info(project_cache, delphi_cache, path)
+            .map(|info| info.name.clone())
+            .unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+        chain.push(name);
+        current = reached_from.get(path).map(PathBuf::as_path);
+    }
+    chain.reverse();
+    chain
+}
+
+/// The units of `found` ordered by their uses depth, then case-insensitively
+/// by name, so the order does not hinge on how uses clauses list them.
+fn by_depth_then_name(mut found: Vec<(usize, UnitFileInfo)>) -> Vec<UnitFileInfo> {
+    found.sort_by_cached_key(|(depth, unit)| (*depth, unit.name.to_ascii_lowercase()));
+    found.into_iter().map(|(_, unit)| unit).collect()
+}
+
+fn reload_dpr_state(
+    path: &Path,
+    writer: &DprWriter,
+    warnings: &mut Warnings,
+) -> io::Result<Result<(Vec<u8>, UsesList), Diagnostic>> {
+    let bytes = writer.read(path)?;
+    Ok(
+        match parse_dpr_uses(path, &bytes, &writer.includes, warnings) {
+            Some(list) => Ok((bytes, list)),
+            None => Err(uses_list_failure(path, &bytes)),
+        },
+    )
+}
+
+fn flatten_unit_uses(info: &UnitFileInfo, assumptions: &Assumptions) -> Vec<UnitName> {
+    conditionals::flatten_conditional_uses(
+        info.section_uses(assumptions.uses_sections()),
+        assumptions,
+    )
+}
+
+fn collect_active_dpr_entry_names(
+    dpr_path: &Path,
+    bytes: &[u8],
+    assumptions: &Assumptions,
+    includes: &Includes,
+    warnings: &mut Warnings,
+) -> Option<HashSet<String>> {
+    let entries = conditionals::parse_dpr_conditional_uses(dpr_path, bytes, includes, warnings)?;
+    Some(
+        entries
+            .into_iter()
+            .filter(|entry| evaluate_entry_condition(entry, assumptions))
+            .map(|entry| entry.unit_name.key().to_string())
+            .collect(),
+    )
+}
+
+fn evaluate_entry_condition(
+    entry: &conditionals::ConditionalUse,
+    assumptions: &Assumptions,
+) -> bool {
+    conditionals::evaluate_condition(&entry.condition, assumptions) != EvalResult::Never
+}
+
+fn is_active_dpr_entry(active_root_names: Option<&HashSet<String>>, entry: &UsesEntry) -> bool {
+    active_root_names
+        .map(|names| names.contains(&entry.name.to_ascii_lowercase()))
+        .unwrap_or(true)
+}
+
+fn has_unit_path(
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    path: &Path,
+) -> bool {
+    if project_cache.by_path.contains_key(path) {
+        return true;
+    }
+    if let Some(delphi_cache) = delphi_cache {
+        if delphi_cache.unit(path).is_some() {
+            return true;
+        }
+    }
+    false
+}
+
+fn lookup_unit_info<'a>(
+    project_cache: &'a UnitCache,
+    delphi_cache: Option<&'a LazyUnitCache>,
+    path: &Path,
+) -> Option<&'a UnitFileInfo> {
+    if let Some(unit) = project_cache.by_path.get(path) {
+        return Some(unit);
+    }
+    delphi_cache.and_then(|cache| cache.unit(path))
+}
+
+fn find_direct_introducer_index(
+    list: &UsesList,
+    project_map: &HashMap<String, PathBuf>,
+    dependents: &ProjectDependents,
+    active_root_names: Option<&HashSet<String>>,
+) -> Option<usize> {
+    list.entries.iter().enumerate().find_map(|(idx, entry)| {
+        if entry.from_include {
+            return None;
+        }
+        if !is_active_dpr_entry(active_root_names, entry) {
+            return None;
+        }
+        let key = entry.name.to_ascii_lowercase();
+        let path = project_map.get(&key)?;
+        dependents.uses_target_directly(path).then_some(idx)
+    })
+}
+
+struct ProjectDependents {
+    graph: UnitGraph,
+    dependents: Vec<bool>,
+}
+
+impl ProjectDependents {
+    fn uses_target_directly(&self, path: &Path) -> bool {
+        self.graph
+            .id(path)
+            .is_some_and(|id| self.graph.marked[id.0])
+    }
+
+    fn reaches_target(&self, path: &Path) -> bool {
+        self.graph.id(path).is_some_and(|id| self.dependents[id.0])
+    }
+}
+
+fn build_project_map(
+    dpr_path: &Path,
+    list: &UsesList,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    warnings: &mut Warnings,
+) -> HashMap<String, PathBuf> {
+    build_project_map_collecting(dpr_path, list, project_cache, delphi_cache, warnings, None)
+}
+
+/// Like [`build_project_map`], but with `missing` the indices of entries whose
+/// in-path names no file and whose unit resolves in no cache are collected
+/// there instead of being reported as broken paths.
+fn build_project_map_collecting(
+    dpr_path: &Path,
+    list: &UsesList,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    warnings: &mut Warnings,
+    mut missing: Option<&mut Vec<usize>>,
+) -> HashMap<String, PathBuf> {
+    let mut map = HashMap::new();
+
+    for (idx, entry) in list.entries.iter().enumerate() {
+        let Some(raw_path) = entry.in_path.as_ref() else {
+            match resolve_by_name(project_cache, delphi_cache, &UnitName::new(&entry.name)) {
+                ResolveByName::Known | ResolveByName::NotFound => {}
+                ResolveByName::Unique {
+                    path: fallback,
+                    source,
+                } => {
+                    if source == ResolutionSource::Project {
+                        warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::MissingInPath,
+                                format!(
+                                    "missing in-path for unit {} in {} (resolved via scan)",
+                                    entry.name,
+                                    dpr_path.display()
+                                ),
+                            )
+                            .at_offset(dpr_path, entry.offset()),
+                        );
+                    }
+                    insert_project_entry(&mut map, entry, fallback);
+                }
+                ResolveByName::Ambiguous { count, source } => {
+                    warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::MissingInPath,
+                            format!(
+                                "missing in-path for unit {} in {} ({} {} matches)",
+                                entry.name,
+                                dpr_path.display(),
+                                count,
+                                source_label(source)
+                            ),
+                        )
+                        .at_offset(dpr_path, entry.offset()),
+                    );
+                }
+            }
+            continue;
+        };
+
+        let resolved = resolve_dpr_unit_path(dpr_path, raw_path);
+        if !resolved.is_file() {
+            let by_name = resolve_by_name(project_cache, delphi_cache, &UnitName::new(&entry.name));
+            let drive_relative = is_drive_relative(raw_path);
+            if let Some(missing) = missing
+                .as_deref_mut()
+                .filter(|_| !drive_relative && matches!(by_name, ResolveByName::NotFound))
+            {
+                missing.push(idx);
+                continue;
+            }
+            let warning = if drive_relative {
+                Diagnostic::warning(
+                    DiagnosticCode::DriveRelativePath,
+                    format!(
+                        "drive-relative in-path for unit {} in {}: '{}' depends on the current folder of its drive; looked the unit up by name",
+                        entry.name,
+                        dpr_path.display(),
+                        raw_path.trim()
+                    ),
+                )
+            } else {
+                Diagnostic::warning(
+                    DiagnosticCode::BrokenUsesPath,
+                    format!(
+                        "dpr uses path not found for unit {} in {}: {}",
+                        entry.name,
+                        dpr_path.display(),
+                        resolved.display()
+                    ),
+                )
+            };
+            warnings.push(warning.at_offset(dpr_path, entry.offset()));
+            match by_name {
+                ResolveByName::Unique { path: fallback, .. } => {
+                    insert_project_entry(&mut map, entry, fallback);
+                }
+                ResolveByName::Ambiguous { count, source } => {
+                    warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::AmbiguousUnit,
+                            format!(
+                                "unit {} referenced in {} is ambiguous ({} {} matches)",
+                                entry.name,
+                                dpr_path.display(),
+                                count,
+                                source_label(source)
+                            ),
+                        )
+                        .at_offset(dpr_path, entry.offset()),
+                    );
+                }
+                ResolveByName::Known | ResolveByName::NotFound => {}
+            }
+            continue;
+        }
+
+        if let Some(declared) =
+            declared_unit_mismatch(project_cache, delphi_cache, entry, &resolved)
+        {
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::MismatchedUnitPath,
+                    format!(
+                        "unit {} in {} points at {}, which declares unit {}",
+                        entry.name,
+                        dpr_path.display(),
+                        resolved.display(),
+                        declared
+                    ),
+                )
+                .at_offset(dpr_path, entry.offset()),
+            );
+        }
+        insert_project_entry(&mut map, entry, resolved);
+    }
+
+    report_duplicate_entries(dpr_path, list, &map, warnings);
+    map
+}
+
+/// Warns once per unit listed with different in-paths, naming the file that is used.
+fn report_duplicate_entries(
+    dpr_path: &Path,
+    list: &UsesList,
+    map: &HashMap<String, PathBuf>,
+    warnings: &mut Warnings,
+) {
+    for group in duplicate_entry_groups(list) {
+        let first = &list.entries[group[0]];
+        if group
+            .iter()
+            .all(|&idx| same_in_path(&list.entries[idx], first))
+        {
+            continue;
+        }
+        let paths: Vec<String> = group
+            .iter()
+            .map(|&idx| describe_in_path(&list.entries[idx]))
+            .collect();
+        let used = map
+            .get(&first.name.to_ascii_lowercase())
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "none of them".to_string());
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::DuplicateUnit,
+                format!(
+                    "duplicate unit name {} in {} with paths {}; using {}",
+                    first.name,
+                    dpr_path.display(),
+                    paths.join(" and "),
+                    used
+                ),
+            )
+            .at_offset(dpr_path, list.entries[group[1]].offset()),
+        );
+    }
+}
+
+/// Indices of the entries of each unit listed more than once, in list order.
+fn duplicate_entry_groups(list: &UsesList) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_by_name: HashMap<String, usize> = HashMap::new();
+    for (idx, entry) in list.entries.iter().enumerate() {
+        let key = entry.name.to_ascii_lowercase();
+        match group_by_name.get(&key) {
+            Some(&group) => groups[group].push(idx),
+            None => {
+                group_by_name.insert(key, groups.len());
+                groups.push(vec![idx]);
+            }
+        }
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+fn same_in_path(first: &UsesEntry, second: &UsesEntry) -> bool {
+    let normalize = |path: &String| path.replace('\\', "/").to_ascii_lowercase();
+    first.in_path.as_ref().map(normalize) == second.in_path.as_ref().map(normalize)
+}
+
+fn describe_in_path(entry: &UsesEntry) -> String {
+    match &entry.in_path {
+        Some(path) => format!("'{path}'"),
+        None => "no in-path".to_string(),
+    }
+}
+
+/// Lowercase names of the dpr entries listed under a conditional.
+fn conditional_entry_names(dpr_path: &Path, bytes: &[u8], includes: &Includes) -> HashSet<String> {
+    conditionals::parse_dpr_conditional_uses(dpr_path, bytes, includes, &mut Warnings::new())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|entry| entry.condition != conditionals::CondExpr::True)
+        .map(|entry| entry.unit_name.key().to_string())
+        .collect()
+}
+
+/// Removes extra entries of units listed more than once, keeping one per `policy`.
+///
+/// Units listed under a conditional or inside an include fragment are left alone.
+#[allow(clippy::too_many_arguments)]
+fn remove_duplicate_entries(
+    dpr_path: &Path,
+    bytes: &[u8],
+    list: &UsesList,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    policy: DuplicatePolicy,
+    writer: &mut DprWriter,
+    warnings: &mut Warnings,
+) -> io::Result<bool> {
+    let conditional_names = conditional_entry_names(dpr_path, bytes, &writer.includes);
+    let target = |entry: &UsesEntry| match &entry.in_path {
+        Some(raw_path) => {
+            Some(resolve_dpr_unit_path(dpr_path, raw_path)).filter(|path| path.is_file())
+        }
+        None => match resolve_by_name(project_cache, delphi_cache, &UnitName::new(&entry.name)) {
+            ResolveByName::Unique { path, .. } => Some(project_cache.canonical.canonicalize(&path)),
+            _ => None,
+        },
+    };
+
+    let mut removed = HashSet::new();
+    let mut notes = Vec::new();
+    for group in duplicate_entry_groups(list) {
+        let first = &list.entries[group[0]];
+        if conditional_names.contains(&first.name.to_ascii_lowercase())
+            || group.iter().any(|&idx| list.entries[idx].from_include)
+        {
+            continue;
+        }
+        let keep = match policy {
+            DuplicatePolicy::KeepFirst | DuplicatePolicy::RemoveExtra => group[0],
+            DuplicatePolicy::KeepResolving => group
+                .iter()
+                .copied()
+                .find(|&idx| target(&list.entries[idx]).is_some())
+                .unwrap_or(group[0]),
+        };
+        let kept = &list.entries[keep];
+        let kept_target = target(kept);
+        for &idx in &group {
+            let entry = &list.entries[idx];
+            if idx == keep
+                || (policy == DuplicatePolicy::RemoveExtra
+                    && (kept_target.is_none() || target(entry) != kept_target))
+            {
+                continue;
+            }
+            removed.insert(idx);
+            notes.push(format!(
+                "removed duplicate unit {} with {} from {}; kept {}",
+                entry.name,
+                describe_in_path(entry),
+                dpr_path.display(),
+                describe_in_path(kept)
+            ));
+        }
+    }
+    if removed.is_empty() {
+        return Ok(false);
+    }
+
+    let Some(ranges) = entry_removal_ranges(bytes, list, &removed) else {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::DuplicateUnit,
+                format!(
+                    "duplicate units in {} sit next to comments or directives and were not removed",
+                    dpr_path.display()
+                ),
+            )
+            .at(dpr_path),
+        );
+        return Ok(false);
+    };
+    // The removed entries no longer have a position in the rewritten file.
+    for message in notes {
+        warnings.push(Diagnostic::warning(DiagnosticCode::DuplicateUnit, message).at(dpr_path));
+    }
+    let mut output = bytes.to_vec();
+    for (start, end) in ranges.into_iter().rev() {
+        output.drain(start..end);
+    }
+    writer.write(dpr_path, output)?;
+    Ok(true)
+}
+
+/// Byte ranges, in order, that drop the `removed` entries and their separators.
+///
+/// A comma left dangling before the semicolon is dropped as well, so the
+/// remaining entries end the list without one.
+///
+/// `None` when an entry cannot be cut out without touching a comment or directive,
+/// or when no entry would be left.
+fn entry_removal_ranges(
+    bytes: &[u8],
+    list: &UsesList,
+    removed: &HashSet<usize>,
+) -> Option<Vec<(usize, usize)>> {
+    let has_comment = |slice: &[u8]| {
+        slice.contains(&b'{') || slice.windows(2).any(|pair| pair == b"(*" || pair == b"//")
+    };
+    let mut tail = list.entries.len();
+    while tail > 0 && removed.contains(&(tail - 1)) {
+        tail -= 1;
+    }
+    if tail == 0 {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for idx in 0..tail {
+        if !removed.contains(&idx) {
+            continue;
+        }
+        let entry = &list.entries[idx];
+        let mut start = entry.start;
+        let mut end = entry.delimiter_pos? + 1;
+        if has_comment(&bytes[start..end]) {
+            return None;
+        }
+        while end < bytes.len() && matches!(bytes[end], b' ' | b'\t') {
+            end += 1;
+        }
+        let line_start = bytes[..start]
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |pos| pos + 1);
+        let own_line = bytes[line_start..start]
+            .iter()
+            .all(|&byte| matches!(byte, b' ' | b'\t'));
+        if own_line && matches!(bytes.get(end), Some(b'\r' | b'\n')) {
+            start = line_start;
+            end += if bytes[end] == b'\r' { 2 } else { 1 };
+            end = end.min(bytes.len());
+        }
+        ranges.push((start, end));
+    }
+    let last_kept = &list.entries[tail - 1];
+    let dangling_comma = list.entries.last().and_then(|last| {
+        last.delimiter_pos
+            .filter(|_| last.delimiter == Some(b',') && !last.from_include)
+    });
+    if tail < list.entries.len() {
+        if last_kept.from_include {
+            return None;
+        }
+        let comma = last_kept.delimiter_pos?;
+        // A dangling comma after the last entry goes too; what follows it stays.
+        let end = dangling_comma.map_or(list.semicolon, |pos| pos + 1);
+        if has_comment(&bytes[comma..end]) {
+            return None;
+        }
+        ranges.push((comma, end));
+    } else if let Some(comma) = dangling_comma {
+        ranges.push((comma, comma + 1));
+    }
+    Some(ranges)
+}
+
+/// The unit name declared by `path` when it differs from the entry name beyond case.
+fn declared_unit_mismatch<'a>(
+    project_cache: &'a UnitCache,
+    delphi_cache: Option<&'a LazyUnitCache>,
+    entry: &UsesEntry,
+    path: &Path,
+) -> Option<&'a str> {
+    let info = lookup_unit_info(project_cache, delphi_cache, path)?;
+    (!info.name.eq_ignore_ascii_case(&entry.name)).then_some(info.name.as_str())
+}
+
+/// Rewrites in-paths whose file declares another unit to the entry's unique unit file.
+///
+/// Entries whose own unit is missing or ambiguous are left for [`build_project_map`] to report.
+fn rewrite_mismatched_paths(
+    dpr_path: &Path,
+    bytes: &[u8],
+    list: &UsesList,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    writer: &mut DprWriter,
+    warnings: &mut Warnings,
+) -> io::Result<bool> {
+    let separator = writer.separator(Some(list));
+    let mut edits = Vec::new();
+    // Warnings point into the rewritten file, where earlier literals may have changed length.
+    let mut shift = 0isize;
+    for entry in &list.entries {
+        let (Some(raw_path), Some(span)) = (entry.in_path.as_ref(), entry.in_path_span) else {
+            continue;
+        };
+        let resolved = resolve_dpr_unit_path(dpr_path, raw_path);
+        if !resolved.is_file() {
+            continue;
+        }
+        let Some(declared) = declared_unit_mismatch(project_cache, delphi_cache, entry, &resolved)
+        else {
+            continue;
+        };
+        let ResolveByName::Unique { path, .. } =
+            resolve_by_name(project_cache, delphi_cache, &UnitName::new(&entry.name))
+        else {
+            continue;
+        };
+        let path = project_cache.canonical.canonicalize(&path);
+        let Some(unit) = lookup_unit_info(project_cache, delphi_cache, &path) else {
+            continue;
+        };
+        if !writer.allows_unit(dpr_path, unit, warnings)? {
+            continue;
+        }
+        let new_path = writer.unit_path_text(dpr_path, &path, separator);
+        let literal = format!("'{new_path}'");
+        let offset = entry
+            .offset()
+            .map(|offset| offset.saturating_add_signed(shift));
+        shift += literal.len() as isize - (span.1 - span.0) as isize;
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::MismatchedUnitPath,
+                format!(
+                    "unit {} in {} points at {}, which declares unit {}; rewrote the path to '{}'",
+                    entry.name,
+                    dpr_path.display(),
+                    resolved.display(),
+                    declared,
+                    new_path
+                ),
+            )
+            .at_offset(dpr_path, offset),
+        );
+        edits.push((span, literal));
+    }
+    if edits.is_empty() {
+        return Ok(false);
+    }
+
+    let mut output = bytes.to_vec();
+    for ((start, end), literal) in edits.into_iter().rev() {
+        output.splice(start..end, literal.into_bytes());
+    }
+    writer.write(dpr_path, output)?;
+    Ok(true)
+}
+
+/// Warns about entries naming the new unit whose in-path points at another
+/// file, and with `retarget` points them at the new unit's file instead.
+fn retarget_new_unit_entries(
+    dpr_path: &Path,
+    bytes: &[u8],
+    list: &UsesList,
+    new_unit: &UnitFileInfo,
+    retarget: bool,
+    writer: &mut DprWriter,
+    warnings: &mut Warnings,
+) -> io::Result<bool> {
+    let new_path = writer.canonical.canonicalize(&new_unit.path);
+    let separator = writer.separator(Some(list));
+    let mut edits = Vec::new();
+    let mut shift = 0isize;
+    for entry in &list.entries {
+        let Some(raw_path) = entry.in_path.as_ref() else {
+            continue;
+        };
+        if !entry.name.eq_ignore_ascii_case(&new_unit.name) {
+            continue;
+        }
+        let resolved = writer
+            .canonical
+            .canonicalize(&resolve_dpr_unit_path(dpr_path, raw_path));
+        if resolved == new_path {
+            continue;
+        }
+        let listed = format!(
+            "{} lists {} in '{}' ({}), not {}",
+            dpr_path.display(),
+            entry.name,
+            raw_path,
+            unit_cache::strip_verbatim_prefix(&resolved).display(),
+            unit_cache::strip_verbatim_prefix(&new_path).display()
+        );
+        let span = entry.in_path_span.filter(|_| retarget);
+        let Some(span) = span else {
+            let reason = if retarget {
+                "the entry comes from an include file and was left unchanged"
+            } else {
+                "left unchanged (use --retarget-existing to point it at the new file)"
+            };
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::ConflictingUnitPath,
+                    format!("{listed}; {reason}"),
+                )
+                .at_offset(dpr_path, entry.offset()),
+            );
+            continue;
+        };
+        if !writer.allows_unit(dpr_path, new_unit, warnings)? {
+            continue;
+        }
+        let written = writer.unit_path_text(dpr_path, &new_path, separator);
+        let literal = format!("'{written}'");
+        let offset = entry
+            .offset()
+            .map(|offset| offset.saturating_add_signed(shift));
+        shift += literal.len() as isize - (span.1 - span.0) as isize;
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::ConflictingUnitPath,
+                format!("{listed}; rewrote the path to '{written}'"),
+            )
+            .at_offset(dpr_path, offset),
+        );
+        edits.push((span, literal));
+    }
+    if edits.is_empty() {
+        return Ok(false);
+    }
+
+    let mut output = bytes.to_vec();
+    for ((start, end), literal) in edits.into_iter().rev() {
+        output.splice(start..end, literal.into_bytes());
+    }
+    writer.write(dpr_path, output)?;
+    Ok(true)
+}
+
+fn insert_project_entry(map: &mut HashMap<String, PathBuf>, entry: &UsesEntry, resolved: PathBuf) {
+    map.entry(entry.name.to_ascii_lowercase())
+        .or_insert(resolved);
+}
+
+/// Which unit cache a unit name resolved in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResolutionSource {
+    Project,
+    Delphi,
+}
+
+impl ResolutionSource {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Project => "project",
+            Self::Delphi => "delphi",
+        }
+    }
+}
+
+/// The cache holding the unit at `path`; units neither cache knows count as project units.
+fn resolution_source(
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    path: &Path,
+) -> ResolutionSource {
+    if !project_cache.by_path.contains_key(path)
+        && delphi_cache.is_some_and(|cache| cache.unit(path).is_some())
+    {
+        ResolutionSource::Delphi
+    } else {
+        ResolutionSource::Project
+    }
+}
+
+enum ResolveByName {
+    NotFound,
+    /// Listed in the known units; resolves without a path.
+    Known,
+    Unique {
+        path: PathBuf,
+        source: ResolutionSource,
+    },
+    Ambiguous {
+        count: usize,
+        source: ResolutionSource,
+    },
+}
+
+fn resolve_by_name(
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    unit_name: &UnitName,
+) -> ResolveByName {
+    if project_cache.known_units.contains_name(unit_name) {
+        return ResolveByName::Known;
+    }
+    if let Some(paths) = project_cache.by_name.get(unit_name.key()) {
+        if paths.len() > 1 {
+            return ResolveByName::Ambiguous {
+                count: paths.len(),
+                source: ResolutionSource::Project,
+            };
+        }
+        return ResolveByName::Unique {
+            path: paths[0].clone(),
+            source: ResolutionSource::Project,
+        };
+    }
+
+    if let Some(delphi_cache) = delphi_cache {
+        let paths = delphi_cache.lookup_name(unit_name.as_str());
+        if !paths.is_empty() {
+            if paths.len() > 1 {
+                return ResolveByName::Ambiguous {
+                    count: paths.len(),
+                    source: ResolutionSource::Delphi,
+                };
+            }
+            return ResolveByName::Unique {
+                path: paths[0].clone(),
+                source: ResolutionSource::Delphi,
+            };
+        }
+    }
+
+    ResolveByName::NotFound
+}
+
+fn source_label(source: ResolutionSource) -> &'static str {
+    match source {
+        ResolutionSource::Project => "project",
+        ResolutionSource::Delphi => "--delphi-path",
+    }
+}
+
+fn compute_project_dependents(
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    project_map: &HashMap<String, PathBuf>,
+    target_names: &[&str],
+    assumptions: &Assumptions,
+    warnings: &mut Warnings,
+) -> io::Result<ProjectDependents> {
+    let graph =
+        graph::build_unit_graph::<io::Error, _>(project_map.values().cloned(), |unit_path| {
+            let Some(uses) = load_unit_uses(
+                project_cache,
+                delphi_cache,
+                unit_path,
+                warnings,
+                assumptions,
+            )?
+            else {
+                warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!("failed to read unit at {}", unit_path.display()),
+                    )
+                    .at(unit_path),
+                );
+                return Ok(None);
+            };
+            let mut edges = UnitEdges::default();
+            for dep in uses {
+                if target_names
+                    .iter()
+                    .any(|target| dep.as_str().eq_ignore_ascii_case(target))
+                {
+                    edges.marked = true;
+                    continue;
+                }
+                if let Some(dep_path) = resolve_dep_path(
+                    project_map,
+                    project_cache,
+                    delphi_cache,
+                    &dep,
+                    unit_path,
+                    warnings,
+                ) {
+                    edges.deps.push(dep_path);
+                }
+            }
+            Ok(Some(edges))
+        })?;
+    let dependents = graph::compute_dependents(&graph, &graph.marked_ids());
+    Ok(ProjectDependents { graph, dependents })
+}
+
+fn resolve_dep_path(
+    project_map: &HashMap<String, PathBuf>,
+    project_cache: &UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    dep_name: &UnitName,
+    source_path: &Path,
+    warnings: &mut Warnings,
+) -> Option<PathBuf> {
+    if let Some(path) = project_map.get(dep_name.key()) {
+        return Some(path.clone());
+    }
+    match resolve_by_name(project_cache, delphi_cache, dep_name) {
+        ResolveByName::Unique { path, .. } => Some(path),
+        ResolveByName::Ambiguous { count, source } => {
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::AmbiguousUnit,
+                    format!(
+                        "ambiguous unit {} referenced by {} ({} {} matches)",
+                        dep_name,
+                        source_path.display(),
+                        count,
+                        source_label(source)
+                    ),
+                )
+                .at(source_path),
+            );
+            None
+        }
+        ResolveByName::Known | ResolveByName::NotFound => None,
+    }
+}
+
+fn load_unit_uses(
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    unit_path: &Path,
+    warnings: &mut Warnings,
+    assumptions: &Assumptions,
+) -> io::Result<Option<Vec<UnitName>>> {
+    let canonical = project_cache.canonical.canonicalize(unit_path);
+    if let Some(info) = project_cache.by_path.get(&canonical) {
+        return Ok(Some(flatten_unit_uses(info, assumptions)));
+    }
+
+    if let Some(delphi_cache) = delphi_cache {
+        if let Some(info) = delphi_cache.unit(&canonical) {
+            return Ok(Some(flatten_unit_uses(info, assumptions)));
+        }
+    }
+
+    Ok(project_cache
+        .load_unit(&canonical, warnings)?
+        .map(|info| flatten_unit_uses(&info, assumptions)))
+}
+
+fn collect_introduced_dependencies(
+    project_cache: &mut UnitCache,
+    delphi_cache: Option<&LazyUnitCache>,
+    project_map: &HashMap<String, PathBuf>,
+    new_unit: &UnitFileInfo,
+    assumptions: &Assumptions,
+    warnings: &mut Warnings,
+    limits: &DependencyLimits,
+) -> io::Result<Vec<UnitFileInfo>> {
+    let mut queue = VecDeque::new();
+    let mut seen_paths = HashSet::new();
+    let mut seen_names = HashSet::new();
+    let mut introduced = Vec::new();
+    let mut uses_of: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+
+    let root_path = project_cache.canonical.canonicalize(&new_unit.path);
+    seen_paths.insert(root_path.clone());
+    queue.push_back((root_path.clone(), 0));
+
+    while let Some((unit_path, depth)) = queue.pop_front() {
+        if !limits.allows_depth(depth + 1) {
+            continue;
+        }
+        let uses = match load_unit_uses(
+            project_cache,
+            delphi_cache,
+            &unit_path,
+            warnings,
+            assumptions,
+        )? {
+            Some(uses) => uses,
+            None => {
+                warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!("failed to read unit at {}", unit_path.display()),
+                    )
+                    .at(&unit_path),
+                );
+                continue;
+            }
+        };
+
+        for dep in uses {
+            if dep.as_str().eq_ignore_ascii_case(&new_unit.name) {
+                continue;
+            }
+            let dep_path = resolve_dep_path(
+                project_map,
+                project_cache,
+                delphi_cache,
+                &dep,
+                unit_path.as_path(),
+                warnings,
+            );
+            let Some(dep_path) = dep_path else {
+                continue;
+            };
+            let dep_path = project_cache.canonical.canonicalize(&dep_path);
+            if dep_path == root_path {
+                continue;
+            }
+            if limits.introduced_scope == IntroducedScope::ProjectOnly
+                && resolution_source(project_cache, delphi_cache, &dep_path)
+                    == ResolutionSource::Delphi
+            {
+                continue;
+            }
+            if limits.topo_order {
+                uses_of
+                    .entry(unit_path.clone())
+                    .or_default()
+                    .push(dep_path.clone());
+            }
+            if seen_paths.insert(dep_path.clone()) {
+                queue.push_back((dep_path.clone(), depth + 1));
+            }
+
+            let dep_key = dep.key().to_string();
+            if limits.is_excluded(&dep_key) || !seen_names.insert(dep_key) {
+                continue;
+            }
+            let name_from_stem = lookup_unit_info(project_cache, delphi_cache, &dep_path)
+                .is_some_and(|info| info.name_from_stem);
+            introduced.push((
+                depth + 1,
+                UnitFileInfo {
+                    name: dep.to_string(),
+                    path: dep_path,
+                    uses: Vec::new(),
+                    conditional_uses: Vec::new(),
+                    interface_uses: 0,
+                    name_from_stem,
+                },
+            ));
+        }
+    }
+
+    let mut introduced = by_depth_then_name(introduced);
+    if limits.topo_order {
+        introduced = topo_sort_introduced(introduced, &uses_of, warnings);
+    }
+    Ok(introduced)
+}
+
+/// `introduced` with every unit after the introduced units it uses, picking
+/// the lowest name among the units that are ready. Units that use each other
+/// keep the incoming order of the whole set, with a warning naming them.
+fn topo_sort_introduced(
+    introduced: Vec<UnitFileInfo>,
+    uses_of: &HashMap<PathBuf, Vec<PathBuf>>,
+    warnings: &mut Warnings,
+) -> Vec<UnitFileInfo> {
+    let index_of: HashMap<&Path, usize> = introduced
+        .iter()
+        .enumerate()
+        .map(|(idx, unit)| (unit.path.as_path(), idx))
+        .collect();
+    let mut pending = vec![0usize; introduced.len()];
+    let mut dependents = vec![Vec::new(); introduced.len()];
+    for (idx, unit) in introduced.iter().enumerate() {
+        let mut deps: Vec<usize> = uses_of
+            .get(&unit.path)
+            .into_iter()
+            .flatten()
+            .filter_map(|dep| index_of.get(dep.as_path()).copied())
+            .filter(|&dep| dep != idx)
+            .collect();
+        deps.sort_unstable();
+        deps.dedup();
+        pending[idx] = deps.len();
+        for dep in deps {
+            dependents[dep].push(idx);
+        }
+    }
+
+    let sort_key = |idx: usize| (introduced[idx].name.to_ascii_lowercase(), idx);
+    let mut ready: BTreeSet<(String, usize)> = (0..introduced.len())
+        .filter(|&idx| pending[idx] == 0)
+        .map(sort_key)
+        .collect();
+    let mut order = Vec::with_capacity(introduced.len());
+    while let Some((_, idx)) = ready.pop_first() {
+        order.push(idx);
+        for &dependent in &dependents[idx] {
+            pending[dependent] -= 1;
+            if pending[dependent] == 0 {
+                ready.insert(sort_key(dependent));
+            }
+        }
+    }
+
+    if order.len() < introduced.len() {
+        let cycle: Vec<&str> = introduced
+            .iter()
+            .zip(&pending)
+            .filter(|(_, &count)| count > 0)
+            .map(|(unit, _)| unit.name.as_str())
+            .collect();
+        let first = introduced
+            .iter()
+            .zip(&pending)
+            .find(|(_, &count)| count > 0)
+            .map(|(unit, _)| unit.path.clone());
+        let mut diagnostic = Diagnostic::warning(
+            DiagnosticCode::DependencyCycle,
+            format!(
+                "introduced units {} use each other; kept their depth and name order",
+                cycle.join(", ")
+            ),
+        );
+        if let Some(path) = first {
+            diagnostic = diagnostic.at(&path);
+        }
+        warnings.push(diagnostic);
+        return introduced;
+    }
+
+    let mut slots: Vec<Option<UnitFileInfo>> = introduced.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .filter_map(|idx| slots[idx].take())
+        .collect()
+}
+
+fn resolve_dpr_unit_path(dpr_path: &Path, raw: &str) -> PathBuf {
+    let raw = normalize_in_path(raw);
+    let candidate = PathBuf::from(&raw);
+    // `C:Foo.pas` is relative to the current folder of drive C:, which fixdpr cannot know.
+    if is_drive_relative(&raw) {
+        return candidate;
+    }
+    let resolved = if candidate.is_absolute() {
+        candidate
+    } else {
+        dpr_path
+            .parent()
+            .map(|parent| parent.join(&candidate))
+            .unwrap_or(candidate)
+    };
+    if !cfg!(windows) && !resolved.exists() {
+        let base = dpr_path.parent().unwrap_or(Path::new("."));
+        if let Some(found) = fs_walk::find_path_ignoring_case(base, &raw) {
+            return unit_cache::canonicalize_if_exists(&found);
+        }
+    }
+    unit_cache::canonicalize_if_exists(&resolved)
+}
+
+/// Trims an in-path literal and converts its separators to the host's.
+fn normalize_in_path(raw: &str) -> String {
+    raw.trim()
+        .replace(['\\', '/'], std::path::MAIN_SEPARATOR_STR)
+}
+
+/// Whether `raw` names a drive without a root folder, like `C:Common\Foo.pas`.
+fn is_drive_relative(raw: &str) -> bool {
+    let bytes = raw.trim().as_bytes();
+    bytes.len() >= 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && !matches!(bytes.get(2), Some(b'\\' | b'/'))
+}
+
+/// `dpr_paths` sorted by canonical path, so edits, warnings, and details come
+/// out in the same order however the caller found the files.
+fn in_processing_order(dpr_paths: &[PathBuf]) -> Vec<&PathBuf> {
+    let mut ordered: Vec<&PathBuf> = dpr_paths.iter().collect();
+    ordered.sort_by_cached_key(|path| unit_cache::canonicalize_if_exists(path));
+    ordered
+}
+
+fn find_listed_unit_index(list: &UsesList, name: &str) -> Option<usize> {
+    list.entries
+        .iter()
+        .position(|entry| !entry.from_include && entry.name.eq_ignore_ascii_case(name))
+}
+
+fn insert_new_unit(
+    bytes: &[u8],
+    dpr_path: &Path,
+    list: &UsesList,
+    new_unit: &UnitFileInfo,
+    insert_after: Option<usize>,
+    writer: &mut DprWriter,
+    warnings: &mut Warnings,
+) -> io::Result<bool> {
+    if let Some(entry) = writer.scoped_variant(list, &new_unit.name) {
+        writer.infos.push(format!(
+            "did not insert {} into {} because it lists {}",
+            new_unit.name,
+            dpr_path.display(),
+            entry.name
+        ));
+        return Ok(false);
+    }
+    if !writer.allows_unit(dpr_path, new_unit, warnings)? {
+        return Ok(false);
+    }
+    let separator = writer.separator(Some(list));
+    let entry_text = format_unit_entry(dpr_path, new_unit, writer, separator);
+    let insert_after = writer.unconditional_anchor(dpr_path, list, insert_after, &new_unit.name);
+    let output = insert_entry_text(
+        bytes,
+        list,
+        &entry_text,
+        insert_after,
+        writer.indent.as_deref(),
+    );
+    let output = writer.wrap_long_list(dpr_path, output);
+    writer.write(dpr_path, output)?;
+    Ok(true)
+}
+
+/// `bytes` with `entry_text` added after entry `insert_after`, or at the end of the list.
+fn insert_entry_text(
+    bytes: &[u8],
+    list: &UsesList,
+    entry_text: &str,
+    insert_after: Option<usize>,
+    indent: Option<&str>,
+) -> Vec<u8> {
+    if let Some(idx) = insert_after {
+        if let Some((insert_at, insert_bytes)) =
+            build_insertion_after(bytes, list, idx, entry_text.as_bytes(), indent)
+        {
+            let mut output = Vec::with_capacity(bytes.len() + insert_bytes.len());
+            output.extend_from_slice(&bytes[..insert_at]);
+            output.extend_from_slice(&insert_bytes);
+            output.extend_from_slice(&bytes[insert_at..]);
+            return output;
+        }
+    }
+
+    let line_ending = detect_line_ending(bytes);
+    let last_delim = list.entries.last().and_then(|entry| entry.delimiter);
+    let insertion = if list.multiline {
+        let prefix = if matches!(last_delim, Some(b',')) {
+            ""
+        } else {
+            ","
+        };
+        let indent = indent.unwrap_or(&list.indent);
+        format!("{prefix}{line_ending}{indent}{entry_text}")
+    } else {
+        let prefix = match (last_delim, list.tight_commas) {
+            (Some(b','), true) => "",
+            (Some(b','), false) => " ",
+            (_, true) => ",",
+            (_, false) => ", ",
+        };
+        format!("{prefix}{entry_text}")
+    };
+
+    let insert_at = if list.multiline && !matches!(last_delim, Some(b',')) {
+        let mut pos = list.semicolon;
+        while pos > 0 && bytes[pos - 1].is_ascii_whitespace() {
+            pos -= 1;
+        }
+        pos
+    } else {
+        list.semicolon
+    };
+
+    let insert_bytes = insertion.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len() + insert_bytes.len());
+    output.extend_from_slice(&bytes[..insert_at]);
+    output.extend_from_slice(insert_bytes);
+    output.extend_from_slice(&bytes[insert_at..]);
+    output
+}
+
+/// Number of characters on the line holding `offset`.
+fn line_width_at(bytes: &[u8], offset: usize) -> usize {
+    let start = bytes[..offset]
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let end = bytes[offset..]
+        .iter()
+        .position(|byte| *byte == b'\n' || *byte == b'\r')
+        .map_or(bytes.len(), |pos| offset + pos);
+    String::from_utf8_lossy(&bytes[start..end]).chars().count()
+}
+
+/// `bytes` with the single-line `list` rewritten to one `indent`ed entry per
+/// line. `None` when comments, directives, or includes sit between the
+/// entries, since moving them could change what they apply to.
+fn wrap_uses_list(bytes: &[u8], list: &UsesList, indent: &str) -> Option<Vec<u8>> {
+    let first = list.entries.first()?;
+    if !bytes[list.start..first.start]
+        .iter()
+        .all(|byte| byte.is_ascii_whitespace())
+    {
+        return None;
+    }
+    let mut texts = Vec::with_capacity(list.entries.len());
+    for (idx, entry) in list.entries.iter().enumerate() {
+        if entry.from_include || entry.cond_depth > 0 {
+            return None;
+        }
+        let end = entry.delimiter_pos?;
+        let next = list
+            .entries
+            .get(idx + 1)
+            .map_or(list.semicolon, |next| next.start);
+        let between = &bytes[end + 1..next.max(end + 1)];
+        if !between.iter().all(|byte| byte.is_ascii_whitespace()) {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&bytes[entry.start..end]);
+        texts.push(text.trim_end().to_string());
+    }
+
+    let line_ending = detect_line_ending(bytes);
+    let mut wrapped = String::new();
+    for (idx, text) in texts.iter().enumerate() {
+        wrapped.push_str(line_ending);
+        wrapped.push_str(indent);
+        wrapped.push_str(text);
+        wrapped.push(if idx + 1 == texts.len() { ';' } else { ',' });
+    }
+    let mut output = Vec::with_capacity(bytes.len() + wrapped.len());
+    output.extend_from_slice(&bytes[..list.start]);
+    output.extend_from_slice(wrapped.as_bytes());
+    output.extend_from_slice(&bytes[list.semicolon + 1..]);
+    Some(output)
+}
+
+fn create_uses_section(
+    bytes: &[u8],
+    dpr_path: &Path,
+    units: &[UnitFileInfo],
+    writer: &mut DprWriter,
+    warnings: &mut Warnings,
+) -> io::Result<bool> {
+    if units.is_empty() {
+        return Ok(false);
+    }
+    for unit in units {
+        if !writer.allows_unit(dpr_path, unit, warnings)? {
+            return Ok(false);
+        }
+    }
+
+    let output = insert_uses_section(bytes, dpr_path, units, writer)?;
+    writer.write(dpr_path, output)?;
+    Ok(true)
+}
+
+/// `bytes` with a multiline uses clause listing `units` after the program header.
+fn insert_uses_section(
+    bytes: &[u8],
+    dpr_path: &Path,
+    units: &[UnitFileInfo],
+    writer: &DprWriter,
+) -> io::Result<Vec<u8>> {
+    let header_semicolon = find_dpr_header_semicolon(bytes).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unable to locate program/library header in {}",
+                dpr_path.display()
+            ),
+        )
+    })?;
+    let line_ending = detect_line_ending(bytes);
+    let block = format!(
+        "{line_ending}{}{line_ending}",
+        format_uses_clause(dpr_path, units, writer, writer.separator(None), line_ending)
+    );
+
+    let suffix = &bytes[header_semicolon + 1..];
+    let (suffix, removed_line_ending) = strip_one_leading_line_ending(suffix);
+    let mut output = Vec::with_capacity(bytes.len() + block.len() + line_ending.len());
+    output.extend_from_slice(&bytes[..header_semicolon + 1]);
+    output.extend_from_slice(block.as_bytes());
+    if !suffix.is_empty() && !removed_line_ending {
+        output.extend_from_slice(line_ending.as_bytes());
+    }
+    output.extend_from_slice(suffix);
+    Ok(output)
+}
+
+/// `uses` followed by one indented entry per line, ending at the semicolon.
+fn format_uses_clause(
+    dpr_path: &Path,
+    units: &[UnitFileInfo],
+    writer: &DprWriter,
+    separator: char,
+    line_ending: &str,
+) -> String {
+    let mut clause = String::from("uses");
+    for (idx, unit) in units.iter().enumerate() {
+        clause.push_str(line_ending);
+        clause.push_str(writer.indent.as_deref().unwrap_or("  "));
+        clause.push_str(&format_unit_entry(dpr_path, unit, writer, separator));
+        clause.push(if idx + 1 == units.len() { ';' } else { ',' });
+    }
+    clause
+}
+
+fn format_unit_entry(
+    dpr_path: &Path,
+    unit: &UnitFileInfo,
+    writer: &DprWriter,
+    separator: char,
+) -> String {
+    let in_path = writer.unit_path_text(dpr_path, &unit.path, separator);
+    format!("{} in '{}'", unit.name, in_path)
+}
+
+/// Number of `..` folders a relative path starts with.
+fn leading_parent_dirs(path: &str) -> usize {
+    path.split(['\\', '/'])
+        .take_while(|component| *component == "..")
+        .count()
+}
+
+fn strip_one_leading_line_ending(bytes: &[u8]) -> (&[u8], bool) {
+    if bytes.starts_with(b"\r\n") {
+        (&bytes[2..], true)
+    } else if bytes.first() == Some(&b'\n') || bytes.first() == Some(&b'\r') {
+        (&bytes[1..], true)
+    } else {
+        (bytes, false)
+    }
+}
+
+fn find_dpr_header_semicolon(bytes: &[u8]) -> Option<usize> {
+    find_dpr_header(bytes).map(|(_, semicolon)| semicolon)
+}
+
+/// The name declared by the `program` or `library` header, such as `App` or
+/// `My.App`, without reading the uses clause. None when there is no header.
+pub fn dpr_program_name(bytes: &[u8]) -> Option<String> {
+    let (name_start, semicolon) = find_dpr_header(bytes)?;
+    let mut i = name_start;
+    while i < semicolon {
+        match bytes[i] {
+            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_paren_comment(bytes, i + 2)
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            byte if pas_lex::is_ident_start(byte) => {
+                return Some(pas_lex::read_ident_with_dots(bytes, i).0);
+            }
+            byte if byte.is_ascii_whitespace() => i += 1,
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Offsets just past the `program`/`library` keyword and of the header's `;`.
+fn find_dpr_header(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut i = pas_lex::skip_bom(bytes);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_paren_comment(bytes, i + 2)
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            byte if pas_lex::is_ident_start(byte) => {
+                let (token, next) = pas_lex::read_ident(bytes, i);
+                if token.eq_ignore_ascii_case("program") || token.eq_ignore_ascii_case("library") {
+                    let mut j = next;
+                    while j < bytes.len() {
+                        match bytes[j] {
+                            b';' => return Some((next, j)),
+                            b'{' => j = pas_lex::skip_brace_comment(bytes, j + 1),
+                            b'(' if bytes.get(j + 1) == Some(&b'*') => {
+                                j = pas_lex::skip_paren_comment(bytes, j + 2)
+                            }
+                            b'/' if bytes.get(j + 1) == Some(&b'/') => {
+                                j = pas_lex::skip_line_comment(bytes, j + 2)
+                            }
+                            b'\'' => j = pas_lex::skip_string(bytes, j + 1),
+                            _ => j += 1,
+                        }
+                    }
+                    return None;
+                }
+                i = next;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn dpr_has_uses_keyword(bytes: &[u8]) -> bool {
+    find_uses_keyword(bytes).is_some()
+}
+
+/// Offset of the first `uses` keyword outside comments and strings.
+fn find_uses_keyword(bytes: &[u8]) -> Option<usize> {
+    let mut i = pas_lex::skip_bom(bytes);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_paren_comment(bytes, i + 2)
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            byte if pas_lex::is_ident_start(byte) => {
+                let (token, next) = pas_lex::read_ident(bytes, i);
+                if token.eq_ignore_ascii_case("uses") {
+                    return Some(i);
+                }
+                i = next;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Explains why `parse_dpr_uses` found nothing by rescanning `bytes`.
+fn uses_list_failure(path: &Path, bytes: &[u8]) -> Diagnostic {
+    let encoding = detect_encoding(bytes);
+    let context = format!("{} bytes, {encoding}", bytes.len());
+    if encoding.starts_with("UTF-16") {
+        return Diagnostic::warning(
+            DiagnosticCode::NoUsesList,
+            format!(
+                "no uses list found in {}: UTF-16 text is not supported ({context})",
+                path.display()
+            ),
+        )
+        .at(path);
+    }
+
+    let mut open_comment = None;
+    let mut i = pas_lex::skip_bom(bytes);
+    while i < bytes.len() {
+        i = skip_trivia_tracking(bytes, i, &mut open_comment);
+        if i >= bytes.len() {
+            break;
+        }
+        if !pas_lex::is_ident_start(bytes[i]) {
+            i += 1;
+            continue;
+        }
+        let (token, next) = pas_lex::read_ident(bytes, i);
+        if token.eq_ignore_ascii_case("uses") {
+            let gave_up_at = uses_list_abort_offset(bytes, next, &mut open_comment);
+            let reason = match open_comment {
+                Some(start) => format!("comment opened at byte {start} is never closed"),
+                None => format!(
+                    "gave up at byte {gave_up_at} near {:?}",
+                    snippet_at(bytes, gave_up_at)
+                ),
+            };
+            return Diagnostic::warning(
+                DiagnosticCode::UsesParseFailed,
+                format!(
+                    "failed to parse uses list in {}: {reason} ({context})",
+                    path.display()
+                ),
+            )
+            .at_offset(path, Some(open_comment.unwrap_or(gave_up_at)));
+        }
+        i = next;
+    }
+
+    let reason = match open_comment {
+        Some(start) => format!("comment opened at byte {start} is never closed"),
+        None => "no 'uses' keyword outside comments and strings".to_string(),
+    };
+    Diagnostic::warning(
+        DiagnosticCode::NoUsesList,
+        format!(
+            "no uses list found in {}: {reason} ({context})",
+            path.display()
+        ),
+    )
+    .at_offset(path, open_comment)
+}
+
+/// Returns the offset of the entry, comment, or `;` where the list parse stopped.
+fn uses_list_abort_offset(bytes: &[u8], mut i: usize, open_comment: &mut Option<usize>) -> usize {
+    let mut entry_start = i;
+    loop {
+        i = skip_trivia_tracking(bytes, i, open_comment);
+        if i >= bytes.len() {
+            return entry_start;
+        }
+        match bytes[i] {
+            b';' => return i,
+            byte if pas_lex::is_ident_start(byte) => {
+                let (name, next) = pas_lex::read_ident_with_dots(bytes, i);
+                if !name.eq_ignore_ascii_case("in") {
+                    entry_start = i;
+                }
+                i = next;
+            }
+            _ => i += 1,
+        }
+    }
+}
+
+fn skip_trivia_tracking(bytes: &[u8], mut i: usize, open_comment: &mut Option<usize>) -> usize {
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
+            b'{' => {
+                i = pas_lex::skip_brace_comment(bytes, i + 1);
+                if bytes[i - 1] != b'}' {
+                    *open_comment = Some(start);
+                }
+            }
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_paren_comment(bytes, i + 2);
+                if i < start + 4 || !bytes[..i].ends_with(b"*)") {
+                    *open_comment = Some(start);
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            _ => break,
+        }
+    }
+    i
+}
+
+fn detect_encoding(bytes: &[u8]) -> &'static str {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        "UTF-8 BOM"
+    } else if bytes.starts_with(&[0xFF, 0xFE]) {
+        "UTF-16LE BOM"
+    } else if bytes.starts_with(&[0xFE, 0xFF]) {
+        "UTF-16BE BOM"
+    } else if bytes.contains(&0) {
+        "UTF-16 without BOM"
+    } else if std::str::from_utf8(bytes).is_ok() {
+        "UTF-8"
+    } else {
+        "ANSI"
+    }
+}
+
+fn snippet_at(bytes: &[u8], offset: usize) -> String {
+    let end = (offset + 32).min(bytes.len());
+    String::from_utf8_lossy(&bytes[offset.min(end)..end]).into_owned()
+}
+
+fn build_insertion_after(
+    bytes: &[u8],
+    list: &UsesList,
+    insert_after: usize,
+    entry_text: &[u8],
+    indent: Option<&str>,
+) -> Option<(usize, Vec<u8>)> {
+    let entry = list.entries.get(insert_after)?;
+    if entry.from_include {
+        return None;
+    }
+    let delimiter_pos = entry.delimiter_pos?;
+    if entry.delimiter != Some(b',') {
+        return None;
+    }
+    let next_entry = list.entries.get(insert_after + 1)?;
+    let next_start = next_entry.start;
+    if delimiter_pos + 1 > next_start || next_start > bytes.len() {
+        return None;
+    }
+
+    let separator_after = &bytes[delimiter_pos + 1..next_start];
+    if has_plain_comment(separator_after) {
+        return Some(insertion_before_entry(
+            bytes, next_start, entry_text, indent,
+        ));
+    }
+    let separator_before = separator_before_new_entry(bytes, list, separator_after, indent);
+
+    let mut insertion = Vec::new();
+    insertion.extend_from_slice(&separator_before);
+    insertion.extend_from_slice(entry_text);
+    insertion.push(b',');
+
+    Some((delimiter_pos + 1, insertion))
+}
+
+/// Whether `separator` holds a comment and no compiler directive.
+fn has_plain_comment(separator: &[u8]) -> bool {
+    let has_comment = separator.contains(&b'{')
+        || separator
+            .windows(2)
+            .any(|pair| pair == b"(*" || pair == b"//");
+    let has_directive = separator.windows(2).any(|pair| pair == b"{$")
+        || separator.windows(3).any(|triple| triple == b"(*$");
+    has_comment && !has_directive
+}
+
+/// The insertion placing `entry_text` just before the entry at `next_start`.
+///
+/// Used when a comment follows the anchor's comma, so the comment stays with
+/// the entry it was written after. An entry starting its own line gets a new
+/// line of its own above it; otherwise the text goes right before the entry.
+fn insertion_before_entry(
+    bytes: &[u8],
+    next_start: usize,
+    entry_text: &[u8],
+    indent: Option<&str>,
+) -> (usize, Vec<u8>) {
+    let line_start = bytes[..next_start]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let leading = &bytes[line_start..next_start];
+    let mut insertion = Vec::new();
+    if line_start > 0 && leading.iter().all(|&byte| matches!(byte, b' ' | b'\t')) {
+        let indent = indent.map_or(leading, str::as_bytes);
+        insertion.extend_from_slice(indent);
+        insertion.extend_from_slice(entry_text);
+        insertion.push(b',');
+        insertion.extend_from_slice(detect_line_ending(bytes).as_bytes());
+        return (line_start, insertion);
+    }
+    insertion.extend_from_slice(entry_text);
+    insertion.extend_from_slice(b", ");
+    (next_start, insertion)
+}
+
+fn separator_before_new_entry<'a>(
+    bytes: &[u8],
+    list: &UsesList,
+    separator_after: &'a [u8],
+    indent: Option<&str>,
+) -> std::borrow::Cow<'a, [u8]> {
+    if let Some(indent) = indent.filter(|_| separator_after.contains(&b'\n')) {
+        let line_ending = detect_line_ending(bytes);
+        return std::borrow::Cow::Owned(format!("{line_ending}{indent}").into_bytes());
+    }
+    if separator_after
+        .iter()
+        .all(|byte| byte.is_ascii_whitespace())
+    {
+        return std::borrow::Cow::Borrowed(separator_after);
+    }
+
+    let leading_ws_len = separator_after
+        .iter()
+        .take_while(|byte| byte.is_ascii_whitespace())
+        .count();
+    if leading_ws_len > 0 {
+        return std::borrow::Cow::Borrowed(&separator_after[..leading_ws_len]);
+    }
+
+    let line_ending = detect_line_ending(bytes);
+    let fallback = if list.multiline {
+        format!("{line_ending}{}", indent.unwrap_or(&list.indent))
+    } else {
+        " ".to_string()
+    };
+    std::borrow::Cow::Owned(fallback.into_bytes())
+}
+
+fn relative_path(target: &Path, base: Option<&Path>) -> String {
+    let target = unit_cache::strip_verbatim_prefix(&unit_cache::canonicalize_if_exists(target));
+    if let Some(base) = base {
+        let base = unit_cache::strip_verbatim_prefix(&unit_cache::canonicalize_if_exists(base));
+        if same_volume(&target, &base) {
+            if let Some(diff) = pathdiff::diff_paths(&target, &base) {
+                return diff.to_string_lossy().to_string();
+            }
+        }
+    }
+    target.to_string_lossy().to_string()
+}
+
+fn is_cross_drive(target: &Path, base: Option<&Path>) -> bool {
+    let Some(base) = base else {
+        return false;
+    };
+    let target = unit_cache::strip_verbatim_prefix(&unit_cache::canonicalize_if_exists(target));
+    let base = unit_cache::strip_verbatim_prefix(&unit_cache::canonicalize_if_exists(base));
+    !same_volume(&target, &base)
+}
+
+fn same_volume(first: &Path, second: &Path) -> bool {
+    let volume = |path: &Path| {
+        path.components()
+            .next()
+            .map(|component| component.as_os_str().to_string_lossy().to_ascii_lowercase())
+    };
+    volume(first) == volume(second)
+}
+
+fn parse_dpr_uses(
+    dpr_path: &Path,
+    bytes: &[u8],
+    includes: &Includes,
+    warnings: &mut Warnings,
+) -> Option<UsesList> {
+    let mut i = pas_lex::skip_bom(bytes);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_paren_comment(bytes, i + 2)
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            byte if pas_lex::is_ident_start(byte) => {
+                let (token, next) = pas_lex::read_ident(bytes, i);
+                if token.eq_ignore_ascii_case("uses") {
+                    return parse_dpr_uses_list(dpr_path, bytes, next, includes, warnings);
+                }
+                i = next;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn parse_dpr_uses_list(
+    dpr_path: &Path,
+    bytes: &[u8],
+    i: usize,
+    includes: &Includes,
+    warnings: &mut Warnings,
+) -> Option<UsesList> {
+    let list_start = i;
+    let mut entries = Vec::new();
+    let mut has_backslash = false;
+    let mut has_slash = false;
+    let mut include_semicolon = false;
+    let mut cond_depth = 0;
+    let mut include_stack = Vec::new();
+    include_stack.push(unit_cache::canonicalize_if_exists(dpr_path));
+    let mut state = DprParseState {
+        warnings,
+        includes,
+        include_stack: &mut include_stack,
+        has_backslash: &mut has_backslash,
+        has_slash: &mut has_slash,
+        include_semicolon: &mut include_semicolon,
+        cond_depth: &mut cond_depth,
+    };
+
+    let semicolon =
+        parse_uses_fragment_for_dpr(bytes, i, dpr_path, &mut entries, &mut state, None)?;
+    if include_semicolon {
+        return None;
+    }
+    if entries.is_empty() {
+        return None;
+    }
+    let multiline = bytes[list_start..semicolon].contains(&b'\n');
+    let indent = if multiline {
+        majority_indent(bytes, &entries)
+    } else {
+        String::new()
+    };
+    let tight_commas = !multiline
+        && entries.len() > 1
+        && entries.windows(2).all(|pair| {
+            pair[0].delimiter == Some(b',')
+                && pair[0]
+                    .delimiter_pos
+                    .is_some_and(|pos| pos + 1 == pair[1].start)
+        });
+
+    Some(UsesList {
+        entries,
+        start: list_start,
+        semicolon,
+        multiline,
+        tight_commas,
+        indent,
+        has_backslash,
+        has_slash,
+    })
+}
+
+struct DprParseState<'a> {
+    warnings: &'a mut Warnings,
+    includes: &'a Includes,
+    include_stack: &'a mut Vec<PathBuf>,
+    has_backslash: &'a mut bool,
+    has_slash: &'a mut bool,
+    include_semicolon: &'a mut bool,
+    cond_depth: &'a mut usize,
+}
+
+fn parse_uses_fragment_for_dpr(
+    bytes: &[u8],
+    mut i: usize,
+    source_path: &Path,
+    entries: &mut Vec<UsesEntry>,
+    state: &mut DprParseState<'_>,
+    entry_start_override: Option<usize>,
+) -> Option<usize> {
+    while i < bytes.len() {
+        i = skip_ws_comments_and_includes_dpr(
+            bytes,
+            i,
+            source_path,
+            entries,
+            state,
+            entry_start_override,
+        );
+        if i >= bytes.len() {
+            return None;
+        }
+        if bytes[i] == b';' {
+            if entry_start_override.is_some() {
+                state.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::IncludeSeparator,
+                        format!(
+                            "include file {} contains ';' in uses list",
+                            source_path.display()
+                        ),
+                    )
+                    .at_offset(source_path, Some(i)),
+                );
+                *state.include_semicolon = true;
+            }
+            return Some(i);
+        }
+        if !pas_lex::is_ident_start(bytes[i]) {
+            i += 1;
+            continue;
+        }
+
+        let entry_start = i;
+        let entry_depth = *state.cond_depth;
+        let (name, next) = pas_lex::read_ident_with_dots(bytes, i);
+        i = next;
+        i = pas_lex::skip_ws_and_comments(bytes, i);
+
+        let mut in_path = None;
+        let mut in_path_span = None;
+        if let Some((token, next_token)) = peek_ident(bytes, i) {
+            if token.eq_ignore_ascii_case("in") {
+                i = next_token;
+                i = skip_ws_and_comments_no_strings(bytes, i);
+                if i < bytes.len() && bytes[i] == b'\'' {
+                    if let Some((value, end)) = pas_lex::read_string_literal(bytes, i) {
+                        in_path = Some(value);
+                        in_path_span = entry_start_override.is_none().then_some((i, end));
+                        i = end;
+                    } else {
+                        i = pas_lex::skip_string(bytes, i + 1);
+                    }
+                }
+            }
+        }
+
+        update_path_separator_flags(&in_path, state.has_backslash, state.has_slash);
+
+        let (pos, delim, include_entries) =
+            scan_to_delimiter_with_includes(bytes, i, source_path, state, entry_start_override);
+        let start = entry_start_override.unwrap_or(entry_start);
+        entries.push(UsesEntry {
+            name,
+            in_path,
+            in_path_span,
+            start,
+            delimiter: delim,
+            delimiter_pos: if entry_start_override.is_some() {
+                None
+            } else {
+                delim.map(|_| pos)
+            },
+            from_include: entry_start_override.is_some(),
+            cond_depth: entry_depth,
+        });
+        if !include_entries.is_empty() {
+            entries.extend(include_entries);
+        }
+        match delim {
+            Some(b',') => i = pos + 1,
+            Some(b';') => return Some(pos),
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn skip_ws_comments_and_includes_dpr(
+    bytes: &[u8],
+    mut i: usize,
+    source_path: &Path,
+    entries: &mut Vec<UsesEntry>,
+    state: &mut DprParseState<'_>,
+    entry_start_override: Option<usize>,
+) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
+            b'{' | b'(' => {
+                if let Some((include_name, end)) = pas_lex::parse_include_directive(bytes, i) {
+                    let anchor = entry_start_override.unwrap_or(i);
+                    let include_entries = parse_include_entries_for_dpr(
+                        include_name.as_str(),
+                        anchor,
+                        source_path,
+                        i,
+                        state,
+                    );
+                    if !include_entries.is_empty() {
+                        entries.extend(include_entries);
+                    }
+                    i = end;
+                    continue;
+                }
+                if let Some((directive, end)) = pas_lex::parse_compiler_directive(bytes, i) {
+                    track_conditional_depth(&directive, state.cond_depth);
+                    i = end;
+                    continue;
+                }
+                i = if bytes[i] == b'{' {
+                    pas_lex::skip_brace_comment(bytes, i + 1)
+                } else if bytes.get(i + 1) == Some(&b'*') {
+                    pas_lex::skip_paren_comment(bytes, i + 2)
+                } else {
+                    i + 1
+                };
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            _ => break,
+        }
+    }
+    i
+}
+
+fn scan_to_delimiter_with_includes(
+    bytes: &[u8],
+    mut i: usize,
+    source_path: &Path,
+    state: &mut DprParseState<'_>,
+    entry_start_override: Option<usize>,
+) -> (usize, Option<u8>, Vec<UsesEntry>) {
+    let mut include_entries = Vec::new();
+    while i < bytes.len() {
+        match bytes[i] {
+            b',' | b';' => return (i, Some(bytes[i]), include_entries),
+            b'{' | b'(' => {
+                if let Some((include_name, end)) = pas_lex::parse_include_directive(bytes, i) {
+                    let anchor = entry_start_override.unwrap_or(i);
+                    let entries = parse_include_entries_for_dpr(
+                        include_name.as_str(),
+                        anchor,
+                        source_path,
+                        i,
+                        state,
+                    );
+                    if !entries.is_empty() {
+                        include_entries.extend(entries);
+                    }
+                    i = end;
+                    continue;
+                }
+                if let Some((directive, end)) = pas_lex::parse_compiler_directive(bytes, i) {
+                    track_conditional_depth(&directive, state.cond_depth);
+                    i = end;
+                    continue;
+                }
+                i = if bytes[i] == b'{' {
+                    pas_lex::skip_brace_comment(bytes, i + 1)
+                } else if bytes.get(i + 1) == Some(&b'*') {
+                    pas_lex::skip_paren_comment(bytes, i + 2)
+                } else {
+                    i + 1
+                };
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            _ => i += 1,
+        }
+    }
+    (i, None, include_entries)
+}
+
+fn parse_include_entries_for_dpr(
+    include_name: &str,
+    anchor: usize,
+    source_path: &Path,
+    directive_offset: usize,
+    state: &mut DprParseState<'_>,
+) -> Vec<UsesEntry> {
+    let DprParseState {
+        warnings,
+        includes,
+        include_stack,
+        has_backslash,
+        has_slash,
+        include_semicolon,
+        cond_depth,
+    } = &mut *state;
+
+    uses_include::with_include_bytes(
+        include_name,
+        source_path,
+        directive_offset,
+        includes,
+        warnings,
+        include_stack,
+        |include_path, bytes, warnings, include_stack| {
+            let mut entries = Vec::new();
+            let mut nested_state = DprParseState {
+                warnings,
+                includes,
+                include_stack,
+                has_backslash,
+                has_slash,
+                include_semicolon,
+                cond_depth,
+            };
+            let _ = parse_uses_fragment_for_dpr(
+                bytes,
+                pas_lex::skip_bom(bytes),
+                include_path,
+                &mut entries,
+                &mut nested_state,
+                Some(anchor),
+            );
+            entries
+        },
+    )
+    .unwrap_or_default()
+}
+
+fn track_conditional_depth(directive: &pas_lex::CompilerDirective, depth: &mut usize) {
+    match directive {
+        pas_lex::CompilerDirective::IfDef(_)
+        | pas_lex::CompilerDirective::IfNDef(_)
+        | pas_lex::CompilerDirective::IfExpr(_)
+        | pas_lex::CompilerDirective::IfOpt(_) => *depth += 1,
+        pas_lex::CompilerDirective::EndIf => *depth = depth.saturating_sub(1),
+        _ => {}
+    }
+}
+
+fn peek_ident(bytes: &[u8], i: usize) -> Option<(String, usize)> {
+    if i < bytes.len() && pas_lex::is_ident_start(bytes[i]) {
+        let (token, next) = pas_lex::read_ident(bytes, i);
+        return Some((token, next));
+    }
+    None
+}
+
+fn update_path_separator_flags(
+    in_path: &Option<String>,
+    has_backslash: &mut bool,
+    has_slash: &mut bool,
+) {
+    let Some(path) = in_path.as_ref() else {
+        return;
+    };
+    if path.contains('\\') {
+        *has_backslash = true;
+    }
+    if path.contains('/') {
+        *has_slash = true;
+    }
+}
+
+fn skip_ws_and_comments_no_strings(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
+            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_paren_comment(bytes, i + 2)
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            _ => break,
+        }
+    }
+    i
+}
+
+/// The indentation most entries that start their own line use, the first
+/// such entry's on a tie; the first entry's line when none starts a line, as
+/// when every entry shares the line of `uses`.
+fn majority_indent(bytes: &[u8], entries: &[UsesEntry]) -> String {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for entry in entries.iter().filter(|entry| !entry.from_include) {
+        let indent = infer_indent(bytes, entry.start);
+        let line_start = entry.start - indent.len();
+        if line_start > 0 && bytes[line_start - 1] != b'\n' {
+            continue;
+        }
+        match counts.iter_mut().find(|(known, _)| *known == indent) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((indent, 1)),
+        }
+    }
+    let mut best: Option<(String, usize)> = None;
+    for (indent, count) in counts {
+        if best
+            .as_ref()
+            .is_none_or(|(_, best_count)| count > *best_count)
+        {
+            best = Some((indent, count));
+        }
+    }
+    match best {
+        Some((indent, _)) => indent,
+        None => entries
+            .first()
+            .map(|entry| infer_indent(bytes, entry.start))
+            .unwrap_or_default(),
+    }
+}
+
+fn infer_indent(bytes: &[u8], entry_start: usize) -> String {
+    let line_start = bytes[..entry_start]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map(|pos| pos + 1)
+        .unwrap_or(0);
+    let indent_bytes = &bytes[line_start..entry_start];
+    let indent = indent_bytes
+        .iter()
+        .take_while(|&&b| b == b' ' || b == b'\t')
+        .copied()
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&indent).to_string()
+}
+
+fn detect_line_ending(bytes: &[u8]) -> &'static str {
+    if bytes.windows(2).any(|pair| pair == b"\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+/// File operations behind [`write_atomic`], replaced in tests.
+trait AtomicFiles {
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn sleep(&self, duration: Duration);
+}
+
+struct RealFiles;
+
+impl AtomicFiles for RealFiles {
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Writes through a temp file renamed over `path`, retrying while another
+/// program holds the file; returns the number of retries it took.
+fn write_atomic(
+    files: &dyn AtomicFiles,
+    path: &Path,
+    contents: &[u8],
+    retry: WriteRetry,
+) -> io::Result<u32> {
+    let attempts = retry.attempts.max(1);
+    let mut backoff = retry.backoff;
+    let mut first_err = None;
+    for attempt in 0..attempts {
+        if attempt > 0 {
+            files.sleep(backoff);
+            backoff *= 2;
+        }
+        match write_atomic_once(files, path, contents) {
+            Ok(()) => return Ok(attempt),
+            Err(err) if is_transient_write_error(&err) => {
+                first_err.get_or_insert(err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let err = first_err.expect("every attempt failed");
+    Err(io::Error::new(
+        err.kind(),
+        format!("{err} (still failing after {attempts} attempts)"),
+    ))
+}
+
+fn write_atomic_once(files: &dyn AtomicFiles, path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    files.write(&temp_path, contents)?;
+    match files.rename(&temp_path, path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            files.remove_file(path)?;
+            files.rename(&temp_path, path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Errors raised while antivirus or the IDE briefly holds a file open.
+fn is_transient_write_error(err: &io::Error) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+    if err.kind() == io::ErrorKind::ResourceBusy {
+        return true;
+    }
+    cfg!(windows)
+        && (err.kind() == io::ErrorKind::PermissionDenied
+            || matches!(
+                err.raw_os_error(),
+                Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+            ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Fails renames with the queued errors, then succeeds.
+    struct FlakyFiles {
+        rename_errors: RefCell<VecDeque<io::ErrorKind>>,
+        renames: Cell<usize>,
+        sleeps: RefCell<Vec<Duration>>,
+    }
+
+    impl FlakyFiles {
+        fn new(rename_errors: &[io::ErrorKind]) -> Self {
+            Self {
+                rename_errors: RefCell::new(rename_errors.iter().copied().collect()),
+                renames: Cell::new(0),
+                sleeps: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl AtomicFiles for FlakyFiles {
+        fn write(&self, _path: &Path, _contents: &[u8]) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+            self.renames.set(self.renames.get() + 1);
+            match self.rename_errors.borrow_mut().pop_front() {
+                Some(kind) => Err(io::Error::new(kind, "held by another program")),
+                None => Ok(()),
+            }
+        }
+
+        fn remove_file(&self, _path: &Path) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.sleeps.borrow_mut().push(duration);
+        }
+    }
+
+    #[test]
+    fn staged_label_reports_written_dprs_as_would_update() {
+        assert_eq!(DprStatus::Updated.staged_label(), "would-update");
+        assert_eq!(DprStatus::Partial.staged_label(), "would-update");
+        assert_eq!(DprStatus::Unchanged.staged_label(), "unchanged");
+        assert_eq!(DprStatus::Failed.staged_label(), "failed");
+    }
+
+    #[test]
+    fn write_atomic_retries_transient_errors_with_growing_backoff() {
+        let files = FlakyFiles::new(&[io::ErrorKind::ResourceBusy, io::ErrorKind::ResourceBusy]);
+        let retries = write_atomic(&files, Path::new("App.dpr"), b"x", WriteRetry::default())
+            .expect("third try succeeds");
+        assert_eq!(retries, 2);
+        assert_eq!(files.renames.get(), 3);
+        assert_eq!(
+            *files.sleeps.borrow(),
+            vec![Duration::from_millis(125), Duration::from_millis(250)]
+        );
+    }
+
+    #[test]
+    fn write_atomic_gives_up_with_the_original_error() {
+        let retry = WriteRetry {
+            attempts: 3,
+            backoff: Duration::from_millis(10),
+        };
+        let files = FlakyFiles::new(&[io::ErrorKind::ResourceBusy; 5]);
+        let err = write_atomic(&files, Path::new("App.dpr"), b"x", retry).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ResourceBusy);
+        assert_eq!(
+            err.to_string(),
+            "held by another program (still failing after 3 attempts)"
+        );
+        assert_eq!(files.renames.get(), 3);
+        assert_eq!(files.sleeps.borrow().len(), 2);
+    }
+
+    #[test]
+    fn write_atomic_does_not_retry_permanent_errors() {
+        let files = FlakyFiles::new(&[io::ErrorKind::NotFound]);
+        let err =
+            write_atomic(&files, Path::new("App.dpr"), b"x", WriteRetry::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(files.renames.get(), 1);
+        assert!(files.sleeps.borrow().is_empty());
+    }
+
+    #[test]
+    fn uses_list_failure_reports_utf16_files() {
+        let dpr_path = Path::new("App.dpr");
+        let mut src = vec![0xFF, 0xFE];
+        for byte in b"program App;\r\nuses A;\r\n" {
+            src.extend([*byte, 0]);
+        }
+        let mut warnings = Warnings::new();
+        assert!(parse_dpr_uses(dpr_path, &src, &Includes::default(), &mut warnings).is_none());
+
+        let diagnostic = uses_list_failure(dpr_path, &src);
+        assert_eq!(diagnostic.code, DiagnosticCode::NoUsesList);
+        assert_eq!(
+            diagnostic.message,
+            "no uses list found in App.dpr: UTF-16 text is not supported (48 bytes, UTF-16LE BOM)"
+        );
+    }
+
+    #[test]
+    fn uses_list_failure_points_at_unterminated_comment() {
+        let dpr_path = Path::new("App.dpr");
+        let src = b"program App;\n{ old header\nuses A;\nbegin end.\n";
+        let diagnostic = uses_list_failure(dpr_path, src);
+        assert_eq!(diagnostic.code, DiagnosticCode::NoUsesList);
+        assert_eq!(diagnostic.offset, Some(13));
+        assert_eq!(
+            diagnostic.message,
+            "no uses list found in App.dpr: comment opened at byte 13 is never closed (45 bytes, UTF-8)"
+        );
+
+        let src = b"program App;\nbegin end.\n";
+        let diagnostic = uses_list_failure(dpr_path, src);
+        assert_eq!(diagnostic.offset, None);
+        assert!(
+            diagnostic
+                .message
+                .ends_with(": no 'uses' keyword outside comments and strings (24 bytes, UTF-8)"),
+            "{}",
+            diagnostic.message
+        );
+    }
+
+    #[test]
+    fn uses_list_failure_reports_where_a_truncated_list_stopped() {
+        let dpr_path = Path::new("App.dpr");
+        let src = b"\xEF\xBB\xBFprogram App;\nuses\n  UnitA,\n  UnitB in 'UnitB.pas'\n";
+        let mut warnings = Warnings::new();
+        assert!(parse_dpr_uses(dpr_path, src, &Includes::default(), &mut warnings).is_none());
+
+        let diagnostic = uses_list_failure(dpr_path, src);
+        assert_eq!(diagnostic.code, DiagnosticCode::UsesParseFailed);
+        assert_eq!(diagnostic.offset, Some(32));
+        assert_eq!(
+            diagnostic.message,
+            "failed to parse uses list in App.dpr: gave up at byte 32 near \"UnitB in 'UnitB.pas'\\n\" (53 bytes, UTF-8 BOM)"
+        );
+
+        let src = b"program App;\nuses (* A,\n  B;\n";
+        let diagnostic = uses_list_failure(dpr_path, src);
+        assert_eq!(diagnostic.code, DiagnosticCode::UsesParseFailed);
+        assert_eq!(diagnostic.offset, Some(18));
+        assert!(
+            diagnostic
+                .message
+                .contains(": comment opened at byte 18 is never closed"),
+            "{}",
+            diagnostic.message
+        );
+    }
+
+    #[test]
+    fn parse_dpr_uses_single_line() {
+        let src = b"program Demo;\nuses Foo, Bar;\nbegin end.";
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let mut warnings = Warnings::new();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        assert_eq!(list.entries.len(), 2);
+        assert_eq!(list.entries[0].name, "Foo");
+        assert_eq!(list.entries[1].name, "Bar");
+        assert!(list.entries[0].in_path.is_none());
+        assert!(list.entries[1].in_path.is_none());
+        assert!(!list.multiline);
+        assert!(list.indent.is_empty());
+    }
+
+    #[test]
+    fn parse_dpr_uses_reads_unicode_entries_after_a_bom() {
+        let src =
+            "\u{FEFF}program 演示;\nuses\n  Foo,\n  主窗体 in '主窗体.pas',\n  Bar;\nbegin end.";
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let mut warnings = Warnings::new();
+        let list = parse_dpr_uses(
+            &dpr_path,
+            src.as_bytes(),
+            &Includes::default(),
+            &mut warnings,
+        )
+        .expect("uses list");
+        let names: Vec<&str> = list
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Foo", "主窗体", "Bar"]);
+        assert_eq!(list.entries[1].in_path.as_deref(), Some("主窗体.pas"));
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn parse_dpr_uses_multiline_with_indent_and_paths() {
+        let src = b"program Demo;\nuses\n  Foo,\n  Bar in 'lib\\Bar.pas',\n  Baz;\nbegin end.";
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let mut warnings = Warnings::new();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        assert_eq!(list.entries.len(), 3);
+        assert!(list.multiline);
+        assert_eq!(list.indent, "  ");
+        assert!(
+            list.has_backslash,
+            "expected backslash path detection, list={list:?}"
+        );
+        assert!(!list.has_slash);
+        assert_eq!(list.entries[1].in_path.as_deref(), Some("lib\\Bar.pas"));
+    }
+
+    #[test]
+    fn parse_dpr_uses_ignores_comments_and_directives() {
+        let src = br#"
+program Demo;
+uses Foo, {Bar}, (*Baz*), {$IFDEF X} Qux, {$ENDIF} RealUnit;
+begin end.
+"#;
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let mut warnings = Warnings::new();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        let names: Vec<String> = list
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        assert_eq!(names, vec!["Foo", "Qux", "RealUnit"]);
+        assert!(list.entries.iter().all(|entry| entry.in_path.is_none()));
+    }
+
+    #[test]
+    fn insert_new_unit_single_line() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_path = root.join("NewUnit.pas");
+        fs::write(&dpr_path, "program Demo;\nuses Foo, Bar;\nbegin end.").unwrap();
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+
+        let bytes = fs::read(&dpr_path).unwrap();
+        let mut warnings = Warnings::new();
+        let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings)
+            .expect("uses list");
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+        insert_new_unit(
+            &bytes,
+            &dpr_path,
+            &list,
+            &new_unit,
+            None,
+            &mut DprWriter::default(),
+            &mut warnings,
+        )
+        .unwrap();
+
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(
+            updated.contains("uses Foo, Bar, NewUnit in 'NewUnit.pas';"),
+            "{updated}"
+        );
+    }
+
+    #[test]
+    fn insert_new_unit_single_line_follows_tight_commas() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_path = root.join("NewUnit.pas");
+        fs::write(&dpr_path, "program Demo;\nuses Foo,Bar;\nbegin end.").unwrap();
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+
+        let bytes = fs::read(&dpr_path).unwrap();
+        let mut warnings = Warnings::new();
+        let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings)
+            .expect("uses list");
+        assert!(list.tight_commas);
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+        insert_new_unit(
+            &bytes,
+            &dpr_path,
+            &list,
+            &new_unit,
+            None,
+            &mut DprWriter::default(),
+            &mut warnings,
+        )
+        .unwrap();
+
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(
+            updated.contains("uses Foo,Bar,NewUnit in 'NewUnit.pas';"),
+            "{updated}"
+        );
+    }
+
+    #[test]
+    fn insert_new_unit_wraps_single_line_list_past_wrap_at() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_path = root.join("NewUnit.pas");
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+
+        for (source, wrap_at, expected) in [
+            (
+                "program Demo;\r\nuses Foo, Bar in 'Bar.pas';\r\nbegin end.",
+                Some(30),
+                "program Demo;\r\nuses\r\n  Foo,\r\n  Bar in 'Bar.pas',\r\n  NewUnit in 'NewUnit.pas';\r\nbegin end.",
+            ),
+            (
+                "program Demo;\nuses Foo, Bar;\nbegin end.",
+                Some(80),
+                "program Demo;\nuses Foo, Bar, NewUnit in 'NewUnit.pas';\nbegin end.",
+            ),
+            (
+                "program Demo;\nuses Foo, {$IFDEF X} Bar, {$ENDIF} Baz;\nbegin end.",
+                Some(30),
+                "program Demo;\nuses Foo, {$IFDEF X} Bar, {$ENDIF} Baz, NewUnit in 'NewUnit.pas';\nbegin end.",
+            ),
+        ] {
+            fs::write(&dpr_path, source).unwrap();
+            let bytes = fs::read(&dpr_path).unwrap();
+            let mut warnings = Warnings::new();
+            let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings).expect("uses list");
+            insert_new_unit(
+                &bytes,
+                &dpr_path,
+                &list,
+                &new_unit,
+                None,
+                &mut DprWriter::default().wrap_at(wrap_at),
+                &mut warnings,
+            )
+            .unwrap();
+
+            assert_eq!(fs::read_to_string(&dpr_path).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn insert_new_unit_multiline_keeps_indent_and_separator() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_dir = root.join("sub");
+        fs::create_dir_all(&pas_dir).unwrap();
+        let pas_path = pas_dir.join("NewUnit.pas");
+        fs::write(
+            &dpr_path,
+            "program Demo;\r\nuses\r\n  Foo,\r\n  Bar in 'lib/Bar.pas',\r\n  Baz;\r\nbegin end.",
+        )
+        .unwrap();
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+
+        let bytes = fs::read(&dpr_path).unwrap();
+        let mut warnings = Warnings::new();
+        let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings)
+            .expect("uses list");
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+        insert_new_unit(
+            &bytes,
+            &dpr_path,
+            &list,
+            &new_unit,
+            None,
+            &mut DprWriter::default(),
+            &mut warnings,
+        )
+        .unwrap();
+
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(
+            updated.contains("Baz,\r\n  NewUnit in 'sub/NewUnit.pas';"),
+            "{updated}"
+        );
+    }
+
+    #[test]
+    fn parse_dpr_uses_infers_indent_from_most_entries() {
+        let src = b"program Demo;\nuses Foo in 'Foo.pas',\n  Bar in 'Bar.pas',\n    Baz in 'Baz.pas',\n  Qux in 'Qux.pas';\nbegin end.";
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let mut warnings = Warnings::new();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        assert!(list.multiline);
+        assert_eq!(list.indent, "  ");
+    }
+
+    #[test]
+    fn insert_new_unit_with_first_entry_on_uses_line_honors_indent_override() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_path = root.join("NewUnit.pas");
+        let original = "program Demo;\nuses Foo in 'Foo.pas',\n  Bar in 'Bar.pas';\nbegin end.";
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+
+        for (indent, expected) in [
+            (None, "Bar in 'Bar.pas',\n  NewUnit in 'NewUnit.pas';"),
+            (Some("\t"), "Bar in 'Bar.pas',\n\tNewUnit in 'NewUnit.pas';"),
+        ] {
+            fs::write(&dpr_path, original).unwrap();
+            let bytes = fs::read(&dpr_path).unwrap();
+            let mut warnings = Warnings::new();
+            let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings)
+                .expect("uses list");
+            insert_new_unit(
+                &bytes,
+                &dpr_path,
+                &list,
+                &new_unit,
+                None,
+                &mut DprWriter::default().indent(indent),
+                &mut warnings,
+            )
+            .unwrap();
+
+            let updated = fs::read_to_string(&dpr_path).unwrap();
+            assert!(updated.contains(expected), "{updated}");
+            assert!(updated.contains("uses Foo in 'Foo.pas',\n"), "{updated}");
+        }
+    }
+
+    #[test]
+    fn parse_dpr_uses_tracks_conditional_depth_of_entries() {
+        let src = b"program Demo;\nuses\n  Foo,\n  {$IFDEF MSWINDOWS}\n  {$IF CompilerVersion > 30} Bar, {$IFEND}\n  Baz,\n  {$ELSE}\n  Qux,\n  {$ENDIF}\n  Last;\nbegin end.";
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let mut warnings = Warnings::new();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        let depths: Vec<(&str, usize)> = list
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.cond_depth))
+            .collect();
+        assert_eq!(
+            depths,
+            vec![("Foo", 0), ("Bar", 2), ("Baz", 1), ("Qux", 1), ("Last", 0)]
+        );
+    }
+
+    #[test]
+    fn insert_new_unit_after_guarded_entry_appends_unless_allowed() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_path = root.join("NewUnit.pas");
+        let original =
+            "program Demo;\nuses\n  Foo,\n  {$IFDEF MSWINDOWS}\n  WinOnly,\n  {$ENDIF}\n  Bar;\nbegin end.";
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+
+        for (allow, expected) in [
+            (false, "  Bar,\n  NewUnit in 'NewUnit.pas';"),
+            (true, "  WinOnly,\n  NewUnit in 'NewUnit.pas',\n  {$ENDIF}"),
+        ] {
+            fs::write(&dpr_path, original).unwrap();
+            let bytes = fs::read(&dpr_path).unwrap();
+            let mut warnings = Warnings::new();
+            let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings)
+                .expect("uses list");
+            let guarded = find_listed_unit_index(&list, "WinOnly").expect("WinOnly entry");
+            let mut writer = DprWriter::default().insert_in_conditional(allow);
+            insert_new_unit(
+                &bytes,
+                &dpr_path,
+                &list,
+                &new_unit,
+                Some(guarded),
+                &mut writer,
+                &mut warnings,
+            )
+            .unwrap();
+
+            let updated = fs::read_to_string(&dpr_path).unwrap();
+            assert!(updated.contains(expected), "{updated}");
+            assert_eq!(
+                writer.infos.len(),
+                usize::from(!allow),
+                "{:?}",
+                writer.infos
+            );
+        }
+    }
+
+    #[test]
+    fn insert_new_unit_after_entry_single_line() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_path = root.join("NewUnit.pas");
+        fs::write(&dpr_path, "program Demo;\nuses Foo, Bar, Baz;\nbegin end.").unwrap();
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+
+        let bytes = fs::read(&dpr_path).unwrap();
+        let mut warnings = Warnings::new();
+        let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings)
+            .expect("uses list");
+        let insert_after = list
+            .entries
+            .iter()
+            .position(|entry| entry.name == "Bar")
+            .expect("Bar entry");
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+        insert_new_unit(
+            &bytes,
+            &dpr_path,
+            &list,
+            &new_unit,
+            Some(insert_after),
+            &mut DprWriter::default(),
+            &mut warnings,
+        )
+        .unwrap();
+
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(
+            updated.contains("uses Foo, Bar, NewUnit in 'NewUnit.pas', Baz;"),
+            "{updated}"
+        );
+    }
+
+    #[test]
+    fn insert_new_unit_after_entry_multiline() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_path = root.join("NewUnit.pas");
+        fs::write(
+            &dpr_path,
+            "program Demo;\r\nuses\r\n  Foo,\r\n  Bar,\r\n  Baz;\r\nbegin end.",
+        )
+        .unwrap();
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+
+        let bytes = fs::read(&dpr_path).unwrap();
+        let mut warnings = Warnings::new();
+        let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings)
+            .expect("uses list");
+        let insert_after = list
+            .entries
+            .iter()
+            .position(|entry| entry.name == "Bar")
+            .expect("Bar entry");
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+        insert_new_unit(
+            &bytes,
+            &dpr_path,
+            &list,
+            &new_unit,
+            Some(insert_after),
+            &mut DprWriter::default(),
+            &mut warnings,
+        )
+        .unwrap();
+
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(
+            updated.contains("Bar,\r\n  NewUnit in 'NewUnit.pas',\r\n  Baz;"),
+            "{updated}"
+        );
+    }
+
+    #[test]
+    fn insert_new_unit_after_entry_keeps_comment_after_its_comma() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let pas_path = root.join("NewUnit.pas");
+        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: pas_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+
+        for (source, expected) in [
+            (
+                "program Demo;\r\nuses\r\n  Foo,\r\n  Bar, // platform stuff\r\n  Baz;\r\nbegin end.",
+                "program Demo;\r\nuses\r\n  Foo,\r\n  Bar, // platform stuff\r\n  NewUnit in 'NewUnit.pas',\r\n  Baz;\r\nbegin end.",
+            ),
+            (
+                "program Demo;\nuses\n  Foo,\n  Bar, { platform stuff\n    spanning lines }\n  Baz;\nbegin end.",
+                "program Demo;\nuses\n  Foo,\n  Bar, { platform stuff\n    spanning lines }\n  NewUnit in 'NewUnit.pas',\n  Baz;\nbegin end.",
+            ),
+            (
+                "program Demo;\nuses Foo, Bar, (* platform *) Baz;\nbegin end.",
+                "program Demo;\nuses Foo, Bar, (* platform *) NewUnit in 'NewUnit.pas', Baz;\nbegin end.",
+            ),
+            (
+                "program Demo;\nuses\n  Foo,\n  Bar,\n  {$IFDEF X} Baz, {$ENDIF}\n  Qux;\nbegin end.",
+                "program Demo;\nuses\n  Foo,\n  Bar,\n  NewUnit in 'NewUnit.pas',\n  {$IFDEF X} Baz, {$ENDIF}\n  Qux;\nbegin end.",
+            ),
+        ] {
+            fs::write(&dpr_path, source).unwrap();
+            let bytes = fs::read(&dpr_path).unwrap();
+            let mut warnings = Warnings::new();
+            let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), &mut warnings).expect("uses list");
+            let insert_after = list
+                .entries
+                .iter()
+                .position(|entry| entry.name == "Bar")
+                .expect("Bar entry");
+            insert_new_unit(
+                &bytes,
+                &dpr_path,
+                &list,
+                &new_unit,
+                Some(insert_after),
+                &mut DprWriter::default(),
+                &mut warnings,
+            )
+            .unwrap();
+
+            assert_eq!(fs::read_to_string(&dpr_path).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn insert_new_unit_applies_cross_drive_policy() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let original = "program Demo;\nuses Foo;\nbegin end.";
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: PathBuf::from(r"\\?\D:\lib\NewUnit.pas"),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+        let insert = |cross_drive: CrossDrive, warnings: &mut Warnings| {
+            fs::write(&dpr_path, original).unwrap();
+            let bytes = fs::read(&dpr_path).unwrap();
+            let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), warnings)
+                .expect("uses list");
+            let mut writer = DprWriter::new(
+                false,
+                PathStyle::default(),
+                cross_drive,
+                None,
+                WriteRetry::default(),
+            );
+            let result = insert_new_unit(
+                &bytes,
+                &dpr_path,
+                &list,
+                &new_unit,
+                None,
+                &mut writer,
+                warnings,
+            );
+            (result, fs::read_to_string(&dpr_path).unwrap())
+        };
+
+        let mut warnings = Warnings::new();
+        let (result, updated) = insert(CrossDrive::Absolute, &mut warnings);
+        assert!(result.unwrap());
+        assert!(
+            updated.contains(r"uses Foo, NewUnit in 'D:\lib\NewUnit.pas';"),
+            "{updated}"
+        );
+        let warning = warnings.iter().next().expect("cross drive warning");
+        assert_eq!(warning.code, DiagnosticCode::CrossDrivePath);
+        assert!(
+            warning.message.ends_with("wrote an absolute path"),
+            "{}",
+            warning.message
+        );
+
+        let mut warnings = Warnings::new();
+        let (result, updated) = insert(CrossDrive::Skip, &mut warnings);
+        assert!(!result.unwrap());
+        assert_eq!(updated, original);
+        assert_eq!(warnings.len(), 1);
+
+        let mut warnings = Warnings::new();
+        let (result, updated) = insert(CrossDrive::Error, &mut warnings);
+        assert!(result.is_err());
+        assert_eq!(updated, original);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn insert_new_unit_applies_deep_relative_policy() {
+        let root = temp_dir();
+        let dpr_dir = root.join("apps").join("group").join("demo");
+        fs::create_dir_all(&dpr_dir).unwrap();
+        fs::create_dir_all(root.join("lib")).unwrap();
+        let dpr_path = dpr_dir.join("Demo.dpr");
+        let unit_path = root.join("lib").join("NewUnit.pas");
+        fs::write(
+            &unit_path,
+            "unit NewUnit;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+        let original = "program Demo;\nuses Foo;\nbegin end.";
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: unit_path.clone(),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+        let insert = |max: Option<usize>, policy: DeepRelative, warnings: &mut Warnings| {
+            fs::write(&dpr_path, original).unwrap();
+            let bytes = fs::read(&dpr_path).unwrap();
+            let list = parse_dpr_uses(&dpr_path, &bytes, &Includes::default(), warnings)
+                .expect("uses list");
+            let mut writer = DprWriter::new(
+                false,
+                PathStyle::default(),
+                CrossDrive::default(),
+                None,
+                WriteRetry::default(),
+            )
+            .limit_parent_dirs(max, policy);
+            let result = insert_new_unit(
+                &bytes,
+                &dpr_path,
+                &list,
+                &new_unit,
+                None,
+                &mut writer,
+                warnings,
+            );
+            (result.unwrap(), fs::read_to_string(&dpr_path).unwrap())
+        };
+
+        let mut warnings = Warnings::new();
+        let (inserted, updated) = insert(Some(3), DeepRelative::Skip, &mut warnings);
+        assert!(inserted);
+        assert!(
+            updated.contains(r"NewUnit in '..\..\..\lib\NewUnit.pas'"),
+            "{updated}"
+        );
+        assert!(warnings.is_empty());
+
+        let mut warnings = Warnings::new();
+        let (inserted, updated) = insert(Some(1), DeepRelative::Absolute, &mut warnings);
+        assert!(inserted);
+        assert!(!updated.contains(".."), "{updated}");
+        assert!(updated.contains(r"lib\NewUnit.pas';"), "{updated}");
+        let warning = warnings.iter().next().expect("deep relative warning");
+        assert_eq!(warning.code, DiagnosticCode::DeepRelativePath);
+        assert!(
+            warning
+                .message
+                .contains("climbs 3 parent folders, more than 1"),
+            "{}",
+            warning.message
+        );
+        assert!(
+            warning.message.ends_with("wrote an absolute path"),
+            "{}",
+            warning.message
+        );
+
+        let mut warnings = Warnings::new();
+        let (inserted, updated) = insert(Some(1), DeepRelative::Skip, &mut warnings);
+        assert!(!inserted);
+        assert_eq!(updated, original);
+        assert_eq!(warnings.len(), 1);
+
+        let mut warnings = Warnings::new();
+        let (inserted, updated) = insert(Some(0), DeepRelative::Allow, &mut warnings);
+        assert!(inserted);
+        assert!(
+            updated.contains(r"NewUnit in '..\..\..\lib\NewUnit.pas'"),
+            "{updated}"
+        );
+        assert!(
+            warnings.iter().next().unwrap().message.ends_with("kept it"),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn leading_parent_dirs_counts_only_leading_components() {
+        assert_eq!(leading_parent_dirs("NewUnit.pas"), 0);
+        assert_eq!(leading_parent_dirs(r"..\..\lib\NewUnit.pas"), 2);
+        assert_eq!(leading_parent_dirs("../lib/../NewUnit.pas"), 1);
+    }
+
+    #[test]
+    fn parse_dpr_uses_semicolon_on_own_line() {
+        let src = b"program Demo;\nuses\n  Foo,\n  Bar\n;\nbegin end.";
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let mut warnings = Warnings::new();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        let names: Vec<String> = list
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        assert_eq!(names, vec!["Foo", "Bar"]);
+        assert!(list.multiline);
+        assert_eq!(list.indent, "  ");
+    }
+
+    #[test]
+    fn parse_dpr_uses_mixed_separators_prefers_existing() {
+        let src = b"program Demo;\nuses Foo in 'lib/Foo.pas', Bar in 'lib\\\\Bar.pas';\nbegin end.";
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let mut warnings = Warnings::new();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        assert!(list.has_slash);
+        assert!(list.has_backslash);
+    }
+
+    #[test]
+    fn parse_dpr_uses_supports_include_fragments() {
+        let root = temp_dir();
+        let dpr_path = root.join("Demo.dpr");
+        let include_path = root.join("Uses.inc");
+        fs::write(
+            &include_path,
+            "Foo in 'lib\\\\Foo.pas',\nBar,\nBaz in 'lib/Baz.pas',",
+        )
+        .unwrap();
+        let src = b"program Demo;\nuses\n  {$I Uses.inc}\n  Qux;\nbegin end.";
+        let mut warnings = Warnings::new();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        let names: Vec<String> = list
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        assert_eq!(names, vec!["Foo", "Bar", "Baz", "Qux"]);
+        assert!(list.has_backslash);
+        assert!(list.has_slash);
+    }
+
+    #[test]
+    fn resolve_by_name_prefers_project_cache_before_delphi_cache() {
+        let mut project_cache = UnitCache::default();
+        let project_path = PathBuf::from(r"C:\project\Foo.pas");
+        project_cache
+            .by_name
+            .insert("foo".to_string(), vec![project_path.clone()]);
+
+        let mut delphi_cache = UnitCache::default();
+        let delphi_path = PathBuf::from(r"C:\delphi\Foo.pas");
+        delphi_cache
+            .by_name
+            .insert("foo".to_string(), vec![delphi_path.clone()]);
+
+        let delphi_cache = LazyUnitCache::from_cache(delphi_cache);
+        match resolve_by_name(&project_cache, Some(&delphi_cache), &UnitName::new("Foo")) {
+            ResolveByName::Unique { path, source } => {
+                assert_eq!(path, project_path);
+                assert_eq!(source, ResolutionSource::Project);
+            }
+            _ => panic!("expected unique project resolution"),
+        }
+    }
+
+    #[test]
+    fn resolve_by_name_uses_delphi_cache_when_project_missing() {
+        let project_cache = UnitCache::default();
+        let mut delphi_cache = UnitCache::default();
+        let delphi_path = PathBuf::from(r"C:\delphi\ExtUnit.pas");
+        delphi_cache
+            .by_name
+            .insert("extunit".to_string(), vec![delphi_path.clone()]);
+
+        let delphi_cache = LazyUnitCache::from_cache(delphi_cache);
+        match resolve_by_name(
+            &project_cache,
+            Some(&delphi_cache),
+            &UnitName::new("ExtUnit"),
+        ) {
+            ResolveByName::Unique { path, source } => {
+                assert_eq!(path, delphi_path);
+                assert_eq!(source, ResolutionSource::Delphi);
+            }
+            _ => panic!("expected unique delphi resolution"),
+        }
+    }
+
+    #[test]
+    fn collect_introduced_dependencies_returns_transitive_closure_without_root() {
+        let root = temp_dir();
+        let new_path = root.join("NewUnit.pas");
+        let mid_path = root.join("MidUnit.pas");
+        let base_path = root.join("BaseUnit.pas");
+        fs::write(
+            &new_path,
+            "unit NewUnit;\ninterface\nuses MidUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &mid_path,
+            "unit MidUnit;\ninterface\nuses BaseUnit, NewUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &base_path,
+            "unit BaseUnit;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let mut project_cache =
+            unit_cache::build_unit_cache(&[new_path.clone(), mid_path, base_path], &mut warnings)
+                .unwrap();
+        let new_unit = unit_cache::load_unit_file(&new_path, &mut warnings)
+            .unwrap()
+            .expect("new unit");
+        let project_map = HashMap::new();
+        let assumptions = Assumptions::default();
+
+        let introduced = collect_introduced_dependencies(
+            &mut project_cache,
+            None,
+            &project_map,
+            &new_unit,
+            &assumptions,
+            &mut warnings,
+            &DependencyLimits::default(),
+        )
+        .unwrap();
+        let names: Vec<String> = introduced
+            .into_iter()
+            .map(|unit| unit.name.to_ascii_lowercase())
+            .collect();
+        assert_eq!(names, vec!["midunit", "baseunit"]);
+    }
+
+    #[test]
+    fn collect_introduced_dependencies_respects_assume_off() {
+        let root = temp_dir();
+        let new_path = root.join("NewUnit.pas");
+        let debug_mid_path = root.join("DebugMid.pas");
+        fs::write(
+            &new_path,
+            "unit NewUnit;\ninterface\nuses {$IFDEF DEBUG} DebugMid {$ENDIF};\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &debug_mid_path,
+            "unit DebugMid;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let mut project_cache =
+            unit_cache::build_unit_cache(&[new_path.clone(), debug_mid_path], &mut warnings)
+                .unwrap();
+        let new_unit = unit_cache::load_unit_file(&new_path, &mut warnings)
+            .unwrap()
+            .expect("new unit");
+        let project_map = HashMap::new();
+        let mut assumptions = Assumptions::default();
+        assumptions.set("DEBUG", conditionals::AssumedValue::Off);
+
+        let introduced = collect_introduced_dependencies(
+            &mut project_cache,
+            None,
+            &project_map,
+            &new_unit,
+            &assumptions,
+            &mut warnings,
+            &DependencyLimits::default(),
+        )
+        .unwrap();
+
+        assert!(introduced.is_empty(), "{introduced:?}");
+    }
+
+    #[test]
+    fn collect_introduced_dependencies_topo_order_puts_dependencies_first() {
+        let root = temp_dir();
+        let sources = [
+            ("NewUnit", "uses Alpha, Gamma, Beta;"),
+            ("Alpha", "uses Beta;"),
+            ("Beta", ""),
+            ("Gamma", ""),
+            ("Cyc1", "uses Cyc2;"),
+            ("Cyc2", "uses Cyc1;"),
+            ("CycleRoot", "uses Cyc1;"),
+        ];
+        let mut paths = Vec::new();
+        for (name, uses) in sources {
+            let path = root.join(format!("{name}.pas"));
+            fs::write(
+                &path,
+                format!("unit {name};\ninterface\n{uses}\nimplementation\nend.\n"),
+            )
+            .unwrap();
+            paths.push(path);
+        }
+
+        let mut warnings = Warnings::new();
+        let mut project_cache = unit_cache::build_unit_cache(&paths, &mut warnings).unwrap();
+        let limits = DependencyLimits {
+            topo_order: true,
+            ..DependencyLimits::default()
+        };
+        let mut introduced_names = |root_name: &str, warnings: &mut Warnings| {
+            let new_unit =
+                unit_cache::load_unit_file(&root.join(format!("{root_name}.pas")), warnings)
+                    .unwrap()
+                    .expect("new unit");
+            collect_introduced_dependencies(
+                &mut project_cache,
+                None,
+                &HashMap::new(),
+                &new_unit,
+                &Assumptions::default(),
+                warnings,
+                &limits,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|unit| unit.name)
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            introduced_names("NewUnit", &mut warnings),
+            vec!["Beta", "Alpha", "Gamma"]
+        );
+        assert!(warnings.is_empty(), "{warnings:?}");
+
+        assert_eq!(
+            introduced_names("CycleRoot", &mut warnings),
+            vec!["Cyc1", "Cyc2"]
+        );
+        let cycle_warnings: Vec<_> = warnings
+            .iter()
+            .filter(|warning| warning.code == DiagnosticCode::DependencyCycle)
+            .collect();
+        assert_eq!(cycle_warnings.len(), 1, "{warnings:?}");
+        assert!(
+            cycle_warnings[0].message.contains("Cyc1, Cyc2"),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn fix_dpr_file_adds_missing_transitive_dependencies_from_project_cache() {
+        let root = temp_dir();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let unit_b = root.join("UnitB.pas");
+        let unit_c = root.join("UnitC.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_b,
+            "unit UnitB;\ninterface\nuses UnitC;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&unit_c, "unit UnitC;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(
+            &[unit_a.clone(), unit_b.clone(), unit_c.clone()],
+            &mut warnings,
+        )
+        .unwrap();
+        let assumptions = Assumptions::default();
+
+        let first = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
+        assert_eq!(first.failures, 0, "{first:?}");
+        assert_eq!(first.updated, 1, "{first:?}");
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(updated.contains("UnitB in 'UnitB.pas'"), "{updated}");
+        assert!(updated.contains("UnitC in 'UnitC.pas'"), "{updated}");
+
+        let second = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
+        assert_eq!(second.failures, 0, "{second:?}");
+        assert_eq!(second.updated, 0, "{second:?}");
+    }
+
+    #[test]
+    fn fix_dpr_file_inserts_units_with_cjk_names() {
+        let root = temp_dir();
+        let dpr_path = root.join("App.dpr");
+        let main_form = root.join("主窗体.pas");
+        let data = root.join("数据模块.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  主窗体 in '主窗体.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &main_form,
+            "unit 主窗体;\ninterface\nuses 数据模块;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&data, "unit 数据模块;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[main_form.clone(), data.clone()], &mut warnings)
+            .unwrap();
+        assert!(cache.by_path.values().all(|info| !info.name_from_stem));
+
+        let summary = fix_dpr_file(&dpr_path, &cache, None, &Assumptions::default()).unwrap();
+        assert_eq!(summary.updated, 1, "{summary:?}");
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(
+            updated.contains("主窗体 in '主窗体.pas',\n  数据模块 in '数据模块.pas';"),
+            "{updated}"
+        );
+    }
+
+    #[test]
+    fn fix_dpr_file_skips_dependencies_not_in_project_cache() {
+        let root = temp_dir();
+        let external = root.join("external");
+        fs::create_dir_all(&external).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let ext_unit = external.join("ExtUnit.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses ExtUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &ext_unit,
+            "unit ExtUnit;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache =
+            unit_cache::build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings).unwrap();
+        let assumptions = Assumptions::default();
+
+        let result = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
+        assert_eq!(result.failures, 0, "{result:?}");
+        assert_eq!(result.updated, 0, "{result:?}");
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(!updated.contains("ExtUnit in "), "{updated}");
+    }
+
+    #[test]
+    fn fix_dpr_file_uses_delphi_fallback_cache_when_provided() {
+        let root = temp_dir();
+        let external = root.join("delphi");
+        fs::create_dir_all(&external).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let ext_mid = external.join("ExtMid.pas");
+        let new_unit = external.join("NewUnit.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses ExtMid;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &ext_mid,
+            "unit ExtMid;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &new_unit,
+            "unit NewUnit;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let project_cache =
+            unit_cache::build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings).unwrap();
+        let delphi_cache =
+            unit_cache::build_unit_cache(&[ext_mid, new_unit], &mut warnings).unwrap();
+        let delphi_cache = LazyUnitCache::from_cache(delphi_cache);
+        let assumptions = Assumptions::default();
+
+        let result =
+            fix_dpr_file(&dpr_path, &project_cache, Some(&delphi_cache), &assumptions).unwrap();
+        assert_eq!(result.failures, 0, "{result:?}");
+        assert_eq!(result.updated, 1, "{result:?}");
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(updated.contains("ExtMid in "), "{updated}");
+        assert!(updated.contains("NewUnit in "), "{updated}");
+    }
+
+    #[test]
+    fn fix_dpr_file_records_which_cache_each_insert_came_from() {
+        let root = temp_dir();
+        let external = root.join("delphi");
+        fs::create_dir_all(&external).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let own_unit = root.join("OwnUnit.pas");
+        let ext_mid = external.join("ExtMid.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses ExtMid;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &ext_mid,
+            "unit ExtMid;\ninterface\nuses OwnUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &own_unit,
+            "unit OwnUnit;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let project_cache =
+            unit_cache::build_unit_cache(&[unit_a, own_unit], &mut warnings).unwrap();
+        let delphi_cache = LazyUnitCache::from_cache(
+            unit_cache::build_unit_cache(&[ext_mid], &mut warnings).unwrap(),
+        );
+
+        let result = fix_dpr_file(
+            &dpr_path,
+            &project_cache,
+            Some(&delphi_cache),
+            &Assumptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.updated, 1, "{result:?}");
+        let sources: Vec<(&str, Option<ResolutionSource>)> = result.details[0]
+            .inserted
+            .iter()
+            .map(|unit| (unit.name.as_str(), unit.source))
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                ("ExtMid", Some(ResolutionSource::Delphi)),
+                ("OwnUnit", Some(ResolutionSource::Project)),
+            ]
+        );
+        assert_eq!(result.inserted_from(ResolutionSource::Project), 1);
+        assert_eq!(result.inserted_from(ResolutionSource::Delphi), 1);
+    }
+
+    #[test]
+    fn fix_dpr_file_max_inserts_stops_early_and_counts_the_rest() {
+        let root = temp_dir();
+        let dpr_path = root.join("App.dpr");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        let mut unit_paths = vec![root.join("UnitA.pas")];
+        fs::write(
+            &unit_paths[0],
+            "unit UnitA;\ninterface\nuses UnitB, UnitC, UnitD;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        for name in ["UnitB", "UnitC", "UnitD"] {
+            let path = root.join(format!("{name}.pas"));
+            fs::write(
+                &path,
+                format!("unit {name};\ninterface\nimplementation\nend.\n"),
+            )
+            .unwrap();
+            unit_paths.push(path);
+        }
+
+        let mut warnings = Warnings::new();
+        let project_cache = unit_cache::build_unit_cache(&unit_paths, &mut warnings).unwrap();
+        let options = FixOptions::builder().max_inserts(Some(2)).build();
+
+        let result = fix_dpr_file_with(
+            &dpr_path,
+            &project_cache,
+            None,
+            &Assumptions::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(result.updated, 1, "{result:?}");
+        let detail = &result.details[0];
+        assert_eq!(detail.status, DprStatus::Partial);
+        assert_eq!(detail.inserted.len(), 2);
+        assert_eq!(detail.not_inserted, 1);
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(updated.contains("UnitB in 'UnitB.pas'"), "{updated}");
+        assert!(updated.contains("UnitC in 'UnitC.pas'"), "{updated}");
+        assert!(!updated.contains("UnitD"), "{updated}");
+    }
+
+    #[test]
+    fn fix_dpr_file_warns_when_an_inserted_unit_is_outside_the_known_roots() {
+        let root = unit_cache::canonicalize_if_exists(&temp_dir());
+        let app = root.join("app");
+        let odd = root.join("odd");
+        fs::create_dir_all(&app).unwrap();
+        fs::create_dir_all(&odd).unwrap();
+        let dpr_path = app.join("App.dpr");
+        let unit_a = app.join("UnitA.pas");
+        let unit_b = odd.join("UnitB.pas");
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&unit_b, "unit UnitB;\ninterface\nimplementation\nend.\n").unwrap();
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a, unit_b], &mut warnings).unwrap();
+        let run = |roots: Vec<PathBuf>| {
+            fs::write(
+                &dpr_path,
+                "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+            )
+            .unwrap();
+            let options = FixOptions::builder()
+                .edit(EditOptions::builder().known_roots(roots))
+                .build();
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap()
+        };
+
+        let result = run(vec![app.clone()]);
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(
+            updated.contains("UnitB in '..\\odd\\UnitB.pas'"),
+            "{updated}"
+        );
+        let outside: Vec<_> = result
+            .warnings
+            .iter()
+            .filter(|warning| warning.code == DiagnosticCode::OutsideSearchRoots)
+            .collect();
+        assert_eq!(outside.len(), 1, "{:?}", result.warnings);
+        assert!(
+            outside[0].message.contains("UnitB"),
+            "{}",
+            outside[0].message
+        );
+        assert!(
+            outside[0].message.contains(&app.display().to_string()),
+            "{}",
+            outside[0].message
+        );
+
+        let result = run(vec![app.clone(), odd.clone()]);
+        assert!(
+            result
+                .warnings
+                .iter()
+                .all(|warning| warning.code != DiagnosticCode::OutsideSearchRoots),
+            "{:?}",
+            result.warnings
+        );
+    }
+
+    #[test]
+    fn fix_dpr_file_reports_and_removes_entries_of_deleted_units() {
+        let root = temp_dir();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        let original = "program App;\nuses\n  Gone in 'old\\Gone.pas',\n  UnitA in 'UnitA.pas',\n  Moved in 'old\\UnitA.pas',\n  Kept in 'Kept.pas'; // fixdpr:pin\nbegin\nend.\n";
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a], &mut warnings).unwrap();
+        let run = |remove: bool| {
+            fs::write(&dpr_path, original).unwrap();
+            let options = FixOptions::builder().remove_missing(remove).build();
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap()
+        };
+
+        let result = run(false);
+        assert_eq!(result.updated, 0, "{result:?}");
+        let entries: Vec<(&str, bool)> = result.details[0]
+            .missing_entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.removed))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![("Gone", false), ("Moved", false), ("Kept", false)]
+        );
+        assert!(
+            !result
+                .warnings
+                .iter()
+                .any(|warning| warning.code == DiagnosticCode::BrokenUsesPath),
+            "{result:?}"
+        );
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), original);
+
+        let result = run(true);
+        assert_eq!(result.updated, 1, "{result:?}");
+        let entries: Vec<(&str, bool)> = result.details[0]
+            .missing_entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.removed))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![("Gone", true), ("Moved", true), ("Kept", false)]
+        );
+        assert_eq!(
+            fs::read_to_string(&dpr_path).unwrap(),
+            "program App;\nuses\n  UnitA in 'UnitA.pas',\n  Kept in 'Kept.pas'; // fixdpr:pin\nbegin\nend.\n"
+        );
+    }
+
+    #[test]
+    fn fix_dpr_file_no_delphi_inserts_follows_but_skips_delphi_units() {
+        let root = temp_dir();
+        let external = root.join("delphi");
+        fs::create_dir_all(&external).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let own_unit = root.join("OwnUnit.pas");
+        let ext_mid = external.join("ExtMid.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses ExtMid;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &ext_mid,
+            "unit ExtMid;\ninterface\nuses OwnUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &own_unit,
+            "unit OwnUnit;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let project_cache =
+            unit_cache::build_unit_cache(&[unit_a, own_unit], &mut warnings).unwrap();
+        let delphi_cache = LazyUnitCache::from_cache(
+            unit_cache::build_unit_cache(&[ext_mid], &mut warnings).unwrap(),
+        );
+        let options = FixOptions::builder().no_delphi_inserts(true).build();
+
+        let result = fix_dpr_file_with(
+            &dpr_path,
+            &project_cache,
+            Some(&delphi_cache),
+            &Assumptions::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(result.updated, 1, "{result:?}");
+        assert_eq!(result.suppressed_delphi_inserts, 1);
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(updated.contains("OwnUnit in 'OwnUnit.pas'"), "{updated}");
+        assert!(!updated.contains("ExtMid"), "{updated}");
+    }
+
+    #[test]
+    fn fix_dpr_file_skips_unit_whose_scoped_variant_is_listed() {
+        let root = temp_dir();
+        let external = root.join("delphi");
+        fs::create_dir_all(&external).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let dialogs = external.join("Dialogs.pas");
+        let original = "program App;\nuses\n  Vcl.Dialogs,\n  UnitA in 'UnitA.pas';\nbegin\nend.\n";
+        fs::write(&dpr_path, original).unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses Dialogs;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&dialogs, "unit Dialogs;\ninterface\nimplementation\nend.\n").unwrap();
 
-    uses_include::with_include_bytes(
-        include_name,
-        source_path,
-        warnings,
-        include_stack,
-        |include_path, bytes, warnings, include_stack| {
-            let mut entries = Vec::new();
-            let mut nested_state = DprParseState {
-                warnings,
-                include_stack,
-                has_backslash,
-                has_slash,
-                include_semicolon,
-            };
-            let _ = parse_uses_fragment_for_dpr(
-                bytes,
-                0,
-                include_path,
-                &mut entries,
-                &mut nested_state,
-                Some(anchor),
-            );
-            entries
-        },
-    )
-    .unwrap_or_default()
-}
+        let mut warnings = Warnings::new();
+        let project_cache =
+            unit_cache::build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings).unwrap();
+        let delphi_cache = LazyUnitCache::from_cache(
+            unit_cache::build_unit_cache(&[dialogs], &mut warnings).unwrap(),
+        );
+        let fix = |options: &FixOptions| {
+            fix_dpr_file_with(
+                &dpr_path,
+                &project_cache,
+                Some(&delphi_cache),
+                &Assumptions::default(),
+                options,
+            )
+            .unwrap()
+        };
 
-fn peek_ident(bytes: &[u8], i: usize) -> Option<(String, usize)> {
-    if i < bytes.len() && pas_lex::is_ident_start(bytes[i]) {
-        let (token, next) = pas_lex::read_ident(bytes, i);
-        return Some((token, next));
+        let result = fix(&FixOptions::default());
+        assert_eq!(result.updated, 0, "{result:?}");
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), original);
+        assert!(
+            result.infos.iter().any(
+                |info| info.contains("did not insert Dialogs") && info.ends_with("Vcl.Dialogs")
+            ),
+            "{:?}",
+            result.infos
+        );
+
+        let result = fix(&FixOptions::builder()
+            .edit(EditOptions::builder().namespace_prefixes(Some(vec!["Fmx".to_string()]))));
+        assert_eq!(result.updated, 1, "{result:?}");
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(updated.contains("Dialogs in 'delphi"), "{updated}");
     }
-    None
-}
 
-fn update_path_separator_flags(
-    in_path: &Option<String>,
-    has_backslash: &mut bool,
-    has_slash: &mut bool,
-) {
-    let Some(path) = in_path.as_ref() else {
-        return;
-    };
-    if path.contains('\\') {
-        *has_backslash = true;
+    #[test]
+    fn fix_dpr_file_never_inserts_known_units() {
+        let root = temp_dir();
+        let external = root.join("delphi");
+        fs::create_dir_all(&external).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let system = external.join("System.pas");
+        let ext_mid = external.join("ExtMid.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses System, ExtMid, DcuOnly;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&system, "unit System;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(&ext_mid, "unit ExtMid;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let mut project_cache =
+            unit_cache::build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings).unwrap();
+        project_cache.known_units.extend_from_list("ExtMid\n");
+        let delphi_cache = unit_cache::build_unit_cache(&[system, ext_mid], &mut warnings).unwrap();
+        let delphi_cache = LazyUnitCache::from_cache(delphi_cache);
+
+        let result = fix_dpr_file(
+            &dpr_path,
+            &project_cache,
+            Some(&delphi_cache),
+            &Assumptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.updated, 0, "{result:?}");
+        assert!(result.warnings.is_empty(), "{result:?}");
+        let contents = fs::read_to_string(&dpr_path).unwrap();
+        assert!(!contents.contains("System in "), "{contents}");
+        assert!(!contents.contains("ExtMid in "), "{contents}");
     }
-    if path.contains('/') {
-        *has_slash = true;
+
+    #[test]
+    fn fix_dpr_file_materializes_delphi_cache_only_on_project_miss() {
+        let root = temp_dir();
+        let external = root.join("delphi");
+        fs::create_dir_all(&external).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let unit_b = root.join("UnitB.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&unit_b, "unit UnitB;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(
+            external.join("ExtMid.pas"),
+            "unit ExtMid;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            external.join("Unused.pas"),
+            "unit Unused;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let project_cache =
+            unit_cache::build_unit_cache(&[unit_a.clone(), unit_b], &mut warnings).unwrap();
+        let delphi_cache = LazyUnitCache::new(vec![fs::canonicalize(&external).unwrap()]);
+        let assumptions = Assumptions::default();
+
+        let result =
+            fix_dpr_file(&dpr_path, &project_cache, Some(&delphi_cache), &assumptions).unwrap();
+        assert_eq!(result.updated, 1, "{result:?}");
+        assert!(!delphi_cache.is_materialized());
+
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses ExtMid;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        let project_cache =
+            unit_cache::build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings).unwrap();
+        let result =
+            fix_dpr_file(&dpr_path, &project_cache, Some(&delphi_cache), &assumptions).unwrap();
+        assert_eq!(result.updated, 1, "{result:?}");
+        assert!(delphi_cache.is_materialized());
+        assert_eq!(delphi_cache.indexed_count(), 2);
+        assert_eq!(delphi_cache.parsed_count(), 1);
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(updated.contains("ExtMid in "), "{updated}");
     }
-}
 
-fn skip_ws_and_comments_no_strings(bytes: &[u8], mut i: usize) -> usize {
-    while i < bytes.len() {
-        match bytes[i] {
-            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
-            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
-            b'(' if bytes.get(i + 1) == Some(&b'*') => {
-                i = pas_lex::skip_paren_comment(bytes, i + 2)
-            }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            _ => break,
-        }
+    #[test]
+    fn fix_dpr_file_skips_inactive_conditional_roots_when_assumed_off() {
+        let root = temp_dir();
+        let dpr_path = root.join("App.dpr");
+        let debug_root = root.join("DebugRoot.pas");
+        let new_unit = root.join("NewUnit.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  {$IFDEF DEBUG} DebugRoot in 'DebugRoot.pas' {$ENDIF};\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &debug_root,
+            "unit DebugRoot;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &new_unit,
+            "unit NewUnit;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache =
+            unit_cache::build_unit_cache(&[debug_root.clone(), new_unit], &mut warnings).unwrap();
+        let mut assumptions = Assumptions::default();
+        assumptions.set("DEBUG", conditionals::AssumedValue::Off);
+
+        let result = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
+        assert_eq!(result.failures, 0, "{result:?}");
+        assert_eq!(result.updated, 0, "{result:?}");
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(!updated.contains("NewUnit in "), "{updated}");
     }
-    i
-}
 
-fn infer_indent(bytes: &[u8], entry_start: usize) -> String {
-    let line_start = bytes[..entry_start]
-        .iter()
-        .rposition(|&b| b == b'\n')
-        .map(|pos| pos + 1)
-        .unwrap_or(0);
-    let indent_bytes = &bytes[line_start..entry_start];
-    let indent = indent_bytes
-        .iter()
-        .take_while(|&&b| b == b' ' || b == b'\t')
-        .copied()
-        .collect::<Vec<_>>();
-    String::from_utf8_lossy(&indent).to_string()
-}
+    #[test]
+    fn fix_dpr_file_interface_sections_ignore_implementation_uses() {
+        let root = temp_dir();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let unit_b = root.join("UnitB.pas");
+        let unit_c = root.join("UnitC.pas");
+        fs::write(
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nuses UnitC;\nend.\n",
+        )
+        .unwrap();
+        fs::write(&unit_b, "unit UnitB;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(&unit_c, "unit UnitC;\ninterface\nimplementation\nend.\n").unwrap();
 
-fn detect_line_ending(bytes: &[u8]) -> &'static str {
-    if bytes.windows(2).any(|pair| pair == b"\r\n") {
-        "\r\n"
-    } else {
-        "\n"
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(
+            &[unit_a.clone(), unit_b.clone(), unit_c.clone()],
+            &mut warnings,
+        )
+        .unwrap();
+        let mut assumptions = Assumptions::default();
+        assumptions.set_uses_sections(unit_cache::UsesSections::Interface);
+
+        let result = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
+        assert_eq!(result.failures, 0, "{result:?}");
+        assert_eq!(result.updated, 1, "{result:?}");
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(updated.contains("UnitB in 'UnitB.pas'"), "{updated}");
+        assert!(!updated.contains("UnitC in "), "{updated}");
     }
-}
 
-fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
-    let temp_path = path.with_extension("tmp");
-    fs::write(&temp_path, contents)?;
-    match fs::rename(&temp_path, path) {
-        Ok(()) => Ok(()),
-        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
-            fs::remove_file(path)?;
-            fs::rename(temp_path, path)
-        }
-        Err(err) => Err(err),
+    #[test]
+    fn fix_dpr_file_reports_missing_in_path_with_code_and_offset() {
+        let root = temp_dir();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        fs::write(&dpr_path, "program App;\nuses\n  UnitA;\nbegin\nend.\n").unwrap();
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a], &mut warnings).unwrap();
+        let result = fix_dpr_file(&dpr_path, &cache, None, &Assumptions::default()).unwrap();
+
+        let diagnostic = result
+            .warnings
+            .iter()
+            .find(|diagnostic| diagnostic.code == DiagnosticCode::MissingInPath)
+            .expect("missing in-path diagnostic");
+        assert_eq!(diagnostic.path.as_deref(), Some(dpr_path.as_path()));
+        assert_eq!(diagnostic.offset, Some(20));
+        assert!(
+            diagnostic
+                .to_string()
+                .starts_with("warning: missing in-path for unit UnitA in "),
+            "{diagnostic}"
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::env;
-    use std::time::{SystemTime, UNIX_EPOCH};
+    #[test]
+    fn fix_dpr_file_rewrites_in_path_pointing_at_another_unit() {
+        let root = temp_dir();
+        fs::create_dir_all(root.join("libs")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("src").join("UnitA.pas");
+        let unit_b = root.join("libs").join("UnitB.pas");
+        let original = "program App;\nuses\n  UnitA in 'libs/UnitB.pas',\n  UnitB in 'libs/UnitB.pas';\nbegin\nend.\n";
+        fs::write(&dpr_path, original).unwrap();
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(&unit_b, "unit UnitB;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a, unit_b], &mut warnings).unwrap();
+        let result = fix_dpr_file(&dpr_path, &cache, None, &Assumptions::default()).unwrap();
+        let diagnostic = result
+            .warnings
+            .iter()
+            .find(|diagnostic| diagnostic.code == DiagnosticCode::MismatchedUnitPath)
+            .expect("mismatched path diagnostic");
+        assert_eq!(diagnostic.offset, Some(20));
+        assert!(
+            diagnostic.message.contains("which declares unit UnitB"),
+            "{diagnostic}"
+        );
+        assert_eq!(result.updated, 0, "{result:?}");
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), original);
+
+        let options = FixOptions::builder().fix_mismatched_paths(true).build();
+        let result =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+        assert_eq!(result.updated, 1, "{result:?}");
+        assert_eq!(result.warnings.len(), 1, "{result:?}");
+        assert_eq!(
+            fs::read_to_string(&dpr_path).unwrap(),
+            "program App;\nuses\n  UnitA in 'src/UnitA.pas',\n  UnitB in 'libs/UnitB.pas';\nbegin\nend.\n"
+        );
+    }
 
     #[test]
-    fn parse_dpr_uses_single_line() {
-        let src = b"program Demo;\nuses Foo, Bar;\nbegin end.";
+    fn fix_dpr_file_keeps_mismatched_entry_when_its_unit_is_unknown() {
         let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, src, &mut warnings).expect("uses list");
-        assert_eq!(list.entries.len(), 2);
-        assert_eq!(list.entries[0].name, "Foo");
-        assert_eq!(list.entries[1].name, "Bar");
-        assert!(list.entries[0].in_path.is_none());
-        assert!(list.entries[1].in_path.is_none());
-        assert!(!list.multiline);
-        assert!(list.indent.is_empty());
+        let dpr_path = root.join("App.dpr");
+        let unit_b = root.join("UnitB.pas");
+        let original = "program App;\nuses\n  UnitX in 'UnitB.pas';\nbegin\nend.\n";
+        fs::write(&dpr_path, original).unwrap();
+        fs::write(&unit_b, "unit UnitB;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_b], &mut warnings).unwrap();
+        let options = FixOptions::builder().fix_mismatched_paths(true).build();
+        let result =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+
+        let codes: Vec<_> = result.warnings.iter().map(|warning| warning.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::MismatchedUnitPath]);
+        assert_eq!(result.updated, 0, "{result:?}");
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), original);
     }
 
     #[test]
-    fn parse_dpr_uses_multiline_with_indent_and_paths() {
-        let src = b"program Demo;\nuses\n  Foo,\n  Bar in 'lib\\Bar.pas',\n  Baz;\nbegin end.";
+    fn entry_removal_ranges_cut_whole_lines_and_trailing_entries() {
         let root = temp_dir();
         let dpr_path = root.join("Demo.dpr");
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, src, &mut warnings).expect("uses list");
-        assert_eq!(list.entries.len(), 3);
-        assert!(list.multiline);
-        assert_eq!(list.indent, "  ");
-        assert!(
-            list.has_backslash,
-            "expected backslash path detection, list={list:?}"
+        let remove = |src: &[u8], removed: &[usize]| {
+            let list = parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut Warnings::new())
+                .expect("uses list");
+            let removed = removed.iter().copied().collect();
+            entry_removal_ranges(src, &list, &removed).map(|ranges| {
+                let mut output = src.to_vec();
+                for (start, end) in ranges.into_iter().rev() {
+                    output.drain(start..end);
+                }
+                String::from_utf8(output).unwrap()
+            })
+        };
+        assert_eq!(
+            remove(b"uses\r\n  A,\r\n  B,\r\n  C;\r\n", &[1]).as_deref(),
+            Some("uses\r\n  A,\r\n  C;\r\n")
+        );
+        assert_eq!(
+            remove(b"uses A, B, C;", &[1, 2]).as_deref(),
+            Some("uses A;")
+        );
+        assert_eq!(remove(b"uses A, B;", &[0, 1]), None);
+        assert_eq!(remove(b"uses A, B {x}, C;", &[1]), None);
+        assert_eq!(
+            remove(b"uses\n  A,\n  B,\n  C,\n;", &[2]).as_deref(),
+            Some("uses\n  A,\n  B\n;")
+        );
+        assert_eq!(
+            remove(b"uses\n  A,\n  B,\n  C,\n;", &[1]).as_deref(),
+            Some("uses\n  A,\n  C\n;")
+        );
+        assert_eq!(
+            remove(b"uses A, B, C,;", &[1, 2]).as_deref(),
+            Some("uses A;")
+        );
+        assert_eq!(
+            remove(b"uses\n  A, // first\n  B,\n  { third }\n  C,\n;", &[1]).as_deref(),
+            Some("uses\n  A, // first\n  { third }\n  C\n;")
         );
-        assert!(!list.has_slash);
-        assert_eq!(list.entries[1].in_path.as_deref(), Some("lib\\Bar.pas"));
     }
 
     #[test]
-    fn parse_dpr_uses_ignores_comments_and_directives() {
-        let src = br#"
-program Demo;
-uses Foo, {Bar}, (*Baz*), {$IFDEF X} Qux, {$ENDIF} RealUnit;
-begin end.
-"#;
+    fn build_project_map_reports_both_duplicate_paths_and_the_one_used() {
         let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, src, &mut warnings).expect("uses list");
-        let names: Vec<String> = list
-            .entries
+        fs::create_dir_all(root.join("lib")).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("lib").join("UnitA.pas");
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        let src = b"program App;\nuses\n  UnitA in 'old\\UnitA.pas',\n  UnitA in 'lib\\UnitA.pas';\nbegin\nend.\n";
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a], &mut warnings).unwrap();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        build_project_map(&dpr_path, &list, &cache, None, &mut warnings);
+        let duplicate = warnings
             .iter()
-            .map(|entry| entry.name.clone())
-            .collect();
-        assert_eq!(names, vec!["Foo", "Qux", "RealUnit"]);
-        assert!(list.entries.iter().all(|entry| entry.in_path.is_none()));
+            .find(|warning| warning.code == DiagnosticCode::DuplicateUnit)
+            .expect("duplicate diagnostic");
+        assert!(
+            duplicate
+                .message
+                .contains("with paths 'old\\UnitA.pas' and 'lib\\UnitA.pas'; using "),
+            "{duplicate}"
+        );
+        assert!(duplicate.message.ends_with("UnitA.pas"), "{duplicate}");
     }
 
     #[test]
-    fn insert_new_unit_single_line() {
+    fn fix_dpr_file_duplicate_policies_pick_the_kept_entry() {
         let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let pas_path = root.join("NewUnit.pas");
-        fs::write(&dpr_path, "program Demo;\nuses Foo, Bar;\nbegin end.").unwrap();
-        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
-
-        let bytes = fs::read(&dpr_path).unwrap();
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, &bytes, &mut warnings).expect("uses list");
-        let new_unit = UnitFileInfo {
-            name: "NewUnit".to_string(),
-            path: pas_path.clone(),
-            uses: Vec::new(),
-            conditional_uses: Vec::new(),
+        fs::create_dir_all(root.join("lib")).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("lib").join("UnitA.pas");
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        let original = "program App;\nuses\n  UnitA in 'old/UnitA.pas',\n  UnitA in 'lib/UnitA.pas';\nbegin\nend.\n";
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a], &mut warnings).unwrap();
+        let run = |policy| {
+            fs::write(&dpr_path, original).unwrap();
+            let options = FixOptions::builder().fix_duplicates(Some(policy)).build();
+            let result =
+                fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options)
+                    .unwrap();
+            (result.updated, fs::read_to_string(&dpr_path).unwrap())
         };
-        insert_new_unit(&bytes, &dpr_path, &list, &new_unit, None).unwrap();
 
-        let updated = fs::read_to_string(&dpr_path).unwrap();
-        assert!(
-            updated.contains("uses Foo, Bar, NewUnit in 'NewUnit.pas';"),
-            "{updated}"
+        assert_eq!(
+            run(DuplicatePolicy::KeepFirst),
+            (
+                1,
+                "program App;\nuses\n  UnitA in 'old/UnitA.pas';\nbegin\nend.\n".to_string()
+            )
         );
+        assert_eq!(
+            run(DuplicatePolicy::KeepResolving),
+            (
+                1,
+                "program App;\nuses\n  UnitA in 'lib/UnitA.pas';\nbegin\nend.\n".to_string()
+            )
+        );
+        assert_eq!(run(DuplicatePolicy::RemoveExtra), (0, original.to_string()));
     }
 
     #[test]
-    fn insert_new_unit_multiline_keeps_indent_and_separator() {
+    fn fix_dpr_file_keeps_duplicates_under_conditionals() {
         let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let pas_dir = root.join("sub");
-        fs::create_dir_all(&pas_dir).unwrap();
-        let pas_path = pas_dir.join("NewUnit.pas");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("a").join("UnitA.pas");
+        let unit_b = root.join("b").join("UnitA.pas");
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(&unit_b, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        let original = "program App;\nuses\n  {$IFDEF A} UnitA in 'a/UnitA.pas', {$ELSE} UnitA in 'b/UnitA.pas', {$ENDIF}\n  Other;\nbegin\nend.\n";
+        fs::write(&dpr_path, original).unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a, unit_b], &mut warnings).unwrap();
+        let options = FixOptions::builder()
+            .fix_duplicates(Some(DuplicatePolicy::KeepFirst))
+            .build();
+        let result =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+        assert_eq!(result.updated, 0, "{result:?}");
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), original);
+    }
+
+    fn write_fix_chain_fixture(root: &Path) -> (PathBuf, UnitCache) {
+        let sub = root.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        let dpr_path = root.join("App.dpr");
         fs::write(
             &dpr_path,
-            "program Demo;\r\nuses\r\n  Foo,\r\n  Bar in 'lib/Bar.pas',\r\n  Baz;\r\nbegin end.",
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
         )
         .unwrap();
-        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+        let unit_a = root.join("UnitA.pas");
+        let unit_b = sub.join("UnitB.pas");
+        let unit_c = sub.join("UnitC.pas");
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_b,
+            "unit UnitB;\ninterface\nuses UnitC;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&unit_c, "unit UnitC;\ninterface\nimplementation\nend.\n").unwrap();
 
-        let bytes = fs::read(&dpr_path).unwrap();
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, &bytes, &mut warnings).expect("uses list");
-        let new_unit = UnitFileInfo {
-            name: "NewUnit".to_string(),
-            path: pas_path.clone(),
-            uses: Vec::new(),
-            conditional_uses: Vec::new(),
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a, unit_b, unit_c], &mut warnings).unwrap();
+        (dpr_path, cache)
+    }
+
+    fn inserted_names(summary: &DprUpdateSummary) -> Vec<String> {
+        summary.details[0]
+            .inserted
+            .iter()
+            .map(|unit| unit.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn fix_dpr_file_with_dry_run_reports_edits_without_writing() {
+        let root = temp_dir();
+        let (dpr_path, cache) = write_fix_chain_fixture(&root);
+        let before = fs::read_to_string(&dpr_path).unwrap();
+
+        let options = FixOptions::builder()
+            .edit(EditOptions::builder().dry_run(true))
+            .build();
+        let summary =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+
+        assert_eq!(summary.updated, 1);
+        assert_eq!(inserted_names(&summary), vec!["UnitB", "UnitC"]);
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), before);
+    }
+
+    #[test]
+    fn edit_options_outcome_key_changes_with_every_edit_option() {
+        let keys = [
+            EditOptions::builder(),
+            EditOptions::builder().insert_position(InsertPosition::End),
+            EditOptions::builder().path_style(PathStyle::Slash),
+            EditOptions::builder().cross_drive(CrossDrive::Skip),
+            EditOptions::builder().max_parent_dirs(Some(1), DeepRelative::Absolute),
+            EditOptions::builder().max_parent_dirs(None, DeepRelative::Skip),
+            EditOptions::builder().namespace_prefixes(Some(vec!["Vcl".to_string()])),
+            EditOptions::builder().namespace_prefixes(Some(Vec::new())),
+            EditOptions::builder().indent(Some("\t".to_string())),
+            EditOptions::builder().wrap_at(Some(80)),
+            EditOptions::builder().insert_in_conditional(true),
+            EditOptions::builder().known_roots(vec![PathBuf::from("lib")]),
+            EditOptions::builder().max_file_size(16),
+        ]
+        .map(|options| options.outcome_key());
+        let distinct: HashSet<&String> = keys.iter().collect();
+        assert_eq!(distinct.len(), keys.len(), "{keys:#?}");
+
+        let unrelated = EditOptions::builder()
+            .dry_run(true)
+            .lock_timeout(Duration::from_secs(1))
+            .write_retry(WriteRetry {
+                attempts: 1,
+                backoff: Duration::ZERO,
+            })
+            .progress(|_, _, _| {})
+            .events(Rc::new(crate::events::NoEvents));
+        assert_eq!(unrelated.outcome_key(), keys[0]);
+    }
+
+    #[test]
+    fn update_options_outcome_key_changes_with_every_edit_option() {
+        let keys = [
+            UpdateOptions::builder(),
+            UpdateOptions::builder().edit(EditOptions::builder().wrap_at(Some(80))),
+            UpdateOptions::builder().direct_only(true),
+            UpdateOptions::builder().retarget_existing(true),
+            UpdateOptions::builder().after_unit(Some("Forms".to_string())),
+            UpdateOptions::builder().exclude_unit("Helper"),
+            UpdateOptions::builder().max_depth(Some(2)),
+            UpdateOptions::builder().topo_order(true),
+            UpdateOptions::builder().introduced_scope(IntroducedScope::ProjectOnly),
+        ]
+        .map(|options| options.outcome_key());
+        let distinct: HashSet<&String> = keys.iter().collect();
+        assert_eq!(distinct.len(), keys.len(), "{keys:#?}");
+
+        let excluded = |names: &[&str]| {
+            names
+                .iter()
+                .fold(UpdateOptions::builder(), |options, name| {
+                    options.exclude_unit(name)
+                })
+                .outcome_key()
         };
-        insert_new_unit(&bytes, &dpr_path, &list, &new_unit, None).unwrap();
+        assert_eq!(excluded(&["A", "B", "C"]), excluded(&["C", "B", "A"]));
+    }
 
-        let updated = fs::read_to_string(&dpr_path).unwrap();
-        assert!(
-            updated.contains("Baz,\r\n  NewUnit in 'sub/NewUnit.pas';"),
-            "{updated}"
-        );
+    #[test]
+    fn fix_options_outcome_key_changes_with_every_edit_option() {
+        let keys = [
+            FixOptions::builder(),
+            FixOptions::builder().edit(EditOptions::builder().wrap_at(Some(100))),
+            FixOptions::builder().fix_mismatched_paths(true),
+            FixOptions::builder().fix_duplicates(Some(DuplicatePolicy::KeepFirst)),
+            FixOptions::builder().report_unresolved(true),
+            FixOptions::builder().remove_missing(true),
+            FixOptions::builder().max_inserts(Some(3)),
+            FixOptions::builder().exclude_unit("Helper"),
+            FixOptions::builder().max_depth(Some(1)),
+            FixOptions::builder().no_delphi_inserts(true),
+        ]
+        .map(|options| options.outcome_key());
+        let distinct: HashSet<&String> = keys.iter().collect();
+        assert_eq!(distinct.len(), keys.len(), "{keys:#?}");
+
+        let staged = HashMap::from([(PathBuf::from("App.dpr"), b"program App;".to_vec())]);
+        let unrelated = FixOptions::builder()
+            .edit(EditOptions::builder().dry_run(true))
+            .staged(staged);
+        assert_eq!(unrelated.outcome_key(), keys[0]);
     }
 
     #[test]
-    fn insert_new_unit_after_entry_single_line() {
+    fn fix_dpr_file_with_skips_dprs_above_max_file_size() {
         let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let pas_path = root.join("NewUnit.pas");
-        fs::write(&dpr_path, "program Demo;\nuses Foo, Bar, Baz;\nbegin end.").unwrap();
-        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
+        let (dpr_path, cache) = write_fix_chain_fixture(&root);
+        let before = fs::read_to_string(&dpr_path).unwrap();
+
+        let options = FixOptions::builder()
+            .edit(EditOptions::builder().max_file_size(16))
+            .build();
+        let summary =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+
+        assert_eq!(summary.updated, 0);
+        assert!(summary
+            .warnings
+            .iter()
+            .any(|warning| warning.code == DiagnosticCode::FileTooLarge));
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), before);
+    }
 
-        let bytes = fs::read(&dpr_path).unwrap();
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, &bytes, &mut warnings).expect("uses list");
-        let insert_after = list
-            .entries
+    #[test]
+    fn fix_dpr_file_with_fails_while_another_process_holds_the_lock() {
+        let root = temp_dir();
+        let (dpr_path, cache) = write_fix_chain_fixture(&root);
+        let dpr_path = unit_cache::canonicalize_if_exists(&dpr_path);
+        let before = fs::read_to_string(&dpr_path).unwrap();
+
+        let lock = dpr_lock::acquire(&dpr_path, Duration::ZERO)
+            .unwrap()
+            .expect("free lock");
+        let options = FixOptions::builder()
+            .edit(EditOptions::builder().lock_timeout(Duration::ZERO))
+            .build();
+        let summary =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+        assert_eq!(summary.failures, 1);
+        assert!(summary
+            .warnings
             .iter()
-            .position(|entry| entry.name == "Bar")
-            .expect("Bar entry");
-        let new_unit = UnitFileInfo {
-            name: "NewUnit".to_string(),
-            path: pas_path.clone(),
-            uses: Vec::new(),
-            conditional_uses: Vec::new(),
-        };
-        insert_new_unit(&bytes, &dpr_path, &list, &new_unit, Some(insert_after)).unwrap();
+            .any(|warning| warning.code == DiagnosticCode::DprLocked));
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), before);
+        drop(lock);
+    }
+
+    #[test]
+    fn fix_dpr_file_with_waits_for_the_lock_and_edits_the_fresh_content() {
+        let root = temp_dir();
+        let (dpr_path, cache) = write_fix_chain_fixture(&root);
+        let dpr_path = unit_cache::canonicalize_if_exists(&dpr_path);
+
+        let lock = dpr_lock::acquire(&dpr_path, Duration::ZERO)
+            .unwrap()
+            .expect("free lock");
+        let holder_path = dpr_path.clone();
+        let holder = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(150));
+            fs::write(
+                &holder_path,
+                "program App;\nuses\n  UnitA in 'UnitA.pas',\n  UnitB in 'sub\\UnitB.pas';\nbegin\nend.\n",
+            )
+            .unwrap();
+            drop(lock);
+        });
+        let options = FixOptions::builder()
+            .edit(EditOptions::builder().lock_timeout(Duration::from_secs(10)))
+            .build();
+        let summary =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+        holder.join().unwrap();
+
+        assert_eq!(summary.failures, 0);
+        assert_eq!(inserted_names(&summary), vec!["UnitC"]);
+        let after = fs::read_to_string(&dpr_path).unwrap();
+        assert!(after.contains("UnitB in 'sub\\UnitB.pas'"), "{after}");
+        assert!(after.contains("UnitC in 'sub\\UnitC.pas'"), "{after}");
+    }
+
+    #[test]
+    fn fix_dpr_file_with_times_graph_walks_writes_and_the_dpr() {
+        let root = temp_dir();
+        let (dpr_path, cache) = write_fix_chain_fixture(&root);
+
+        let dry_run = FixOptions::builder()
+            .edit(EditOptions::builder().dry_run(true))
+            .build();
+        let summary =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &dry_run).unwrap();
+        assert_eq!(summary.write_time, Duration::ZERO);
+
+        let summary = fix_dpr_file(&dpr_path, &cache, None, &Assumptions::default()).unwrap();
+        assert_eq!(summary.updated, 1);
+        assert!(summary.graph_time > Duration::ZERO);
+        assert!(summary.write_time > Duration::ZERO);
+        let elapsed = summary.details[0].elapsed;
+        assert!(elapsed >= summary.graph_time + summary.write_time);
+    }
+
+    #[test]
+    fn fix_dpr_file_with_honors_max_depth_and_excluded_units() {
+        let root = temp_dir();
+        let (dpr_path, cache) = write_fix_chain_fixture(&root);
+        let options = FixOptions::builder()
+            .edit(EditOptions::builder().dry_run(true))
+            .max_depth(Some(1))
+            .build();
+        let summary =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+        assert_eq!(inserted_names(&summary), vec!["UnitB"]);
+
+        let options = FixOptions::builder()
+            .edit(EditOptions::builder().dry_run(true))
+            .exclude_unit("unitb")
+            .build();
+        let summary =
+            fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
+        assert_eq!(inserted_names(&summary), vec!["UnitC"]);
+    }
+
+    #[test]
+    fn fix_dpr_file_with_path_style_controls_written_separator() {
+        let root = temp_dir();
+        let (dpr_path, cache) = write_fix_chain_fixture(&root);
+        let options = FixOptions::builder()
+            .edit(EditOptions::builder().path_style(PathStyle::Slash))
+            .build();
+        fix_dpr_file_with(&dpr_path, &cache, None, &Assumptions::default(), &options).unwrap();
 
         let updated = fs::read_to_string(&dpr_path).unwrap();
-        assert!(
-            updated.contains("uses Foo, Bar, NewUnit in 'NewUnit.pas', Baz;"),
-            "{updated}"
-        );
+        assert!(updated.contains("UnitB in 'sub/UnitB.pas'"), "{updated}");
+        assert!(updated.contains("UnitC in 'sub/UnitC.pas'"), "{updated}");
     }
 
     #[test]
-    fn insert_new_unit_after_entry_multiline() {
+    fn update_dpr_files_with_insert_position_end_appends_and_reports_progress() {
         let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let pas_path = root.join("NewUnit.pas");
+        let dpr_path = root.join("App.dpr");
+        let unit_a = root.join("UnitA.pas");
+        let other = root.join("Other.pas");
+        let new_path = root.join("NewUnit.pas");
         fs::write(
             &dpr_path,
-            "program Demo;\r\nuses\r\n  Foo,\r\n  Bar,\r\n  Baz;\r\nbegin end.",
+            "program App;\nuses\n  UnitA in 'UnitA.pas',\n  Other in 'Other.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &unit_a,
+            "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&other, "unit Other;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(
+            &new_path,
+            "unit NewUnit;\ninterface\nimplementation\nend.\n",
         )
         .unwrap();
-        fs::write(&pas_path, "unit NewUnit;\ninterface\nend.").unwrap();
 
-        let bytes = fs::read(&dpr_path).unwrap();
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, &bytes, &mut warnings).expect("uses list");
-        let insert_after = list
-            .entries
-            .iter()
-            .position(|entry| entry.name == "Bar")
-            .expect("Bar entry");
-        let new_unit = UnitFileInfo {
-            name: "NewUnit".to_string(),
-            path: pas_path.clone(),
-            uses: Vec::new(),
-            conditional_uses: Vec::new(),
-        };
-        insert_new_unit(&bytes, &dpr_path, &list, &new_unit, Some(insert_after)).unwrap();
+        let mut warnings = Warnings::new();
+        let mut cache =
+            unit_cache::build_unit_cache(&[unit_a, other, new_path.clone()], &mut warnings)
+                .unwrap();
+        let new_unit = unit_cache::load_unit_file(&new_path, &mut warnings)
+            .unwrap()
+            .unwrap();
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let seen = calls.clone();
+        let options = UpdateOptions::builder()
+            .edit(
+                EditOptions::builder()
+                    .insert_position(InsertPosition::End)
+                    .progress(move |index, total, _path| {
+                        assert_eq!((index, total), (0, 1));
+                        seen.set(seen.get() + 1);
+                    }),
+            )
+            .build();
+        let summary = update_dpr_files_with(
+            std::slice::from_ref(&dpr_path),
+            &mut cache,
+            None,
+            &new_unit,
+            &Assumptions::default(),
+            &options,
+        )
+        .unwrap();
 
+        assert_eq!(summary.updated, 1);
+        assert_eq!(calls.get(), 1);
         let updated = fs::read_to_string(&dpr_path).unwrap();
         assert!(
-            updated.contains("Bar,\r\n  NewUnit in 'NewUnit.pas',\r\n  Baz;"),
+            updated.contains("Other in 'Other.pas',\n  NewUnit in 'NewUnit.pas';"),
             "{updated}"
         );
     }
 
     #[test]
-    fn parse_dpr_uses_semicolon_on_own_line() {
-        let src = b"program Demo;\nuses\n  Foo,\n  Bar\n;\nbegin end.";
+    fn update_dpr_files_records_the_dependency_outcome_per_dpr() {
         let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, src, &mut warnings).expect("uses list");
-        let names: Vec<String> = list
-            .entries
-            .iter()
-            .map(|entry| entry.name.clone())
-            .collect();
-        assert_eq!(names, vec!["Foo", "Bar"]);
-        assert!(list.multiline);
-        assert_eq!(list.indent, "  ");
-    }
+        let paths = ["UnitA", "Other", "NewUnit"].map(|name| {
+            let path = root.join(format!("{name}.pas"));
+            let uses = if name == "UnitA" {
+                "uses NewUnit;\n"
+            } else {
+                ""
+            };
+            fs::write(
+                &path,
+                format!("unit {name};\ninterface\n{uses}implementation\nend.\n"),
+            )
+            .unwrap();
+            path
+        });
+        let dprs = [
+            ("Needs", "UnitA in 'UnitA.pas'"),
+            ("Has", "UnitA in 'UnitA.pas',\n  NewUnit in 'NewUnit.pas'"),
+            ("Free", "Other in 'Other.pas'"),
+        ]
+        .map(|(name, entries)| {
+            let path = root.join(format!("{name}.dpr"));
+            fs::write(
+                &path,
+                format!("program {name};\nuses\n  {entries};\nbegin\nend.\n"),
+            )
+            .unwrap();
+            path
+        });
 
-    #[test]
-    fn parse_dpr_uses_mixed_separators_prefers_existing() {
-        let src = b"program Demo;\nuses Foo in 'lib/Foo.pas', Bar in 'lib\\\\Bar.pas';\nbegin end.";
-        let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, src, &mut warnings).expect("uses list");
-        assert!(list.has_slash);
-        assert!(list.has_backslash);
+        let mut warnings = Warnings::new();
+        let mut cache = unit_cache::build_unit_cache(&paths, &mut warnings).unwrap();
+        let new_unit = unit_cache::load_unit_file(&paths[2], &mut warnings)
+            .unwrap()
+            .unwrap();
+        let summary = update_dpr_files_with(
+            &dprs,
+            &mut cache,
+            None,
+            &new_unit,
+            &Assumptions::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+
+        let outcome = |path: &PathBuf| {
+            summary
+                .details
+                .iter()
+                .find(|detail| &detail.path == path)
+                .and_then(|detail| detail.dependency)
+        };
+        assert_eq!(outcome(&dprs[0]), Some(DependencyOutcome::Inserted));
+        assert_eq!(outcome(&dprs[1]), Some(DependencyOutcome::AlreadyPresent));
+        assert_eq!(outcome(&dprs[2]), Some(DependencyOutcome::NotNeeded));
+        for outcome in [
+            DependencyOutcome::Inserted,
+            DependencyOutcome::AlreadyPresent,
+            DependencyOutcome::NotNeeded,
+        ] {
+            assert_eq!(summary.dependency_count(outcome), 1, "{outcome:?}");
+        }
     }
 
     #[test]
-    fn parse_dpr_uses_supports_include_fragments() {
+    fn update_dpr_files_end_position_appends_introduced_dependencies_on_own_lines() {
         let root = temp_dir();
-        let dpr_path = root.join("Demo.dpr");
-        let include_path = root.join("Uses.inc");
+        let dpr_path = root.join("App.dpr");
+        let paths = ["UnitA", "Other", "NewUnit", "Dep"].map(|name| {
+            let path = root.join(format!("{name}.pas"));
+            let uses = match name {
+                "UnitA" => "uses NewUnit;\n",
+                "NewUnit" => "uses Dep;\n",
+                _ => "",
+            };
+            fs::write(
+                &path,
+                format!("unit {name};\ninterface\n{uses}implementation\nend.\n"),
+            )
+            .unwrap();
+            path
+        });
         fs::write(
-            &include_path,
-            "Foo in 'lib\\\\Foo.pas',\nBar,\nBaz in 'lib/Baz.pas',",
+            &dpr_path,
+            "program App;\r\nuses\r\n    UnitA in 'UnitA.pas',\r\n    Other in 'Other.pas'\r\n    ;\r\nbegin\r\nend.\r\n",
         )
         .unwrap();
-        let src = b"program Demo;\nuses\n  {$I Uses.inc}\n  Qux;\nbegin end.";
-        let mut warnings = Vec::new();
-        let list = parse_dpr_uses(&dpr_path, src, &mut warnings).expect("uses list");
-        let names: Vec<String> = list
-            .entries
-            .iter()
-            .map(|entry| entry.name.clone())
-            .collect();
-        assert_eq!(names, vec!["Foo", "Bar", "Baz", "Qux"]);
-        assert!(list.has_backslash);
-        assert!(list.has_slash);
+
+        let mut warnings = Warnings::new();
+        let mut cache = unit_cache::build_unit_cache(&paths, &mut warnings).unwrap();
+        let new_unit = unit_cache::load_unit_file(&paths[2], &mut warnings)
+            .unwrap()
+            .unwrap();
+        let options = UpdateOptions::builder()
+            .edit(EditOptions::builder().insert_position(InsertPosition::End))
+            .build();
+        let summary = update_dpr_files_with(
+            std::slice::from_ref(&dpr_path),
+            &mut cache,
+            None,
+            &new_unit,
+            &Assumptions::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(summary.updated, 1);
+        assert_eq!(
+            fs::read_to_string(&dpr_path).unwrap(),
+            "program App;\r\nuses\r\n    UnitA in 'UnitA.pas',\r\n    Other in 'Other.pas',\r\n    NewUnit in 'NewUnit.pas',\r\n    Dep in 'Dep.pas'\r\n    ;\r\nbegin\r\nend.\r\n"
+        );
     }
 
     #[test]
-    fn resolve_by_name_prefers_project_cache_before_delphi_cache() {
-        let mut project_cache = UnitCache::default();
-        let project_path = PathBuf::from(r"C:\project\Foo.pas");
-        project_cache
-            .by_name
-            .insert("foo".to_string(), vec![project_path.clone()]);
+    fn update_dpr_files_introduced_scope_filters_delphi_units() {
+        let root = temp_dir();
+        let external = root.join("delphi");
+        fs::create_dir_all(&external).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let paths = ["UnitA", "NewUnit", "Dep"].map(|name| {
+            let path = root.join(format!("{name}.pas"));
+            let uses = match name {
+                "UnitA" => "uses NewUnit;\n",
+                "NewUnit" => "uses Dep, RtlUnit;\n",
+                _ => "",
+            };
+            fs::write(
+                &path,
+                format!("unit {name};\ninterface\n{uses}implementation\nend.\n"),
+            )
+            .unwrap();
+            path
+        });
+        let rtl_unit = external.join("RtlUnit.pas");
+        fs::write(
+            &rtl_unit,
+            "unit RtlUnit;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
 
-        let mut delphi_cache = UnitCache::default();
-        let delphi_path = PathBuf::from(r"C:\delphi\Foo.pas");
-        delphi_cache
-            .by_name
-            .insert("foo".to_string(), vec![delphi_path.clone()]);
+        let mut warnings = Warnings::new();
+        let mut cache = unit_cache::build_unit_cache(&paths, &mut warnings).unwrap();
+        let delphi_cache = LazyUnitCache::from_cache(
+            unit_cache::build_unit_cache(&[rtl_unit], &mut warnings).unwrap(),
+        );
+        let new_unit = unit_cache::load_unit_file(&paths[1], &mut warnings)
+            .unwrap()
+            .unwrap();
+        let mut run = |scope: IntroducedScope| {
+            fs::write(
+                &dpr_path,
+                "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+            )
+            .unwrap();
+            let options = UpdateOptions::builder().introduced_scope(scope).build();
+            update_dpr_files_with(
+                std::slice::from_ref(&dpr_path),
+                &mut cache,
+                Some(&delphi_cache),
+                &new_unit,
+                &Assumptions::default(),
+                &options,
+            )
+            .unwrap();
+            fs::read_to_string(&dpr_path).unwrap()
+        };
 
-        match resolve_by_name(&project_cache, Some(&delphi_cache), "Foo") {
-            ResolveByName::Unique { path, source } => {
-                assert_eq!(path, project_path);
-                assert_eq!(source, ResolutionSource::Project);
-            }
-            _ => panic!("expected unique project resolution"),
-        }
+        let all = run(IntroducedScope::All);
+        assert!(all.contains("Dep in 'Dep.pas'"), "{all}");
+        assert!(all.contains("RtlUnit in 'delphi\\RtlUnit.pas'"), "{all}");
+
+        let project_only = run(IntroducedScope::ProjectOnly);
+        assert!(
+            project_only.contains("NewUnit in 'NewUnit.pas'"),
+            "{project_only}"
+        );
+        assert!(project_only.contains("Dep in 'Dep.pas'"), "{project_only}");
+        assert!(!project_only.contains("RtlUnit"), "{project_only}");
+
+        let none = run(IntroducedScope::None);
+        assert!(none.contains("NewUnit in 'NewUnit.pas'"), "{none}");
+        assert!(!none.contains("Dep"), "{none}");
+        assert!(!none.contains("RtlUnit"), "{none}");
     }
 
     #[test]
-    fn resolve_by_name_uses_delphi_cache_when_project_missing() {
-        let project_cache = UnitCache::default();
-        let mut delphi_cache = UnitCache::default();
-        let delphi_path = PathBuf::from(r"C:\delphi\ExtUnit.pas");
-        delphi_cache
-            .by_name
-            .insert("extunit".to_string(), vec![delphi_path.clone()]);
+    fn update_dpr_files_after_unit_places_the_whole_group_after_the_marker() {
+        let root = temp_dir();
+        let with_marker = root.join("WithMarker.dpr");
+        let without_marker = root.join("WithoutMarker.dpr");
+        let paths = ["UnitA", "Marker", "Other", "NewUnit", "Dep"].map(|name| {
+            let path = root.join(format!("{name}.pas"));
+            let uses = match name {
+                "UnitA" => "uses NewUnit;\n",
+                "NewUnit" => "uses Dep;\n",
+                _ => "",
+            };
+            fs::write(
+                &path,
+                format!("unit {name};\ninterface\n{uses}implementation\nend.\n"),
+            )
+            .unwrap();
+            path
+        });
+        fs::write(
+            &with_marker,
+            "program WithMarker;\nuses\n  UnitA in 'UnitA.pas',\n  Marker in 'Marker.pas',\n  Other in 'Other.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &without_marker,
+            "program WithoutMarker;\nuses\n  UnitA in 'UnitA.pas',\n  Other in 'Other.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
 
-        match resolve_by_name(&project_cache, Some(&delphi_cache), "ExtUnit") {
-            ResolveByName::Unique { path, source } => {
-                assert_eq!(path, delphi_path);
-                assert_eq!(source, ResolutionSource::Delphi);
-            }
-            _ => panic!("expected unique delphi resolution"),
-        }
+        let mut warnings = Warnings::new();
+        let mut cache = unit_cache::build_unit_cache(&paths, &mut warnings).unwrap();
+        let new_unit = unit_cache::load_unit_file(&paths[3], &mut warnings)
+            .unwrap()
+            .unwrap();
+        let options = UpdateOptions::builder()
+            .after_unit(Some("marker".to_string()))
+            .build();
+        let summary = update_dpr_files_with(
+            &[with_marker.clone(), without_marker.clone()],
+            &mut cache,
+            None,
+            &new_unit,
+            &Assumptions::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(summary.updated, 2);
+        let updated = fs::read_to_string(&with_marker).unwrap();
+        assert!(
+            updated.contains(
+                "Marker in 'Marker.pas',\n  NewUnit in 'NewUnit.pas',\n  Dep in 'Dep.pas',\n  Other in 'Other.pas';"
+            ),
+            "{updated}"
+        );
+        let updated = fs::read_to_string(&without_marker).unwrap();
+        assert!(
+            updated.contains(
+                "UnitA in 'UnitA.pas',\n  NewUnit in 'NewUnit.pas',\n  Dep in 'Dep.pas',\n  Other in 'Other.pas';"
+            ),
+            "{updated}"
+        );
+        assert_eq!(summary.infos.len(), 1);
+        assert!(
+            summary.infos[0].starts_with("marker is not listed in "),
+            "{:?}",
+            summary.infos
+        );
     }
 
     #[test]
-    fn collect_introduced_dependencies_returns_transitive_closure_without_root() {
+    fn update_dpr_files_merges_inserted_units_outside_caches_for_fix_pass() {
         let root = temp_dir();
-        let new_path = root.join("NewUnit.pas");
-        let mid_path = root.join("MidUnit.pas");
-        let base_path = root.join("BaseUnit.pas");
+        let project = root.join("project");
+        let shared = root.join("shared");
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        let dpr_path = project.join("App.dpr");
+        let unit_a = project.join("UnitA.pas");
+        let helper = project.join("Helper.pas");
+        let new_path = shared.join("NewUnit.pas");
         fs::write(
-            &new_path,
-            "unit NewUnit;\ninterface\nuses MidUnit;\nimplementation\nend.\n",
+            &dpr_path,
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
         )
         .unwrap();
         fs::write(
-            &mid_path,
-            "unit MidUnit;\ninterface\nuses BaseUnit, NewUnit;\nimplementation\nend.\n",
+            &unit_a,
+            "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
         )
         .unwrap();
+        fs::write(&helper, "unit Helper;\ninterface\nimplementation\nend.\n").unwrap();
         fs::write(
-            &base_path,
-            "unit BaseUnit;\ninterface\nimplementation\nend.\n",
+            &new_path,
+            "unit NewUnit;\ninterface\nimplementation\nuses Helper;\nend.\n",
         )
         .unwrap();
 
-        let mut warnings = Vec::new();
-        let mut project_cache =
-            unit_cache::build_unit_cache(&[new_path.clone(), mid_path, base_path], &mut warnings)
-                .unwrap();
+        let mut warnings = Warnings::new();
+        let mut cache = unit_cache::build_unit_cache(&[unit_a, helper], &mut warnings).unwrap();
         let new_unit = unit_cache::load_unit_file(&new_path, &mut warnings)
             .unwrap()
-            .expect("new unit");
-        let project_map = HashMap::new();
-        let assumptions = Assumptions::default();
-
-        let introduced = collect_introduced_dependencies(
-            &mut project_cache,
+            .unwrap();
+        let options = UpdateOptions::builder()
+            .add_introduced_dependencies(false)
+            .build();
+        let summary = update_dpr_files_with(
+            std::slice::from_ref(&dpr_path),
+            &mut cache,
             None,
-            &project_map,
             &new_unit,
-            &assumptions,
-            &mut warnings,
+            &Assumptions::default(),
+            &options,
         )
         .unwrap();
-        let names: Vec<String> = introduced
-            .into_iter()
-            .map(|unit| unit.name.to_ascii_lowercase())
-            .collect();
-        assert_eq!(names, vec!["midunit", "baseunit"]);
+        assert_eq!(summary.updated, 1);
+        assert!(cache
+            .by_path
+            .contains_key(&unit_cache::canonicalize_if_exists(&new_path)));
+
+        let result = fix_dpr_file(&dpr_path, &cache, None, &Assumptions::default()).unwrap();
+        assert!(
+            !result
+                .warnings
+                .iter()
+                .any(|warning| warning.code == DiagnosticCode::UnresolvedUnit),
+            "{result:?}"
+        );
+        let updated = fs::read_to_string(&dpr_path).unwrap();
+        assert!(updated.contains("Helper in 'Helper.pas'"), "{updated}");
     }
 
     #[test]
-    fn collect_introduced_dependencies_respects_assume_off() {
+    fn update_dpr_files_warns_about_and_retargets_a_differently_pathed_new_unit() {
         let root = temp_dir();
+        fs::create_dir_all(root.join("legacy")).unwrap();
+        let dpr_path = root.join("App.dpr");
+        let legacy_path = root.join("legacy").join("NewUnit.pas");
         let new_path = root.join("NewUnit.pas");
-        let debug_mid_path = root.join("DebugMid.pas");
+        let source = "program App;\nuses\n  NewUnit in 'legacy\\NewUnit.pas';\nbegin\nend.\n";
+        fs::write(&dpr_path, source).unwrap();
         fs::write(
-            &new_path,
-            "unit NewUnit;\ninterface\nuses {$IFDEF DEBUG} DebugMid {$ENDIF};\nimplementation\nend.\n",
+            &legacy_path,
+            "unit NewUnit;\ninterface\nimplementation\nend.\n",
         )
         .unwrap();
         fs::write(
-            &debug_mid_path,
-            "unit DebugMid;\ninterface\nimplementation\nend.\n",
+            &new_path,
+            "unit NewUnit;\ninterface\nimplementation\nend.\n",
         )
         .unwrap();
 
-        let mut warnings = Vec::new();
-        let mut project_cache =
-            unit_cache::build_unit_cache(&[new_path.clone(), debug_mid_path], &mut warnings)
-                .unwrap();
+        let mut warnings = Warnings::new();
+        let mut cache = unit_cache::build_unit_cache(&[legacy_path], &mut warnings).unwrap();
         let new_unit = unit_cache::load_unit_file(&new_path, &mut warnings)
             .unwrap()
-            .expect("new unit");
-        let project_map = HashMap::new();
-        let mut assumptions = Assumptions::default();
-        assumptions.set("DEBUG", conditionals::AssumedValue::Off);
+            .unwrap();
+        let update = |cache: &mut UnitCache, options: &UpdateOptions| {
+            update_dpr_files_with(
+                std::slice::from_ref(&dpr_path),
+                cache,
+                None,
+                &new_unit,
+                &Assumptions::default(),
+                options,
+            )
+            .unwrap()
+        };
 
-        let introduced = collect_introduced_dependencies(
-            &mut project_cache,
-            None,
-            &project_map,
-            &new_unit,
-            &assumptions,
-            &mut warnings,
-        )
-        .unwrap();
+        let summary = update(&mut cache, &UpdateOptions::default());
+        assert_eq!(summary.updated, 0);
+        let conflicts: Vec<_> = summary
+            .warnings
+            .iter()
+            .filter(|warning| warning.code == DiagnosticCode::ConflictingUnitPath)
+            .collect();
+        assert_eq!(conflicts.len(), 1);
+        assert!(
+            conflicts[0].message.contains("--retarget-existing"),
+            "{}",
+            conflicts[0].message
+        );
+        assert_eq!(fs::read_to_string(&dpr_path).unwrap(), source);
+
+        let options = UpdateOptions::builder().retarget_existing(true).build();
+        let summary = update(&mut cache, &options);
+        assert_eq!(summary.updated_paths, vec![dpr_path.clone()]);
+        assert_eq!(
+            fs::read_to_string(&dpr_path).unwrap(),
+            "program App;\nuses\n  NewUnit in 'NewUnit.pas';\nbegin\nend.\n"
+        );
 
-        assert!(introduced.is_empty(), "{introduced:?}");
+        let summary = update(&mut cache, &options);
+        assert_eq!(summary.updated, 0);
+        assert!(summary.warnings.is_empty(), "{:?}", summary.warnings);
     }
 
     #[test]
-    fn fix_dpr_file_adds_missing_transitive_dependencies_from_project_cache() {
+    fn update_dpr_files_direct_only_skips_transitive_users() {
         let root = temp_dir();
-        let dpr_path = root.join("App.dpr");
+        let direct_dpr = root.join("Direct.dpr");
+        let chain_dpr = root.join("Chain.dpr");
         let unit_a = root.join("UnitA.pas");
         let unit_b = root.join("UnitB.pas");
-        let unit_c = root.join("UnitC.pas");
+        let new_path = root.join("NewUnit.pas");
+        let chain_source = "program Chain;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n";
         fs::write(
-            &dpr_path,
-            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+            &direct_dpr,
+            "program Direct;\nuses\n  UnitB in 'UnitB.pas';\nbegin\nend.\n",
         )
         .unwrap();
+        fs::write(&chain_dpr, chain_source).unwrap();
         fs::write(
             &unit_a,
             "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
@@ -2596,146 +9167,284 @@ begin end.
         .unwrap();
         fs::write(
             &unit_b,
-            "unit UnitB;\ninterface\nuses UnitC;\nimplementation\nend.\n",
+            "unit UnitB;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &new_path,
+            "unit NewUnit;\ninterface\nimplementation\nend.\n",
         )
         .unwrap();
-        fs::write(&unit_c, "unit UnitC;\ninterface\nimplementation\nend.\n").unwrap();
 
-        let mut warnings = Vec::new();
-        let cache = unit_cache::build_unit_cache(
-            &[unit_a.clone(), unit_b.clone(), unit_c.clone()],
-            &mut warnings,
+        let mut warnings = Warnings::new();
+        let mut cache =
+            unit_cache::build_unit_cache(&[unit_a, unit_b, new_path.clone()], &mut warnings)
+                .unwrap();
+        let new_unit = unit_cache::load_unit_file(&new_path, &mut warnings)
+            .unwrap()
+            .unwrap();
+        let options = UpdateOptions::builder().direct_only(true).build();
+        let summary = update_dpr_files_with(
+            &[direct_dpr.clone(), chain_dpr.clone()],
+            &mut cache,
+            None,
+            &new_unit,
+            &Assumptions::default(),
+            &options,
         )
         .unwrap();
-        let assumptions = Assumptions::default();
 
-        let first = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
-        assert_eq!(first.failures, 0, "{first:?}");
-        assert_eq!(first.updated, 1, "{first:?}");
-        let updated = fs::read_to_string(&dpr_path).unwrap();
-        assert!(updated.contains("UnitB in 'UnitB.pas'"), "{updated}");
-        assert!(updated.contains("UnitC in 'UnitC.pas'"), "{updated}");
+        assert_eq!(summary.updated_paths, vec![direct_dpr.clone()]);
+        assert!(fs::read_to_string(&direct_dpr)
+            .unwrap()
+            .contains("NewUnit in 'NewUnit.pas'"));
+        assert_eq!(fs::read_to_string(&chain_dpr).unwrap(), chain_source);
 
-        let second = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
-        assert_eq!(second.failures, 0, "{second:?}");
-        assert_eq!(second.updated, 0, "{second:?}");
+        let summary = update_dpr_files_with(
+            std::slice::from_ref(&chain_dpr),
+            &mut cache,
+            None,
+            &new_unit,
+            &Assumptions::default(),
+            &UpdateOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(summary.updated, 1);
     }
 
     #[test]
-    fn fix_dpr_file_skips_dependencies_not_in_project_cache() {
+    fn fix_dpr_file_records_detail_with_inserted_units_and_warnings() {
         let root = temp_dir();
-        let external = root.join("external");
-        fs::create_dir_all(&external).unwrap();
         let dpr_path = root.join("App.dpr");
         let unit_a = root.join("UnitA.pas");
-        let ext_unit = external.join("ExtUnit.pas");
-        fs::write(
-            &dpr_path,
-            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
-        )
-        .unwrap();
+        let unit_b = root.join("UnitB.pas");
+        fs::write(&dpr_path, "program App;\nuses\n  UnitA;\nbegin\nend.\n").unwrap();
         fs::write(
             &unit_a,
-            "unit UnitA;\ninterface\nuses ExtUnit;\nimplementation\nend.\n",
-        )
-        .unwrap();
-        fs::write(
-            &ext_unit,
-            "unit ExtUnit;\ninterface\nimplementation\nend.\n",
+            "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
         )
         .unwrap();
+        fs::write(&unit_b, "unit UnitB;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&[unit_a, unit_b.clone()], &mut warnings).unwrap();
+        let result = fix_dpr_file(&dpr_path, &cache, None, &Assumptions::default()).unwrap();
+
+        assert_eq!(result.details.len(), 1);
+        let detail = &result.details[0];
+        assert_eq!(detail.path, unit_cache::canonicalize_if_exists(&dpr_path));
+        assert_eq!(detail.status, DprStatus::Updated);
+        assert_eq!(
+            detail.inserted,
+            vec![InsertedUnit {
+                name: "UnitB".to_string(),
+                in_path: Some("UnitB.pas".to_string()),
+                name_from_stem: false,
+                source: Some(ResolutionSource::Project),
+                path: Some(unit_cache::canonicalize_if_exists(&unit_b)),
+                chain: vec!["UnitA".to_string()],
+            }]
+        );
+        assert_eq!(detail.warnings.len(), result.warnings.len());
+        assert!(detail
+            .warnings
+            .iter()
+            .any(|diagnostic| diagnostic.code == DiagnosticCode::MissingInPath));
+    }
 
-        let mut warnings = Vec::new();
-        let cache =
-            unit_cache::build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings).unwrap();
-        let assumptions = Assumptions::default();
+    #[cfg(not(windows))]
+    #[test]
+    fn resolve_dpr_unit_path_matches_windows_paths_ignoring_case() {
+        let root = temp_dir();
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::create_dir_all(root.join("lib/core")).unwrap();
+        fs::create_dir_all(root.join("twin/a")).unwrap();
+        fs::create_dir_all(root.join("twin/A")).unwrap();
+        fs::write(root.join("lib/core/Utils.pas"), "unit Utils;").unwrap();
+        fs::write(root.join("twin/a/Same.pas"), "unit Same;").unwrap();
+        fs::write(root.join("twin/A/Same.pas"), "unit Same;").unwrap();
+        let dpr_path = root.join("app/App.dpr");
+
+        let resolved = resolve_dpr_unit_path(&dpr_path, "..\\Lib\\CORE\\utils.pas");
+        assert_eq!(
+            resolved,
+            fs::canonicalize(root.join("lib/core/Utils.pas")).unwrap()
+        );
+        let ambiguous = resolve_dpr_unit_path(&dpr_path, "..\\TWIN\\a\\Same.pas");
+        assert!(!ambiguous.is_file(), "{}", ambiguous.display());
+        let missing = resolve_dpr_unit_path(&dpr_path, "..\\lib\\Missing.pas");
+        assert!(!missing.is_file());
+    }
 
-        let result = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
-        assert_eq!(result.failures, 0, "{result:?}");
-        assert_eq!(result.updated, 0, "{result:?}");
-        let updated = fs::read_to_string(&dpr_path).unwrap();
-        assert!(!updated.contains("ExtUnit in "), "{updated}");
+    #[test]
+    fn resolve_dpr_unit_path_accepts_legacy_in_path_forms() {
+        let root = temp_dir();
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::create_dir_all(root.join("common/sub")).unwrap();
+        fs::write(root.join("common/sub/Foo.pas"), "unit Foo;").unwrap();
+        let dpr_path = root.join("app/App.dpr");
+        let expected = fs::canonicalize(root.join("common/sub/Foo.pas")).unwrap();
+
+        for raw in [
+            r"..\common\sub\Foo.pas",
+            "../common/sub/Foo.pas",
+            r"..\common/sub\Foo.pas",
+            r"..\\common\\sub\\Foo.pas",
+            r".\..\common\sub\Foo.pas",
+            r"  ..\common\sub\Foo.pas  ",
+            "\t../common/sub/Foo.pas",
+        ] {
+            assert_eq!(resolve_dpr_unit_path(&dpr_path, raw), expected, "{raw:?}");
+        }
+
+        for raw in [
+            r"C:common\sub\Foo.pas",
+            "c:../common/sub/Foo.pas",
+            " D:Foo.pas",
+        ] {
+            assert!(is_drive_relative(raw), "{raw:?}");
+            assert!(!resolve_dpr_unit_path(&dpr_path, raw).is_file(), "{raw:?}");
+        }
+        for raw in [
+            r"C:\common\Foo.pas",
+            "C:/common/Foo.pas",
+            "Foo.pas",
+            r"\\server\share\Foo.pas",
+        ] {
+            assert!(!is_drive_relative(raw), "{raw:?}");
+        }
     }
 
     #[test]
-    fn fix_dpr_file_uses_delphi_fallback_cache_when_provided() {
+    fn build_project_map_reports_drive_relative_in_paths() {
         let root = temp_dir();
-        let external = root.join("delphi");
-        fs::create_dir_all(&external).unwrap();
+        fs::create_dir_all(root.join("lib")).unwrap();
         let dpr_path = root.join("App.dpr");
-        let unit_a = root.join("UnitA.pas");
-        let ext_mid = external.join("ExtMid.pas");
-        let new_unit = external.join("NewUnit.pas");
-        fs::write(
-            &dpr_path,
-            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
-        )
-        .unwrap();
-        fs::write(
-            &unit_a,
-            "unit UnitA;\ninterface\nuses ExtMid;\nimplementation\nend.\n",
-        )
-        .unwrap();
-        fs::write(
-            &ext_mid,
-            "unit ExtMid;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
-        )
-        .unwrap();
-        fs::write(
-            &new_unit,
-            "unit NewUnit;\ninterface\nimplementation\nend.\n",
-        )
-        .unwrap();
+        let unit_a = root.join("lib").join("UnitA.pas");
+        fs::write(&unit_a, "unit UnitA;\ninterface\nimplementation\nend.\n").unwrap();
+        let src = b"program App;\nuses\n  UnitA in 'C:lib\\UnitA.pas';\nbegin\nend.\n";
 
-        let mut warnings = Vec::new();
-        let project_cache =
+        let mut warnings = Warnings::new();
+        let cache =
             unit_cache::build_unit_cache(std::slice::from_ref(&unit_a), &mut warnings).unwrap();
-        let delphi_cache =
-            unit_cache::build_unit_cache(&[ext_mid, new_unit], &mut warnings).unwrap();
-        let assumptions = Assumptions::default();
+        let list =
+            parse_dpr_uses(&dpr_path, src, &Includes::default(), &mut warnings).expect("uses list");
+        let map = build_project_map(&dpr_path, &list, &cache, None, &mut warnings);
+        let codes: Vec<DiagnosticCode> = warnings.iter().map(|warning| warning.code).collect();
+        assert_eq!(codes, vec![DiagnosticCode::DriveRelativePath]);
+        assert_eq!(
+            map.get("unita"),
+            Some(&unit_cache::canonicalize_if_exists(&unit_a))
+        );
+    }
 
-        let result =
-            fix_dpr_file(&dpr_path, &project_cache, Some(&delphi_cache), &assumptions).unwrap();
-        assert_eq!(result.failures, 0, "{result:?}");
-        assert_eq!(result.updated, 1, "{result:?}");
-        let updated = fs::read_to_string(&dpr_path).unwrap();
-        assert!(updated.contains("ExtMid in "), "{updated}");
-        assert!(updated.contains("NewUnit in "), "{updated}");
+    #[test]
+    fn relative_path_never_writes_verbatim_prefixes() {
+        assert_eq!(
+            relative_path(
+                Path::new(r"\\?\D:\lib\NewUnit.pas"),
+                Some(Path::new(r"\\?\C:\app"))
+            ),
+            r"D:\lib\NewUnit.pas"
+        );
+        assert_eq!(
+            relative_path(
+                Path::new(r"\\?\UNC\server\share\NewUnit.pas"),
+                Some(Path::new(r"C:\app"))
+            ),
+            r"\\server\share\NewUnit.pas"
+        );
+        assert_eq!(
+            unit_cache::strip_verbatim_prefix(Path::new(r"\\?\C:\app\App.dpr")),
+            PathBuf::from(r"C:\app\App.dpr")
+        );
     }
 
     #[test]
-    fn fix_dpr_file_skips_inactive_conditional_roots_when_assumed_off() {
+    fn fix_dpr_file_records_the_chain_that_introduces_each_unit() {
         let root = temp_dir();
         let dpr_path = root.join("App.dpr");
-        let debug_root = root.join("DebugRoot.pas");
-        let new_unit = root.join("NewUnit.pas");
         fs::write(
             &dpr_path,
-            "program App;\nuses\n  {$IFDEF DEBUG} DebugRoot in 'DebugRoot.pas' {$ENDIF};\nbegin\nend.\n",
-        )
-        .unwrap();
-        fs::write(
-            &debug_root,
-            "unit DebugRoot;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
-        )
-        .unwrap();
-        fs::write(
-            &new_unit,
-            "unit NewUnit;\ninterface\nimplementation\nend.\n",
+            "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
         )
         .unwrap();
+        let mut paths = Vec::new();
+        for (name, uses) in [
+            ("UnitA", "uses UnitB;"),
+            ("UnitB", "uses UnitC;"),
+            ("UnitC", ""),
+        ] {
+            let path = root.join(format!("{name}.pas"));
+            fs::write(
+                &path,
+                format!("unit {name};\ninterface\n{uses}\nimplementation\nend.\n"),
+            )
+            .unwrap();
+            paths.push(path);
+        }
 
-        let mut warnings = Vec::new();
-        let cache =
-            unit_cache::build_unit_cache(&[debug_root.clone(), new_unit], &mut warnings).unwrap();
-        let mut assumptions = Assumptions::default();
-        assumptions.set("DEBUG", conditionals::AssumedValue::Off);
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(&paths, &mut warnings).unwrap();
+        let result = fix_dpr_file(&dpr_path, &cache, None, &Assumptions::default()).unwrap();
 
-        let result = fix_dpr_file(&dpr_path, &cache, None, &assumptions).unwrap();
-        assert_eq!(result.failures, 0, "{result:?}");
-        assert_eq!(result.updated, 0, "{result:?}");
-        let updated = fs::read_to_string(&dpr_path).unwrap();
-        assert!(!updated.contains("NewUnit in "), "{updated}");
+        let chains: Vec<(&str, Vec<String>)> = result.details[0]
+            .inserted
+            .iter()
+            .map(|unit| (unit.name.as_str(), unit.chain.clone()))
+            .collect();
+        assert_eq!(
+            chains,
+            vec![
+                ("UnitB", vec!["UnitA".to_string()]),
+                ("UnitC", vec!["UnitA".to_string(), "UnitB".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_detail_combines_records_for_the_same_dpr() {
+        let mut summary = DprUpdateSummary::new();
+        let path = PathBuf::from("App.dpr");
+        let mut updated = DprDetail::new(&path, DprStatus::Updated);
+        updated.inserted.push(InsertedUnit {
+            name: "UnitA".to_string(),
+            in_path: Some("UnitA.pas".to_string()),
+            name_from_stem: false,
+            source: Some(ResolutionSource::Project),
+            path: None,
+            chain: Vec::new(),
+        });
+        summary.merge_detail(updated);
+        let mut failed = DprDetail::new(&path, DprStatus::Failed);
+        failed
+            .warnings
+            .push(Diagnostic::warning(DiagnosticCode::FixFailed, "boom"));
+        summary.merge_detail(failed);
+        summary.merge_detail(DprDetail::new(Path::new("Other.dpr"), DprStatus::Ignored));
+
+        assert_eq!(summary.details.len(), 2);
+        assert_eq!(summary.details[0].status, DprStatus::Failed);
+        assert_eq!(summary.details[0].inserted.len(), 1);
+        assert_eq!(summary.details[0].warnings.len(), 1);
+        assert_eq!(summary.details[1].status, DprStatus::Ignored);
+    }
+
+    #[test]
+    fn dpr_program_name_reads_program_and_library_headers() {
+        assert_eq!(
+            dpr_program_name(b"\xEF\xBB\xBF{ generated }\r\nprogram AutoBuild_Core;\r\nuses A;"),
+            Some("AutoBuild_Core".to_string())
+        );
+        assert_eq!(
+            dpr_program_name(b"library (* plugin *) My.Plugin;\nbegin end."),
+            Some("My.Plugin".to_string())
+        );
+        assert_eq!(
+            dpr_program_name(b"program App(Input, Output);\nbegin end."),
+            Some("App".to_string())
+        );
+        assert_eq!(dpr_program_name(b"// program Fake;\nuses A;"), None);
     }
 
     #[test]
@@ -2751,9 +9460,18 @@ begin end.
             path: unit_path,
             uses: Vec::new(),
             conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
         };
         let bytes = fs::read(&dpr_path).unwrap();
-        create_uses_section(&bytes, &dpr_path, std::slice::from_ref(&new_unit)).unwrap();
+        create_uses_section(
+            &bytes,
+            &dpr_path,
+            std::slice::from_ref(&new_unit),
+            &mut DprWriter::default(),
+            &mut Warnings::new(),
+        )
+        .unwrap();
 
         let updated = fs::read_to_string(&dpr_path).unwrap();
         assert!(
@@ -2762,6 +9480,114 @@ begin end.
         );
     }
 
+    #[test]
+    fn init_dpr_file_creates_program_and_refuses_existing_list_without_force() {
+        let root = temp_dir();
+        let dpr_path = root.join("NewApp.dpr");
+        let seed_path = root.join("MainForm.pas");
+        let helper_path = root.join("Helper.pas");
+        fs::write(
+            &seed_path,
+            "unit MainForm;\ninterface\nuses Helper, SysUtils;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &helper_path,
+            "unit Helper;\ninterface\nimplementation\nuses MainForm;\nend.\n",
+        )
+        .unwrap();
+        let mut warnings = Warnings::new();
+        let cache =
+            unit_cache::build_unit_cache(&[seed_path.clone(), helper_path], &mut warnings).unwrap();
+        let init = |force: bool| {
+            init_dpr_file_with(
+                &dpr_path,
+                &seed_path,
+                &cache,
+                None,
+                &Assumptions::default(),
+                force,
+                &FixOptions::default(),
+            )
+            .unwrap()
+        };
+
+        let summary = init(false);
+        assert_eq!(summary.updated, 1);
+        assert_eq!(summary.details[0].inserted.len(), 2);
+        assert_eq!(
+            fs::read_to_string(&dpr_path).unwrap(),
+            "program NewApp;\r\nuses\r\n  MainForm in 'MainForm.pas',\r\n  Helper in 'Helper.pas';\r\n\r\nbegin\r\nend.\r\n"
+        );
+
+        let summary = init(false);
+        assert_eq!(summary.failures, 1);
+        assert!(summary
+            .warnings
+            .iter()
+            .any(|warning| warning.code == DiagnosticCode::UsesListExists));
+
+        fs::write(
+            &dpr_path,
+            "program NewApp;\nuses\n  Old in 'Old.pas';\n{$R *.res}\nbegin\nend.\n",
+        )
+        .unwrap();
+        let summary = init(true);
+        assert_eq!(summary.updated, 1);
+        assert_eq!(
+            fs::read_to_string(&dpr_path).unwrap(),
+            "program NewApp;\nuses\n  MainForm in 'MainForm.pas',\n  Helper in 'Helper.pas';\n{$R *.res}\nbegin\nend.\n"
+        );
+        assert_eq!(init(true).updated, 0, "an identical list is left alone");
+    }
+
+    #[test]
+    fn copy_uses_with_copies_missing_entries_and_syncs_unpinned_ones() {
+        let root = temp_dir();
+        fs::create_dir_all(root.join("libs")).unwrap();
+        fs::create_dir_all(root.join("test")).unwrap();
+        for name in ["Alpha", "Beta", "Gamma"] {
+            fs::write(
+                root.join("libs").join(format!("{name}.pas")),
+                format!("unit {name};\ninterface\nimplementation\nend.\n"),
+            )
+            .unwrap();
+        }
+        let source_path = root.join("App.dpr");
+        let dpr_path = root.join("test").join("AppTest.dpr");
+        fs::write(
+            &source_path,
+            "program App;\nuses\n  Alpha in 'libs\\Alpha.pas',\n  Beta in 'libs\\Beta.pas',\n  SysUtils;\nbegin\nend.\n",
+        )
+        .unwrap();
+        let target = "program AppTest;\nuses\n  Alpha in '..\\libs\\Alpha.pas',\n  Gamma in '..\\libs\\Gamma.pas',\n  Stale in 'Stale.pas',\n  TestRunner in 'TestRunner.pas'; // fixdpr:pin\nbegin\nend.\n";
+        fs::write(&dpr_path, target).unwrap();
+
+        let summary =
+            copy_uses_with(&source_path, &dpr_path, false, &FixOptions::default()).unwrap();
+        assert_eq!(summary.updated, 1);
+        assert_eq!(
+            fs::read_to_string(&dpr_path).unwrap(),
+            "program AppTest;\nuses\n  Alpha in '..\\libs\\Alpha.pas',\n  Beta in '..\\libs\\Beta.pas',\n  SysUtils,\n  Gamma in '..\\libs\\Gamma.pas',\n  Stale in 'Stale.pas',\n  TestRunner in 'TestRunner.pas'; // fixdpr:pin\nbegin\nend.\n"
+        );
+        let inserted: Vec<&str> = summary.details[0]
+            .inserted
+            .iter()
+            .map(|unit| unit.name.as_str())
+            .collect();
+        assert_eq!(inserted, ["Beta", "SysUtils"]);
+
+        fs::write(&dpr_path, target).unwrap();
+        let summary =
+            copy_uses_with(&source_path, &dpr_path, true, &FixOptions::default()).unwrap();
+        assert_eq!(summary.updated, 1);
+        assert_eq!(
+            fs::read_to_string(&dpr_path).unwrap(),
+            "program AppTest;\nuses\n  Alpha in '..\\libs\\Alpha.pas',\n  Beta in '..\\libs\\Beta.pas',\n  SysUtils,\n  TestRunner in 'TestRunner.pas'; // fixdpr:pin\nbegin\nend.\n"
+        );
+        assert_eq!(summary.infos.len(), 2, "{:?}", summary.infos);
+    }
+
     #[test]
     fn insert_dependency_files_creates_missing_uses_and_adds_chain() {
         let root = temp_dir();
@@ -2786,7 +9612,7 @@ begin end.
         )
         .unwrap();
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let mut cache = unit_cache::build_unit_cache(
             &[new_path.clone(), mid_path.clone(), base_path.clone()],
             &mut warnings,
@@ -2858,7 +9684,7 @@ begin end.
         )
         .unwrap();
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let cache = unit_cache::build_unit_cache(
             &[
                 unit_a.clone(),
@@ -2910,7 +9736,7 @@ begin end.
         )
         .unwrap();
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let cache =
             unit_cache::build_unit_cache(std::slice::from_ref(&keep_unit), &mut warnings).unwrap();
         let assumptions = Assumptions::default();
@@ -2956,7 +9782,7 @@ begin end.
         )
         .unwrap();
 
-        let mut warnings = Vec::new();
+        let mut warnings = Warnings::new();
         let cache = unit_cache::build_unit_cache(
             &[old_unit.clone(), maybe_root.clone(), shared_dep.clone()],
             &mut warnings,
@@ -2988,6 +9814,272 @@ begin end.
         );
     }
 
+    #[test]
+    fn who_uses_separates_direct_and_transitive_dprs() {
+        let root = temp_dir();
+        let direct_dpr = root.join("Direct.dpr");
+        let indirect_dpr = root.join("Indirect.dpr");
+        let other_dpr = root.join("Other.dpr");
+        let target = root.join("Target.pas");
+        let middle = root.join("Middle.pas");
+        let other = root.join("Other.pas");
+
+        fs::write(
+            &direct_dpr,
+            "program Direct;\nuses\n  Target in 'Target.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &indirect_dpr,
+            "program Indirect;\nuses\n  Middle in 'Middle.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &other_dpr,
+            "program Other;\nuses\n  Other in 'Other.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(&target, "unit Target;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(
+            &middle,
+            "unit Middle;\ninterface\nimplementation\nuses Target;\nend.\n",
+        )
+        .unwrap();
+        fs::write(&other, "unit Other;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let mut cache = unit_cache::build_unit_cache(
+            &[target.clone(), middle.clone(), other.clone()],
+            &mut warnings,
+        )
+        .unwrap();
+        let dprs = vec![direct_dpr.clone(), indirect_dpr.clone(), other_dpr.clone()];
+
+        let report = who_uses(
+            &dprs,
+            &mut cache,
+            None,
+            "target",
+            &Assumptions::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(report.scanned, 3);
+        assert_eq!(report.direct_dprs, vec![direct_dpr.clone()]);
+        assert!(report.transitive_dprs.is_empty());
+        assert_eq!(report.units, vec![middle.clone()]);
+
+        let report = who_uses(
+            &dprs,
+            &mut cache,
+            None,
+            "Target",
+            &Assumptions::default(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(report.direct_dprs, vec![direct_dpr]);
+        assert_eq!(report.transitive_dprs, vec![indirect_dpr]);
+
+        let report = who_uses(
+            &dprs,
+            &mut cache,
+            None,
+            "Missing",
+            &Assumptions::default(),
+            true,
+        )
+        .unwrap();
+        assert!(report.is_empty(), "{report:?}");
+    }
+
+    #[test]
+    fn impacted_dprs_unions_direct_and_transitive_reach_of_all_units() {
+        let root = temp_dir();
+        let first_dpr = root.join("First.dpr");
+        let second_dpr = root.join("Second.dpr");
+        let third_dpr = root.join("Third.dpr");
+        let a = root.join("A.pas");
+        let b = root.join("B.pas");
+        let c = root.join("C.pas");
+
+        fs::write(
+            &first_dpr,
+            "program First;\nuses\n  A in 'A.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &second_dpr,
+            "program Second;\nuses\n  B in 'B.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &third_dpr,
+            "program Third;\nuses\n  C in 'C.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(&a, "unit A;\ninterface\nuses B;\nimplementation\nend.\n").unwrap();
+        fs::write(&b, "unit B;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(&c, "unit C;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let mut cache =
+            unit_cache::build_unit_cache(&[a.clone(), b.clone(), c.clone()], &mut warnings)
+                .unwrap();
+        let dprs = vec![third_dpr.clone(), second_dpr.clone(), first_dpr.clone()];
+
+        let report = impacted_dprs(
+            &dprs,
+            &mut cache,
+            None,
+            &["b".to_string()],
+            &Assumptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.scanned, 3);
+        let mut expected = vec![first_dpr.clone(), second_dpr.clone()];
+        expected.sort();
+        assert_eq!(report.affected, expected);
+
+        let report = impacted_dprs(
+            &dprs,
+            &mut cache,
+            None,
+            &["B".to_string(), "C".to_string()],
+            &Assumptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.affected.len(), 3);
+
+        let report = impacted_dprs(&dprs, &mut cache, None, &[], &Assumptions::default()).unwrap();
+        assert!(report.affected.is_empty());
+    }
+
+    #[test]
+    fn dpr_fanout_counts_active_closure_and_sorts_heaviest_first() {
+        let root = temp_dir();
+        let heavy_dpr = root.join("Heavy.dpr");
+        let light_dpr = root.join("Light.dpr");
+        let outer = root.join("Outer.pas");
+        let leaf = root.join("Leaf.pas");
+        let debug = root.join("Debug.pas");
+
+        fs::write(
+            &heavy_dpr,
+            "program Heavy;\nuses\n  Outer in 'Outer.pas'\n  {$IFDEF DEBUG}, Debug in 'Debug.pas'{$ENDIF};\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &light_dpr,
+            "program Light;\nuses\n  Leaf in 'Leaf.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &outer,
+            "unit Outer;\ninterface\nuses Leaf;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &leaf,
+            "unit Leaf;\ninterface\nimplementation\nuses Outer;\nend.\n",
+        )
+        .unwrap();
+        fs::write(&debug, "unit Debug;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let cache = unit_cache::build_unit_cache(
+            &[outer.clone(), leaf.clone(), debug.clone()],
+            &mut warnings,
+        )
+        .unwrap();
+        let dprs = vec![light_dpr.clone(), heavy_dpr.clone()];
+
+        let mut assumptions = Assumptions::default();
+        assumptions.set("DEBUG", conditionals::AssumedValue::On);
+        let report = dpr_fanout(&dprs, &cache, None, &assumptions).unwrap();
+        let found: Vec<(&Path, usize, usize)> = report
+            .dprs
+            .iter()
+            .map(|dpr| (dpr.path.as_path(), dpr.direct, dpr.closure()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(heavy_dpr.as_path(), 2, 3), (light_dpr.as_path(), 1, 2)]
+        );
+
+        assumptions.set("DEBUG", conditionals::AssumedValue::Off);
+        let report = dpr_fanout(&dprs, &cache, None, &assumptions).unwrap();
+        assert_eq!(report.scanned, 2);
+        assert_eq!(report.dprs[0].closure(), 2);
+        assert_eq!(report.dprs[0].path, heavy_dpr);
+        assert_eq!(report.dprs[0].delphi_units, 0);
+    }
+
+    #[test]
+    fn unit_dependents_walks_reverse_uses_and_records_listing_dprs() {
+        let root = temp_dir();
+        let app_dpr = root.join("App.dpr");
+        let leaf = root.join("Leaf.pas");
+        let direct = root.join("Direct.pas");
+        let outer = root.join("Outer.pas");
+        let other = root.join("Other.pas");
+
+        fs::write(
+            &app_dpr,
+            "program App;\nuses\n  Outer in 'Outer.pas',\n  Other in 'Other.pas';\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(&leaf, "unit Leaf;\ninterface\nimplementation\nend.\n").unwrap();
+        fs::write(
+            &direct,
+            "unit Direct;\ninterface\nimplementation\nuses Leaf;\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            &outer,
+            "unit Outer;\ninterface\nuses Direct;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(&other, "unit Other;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        let mut cache = unit_cache::build_unit_cache(
+            &[leaf.clone(), direct.clone(), outer.clone(), other.clone()],
+            &mut warnings,
+        )
+        .unwrap();
+        let dprs = vec![app_dpr.clone()];
+
+        let report = unit_dependents(
+            &dprs,
+            &mut cache,
+            None,
+            "leaf",
+            &Assumptions::default(),
+            false,
+        )
+        .unwrap();
+        let found: Vec<(&str, bool, usize)> = report
+            .units
+            .iter()
+            .map(|unit| (unit.name.as_str(), unit.direct, unit.dprs.len()))
+            .collect();
+        assert_eq!(found, vec![("Direct", true, 0), ("Outer", false, 1)]);
+        assert_eq!(report.units[1].dprs, vec![app_dpr.clone()]);
+
+        let report = unit_dependents(
+            &dprs,
+            &mut cache,
+            None,
+            "Leaf",
+            &Assumptions::default(),
+            true,
+        )
+        .unwrap();
+        let names: Vec<&str> = report.units.iter().map(|unit| unit.name.as_str()).collect();
+        assert_eq!(names, vec!["Direct"]);
+    }
+
     fn temp_dir() -> PathBuf {
         let mut root = env::temp_dir();
         let nanos = SystemTime::now()
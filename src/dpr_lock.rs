@@ -0,0 +1,137 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for another run to release a dpr by default.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Exclusive advisory lock on the `<dpr>.lock` file next to a dpr.
+///
+/// The lock file is removed again when the lock is dropped.
+#[derive(Debug)]
+pub struct DprLock {
+    file: Option<File>,
+    path: PathBuf,
+}
+
+/// Waits up to `timeout` for the lock of `dpr_path`; `Ok(None)` means another
+/// process still holds it.
+pub fn acquire(dpr_path: &Path, timeout: Duration) -> io::Result<Option<DprLock>> {
+    let path = lock_path(dpr_path);
+    let deadline = Instant::now() + timeout;
+    loop {
+        let file = open_lock_file(&path)?;
+        match file.try_lock() {
+            // The previous holder may have removed the file while we waited on it.
+            Ok(()) if is_current(&file, &path) => {
+                return Ok(Some(DprLock {
+                    file: Some(file),
+                    path,
+                }))
+            }
+            Ok(()) => continue,
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+fn lock_path(dpr_path: &Path) -> PathBuf {
+    let mut name = OsString::from(dpr_path.as_os_str());
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+#[cfg(windows)]
+fn open_lock_file(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_WRITE: u32 = 0x2;
+    // Without FILE_SHARE_DELETE the file cannot be removed while another run has it open.
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+        .open(path)
+}
+
+#[cfg(not(windows))]
+fn open_lock_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(held), Ok(current)) => held.dev() == current.dev() && held.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_current(_file: &File, _path: &Path) -> bool {
+    true
+}
+
+impl Drop for DprLock {
+    fn drop(&mut self) {
+        // Windows refuses to remove a file that is still open, so close it first.
+        if cfg!(windows) {
+            self.file.take();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_dir() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time")
+            .as_nanos();
+        let dir = env::temp_dir().join(format!("fixdpr_lock_{nanos}"));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    #[test]
+    fn lock_waits_for_the_holder_and_removes_its_file() {
+        let dpr_path = temp_dir().join("App.dpr");
+        let lock = acquire(&dpr_path, Duration::ZERO)
+            .expect("lock")
+            .expect("free lock");
+        assert!(lock_path(&dpr_path).is_file());
+        assert!(acquire(&dpr_path, Duration::from_millis(120))
+            .expect("lock")
+            .is_none());
+
+        drop(lock);
+        assert!(!lock_path(&dpr_path).exists());
+        let lock = acquire(&dpr_path, Duration::ZERO).expect("lock");
+        assert!(lock.is_some());
+    }
+}
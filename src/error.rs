@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum FixdprError {
+    /// A command line value is empty, malformed, or conflicts with another one.
+    InvalidArgument(String),
+    PathNotFound {
+        flag: String,
+        path: PathBuf,
+    },
+    NotADirectory {
+        flag: String,
+        path: PathBuf,
+    },
+    WrongFileKind {
+        flag: String,
+        path: PathBuf,
+        expected: &'static str,
+    },
+    PatternParse {
+        pattern: String,
+        message: String,
+    },
+    DelphiVersionNotFound(String),
+    RegistryLookup {
+        key: String,
+        source: io::Error,
+    },
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// The inputs were valid but the operation could not be completed.
+    Failed(String),
+}
+
+impl FixdprError {
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::InvalidArgument(message.into())
+    }
+
+    pub fn io_at(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    /// The exit code of the command line tool: 2 for bad arguments and 1 when
+    /// the operation itself failed.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io { .. } | Self::Failed(_) => 1,
+            Self::InvalidArgument(_)
+            | Self::PathNotFound { .. }
+            | Self::NotADirectory { .. }
+            | Self::WrongFileKind { .. }
+            | Self::PatternParse { .. }
+            | Self::DelphiVersionNotFound(_)
+            | Self::RegistryLookup { .. } => 2,
+        }
+    }
+}
+
+impl fmt::Display for FixdprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidArgument(message) | Self::Failed(message) => f.write_str(message),
+            Self::PathNotFound { flag, path } => {
+                write!(f, "{flag} does not exist: {}", path.display())
+            }
+            Self::NotADirectory { flag, path } => {
+                write!(f, "{flag} is not a directory: {}", path.display())
+            }
+            Self::WrongFileKind {
+                flag,
+                path,
+                expected,
+            } => write!(
+                f,
+                "{flag} must point to a {expected} file: {}",
+                path.display()
+            ),
+            Self::PatternParse { pattern, message } => {
+                write!(f, "invalid pattern '{pattern}': {message}")
+            }
+            Self::DelphiVersionNotFound(version) => {
+                write!(f, "--delphi-version not found in registry: {version}")
+            }
+            Self::RegistryLookup { key, source } => {
+                write!(f, "failed to query registry key {key}: {source}")
+            }
+            Self::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {source}", path.display()),
+            Self::Io { path: None, source } => write!(f, "{source}"),
+        }
+    }
+}
+
+impl Error for FixdprError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::RegistryLookup { source, .. } | Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FixdprError {
+    fn from(source: io::Error) -> Self {
+        Self::Io { path: None, source }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_keeps_flag_and_path_context() {
+        let err = FixdprError::PathNotFound {
+            flag: "--search-path".to_string(),
+            path: PathBuf::from("missing"),
+        };
+        assert_eq!(err.to_string(), "--search-path does not exist: missing");
+
+        let err = FixdprError::WrongFileKind {
+            flag: "DPR_FILE".to_string(),
+            path: PathBuf::from("App.pas"),
+            expected: ".dpr",
+        };
+        assert_eq!(
+            err.to_string(),
+            "DPR_FILE must point to a .dpr file: App.pas"
+        );
+    }
+
+    #[test]
+    fn io_errors_expose_their_source() {
+        let err = FixdprError::io_at("App.dpr", io::Error::new(io::ErrorKind::NotFound, "gone"));
+        assert_eq!(err.to_string(), "App.dpr: gone");
+        assert!(err.source().is_some());
+        assert!(FixdprError::invalid_argument("bad").source().is_none());
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use crate::diagnostic::Diagnostic;
+use crate::dpr_edit::DprDetail;
+use crate::warnings::Warnings;
+
+const PROGRESS_INTERVAL: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanProgress {
+    /// Files found so far while walking the search roots.
+    Walking {
+        pas_files: usize,
+        dpr_files: usize,
+    },
+    WalkFinished {
+        pas_files: usize,
+        dpr_files: usize,
+    },
+    /// Units loaded into the unit cache so far.
+    ParsingUnits {
+        done: usize,
+        total: usize,
+    },
+}
+
+/// Receives progress while scanning, building the unit cache, and editing dprs.
+///
+/// Every method has an empty default so sinks only implement what they show.
+pub trait EventSink {
+    fn on_scan_progress(&self, _progress: ScanProgress) {}
+
+    fn on_dpr_started(&self, _index: usize, _total: usize, _path: &Path) {}
+
+    fn on_dpr_finished(&self, _detail: &DprDetail) {}
+
+    fn on_warning(&self, _warning: &Diagnostic) {}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoEvents;
+
+impl EventSink for NoEvents {}
+
+pub(crate) fn should_report(done: usize, total: usize) -> bool {
+    done.is_multiple_of(PROGRESS_INTERVAL) || done == total
+}
+
+/// Forwards warnings pushed since `cursor` and advances it.
+pub(crate) fn forward_warnings(events: &dyn EventSink, warnings: &Warnings, cursor: &mut usize) {
+    for warning in warnings.iter().skip(*cursor) {
+        events.on_warning(warning);
+    }
+    *cursor = warnings.len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::DiagnosticCode;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Recorder {
+        warnings: RefCell<Vec<String>>,
+    }
+
+    impl EventSink for Recorder {
+        fn on_warning(&self, warning: &Diagnostic) {
+            self.warnings.borrow_mut().push(warning.message.clone());
+        }
+    }
+
+    #[test]
+    fn forward_warnings_only_sends_new_entries() {
+        let recorder = Recorder::default();
+        let mut warnings = Warnings::new();
+        let mut cursor = 0;
+        warnings.push(Diagnostic::warning(DiagnosticCode::ReadFailed, "a"));
+        forward_warnings(&recorder, &warnings, &mut cursor);
+        warnings.push(Diagnostic::warning(DiagnosticCode::ReadFailed, "a"));
+        warnings.push(Diagnostic::warning(DiagnosticCode::ReadFailed, "b"));
+        forward_warnings(&recorder, &warnings, &mut cursor);
+
+        assert_eq!(*recorder.warnings.borrow(), vec!["a", "a", "b"]);
+        assert_eq!(cursor, 3);
+    }
+
+    #[test]
+    fn should_report_every_interval_and_at_the_end() {
+        assert!(!should_report(1, 1000));
+        assert!(should_report(256, 1000));
+        assert!(should_report(1000, 1000));
+    }
+}
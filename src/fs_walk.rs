@@ -2,29 +2,228 @@ use std::collections::HashSet;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use walkdir::WalkDir;
 
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::error::FixdprError;
+use crate::events::{self, EventSink, NoEvents, ScanProgress};
+use crate::unit_cache::{Prefetched, SourceOptions};
+use crate::warnings::Warnings;
+
 #[derive(Debug)]
 pub struct FsScan {
     pub pas_files: Vec<PathBuf>,
     pub dpr_files: Vec<PathBuf>,
+    /// `.inc` files, for resolving `{$I}` directives by name.
+    pub inc_files: Vec<PathBuf>,
+    /// Problems that skipped part of the tree without aborting the scan.
+    pub warnings: Warnings,
+    /// Folders left out because their name is in the ignored folder names.
+    pub skipped_dirs: usize,
+    /// Files found under each search root, in the order the roots were given.
+    pub roots: Vec<RootCounts>,
+    /// Units parsed in the background during the walk, for the unit cache build.
+    pub prefetched: Prefetched,
+}
+
+/// Files found under one search root; a file reachable from several roots counts for the first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootCounts {
+    pub root: PathBuf,
+    pub pas_files: usize,
+    pub dpr_files: usize,
+}
+
+/// Position in `roots` of the first root containing `path`, matching how the scan counts files.
+pub fn root_index(roots: &[RootCounts], path: &Path) -> Option<usize> {
+    roots
+        .iter()
+        .position(|counts| path.starts_with(&counts.root))
+}
+
+/// Folder names skipped unless [`ScanOptions::default_ignores`] is turned off.
+pub const DEFAULT_IGNORED_DIR_NAMES: &[&str] =
+    &[".git", ".svn", "__history", "__recovery", "backup"];
+
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    follow_symlinks: bool,
+    max_depth: Option<usize>,
+    default_ignores: bool,
+    ignored_dir_names: Vec<String>,
+    strict: bool,
+    source: SourceOptions,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            follow_symlinks: false,
+            max_depth: None,
+            default_ignores: true,
+            ignored_dir_names: Vec::new(),
+            strict: false,
+            source: SourceOptions::default(),
+        }
+    }
+}
+
+impl ScanOptions {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    /// Skip folders named in [`DEFAULT_IGNORED_DIR_NAMES`]; on by default.
+    pub fn default_ignores(mut self, value: bool) -> Self {
+        self.default_ignores = value;
+        self
+    }
+
+    /// Also skip every folder with this name, compared case-insensitively.
+    pub fn ignore_dir_name(mut self, name: impl AsRef<str>) -> Self {
+        let name = name.as_ref().trim();
+        if !name.is_empty() {
+            self.ignored_dir_names.push(name.to_ascii_lowercase());
+        }
+        self
+    }
+
+    /// Abort on the first unreadable entry instead of warning and continuing.
+    pub fn strict(mut self, value: bool) -> Self {
+        self.strict = value;
+        self
+    }
+
+    /// Folder names that are skipped with these options.
+    pub fn ignored_dir_names(&self) -> Vec<&str> {
+        let defaults = if self.default_ignores {
+            DEFAULT_IGNORED_DIR_NAMES
+        } else {
+            &[]
+        };
+        let mut names: Vec<&str> = defaults.to_vec();
+        for name in &self.ignored_dir_names {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    fn skips_dir_name(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        (self.default_ignores && DEFAULT_IGNORED_DIR_NAMES.contains(&name.as_str()))
+            || self.ignored_dir_names.contains(&name)
+    }
+
+    /// Descend into symbolic links and junctions, walking each target directory once.
+    pub fn follow_symlinks(mut self, value: bool) -> Self {
+        self.follow_symlinks = value;
+        self
+    }
+
+    /// Descend at most this many directory levels below each root.
+    pub fn max_depth(mut self, value: Option<usize>) -> Self {
+        self.max_depth = value;
+        self
+    }
+
+    /// Skip `.pas` and `.dpr` files larger than `bytes` when parsing what the
+    /// scan found; 0 removes the limit.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.source.max_file_size = bytes;
+        self
+    }
+
+    /// Name units without a parsable `unit` header after their file stem; on by default.
+    pub fn stem_fallback(mut self, value: bool) -> Self {
+        self.source.stem_fallback = value;
+        self
+    }
+
+    /// How the files found by the scan are read.
+    pub fn source(&self) -> SourceOptions {
+        self.source
+    }
+
+    pub fn build(self) -> Self {
+        self
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct IgnoreMatcher {
-    prefixes: Vec<String>,
+    /// Rules in the order given; the last rule matching a path decides.
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Debug)]
+struct IgnoreRule {
+    negated: bool,
+    target: IgnoreTarget,
+}
+
+#[derive(Debug)]
+enum IgnoreTarget {
+    Prefix(String),
+    Pattern(GlobPattern),
+}
+
+impl IgnoreRule {
+    /// A rule covers the paths it matches and everything below them.
+    fn matches(&self, prefix_path: &str, match_path: &str) -> bool {
+        match &self.target {
+            IgnoreTarget::Prefix(prefix) => is_prefix(prefix_path, prefix),
+            IgnoreTarget::Pattern(pattern) => {
+                pattern.matches(match_path)
+                    || match_path
+                        .match_indices('/')
+                        .any(|(idx, _)| idx > 0 && pattern.matches(&match_path[..idx]))
+            }
+        }
+    }
+
+    /// Returns true when the rule could match a path below `dir`.
+    fn may_match_below(&self, prefix_dir: &str, match_dir: &str) -> bool {
+        match &self.target {
+            IgnoreTarget::Prefix(prefix) => is_prefix(prefix, prefix_dir),
+            IgnoreTarget::Pattern(pattern) => {
+                let dir = format!("{}/", match_dir.trim_end_matches('/'));
+                pattern
+                    .literal_prefixes()
+                    .any(|literal| literal.starts_with(&dir) || dir.starts_with(&literal))
+            }
+        }
+    }
 }
 
 impl IgnoreMatcher {
     pub fn is_ignored(&self, path: &Path) -> bool {
-        if self.prefixes.is_empty() {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let prefix_path = normalize_path_for_prefix_match(path);
+        let match_path = normalize_path_like_for_match(&path.to_string_lossy());
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&prefix_path, &match_path))
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    /// Returns true when a `!` rule could re-include something below the
+    /// ignored directory `dir`, so the walk still has to descend into it.
+    fn may_reinclude_below(&self, dir: &Path) -> bool {
+        if !self.rules.iter().any(|rule| rule.negated) {
             return false;
         }
-        let normalized = normalize_path_for_prefix_match(path);
-        self.prefixes
+        let prefix_dir = normalize_path_for_prefix_match(dir);
+        let match_dir = normalize_path_like_for_match(&dir.to_string_lossy());
+        self.rules
             .iter()
-            .any(|prefix| is_prefix(&normalized, prefix))
+            .any(|rule| rule.negated && rule.may_match_below(&prefix_dir, &match_dir))
     }
 }
 
@@ -32,22 +231,76 @@ impl IgnoreMatcher {
 pub struct DprIgnoreMatcher {
     patterns: Vec<GlobPattern>,
     normalized_patterns: Vec<String>,
+    /// `--ignore-program` globs, matched against the name in the dpr header.
+    program_patterns: Vec<GlobPattern>,
+    normalized_program_patterns: Vec<String>,
 }
 
 impl DprIgnoreMatcher {
     pub fn is_empty(&self) -> bool {
-        self.patterns.is_empty()
+        self.patterns.is_empty() && self.program_patterns.is_empty()
     }
 
     pub fn normalized_patterns(&self) -> &[String] {
         &self.normalized_patterns
     }
 
+    pub fn normalized_program_patterns(&self) -> &[String] {
+        &self.normalized_program_patterns
+    }
+
+    /// Also ignores dprs whose `program`/`library` name matches one of the
+    /// globs, such as `AutoBuild_*`.
+    pub fn with_program_patterns(mut self, raw_values: &[String]) -> Result<Self, FixdprError> {
+        for raw in raw_values {
+            let trimmed = raw.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            let normalized = trimmed.to_ascii_lowercase();
+            let pattern =
+                parse_glob_pattern(&normalized).map_err(|err| glob_error(trimmed, err))?;
+            if !self.normalized_program_patterns.contains(&normalized) {
+                self.program_patterns.push(pattern);
+                self.normalized_program_patterns.push(normalized);
+            }
+        }
+        Ok(self)
+    }
+
     pub fn is_ignored(&self, absolute_path: &str) -> bool {
+        self.matches(absolute_path)
+    }
+
+    /// Returns true when the dpr at `path` declares a program name matching
+    /// a program glob. Only the header is parsed; unreadable files are kept.
+    pub fn ignores_program(&self, path: &Path) -> bool {
+        if self.program_patterns.is_empty() {
+            return false;
+        }
+        let Some(name) = fs::read(path)
+            .ok()
+            .and_then(|bytes| crate::dpr_edit::dpr_program_name(&bytes))
+        else {
+            return false;
+        };
+        let name = name.to_ascii_lowercase();
+        self.program_patterns
+            .iter()
+            .any(|pattern| pattern.matches(&name))
+    }
+
+    /// [`Self::is_ignored`] by path or by declared program name.
+    pub fn ignores_dpr(&self, path: &Path) -> bool {
+        self.is_ignored(&path.to_string_lossy()) || self.ignores_program(path)
+    }
+
+    /// Returns true when any pattern matches; used for `--dpr-glob` include filters.
+    pub fn matches(&self, absolute_path: &str) -> bool {
         let normalized = normalize_path_like_for_match(absolute_path);
         self.patterns
             .iter()
-            .any(|pattern| glob_matches(&pattern.tokens, &normalized))
+            .any(|pattern| pattern.matches(&normalized))
     }
 }
 
@@ -55,13 +308,33 @@ impl DprIgnoreMatcher {
 pub struct DprFilterResult {
     pub included_files: Vec<PathBuf>,
     pub ignored_files: Vec<PathBuf>,
+    /// Files left out because they match none of the include globs.
+    pub filtered_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct SearchRootsResolution {
+    pub roots: Vec<PathBuf>,
+    /// Glob patterns, as given, that matched no directory.
+    pub unmatched_patterns: Vec<String>,
+}
+
+/// Returns the nearest folder at or above `start` that contains a `.git` entry.
+pub fn find_repo_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
 }
 
 pub fn canonicalize_root(root: &Path) -> PathBuf {
     canonicalize_if_exists(root)
 }
 
-pub fn resolve_search_roots(raw_values: &[String], cwd: &Path) -> Result<Vec<PathBuf>, String> {
+pub fn resolve_search_roots(
+    raw_values: &[String],
+    cwd: &Path,
+) -> Result<SearchRootsResolution, FixdprError> {
     resolve_roots(raw_values, cwd, "--search-path", true)
 }
 
@@ -69,8 +342,14 @@ pub fn resolve_optional_roots(
     raw_values: &[String],
     cwd: &Path,
     flag_name: &str,
-) -> Result<Vec<PathBuf>, String> {
-    resolve_roots(raw_values, cwd, flag_name, false)
+) -> Result<Vec<PathBuf>, FixdprError> {
+    let resolution = resolve_roots(raw_values, cwd, flag_name, false)?;
+    if let Some(pattern) = resolution.unmatched_patterns.first() {
+        return Err(FixdprError::invalid_argument(format!(
+            "{flag_name} pattern matched no directories: {pattern}"
+        )));
+    }
+    Ok(resolution.roots)
 }
 
 fn resolve_roots(
@@ -78,9 +357,10 @@ fn resolve_roots(
     cwd: &Path,
     flag_name: &str,
     require_at_least_one: bool,
-) -> Result<Vec<PathBuf>, String> {
+) -> Result<SearchRootsResolution, FixdprError> {
     let mut roots = Vec::new();
     let mut seen = HashSet::new();
+    let mut unmatched_patterns = Vec::new();
 
     for raw in raw_values {
         let trimmed = raw.trim();
@@ -94,68 +374,203 @@ fn resolve_roots(
             cwd.join(trimmed)
         };
 
+        if is_glob_pattern(trimmed) {
+            let (_, pattern) = compile_glob(trimmed, cwd)?;
+            let matches = expand_directory_glob(&absolute_path, &pattern);
+            if matches.is_empty() {
+                unmatched_patterns.push(trimmed.to_string());
+            }
+            for path in matches {
+                push_unique_root(&mut roots, &mut seen, &path);
+            }
+            continue;
+        }
+
         if !absolute_path.exists() {
-            return Err(format!(
-                "{flag_name} does not exist: {}",
-                absolute_path.display()
-            ));
+            return Err(FixdprError::PathNotFound {
+                flag: flag_name.to_string(),
+                path: absolute_path,
+            });
         }
         if !absolute_path.is_dir() {
-            return Err(format!(
-                "{flag_name} is not a directory: {}",
-                absolute_path.display()
-            ));
+            return Err(FixdprError::NotADirectory {
+                flag: flag_name.to_string(),
+                path: absolute_path,
+            });
         }
 
         push_unique_root(&mut roots, &mut seen, &absolute_path);
     }
 
     if require_at_least_one && roots.is_empty() {
-        return Err(format!("{flag_name} must be provided at least once"));
+        if !unmatched_patterns.is_empty() {
+            return Err(FixdprError::invalid_argument(format!(
+                "{flag_name} pattern matched no directories: {}",
+                unmatched_patterns.join(", ")
+            )));
+        }
+        return Err(FixdprError::invalid_argument(format!(
+            "{flag_name} must be provided at least once"
+        )));
     }
 
     roots.sort_by_key(|path| normalize_path_for_prefix_match(path));
-    Ok(roots)
+    Ok(SearchRootsResolution {
+        roots,
+        unmatched_patterns,
+    })
+}
+
+fn is_glob_pattern(value: &str) -> bool {
+    value.contains(['*', '?', '[', '{'])
 }
 
-pub fn build_ignore_matcher(raw_values: &[String], cwd: &Path) -> Result<IgnoreMatcher, String> {
-    let mut prefixes = Vec::new();
+/// Lists directories matching `pattern`, walking from the longest literal
+/// prefix of `path`, the same pattern as a plain path.
+fn expand_directory_glob(path: &Path, pattern: &GlobPattern) -> Vec<PathBuf> {
+    let mut base = PathBuf::new();
+    let mut wildcard_components = Vec::new();
+    for component in path.components() {
+        let text = component.as_os_str().to_string_lossy();
+        if wildcard_components.is_empty() && !is_glob_pattern(&text) {
+            base.push(component);
+        } else {
+            wildcard_components.push(text.into_owned());
+        }
+    }
+    if !base.is_dir() {
+        return Vec::new();
+    }
+
+    let max_depth = if wildcard_components.iter().any(|part| part.contains("**")) {
+        usize::MAX
+    } else {
+        wildcard_components.len()
+    };
+
+    let mut matches: Vec<PathBuf> = WalkDir::new(&base)
+        .min_depth(1)
+        .max_depth(max_depth)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_dir())
+        .filter(|entry| {
+            let candidate = normalize_path_like_for_match(&entry.path().to_string_lossy());
+            pattern.matches(&candidate)
+        })
+        .map(|entry| entry.into_path())
+        .collect();
+    matches.sort();
+    matches
+}
+
+pub fn build_ignore_matcher(
+    raw_values: &[String],
+    cwd: &Path,
+) -> Result<IgnoreMatcher, FixdprError> {
+    build_ignore_matcher_for("--ignore-path", raw_values, cwd)
+}
+
+/// [`build_ignore_matcher`] for the `--delphi-ignore-path` values, which
+/// apply to the scan of the Delphi fallback roots.
+pub fn build_delphi_ignore_matcher(
+    raw_values: &[String],
+    cwd: &Path,
+) -> Result<IgnoreMatcher, FixdprError> {
+    build_ignore_matcher_for("--delphi-ignore-path", raw_values, cwd)
+}
+
+fn build_ignore_matcher_for(
+    flag: &str,
+    raw_values: &[String],
+    cwd: &Path,
+) -> Result<IgnoreMatcher, FixdprError> {
+    let mut rules = Vec::new();
     for raw in raw_values {
         let trimmed = raw.trim();
+        let (negated, trimmed) = match trimmed.strip_prefix('!') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, trimmed),
+        };
         if trimmed.is_empty() {
             continue;
         }
+        if is_glob_pattern(trimmed) {
+            let (_, pattern) = compile_glob(trimmed, cwd)?;
+            rules.push(IgnoreRule {
+                negated,
+                target: IgnoreTarget::Pattern(pattern),
+            });
+            continue;
+        }
 
         let mut path = PathBuf::from(trimmed);
         if path.is_relative() {
             path = cwd.join(path);
         }
         if !path.exists() {
-            return Err(format!("--ignore-path does not exist: {}", path.display()));
+            return Err(FixdprError::PathNotFound {
+                flag: flag.to_string(),
+                path,
+            });
         }
         if !path.is_dir() {
-            return Err(format!(
-                "--ignore-path is not a directory: {}",
-                path.display()
-            ));
+            return Err(FixdprError::NotADirectory {
+                flag: flag.to_string(),
+                path,
+            });
         }
         let path = canonicalize_if_exists(&path);
         let normalized = normalize_path_for_prefix_match(&path);
         if !normalized.is_empty() {
-            prefixes.push(normalized);
+            rules.push(IgnoreRule {
+                negated,
+                target: IgnoreTarget::Prefix(normalized),
+            });
         }
     }
 
-    prefixes.sort();
-    prefixes.dedup();
+    Ok(IgnoreMatcher { rules })
+}
+
+/// What relative `--ignore-dpr` patterns are anchored to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DprGlobAnchor {
+    /// Resolve relative patterns from the current working directory.
+    #[default]
+    Cwd,
+    /// Resolve relative patterns from each search root.
+    SearchRoot,
+}
+
+impl FromStr for DprGlobAnchor {
+    type Err = String;
 
-    Ok(IgnoreMatcher { prefixes })
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "cwd" => Ok(Self::Cwd),
+            "search-root" => Ok(Self::SearchRoot),
+            other => Err(format!(
+                "--ignore-dpr-relative-to must be cwd or search-root, got '{other}'"
+            )),
+        }
+    }
 }
 
 pub fn build_dpr_ignore_matcher(
     raw_values: &[String],
     cwd: &Path,
-) -> Result<DprIgnoreMatcher, String> {
+) -> Result<DprIgnoreMatcher, FixdprError> {
+    build_dpr_ignore_matcher_anchored(raw_values, &[cwd.to_path_buf()])
+}
+
+/// [`build_dpr_ignore_matcher`] with relative patterns anchored to every
+/// folder in `anchors`, giving one normalized pattern per anchor.
+pub fn build_dpr_ignore_matcher_anchored(
+    raw_values: &[String],
+    anchors: &[PathBuf],
+) -> Result<DprIgnoreMatcher, FixdprError> {
     let mut patterns = Vec::new();
     let mut normalized_patterns = Vec::new();
 
@@ -165,67 +580,171 @@ pub fn build_dpr_ignore_matcher(
             continue;
         }
 
-        let normalized = normalize_dpr_glob_pattern(trimmed, cwd);
-        patterns.push(GlobPattern {
-            tokens: parse_glob_tokens(&normalized),
-        });
-        normalized_patterns.push(normalized);
+        for anchor in anchors {
+            let (normalized, pattern) = compile_glob(trimmed, anchor)?;
+            if normalized_patterns.contains(&normalized) {
+                continue;
+            }
+            patterns.push(pattern);
+            normalized_patterns.push(normalized);
+        }
     }
 
     Ok(DprIgnoreMatcher {
         patterns,
         normalized_patterns,
+        ..DprIgnoreMatcher::default()
     })
 }
 
 pub fn scan_files(search_roots: &[PathBuf], ignore: &IgnoreMatcher) -> io::Result<FsScan> {
-    let mut pas_files = Vec::new();
-    let mut dpr_files = Vec::new();
-    let mut seen_pas = HashSet::new();
-    let mut seen_dpr = HashSet::new();
+    scan_files_with_events(search_roots, ignore, &NoEvents)
+}
+
+pub fn scan_files_with_events(
+    search_roots: &[PathBuf],
+    ignore: &IgnoreMatcher,
+    events: &dyn EventSink,
+) -> io::Result<FsScan> {
+    scan_files_with(search_roots, ignore, &ScanOptions::default(), events)
+}
+
+pub fn scan_files_with(
+    search_roots: &[PathBuf],
+    ignore: &IgnoreMatcher,
+    options: &ScanOptions,
+    events: &dyn EventSink,
+) -> io::Result<FsScan> {
+    scan_files_streaming(search_roots, ignore, options, events, &mut |_| {})
+}
 
+/// [`scan_files_with`], handing each `.pas` file to `on_pas` as soon as the walk finds it.
+///
+/// Files arrive in walk order; the returned scan is sorted as usual.
+pub fn scan_files_streaming(
+    search_roots: &[PathBuf],
+    ignore: &IgnoreMatcher,
+    options: &ScanOptions,
+    events: &dyn EventSink,
+    on_pas: &mut dyn FnMut(&Path),
+) -> io::Result<FsScan> {
+    let mut state = ScanState::default();
+    let mut roots = Vec::with_capacity(search_roots.len());
     for root in search_roots {
-        scan_files_under_root(
-            root,
-            ignore,
-            &mut pas_files,
-            &mut dpr_files,
-            &mut seen_pas,
-            &mut seen_dpr,
-        )?;
+        let (pas_before, dpr_before) = (state.pas_files.len(), state.dpr_files.len());
+        scan_files_under_root(root, ignore, options, &mut state, events, on_pas)?;
+        roots.push(RootCounts {
+            root: root.clone(),
+            pas_files: state.pas_files.len() - pas_before,
+            dpr_files: state.dpr_files.len() - dpr_before,
+        });
     }
 
+    let ScanState {
+        mut pas_files,
+        mut dpr_files,
+        mut inc_files,
+        warnings,
+        skipped_dirs,
+        ..
+    } = state;
     pas_files.sort();
     dpr_files.sort();
+    inc_files.sort();
+    events.on_scan_progress(ScanProgress::WalkFinished {
+        pas_files: pas_files.len(),
+        dpr_files: dpr_files.len(),
+    });
 
     Ok(FsScan {
         pas_files,
         dpr_files,
+        inc_files,
+        warnings,
+        skipped_dirs,
+        roots,
+        prefetched: Prefetched::default(),
     })
 }
 
+#[derive(Default)]
+struct ScanState {
+    pas_files: Vec<PathBuf>,
+    dpr_files: Vec<PathBuf>,
+    seen_pas: HashSet<String>,
+    seen_dpr: HashSet<String>,
+    inc_files: Vec<PathBuf>,
+    seen_inc: HashSet<String>,
+    visited_dirs: HashSet<PathBuf>,
+    warnings: Warnings,
+    skipped_dirs: usize,
+}
+
 fn scan_files_under_root(
     search_root: &Path,
     ignore: &IgnoreMatcher,
-    pas_files: &mut Vec<PathBuf>,
-    dpr_files: &mut Vec<PathBuf>,
-    seen_pas: &mut HashSet<String>,
-    seen_dpr: &mut HashSet<String>,
+    options: &ScanOptions,
+    state: &mut ScanState,
+    events: &dyn EventSink,
+    on_pas: &mut dyn FnMut(&Path),
 ) -> io::Result<()> {
-    let walker = WalkDir::new(search_root)
-        .follow_links(false)
-        .into_iter()
-        .filter_entry(|entry| !ignore.is_ignored(entry.path()));
+    let follow_symlinks = options.follow_symlinks;
+    let visited_dirs = &mut state.visited_dirs;
+    let skipped_dirs = &mut state.skipped_dirs;
+    let mut walker = WalkDir::new(search_root).follow_links(follow_symlinks);
+    if let Some(max_depth) = options.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+    let walker = walker.into_iter().filter_entry(|entry| {
+        // Ignored directories are still walked when a `!` rule may re-include
+        // something below them; their files are checked one by one.
+        if ignore.is_ignored(entry.path())
+            && !(entry.file_type().is_dir() && ignore.may_reinclude_below(entry.path()))
+        {
+            return false;
+        }
+        if entry.depth() > 0
+            && entry.file_type().is_dir()
+            && options.skips_dir_name(&entry.file_name().to_string_lossy())
+        {
+            *skipped_dirs += 1;
+            return false;
+        }
+        // Directories reached through several links are only walked once.
+        !(follow_symlinks
+            && entry.file_type().is_dir()
+            && !visited_dirs.insert(canonicalize_if_exists(entry.path())))
+    });
 
+    let mut truncated_dirs = 0;
     for entry in walker {
         let entry = match entry {
             Ok(value) => value,
-            Err(err) => {
+            Err(err) if follow_symlinks && err.loop_ancestor().is_some() => continue,
+            Err(err) if follow_symlinks && is_broken_link(&err) => {
+                let path = err.path().map(Path::to_path_buf).unwrap_or_default();
+                state.warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::BrokenSymlink,
+                        format!("broken symbolic link skipped: {}", path.display()),
+                    )
+                    .at(&path),
+                );
+                continue;
+            }
+            Err(err) if options.strict || err.depth() == 0 => {
                 return Err(io::Error::other(err));
             }
+            Err(err) => {
+                state.warnings.push(unreadable_entry_warning(&err));
+                continue;
+            }
         };
 
         if !entry.file_type().is_file() {
+            if entry.file_type().is_dir() && Some(entry.depth()) == options.max_depth {
+                truncated_dirs += 1;
+            }
             continue;
         }
 
@@ -234,19 +753,130 @@ fn scan_files_under_root(
             continue;
         }
 
-        let dedupe_key = normalize_path_for_prefix_match(path);
+        let dedupe_key = if follow_symlinks {
+            normalize_path_for_prefix_match(&canonicalize_if_exists(path))
+        } else {
+            normalize_path_for_prefix_match(path)
+        };
         if has_extension(path, "pas") {
-            if seen_pas.insert(dedupe_key) {
-                pas_files.push(path.to_path_buf());
+            if !state.seen_pas.insert(dedupe_key) {
+                continue;
+            }
+            state.pas_files.push(path.to_path_buf());
+            on_pas(path);
+        } else if is_dpr_file(path) {
+            if !state.seen_dpr.insert(dedupe_key) {
+                continue;
             }
-        } else if has_extension(path, "dpr") && seen_dpr.insert(dedupe_key) {
-            dpr_files.push(path.to_path_buf());
+            state.dpr_files.push(path.to_path_buf());
+        } else if has_extension(path, "inc") {
+            if state.seen_inc.insert(dedupe_key) {
+                state.inc_files.push(path.to_path_buf());
+            }
+            continue;
+        } else {
+            continue;
+        }
+        let found = state.pas_files.len() + state.dpr_files.len();
+        if events::should_report(found, usize::MAX) {
+            events.on_scan_progress(ScanProgress::Walking {
+                pas_files: state.pas_files.len(),
+                dpr_files: state.dpr_files.len(),
+            });
         }
     }
 
+    if truncated_dirs > 0 {
+        state.warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::ScanDepthLimited,
+                format!(
+                    "scan depth limit {} left {truncated_dirs} directories unscanned under {}",
+                    options.max_depth.unwrap_or_default(),
+                    search_root.display()
+                ),
+            )
+            .at(search_root),
+        );
+    }
+
     Ok(())
 }
 
+fn unreadable_entry_warning(err: &walkdir::Error) -> Diagnostic {
+    let path = err.path().map(Path::to_path_buf).unwrap_or_default();
+    let permission_denied = err
+        .io_error()
+        .is_some_and(|io_err| io_err.kind() == io::ErrorKind::PermissionDenied);
+    let diagnostic = if permission_denied {
+        Diagnostic::warning(
+            DiagnosticCode::PermissionDenied,
+            format!("permission denied, skipped: {}", path.display()),
+        )
+    } else {
+        Diagnostic::warning(
+            DiagnosticCode::UnreadableEntry,
+            format!("failed to read {}, skipped: {err}", path.display()),
+        )
+    };
+    diagnostic.at(&path)
+}
+
+fn is_broken_link(err: &walkdir::Error) -> bool {
+    err.io_error()
+        .is_some_and(|io_err| io_err.kind() == io::ErrorKind::NotFound)
+        && err
+            .path()
+            .is_some_and(|path| path.symlink_metadata().is_ok() && !path.exists())
+}
+
+/// Resolves a Windows-authored path on a case-sensitive file system, matching
+/// each component case-insensitively. Returns `None` unless exactly one path matches.
+pub(crate) fn find_path_ignoring_case(base: &Path, raw: &str) -> Option<PathBuf> {
+    let normalized = raw.replace('\\', "/");
+    let start = if normalized.starts_with('/') {
+        PathBuf::from("/")
+    } else {
+        base.to_path_buf()
+    };
+    let mut current = vec![start];
+    for part in normalized.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => {
+                for dir in &mut current {
+                    dir.push("..");
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let mut next = Vec::new();
+        for dir in &current {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry
+                    .file_name()
+                    .to_string_lossy()
+                    .eq_ignore_ascii_case(part)
+                {
+                    next.push(entry.path());
+                }
+            }
+        }
+        if next.is_empty() {
+            return None;
+        }
+        current = next;
+    }
+    match current.as_slice() {
+        [single] => Some(single.clone()),
+        _ => None,
+    }
+}
+
 pub fn filter_ignored_dpr_files(
     dpr_files: &[PathBuf],
     ignore_dpr_matcher: &DprIgnoreMatcher,
@@ -254,7 +884,7 @@ pub fn filter_ignored_dpr_files(
     if ignore_dpr_matcher.is_empty() {
         return DprFilterResult {
             included_files: dpr_files.to_vec(),
-            ignored_files: Vec::new(),
+            ..DprFilterResult::default()
         };
     }
 
@@ -262,8 +892,7 @@ pub fn filter_ignored_dpr_files(
     let mut ignored_files = Vec::new();
 
     for path in dpr_files {
-        let path_str = path.to_string_lossy();
-        if ignore_dpr_matcher.is_ignored(&path_str) {
+        if ignore_dpr_matcher.ignores_dpr(path) {
             ignored_files.push(path.clone());
         } else {
             included_files.push(path.clone());
@@ -273,16 +902,25 @@ pub fn filter_ignored_dpr_files(
     DprFilterResult {
         included_files,
         ignored_files,
+        filtered_files: Vec::new(),
     }
 }
 
-fn normalize_dpr_glob_pattern(raw_pattern: &str, cwd: &Path) -> String {
-    let absolute_pattern = if Path::new(raw_pattern).is_absolute() {
-        PathBuf::from(raw_pattern)
-    } else {
-        cwd.join(raw_pattern)
-    };
-    normalize_path_like_for_match(&absolute_pattern.to_string_lossy())
+/// Keeps only included files matching `include_matcher`; an empty matcher keeps all.
+pub fn filter_included_dpr_files(
+    mut result: DprFilterResult,
+    include_matcher: &DprIgnoreMatcher,
+) -> DprFilterResult {
+    if include_matcher.is_empty() {
+        return result;
+    }
+
+    let (included, filtered) = std::mem::take(&mut result.included_files)
+        .into_iter()
+        .partition(|path| include_matcher.matches(&path.to_string_lossy()));
+    result.included_files = included;
+    result.filtered_files = filtered;
+    result
 }
 
 fn normalize_path_like_for_match(value: &str) -> String {
@@ -303,7 +941,7 @@ fn strip_windows_verbatim_prefix(value: String) -> String {
 }
 
 fn canonicalize_if_exists(path: &Path) -> PathBuf {
-    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    crate::unit_cache::canonicalize_if_exists(path)
 }
 
 fn push_unique_root(roots: &mut Vec<PathBuf>, seen: &mut HashSet<String>, path: &Path) {
@@ -329,50 +967,275 @@ fn trim_trailing_separators(mut value: String) -> String {
     value
 }
 
+/// Characters a `\` makes literal in a glob. `*` and `?` are left out so that
+/// `apps\*.dpr` keeps meaning a folder separator on Windows.
+const GLOB_ESCAPABLE: [char; 5] = ['[', ']', '{', '}', ','];
+
 #[derive(Debug)]
 struct GlobPattern {
-    tokens: Vec<GlobToken>,
+    /// One token sequence per `{a,b}` expansion; any of them may match.
+    alternatives: Vec<Vec<GlobToken>>,
+}
+
+impl GlobPattern {
+    fn matches(&self, value: &str) -> bool {
+        self.alternatives
+            .iter()
+            .any(|tokens| glob_matches(tokens, value))
+    }
+
+    /// The literal text every alternative starts with, per alternative.
+    fn literal_prefixes(&self) -> impl Iterator<Item = String> + '_ {
+        self.alternatives.iter().map(|tokens| {
+            tokens
+                .iter()
+                .map_while(|token| match token {
+                    GlobToken::Literal(ch) => Some(*ch),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 enum GlobToken {
     Literal(char),
     Star,
     DoubleStar,
     Question,
+    /// `[...]`: one character inside (or, when negated, outside) the ranges.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct GlobSyntaxError {
+    /// Zero-based character offset into the parsed pattern.
+    position: usize,
+    message: &'static str,
+}
+
+/// Normalizes `raw_pattern` like a path, anchors it at `anchor` when relative,
+/// and parses it. Syntax errors name the pattern as given.
+fn compile_glob(raw_pattern: &str, anchor: &Path) -> Result<(String, GlobPattern), FixdprError> {
+    let mut relative = normalize_glob_for_match(raw_pattern);
+    while relative.contains("/./") {
+        relative = relative.replace("/./", "/");
+    }
+    while let Some(rest) = relative.strip_prefix("./") {
+        relative = rest.to_string();
+    }
+    if let Err(err) = parse_glob_pattern(&relative) {
+        return Err(glob_error(raw_pattern, err));
+    }
+    let normalized = if Path::new(raw_pattern).is_absolute() {
+        relative
+    } else {
+        let anchor = escape_glob_literal(&normalize_path_like_for_match(&anchor.to_string_lossy()));
+        format!("{}/{relative}", anchor.trim_end_matches('/'))
+    };
+    let pattern = parse_glob_pattern(&normalized).map_err(|err| glob_error(raw_pattern, err))?;
+    Ok((normalized, pattern))
+}
+
+fn glob_error(raw_pattern: &str, err: GlobSyntaxError) -> FixdprError {
+    FixdprError::PatternParse {
+        pattern: raw_pattern.to_string(),
+        message: format!("{} at position {}", err.message, err.position + 1),
+    }
+}
+
+/// [`normalize_path_like_for_match`] for glob text: a `\` in front of a
+/// [`GLOB_ESCAPABLE`] character stays an escape instead of a separator.
+fn normalize_glob_for_match(value: &str) -> String {
+    let mut normalized = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars
+                .peek()
+                .is_some_and(|next| GLOB_ESCAPABLE.contains(next)) =>
+            {
+                normalized.push('\\');
+                normalized.extend(chars.next());
+            }
+            '\\' => normalized.push('/'),
+            _ => normalized.push(ch.to_ascii_lowercase()),
+        }
+    }
+    strip_windows_verbatim_prefix(normalized)
+}
+
+fn escape_glob_literal(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if GLOB_ESCAPABLE.contains(&ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
 }
 
-fn parse_glob_tokens(pattern: &str) -> Vec<GlobToken> {
+fn parse_glob_pattern(pattern: &str) -> Result<GlobPattern, GlobSyntaxError> {
     let chars: Vec<char> = pattern.chars().collect();
-    let mut tokens = Vec::new();
-    let mut i = 0;
+    let mut pos = 0;
+    let alternatives = parse_glob_sequence(&chars, &mut pos, false)?;
+    Ok(GlobPattern { alternatives })
+}
 
-    while i < chars.len() {
-        match chars[i] {
+/// Parses up to the end of the pattern or, inside braces, up to the next `,`
+/// or `}`. Returns every token sequence the braces expand to.
+fn parse_glob_sequence(
+    chars: &[char],
+    pos: &mut usize,
+    in_braces: bool,
+) -> Result<Vec<Vec<GlobToken>>, GlobSyntaxError> {
+    let mut sequences = vec![Vec::new()];
+    while let Some(&ch) = chars.get(*pos) {
+        let token = match ch {
+            ',' | '}' if in_braces => break,
+            '}' => {
+                return Err(GlobSyntaxError {
+                    position: *pos,
+                    message: "unmatched '}'",
+                })
+            }
+            ']' => {
+                return Err(GlobSyntaxError {
+                    position: *pos,
+                    message: "unmatched ']'",
+                })
+            }
+            '{' => {
+                let open = *pos;
+                *pos += 1;
+                let mut branches = Vec::new();
+                loop {
+                    branches.extend(parse_glob_sequence(chars, pos, true)?);
+                    match chars.get(*pos) {
+                        Some(',') => *pos += 1,
+                        Some('}') => {
+                            *pos += 1;
+                            break;
+                        }
+                        _ => {
+                            return Err(GlobSyntaxError {
+                                position: open,
+                                message: "unclosed '{'",
+                            })
+                        }
+                    }
+                }
+                sequences = sequences
+                    .iter()
+                    .flat_map(|prefix| {
+                        branches.iter().map(move |branch| {
+                            let mut combined = prefix.clone();
+                            combined.extend(branch.iter().cloned());
+                            combined
+                        })
+                    })
+                    .collect();
+                continue;
+            }
+            '[' => parse_glob_class(chars, pos)?,
             '*' => {
                 let mut run = 1;
-                while i + run < chars.len() && chars[i + run] == '*' {
+                while chars.get(*pos + run) == Some(&'*') {
                     run += 1;
                 }
+                *pos += run;
                 if run >= 2 {
-                    tokens.push(GlobToken::DoubleStar);
+                    GlobToken::DoubleStar
                 } else {
-                    tokens.push(GlobToken::Star);
+                    GlobToken::Star
                 }
-                i += run;
             }
             '?' => {
-                tokens.push(GlobToken::Question);
-                i += 1;
+                *pos += 1;
+                GlobToken::Question
+            }
+            '\\' => {
+                let Some(&escaped) = chars.get(*pos + 1) else {
+                    return Err(GlobSyntaxError {
+                        position: *pos,
+                        message: "dangling '\\'",
+                    });
+                };
+                *pos += 2;
+                GlobToken::Literal(escaped)
             }
-            ch => {
-                tokens.push(GlobToken::Literal(ch));
-                i += 1;
+            _ => {
+                *pos += 1;
+                GlobToken::Literal(ch)
             }
+        };
+        for sequence in &mut sequences {
+            sequence.push(token.clone());
         }
     }
+    Ok(sequences)
+}
 
-    tokens
+/// Parses a `[...]` class starting at `chars[*pos] == '['`. A leading `!` or
+/// `^` negates it, and a `]` right after the opening bracket is literal.
+fn parse_glob_class(chars: &[char], pos: &mut usize) -> Result<GlobToken, GlobSyntaxError> {
+    let open = *pos;
+    let unclosed = GlobSyntaxError {
+        position: open,
+        message: "unclosed '['",
+    };
+    *pos += 1;
+    let negated = matches!(chars.get(*pos), Some('!' | '^'));
+    if negated {
+        *pos += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let start_pos = *pos;
+        let start = match chars.get(*pos) {
+            None => return Err(unclosed),
+            Some(']') if !first => {
+                *pos += 1;
+                break;
+            }
+            Some('\\') => {
+                *pos += 1;
+                *chars.get(*pos).ok_or(unclosed)?
+            }
+            Some(&ch) => ch,
+        };
+        *pos += 1;
+        first = false;
+        let end =
+            if chars.get(*pos) == Some(&'-') && !matches!(chars.get(*pos + 1), Some(']') | None) {
+                *pos += 1;
+                let end = match chars[*pos] {
+                    '\\' => {
+                        *pos += 1;
+                        *chars.get(*pos).ok_or(unclosed)?
+                    }
+                    ch => ch,
+                };
+                *pos += 1;
+                if end < start {
+                    return Err(GlobSyntaxError {
+                        position: start_pos,
+                        message: "invalid range in '[...]'",
+                    });
+                }
+                end
+            } else {
+                start
+            };
+        ranges.push((start, end));
+    }
+    Ok(GlobToken::Class { negated, ranges })
 }
 
 fn glob_matches(tokens: &[GlobToken], value: &str) -> bool {
@@ -395,12 +1258,12 @@ fn glob_matches_from(
     let matched = if token_idx == tokens.len() {
         value_idx == value.len()
     } else {
-        match tokens[token_idx] {
+        match &tokens[token_idx] {
             GlobToken::Literal(expected) => {
                 value
                     .get(value_idx)
                     .copied()
-                    .map(|ch| ch == expected)
+                    .map(|ch| ch == *expected)
                     .unwrap_or(false)
                     && glob_matches_from(tokens, value, token_idx + 1, value_idx + 1, memo)
             }
@@ -412,6 +1275,20 @@ fn glob_matches_from(
                     .unwrap_or(false)
                     && glob_matches_from(tokens, value, token_idx + 1, value_idx + 1, memo)
             }
+            GlobToken::Class { negated, ranges } => {
+                value
+                    .get(value_idx)
+                    .copied()
+                    .map(|ch| {
+                        ch != '/'
+                            && ranges
+                                .iter()
+                                .any(|&(start, end)| (start..=end).contains(&ch))
+                                != *negated
+                    })
+                    .unwrap_or(false)
+                    && glob_matches_from(tokens, value, token_idx + 1, value_idx + 1, memo)
+            }
             GlobToken::Star => {
                 let mut idx = value_idx;
                 loop {
@@ -463,6 +1340,20 @@ fn is_prefix(path: &str, prefix: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// Program file extensions with a fixable uses list: Delphi `.dpr` and Lazarus/FPC `.lpr`.
+pub const PROGRAM_EXTENSIONS: &[&str] = &["dpr", "lpr"];
+
+/// Whether `path` is a `.dpr` or `.lpr` program file.
+pub fn is_dpr_file(path: &Path) -> bool {
+    PROGRAM_EXTENSIONS
+        .iter()
+        .any(|extension| has_extension(path, extension))
+}
+
+pub fn is_pas_file(path: &Path) -> bool {
+    has_extension(path, "pas")
+}
+
 fn has_extension(path: &Path, extension: &str) -> bool {
     path.extension()
         .and_then(|ext| ext.to_str())
@@ -470,6 +1361,92 @@ fn has_extension(path: &Path, extension: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// `value` resolved against `cwd`; `flag_name` names it in the error for an empty value.
+pub fn resolve_path_with_flag(
+    value: &str,
+    cwd: &Path,
+    flag_name: &str,
+) -> Result<PathBuf, FixdprError> {
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Err(FixdprError::invalid_argument(format!(
+            "{flag_name} cannot be empty"
+        )));
+    }
+
+    let mut path = PathBuf::from(trimmed);
+    if path.is_relative() {
+        path = cwd.join(path);
+    }
+    Ok(path)
+}
+
+/// Fails unless `path` is an existing `.pas` file, naming it after `flag_name`.
+pub fn validate_pas_file_path(path: &Path, flag_name: &str) -> Result<(), FixdprError> {
+    if !path.is_file() {
+        return Err(FixdprError::PathNotFound {
+            flag: flag_name.to_string(),
+            path: path.to_path_buf(),
+        });
+    }
+    if !is_pas_file(path) {
+        return Err(FixdprError::WrongFileKind {
+            flag: flag_name.to_string(),
+            path: path.to_path_buf(),
+            expected: ".pas",
+        });
+    }
+    Ok(())
+}
+
+/// Fails unless `path` is an existing `.dpr` or `.lpr` file, naming it after `flag_name`.
+pub fn validate_dpr_file_path(path: &Path, flag_name: &str) -> Result<(), FixdprError> {
+    if !path.is_file() {
+        return Err(FixdprError::PathNotFound {
+            flag: flag_name.to_string(),
+            path: path.to_path_buf(),
+        });
+    }
+    if !is_dpr_file(path) {
+        return Err(FixdprError::WrongFileKind {
+            flag: flag_name.to_string(),
+            path: path.to_path_buf(),
+            expected: ".dpr or .lpr",
+        });
+    }
+    Ok(())
+}
+
+/// Whether `paths` holds `target`, ignoring case and separator style.
+pub fn contains_path(paths: &[PathBuf], target: &Path) -> bool {
+    let target_key = normalize_path_key(target);
+    paths
+        .iter()
+        .any(|path| normalize_path_key(path) == target_key)
+}
+
+/// `paths` sorted without entries that differ only in case or separator style.
+pub fn dedupe_paths(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut deduped = Vec::new();
+    let mut seen = HashSet::new();
+
+    for path in paths {
+        let key = normalize_path_key(&path);
+        if seen.insert(key) {
+            deduped.push(path);
+        }
+    }
+
+    deduped.sort_by_key(|path| normalize_path_key(path));
+    deduped
+}
+
+fn normalize_path_key(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('/', "\\")
+        .to_ascii_lowercase()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -494,7 +1471,8 @@ mod tests {
             canonicalize_if_exists(&root.join("app1")),
             canonicalize_if_exists(&root.join("app2")),
         ];
-        assert_eq!(resolved, expected);
+        assert_eq!(resolved.roots, expected);
+        assert!(resolved.unmatched_patterns.is_empty());
     }
 
     #[test]
@@ -504,7 +1482,43 @@ mod tests {
         fs::create_dir_all(root.join("app1")).expect("create app1");
 
         let resolved = resolve_search_roots(&["repo/app1".to_string()], &cwd).expect("roots");
-        assert_eq!(resolved, vec![canonicalize_if_exists(&root.join("app1"))]);
+        assert_eq!(
+            resolved.roots,
+            vec![canonicalize_if_exists(&root.join("app1"))]
+        );
+    }
+
+    #[test]
+    fn resolve_search_roots_expands_globs_and_reports_unmatched_patterns() {
+        let cwd = temp_dir("fixdpr_search_roots_glob_");
+        fs::create_dir_all(cwd.join("apps/one/src")).expect("create one");
+        fs::create_dir_all(cwd.join("apps/two")).expect("create two");
+        fs::write(cwd.join("apps/readme.txt"), "x").expect("create file");
+
+        let resolved = resolve_search_roots(
+            &[
+                "apps/*".to_string(),
+                "aps/*".to_string(),
+                "apps/**/src".to_string(),
+            ],
+            &cwd,
+        )
+        .expect("roots");
+        assert_eq!(
+            resolved.roots,
+            vec![
+                canonicalize_if_exists(&cwd.join("apps/one")),
+                canonicalize_if_exists(&cwd.join("apps/one/src")),
+                canonicalize_if_exists(&cwd.join("apps/two")),
+            ]
+        );
+        assert_eq!(resolved.unmatched_patterns, vec!["aps/*".to_string()]);
+
+        let err = resolve_search_roots(&["aps/*".to_string()], &cwd).expect_err("no roots");
+        assert_eq!(
+            err.to_string(),
+            "--search-path pattern matched no directories: aps/*"
+        );
     }
 
     #[test]
@@ -516,7 +1530,10 @@ mod tests {
 
         let path = root.join("app1.txt").to_string_lossy().to_string();
         let err = resolve_search_roots(&[path], &cwd).expect_err("should reject file path");
-        assert!(err.contains("--search-path is not a directory"), "{err}");
+        assert!(
+            err.to_string().contains("--search-path is not a directory"),
+            "{err}"
+        );
     }
 
     #[test]
@@ -527,7 +1544,11 @@ mod tests {
 
         let missing = root.join("missing").to_string_lossy().to_string();
         let err = resolve_search_roots(&[missing], &cwd).expect_err("should reject missing path");
-        assert!(err.contains("--search-path does not exist"), "{err}");
+        assert!(matches!(err, FixdprError::PathNotFound { .. }), "{err}");
+        assert!(
+            err.to_string().contains("--search-path does not exist"),
+            "{err}"
+        );
     }
 
     #[test]
@@ -544,7 +1565,10 @@ mod tests {
         fs::create_dir_all(cwd.join("repo")).expect("create repo");
         let err = resolve_optional_roots(&["repo/missing".to_string()], &cwd, "--delphi-path")
             .expect_err("missing");
-        assert!(err.contains("--delphi-path does not exist"), "{err}");
+        assert!(
+            err.to_string().contains("--delphi-path does not exist"),
+            "{err}"
+        );
     }
 
     #[test]
@@ -563,7 +1587,328 @@ mod tests {
         let cwd = temp_dir("fixdpr_ignore_path_missing_");
         fs::create_dir_all(cwd.join("repo")).expect("create repo");
         let err = build_ignore_matcher(&["repo/missing".to_string()], &cwd).expect_err("missing");
-        assert!(err.contains("--ignore-path does not exist"), "{err}");
+        assert!(
+            err.to_string().contains("--ignore-path does not exist"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn build_ignore_matcher_glob_skips_matching_folders_anywhere() {
+        let cwd = temp_dir("fixdpr_ignore_path_glob_");
+        let root = canonicalize_if_exists(&cwd);
+        for dir in ["app/__history", "lib/deep/__History", "lib/backup.old"] {
+            fs::create_dir_all(root.join(dir)).expect("create dir");
+        }
+        for file in [
+            "app/App.dpr",
+            "app/UnitA.pas",
+            "app/__history/UnitA.pas",
+            "lib/deep/__History/UnitB.pas",
+            "lib/deep/UnitB.pas",
+            "lib/backup.old/UnitC.pas",
+        ] {
+            fs::write(root.join(file), "x").expect("write file");
+        }
+
+        let matcher = build_ignore_matcher(
+            &["**/__history".to_string(), "lib/backup.*".to_string()],
+            &root,
+        )
+        .expect("matcher");
+        assert!(matcher.is_ignored(&root.join("app/__history")));
+        assert!(!matcher.is_ignored(&root.join("app/history")));
+
+        let options = ScanOptions::builder().default_ignores(false).build();
+        let scan = scan_files_with(std::slice::from_ref(&root), &matcher, &options, &NoEvents)
+            .expect("scan");
+        assert_eq!(
+            scan.pas_files,
+            vec![root.join("app/UnitA.pas"), root.join("lib/deep/UnitB.pas")]
+        );
+        assert_eq!(scan.dpr_files, vec![root.join("app/App.dpr")]);
+    }
+
+    #[test]
+    fn build_ignore_matcher_negation_reincludes_subtree_last_match_wins() {
+        let cwd = temp_dir("fixdpr_ignore_path_negated_");
+        let root = canonicalize_if_exists(&cwd);
+        for dir in ["app", "lib/third_party/zlib", "lib/third_party/ourfork/sub"] {
+            fs::create_dir_all(root.join(dir)).expect("create dir");
+        }
+        for file in [
+            "app/UnitA.pas",
+            "lib/third_party/zlib/ZLib.pas",
+            "lib/third_party/ourfork/Fork.pas",
+            "lib/third_party/ourfork/sub/ForkSub.pas",
+        ] {
+            fs::write(root.join(file), "x").expect("write file");
+        }
+        let options = ScanOptions::builder().default_ignores(false).build();
+        let scan_pas = |values: &[&str]| {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            let matcher = build_ignore_matcher(&values, &root).expect("matcher");
+            let scan = scan_files_with(std::slice::from_ref(&root), &matcher, &options, &NoEvents)
+                .expect("scan");
+            let mut files = scan.pas_files;
+            files.sort();
+            files
+        };
+        let reincluded = vec![
+            root.join("app/UnitA.pas"),
+            root.join("lib/third_party/ourfork/Fork.pas"),
+            root.join("lib/third_party/ourfork/sub/ForkSub.pas"),
+        ];
+
+        assert_eq!(
+            scan_pas(&["lib/third_party", "!lib/third_party/ourfork"]),
+            reincluded
+        );
+        assert_eq!(
+            scan_pas(&["**/third_party", "!**/third_party/ourfork"]),
+            reincluded
+        );
+        assert_eq!(
+            scan_pas(&["!lib/third_party/ourfork", "lib/third_party"]),
+            vec![root.join("app/UnitA.pas")]
+        );
+
+        let matcher = build_ignore_matcher(
+            &[
+                "lib/third_party".to_string(),
+                "!lib/third_party/ourfork".to_string(),
+            ],
+            &root,
+        )
+        .expect("matcher");
+        assert!(matcher.is_ignored(&root.join("lib/third_party/zlib/ZLib.pas")));
+        assert!(!matcher.is_ignored(&root.join("lib/third_party/ourfork/sub/ForkSub.pas")));
+        assert!(matcher.may_reinclude_below(&root.join("lib/third_party")));
+        assert!(!matcher.may_reinclude_below(&root.join("lib/third_party/zlib")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_files_with_follow_symlinks_walks_each_target_once() {
+        use std::os::unix::fs::symlink;
+
+        let temp = canonicalize_if_exists(&temp_dir("fixdpr_scan_symlinks_"));
+        let root = temp.join("repo");
+        let shared = temp.join("shared");
+        fs::create_dir_all(root.join("app")).expect("create app");
+        fs::create_dir_all(&shared).expect("create shared");
+        fs::write(root.join("app/App.dpr"), "x").expect("write dpr");
+        fs::write(shared.join("Shared.pas"), "x").expect("write shared");
+        symlink(&shared, root.join("shared_a")).expect("link a");
+        symlink(&shared, root.join("app/shared_b")).expect("link b");
+        symlink(&root, root.join("app/loop")).expect("link loop");
+        symlink(temp.join("missing"), root.join("broken")).expect("link broken");
+
+        let ignore = IgnoreMatcher::default();
+        let scan = scan_files(std::slice::from_ref(&root), &ignore).expect("scan");
+        assert!(scan.pas_files.is_empty());
+        assert!(scan.warnings.is_empty());
+
+        let options = ScanOptions::builder().follow_symlinks(true).build();
+        let scan = scan_files_with(std::slice::from_ref(&root), &ignore, &options, &NoEvents)
+            .expect("scan following links");
+        assert_eq!(scan.dpr_files, vec![root.join("app/App.dpr")]);
+        assert_eq!(scan.pas_files.len(), 1, "{:?}", scan.pas_files);
+        assert!(scan.pas_files[0].ends_with("Shared.pas"));
+        let warnings: Vec<_> = scan.warnings.iter().collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, DiagnosticCode::BrokenSymlink);
+        assert!(warnings[0].message.contains("broken"), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn scan_files_lists_lazarus_programs_with_dprs() {
+        let root = temp_dir("fixdpr_scan_lpr_");
+        fs::create_dir_all(&root).expect("create root");
+        let root = canonicalize_if_exists(&root);
+        fs::write(root.join("App.dpr"), "x").expect("write dpr");
+        fs::write(root.join("LazApp.LPR"), "x").expect("write lpr");
+        fs::write(root.join("LazApp.lpi"), "x").expect("write lpi");
+
+        let scan =
+            scan_files(std::slice::from_ref(&root), &IgnoreMatcher::default()).expect("scan");
+        let mut dprs = scan.dpr_files.clone();
+        dprs.sort();
+        assert_eq!(dprs, vec![root.join("App.dpr"), root.join("LazApp.LPR")]);
+    }
+
+    #[test]
+    fn scan_files_with_max_depth_reports_truncated_directories() {
+        let root = canonicalize_if_exists(&temp_dir("fixdpr_scan_max_depth_"));
+        fs::create_dir_all(root.join("app/third_party/mirror")).expect("create dirs");
+        fs::create_dir_all(root.join("lib")).expect("create lib");
+        fs::write(root.join("App.dpr"), "x").expect("write dpr");
+        fs::write(root.join("app/UnitA.pas"), "x").expect("write unit a");
+        fs::write(root.join("app/third_party/UnitB.pas"), "x").expect("write unit b");
+        fs::write(root.join("app/third_party/mirror/UnitC.pas"), "x").expect("write unit c");
+
+        let ignore = IgnoreMatcher::default();
+        let options = ScanOptions::builder().max_depth(Some(2)).build();
+        let scan = scan_files_with(std::slice::from_ref(&root), &ignore, &options, &NoEvents)
+            .expect("scan");
+        assert_eq!(scan.pas_files, vec![root.join("app/UnitA.pas")]);
+        assert_eq!(scan.dpr_files, vec![root.join("App.dpr")]);
+        let warnings: Vec<_> = scan.warnings.iter().collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, DiagnosticCode::ScanDepthLimited);
+        assert!(
+            warnings[0].message.contains("limit 2 left 1 directories"),
+            "{}",
+            warnings[0]
+        );
+
+        let options = ScanOptions::builder().max_depth(Some(4)).build();
+        let scan = scan_files_with(std::slice::from_ref(&root), &ignore, &options, &NoEvents)
+            .expect("scan");
+        assert_eq!(scan.pas_files.len(), 3);
+        assert!(scan.warnings.is_empty());
+    }
+
+    #[test]
+    fn scan_files_counts_files_per_root() {
+        let base = canonicalize_if_exists(&temp_dir("fixdpr_scan_root_counts_"));
+        let app = base.join("app");
+        let lib = base.join("lib");
+        let empty = base.join("empty");
+        for dir in [&app, &lib, &empty] {
+            fs::create_dir_all(dir).expect("create dir");
+        }
+        fs::write(app.join("App.dpr"), "x").expect("write dpr");
+        fs::write(app.join("UnitA.pas"), "x").expect("write pas");
+        fs::write(lib.join("UnitB.pas"), "x").expect("write pas");
+        fs::write(lib.join("UnitC.pas"), "x").expect("write pas");
+
+        let roots = vec![app.clone(), lib.clone(), empty.clone(), base.clone()];
+        let scan = scan_files(&roots, &IgnoreMatcher::default()).expect("scan");
+        let counts: Vec<(usize, usize)> = scan
+            .roots
+            .iter()
+            .map(|counts| (counts.pas_files, counts.dpr_files))
+            .collect();
+        assert_eq!(counts, vec![(1, 1), (2, 0), (0, 0), (0, 0)]);
+        assert_eq!(scan.roots[1].root, lib);
+        assert_eq!(root_index(&scan.roots, &lib.join("UnitB.pas")), Some(1));
+        assert_eq!(root_index(&scan.roots, &base.join("Other.pas")), Some(3));
+    }
+
+    #[test]
+    fn scan_files_skips_default_ignored_folders_unless_disabled() {
+        let root = canonicalize_if_exists(&temp_dir("fixdpr_scan_default_ignores_"));
+        for dir in [
+            "app/__history",
+            "app/.git/objects",
+            "Backup",
+            "vendor/stale",
+        ] {
+            fs::create_dir_all(root.join(dir)).expect("create dir");
+        }
+        for file in [
+            "app/UnitA.pas",
+            "app/__history/UnitA.pas",
+            "app/.git/objects/UnitA.pas",
+            "Backup/UnitA.pas",
+            "vendor/stale/UnitA.pas",
+        ] {
+            fs::write(root.join(file), "x").expect("write file");
+        }
+
+        let ignore = IgnoreMatcher::default();
+        fs::write(root.join("app/Shared.inc"), "x").expect("write inc");
+        fs::write(root.join("app/__history/Shared.inc"), "x").expect("write stale inc");
+        let scan = scan_files(std::slice::from_ref(&root), &ignore).expect("scan");
+        assert_eq!(
+            scan.pas_files,
+            vec![
+                root.join("app/UnitA.pas"),
+                root.join("vendor/stale/UnitA.pas")
+            ]
+        );
+        assert_eq!(scan.inc_files, vec![root.join("app/Shared.inc")]);
+        assert_eq!(scan.skipped_dirs, 3);
+
+        let options = ScanOptions::builder().ignore_dir_name("Stale").build();
+        let scan = scan_files_with(std::slice::from_ref(&root), &ignore, &options, &NoEvents)
+            .expect("scan with extra name");
+        assert_eq!(scan.pas_files, vec![root.join("app/UnitA.pas")]);
+        assert_eq!(scan.skipped_dirs, 4);
+
+        let options = ScanOptions::builder().default_ignores(false).build();
+        let scan = scan_files_with(std::slice::from_ref(&root), &ignore, &options, &NoEvents)
+            .expect("scan without defaults");
+        assert_eq!(scan.pas_files.len(), 5);
+        assert_eq!(scan.skipped_dirs, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_files_warns_on_unreadable_folders_unless_strict() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = canonicalize_if_exists(&temp_dir("fixdpr_scan_unreadable_"));
+        let locked = root.join("locked");
+        fs::create_dir_all(&locked).expect("create locked");
+        fs::write(root.join("UnitA.pas"), "x").expect("write unit a");
+        fs::write(locked.join("UnitB.pas"), "x").expect("write unit b");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).expect("lock dir");
+        if fs::read_dir(&locked).is_ok() {
+            // Running with rights that ignore permissions, e.g. as root.
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).expect("unlock");
+            return;
+        }
+
+        let ignore = IgnoreMatcher::default();
+        let scan = scan_files(std::slice::from_ref(&root), &ignore);
+        let strict = ScanOptions::builder().strict(true).build();
+        let strict_scan = scan_files_with(std::slice::from_ref(&root), &ignore, &strict, &NoEvents);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).expect("unlock");
+
+        let scan = scan.expect("scan continues past unreadable folder");
+        assert_eq!(scan.pas_files, vec![root.join("UnitA.pas")]);
+        let warnings: Vec<_> = scan.warnings.iter().collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, DiagnosticCode::PermissionDenied);
+        assert!(warnings[0].message.contains("locked"), "{}", warnings[0]);
+        assert!(strict_scan.is_err());
+    }
+
+    #[test]
+    fn scan_files_fails_when_search_root_is_missing() {
+        let root = temp_dir("fixdpr_scan_missing_root_").join("missing");
+        let err = scan_files(&[root], &IgnoreMatcher::default()).expect_err("missing root");
+        assert!(!err.to_string().is_empty());
+    }
+
+    #[test]
+    fn build_dpr_ignore_matcher_anchored_builds_one_pattern_per_root() {
+        let cwd = temp_dir("fixdpr_ignore_dpr_anchored_");
+        let roots = vec![cwd.join("repo1"), cwd.join("repo2")];
+        let absolute = cwd.join("other").join("Demo.dpr");
+        let matcher = build_dpr_ignore_matcher_anchored(
+            &[
+                "app4/*.dpr".to_string(),
+                absolute.to_string_lossy().to_string(),
+            ],
+            &roots,
+        )
+        .expect("matcher");
+
+        assert_eq!(matcher.normalized_patterns().len(), 3);
+        for root in &roots {
+            let dpr = root.join("app4").join("App4.dpr");
+            assert!(matcher.is_ignored(&dpr.to_string_lossy()));
+        }
+        assert!(!matcher.is_ignored(&cwd.join("app4").join("App4.dpr").to_string_lossy()));
+        assert!(matcher.is_ignored(&absolute.to_string_lossy()));
+        assert_eq!(
+            "Search-Root".parse::<DprGlobAnchor>(),
+            Ok(DprGlobAnchor::SearchRoot)
+        );
+        assert!("root".parse::<DprGlobAnchor>().is_err());
     }
 
     #[test]
@@ -603,6 +1948,85 @@ mod tests {
         assert_eq!(filtered.ignored_files, vec![dpr_b]);
     }
 
+    #[test]
+    fn find_repo_root_walks_up_to_git_entry() {
+        let root = temp_dir("fixdpr_repo_root_");
+        let nested = root.join("apps").join("billing");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        assert_eq!(find_repo_root(&nested), Some(root.clone()));
+        fs::write(
+            nested.join(".git"),
+            "gitdir: ../../.git/worktrees/billing\n",
+        )
+        .unwrap();
+        assert_eq!(find_repo_root(&nested), Some(nested));
+    }
+
+    #[test]
+    fn filter_ignored_dpr_files_matches_declared_program_names() {
+        let cwd = temp_dir("fixdpr_ignore_program_");
+        let generated = cwd.join("out").join("Gen1.dpr");
+        let library = cwd.join("Plugin.dpr");
+        let kept = cwd.join("AutoBuild_Notes.dpr");
+        fs::create_dir_all(cwd.join("out")).expect("create out");
+        fs::write(&generated, "program AutoBuild_Core;\nbegin end.\n").expect("write generated");
+        fs::write(&library, "library autobuild_plugin;\nbegin end.\n").expect("write library");
+        fs::write(&kept, "program Notes;\nbegin end.\n").expect("write kept");
+
+        let matcher = build_dpr_ignore_matcher(&[], &cwd)
+            .expect("matcher")
+            .with_program_patterns(&["AutoBuild_*".to_string()])
+            .expect("program patterns");
+        assert!(!matcher.is_empty());
+        assert_eq!(matcher.normalized_program_patterns(), ["autobuild_*"]);
+
+        let result = filter_ignored_dpr_files(
+            &[generated.clone(), library.clone(), kept.clone()],
+            &matcher,
+        );
+        assert_eq!(result.ignored_files, vec![generated, library]);
+        assert_eq!(result.included_files, vec![kept]);
+
+        let err = DprIgnoreMatcher::default()
+            .with_program_patterns(&["App[".to_string()])
+            .expect_err("invalid glob");
+        assert_eq!(
+            err.to_string(),
+            "invalid pattern 'App[': unclosed '[' at position 4"
+        );
+    }
+
+    #[test]
+    fn filter_included_dpr_files_keeps_matches_of_any_glob() {
+        let cwd = temp_dir("fixdpr_include_filter_");
+        let billing = cwd
+            .join("apps")
+            .join("billing")
+            .join("sub")
+            .join("Bill.dpr");
+        let hr = cwd.join("apps").join("hr").join("Hr.dpr");
+        let tools = cwd.join("tools").join("Tool.dpr");
+        let ignore = build_dpr_ignore_matcher(&["tools/*.dpr".to_string()], &cwd).expect("ignore");
+        let include = build_dpr_ignore_matcher(
+            &[
+                "apps/billing/**/*.dpr".to_string(),
+                "apps/none/*.dpr".to_string(),
+            ],
+            &cwd,
+        )
+        .expect("include");
+
+        let result = filter_included_dpr_files(
+            filter_ignored_dpr_files(&[billing.clone(), hr.clone(), tools.clone()], &ignore),
+            &include,
+        );
+
+        assert_eq!(result.included_files, vec![billing]);
+        assert_eq!(result.ignored_files, vec![tools]);
+        assert_eq!(result.filtered_files, vec![hr]);
+    }
+
     #[test]
     fn dpr_glob_matcher_supports_single_and_double_star() {
         let cwd = temp_dir("fixdpr_ignore_glob_");
@@ -626,6 +2050,60 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn dpr_glob_matcher_supports_classes_and_brace_alternation() {
+        let cwd = temp_dir("fixdpr_ignore_glob_classes_");
+        let ignored = |pattern: &str, relative: &str| {
+            let matcher = build_dpr_ignore_matcher(&[pattern.to_string()], &cwd).expect("matcher");
+            matcher.is_ignored(&cwd.join(relative).to_string_lossy())
+        };
+
+        assert!(ignored("[Tt]est*.dpr", "TestApp.dpr"));
+        assert!(!ignored("[Tt]est*.dpr", "Best.dpr"));
+        assert!(ignored("{App,Tool}*.dpr", "ToolBox.dpr"));
+        assert!(!ignored("{App,Tool}*.dpr", "Demo.dpr"));
+        assert!(ignored("app[0-9].dpr", "App7.dpr"));
+        assert!(!ignored("app[!0-9].dpr", "App7.dpr"));
+        assert!(ignored("app[^0-9].dpr", "AppX.dpr"));
+        assert!(ignored("[]x]*.dpr", "]a.dpr"));
+        assert!(ignored("\\[x\\]\\{y\\,z\\}.dpr", "[x]{y,z}.dpr"));
+
+        let nested = "{apps/{billing,hr},tools}/**/[a-c]*.dpr";
+        assert!(ignored(nested, "apps/billing/x/y/Alpha.dpr"));
+        assert!(ignored(nested, "apps/hr/x/Core.dpr"));
+        assert!(ignored(nested, "tools/deep/Build.dpr"));
+        assert!(!ignored(nested, "apps/sales/Alpha.dpr"));
+        assert!(!ignored(nested, "tools/x/Deploy.dpr"));
+        assert!(!ignored("[a-c]*.dpr", "sub/alpha.dpr"));
+    }
+
+    #[test]
+    fn dpr_glob_matcher_reports_syntax_errors_with_position() {
+        let cwd = temp_dir("fixdpr_ignore_glob_errors_");
+        for (pattern, expected) in [
+            (
+                "app[abc.dpr",
+                "invalid pattern 'app[abc.dpr': unclosed '[' at position 4",
+            ),
+            (
+                "{app,tool.dpr",
+                "invalid pattern '{app,tool.dpr': unclosed '{' at position 1",
+            ),
+            (
+                "app}.dpr",
+                "invalid pattern 'app}.dpr': unmatched '}' at position 4",
+            ),
+            (
+                "a[z-a].dpr",
+                "invalid pattern 'a[z-a].dpr': invalid range in '[...]' at position 3",
+            ),
+        ] {
+            let err = build_dpr_ignore_matcher(&[pattern.to_string()], &cwd).expect_err(pattern);
+            assert!(matches!(err, FixdprError::PatternParse { .. }), "{err:?}");
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
     #[cfg(windows)]
     #[test]
     fn build_dpr_ignore_matcher_accepts_cross_drive_absolute_pattern() {
@@ -1,317 +1,188 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::fs;
-use std::io;
-use std::path::Path;
-
-use crate::pas_index::{self, UnitIndex, UnitInfo};
-use crate::pas_lex;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct UnitId(pub usize);
 
-#[derive(Debug)]
+/// Dependencies of one unit, as reported to [`build_unit_graph`].
+#[derive(Debug, Default)]
+pub struct UnitEdges {
+    /// Resolved paths of the units this unit uses.
+    pub deps: Vec<PathBuf>,
+    /// Caller-defined flag, such as "uses the unit being searched for".
+    pub marked: bool,
+}
+
+#[derive(Debug, Default)]
 pub struct UnitGraph {
-    pub units: Vec<UnitInfo>,
-    #[allow(dead_code)]
+    pub paths: Vec<PathBuf>,
     pub deps: Vec<Vec<UnitId>>,
     pub rev: Vec<Vec<UnitId>>,
-    pub name_to_id: HashMap<String, UnitId>,
-    pub warnings: Vec<String>,
+    pub marked: Vec<bool>,
+    pub id_by_path: HashMap<PathBuf, UnitId>,
 }
 
-pub fn build_unit_graph(index: &UnitIndex) -> io::Result<UnitGraph> {
-    let mut entries: Vec<(&String, &UnitInfo)> = index.units.iter().collect();
-    entries.sort_by(|a, b| a.0.cmp(b.0));
-
-    let mut units = Vec::with_capacity(entries.len());
-    let mut name_to_id = HashMap::with_capacity(entries.len());
-    for (idx, (key, info)) in entries.iter().enumerate() {
-        let id = UnitId(idx);
-        name_to_id.insert((*key).clone(), id);
-        units.push((*info).clone());
+impl UnitGraph {
+    pub fn len(&self) -> usize {
+        self.paths.len()
     }
 
-    let mut deps = vec![Vec::new(); units.len()];
-    let mut warnings = Vec::new();
-    for (idx, info) in units.iter().enumerate() {
-        let bytes = fs::read(&info.path)?;
-        let parsed = parse_unit_uses(&bytes);
-        let mut seen = HashSet::new();
-        for dep in parsed {
-            let key = dep.to_ascii_lowercase();
-            if index.ambiguous.contains_key(&key) {
-                warnings.push(format!(
-                    "warning: ambiguous unit reference '{dep}' in {}",
-                    info.path.display()
-                ));
-            }
-            let Some(dep_id) = name_to_id.get(&key).copied() else {
-                continue;
-            };
-            if seen.insert(dep_id.0) {
-                deps[idx].push(dep_id);
-            }
-        }
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
     }
 
-    let mut rev = vec![Vec::new(); units.len()];
-    for (source, deps) in deps.iter().enumerate() {
-        for target in deps {
-            rev[target.0].push(UnitId(source));
-        }
-    }
-
-    Ok(UnitGraph {
-        units,
-        deps,
-        rev,
-        name_to_id,
-        warnings,
-    })
-}
-
-pub fn compute_dependents(graph: &UnitGraph, root: UnitId) -> Vec<bool> {
-    let mut visited = vec![false; graph.units.len()];
-    let mut queue = VecDeque::new();
-    visited[root.0] = true;
-    queue.push_back(root);
-
-    while let Some(current) = queue.pop_front() {
-        for next in &graph.rev[current.0] {
-            if !visited[next.0] {
-                visited[next.0] = true;
-                queue.push_back(*next);
-            }
-        }
+    pub fn id(&self, path: &Path) -> Option<UnitId> {
+        self.id_by_path.get(path).copied()
     }
 
-    visited
-}
-
-pub fn resolve_new_unit_id(
-    new_dependency: &str,
-    graph: &UnitGraph,
-    search_root: &Path,
-) -> Result<UnitId, String> {
-    let trimmed = new_dependency.trim();
-    if trimmed.is_empty() {
-        return Err("--new-dependency cannot be empty".to_string());
+    pub fn marked_ids(&self) -> Vec<UnitId> {
+        self.marked
+            .iter()
+            .enumerate()
+            .filter(|(_, marked)| **marked)
+            .map(|(id, _)| UnitId(id))
+            .collect()
     }
 
-    if is_probably_path(trimmed) {
-        let path = resolve_candidate_path(trimmed, search_root)
-            .ok_or_else(|| format!("--new-dependency path not found: {trimmed}"))?;
-        let (name, _) = pas_index::derive_unit_name_from_file(&path)
-            .map_err(|err| format!("failed to read new dependency: {err}"))?;
-        if name.is_empty() {
-            return Err(format!(
-                "unable to derive unit name from new dependency: {}",
-                path.display()
-            ));
+    fn add(&mut self, path: &Path, queue: &mut VecDeque<PathBuf>) -> UnitId {
+        if let Some(&id) = self.id_by_path.get(path) {
+            return id;
         }
-        let key = name.to_ascii_lowercase();
-        return graph.name_to_id.get(&key).copied().ok_or_else(|| {
-            format!(
-                "new dependency unit not found in index: {name} (from {})",
-                path.display()
-            )
-        });
+        let id = UnitId(self.paths.len());
+        self.id_by_path.insert(path.to_path_buf(), id);
+        self.paths.push(path.to_path_buf());
+        self.deps.push(Vec::new());
+        self.rev.push(Vec::new());
+        self.marked.push(false);
+        queue.push_back(path.to_path_buf());
+        id
     }
-
-    let key = trimmed.to_ascii_lowercase();
-    graph
-        .name_to_id
-        .get(&key)
-        .copied()
-        .ok_or_else(|| format!("new dependency unit not found in index: {trimmed}"))
 }
 
-fn resolve_candidate_path(value: &str, search_root: &Path) -> Option<std::path::PathBuf> {
-    let candidate = std::path::PathBuf::from(value);
-    if candidate.is_file() {
-        return Some(candidate);
-    }
-    if candidate.is_relative() {
-        let alt = search_root.join(&candidate);
-        if alt.is_file() {
-            return Some(alt);
-        }
+/// Builds the graph of every unit reachable from `roots`.
+///
+/// `edges_of` returns the dependencies of one unit, or `None` when the unit
+/// cannot be loaded; such units stay in the graph without edges.
+pub fn build_unit_graph<E, F>(
+    roots: impl IntoIterator<Item = PathBuf>,
+    mut edges_of: F,
+) -> Result<UnitGraph, E>
+where
+    F: FnMut(&Path) -> Result<Option<UnitEdges>, E>,
+{
+    let mut graph = UnitGraph::default();
+    let mut queue = VecDeque::new();
+    for root in roots {
+        graph.add(&root, &mut queue);
     }
-    None
-}
-
-fn is_probably_path(value: &str) -> bool {
-    value.contains('/') || value.contains('\\') || value.to_ascii_lowercase().ends_with(".pas")
-}
-
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum Section {
-    None,
-    Interface,
-    Implementation,
-}
-
-fn parse_unit_uses(bytes: &[u8]) -> Vec<String> {
-    let mut deps = Vec::new();
-    let mut i = 0;
-    let mut section = Section::None;
 
-    while i < bytes.len() {
-        match bytes[i] {
-            b'{' => {
-                i = pas_lex::skip_brace_comment(bytes, i + 1);
-            }
-            b'(' if bytes.get(i + 1) == Some(&b'*') => {
-                i = pas_lex::skip_paren_comment(bytes, i + 2);
-            }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => {
-                i = pas_lex::skip_line_comment(bytes, i + 2);
-            }
-            b'\'' => {
-                i = pas_lex::skip_string(bytes, i + 1);
-            }
-            byte if pas_lex::is_ident_start(byte) => {
-                let (token, next) = pas_lex::read_ident(bytes, i);
-                if token.eq_ignore_ascii_case("interface") {
-                    section = Section::Interface;
-                } else if token.eq_ignore_ascii_case("implementation") {
-                    section = Section::Implementation;
-                } else if token.eq_ignore_ascii_case("uses") && section != Section::None {
-                    i = parse_uses_list(bytes, next, &mut deps);
-                    continue;
-                }
-                i = next;
-            }
-            _ => {
-                i += 1;
-            }
+    while let Some(unit_path) = queue.pop_front() {
+        let Some(edges) = edges_of(&unit_path)? else {
+            continue;
+        };
+        let source = graph.id_by_path[&unit_path];
+        graph.marked[source.0] = edges.marked;
+        for dep_path in edges.deps {
+            let target = graph.add(&dep_path, &mut queue);
+            graph.deps[source.0].push(target);
+            graph.rev[target.0].push(source);
         }
     }
 
-    deps
+    Ok(graph)
 }
 
-fn parse_uses_list(bytes: &[u8], mut i: usize, deps: &mut Vec<String>) -> usize {
-    loop {
-        i = pas_lex::skip_ws_and_comments(bytes, i);
-        if i >= bytes.len() {
-            return i;
-        }
-        if bytes[i] == b';' {
-            return i + 1;
-        }
-        if !pas_lex::is_ident_start(bytes[i]) {
-            i += 1;
-            continue;
-        }
-        let (name, next) = pas_lex::read_ident_with_dots(bytes, i);
-        if !name.is_empty() {
-            deps.push(name);
-        }
-        i = next;
-        i = pas_lex::skip_ws_and_comments(bytes, i);
-
-        if let Some((token, next_token)) = peek_ident(bytes, i) {
-            if token.eq_ignore_ascii_case("in") {
-                i = next_token;
-                i = pas_lex::skip_ws_and_comments(bytes, i);
-                if i < bytes.len() && bytes[i] == b'\'' {
-                    i = pas_lex::skip_string(bytes, i + 1);
-                }
-            }
-        }
-
-        let (pos, delim) = skip_to_delimiter(bytes, i);
-        i = pos;
-        match delim {
-            Some(b',') => i += 1,
-            Some(b';') => return i + 1,
-            _ => return i,
+/// Flags every unit that reaches one of `seeds`, the seeds included.
+pub fn compute_dependents(graph: &UnitGraph, seeds: &[UnitId]) -> Vec<bool> {
+    let mut visited = vec![false; graph.len()];
+    let mut queue = VecDeque::new();
+    for &seed in seeds {
+        if !visited[seed.0] {
+            visited[seed.0] = true;
+            queue.push_back(seed);
         }
     }
-}
-
-fn peek_ident(bytes: &[u8], i: usize) -> Option<(String, usize)> {
-    if i < bytes.len() && pas_lex::is_ident_start(bytes[i]) {
-        let (token, next) = pas_lex::read_ident(bytes, i);
-        return Some((token, next));
-    }
-    None
-}
 
-fn skip_to_delimiter(bytes: &[u8], mut i: usize) -> (usize, Option<u8>) {
-    while i < bytes.len() {
-        match bytes[i] {
-            b',' | b';' => return (i, Some(bytes[i])),
-            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
-            b'(' if bytes.get(i + 1) == Some(&b'*') => {
-                i = pas_lex::skip_paren_comment(bytes, i + 2)
+    while let Some(current) = queue.pop_front() {
+        for next in &graph.rev[current.0] {
+            if !visited[next.0] {
+                visited[next.0] = true;
+                queue.push_back(*next);
             }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
-            _ => i += 1,
         }
     }
-    (i, None)
+
+    visited
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::convert::Infallible;
 
-    #[test]
-    fn parse_unit_uses_in_interface_and_implementation() {
-        let src = br#"
-unit Demo;
-interface
-uses Foo, Bar;
-implementation
-uses Baz;
-end.
-"#;
-        let deps = parse_unit_uses(src);
-        assert_eq!(deps, vec!["Foo", "Bar", "Baz"]);
-    }
-
-    #[test]
-    fn parse_unit_uses_ignores_comments_and_strings() {
-        let src = br#"
-unit Demo;
-interface
-uses Foo, {Bar}, (*Baz*), // Quux
-  'NotAUnit', RealUnit;
-implementation
-uses ImplUnit;
-end.
-"#;
-        let deps = parse_unit_uses(src);
-        assert_eq!(deps, vec!["Foo", "RealUnit", "ImplUnit"]);
+    fn edges(deps: &[&str], marked: bool) -> UnitEdges {
+        UnitEdges {
+            deps: deps.iter().map(PathBuf::from).collect(),
+            marked,
+        }
     }
 
     #[test]
-    fn parse_unit_uses_allows_directives_inside_list() {
-        let src = br#"
-unit Demo;
-interface
-uses Foo, {$IFDEF X} Bar, {$ENDIF} Baz;
-implementation
-uses Qux;
-end.
-"#;
-        let deps = parse_unit_uses(src);
-        assert_eq!(deps, vec!["Foo", "Bar", "Baz", "Qux"]);
+    fn build_unit_graph_follows_edges_from_roots() {
+        let graph = build_unit_graph::<Infallible, _>([PathBuf::from("A")], |path| {
+            Ok(match path.to_str() {
+                Some("A") => Some(edges(&["B", "C"], false)),
+                Some("B") => Some(edges(&["C"], true)),
+                Some("C") => Some(edges(&[], false)),
+                _ => None,
+            })
+        })
+        .unwrap();
+
+        assert_eq!(graph.len(), 3);
+        let a = graph.id(Path::new("A")).unwrap();
+        let b = graph.id(Path::new("B")).unwrap();
+        let c = graph.id(Path::new("C")).unwrap();
+        assert_eq!(graph.deps[a.0], vec![b, c]);
+        assert_eq!(graph.rev[c.0], vec![a, b]);
+        assert_eq!(graph.marked_ids(), vec![b]);
     }
 
     #[test]
-    fn parse_unit_uses_with_in_paths() {
-        let src = br#"
-unit Demo;
-interface
-uses Foo in 'Foo.pas', Bar in 'path\Bar.pas';
-implementation
-end.
-"#;
-        let deps = parse_unit_uses(src);
-        assert_eq!(deps, vec!["Foo", "Bar"]);
+    fn compute_dependents_walks_reverse_edges_from_every_seed() {
+        let graph = build_unit_graph::<Infallible, _>(
+            [PathBuf::from("App1"), PathBuf::from("App2")],
+            |path| {
+                Ok(match path.to_str() {
+                    Some("App1") => Some(edges(&["Mid"], false)),
+                    Some("App2") => Some(edges(&["Leaf2"], false)),
+                    Some("Mid") => Some(edges(&["Leaf1"], false)),
+                    _ => None,
+                })
+            },
+        )
+        .unwrap();
+        let leaf1 = graph.id(Path::new("Leaf1")).unwrap();
+        let leaf2 = graph.id(Path::new("Leaf2")).unwrap();
+
+        let flags = compute_dependents(&graph, &[leaf1]);
+        let reached: Vec<&PathBuf> = graph
+            .paths
+            .iter()
+            .zip(&flags)
+            .filter(|(_, flag)| **flag)
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            reached,
+            vec![
+                &PathBuf::from("App1"),
+                &PathBuf::from("Mid"),
+                &PathBuf::from("Leaf1")
+            ]
+        );
+        assert!(compute_dependents(&graph, &[leaf1, leaf2])
+            .iter()
+            .all(|flag| *flag));
     }
 }
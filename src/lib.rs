@@ -0,0 +1,69 @@
+//! Library behind the `fixdpr` command line tool.
+//!
+//! Functions in this crate never print and never exit the process. Scans and
+//! edits return their results as summaries together with [`warnings::Warnings`]
+//! so the caller decides how to render them.
+//!
+//! A typical repair of one `.dpr` file:
+//!
+//! ```no_run
+//! use std::path::PathBuf;
+//!
+//! use fixdpr::conditionals::Assumptions;
+//! use fixdpr::warnings::Warnings;
+//! use fixdpr::{dpr_edit, fs_walk, unit_cache};
+//!
+//! let root = fs_walk::canonicalize_root(&PathBuf::from("C:/src/project"));
+//! let scan = fs_walk::scan_files(&[root.clone()], &fs_walk::IgnoreMatcher::default())?;
+//! let mut warnings = Warnings::new();
+//! let cache = unit_cache::build_unit_cache(&scan.pas_files, &mut warnings)?;
+//! let summary = dpr_edit::fix_dpr_file(
+//!     &root.join("App.dpr"),
+//!     &cache,
+//!     None,
+//!     &Assumptions::default(),
+//! )?;
+//! for warning in summary.warnings.iter() {
+//!     eprintln!("{warning}");
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+/// Persistent on-disk unit cache reused between runs.
+pub mod cache_file;
+/// Compiler conditional evaluation and `{$IFDEF}` aware uses parsing.
+pub mod conditionals;
+/// Delphi installation lookup for `--delphi-version`.
+pub mod delphi;
+/// Warning records with codes, source locations, and messages.
+pub mod diagnostic;
+/// Reading and rewriting `.dpr` uses lists.
+pub mod dpr_edit;
+mod dpr_lock;
+/// Error type returned by argument resolution and lookups.
+pub mod error;
+/// Progress and warning callbacks for embedding callers.
+pub mod events;
+/// Search root scanning and ignore rules.
+pub mod fs_walk;
+/// Unit dependency graph behind the dependent unit passes.
+pub mod graph;
+mod pas_lex;
+/// Unit header and uses clause parsing shared by the unit caches.
+pub mod pas_parse;
+/// Unified diffs of `.dpr` edits for `--emit-patch`.
+pub mod patch;
+/// Project scanning, unit cache setup, and the dpr operations on a scanned project.
+pub mod project;
+/// JSON and display path rendering shared by the command reports and `serve`.
+pub mod report;
+/// Newline-delimited JSON requests against a warm project for `fixdpr serve`.
+pub mod serve;
+/// Parsed `.pas` unit metadata and name lookup caches.
+pub mod unit_cache;
+/// `{$I}` include resolution inside uses clauses.
+pub mod uses_include;
+/// Warning collector that groups identical diagnostics.
+pub mod warnings;
+
+pub use error::FixdprError;
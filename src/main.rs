@@ -1,19 +1,32 @@
 use clap::{ArgGroup, Args, Parser, Subcommand};
+use fixdpr::diagnostic::{Diagnostic, DiagnosticCode};
+use fixdpr::events::{EventSink, NoEvents, ScanProgress};
+use fixdpr::fs_walk::{
+    contains_path, dedupe_paths, is_pas_file, resolve_path_with_flag, validate_dpr_file_path,
+    validate_pas_file_path,
+};
+use fixdpr::project;
+use fixdpr::report::{
+    dpr_detail_json, json_array, json_string, json_warnings, plain_path, who_uses_fields,
+    DisplayPaths, ReportDisplay,
+};
+use fixdpr::serve;
+use fixdpr::unit_cache::UsesSections;
+use fixdpr::warnings::{self, Warnings};
+use fixdpr::{
+    cache_file, conditionals, delphi, dpr_edit, fs_walk, patch, unit_cache, uses_include,
+    FixdprError,
+};
 use pathdiff::diff_paths;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process;
+use std::rc::Rc;
 use std::str::FromStr;
-
-mod conditionals;
-mod delphi;
-mod dpr_edit;
-mod fs_walk;
-mod pas_lex;
-mod unit_cache;
-mod uses_include;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -40,6 +53,22 @@ enum Commands {
     FixDpr(FixDprArgs),
     /// List conditional unit dependencies for a single .dpr file
     ListConditionals(ListConditionalsArgs),
+    /// List the .dpr files and units that use a unit
+    WhoUses(WhoUsesArgs),
+    /// List the .dpr files whose dependency closure includes any changed unit
+    Impact(ImpactArgs),
+    /// List the units that depend on a unit, directly or through other units
+    Dependents(DependentsArgs),
+    /// Report the direct entries and dependency closure size of each .dpr file, heaviest first
+    Fanout(FanoutArgs),
+    /// Build, inspect, or verify the persistent unit cache
+    Cache(CacheArgs),
+    /// Create or fill a .dpr uses list from a seed unit and its dependency closure
+    Init(InitArgs),
+    /// Copy the uses entries of a template .dpr file that another .dpr file lacks
+    CopyUses(CopyUsesArgs),
+    /// Keep the caches warm and answer JSON requests on stdin, one per line
+    Serve(ServeArgs),
 }
 
 #[derive(Args, Debug)]
@@ -47,31 +76,75 @@ struct AddDependencyArgs {
     #[command(flatten)]
     common: SharedArgs,
 
+    #[command(flatten)]
+    dpr_write: DprWriteArgs,
+
+    #[command(flatten)]
+    relative_paths: RelativePathArgs,
+
+    #[command(flatten)]
+    layout: EntryLayoutArgs,
+
     #[command(flatten)]
     dependency_lookup: DependencyLookupArgs,
 
     #[command(flatten)]
     dpr_filter: AddDependencyDprFilterArgs,
 
-    /// Optional Delphi/VCL source root path to scan for fallback unit resolution (repeatable)
-    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
-    delphi_path: Vec<String>,
+    /// Only update dpr files matching this glob (repeatable; any match counts)
+    #[arg(long, value_name = "GLOB", action = clap::ArgAction::Append)]
+    dpr_glob: Vec<String>,
 
-    /// Optional Delphi version to resolve from registry and use as fallback source root (repeatable)
-    #[arg(long, value_name = "VERSION", action = clap::ArgAction::Append)]
-    delphi_version: Vec<String>,
+    #[command(flatten)]
+    delphi: DelphiArgs,
+
+    /// Unit scope name under which Vcl.Dialogs and Dialogs name the same unit (repeatable; replaces the Delphi defaults)
+    #[arg(long, value_name = "PREFIX", value_delimiter = ',', action = clap::ArgAction::Append)]
+    namespace_prefix: Vec<String>,
 
     /// Path to a .pas file (absolute or relative to the current directory)
     #[arg(value_name = "NEW_DEPENDENCY")]
     new_dependency: String,
 
-    /// Disable adding transitive dependencies introduced by NEW_DEPENDENCY
-    #[arg(long)]
+    /// Which units used by NEW_DEPENDENCY are inserted with it: all, project-only, or none
+    #[arg(long, value_name = "SCOPE", default_value = "all")]
+    introduced_scope: dpr_edit::IntroducedScope,
+
+    /// Alias for --introduced-scope none
+    #[arg(long, conflicts_with = "introduced_scope")]
     disable_introduced_dependencies: bool,
 
+    /// Insert introduced dependencies before the units that use them, ties broken by name
+    #[arg(long)]
+    topo_order: bool,
+
+    /// Only update dprs listing a unit that uses NEW_DEPENDENCY directly
+    #[arg(long)]
+    direct_only: bool,
+
+    /// Point entries naming NEW_DEPENDENCY's unit at another file to NEW_DEPENDENCY instead
+    #[arg(long)]
+    retarget_existing: bool,
+
+    /// Insert NEW_DEPENDENCY and its introduced dependencies after this unit when it is listed
+    #[arg(long, value_name = "NAME")]
+    after_unit: Option<String>,
+
+    /// Always insert at the end of the uses list instead of after the introducing unit
+    #[arg(long, conflicts_with = "after_unit")]
+    append: bool,
+
+    /// Descend at most N folder levels when scanning the folder of a NEW_DEPENDENCY outside --search-path
+    #[arg(long, value_name = "N")]
+    dependency_root_depth: Option<usize>,
+
     /// Run a follow-up fix pass on each dpr updated by add-dependency
     #[arg(long)]
     fix_updated_dprs: bool,
+
+    /// In the --fix-updated-dprs pass, never insert units found only in Delphi fallback roots
+    #[arg(long, requires = "fix_updated_dprs")]
+    no_delphi_inserts: bool,
 }
 
 #[derive(Args, Debug)]
@@ -79,6 +152,15 @@ struct InsertDependencyArgs {
     #[command(flatten)]
     common: SharedArgs,
 
+    #[command(flatten)]
+    dpr_write: DprWriteArgs,
+
+    #[command(flatten)]
+    relative_paths: RelativePathArgs,
+
+    #[command(flatten)]
+    layout: EntryLayoutArgs,
+
     #[command(flatten)]
     dependency_lookup: DependencyLookupArgs,
 
@@ -88,21 +170,32 @@ struct InsertDependencyArgs {
     #[command(flatten)]
     targets: InsertDependencyTargetArgs,
 
-    /// Optional Delphi/VCL source root path to scan for fallback unit resolution (repeatable)
-    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
-    delphi_path: Vec<String>,
+    #[command(flatten)]
+    delphi: DelphiArgs,
 
-    /// Optional Delphi version to resolve from registry and use as fallback source root (repeatable)
-    #[arg(long, value_name = "VERSION", action = clap::ArgAction::Append)]
-    delphi_version: Vec<String>,
+    /// Unit scope name under which Vcl.Dialogs and Dialogs name the same unit (repeatable; replaces the Delphi defaults)
+    #[arg(long, value_name = "PREFIX", value_delimiter = ',', action = clap::ArgAction::Append)]
+    namespace_prefix: Vec<String>,
 
     /// Path to a .pas file (absolute or relative to the current directory)
     #[arg(value_name = "NEW_DEPENDENCY")]
     new_dependency: String,
 
-    /// Disable adding transitive dependencies introduced by NEW_DEPENDENCY
-    #[arg(long)]
+    /// Which units used by NEW_DEPENDENCY are inserted with it: all, project-only, or none
+    #[arg(long, value_name = "SCOPE", default_value = "all")]
+    introduced_scope: dpr_edit::IntroducedScope,
+
+    /// Alias for --introduced-scope none
+    #[arg(long, conflicts_with = "introduced_scope")]
     disable_introduced_dependencies: bool,
+
+    /// Insert introduced dependencies before the units that use them, ties broken by name
+    #[arg(long)]
+    topo_order: bool,
+
+    /// Descend at most N folder levels when scanning the folder of a NEW_DEPENDENCY outside --search-path
+    #[arg(long, value_name = "N")]
+    dependency_root_depth: Option<usize>,
 }
 
 #[derive(Args, Debug)]
@@ -110,6 +203,9 @@ struct DeleteDependencyArgs {
     #[command(flatten)]
     common: SharedArgs,
 
+    #[command(flatten)]
+    dpr_write: DprWriteArgs,
+
     #[command(flatten)]
     dependency_lookup: DependencyLookupArgs,
 
@@ -119,13 +215,8 @@ struct DeleteDependencyArgs {
     #[command(flatten)]
     targets: InsertDependencyTargetArgs,
 
-    /// Optional Delphi/VCL source root path to scan for fallback unit resolution (repeatable)
-    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
-    delphi_path: Vec<String>,
-
-    /// Optional Delphi version to resolve from registry and use as fallback source root (repeatable)
-    #[arg(long, value_name = "VERSION", action = clap::ArgAction::Append)]
-    delphi_version: Vec<String>,
+    #[command(flatten)]
+    delphi: DelphiArgs,
 
     /// Path to a .pas file (absolute or relative to the current directory)
     #[arg(value_name = "OLD_DEPENDENCY")]
@@ -137,16 +228,72 @@ struct FixDprArgs {
     #[command(flatten)]
     common: SharedArgs,
 
+    #[command(flatten)]
+    dpr_write: DprWriteArgs,
+
+    #[command(flatten)]
+    relative_paths: RelativePathArgs,
+
+    #[command(flatten)]
+    layout: EntryLayoutArgs,
+
     #[command(flatten)]
     dependency_lookup: DependencyLookupArgs,
 
-    /// Optional Delphi/VCL source root path to scan for fallback unit resolution (repeatable)
-    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
-    delphi_path: Vec<String>,
+    #[command(flatten)]
+    delphi: DelphiArgs,
 
-    /// Optional Delphi version to resolve from registry and use as fallback source root (repeatable)
-    #[arg(long, value_name = "VERSION", action = clap::ArgAction::Append)]
-    delphi_version: Vec<String>,
+    /// Unit scope name under which Vcl.Dialogs and Dialogs name the same unit (repeatable; replaces the Delphi defaults)
+    #[arg(long, value_name = "PREFIX", value_delimiter = ',', action = clap::ArgAction::Append)]
+    namespace_prefix: Vec<String>,
+
+    /// Rewrite in-paths that point at a file declaring a different unit
+    #[arg(long)]
+    fix_mismatched_paths: bool,
+
+    /// Keep one entry per unit listed more than once: keep-first, keep-resolving, or remove-extra
+    #[arg(long, value_name = "POLICY")]
+    fix_duplicates: Option<dpr_edit::DuplicatePolicy>,
+
+    /// Follow units found only in Delphi fallback roots but never insert them
+    #[arg(long)]
+    no_delphi_inserts: bool,
+
+    /// Add the repo root (or folder) of DPR_FILE as a search root when no --search-path covers it
+    #[arg(long)]
+    auto_root: bool,
+
+    /// List uses references in DPR_FILE and its dependency closure that resolve to no unit
+    #[arg(long)]
+    report_unresolved: bool,
+
+    /// Exit with code 1 when --report-unresolved finds any reference
+    #[arg(long, requires = "report_unresolved")]
+    strict: bool,
+
+    /// Remove entries whose in-path names no file and whose unit resolves nowhere, unless pinned with fixdpr:pin
+    #[arg(long)]
+    remove_missing: bool,
+
+    /// Insert at most N missing units; the dpr is reported as partially updated with the units left out
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedI64ValueParser::<usize>::new().range(1..))]
+    max_inserts: Option<usize>,
+
+    /// Exit with code 1 when --max-inserts leaves missing units out
+    #[arg(long, requires = "max_inserts")]
+    partial_is_failure: bool,
+
+    /// Report the units that would be inserted without writing DPR_FILE; exits with code 4 when there are any
+    #[arg(long)]
+    dry_run: bool,
+
+    /// With --dry-run, exit with code 0 even when units would be inserted
+    #[arg(long, requires = "dry_run")]
+    no_exit_on_changes: bool,
+
+    /// Output format: text, or jsonl for one JSON object per dpr with missing units or warnings
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    format: FixDprFormat,
 
     /// Path to the target .dpr file to repair (absolute or relative to the current directory)
     #[arg(value_name = "DPR_FILE")]
@@ -154,32 +301,61 @@ struct FixDprArgs {
 }
 
 #[derive(Args, Debug)]
-struct ListConditionalsArgs {
+struct InitArgs {
     #[command(flatten)]
     common: SharedArgs,
 
-    /// Optional Delphi/VCL source root path to scan for fallback unit resolution (repeatable)
-    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
-    delphi_path: Vec<String>,
+    #[command(flatten)]
+    relative_paths: RelativePathArgs,
 
-    /// Optional Delphi version to resolve from registry and use as fallback source root (repeatable)
-    #[arg(long, value_name = "VERSION", action = clap::ArgAction::Append)]
-    delphi_version: Vec<String>,
+    #[command(flatten)]
+    layout: EntryLayoutArgs,
 
-    /// Path to the target .dpr file to inspect (absolute or relative to the current directory)
+    #[command(flatten)]
+    dependency_lookup: DependencyLookupArgs,
+
+    #[command(flatten)]
+    delphi: DelphiArgs,
+
+    /// Follow units found only in Delphi fallback roots but never list them
+    #[arg(long)]
+    no_delphi_inserts: bool,
+
+    /// .pas file whose unit and dependency closure fill the uses list
+    #[arg(long, value_name = "PAS_FILE")]
+    seed: String,
+
+    /// Replace a uses list that already has entries
+    #[arg(long)]
+    force: bool,
+
+    /// Path to the .dpr file to create or fill (absolute or relative to the current directory)
     #[arg(value_name = "DPR_FILE")]
     dpr_file: String,
 }
 
 #[derive(Args, Debug)]
-struct SharedArgs {
-    /// Root folder path to recursively scan for .dpr and .pas (repeatable)
-    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
-    search_path: Vec<String>,
+struct CopyUsesArgs {
+    #[command(flatten)]
+    dpr_write: DprWriteArgs,
 
-    /// Optional folder path to skip recursively (repeatable)
-    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
-    ignore_path: Vec<String>,
+    #[command(flatten)]
+    relative_paths: RelativePathArgs,
+
+    #[command(flatten)]
+    layout: EntryLayoutArgs,
+
+    /// Template .dpr file whose uses entries are copied
+    #[arg(long, value_name = "DPR_FILE")]
+    from: String,
+
+    /// .dpr file that receives the missing entries
+    #[arg(long, value_name = "DPR_FILE")]
+    to: String,
+
+    /// Also remove entries of --to that --from does not list, except pinned ones
+    #[arg(long)]
+    sync: bool,
 
     /// Show detailed info list
     #[arg(long)]
@@ -188,166 +364,678 @@ struct SharedArgs {
     /// Show detailed warnings list
     #[arg(long)]
     show_warnings: bool,
-}
 
-#[derive(Args, Debug, Default)]
-struct DependencyLookupArgs {
-    /// Assume compiler symbol is on or off during dependency traversal (repeatable)
-    #[arg(long, value_name = "SYMBOL=on|off", action = clap::ArgAction::Append)]
-    assume: Vec<DependencyAssumptionArg>,
+    /// List every warning instance instead of grouping identical warnings
+    #[arg(long)]
+    no_dedupe: bool,
+
+    /// Name every file referencing a missing include instead of the first few
+    #[arg(long)]
+    show_all_referencers: bool,
+
+    /// Silence warnings with the given diagnostic code (repeatable)
+    #[arg(long, value_name = "CODE", action = clap::ArgAction::Append)]
+    suppress: Vec<DiagnosticCode>,
+
+    /// Print full paths in warnings, infos, and the updated dpr list
+    #[arg(long)]
+    absolute_display_paths: bool,
+
+    /// Print per-dpr status, inserted units, and warnings after the report
+    #[arg(long)]
+    show_details: bool,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct DependencyAssumptionArg {
-    symbol: String,
-    value: conditionals::AssumedValue,
+#[derive(Args, Debug)]
+struct ServeArgs {
+    #[command(flatten)]
+    common: SharedArgs,
+
+    #[command(flatten)]
+    relative_paths: RelativePathArgs,
+
+    #[command(flatten)]
+    layout: EntryLayoutArgs,
+
+    #[command(flatten)]
+    dependency_lookup: DependencyLookupArgs,
+
+    #[command(flatten)]
+    delphi: DelphiArgs,
+
+    /// Unit scope name under which Vcl.Dialogs and Dialogs name the same unit (repeatable; replaces the Delphi defaults)
+    #[arg(long, value_name = "PREFIX", value_delimiter = ',', action = clap::ArgAction::Append)]
+    namespace_prefix: Vec<String>,
+
+    /// Seconds a fix request waits for another fixdpr run editing the same dpr
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    lock_timeout: u64,
 }
 
-impl fmt::Display for DependencyAssumptionArg {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}={}", self.symbol, assumed_value_label(self.value))
-    }
+#[derive(Args, Debug)]
+struct ListConditionalsArgs {
+    #[command(flatten)]
+    common: SharedArgs,
+
+    /// File of DCU-only unit names (one per line) that are never inserted or warned about
+    #[arg(long, value_name = "FILE")]
+    known_units: Option<String>,
+
+    #[command(flatten)]
+    delphi: DelphiArgs,
+
+    /// Path to the target .dpr file to inspect (absolute or relative to the current directory)
+    #[arg(value_name = "DPR_FILE")]
+    dpr_file: String,
 }
 
-impl FromStr for DependencyAssumptionArg {
-    type Err = String;
+#[derive(Args, Debug)]
+struct WhoUsesArgs {
+    #[command(flatten)]
+    common: SharedArgs,
 
-    fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let trimmed = value.trim();
-        if trimmed.is_empty() {
-            return Err("--assume cannot be empty".to_string());
-        }
+    #[command(flatten)]
+    dependency_lookup: DependencyLookupArgs,
 
-        let Some((symbol, raw_value)) = trimmed.split_once('=') else {
-            return Err("--assume must use SYMBOL=on|off".to_string());
-        };
+    /// Also list dprs that reach UNIT through the units they list
+    #[arg(long)]
+    transitive: bool,
 
-        let symbol = symbol.trim();
-        if symbol.is_empty() {
-            return Err("--assume symbol cannot be empty".to_string());
-        }
+    /// List the dprs, units, and include files that pull in this .inc file instead of UNIT
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["unit", "transitive"])]
+    include: Option<String>,
 
-        let raw_value = raw_value.trim();
-        if raw_value.is_empty() {
-            return Err("--assume value cannot be empty; expected on or off".to_string());
-        }
+    /// Output format: text or json
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    format: OutputFormat,
 
-        let value = match raw_value.to_ascii_lowercase().as_str() {
-            "on" => conditionals::AssumedValue::On,
-            "off" => conditionals::AssumedValue::Off,
-            _ => {
-                return Err(format!(
-                    "--assume value must be 'on' or 'off', got '{}'",
-                    raw_value
-                ));
-            }
-        };
+    /// Unit name, or path to its .pas file (absolute or relative to the current directory)
+    #[arg(value_name = "UNIT", required_unless_present = "include")]
+    unit: Option<String>,
+}
 
-        Ok(Self {
-            symbol: symbol.to_ascii_uppercase(),
-            value,
-        })
-    }
+#[derive(Args, Debug)]
+struct DependentsArgs {
+    #[command(flatten)]
+    common: SharedArgs,
+
+    #[command(flatten)]
+    dependency_lookup: DependencyLookupArgs,
+
+    /// List only units whose own uses clauses name UNIT
+    #[arg(long)]
+    direct_only: bool,
+
+    /// Group the dependent units by the .dpr files that list them
+    #[arg(long)]
+    group_by_dpr: bool,
+
+    /// Output format: text or json
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    format: OutputFormat,
+
+    /// Unit name, or path to its .pas file (absolute or relative to the current directory)
+    #[arg(value_name = "UNIT")]
+    unit: String,
 }
 
 #[derive(Args, Debug)]
-struct AddDependencyDprFilterArgs {
-    /// Optional glob pattern for .dpr files to ignore (repeatable)
-    #[arg(long, value_name = "GLOB", action = clap::ArgAction::Append)]
-    ignore_dpr: Vec<String>,
+struct FanoutArgs {
+    #[command(flatten)]
+    common: SharedArgs,
+
+    #[command(flatten)]
+    dependency_lookup: DependencyLookupArgs,
+
+    #[command(flatten)]
+    delphi: DelphiArgs,
+
+    /// Output format: text or json
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    format: OutputFormat,
 }
 
 #[derive(Args, Debug)]
 #[command(group(
-    ArgGroup::new("insert_targets")
+    ArgGroup::new("changed_units")
         .required(true)
         .multiple(true)
-        .args(["target_path", "target_dpr"])
+        .args(["units", "changed_since"])
 ))]
-struct InsertDependencyTargetArgs {
-    /// Directory whose .dpr files should be updated recursively (repeatable)
-    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
-    target_path: Vec<String>,
+struct ImpactArgs {
+    #[command(flatten)]
+    common: SharedArgs,
 
-    /// Specific .dpr file to update (repeatable)
-    #[arg(long, value_name = "DPR_FILE", action = clap::ArgAction::Append)]
-    target_dpr: Vec<String>,
+    #[command(flatten)]
+    dependency_lookup: DependencyLookupArgs,
+
+    /// Also treat the .pas files changed since this git revision as changed units
+    #[arg(long, value_name = "REF")]
+    changed_since: Option<String>,
+
+    /// Output format: text or json
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    format: OutputFormat,
+
+    /// Changed unit names or .pas file paths (absolute or relative to the current directory)
+    #[arg(value_name = "UNIT")]
+    units: Vec<String>,
 }
 
-fn main() {
-    let cli = Cli::parse();
-    match cli.command {
-        Commands::AddDependency(args) => run_add_dependency(args),
-        Commands::InsertDependency(args) => run_insert_dependency(args),
-        Commands::DeleteDependency(args) => run_delete_dependency(args),
-        Commands::FixDpr(args) => run_fix_dpr(args),
-        Commands::ListConditionals(args) => run_list_conditionals(args),
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
-fn run_add_dependency(args: AddDependencyArgs) {
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("--format must be text or json, got '{other}'")),
+        }
+    }
+}
+
+/// Output format of `fix-dpr`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FixDprFormat {
+    Text,
+    Jsonl,
+}
+
+impl FromStr for FixDprFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "jsonl" => Ok(Self::Jsonl),
+            other => Err(format!("--format must be text or jsonl, got '{other}'")),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct CacheArgs {
+    #[command(subcommand)]
+    action: CacheAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// Populate the cache for the given search paths without touching any .dpr
+    Build(CacheBuildArgs),
+    /// Show entry count, file size, and hit/miss counts from the last run
+    Stats(CacheStatsArgs),
+    /// Re-parse cached entries and report entries that no longer match
+    Verify(CacheVerifyArgs),
+}
+
+#[derive(Args, Debug)]
+struct CacheBuildArgs {
+    #[command(flatten)]
+    common: SharedArgs,
+}
+
+#[derive(Args, Debug)]
+struct CacheStatsArgs {
+    #[command(flatten)]
+    common: SharedArgs,
+}
+
+#[derive(Args, Debug)]
+struct CacheVerifyArgs {
+    #[command(flatten)]
+    common: SharedArgs,
+
+    /// Verify only an evenly spread sample of N entries instead of all entries
+    #[arg(long, value_name = "N")]
+    sample: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+struct SharedArgs {
+    /// Root folder path or glob to recursively scan for .dpr and .pas (repeatable)
+    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
+    search_path: Vec<String>,
+
+    /// Fail instead of warning when a --search-path glob matches no directory
+    #[arg(long)]
+    error_on_unmatched_search_path: bool,
+
+    /// Optional folder path or glob to skip recursively (repeatable)
+    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
+    ignore_path: Vec<String>,
+
+    /// File listing one --search-path value per line; relative lines start at the file's folder (repeatable)
+    #[arg(long, value_name = "FILE", action = clap::ArgAction::Append)]
+    search_path_file: Vec<String>,
+
+    /// File listing one --ignore-path value per line; relative lines start at the file's folder (repeatable)
+    #[arg(long, value_name = "FILE", action = clap::ArgAction::Append)]
+    ignore_path_file: Vec<String>,
+
+    /// Follow symbolic links and junctions while scanning, walking each target once
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Descend at most N folder levels below each search root
+    #[arg(long, value_name = "N")]
+    max_scan_depth: Option<usize>,
+
+    /// Scan folders named .git, .svn, __history, __recovery, or backup
+    #[arg(long)]
+    no_default_ignores: bool,
+
+    /// Folder name to skip wherever it appears, in addition to the defaults (repeatable)
+    #[arg(long, value_name = "NAME", action = clap::ArgAction::Append)]
+    default_ignore: Vec<String>,
+
+    /// Abort on the first unreadable folder or file instead of warning and continuing
+    #[arg(long)]
+    strict_scan: bool,
+
+    /// Skip .pas and .dpr files larger than SIZE, in bytes or with a KB, MB, or GB suffix (0 = no limit)
+    #[arg(long, value_name = "SIZE", default_value = "16MB", value_parser = parse_file_size)]
+    max_file_size: u64,
+
+    /// Skip .pas files without a parsable unit header instead of naming them after the file
+    #[arg(long)]
+    no_stem_fallback: bool,
+
+    /// Show detailed info list
+    #[arg(long)]
+    show_infos: bool,
+
+    /// Show detailed warnings list
+    #[arg(long)]
+    show_warnings: bool,
+
+    /// List every warning instance instead of grouping identical warnings
+    #[arg(long)]
+    no_dedupe: bool,
+
+    /// Name every file referencing a missing include instead of the first few
+    #[arg(long)]
+    show_all_referencers: bool,
+
+    /// Silence warnings with the given diagnostic code (repeatable)
+    #[arg(long, value_name = "CODE", action = clap::ArgAction::Append)]
+    suppress: Vec<DiagnosticCode>,
+
+    /// Print full paths in warnings, infos, and the updated dpr list
+    #[arg(long)]
+    absolute_display_paths: bool,
+
+    /// Print per-dpr status, inserted units, and warnings after the report
+    #[arg(long)]
+    show_details: bool,
+
+    /// Persistent unit cache file (defaults to .fixdpr-cache under the first search path, which read-only runs do not write)
+    #[arg(long, value_name = "PATH")]
+    cache_file: Option<String>,
+
+    /// Disable the persistent unit cache
+    #[arg(long, conflicts_with = "cache_file")]
+    no_cache: bool,
+
+    /// Parse .pas files on N threads while the folder walk is still running (1 = sequential)
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "1",
+        value_parser = clap::builder::RangedI64ValueParser::<usize>::new().range(1..)
+    )]
+    jobs: usize,
+
+    /// Print how long each phase took and the slowest dprs to process
+    #[arg(long)]
+    timings: bool,
+}
+
+#[derive(Args, Debug, Default)]
+struct DependencyLookupArgs {
+    /// Assume compiler symbol is on or off during dependency traversal (repeatable)
+    #[arg(long, value_name = "SYMBOL=on|off", action = clap::ArgAction::Append)]
+    assume: Vec<DependencyAssumptionArg>,
+
+    /// Uses sections that feed the dependency graph: interface, implementation, or both (all)
+    #[arg(
+        long,
+        visible_alias = "closure",
+        value_name = "SECTIONS",
+        default_value = "both"
+    )]
+    uses_sections: UsesSections,
+
+    /// File of DCU-only unit names (one per line) that are never inserted or warned about
+    #[arg(long, value_name = "FILE")]
+    known_units: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct DependencyAssumptionArg {
+    symbol: String,
+    value: conditionals::AssumedValue,
+}
+
+impl fmt::Display for DependencyAssumptionArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.symbol, assumed_value_label(self.value))
+    }
+}
+
+impl FromStr for DependencyAssumptionArg {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let trimmed = value.trim();
+        if trimmed.is_empty() {
+            return Err("--assume cannot be empty".to_string());
+        }
+
+        let Some((symbol, raw_value)) = trimmed.split_once('=') else {
+            return Err("--assume must use SYMBOL=on|off".to_string());
+        };
+
+        let symbol = symbol.trim();
+        if symbol.is_empty() {
+            return Err("--assume symbol cannot be empty".to_string());
+        }
+
+        let raw_value = raw_value.trim();
+        if raw_value.is_empty() {
+            return Err("--assume value cannot be empty; expected on or off".to_string());
+        }
+
+        let value = match raw_value.to_ascii_lowercase().as_str() {
+            "on" => conditionals::AssumedValue::On,
+            "off" => conditionals::AssumedValue::Off,
+            _ => {
+                return Err(format!(
+                    "--assume value must be 'on' or 'off', got '{}'",
+                    raw_value
+                ));
+            }
+        };
+
+        Ok(Self {
+            symbol: symbol.to_ascii_uppercase(),
+            value,
+        })
+    }
+}
+
+#[derive(Args, Debug)]
+struct DelphiArgs {
+    /// Optional Delphi/VCL source root path to scan for fallback unit resolution (repeatable)
+    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
+    delphi_path: Vec<String>,
+
+    /// Optional Delphi version to resolve from registry and use as fallback source root (repeatable)
+    #[arg(long, value_name = "VERSION", action = clap::ArgAction::Append)]
+    delphi_version: Vec<String>,
+
+    /// Also use the Win32 library search path of each --delphi-version as fallback roots
+    #[arg(long, requires = "delphi_version")]
+    delphi_library_paths: bool,
+
+    /// Delphi BDS root whose source folder is used as fallback source root (repeatable)
+    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
+    delphi_root: Vec<String>,
+
+    /// Use each --delphi-root as the source folder itself
+    #[arg(long, requires = "delphi_root")]
+    delphi_root_is_source: bool,
+
+    /// Scan only these subdirectories of each Delphi source root, e.g. rtl,vcl,vcl/imaging
+    #[arg(long, value_name = "DIRS", value_delimiter = ',', action = clap::ArgAction::Append)]
+    delphi_source_dirs: Vec<String>,
+
+    /// Descend at most N folder levels below each Delphi fallback root
+    #[arg(long, value_name = "N")]
+    delphi_max_depth: Option<usize>,
+
+    /// Optional folder path or glob to skip while scanning the Delphi fallback roots (repeatable)
+    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
+    delphi_ignore_path: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct DprWriteArgs {
+    /// Write the absolute path of every updated dpr to FILE, one per line
+    #[arg(long, value_name = "FILE")]
+    write_updated_list: Option<String>,
+
+    /// Use forward slashes in the --write-updated-list file
+    #[arg(long, requires = "write_updated_list")]
+    forward_slashes: bool,
+
+    /// Seconds to wait for another fixdpr run editing the same dpr
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    lock_timeout: u64,
+
+    /// Write the changes as a unified diff to FILE instead of editing any dpr
+    #[arg(long, value_name = "FILE")]
+    emit_patch: Option<String>,
+
+    /// Run CMD on each updated dpr after all edits; the path replaces {} or is appended
+    #[arg(long, value_name = "CMD", conflicts_with = "emit_patch")]
+    on_update: Option<String>,
+
+    /// Count a dpr as failed when its --on-update command fails
+    #[arg(long, requires = "on_update")]
+    on_update_strict: bool,
+}
+
+impl DprWriteArgs {
+    fn lock_timeout(&self) -> Duration {
+        Duration::from_secs(self.lock_timeout)
+    }
+}
+
+#[derive(Args, Debug)]
+struct RelativePathArgs {
+    /// How to add a unit on another drive than the dpr: absolute, skip, or error
+    #[arg(long, value_name = "MODE", default_value = "absolute")]
+    cross_drive: dpr_edit::CrossDrive,
+
+    /// Most `..` folders a relative in-path may start with (default: unlimited)
+    #[arg(long, value_name = "N")]
+    max_parent_dirs: Option<usize>,
+
+    /// What to do with a path climbing more than --max-parent-dirs: absolute, skip, or allow
+    #[arg(long, value_name = "MODE", default_value = "absolute")]
+    on_deep_relative: dpr_edit::DeepRelative,
+}
+
+#[derive(Args, Debug)]
+struct EntryLayoutArgs {
+    /// Indentation for entries inserted on their own line, such as two spaces or \t for a tab
+    #[arg(long, value_name = "STRING", value_parser = parse_indent, allow_hyphen_values = true)]
+    indent: Option<String>,
+    /// Insert after an anchor entry inside an {$IFDEF} region instead of at the end of the list
+    #[arg(long)]
+    insert_in_conditional: bool,
+    /// Split a single-line uses list into one entry per line once an insert makes it longer than N columns
+    #[arg(long, value_name = "N")]
+    wrap_at: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+struct AddDependencyDprFilterArgs {
+    /// Optional glob pattern for .dpr files to ignore (repeatable)
+    #[arg(long, value_name = "GLOB", action = clap::ArgAction::Append)]
+    ignore_dpr: Vec<String>,
+    /// Optional glob for program or library names declared in .dpr headers to ignore (repeatable)
+    #[arg(long, value_name = "GLOB", action = clap::ArgAction::Append)]
+    ignore_program: Vec<String>,
+    /// Anchor relative --ignore-dpr patterns at the current folder (cwd) or at each search root (search-root)
+    #[arg(long, value_name = "MODE", default_value = "cwd")]
+    ignore_dpr_relative_to: fs_walk::DprGlobAnchor,
+}
+
+impl AddDependencyDprFilterArgs {
+    fn build_matcher(
+        &self,
+        cwd: &Path,
+        search_roots: &[PathBuf],
+    ) -> Result<fs_walk::DprIgnoreMatcher, FixdprError> {
+        let matcher = match self.ignore_dpr_relative_to {
+            fs_walk::DprGlobAnchor::Cwd => fs_walk::build_dpr_ignore_matcher(&self.ignore_dpr, cwd),
+            fs_walk::DprGlobAnchor::SearchRoot => {
+                fs_walk::build_dpr_ignore_matcher_anchored(&self.ignore_dpr, search_roots)
+            }
+        }?;
+        matcher.with_program_patterns(&self.ignore_program)
+    }
+}
+
+#[derive(Args, Debug)]
+#[command(group(
+    ArgGroup::new("insert_targets")
+        .required(true)
+        .multiple(true)
+        .args(["target_path", "target_dpr"])
+))]
+struct InsertDependencyTargetArgs {
+    /// Directory whose .dpr files should be updated recursively (repeatable)
+    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
+    target_path: Vec<String>,
+
+    /// Specific .dpr file to update (repeatable)
+    #[arg(long, value_name = "DPR_FILE", action = clap::ArgAction::Append)]
+    target_dpr: Vec<String>,
+}
+
+fn main() {
+    let mut cli = Cli::parse();
+    if let Some(common) = shared_args_mut(&mut cli.command) {
+        let cwd = match env::current_dir() {
+            Ok(path) => path,
+            Err(err) => exit_with(FixdprError::invalid_argument(format!(
+                "failed to read current directory: {err}"
+            ))),
+        };
+        if let Err(err) = expand_path_files(common, &cwd) {
+            exit_with(err);
+        }
+    }
+    match cli.command {
+        Commands::AddDependency(args) => run_add_dependency(args),
+        Commands::InsertDependency(args) => run_insert_dependency(args),
+        Commands::DeleteDependency(args) => run_delete_dependency(args),
+        Commands::FixDpr(args) => run_fix_dpr(args),
+        Commands::ListConditionals(args) => run_list_conditionals(args),
+        Commands::WhoUses(args) => run_who_uses(args),
+        Commands::Impact(args) => run_impact(args),
+        Commands::Dependents(args) => run_dependents(args),
+        Commands::Fanout(args) => run_fanout(args),
+        Commands::Cache(args) => run_cache(args),
+        Commands::Init(args) => run_init(args),
+        Commands::CopyUses(args) => run_copy_uses(args),
+        Commands::Serve(args) => run_serve(args),
+    }
+}
+
+fn run_add_dependency(args: AddDependencyArgs) {
+    let mut timings = Timings::start(args.common.timings);
     let cwd = match env::current_dir() {
         Ok(path) => path,
-        Err(err) => exit_with_error(format!("failed to read current directory: {err}"), 2),
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
     };
     let cwd = fs_walk::canonicalize_root(&cwd);
+    let updated_list = resolve_updated_list(&args.dpr_write, &cwd);
+    let emit_patch = resolve_emit_patch(&args.dpr_write, &cwd);
 
-    let search_roots = match fs_walk::resolve_search_roots(&args.common.search_path, &cwd) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
-    };
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
     let mut delphi_roots =
-        match fs_walk::resolve_optional_roots(&args.delphi_path, &cwd, "--delphi-path") {
+        match fs_walk::resolve_optional_roots(&args.delphi.delphi_path, &cwd, "--delphi-path") {
             Ok(roots) => roots,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
-    let mut delphi_roots_from_version = match delphi::resolve_source_roots(&args.delphi_version) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
+    let delphi::SourceRootsResolution {
+        roots: mut delphi_roots_from_version,
+        discovered_versions: discovered_delphi_versions,
+        library_roots: delphi_library_roots,
+        missing_source_dirs: missing_delphi_source_dirs,
+    } = match delphi::resolve_source_roots(
+        &args.delphi.delphi_version,
+        &delphi_source_options(&args.delphi, &cwd),
+    ) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
     };
     delphi_roots.append(&mut delphi_roots_from_version);
     delphi_roots = dedupe_paths(delphi_roots);
 
-    let mut warnings = Vec::new();
-    let dependency_assumptions = match build_dependency_assumptions(&args.dependency_lookup.assume)
-    {
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    push_missing_delphi_source_dir_warnings(&mut warnings, &missing_delphi_source_dirs);
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
         Ok(value) => value,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     let new_dependency_path = match resolve_new_dependency_path(&args.new_dependency, &cwd) {
         Ok(path) => path,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     if let Err(err) = validate_new_dependency_path(&new_dependency_path) {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
 
     let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
         Ok(matcher) => matcher,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
-    let ignore_dpr_matcher =
-        match fs_walk::build_dpr_ignore_matcher(&args.dpr_filter.ignore_dpr, &cwd) {
+    let delphi_ignore_matcher =
+        match fs_walk::build_delphi_ignore_matcher(&args.delphi.delphi_ignore_path, &cwd) {
             Ok(matcher) => matcher,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
+    let ignore_dpr_matcher = match args.dpr_filter.build_matcher(&cwd, &search_roots) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
+    let dpr_glob_matcher = match fs_walk::build_dpr_ignore_matcher(&args.dpr_glob, &cwd) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
 
     println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
     println!("Mode: add-dependency");
     println!("Scanning {} root(s):", search_roots.len());
     for root in &search_roots {
-        println!("  {}", root.display());
+        println!("  {}", plain_path(root));
     }
+    print_unmatched_search_paths(&unmatched_search_paths);
     if !delphi_roots.is_empty() {
         println!("Delphi fallback roots ({}):", delphi_roots.len());
         for root in &delphi_roots {
-            println!("  {}", root.display());
+            println!("  {}", plain_path(root));
         }
     }
-    let delphi_version_display = format_values(&args.delphi_version);
+    let delphi_version_display = format_values(&args.delphi.delphi_version);
     if !delphi_version_display.is_empty() {
         println!("Delphi version lookup: {}", delphi_version_display);
     }
+    print_discovered_delphi_versions(&discovered_delphi_versions);
+    print_delphi_library_roots(&delphi_library_roots);
+    print_delphi_ignores(&args.delphi.delphi_ignore_path);
+    delphi_roots = dedupe_paths([delphi_roots, delphi_library_roots].concat());
     let ignore_display = format_values(&args.common.ignore_path);
     if !ignore_display.is_empty() {
         println!("Ignoring: {}", ignore_display);
@@ -356,68 +1044,100 @@ fn run_add_dependency(args: AddDependencyArgs) {
     if !assume_display.is_empty() {
         println!("Assumptions: {}", assume_display);
     }
+    if args.dependency_lookup.uses_sections != UsesSections::Both {
+        println!(
+            "Uses sections: {}",
+            uses_sections_label(args.dependency_lookup.uses_sections)
+        );
+    }
+    print_known_units(args.dependency_lookup.known_units.as_deref(), &known_units);
     let ignore_dpr_display = format_values(ignore_dpr_matcher.normalized_patterns());
     if !ignore_dpr_display.is_empty() {
         println!("Ignoring dpr (absolute): {}", ignore_dpr_display);
     }
+    let ignore_program_display = format_values(ignore_dpr_matcher.normalized_program_patterns());
+    if !ignore_program_display.is_empty() {
+        println!("Ignoring programs: {}", ignore_program_display);
+    }
+    let dpr_glob_display = format_values(dpr_glob_matcher.normalized_patterns());
+    if !dpr_glob_display.is_empty() {
+        println!("Only dpr matching (absolute): {}", dpr_glob_display);
+    }
 
-    let scan = match fs_walk::scan_files(&search_roots, &ignore_matcher) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
-    let dpr_filter = fs_walk::filter_ignored_dpr_files(&scan.dpr_files, &ignore_dpr_matcher);
+    let started = Instant::now();
+    let scan = scan_search_roots(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &ignore_matcher,
+        &ConsoleEvents,
+        &mut warnings,
+    );
+    timings.record("scan", started);
+    let dpr_filter = fs_walk::filter_included_dpr_files(
+        fs_walk::filter_ignored_dpr_files(&scan.dpr_files, &ignore_dpr_matcher),
+        &dpr_glob_matcher,
+    );
     let mut infos = Vec::new();
     for path in &dpr_filter.ignored_files {
         infos.push(format!("info: ignored dpr {}", path.display()));
     }
-
-    println!(
-        "Found {} .pas, {} .dpr",
-        scan.pas_files.len(),
-        scan.dpr_files.len()
-    );
-    println!("Building unit cache...");
-    let mut unit_cache = match unit_cache::build_unit_cache(&scan.pas_files, &mut warnings) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
+    for path in &dpr_filter.filtered_files {
+        infos.push(format!(
+            "info: filtered dpr {} (no --dpr-glob match)",
+            path.display()
+        ));
+    }
+    let known_roots = [search_roots.as_slice(), delphi_roots.as_slice()].concat();
+    let dependency_root = match project::scan_dependency_root(
+        &project_options(&args.common, &cwd),
+        &known_roots,
+        &new_dependency_path,
+        args.dependency_root_depth,
+        &ignore_matcher,
+        &mut warnings,
+    ) {
+        Ok(dependency_root) => dependency_root,
+        Err(err) => exit_with(err),
     };
-    println!("Unit cache ready ({} units)", scan.pas_files.len());
+    let pas_files = merge_dependency_root(&scan.pas_files, dependency_root, &mut infos);
 
-    let mut delphi_unit_cache = if delphi_roots.is_empty() {
-        None
-    } else {
-        println!("Scanning Delphi fallback roots...");
-        let delphi_scan =
-            match fs_walk::scan_files(&delphi_roots, &fs_walk::IgnoreMatcher::default()) {
-                Ok(result) => result,
-                Err(err) => exit_with_error(err.to_string(), 1),
-            };
-        println!("Found {} fallback .pas", delphi_scan.pas_files.len());
-        println!("Building Delphi fallback unit cache...");
-        let cache = match unit_cache::build_unit_cache(&delphi_scan.pas_files, &mut warnings) {
-            Ok(result) => result,
-            Err(err) => exit_with_error(err.to_string(), 1),
-        };
-        println!(
-            "Delphi fallback unit cache ready ({} units)",
-            cache.by_path.len()
-        );
-        Some(cache)
-    };
+    println!("Building unit cache...");
+    let started = Instant::now();
+    let (unit_cache, mut cache_file) = build_project_unit_cache(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &pas_files,
+        &scan.inc_files,
+        &scan.prefetched,
+        known_units,
+        &mut warnings,
+    );
+    timings.record("project cache", started);
+    println!("Unit cache ready ({} units)", pas_files.len());
+
+    let delphi_unit_cache = build_delphi_unit_cache(
+        &delphi_roots,
+        args.delphi.delphi_max_depth,
+        delphi_ignore_matcher,
+        source_options(&args.common),
+    );
 
     let new_dependency_path = unit_cache::canonicalize_if_exists(&new_dependency_path);
-    let new_unit = match unit_cache::load_unit_file(&new_dependency_path, &mut warnings) {
+    let new_unit = match unit_cache::load_unit_file_with(
+        &new_dependency_path,
+        source_options(&args.common),
+        &mut warnings,
+    ) {
         Ok(Some(unit)) => unit,
         Ok(None) => {
-            exit_with_error(
-                format!(
-                    "unable to determine unit name from new dependency: {}",
-                    new_dependency_path.display()
-                ),
-                1,
-            );
+            exit_with(FixdprError::Failed(format!(
+                "unable to determine unit name from new dependency: {}",
+                new_dependency_path.display()
+            )));
         }
-        Err(err) => exit_with_error(err.to_string(), 1),
+        Err(err) => exit_with(err.into()),
     };
     println!(
         "New dependency: {} ({})",
@@ -425,73 +1145,133 @@ fn run_add_dependency(args: AddDependencyArgs) {
         new_unit.path.display()
     );
 
-    println!("Updating .dpr files... {}", dpr_filter.included_files.len());
-    let mut dpr_summary = match dpr_edit::update_dpr_files(
-        &dpr_filter.included_files,
-        &mut unit_cache,
-        delphi_unit_cache.as_mut(),
+    let edit = edit_options(&args.relative_paths, &args.layout)
+        .max_file_size(args.common.max_file_size)
+        .known_roots(known_roots)
+        .namespace_prefixes(namespace_prefixes(&args.namespace_prefix))
+        .lock_timeout(args.dpr_write.lock_timeout())
+        .dry_run(emit_patch.is_some())
+        .insert_position(if args.append {
+            dpr_edit::InsertPosition::End
+        } else {
+            dpr_edit::InsertPosition::AfterIntroducer
+        })
+        .events(Rc::new(ConsoleEvents))
+        .build();
+    let update_options = dpr_edit::UpdateOptions::builder()
+        .edit(edit)
+        .introduced_scope(introduced_scope(
+            args.introduced_scope,
+            args.disable_introduced_dependencies,
+        ))
+        .topo_order(args.topo_order)
+        .direct_only(args.direct_only)
+        .retarget_existing(args.retarget_existing)
+        .after_unit(args.after_unit.clone())
+        .build();
+    let fix_options = args.fix_updated_dprs.then(|| {
+        dpr_edit::FixOptions::for_updated_dprs(&update_options)
+            .no_delphi_inserts(args.no_delphi_inserts)
+            .build()
+    });
+    let mut project = project::Project {
+        cwd,
+        search_roots,
+        delphi_roots,
+        ignore: ignore_matcher,
+        scan,
+        unit_cache,
+        delphi_cache: delphi_unit_cache,
+        assumptions: dependency_assumptions,
+    };
+    let unchanged_dprs = project::UnchangedDprs::new(
+        &project,
+        cache_file.as_ref(),
         &new_unit,
-        !args.disable_introduced_dependencies,
-        &dependency_assumptions,
-    ) {
+        &update_options,
+        fix_options.as_ref(),
+    );
+    let (dprs_to_update, cached_unchanged) =
+        unchanged_dprs.skip(cache_file.as_ref(), &dpr_filter.included_files);
+    if cached_unchanged > 0 {
+        println!("Skipping {cached_unchanged} dpr file(s) unchanged since the last identical run");
+    }
+
+    println!("Updating .dpr files... {}", dprs_to_update.len());
+    let started = Instant::now();
+    let mut dpr_summary = match project.update(&dprs_to_update, &new_unit, &update_options) {
         Ok(summary) => summary,
-        Err(err) => exit_with_error(err.to_string(), 1),
+        Err(err) => exit_with(err),
     };
-    warnings.extend(dpr_summary.warnings.iter().cloned());
+    timings.record("dpr processing", started);
+    for path in &dpr_filter.ignored_files {
+        dpr_summary.merge_detail(dpr_edit::DprDetail::new(path, dpr_edit::DprStatus::Ignored));
+    }
+    for path in &dpr_filter.filtered_files {
+        dpr_summary.merge_detail(dpr_edit::DprDetail::new(
+            path,
+            dpr_edit::DprStatus::Filtered,
+        ));
+    }
 
-    if args.fix_updated_dprs && !dpr_summary.updated_paths.is_empty() {
+    let started = Instant::now();
+    if let Some(fix_options) = fix_options.filter(|_| !dpr_summary.updated_paths.is_empty()) {
         println!(
             "Running fix-dpr pass on updated dpr files... {}",
             dpr_summary.updated_paths.len()
         );
-        let mut fix_pass_scanned = 0usize;
-        let mut fix_pass_updated = 0usize;
-        let mut fix_pass_failures = 0usize;
-        let updated_paths = dpr_summary.updated_paths.clone();
-        for dpr_path in &updated_paths {
-            let fix_summary = match dpr_edit::fix_dpr_file(
-                dpr_path,
-                &unit_cache,
-                delphi_unit_cache.as_ref(),
-                &dependency_assumptions,
-            ) {
-                Ok(summary) => summary,
-                Err(err) => {
-                    warnings.push(format!(
-                        "warning: failed to run fix-dpr on {}: {err}",
-                        dpr_path.display()
-                    ));
-                    fix_pass_failures += 1;
-                    continue;
-                }
-            };
-            fix_pass_scanned += fix_summary.scanned;
-            fix_pass_updated += fix_summary.updated;
-            fix_pass_failures += fix_summary.failures;
-            warnings.extend(fix_summary.warnings);
-            for path in fix_summary.updated_paths {
-                if !contains_path(&dpr_summary.updated_paths, &path) {
-                    dpr_summary.updated_paths.push(path);
-                }
-            }
-        }
-        dpr_summary.updated = dpr_summary.updated_paths.len();
-        dpr_summary.failures += fix_pass_failures;
+        let pass = project.fix_updated_dprs(&mut dpr_summary, fix_options);
         println!(
             "fix-dpr pass report: scanned {}, updated {}, failures {}",
-            fix_pass_scanned, fix_pass_updated, fix_pass_failures
+            pass.scanned, pass.updated, pass.failures
         );
+        timings.record("fix pass", started);
     }
-
+    warnings.extend(dpr_summary.warnings.iter().cloned());
+    infos.extend(dpr_summary.infos.iter().map(|info| format!("info: {info}")));
+
+    unchanged_dprs.record(cache_file.as_mut(), &dprs_to_update, &dpr_summary);
+    save_project_cache_file(
+        &args.common,
+        &project.cwd,
+        emit_patch.is_some(),
+        cache_file.as_ref(),
+        &mut warnings,
+    );
+    dpr_summary.scanned += cached_unchanged;
+
+    timings.record_dprs(&dpr_summary);
+    timings.record_delphi_cache(project.delphi_cache.as_ref());
+    timings.record_canonical_paths(&project.unit_cache);
+    run_update_hooks(&args.dpr_write, &mut dpr_summary, &mut warnings);
+    write_updated_list(
+        updated_list.as_deref(),
+        args.dpr_write.forward_slashes,
+        &dpr_summary.updated_paths,
+        &mut warnings,
+    );
+    write_patch(
+        emit_patch.as_deref(),
+        &project.cwd,
+        &dpr_summary.updated_paths,
+        &dpr_summary.staged,
+        &mut warnings,
+    );
+    report_delphi_unit_cache(project.delphi_cache.as_ref(), &mut warnings);
     print_summary(SummaryOutput {
         infos: &infos,
         warnings: &warnings,
         show_infos: args.common.show_infos,
-        show_warnings: args.common.show_warnings,
-        pas_scanned: scan.pas_files.len(),
+        display: report_display(&args.common, &project.search_roots),
+        pas_scanned: pas_files.len(),
         dpr_summary: &dpr_summary,
+        staged_only: emit_patch.is_some(),
         ignored_dpr: dpr_filter.ignored_files.len(),
-        search_roots: &search_roots,
+        filtered_dpr: dpr_filter.filtered_files.len(),
+        cached_unchanged_dpr: cached_unchanged,
+        roots: &project.scan.roots,
+        report_unresolved: false,
+        timings: &timings,
     });
 
     if dpr_summary.failures > 0 {
@@ -500,62 +1280,618 @@ fn run_add_dependency(args: AddDependencyArgs) {
 }
 
 fn run_fix_dpr(args: FixDprArgs) {
+    let mut timings = Timings::start(args.common.timings);
     let cwd = match env::current_dir() {
         Ok(path) => path,
-        Err(err) => exit_with_error(format!("failed to read current directory: {err}"), 2),
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
     };
     let cwd = fs_walk::canonicalize_root(&cwd);
+    let updated_list = resolve_updated_list(&args.dpr_write, &cwd);
+    let emit_patch = resolve_emit_patch(&args.dpr_write, &cwd);
 
-    let search_roots = match fs_walk::resolve_search_roots(&args.common.search_path, &cwd) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
-    };
+    let fs_walk::SearchRootsResolution {
+        roots: mut search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
     let mut delphi_roots =
-        match fs_walk::resolve_optional_roots(&args.delphi_path, &cwd, "--delphi-path") {
+        match fs_walk::resolve_optional_roots(&args.delphi.delphi_path, &cwd, "--delphi-path") {
             Ok(roots) => roots,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
-    let mut delphi_roots_from_version = match delphi::resolve_source_roots(&args.delphi_version) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
+    let delphi::SourceRootsResolution {
+        roots: mut delphi_roots_from_version,
+        discovered_versions: discovered_delphi_versions,
+        library_roots: delphi_library_roots,
+        missing_source_dirs: missing_delphi_source_dirs,
+    } = match delphi::resolve_source_roots(
+        &args.delphi.delphi_version,
+        &delphi_source_options(&args.delphi, &cwd),
+    ) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
     };
     delphi_roots.append(&mut delphi_roots_from_version);
     delphi_roots = dedupe_paths(delphi_roots);
     let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
         Ok(matcher) => matcher,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
+    let delphi_ignore_matcher =
+        match fs_walk::build_delphi_ignore_matcher(&args.delphi.delphi_ignore_path, &cwd) {
+            Ok(matcher) => matcher,
+            Err(err) => exit_with(err),
+        };
     let target_dpr = match resolve_dpr_file_path(&args.dpr_file, &cwd) {
         Ok(path) => path,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     if let Err(err) = validate_dpr_file_path(&target_dpr, "DPR_FILE") {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
     let target_dpr = unit_cache::canonicalize_if_exists(&target_dpr);
-    let dependency_assumptions = match build_dependency_assumptions(&args.dependency_lookup.assume)
+    let mut infos = Vec::new();
+    if args.auto_root && !search_roots.iter().any(|root| target_dpr.starts_with(root)) {
+        if let Some(dpr_dir) = target_dpr.parent() {
+            let implicit_root = fs_walk::find_repo_root(dpr_dir).unwrap_or(dpr_dir.to_path_buf());
+            infos.push(format!(
+                "info: added search root {} because DPR_FILE is outside every --search-path",
+                implicit_root.display()
+            ));
+            search_roots.push(implicit_root);
+        }
+    }
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+
+    let text = args.format == FixDprFormat::Text;
+    if text {
+        println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
+        println!("Mode: fix-dpr");
+        println!("Target dpr: {}", plain_path(&target_dpr));
+        println!("Scanning {} root(s):", search_roots.len());
+        for root in &search_roots {
+            println!("  {}", plain_path(root));
+        }
+        print_unmatched_search_paths(&unmatched_search_paths);
+        if !delphi_roots.is_empty() {
+            println!("Delphi fallback roots ({}):", delphi_roots.len());
+            for root in &delphi_roots {
+                println!("  {}", plain_path(root));
+            }
+        }
+        let delphi_version_display = format_values(&args.delphi.delphi_version);
+        if !delphi_version_display.is_empty() {
+            println!("Delphi version lookup: {}", delphi_version_display);
+        }
+        print_discovered_delphi_versions(&discovered_delphi_versions);
+        print_delphi_library_roots(&delphi_library_roots);
+        print_delphi_ignores(&args.delphi.delphi_ignore_path);
+        let ignore_display = format_values(&args.common.ignore_path);
+        if !ignore_display.is_empty() {
+            println!("Ignoring: {}", ignore_display);
+        }
+        let assume_display = format_assumptions(&args.dependency_lookup.assume);
+        if !assume_display.is_empty() {
+            println!("Assumptions: {}", assume_display);
+        }
+        if args.dependency_lookup.uses_sections != UsesSections::Both {
+            println!(
+                "Uses sections: {}",
+                uses_sections_label(args.dependency_lookup.uses_sections)
+            );
+        }
+        print_known_units(args.dependency_lookup.known_units.as_deref(), &known_units);
+    }
+    delphi_roots = dedupe_paths([delphi_roots, delphi_library_roots].concat());
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    push_missing_delphi_source_dir_warnings(&mut warnings, &missing_delphi_source_dirs);
+    let started = Instant::now();
+    let scan = if text {
+        scan_search_roots(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &ignore_matcher,
+            &ConsoleEvents,
+            &mut warnings,
+        )
+    } else {
+        scan_search_roots_quiet(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &ignore_matcher,
+            &NoEvents,
+            &mut warnings,
+        )
+        .0
+    };
+    timings.record("scan", started);
+
+    if !contains_path(&scan.dpr_files, &target_dpr) {
+        exit_with(FixdprError::InvalidArgument(format!(
+            "DPR_FILE not found under --search-path after ignore filters: {}",
+            target_dpr.display()
+        )));
+    }
+
+    let started = Instant::now();
+    let (unit_cache, cache_file) = if text {
+        println!("Building unit cache...");
+        build_project_unit_cache(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &scan.pas_files,
+            &scan.inc_files,
+            &scan.prefetched,
+            known_units,
+            &mut warnings,
+        )
+    } else {
+        load_project_unit_cache(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &scan.pas_files,
+            &scan.inc_files,
+            &scan.prefetched,
+            known_units,
+            &NoEvents,
+            &mut warnings,
+        )
+    };
+    timings.record("project cache", started);
+    save_project_cache_file(
+        &args.common,
+        &cwd,
+        args.dry_run || emit_patch.is_some(),
+        cache_file.as_ref(),
+        &mut warnings,
+    );
+    let delphi_unit_cache = if text {
+        println!("Unit cache ready ({} units)", scan.pas_files.len());
+        let cache = build_delphi_unit_cache(
+            &delphi_roots,
+            args.delphi.delphi_max_depth,
+            delphi_ignore_matcher,
+            source_options(&args.common),
+        );
+        println!("Repairing target dpr...");
+        if args.dry_run {
+            println!("Dry run: the target dpr is not written");
+        }
+        cache
+    } else {
+        project::lazy_delphi_unit_cache(
+            &delphi_roots,
+            args.delphi.delphi_max_depth,
+            delphi_ignore_matcher,
+            source_options(&args.common),
+        )
+    };
+    let project = project::Project {
+        cwd,
+        search_roots,
+        delphi_roots,
+        ignore: ignore_matcher,
+        scan,
+        unit_cache,
+        delphi_cache: delphi_unit_cache,
+        assumptions: dependency_assumptions,
+    };
+    let events: Rc<dyn EventSink> = if text {
+        Rc::new(ConsoleEvents)
+    } else {
+        Rc::new(NoEvents)
+    };
+
+    let fix_options = dpr_edit::FixOptions::builder()
+        .edit(
+            edit_options(&args.relative_paths, &args.layout)
+                .max_file_size(args.common.max_file_size)
+                .known_roots(project.known_roots())
+                .namespace_prefixes(namespace_prefixes(&args.namespace_prefix))
+                .lock_timeout(args.dpr_write.lock_timeout())
+                .dry_run(args.dry_run || emit_patch.is_some())
+                .events(events),
+        )
+        .fix_mismatched_paths(args.fix_mismatched_paths)
+        .fix_duplicates(args.fix_duplicates)
+        .no_delphi_inserts(args.no_delphi_inserts)
+        .report_unresolved(args.report_unresolved)
+        .max_inserts(args.max_inserts)
+        .remove_missing(args.remove_missing)
+        .build();
+    let started = Instant::now();
+    let mut dpr_summary = match project.fix(&target_dpr, &fix_options) {
+        Ok(summary) => summary,
+        Err(err) => exit_with(err),
+    };
+    timings.record("dpr processing", started);
+    warnings.extend(dpr_summary.warnings.iter().cloned());
+    infos.extend(dpr_summary.infos.iter().map(|info| format!("info: {info}")));
+
+    timings.record_dprs(&dpr_summary);
+    timings.record_delphi_cache(project.delphi_cache.as_ref());
+    timings.record_canonical_paths(&project.unit_cache);
+    // A dry run writes nothing, so there is no changed file to hand to the hook.
+    if !args.dry_run {
+        run_update_hooks(&args.dpr_write, &mut dpr_summary, &mut warnings);
+    }
+    write_updated_list(
+        updated_list.as_deref(),
+        args.dpr_write.forward_slashes,
+        &dpr_summary.updated_paths,
+        &mut warnings,
+    );
+    write_patch(
+        emit_patch.as_deref(),
+        &project.cwd,
+        &dpr_summary.updated_paths,
+        &dpr_summary.staged,
+        &mut warnings,
+    );
+    let inserted = inserted_count(&dpr_summary);
+    if text {
+        report_delphi_unit_cache(project.delphi_cache.as_ref(), &mut warnings);
+        print_summary(SummaryOutput {
+            infos: &infos,
+            warnings: &warnings,
+            show_infos: args.common.show_infos,
+            display: report_display(&args.common, &project.search_roots),
+            pas_scanned: project.scan.pas_files.len(),
+            staged_only: args.dry_run || emit_patch.is_some(),
+            dpr_summary: &dpr_summary,
+            ignored_dpr: 0,
+            filtered_dpr: 0,
+            cached_unchanged_dpr: 0,
+            roots: &project.scan.roots,
+            report_unresolved: args.report_unresolved,
+            timings: &timings,
+        });
+        if args.dry_run {
+            let paths =
+                DisplayPaths::new(&project.search_roots, args.common.absolute_display_paths);
+            print_would_be_insertions(&dpr_summary, inserted, &paths);
+        }
+    } else {
+        if let Some(cache) = &project.delphi_cache {
+            warnings.extend(cache.take_warnings());
+        }
+        let display = report_display(&args.common, &project.search_roots);
+        print_missing_units_jsonl(&dpr_summary, args.dry_run || emit_patch.is_some(), &display);
+        let not_inserted = not_inserted_count(&dpr_summary);
+        eprintln!(
+            "fix-dpr: {inserted} unit(s) {} across {} dpr(s){}, {} warning(s)",
+            if args.dry_run {
+                "would be inserted"
+            } else {
+                "inserted"
+            },
+            dpr_summary.scanned,
+            if not_inserted > 0 {
+                format!(", {not_inserted} left out by --max-inserts")
+            } else {
+                String::new()
+            },
+            warnings.without(display.suppressed).len()
+        );
+    }
+
+    if dpr_summary.failures > 0
+        || (args.strict && !dpr_summary.unresolved.is_empty())
+        || (args.partial_is_failure && not_inserted_count(&dpr_summary) > 0)
+    {
+        process::exit(1);
+    }
+    if args.dry_run && inserted > 0 && !args.no_exit_on_changes {
+        process::exit(EXIT_CHANGES_NEEDED);
+    }
+}
+
+/// Exit code of `fix-dpr --dry-run` when the dpr is missing units.
+const EXIT_CHANGES_NEEDED: i32 = 4;
+
+/// Units inserted, or for a dry run to be inserted, across all dprs.
+fn inserted_count(dpr_summary: &dpr_edit::DprUpdateSummary) -> usize {
+    dpr_summary
+        .details
+        .iter()
+        .map(|detail| detail.inserted.len())
+        .sum()
+}
+
+/// Missing units left out by `--max-inserts` across all dprs.
+fn not_inserted_count(dpr_summary: &dpr_edit::DprUpdateSummary) -> usize {
+    dpr_summary
+        .details
+        .iter()
+        .map(|detail| detail.not_inserted)
+        .sum()
+}
+
+/// Prints the `count` units a dry run would have inserted.
+fn print_would_be_insertions(
+    dpr_summary: &dpr_edit::DprUpdateSummary,
+    count: usize,
+    paths: &DisplayPaths,
+) {
+    if count == 0 {
+        println!("Dry run: no units would be inserted");
+        return;
+    }
+    println!("Dry run: {count} unit(s) would be inserted; run without --dry-run to apply:");
+    for detail in &dpr_summary.details {
+        if detail.inserted.is_empty() {
+            continue;
+        }
+        println!("  {}", paths.path(&detail.path));
+        for unit in &detail.inserted {
+            match &unit.in_path {
+                Some(in_path) => println!("    {} in '{in_path}'", unit.name),
+                None => println!("    {}", unit.name),
+            }
+        }
+        if detail.not_inserted > 0 {
+            println!(
+                "    ({} more left out by --max-inserts)",
+                detail.not_inserted
+            );
+        }
+    }
+}
+
+/// Prints one JSON object per dpr with missing units or warnings, so large
+/// runs can be consumed line by line.
+fn print_missing_units_jsonl(
+    dpr_summary: &dpr_edit::DprUpdateSummary,
+    staged_only: bool,
+    display: &ReportDisplay<'_>,
+) {
+    for detail in &dpr_summary.details {
+        let shows_warnings = detail
+            .warnings
+            .iter()
+            .any(|warning| !display.suppressed.contains(&warning.code));
+        if detail.inserted.is_empty()
+            && detail.not_inserted == 0
+            && detail.missing_entries.is_empty()
+            && !shows_warnings
+        {
+            continue;
+        }
+        println!("{}", dpr_detail_json(detail, staged_only, display));
+    }
+}
+
+fn run_serve(args: ServeArgs) {
+    let cwd = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
+    };
+    let cwd = fs_walk::canonicalize_root(&cwd);
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
+    let mut delphi_roots =
+        match fs_walk::resolve_optional_roots(&args.delphi.delphi_path, &cwd, "--delphi-path") {
+            Ok(roots) => roots,
+            Err(err) => exit_with(err),
+        };
+    let delphi::SourceRootsResolution {
+        roots: mut delphi_roots_from_version,
+        library_roots: delphi_library_roots,
+        missing_source_dirs: missing_delphi_source_dirs,
+        ..
+    } = match delphi::resolve_source_roots(
+        &args.delphi.delphi_version,
+        &delphi_source_options(&args.delphi, &cwd),
+    ) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
+    };
+    delphi_roots.append(&mut delphi_roots_from_version);
+    delphi_roots = dedupe_paths([delphi_roots, delphi_library_roots].concat());
+    let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
+    let delphi_ignore_matcher =
+        match fs_walk::build_delphi_ignore_matcher(&args.delphi.delphi_ignore_path, &cwd) {
+            Ok(matcher) => matcher,
+            Err(err) => exit_with(err),
+        };
+    let assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    push_missing_delphi_source_dir_warnings(&mut warnings, &missing_delphi_source_dirs);
+    let scan = scan_search_roots_quiet(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &ignore_matcher,
+        &NoEvents,
+        &mut warnings,
+    )
+    .0;
+    let (unit_cache, cache_file) = load_project_unit_cache(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &scan.pas_files,
+        &scan.inc_files,
+        &scan.prefetched,
+        known_units,
+        &NoEvents,
+        &mut warnings,
+    );
+    save_project_cache_file(
+        &args.common,
+        &cwd,
+        false,
+        cache_file.as_ref(),
+        &mut warnings,
+    );
+    let delphi_cache = project::lazy_delphi_unit_cache(
+        &delphi_roots,
+        args.delphi.delphi_max_depth,
+        delphi_ignore_matcher,
+        source_options(&args.common),
+    );
+
+    let project = project::Project {
+        cwd,
+        search_roots,
+        delphi_roots,
+        ignore: ignore_matcher,
+        scan,
+        unit_cache,
+        delphi_cache,
+        assumptions,
+    };
+    let fix_options = dpr_edit::FixOptions::builder()
+        .edit(
+            edit_options(&args.relative_paths, &args.layout)
+                .max_file_size(args.common.max_file_size)
+                .known_roots(project.known_roots())
+                .namespace_prefixes(namespace_prefixes(&args.namespace_prefix))
+                .lock_timeout(Duration::from_secs(args.lock_timeout)),
+        )
+        .build();
+    let display = report_display(&args.common, &project.search_roots);
+    let mut session = serve::Session {
+        events: Rc::new(StderrWarnings(display.paths.clone())),
+        project,
+        fix_options,
+        display,
+    };
+    for warning in warnings.without(session.display.suppressed).iter() {
+        session.events.on_warning(warning);
+    }
+    eprintln!(
+        "fixdpr serve: {} unit(s), {} dpr(s); reading requests from stdin",
+        session.project.unit_cache.by_path.len(),
+        session.project.scan.dpr_files.len()
+    );
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    if let Err(err) = session.run(stdin.lock(), stdout.lock()) {
+        exit_with(FixdprError::Io {
+            path: None,
+            source: err,
+        });
+    }
+}
+
+fn run_init(args: InitArgs) {
+    let mut timings = Timings::start(args.common.timings);
+    let cwd = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
+    };
+    let cwd = fs_walk::canonicalize_root(&cwd);
+
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
+    let mut delphi_roots =
+        match fs_walk::resolve_optional_roots(&args.delphi.delphi_path, &cwd, "--delphi-path") {
+            Ok(roots) => roots,
+            Err(err) => exit_with(err),
+        };
+    let delphi::SourceRootsResolution {
+        roots: mut delphi_roots_from_version,
+        discovered_versions: discovered_delphi_versions,
+        library_roots: delphi_library_roots,
+        missing_source_dirs: missing_delphi_source_dirs,
+    } = match delphi::resolve_source_roots(
+        &args.delphi.delphi_version,
+        &delphi_source_options(&args.delphi, &cwd),
+    ) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
+    };
+    delphi_roots.append(&mut delphi_roots_from_version);
+    delphi_roots = dedupe_paths(delphi_roots);
+    let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
+    let delphi_ignore_matcher =
+        match fs_walk::build_delphi_ignore_matcher(&args.delphi.delphi_ignore_path, &cwd) {
+            Ok(matcher) => matcher,
+            Err(err) => exit_with(err),
+        };
+    let target_dpr = match resolve_dpr_file_path(&args.dpr_file, &cwd)
+        .and_then(|path| validate_new_dpr_file_path(&path, "DPR_FILE"))
     {
+        Ok(path) => path,
+        Err(err) => exit_with(err),
+    };
+    let seed_path = match resolve_path_with_flag(&args.seed, &cwd, "--seed") {
+        Ok(path) => path,
+        Err(err) => exit_with(err),
+    };
+    if let Err(err) = validate_pas_file_path(&seed_path, "--seed") {
+        exit_with(err);
+    }
+    let seed_path = unit_cache::canonicalize_if_exists(&seed_path);
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
         Ok(value) => value,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
 
     println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
-    println!("Mode: fix-dpr");
-    println!("Target dpr: {}", target_dpr.display());
+    println!("Mode: init");
+    println!("Target dpr: {}", plain_path(&target_dpr));
+    println!("Seed unit: {}", plain_path(&seed_path));
     println!("Scanning {} root(s):", search_roots.len());
     for root in &search_roots {
-        println!("  {}", root.display());
+        println!("  {}", plain_path(root));
     }
+    print_unmatched_search_paths(&unmatched_search_paths);
     if !delphi_roots.is_empty() {
         println!("Delphi fallback roots ({}):", delphi_roots.len());
         for root in &delphi_roots {
-            println!("  {}", root.display());
+            println!("  {}", plain_path(root));
         }
     }
-    let delphi_version_display = format_values(&args.delphi_version);
+    let delphi_version_display = format_values(&args.delphi.delphi_version);
     if !delphi_version_display.is_empty() {
         println!("Delphi version lookup: {}", delphi_version_display);
     }
+    print_discovered_delphi_versions(&discovered_delphi_versions);
+    print_delphi_library_roots(&delphi_library_roots);
+    print_delphi_ignores(&args.delphi.delphi_ignore_path);
+    delphi_roots = dedupe_paths([delphi_roots, delphi_library_roots].concat());
     let ignore_display = format_values(&args.common.ignore_path);
     if !ignore_display.is_empty() {
         println!("Ignoring: {}", ignore_display);
@@ -564,77 +1900,104 @@ fn run_fix_dpr(args: FixDprArgs) {
     if !assume_display.is_empty() {
         println!("Assumptions: {}", assume_display);
     }
-    let scan = match fs_walk::scan_files(&search_roots, &ignore_matcher) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
-    let infos = Vec::new();
-    println!(
-        "Found {} .pas, {} .dpr",
-        scan.pas_files.len(),
-        scan.dpr_files.len()
-    );
-
-    if !contains_path(&scan.dpr_files, &target_dpr) {
-        exit_with_error(
-            format!(
-                "DPR_FILE not found under --search-path after ignore filters: {}",
-                target_dpr.display()
-            ),
-            2,
+    if args.dependency_lookup.uses_sections != UsesSections::Both {
+        println!(
+            "Uses sections: {}",
+            uses_sections_label(args.dependency_lookup.uses_sections)
         );
     }
+    print_known_units(args.dependency_lookup.known_units.as_deref(), &known_units);
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    push_missing_delphi_source_dir_warnings(&mut warnings, &missing_delphi_source_dirs);
+    let started = Instant::now();
+    let scan = scan_search_roots(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &ignore_matcher,
+        &ConsoleEvents,
+        &mut warnings,
+    );
+    timings.record("scan", started);
 
-    let mut warnings = Vec::new();
     println!("Building unit cache...");
-    let unit_cache = match unit_cache::build_unit_cache(&scan.pas_files, &mut warnings) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
+    let started = Instant::now();
+    let (unit_cache, cache_file) = build_project_unit_cache(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &scan.pas_files,
+        &scan.inc_files,
+        &scan.prefetched,
+        known_units,
+        &mut warnings,
+    );
+    timings.record("project cache", started);
+    save_project_cache_file(
+        &args.common,
+        &cwd,
+        false,
+        cache_file.as_ref(),
+        &mut warnings,
+    );
     println!("Unit cache ready ({} units)", scan.pas_files.len());
-    let delphi_unit_cache = if delphi_roots.is_empty() {
-        None
-    } else {
-        println!("Scanning Delphi fallback roots...");
-        let delphi_scan =
-            match fs_walk::scan_files(&delphi_roots, &fs_walk::IgnoreMatcher::default()) {
-                Ok(result) => result,
-                Err(err) => exit_with_error(err.to_string(), 1),
-            };
-        println!("Found {} fallback .pas", delphi_scan.pas_files.len());
-        println!("Building Delphi fallback unit cache...");
-        let cache = match unit_cache::build_unit_cache(&delphi_scan.pas_files, &mut warnings) {
-            Ok(result) => result,
-            Err(err) => exit_with_error(err.to_string(), 1),
-        };
-        println!(
-            "Delphi fallback unit cache ready ({} units)",
-            cache.by_path.len()
-        );
-        Some(cache)
-    };
-    println!("Repairing target dpr...");
-
-    let dpr_summary = match dpr_edit::fix_dpr_file(
+    let delphi_unit_cache = build_delphi_unit_cache(
+        &delphi_roots,
+        args.delphi.delphi_max_depth,
+        delphi_ignore_matcher,
+        source_options(&args.common),
+    );
+    println!("Writing uses list...");
+
+    let init_options = dpr_edit::FixOptions::builder()
+        .edit(
+            edit_options(&args.relative_paths, &args.layout)
+                .max_file_size(args.common.max_file_size)
+                .known_roots([search_roots.as_slice(), delphi_roots.as_slice()].concat())
+                .events(Rc::new(ConsoleEvents)),
+        )
+        .no_delphi_inserts(args.no_delphi_inserts)
+        .build();
+    let started = Instant::now();
+    let dpr_summary = match dpr_edit::init_dpr_file_with(
         &target_dpr,
+        &seed_path,
         &unit_cache,
         delphi_unit_cache.as_ref(),
         &dependency_assumptions,
+        args.force,
+        &init_options,
     ) {
         Ok(summary) => summary,
-        Err(err) => exit_with_error(err.to_string(), 1),
+        Err(err) => exit_with(err.into()),
     };
+    timings.record("dpr processing", started);
     warnings.extend(dpr_summary.warnings.iter().cloned());
+    let infos: Vec<String> = dpr_summary
+        .infos
+        .iter()
+        .map(|info| format!("info: {info}"))
+        .collect();
 
+    timings.record_dprs(&dpr_summary);
+    timings.record_delphi_cache(delphi_unit_cache.as_ref());
+    timings.record_canonical_paths(&unit_cache);
+    report_delphi_unit_cache(delphi_unit_cache.as_ref(), &mut warnings);
     print_summary(SummaryOutput {
         infos: &infos,
         warnings: &warnings,
         show_infos: args.common.show_infos,
-        show_warnings: args.common.show_warnings,
+        display: report_display(&args.common, &search_roots),
         pas_scanned: scan.pas_files.len(),
+        staged_only: false,
         dpr_summary: &dpr_summary,
         ignored_dpr: 0,
-        search_roots: &search_roots,
+        filtered_dpr: 0,
+        cached_unchanged_dpr: 0,
+        roots: &scan.roots,
+        report_unresolved: false,
+        timings: &timings,
     });
 
     if dpr_summary.failures > 0 {
@@ -642,114 +2005,237 @@ fn run_fix_dpr(args: FixDprArgs) {
     }
 }
 
-fn run_list_conditionals(args: ListConditionalsArgs) {
+fn run_copy_uses(args: CopyUsesArgs) {
+    let timings = Timings::start(false);
     let cwd = match env::current_dir() {
         Ok(path) => path,
-        Err(err) => exit_with_error(format!("failed to read current directory: {err}"), 2),
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
     };
     let cwd = fs_walk::canonicalize_root(&cwd);
+    let updated_list = resolve_updated_list(&args.dpr_write, &cwd);
+    let emit_patch = resolve_emit_patch(&args.dpr_write, &cwd);
+    let resolve = |value: &str, flag: &str| {
+        resolve_path_with_flag(value, &cwd, flag)
+            .and_then(|path| validate_dpr_file_path(&path, flag).map(|()| path))
+            .map(|path| unit_cache::canonicalize_if_exists(&path))
+            .unwrap_or_else(|err| exit_with(err))
+    };
+    let source_dpr = resolve(&args.from, "--from");
+    let target_dpr = resolve(&args.to, "--to");
+    if source_dpr == target_dpr {
+        exit_with(FixdprError::invalid_argument(
+            "--from and --to name the same dpr file",
+        ));
+    }
 
-    let search_roots = match fs_walk::resolve_search_roots(&args.common.search_path, &cwd) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
+    println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
+    println!("Mode: copy-uses");
+    println!("Source dpr: {}", plain_path(&source_dpr));
+    println!("Target dpr: {}", plain_path(&target_dpr));
+    if args.sync {
+        println!("Removing target entries the source does not list");
+    }
+    println!("Copying uses entries...");
+
+    let copy_options = dpr_edit::FixOptions::builder()
+        .edit(
+            edit_options(&args.relative_paths, &args.layout)
+                .lock_timeout(args.dpr_write.lock_timeout())
+                .dry_run(emit_patch.is_some())
+                .events(Rc::new(ConsoleEvents)),
+        )
+        .build();
+    let mut dpr_summary =
+        match dpr_edit::copy_uses_with(&source_dpr, &target_dpr, args.sync, &copy_options) {
+            Ok(summary) => summary,
+            Err(err) => exit_with(err.into()),
+        };
+    let mut warnings = Warnings::new();
+    warnings.extend(dpr_summary.warnings.iter().cloned());
+    let infos: Vec<String> = dpr_summary
+        .infos
+        .iter()
+        .map(|info| format!("info: {info}"))
+        .collect();
+
+    run_update_hooks(&args.dpr_write, &mut dpr_summary, &mut warnings);
+    write_updated_list(
+        updated_list.as_deref(),
+        args.dpr_write.forward_slashes,
+        &dpr_summary.updated_paths,
+        &mut warnings,
+    );
+    write_patch(
+        emit_patch.as_deref(),
+        &cwd,
+        &dpr_summary.updated_paths,
+        &dpr_summary.staged,
+        &mut warnings,
+    );
+    let display_roots: Vec<PathBuf> = target_dpr
+        .parent()
+        .map(Path::to_path_buf)
+        .into_iter()
+        .collect();
+    print_summary(SummaryOutput {
+        infos: &infos,
+        warnings: &warnings,
+        show_infos: args.show_infos,
+        display: ReportDisplay {
+            show_warnings: args.show_warnings,
+            show_details: args.show_details,
+            dedupe_warnings: !args.no_dedupe,
+            show_all_referencers: args.show_all_referencers,
+            suppressed: &args.suppress,
+            paths: DisplayPaths::new(&display_roots, args.absolute_display_paths),
+        },
+        pas_scanned: 0,
+        dpr_summary: &dpr_summary,
+        staged_only: emit_patch.is_some(),
+        ignored_dpr: 0,
+        filtered_dpr: 0,
+        cached_unchanged_dpr: 0,
+        roots: &[],
+        report_unresolved: false,
+        timings: &timings,
+    });
+
+    if dpr_summary.failures > 0 {
+        process::exit(1);
+    }
+}
+
+fn run_list_conditionals(args: ListConditionalsArgs) {
+    let mut timings = Timings::start(args.common.timings);
+    let cwd = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
     };
+    let cwd = fs_walk::canonicalize_root(&cwd);
+
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
     let mut delphi_roots =
-        match fs_walk::resolve_optional_roots(&args.delphi_path, &cwd, "--delphi-path") {
+        match fs_walk::resolve_optional_roots(&args.delphi.delphi_path, &cwd, "--delphi-path") {
             Ok(roots) => roots,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
-    let mut delphi_roots_from_version = match delphi::resolve_source_roots(&args.delphi_version) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
+    let delphi::SourceRootsResolution {
+        roots: mut delphi_roots_from_version,
+        discovered_versions: discovered_delphi_versions,
+        library_roots: delphi_library_roots,
+        missing_source_dirs: missing_delphi_source_dirs,
+    } = match delphi::resolve_source_roots(
+        &args.delphi.delphi_version,
+        &delphi_source_options(&args.delphi, &cwd),
+    ) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
     };
     delphi_roots.append(&mut delphi_roots_from_version);
     delphi_roots = dedupe_paths(delphi_roots);
     let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
         Ok(matcher) => matcher,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
+    let delphi_ignore_matcher =
+        match fs_walk::build_delphi_ignore_matcher(&args.delphi.delphi_ignore_path, &cwd) {
+            Ok(matcher) => matcher,
+            Err(err) => exit_with(err),
+        };
     let target_dpr = match resolve_dpr_file_path(&args.dpr_file, &cwd) {
         Ok(path) => path,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     if let Err(err) = validate_dpr_file_path(&target_dpr, "DPR_FILE") {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
     let target_dpr = unit_cache::canonicalize_if_exists(&target_dpr);
     let dependency_assumptions = conditionals::Assumptions::default();
+    let known_units = match load_known_units(args.known_units.as_deref(), &cwd) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
 
     println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
     println!("Mode: list-conditionals");
-    println!("Target dpr: {}", target_dpr.display());
+    println!("Target dpr: {}", plain_path(&target_dpr));
     println!("Scanning {} root(s):", search_roots.len());
     for root in &search_roots {
-        println!("  {}", root.display());
+        println!("  {}", plain_path(root));
     }
+    print_unmatched_search_paths(&unmatched_search_paths);
     if !delphi_roots.is_empty() {
         println!("Delphi fallback roots ({}):", delphi_roots.len());
         for root in &delphi_roots {
-            println!("  {}", root.display());
+            println!("  {}", plain_path(root));
         }
     }
-    let delphi_version_display = format_values(&args.delphi_version);
+    let delphi_version_display = format_values(&args.delphi.delphi_version);
     if !delphi_version_display.is_empty() {
         println!("Delphi version lookup: {}", delphi_version_display);
     }
+    print_discovered_delphi_versions(&discovered_delphi_versions);
+    print_delphi_library_roots(&delphi_library_roots);
+    print_delphi_ignores(&args.delphi.delphi_ignore_path);
+    delphi_roots = dedupe_paths([delphi_roots, delphi_library_roots].concat());
     let ignore_display = format_values(&args.common.ignore_path);
     if !ignore_display.is_empty() {
         println!("Ignoring: {}", ignore_display);
     }
-
-    let scan = match fs_walk::scan_files(&search_roots, &ignore_matcher) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
-    println!(
-        "Found {} .pas, {} .dpr",
-        scan.pas_files.len(),
-        scan.dpr_files.len()
+    print_known_units(args.known_units.as_deref(), &known_units);
+
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    push_missing_delphi_source_dir_warnings(&mut warnings, &missing_delphi_source_dirs);
+    let started = Instant::now();
+    let scan = scan_search_roots(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &ignore_matcher,
+        &ConsoleEvents,
+        &mut warnings,
     );
+    timings.record("scan", started);
 
     if !contains_path(&scan.dpr_files, &target_dpr) {
-        exit_with_error(
-            format!(
-                "DPR_FILE not found under --search-path after ignore filters: {}",
-                target_dpr.display()
-            ),
-            2,
-        );
+        exit_with(FixdprError::InvalidArgument(format!(
+            "DPR_FILE not found under --search-path after ignore filters: {}",
+            target_dpr.display()
+        )));
     }
 
-    let mut warnings = Vec::new();
     println!("Building unit cache...");
-    let unit_cache = match unit_cache::build_unit_cache(&scan.pas_files, &mut warnings) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
+    let started = Instant::now();
+    let (unit_cache, cache_file) = build_project_unit_cache(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &scan.pas_files,
+        &scan.inc_files,
+        &scan.prefetched,
+        known_units,
+        &mut warnings,
+    );
+    timings.record("project cache", started);
+    save_project_cache_file(&args.common, &cwd, true, cache_file.as_ref(), &mut warnings);
     println!("Unit cache ready ({} units)", scan.pas_files.len());
-    let delphi_unit_cache = if delphi_roots.is_empty() {
-        None
-    } else {
-        println!("Scanning Delphi fallback roots...");
-        let delphi_scan =
-            match fs_walk::scan_files(&delphi_roots, &fs_walk::IgnoreMatcher::default()) {
-                Ok(result) => result,
-                Err(err) => exit_with_error(err.to_string(), 1),
-            };
-        println!("Found {} fallback .pas", delphi_scan.pas_files.len());
-        println!("Building Delphi fallback unit cache...");
-        let cache = match unit_cache::build_unit_cache(&delphi_scan.pas_files, &mut warnings) {
-            Ok(result) => result,
-            Err(err) => exit_with_error(err.to_string(), 1),
-        };
-        println!(
-            "Delphi fallback unit cache ready ({} units)",
-            cache.by_path.len()
-        );
-        Some(cache)
-    };
+    let delphi_unit_cache = build_delphi_unit_cache(
+        &delphi_roots,
+        args.delphi.delphi_max_depth,
+        delphi_ignore_matcher,
+        source_options(&args.common),
+    );
 
     println!("Analyzing target dpr conditionals...");
+    let started = Instant::now();
     let conditional_units = match conditionals::collect_dpr_conditional_units(
         &target_dpr,
         &unit_cache,
@@ -758,113 +2244,1028 @@ fn run_list_conditionals(args: ListConditionalsArgs) {
         &mut warnings,
     ) {
         Ok(Some(units)) => units,
-        Ok(None) => exit_with_error(format!("no uses list found in {}", target_dpr.display()), 1),
-        Err(err) => exit_with_error(err.to_string(), 1),
+        Ok(None) => exit_with(FixdprError::Failed(format!(
+            "no uses list found in {}",
+            target_dpr.display()
+        ))),
+        Err(err) => exit_with(err.into()),
     };
+    timings.record("dpr analysis", started);
     let buckets = conditionals::bucket_conditionals(&conditional_units);
+    timings.record_delphi_cache(delphi_unit_cache.as_ref());
+    timings.record_canonical_paths(&unit_cache);
+    report_delphi_unit_cache(delphi_unit_cache.as_ref(), &mut warnings);
 
     print_conditionals_summary(ConditionalsOutput {
         warnings: &warnings,
-        show_warnings: args.common.show_warnings,
+        display: report_display(&args.common, &search_roots),
         pas_scanned: scan.pas_files.len(),
         dpr_scanned: 1,
         buckets: &buckets,
     });
+    if args.common.timings {
+        timings.print(&report_display(&args.common, &search_roots));
+    }
 }
 
-fn run_insert_dependency(args: InsertDependencyArgs) {
+fn run_who_uses(args: WhoUsesArgs) {
+    let mut timings = Timings::start(args.common.timings);
     let cwd = match env::current_dir() {
         Ok(path) => path,
-        Err(err) => exit_with_error(format!("failed to read current directory: {err}"), 2),
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
     };
     let cwd = fs_walk::canonicalize_root(&cwd);
+    let text = args.format == OutputFormat::Text;
 
-    let search_roots = match fs_walk::resolve_search_roots(&args.common.search_path, &cwd) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
+    let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    if let Some(include) = &args.include {
+        let include_path = match resolve_path_with_flag(include, &cwd, "--include") {
+            Ok(path) if path.is_file() => path,
+            Ok(path) => exit_with(FixdprError::PathNotFound {
+                flag: "--include".to_string(),
+                path,
+            }),
+            Err(err) => exit_with(err),
+        };
+        let started = Instant::now();
+        let scan = if text {
+            println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
+            println!("Mode: who-uses");
+            println!("Include: {}", plain_path(&include_path));
+            println!("Scanning {} root(s):", search_roots.len());
+            for root in &search_roots {
+                println!("  {}", plain_path(root));
+            }
+            print_unmatched_search_paths(&unmatched_search_paths);
+            scan_search_roots(
+                &args.common,
+                &search_roots,
+                &cwd,
+                &ignore_matcher,
+                &ConsoleEvents,
+                &mut warnings,
+            )
+        } else {
+            scan_search_roots_quiet(
+                &args.common,
+                &search_roots,
+                &cwd,
+                &ignore_matcher,
+                &NoEvents,
+                &mut warnings,
+            )
+            .0
+        };
+        timings.record("scan", started);
+        let display = report_display(&args.common, &search_roots);
+        run_who_uses_include(
+            &include_path,
+            &scan,
+            &warnings,
+            &display,
+            text,
+            &mut timings,
+        );
+        return;
+    }
+    let unit = args.unit.as_deref().unwrap_or_default();
+    let unit_name =
+        match project::resolve_unit_name(unit, &cwd, source_options(&args.common), &mut warnings) {
+            Ok(name) => name,
+            Err(err) => exit_with(err),
+        };
+
+    if text {
+        println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
+        println!("Mode: who-uses");
+        println!("Unit: {unit_name}");
+        println!("Scanning {} root(s):", search_roots.len());
+        for root in &search_roots {
+            println!("  {}", plain_path(root));
+        }
+        print_unmatched_search_paths(&unmatched_search_paths);
+        let ignore_display = format_values(&args.common.ignore_path);
+        if !ignore_display.is_empty() {
+            println!("Ignoring: {}", ignore_display);
+        }
+        let assume_display = format_assumptions(&args.dependency_lookup.assume);
+        if !assume_display.is_empty() {
+            println!("Assumptions: {}", assume_display);
+        }
+        if args.dependency_lookup.uses_sections != UsesSections::Both {
+            println!(
+                "Uses sections: {}",
+                uses_sections_label(args.dependency_lookup.uses_sections)
+            );
+        }
+        print_known_units(args.dependency_lookup.known_units.as_deref(), &known_units);
+    }
+
+    let (scan, (unit_cache, cache_file)) = if text {
+        let started = Instant::now();
+        let scan = scan_search_roots(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &ignore_matcher,
+            &ConsoleEvents,
+            &mut warnings,
+        );
+        timings.record("scan", started);
+        println!("Building unit cache...");
+        let started = Instant::now();
+        let cache = build_project_unit_cache(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &scan.pas_files,
+            &scan.inc_files,
+            &scan.prefetched,
+            known_units,
+            &mut warnings,
+        );
+        timings.record("project cache", started);
+        println!("Unit cache ready ({} units)", scan.pas_files.len());
+        (scan, cache)
+    } else {
+        let started = Instant::now();
+        let scan = scan_search_roots_quiet(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &ignore_matcher,
+            &NoEvents,
+            &mut warnings,
+        )
+        .0;
+        timings.record("scan", started);
+        let started = Instant::now();
+        let cache = load_project_unit_cache(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &scan.pas_files,
+            &scan.inc_files,
+            &scan.prefetched,
+            known_units,
+            &NoEvents,
+            &mut warnings,
+        );
+        timings.record("project cache", started);
+        (scan, cache)
+    };
+    save_project_cache_file(&args.common, &cwd, true, cache_file.as_ref(), &mut warnings);
+
+    let mut project = project::Project {
+        cwd,
+        search_roots,
+        delphi_roots: Vec::new(),
+        ignore: ignore_matcher,
+        scan,
+        unit_cache,
+        delphi_cache: None,
+        assumptions: dependency_assumptions,
+    };
+    let started = Instant::now();
+    let mut report = match project.who_uses(&unit_name, args.transitive) {
+        Ok(report) => report,
+        Err(err) => exit_with(err),
+    };
+    timings.record("dpr analysis", started);
+    timings.record_canonical_paths(&project.unit_cache);
+    warnings.extend(std::mem::take(&mut report.warnings));
+
+    let display = report_display(&args.common, &project.search_roots);
+    if text {
+        print_who_uses_report(
+            &report,
+            &unit_name,
+            args.transitive,
+            project.scan.pas_files.len(),
+            &warnings,
+            &display,
+        );
+        if args.common.timings {
+            timings.print(&display);
+        }
+    } else {
+        println!(
+            "{}",
+            who_uses_json(
+                &report,
+                &unit_name,
+                args.transitive,
+                &warnings,
+                &display,
+                &timings,
+            )
+        );
+    }
+
+    if report.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// Lists the files that pull in `include_path` through `{$I}` directives.
+fn run_who_uses_include(
+    include_path: &Path,
+    scan: &fs_walk::FsScan,
+    warnings: &Warnings,
+    display: &ReportDisplay<'_>,
+    text: bool,
+    timings: &mut Timings,
+) {
+    let started = Instant::now();
+    let users = project::include_users(scan, include_path);
+    timings.record("include analysis", started);
+
+    let name = display.paths.path(include_path);
+    if text {
+        println!();
+        print_warnings(warnings, display);
+        println!();
+        println!("Report:");
+        println!("  pas scanned: {}", scan.pas_files.len());
+        println!("  dpr scanned: {}", scan.dpr_files.len());
+        println!("  inc scanned: {}", scan.inc_files.len());
+        println!();
+        print_path_list(&format!("Dprs including {name}"), &users.dprs, display);
+        print_path_list(&format!("Units including {name}"), &users.units, display);
+        print_path_list(
+            &format!("Include files including {name}"),
+            &users.includes,
+            display,
+        );
+        if timings.detailed {
+            timings.print(display);
+        }
+    } else {
+        let paths =
+            |paths: &[PathBuf]| json_array(paths.iter().map(|path| display.paths.path(path)));
+        println!(
+            "{{\"include\":{},\"dprs\":{},\"units\":{},\"includes\":{},\"warnings\":{}{}}}",
+            json_string(&name),
+            paths(&users.dprs),
+            paths(&users.units),
+            paths(&users.includes),
+            json_warnings(warnings, display),
+            json_timings_field(timings, display)
+        );
+    }
+
+    if users.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn run_dependents(args: DependentsArgs) {
+    let mut timings = Timings::start(args.common.timings);
+    let cwd = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
+    };
+    let cwd = fs_walk::canonicalize_root(&cwd);
+    let text = args.format == OutputFormat::Text;
+
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
+    let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    let unit_name = match project::resolve_unit_name(
+        &args.unit,
+        &cwd,
+        source_options(&args.common),
+        &mut warnings,
+    ) {
+        Ok(name) => name,
+        Err(err) => exit_with(err),
+    };
+
+    if text {
+        println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
+        println!("Mode: dependents");
+        println!("Unit: {unit_name}");
+        println!("Scanning {} root(s):", search_roots.len());
+        for root in &search_roots {
+            println!("  {}", plain_path(root));
+        }
+        print_unmatched_search_paths(&unmatched_search_paths);
+        let ignore_display = format_values(&args.common.ignore_path);
+        if !ignore_display.is_empty() {
+            println!("Ignoring: {}", ignore_display);
+        }
+        let assume_display = format_assumptions(&args.dependency_lookup.assume);
+        if !assume_display.is_empty() {
+            println!("Assumptions: {}", assume_display);
+        }
+        if args.dependency_lookup.uses_sections != UsesSections::Both {
+            println!(
+                "Uses sections: {}",
+                uses_sections_label(args.dependency_lookup.uses_sections)
+            );
+        }
+        print_known_units(args.dependency_lookup.known_units.as_deref(), &known_units);
+    }
+
+    let (scan, (mut unit_cache, cache_file)) = if text {
+        let started = Instant::now();
+        let scan = scan_search_roots(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &ignore_matcher,
+            &ConsoleEvents,
+            &mut warnings,
+        );
+        timings.record("scan", started);
+        println!("Building unit cache...");
+        let started = Instant::now();
+        let cache = build_project_unit_cache(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &scan.pas_files,
+            &scan.inc_files,
+            &scan.prefetched,
+            known_units,
+            &mut warnings,
+        );
+        timings.record("project cache", started);
+        println!("Unit cache ready ({} units)", scan.pas_files.len());
+        (scan, cache)
+    } else {
+        let started = Instant::now();
+        let scan = scan_search_roots_quiet(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &ignore_matcher,
+            &NoEvents,
+            &mut warnings,
+        )
+        .0;
+        timings.record("scan", started);
+        let started = Instant::now();
+        let cache = load_project_unit_cache(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &scan.pas_files,
+            &scan.inc_files,
+            &scan.prefetched,
+            known_units,
+            &NoEvents,
+            &mut warnings,
+        );
+        timings.record("project cache", started);
+        (scan, cache)
+    };
+    save_project_cache_file(&args.common, &cwd, true, cache_file.as_ref(), &mut warnings);
+
+    let started = Instant::now();
+    let mut report = match dpr_edit::unit_dependents(
+        &scan.dpr_files,
+        &mut unit_cache,
+        None,
+        &unit_name,
+        &dependency_assumptions,
+        args.direct_only,
+    ) {
+        Ok(report) => report,
+        Err(err) => exit_with(err.into()),
+    };
+    timings.record("unit graph", started);
+    timings.record_canonical_paths(&unit_cache);
+    warnings.extend(std::mem::take(&mut report.warnings));
+
+    let display = report_display(&args.common, &search_roots);
+    if text {
+        print_dependents_report(
+            &report,
+            &unit_name,
+            &args,
+            (scan.pas_files.len(), scan.dpr_files.len()),
+            &warnings,
+            &display,
+        );
+        if args.common.timings {
+            timings.print(&display);
+        }
+    } else {
+        println!(
+            "{}",
+            dependents_json(&report, &unit_name, &warnings, &display, &timings,)
+        );
+    }
+
+    if report.units.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn run_fanout(args: FanoutArgs) {
+    let mut timings = Timings::start(args.common.timings);
+    let cwd = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
+    };
+    let cwd = fs_walk::canonicalize_root(&cwd);
+    let text = args.format == OutputFormat::Text;
+
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
+    let mut delphi_roots =
+        match fs_walk::resolve_optional_roots(&args.delphi.delphi_path, &cwd, "--delphi-path") {
+            Ok(roots) => roots,
+            Err(err) => exit_with(err),
+        };
+    let delphi::SourceRootsResolution {
+        roots: mut delphi_roots_from_version,
+        discovered_versions: discovered_delphi_versions,
+        library_roots: delphi_library_roots,
+        missing_source_dirs: missing_delphi_source_dirs,
+    } = match delphi::resolve_source_roots(
+        &args.delphi.delphi_version,
+        &delphi_source_options(&args.delphi, &cwd),
+    ) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
+    };
+    delphi_roots.append(&mut delphi_roots_from_version);
+    delphi_roots = dedupe_paths(delphi_roots);
+    let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
+    let delphi_ignore_matcher =
+        match fs_walk::build_delphi_ignore_matcher(&args.delphi.delphi_ignore_path, &cwd) {
+            Ok(matcher) => matcher,
+            Err(err) => exit_with(err),
+        };
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
     };
+
+    if text {
+        println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
+        println!("Mode: fanout");
+        println!("Scanning {} root(s):", search_roots.len());
+        for root in &search_roots {
+            println!("  {}", plain_path(root));
+        }
+        print_unmatched_search_paths(&unmatched_search_paths);
+        if !delphi_roots.is_empty() {
+            println!("Delphi fallback roots ({}):", delphi_roots.len());
+            for root in &delphi_roots {
+                println!("  {}", plain_path(root));
+            }
+        }
+        let delphi_version_display = format_values(&args.delphi.delphi_version);
+        if !delphi_version_display.is_empty() {
+            println!("Delphi version lookup: {}", delphi_version_display);
+        }
+        print_discovered_delphi_versions(&discovered_delphi_versions);
+        print_delphi_library_roots(&delphi_library_roots);
+        print_delphi_ignores(&args.delphi.delphi_ignore_path);
+        let ignore_display = format_values(&args.common.ignore_path);
+        if !ignore_display.is_empty() {
+            println!("Ignoring: {}", ignore_display);
+        }
+        let assume_display = format_assumptions(&args.dependency_lookup.assume);
+        if !assume_display.is_empty() {
+            println!("Assumptions: {}", assume_display);
+        }
+        if args.dependency_lookup.uses_sections != UsesSections::Both {
+            println!(
+                "Uses sections: {}",
+                uses_sections_label(args.dependency_lookup.uses_sections)
+            );
+        }
+        print_known_units(args.dependency_lookup.known_units.as_deref(), &known_units);
+    }
+    delphi_roots = dedupe_paths([delphi_roots, delphi_library_roots].concat());
+
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    push_missing_delphi_source_dir_warnings(&mut warnings, &missing_delphi_source_dirs);
+    let (scan, (unit_cache, cache_file), delphi_unit_cache) = if text {
+        let started = Instant::now();
+        let scan = scan_search_roots(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &ignore_matcher,
+            &ConsoleEvents,
+            &mut warnings,
+        );
+        timings.record("scan", started);
+        println!("Building unit cache...");
+        let started = Instant::now();
+        let cache = build_project_unit_cache(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &scan.pas_files,
+            &scan.inc_files,
+            &scan.prefetched,
+            known_units,
+            &mut warnings,
+        );
+        timings.record("project cache", started);
+        println!("Unit cache ready ({} units)", scan.pas_files.len());
+        let delphi_unit_cache = build_delphi_unit_cache(
+            &delphi_roots,
+            args.delphi.delphi_max_depth,
+            delphi_ignore_matcher,
+            source_options(&args.common),
+        );
+        (scan, cache, delphi_unit_cache)
+    } else {
+        let started = Instant::now();
+        let scan = scan_search_roots_quiet(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &ignore_matcher,
+            &NoEvents,
+            &mut warnings,
+        )
+        .0;
+        timings.record("scan", started);
+        let started = Instant::now();
+        let cache = load_project_unit_cache(
+            &args.common,
+            &search_roots,
+            &cwd,
+            &scan.pas_files,
+            &scan.inc_files,
+            &scan.prefetched,
+            known_units,
+            &NoEvents,
+            &mut warnings,
+        );
+        timings.record("project cache", started);
+        let delphi_unit_cache = project::lazy_delphi_unit_cache(
+            &delphi_roots,
+            args.delphi.delphi_max_depth,
+            delphi_ignore_matcher,
+            source_options(&args.common),
+        );
+        (scan, cache, delphi_unit_cache)
+    };
+    save_project_cache_file(&args.common, &cwd, true, cache_file.as_ref(), &mut warnings);
+
+    let started = Instant::now();
+    let mut report = match dpr_edit::dpr_fanout(
+        &scan.dpr_files,
+        &unit_cache,
+        delphi_unit_cache.as_ref(),
+        &dependency_assumptions,
+    ) {
+        Ok(report) => report,
+        Err(err) => exit_with(err.into()),
+    };
+    timings.record("dpr analysis", started);
+    timings.record_delphi_cache(delphi_unit_cache.as_ref());
+    timings.record_canonical_paths(&unit_cache);
+    warnings.extend(std::mem::take(&mut report.warnings));
+
+    let display = report_display(&args.common, &search_roots);
+    if text {
+        report_delphi_unit_cache(delphi_unit_cache.as_ref(), &mut warnings);
+        print_fanout_report(&report, scan.pas_files.len(), &warnings, &display);
+        if args.common.timings {
+            timings.print(&display);
+        }
+    } else {
+        if let Some(cache) = &delphi_unit_cache {
+            warnings.extend(cache.take_warnings());
+        }
+        println!("{}", fanout_json(&report, &warnings, &display, &timings,));
+    }
+}
+
+fn run_impact(args: ImpactArgs) {
+    let mut timings = Timings::start(args.common.timings);
+    let cwd = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
+    };
+    let cwd = fs_walk::canonicalize_root(&cwd);
+
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
+    let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let mut changed = args.units;
+    if let Some(reference) = &args.changed_since {
+        match git_changed_pas_files(reference, &cwd) {
+            Ok(paths) => changed.extend(paths.iter().map(|path| plain_path(path))),
+            Err(err) => exit_with(err),
+        }
+    }
+
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    let started = Instant::now();
+    let (scan, _) = scan_search_roots_quiet(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &ignore_matcher,
+        &NoEvents,
+        &mut warnings,
+    );
+    timings.record("scan", started);
+    let started = Instant::now();
+    let (mut unit_cache, cache_file) = load_project_unit_cache(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &scan.pas_files,
+        &scan.inc_files,
+        &scan.prefetched,
+        known_units,
+        &NoEvents,
+        &mut warnings,
+    );
+    timings.record("project cache", started);
+    save_project_cache_file(&args.common, &cwd, true, cache_file.as_ref(), &mut warnings);
+
+    let unit_names = changed_unit_names(&changed, &unit_cache, &cwd, &mut warnings);
+    let started = Instant::now();
+    let mut report = match dpr_edit::impacted_dprs(
+        &scan.dpr_files,
+        &mut unit_cache,
+        None,
+        &unit_names,
+        &dependency_assumptions,
+    ) {
+        Ok(report) => report,
+        Err(err) => exit_with(err.into()),
+    };
+    timings.record("dpr analysis", started);
+    timings.record_canonical_paths(&unit_cache);
+    warnings.extend(std::mem::take(&mut report.warnings));
+
+    let display = report_display(&args.common, &search_roots);
+    match args.format {
+        OutputFormat::Text => {
+            for path in &report.affected {
+                println!("{}", display.paths.path(path));
+            }
+            eprintln!(
+                "Affected dprs: {} of {} ({} changed unit(s))",
+                report.affected.len(),
+                report.scanned,
+                unit_names.len()
+            );
+            for line in warning_lines(&warnings, &display) {
+                eprintln!("{line}");
+            }
+            if args.common.timings {
+                for line in timings.lines(&display) {
+                    eprintln!("{line}");
+                }
+            }
+        }
+        OutputFormat::Json => println!(
+            "{{\"units\":{},\"dprs\":{},\"scanned\":{},\"warnings\":{}{}}}",
+            json_array(unit_names.iter().cloned()),
+            json_array(report.affected.iter().map(|path| display.paths.path(path))),
+            report.scanned,
+            json_warnings(&warnings, &display),
+            json_timings_field(&timings, &display)
+        ),
+    }
+}
+
+fn run_cache(args: CacheArgs) {
+    let (common, mode) = match &args.action {
+        CacheAction::Build(action) => (&action.common, "cache build"),
+        CacheAction::Stats(action) => (&action.common, "cache stats"),
+        CacheAction::Verify(action) => (&action.common, "cache verify"),
+    };
+    let mut timings = Timings::start(common.timings);
+    if common.no_cache {
+        exit_with(FixdprError::invalid_argument(
+            "--no-cache cannot be used with the cache subcommand",
+        ));
+    }
+
+    let cwd = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
+    };
+    let cwd = fs_walk::canonicalize_root(&cwd);
+
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(common, &cwd);
+    let ignore_matcher = match fs_walk::build_ignore_matcher(&common.ignore_path, &cwd) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
+    let cache_path = project_options(common, &cwd).cache_path(&search_roots, &cwd);
+
+    println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
+    println!("Mode: {mode}");
+    println!("Cache file: {}", plain_path(&cache_path));
+
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    let mut cache_file = cache_file::CacheFile::load(&cache_path, &mut warnings);
+    let mut drift = false;
+    match &args.action {
+        CacheAction::Build(_) => {
+            println!("Scanning {} root(s):", search_roots.len());
+            for root in &search_roots {
+                println!("  {}", plain_path(root));
+            }
+            print_unmatched_search_paths(&unmatched_search_paths);
+            let ignore_display = format_values(&common.ignore_path);
+            if !ignore_display.is_empty() {
+                println!("Ignoring: {}", ignore_display);
+            }
+            let started = Instant::now();
+            let scan = scan_search_roots(
+                common,
+                &search_roots,
+                &cwd,
+                &ignore_matcher,
+                &NoEvents,
+                &mut warnings,
+            );
+            timings.record("scan", started);
+            println!("Found {} .pas", scan.pas_files.len());
+            let started = Instant::now();
+            let unit_cache = match cache_file.build_unit_cache_with_events(
+                &scan.pas_files,
+                source_options(common),
+                uses_include::Includes::from_paths(&scan.inc_files),
+                &scan.prefetched,
+                &mut warnings,
+                &NoEvents,
+            ) {
+                Ok(unit_cache) => unit_cache,
+                Err(err) => exit_with(err.into()),
+            };
+            timings.record("project cache", started);
+            timings.record_canonical_paths(&unit_cache);
+            if let Err(err) = cache_file.save() {
+                exit_with(FixdprError::Failed(format!(
+                    "failed to write cache file {}: {err}",
+                    cache_file.path().display()
+                )));
+            }
+            println!(
+                "Cache ready ({} entries, {} reused, {} parsed)",
+                cache_file.entry_count(),
+                cache_file.hits,
+                cache_file.misses
+            );
+        }
+        CacheAction::Stats(_) => {
+            let size = std::fs::metadata(&cache_path)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+            println!("Report:");
+            println!("  entries: {}", cache_file.entry_count());
+            println!("  size: {} bytes", size);
+            println!("  last run reused: {}", cache_file.last_hits);
+            println!("  last run parsed: {}", cache_file.last_misses);
+        }
+        CacheAction::Verify(action) => {
+            let started = Instant::now();
+            let report = cache_file.verify(action.sample, source_options(common));
+            timings.record("verify", started);
+            println!("Report:");
+            println!("  entries checked: {}", report.checked);
+            println!("  entries stale: {}", report.stale.len());
+            println!("  entries drifted: {}", report.drifted.len());
+            for path in &report.stale {
+                warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::CacheInvalid,
+                        format!(
+                            "cache entry is stale (file changed or missing): {}",
+                            path.display()
+                        ),
+                    )
+                    .at(path),
+                );
+            }
+            for path in &report.drifted {
+                warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::CacheInvalid,
+                        format!(
+                            "cache entry does not match a fresh parse: {}",
+                            path.display()
+                        ),
+                    )
+                    .at(path),
+                );
+            }
+            drift = !report.drifted.is_empty();
+        }
+    }
+
+    let display = report_display(common, &search_roots);
+    print_warnings(&warnings, &display);
+    if common.timings {
+        timings.print(&display);
+    }
+
+    if drift {
+        process::exit(1);
+    }
+}
+
+fn run_insert_dependency(args: InsertDependencyArgs) {
+    let mut timings = Timings::start(args.common.timings);
+    let cwd = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
+    };
+    let cwd = fs_walk::canonicalize_root(&cwd);
+    let updated_list = resolve_updated_list(&args.dpr_write, &cwd);
+    let emit_patch = resolve_emit_patch(&args.dpr_write, &cwd);
+
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
     let target_paths =
         match fs_walk::resolve_optional_roots(&args.targets.target_path, &cwd, "--target-path") {
             Ok(paths) => paths,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
     if let Err(err) = ensure_paths_under_search_roots(&target_paths, &search_roots, "--target-path")
     {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
 
     let target_dprs = match resolve_target_dpr_paths(&args.targets.target_dpr, &cwd) {
         Ok(paths) => paths,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     if let Err(err) = ensure_paths_under_search_roots(&target_dprs, &search_roots, "--target-dpr") {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
 
     let mut delphi_roots =
-        match fs_walk::resolve_optional_roots(&args.delphi_path, &cwd, "--delphi-path") {
+        match fs_walk::resolve_optional_roots(&args.delphi.delphi_path, &cwd, "--delphi-path") {
             Ok(roots) => roots,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
-    let mut delphi_roots_from_version = match delphi::resolve_source_roots(&args.delphi_version) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
+    let delphi::SourceRootsResolution {
+        roots: mut delphi_roots_from_version,
+        discovered_versions: discovered_delphi_versions,
+        library_roots: delphi_library_roots,
+        missing_source_dirs: missing_delphi_source_dirs,
+    } = match delphi::resolve_source_roots(
+        &args.delphi.delphi_version,
+        &delphi_source_options(&args.delphi, &cwd),
+    ) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
     };
     delphi_roots.append(&mut delphi_roots_from_version);
     delphi_roots = dedupe_paths(delphi_roots);
 
-    let mut warnings = Vec::new();
-    let dependency_assumptions = match build_dependency_assumptions(&args.dependency_lookup.assume)
-    {
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    push_missing_delphi_source_dir_warnings(&mut warnings, &missing_delphi_source_dirs);
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
         Ok(value) => value,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     let new_dependency_path = match resolve_new_dependency_path(&args.new_dependency, &cwd) {
         Ok(path) => path,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     if let Err(err) = validate_new_dependency_path(&new_dependency_path) {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
 
     let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
         Ok(matcher) => matcher,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
-    let ignore_dpr_matcher =
-        match fs_walk::build_dpr_ignore_matcher(&args.dpr_filter.ignore_dpr, &cwd) {
+    let delphi_ignore_matcher =
+        match fs_walk::build_delphi_ignore_matcher(&args.delphi.delphi_ignore_path, &cwd) {
             Ok(matcher) => matcher,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
+    let ignore_dpr_matcher = match args.dpr_filter.build_matcher(&cwd, &search_roots) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
 
     println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
     println!("Mode: insert-dependency");
     println!("Scanning {} root(s):", search_roots.len());
     for root in &search_roots {
-        println!("  {}", root.display());
+        println!("  {}", plain_path(root));
     }
+    print_unmatched_search_paths(&unmatched_search_paths);
     if !target_paths.is_empty() {
         println!("Target paths ({}):", target_paths.len());
         for path in &target_paths {
-            println!("  {}", path.display());
+            println!("  {}", plain_path(path));
         }
     }
     if !target_dprs.is_empty() {
         println!("Target dpr files ({}):", target_dprs.len());
         for path in &target_dprs {
-            println!("  {}", path.display());
+            println!("  {}", plain_path(path));
         }
     }
     if !delphi_roots.is_empty() {
         println!("Delphi fallback roots ({}):", delphi_roots.len());
         for root in &delphi_roots {
-            println!("  {}", root.display());
+            println!("  {}", plain_path(root));
         }
     }
-    let delphi_version_display = format_values(&args.delphi_version);
+    let delphi_version_display = format_values(&args.delphi.delphi_version);
     if !delphi_version_display.is_empty() {
         println!("Delphi version lookup: {}", delphi_version_display);
     }
+    print_discovered_delphi_versions(&discovered_delphi_versions);
+    print_delphi_library_roots(&delphi_library_roots);
+    print_delphi_ignores(&args.delphi.delphi_ignore_path);
+    delphi_roots = dedupe_paths([delphi_roots, delphi_library_roots].concat());
     let ignore_display = format_values(&args.common.ignore_path);
     if !ignore_display.is_empty() {
         println!("Ignoring: {}", ignore_display);
@@ -873,15 +3274,32 @@ fn run_insert_dependency(args: InsertDependencyArgs) {
     if !assume_display.is_empty() {
         println!("Assumptions: {}", assume_display);
     }
+    if args.dependency_lookup.uses_sections != UsesSections::Both {
+        println!(
+            "Uses sections: {}",
+            uses_sections_label(args.dependency_lookup.uses_sections)
+        );
+    }
+    print_known_units(args.dependency_lookup.known_units.as_deref(), &known_units);
     let ignore_dpr_display = format_values(ignore_dpr_matcher.normalized_patterns());
     if !ignore_dpr_display.is_empty() {
         println!("Ignoring dpr (absolute): {}", ignore_dpr_display);
     }
+    let ignore_program_display = format_values(ignore_dpr_matcher.normalized_program_patterns());
+    if !ignore_program_display.is_empty() {
+        println!("Ignoring programs: {}", ignore_program_display);
+    }
 
-    let scan = match fs_walk::scan_files(&search_roots, &ignore_matcher) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
+    let started = Instant::now();
+    let scan = scan_search_roots(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &ignore_matcher,
+        &ConsoleEvents,
+        &mut warnings,
+    );
+    timings.record("scan", started);
     let (target_dpr_files, ignored_target_dprs) = match select_target_dpr_files(
         &scan.dpr_files,
         &target_paths,
@@ -889,61 +3307,69 @@ fn run_insert_dependency(args: InsertDependencyArgs) {
         &ignore_dpr_matcher,
     ) {
         Ok(value) => value,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     let mut infos = Vec::new();
     for path in &ignored_target_dprs {
         infos.push(format!("info: ignored dpr {}", path.display()));
     }
+    let dependency_root = match project::scan_dependency_root(
+        &project_options(&args.common, &cwd),
+        &[search_roots.as_slice(), delphi_roots.as_slice()].concat(),
+        &new_dependency_path,
+        args.dependency_root_depth,
+        &ignore_matcher,
+        &mut warnings,
+    ) {
+        Ok(dependency_root) => dependency_root,
+        Err(err) => exit_with(err),
+    };
+    let pas_files = merge_dependency_root(&scan.pas_files, dependency_root, &mut infos);
 
-    println!(
-        "Found {} .pas, {} .dpr",
-        scan.pas_files.len(),
-        scan.dpr_files.len()
-    );
     println!("Updating selected .dpr files... {}", target_dpr_files.len());
     println!("Building unit cache...");
-    let mut unit_cache = match unit_cache::build_unit_cache(&scan.pas_files, &mut warnings) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
-    println!("Unit cache ready ({} units)", scan.pas_files.len());
+    let started = Instant::now();
+    let (mut unit_cache, cache_file) = build_project_unit_cache(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &pas_files,
+        &scan.inc_files,
+        &scan.prefetched,
+        known_units,
+        &mut warnings,
+    );
+    timings.record("project cache", started);
+    save_project_cache_file(
+        &args.common,
+        &cwd,
+        emit_patch.is_some(),
+        cache_file.as_ref(),
+        &mut warnings,
+    );
+    println!("Unit cache ready ({} units)", pas_files.len());
 
-    let mut delphi_unit_cache = if delphi_roots.is_empty() {
-        None
-    } else {
-        println!("Scanning Delphi fallback roots...");
-        let delphi_scan =
-            match fs_walk::scan_files(&delphi_roots, &fs_walk::IgnoreMatcher::default()) {
-                Ok(result) => result,
-                Err(err) => exit_with_error(err.to_string(), 1),
-            };
-        println!("Found {} fallback .pas", delphi_scan.pas_files.len());
-        println!("Building Delphi fallback unit cache...");
-        let cache = match unit_cache::build_unit_cache(&delphi_scan.pas_files, &mut warnings) {
-            Ok(result) => result,
-            Err(err) => exit_with_error(err.to_string(), 1),
-        };
-        println!(
-            "Delphi fallback unit cache ready ({} units)",
-            cache.by_path.len()
-        );
-        Some(cache)
-    };
+    let delphi_unit_cache = build_delphi_unit_cache(
+        &delphi_roots,
+        args.delphi.delphi_max_depth,
+        delphi_ignore_matcher,
+        source_options(&args.common),
+    );
 
     let new_dependency_path = unit_cache::canonicalize_if_exists(&new_dependency_path);
-    let new_unit = match unit_cache::load_unit_file(&new_dependency_path, &mut warnings) {
+    let new_unit = match unit_cache::load_unit_file_with(
+        &new_dependency_path,
+        source_options(&args.common),
+        &mut warnings,
+    ) {
         Ok(Some(unit)) => unit,
         Ok(None) => {
-            exit_with_error(
-                format!(
-                    "unable to determine unit name from new dependency: {}",
-                    new_dependency_path.display()
-                ),
-                1,
-            );
+            exit_with(FixdprError::Failed(format!(
+                "unable to determine unit name from new dependency: {}",
+                new_dependency_path.display()
+            )));
         }
-        Err(err) => exit_with_error(err.to_string(), 1),
+        Err(err) => exit_with(err.into()),
     };
     println!(
         "New dependency: {} ({})",
@@ -951,28 +3377,71 @@ fn run_insert_dependency(args: InsertDependencyArgs) {
         new_unit.path.display()
     );
 
-    let dpr_summary = match dpr_edit::insert_dependency_files(
+    let insert_options = dpr_edit::UpdateOptions::builder()
+        .edit(
+            edit_options(&args.relative_paths, &args.layout)
+                .max_file_size(args.common.max_file_size)
+                .namespace_prefixes(namespace_prefixes(&args.namespace_prefix))
+                .lock_timeout(args.dpr_write.lock_timeout())
+                .dry_run(emit_patch.is_some()),
+        )
+        .introduced_scope(introduced_scope(
+            args.introduced_scope,
+            args.disable_introduced_dependencies,
+        ))
+        .topo_order(args.topo_order)
+        .build();
+    let started = Instant::now();
+    let mut dpr_summary = match dpr_edit::insert_dependency_files_with(
         &target_dpr_files,
         &mut unit_cache,
-        delphi_unit_cache.as_mut(),
+        delphi_unit_cache.as_ref(),
         &new_unit,
-        !args.disable_introduced_dependencies,
         &dependency_assumptions,
+        &insert_options,
     ) {
         Ok(summary) => summary,
-        Err(err) => exit_with_error(err.to_string(), 1),
+        Err(err) => exit_with(err.into()),
     };
+    timings.record("dpr processing", started);
     warnings.extend(dpr_summary.warnings.iter().cloned());
+    infos.extend(dpr_summary.infos.iter().map(|info| format!("info: {info}")));
+    for path in &ignored_target_dprs {
+        dpr_summary.merge_detail(dpr_edit::DprDetail::new(path, dpr_edit::DprStatus::Ignored));
+    }
 
+    timings.record_dprs(&dpr_summary);
+    timings.record_delphi_cache(delphi_unit_cache.as_ref());
+    timings.record_canonical_paths(&unit_cache);
+    run_update_hooks(&args.dpr_write, &mut dpr_summary, &mut warnings);
+    write_updated_list(
+        updated_list.as_deref(),
+        args.dpr_write.forward_slashes,
+        &dpr_summary.updated_paths,
+        &mut warnings,
+    );
+    write_patch(
+        emit_patch.as_deref(),
+        &cwd,
+        &dpr_summary.updated_paths,
+        &dpr_summary.staged,
+        &mut warnings,
+    );
+    report_delphi_unit_cache(delphi_unit_cache.as_ref(), &mut warnings);
     print_summary(SummaryOutput {
         infos: &infos,
         warnings: &warnings,
         show_infos: args.common.show_infos,
-        show_warnings: args.common.show_warnings,
-        pas_scanned: scan.pas_files.len(),
+        display: report_display(&args.common, &search_roots),
+        pas_scanned: pas_files.len(),
         dpr_summary: &dpr_summary,
+        staged_only: emit_patch.is_some(),
         ignored_dpr: ignored_target_dprs.len(),
-        search_roots: &search_roots,
+        filtered_dpr: 0,
+        cached_unchanged_dpr: 0,
+        roots: &scan.roots,
+        report_unresolved: false,
+        timings: &timings,
     });
 
     if dpr_summary.failures > 0 {
@@ -981,86 +3450,113 @@ fn run_insert_dependency(args: InsertDependencyArgs) {
 }
 
 fn run_delete_dependency(args: DeleteDependencyArgs) {
+    let mut timings = Timings::start(args.common.timings);
     let cwd = match env::current_dir() {
         Ok(path) => path,
-        Err(err) => exit_with_error(format!("failed to read current directory: {err}"), 2),
+        Err(err) => exit_with(FixdprError::invalid_argument(format!(
+            "failed to read current directory: {err}"
+        ))),
     };
     let cwd = fs_walk::canonicalize_root(&cwd);
+    let updated_list = resolve_updated_list(&args.dpr_write, &cwd);
+    let emit_patch = resolve_emit_patch(&args.dpr_write, &cwd);
 
-    let search_roots = match fs_walk::resolve_search_roots(&args.common.search_path, &cwd) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
-    };
+    let fs_walk::SearchRootsResolution {
+        roots: search_roots,
+        unmatched_patterns: unmatched_search_paths,
+    } = resolve_search_roots(&args.common, &cwd);
     let target_paths =
         match fs_walk::resolve_optional_roots(&args.targets.target_path, &cwd, "--target-path") {
             Ok(paths) => paths,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
     if let Err(err) = ensure_paths_under_search_roots(&target_paths, &search_roots, "--target-path")
     {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
 
     let target_dprs = match resolve_target_dpr_paths(&args.targets.target_dpr, &cwd) {
         Ok(paths) => paths,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     if let Err(err) = ensure_paths_under_search_roots(&target_dprs, &search_roots, "--target-dpr") {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
 
     let mut delphi_roots =
-        match fs_walk::resolve_optional_roots(&args.delphi_path, &cwd, "--delphi-path") {
+        match fs_walk::resolve_optional_roots(&args.delphi.delphi_path, &cwd, "--delphi-path") {
             Ok(roots) => roots,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
-    let mut delphi_roots_from_version = match delphi::resolve_source_roots(&args.delphi_version) {
-        Ok(roots) => roots,
-        Err(err) => exit_with_error(err, 2),
+    let delphi::SourceRootsResolution {
+        roots: mut delphi_roots_from_version,
+        discovered_versions: discovered_delphi_versions,
+        library_roots: delphi_library_roots,
+        missing_source_dirs: missing_delphi_source_dirs,
+    } = match delphi::resolve_source_roots(
+        &args.delphi.delphi_version,
+        &delphi_source_options(&args.delphi, &cwd),
+    ) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
     };
     delphi_roots.append(&mut delphi_roots_from_version);
     delphi_roots = dedupe_paths(delphi_roots);
 
-    let dependency_assumptions = match build_dependency_assumptions(&args.dependency_lookup.assume)
-    {
+    let dependency_assumptions = match build_lookup_assumptions(&args.dependency_lookup) {
+        Ok(value) => value,
+        Err(err) => exit_with(err),
+    };
+    let known_units = match load_known_units(args.dependency_lookup.known_units.as_deref(), &cwd) {
         Ok(value) => value,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     let old_dependency_path = match resolve_new_dependency_path(&args.old_dependency, &cwd) {
         Ok(path) => path,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     if let Err(err) = validate_new_dependency_path(&old_dependency_path) {
-        exit_with_error(err, 2);
+        exit_with(err);
     }
 
     let ignore_matcher = match fs_walk::build_ignore_matcher(&args.common.ignore_path, &cwd) {
         Ok(matcher) => matcher,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
-    let ignore_dpr_matcher =
-        match fs_walk::build_dpr_ignore_matcher(&args.dpr_filter.ignore_dpr, &cwd) {
+    let delphi_ignore_matcher =
+        match fs_walk::build_delphi_ignore_matcher(&args.delphi.delphi_ignore_path, &cwd) {
             Ok(matcher) => matcher,
-            Err(err) => exit_with_error(err, 2),
+            Err(err) => exit_with(err),
         };
+    let ignore_dpr_matcher = match args.dpr_filter.build_matcher(&cwd, &search_roots) {
+        Ok(matcher) => matcher,
+        Err(err) => exit_with(err),
+    };
 
-    let mut warnings = Vec::new();
+    let mut warnings = Warnings::new();
+    push_unmatched_search_path_warnings(&mut warnings, &unmatched_search_paths);
+    push_missing_delphi_source_dir_warnings(&mut warnings, &missing_delphi_source_dirs);
     println!("fixdpr {}", env!("CARGO_PKG_VERSION"));
     println!("Mode: delete-dependency");
     println!("Scanning {} root(s):", search_roots.len());
     for root in &search_roots {
-        println!("  {}", root.display());
+        println!("  {}", plain_path(root));
     }
+    print_unmatched_search_paths(&unmatched_search_paths);
     if !delphi_roots.is_empty() {
         println!("Delphi fallback roots ({}):", delphi_roots.len());
         for root in &delphi_roots {
-            println!("  {}", root.display());
+            println!("  {}", plain_path(root));
         }
     }
-    let delphi_version_display = format_values(&args.delphi_version);
+    let delphi_version_display = format_values(&args.delphi.delphi_version);
     if !delphi_version_display.is_empty() {
         println!("Delphi version lookup: {}", delphi_version_display);
     }
+    print_discovered_delphi_versions(&discovered_delphi_versions);
+    print_delphi_library_roots(&delphi_library_roots);
+    print_delphi_ignores(&args.delphi.delphi_ignore_path);
+    delphi_roots = dedupe_paths([delphi_roots, delphi_library_roots].concat());
     let ignore_display = format_values(&args.common.ignore_path);
     if !ignore_display.is_empty() {
         println!("Ignoring: {}", ignore_display);
@@ -1069,15 +3565,32 @@ fn run_delete_dependency(args: DeleteDependencyArgs) {
     if !assume_display.is_empty() {
         println!("Assumptions: {}", assume_display);
     }
+    if args.dependency_lookup.uses_sections != UsesSections::Both {
+        println!(
+            "Uses sections: {}",
+            uses_sections_label(args.dependency_lookup.uses_sections)
+        );
+    }
+    print_known_units(args.dependency_lookup.known_units.as_deref(), &known_units);
     let ignore_dpr_display = format_values(ignore_dpr_matcher.normalized_patterns());
     if !ignore_dpr_display.is_empty() {
         println!("Ignoring dpr (absolute): {}", ignore_dpr_display);
     }
+    let ignore_program_display = format_values(ignore_dpr_matcher.normalized_program_patterns());
+    if !ignore_program_display.is_empty() {
+        println!("Ignoring programs: {}", ignore_program_display);
+    }
 
-    let scan = match fs_walk::scan_files(&search_roots, &ignore_matcher) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
+    let started = Instant::now();
+    let scan = scan_search_roots(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &ignore_matcher,
+        &ConsoleEvents,
+        &mut warnings,
+    );
+    timings.record("scan", started);
     let (target_dpr_files, ignored_target_dprs) = match select_target_dpr_files(
         &scan.dpr_files,
         &target_paths,
@@ -1085,61 +3598,57 @@ fn run_delete_dependency(args: DeleteDependencyArgs) {
         &ignore_dpr_matcher,
     ) {
         Ok(value) => value,
-        Err(err) => exit_with_error(err, 2),
+        Err(err) => exit_with(err),
     };
     let mut infos = Vec::new();
     for path in &ignored_target_dprs {
         infos.push(format!("info: ignored dpr {}", path.display()));
     }
 
-    println!(
-        "Found {} .pas, {} .dpr",
-        scan.pas_files.len(),
-        scan.dpr_files.len()
-    );
     println!("Updating selected .dpr files... {}", target_dpr_files.len());
     println!("Building unit cache...");
-    let unit_cache = match unit_cache::build_unit_cache(&scan.pas_files, &mut warnings) {
-        Ok(result) => result,
-        Err(err) => exit_with_error(err.to_string(), 1),
-    };
+    let started = Instant::now();
+    let (unit_cache, cache_file) = build_project_unit_cache(
+        &args.common,
+        &search_roots,
+        &cwd,
+        &scan.pas_files,
+        &scan.inc_files,
+        &scan.prefetched,
+        known_units,
+        &mut warnings,
+    );
+    timings.record("project cache", started);
+    save_project_cache_file(
+        &args.common,
+        &cwd,
+        emit_patch.is_some(),
+        cache_file.as_ref(),
+        &mut warnings,
+    );
     println!("Unit cache ready ({} units)", scan.pas_files.len());
 
-    let delphi_unit_cache = if delphi_roots.is_empty() {
-        None
-    } else {
-        println!("Scanning Delphi fallback roots...");
-        let delphi_scan =
-            match fs_walk::scan_files(&delphi_roots, &fs_walk::IgnoreMatcher::default()) {
-                Ok(result) => result,
-                Err(err) => exit_with_error(err.to_string(), 1),
-            };
-        println!("Found {} fallback .pas", delphi_scan.pas_files.len());
-        println!("Building Delphi fallback unit cache...");
-        let cache = match unit_cache::build_unit_cache(&delphi_scan.pas_files, &mut warnings) {
-            Ok(result) => result,
-            Err(err) => exit_with_error(err.to_string(), 1),
-        };
-        println!(
-            "Delphi fallback unit cache ready ({} units)",
-            cache.by_path.len()
-        );
-        Some(cache)
-    };
+    let delphi_unit_cache = build_delphi_unit_cache(
+        &delphi_roots,
+        args.delphi.delphi_max_depth,
+        delphi_ignore_matcher,
+        source_options(&args.common),
+    );
 
     let old_dependency_path = unit_cache::canonicalize_if_exists(&old_dependency_path);
-    let old_unit = match unit_cache::load_unit_file(&old_dependency_path, &mut warnings) {
+    let old_unit = match unit_cache::load_unit_file_with(
+        &old_dependency_path,
+        source_options(&args.common),
+        &mut warnings,
+    ) {
         Ok(Some(unit)) => unit,
         Ok(None) => {
-            exit_with_error(
-                format!(
-                    "unable to determine unit name from old dependency: {}",
-                    old_dependency_path.display()
-                ),
-                1,
-            );
+            exit_with(FixdprError::Failed(format!(
+                "unable to determine unit name from old dependency: {}",
+                old_dependency_path.display()
+            )));
         }
-        Err(err) => exit_with_error(err.to_string(), 1),
+        Err(err) => exit_with(err.into()),
     };
     println!(
         "Old dependency: {} ({})",
@@ -1147,27 +3656,65 @@ fn run_delete_dependency(args: DeleteDependencyArgs) {
         old_unit.path.display()
     );
 
-    let dpr_summary = match dpr_edit::delete_dependency_files(
+    let started = Instant::now();
+    let delete_options = dpr_edit::UpdateOptions::builder()
+        .edit(
+            dpr_edit::EditOptions::builder()
+                .max_file_size(args.common.max_file_size)
+                .lock_timeout(args.dpr_write.lock_timeout())
+                .dry_run(emit_patch.is_some()),
+        )
+        .build();
+    let mut dpr_summary = match dpr_edit::delete_dependency_files_with(
         &target_dpr_files,
         &unit_cache,
         delphi_unit_cache.as_ref(),
         &old_unit.name,
         &dependency_assumptions,
+        &delete_options,
     ) {
         Ok(summary) => summary,
-        Err(err) => exit_with_error(err.to_string(), 1),
+        Err(err) => exit_with(err.into()),
     };
+    timings.record("dpr processing", started);
     warnings.extend(dpr_summary.warnings.iter().cloned());
+    infos.extend(dpr_summary.infos.iter().map(|info| format!("info: {info}")));
+    for path in &ignored_target_dprs {
+        dpr_summary.merge_detail(dpr_edit::DprDetail::new(path, dpr_edit::DprStatus::Ignored));
+    }
 
+    timings.record_dprs(&dpr_summary);
+    timings.record_delphi_cache(delphi_unit_cache.as_ref());
+    timings.record_canonical_paths(&unit_cache);
+    run_update_hooks(&args.dpr_write, &mut dpr_summary, &mut warnings);
+    write_updated_list(
+        updated_list.as_deref(),
+        args.dpr_write.forward_slashes,
+        &dpr_summary.updated_paths,
+        &mut warnings,
+    );
+    write_patch(
+        emit_patch.as_deref(),
+        &cwd,
+        &dpr_summary.updated_paths,
+        &dpr_summary.staged,
+        &mut warnings,
+    );
+    report_delphi_unit_cache(delphi_unit_cache.as_ref(), &mut warnings);
     print_summary(SummaryOutput {
         infos: &infos,
         warnings: &warnings,
         show_infos: args.common.show_infos,
-        show_warnings: args.common.show_warnings,
+        display: report_display(&args.common, &search_roots),
         pas_scanned: scan.pas_files.len(),
         dpr_summary: &dpr_summary,
+        staged_only: emit_patch.is_some(),
         ignored_dpr: ignored_target_dprs.len(),
-        search_roots: &search_roots,
+        filtered_dpr: 0,
+        cached_unchanged_dpr: 0,
+        roots: &scan.roots,
+        report_unresolved: false,
+        timings: &timings,
     });
 
     if dpr_summary.failures > 0 {
@@ -1177,33 +3724,327 @@ fn run_delete_dependency(args: DeleteDependencyArgs) {
 
 struct SummaryOutput<'a> {
     infos: &'a [String],
-    warnings: &'a [String],
+    warnings: &'a Warnings,
     show_infos: bool,
-    show_warnings: bool,
+    display: ReportDisplay<'a>,
     pas_scanned: usize,
     dpr_summary: &'a dpr_edit::DprUpdateSummary,
+    /// The edits were only staged, for `--emit-patch` or `--dry-run`.
+    staged_only: bool,
     ignored_dpr: usize,
-    search_roots: &'a [PathBuf],
+    filtered_dpr: usize,
+    cached_unchanged_dpr: usize,
+    roots: &'a [fs_walk::RootCounts],
+    /// List [`dpr_edit::DprUpdateSummary::unresolved`], even when it is empty.
+    report_unresolved: bool,
+    timings: &'a Timings,
 }
 
 struct ConditionalsOutput<'a> {
-    warnings: &'a [String],
-    show_warnings: bool,
+    warnings: &'a Warnings,
+    display: ReportDisplay<'a>,
     pas_scanned: usize,
     dpr_scanned: usize,
     buckets: &'a conditionals::ConditionBuckets,
 }
 
+fn resolve_updated_list(args: &DprWriteArgs, cwd: &Path) -> Option<PathBuf> {
+    let value = args.write_updated_list.as_deref()?;
+    match resolve_path_with_flag(value, cwd, "--write-updated-list") {
+        Ok(path) => Some(path),
+        Err(err) => exit_with(err),
+    }
+}
+
+fn resolve_emit_patch(args: &DprWriteArgs, cwd: &Path) -> Option<PathBuf> {
+    let value = args.emit_patch.as_deref()?;
+    match resolve_path_with_flag(value, cwd, "--emit-patch") {
+        Ok(path) => Some(path),
+        Err(err) => exit_with(err),
+    }
+}
+
+fn updated_list_contents(updated_paths: &[PathBuf], forward_slashes: bool) -> String {
+    let mut contents = String::new();
+    for path in updated_paths {
+        let line = plain_path(path);
+        if forward_slashes {
+            contents.push_str(&line.replace('\\', "/"));
+        } else {
+            contents.push_str(&line);
+        }
+        contents.push('\n');
+    }
+    contents
+}
+
+fn write_updated_list(
+    path: Option<&Path>,
+    forward_slashes: bool,
+    updated_paths: &[PathBuf],
+    warnings: &mut Warnings,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    if let Err(err) = fs::write(path, updated_list_contents(updated_paths, forward_slashes)) {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::WriteFailed,
+                format!("failed to write updated list {}: {err}", path.display()),
+            )
+            .at(path),
+        );
+    }
+}
+
+/// Runs the `--on-update` command once per updated dpr, after all edits.
+fn run_update_hooks(
+    args: &DprWriteArgs,
+    dpr_summary: &mut dpr_edit::DprUpdateSummary,
+    warnings: &mut Warnings,
+) {
+    let Some(command) = args.on_update.as_deref() else {
+        return;
+    };
+    if dpr_summary.updated_paths.is_empty() {
+        return;
+    }
+    println!(
+        "Running --on-update on updated dpr files... {}",
+        dpr_summary.updated_paths.len()
+    );
+    for path in dpr_summary.updated_paths.clone() {
+        let Err(message) = run_update_hook(command, &path) else {
+            continue;
+        };
+        warnings.push(Diagnostic::warning(DiagnosticCode::HookFailed, message).at(&path));
+        if args.on_update_strict {
+            dpr_summary.failures += 1;
+            if !contains_path(&dpr_summary.failed_paths, &path) {
+                dpr_summary.failed_paths.push(path);
+            }
+        }
+    }
+    dpr_summary.sort_paths();
+}
+
+fn run_update_hook(command: &str, path: &Path) -> Result<(), String> {
+    let line = hook_command_line(command, &plain_path(path));
+    let output = shell_command(&line)
+        .output()
+        .map_err(|err| format!("failed to run --on-update command `{line}`: {err}"))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let detail = stderr
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(|line| format!(": {line}"))
+        .unwrap_or_default();
+    Err(format!(
+        "--on-update command `{line}` exited with {}{detail}",
+        output.status
+    ))
+}
+
+/// `command` with the quoted `path` in place of every `{}`, or appended when there is none.
+fn hook_command_line(command: &str, path: &str) -> String {
+    let quoted = shell_quote(path);
+    if command.contains("{}") {
+        command.replace("{}", &quoted)
+    } else {
+        format!("{command} {quoted}")
+    }
+}
+
+#[cfg(windows)]
+fn shell_quote(text: &str) -> String {
+    format!("\"{text}\"")
+}
+
+#[cfg(not(windows))]
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn shell_command(line: &str) -> process::Command {
+    use std::os::windows::process::CommandExt;
+
+    let mut command = process::Command::new("cmd");
+    command.arg("/C").raw_arg(line);
+    command
+}
+
+#[cfg(not(windows))]
+fn shell_command(line: &str) -> process::Command {
+    let mut command = process::Command::new("sh");
+    command.arg("-c").arg(line);
+    command
+}
+
+fn write_patch(
+    path: Option<&Path>,
+    cwd: &Path,
+    updated_paths: &[PathBuf],
+    staged: &HashMap<PathBuf, Vec<u8>>,
+    warnings: &mut Warnings,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    let mut contents = Vec::new();
+    for dpr_path in updated_paths {
+        let Some(new) = staged.get(dpr_path) else {
+            continue;
+        };
+        let old = match fs::read(dpr_path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                warnings.push(
+                    Diagnostic::warning(
+                        DiagnosticCode::ReadFailed,
+                        format!(
+                            "failed to read dpr {} for the patch: {err}",
+                            dpr_path.display()
+                        ),
+                    )
+                    .at(dpr_path),
+                );
+                continue;
+            }
+        };
+        contents.extend(patch::file_diff(&patch_path(dpr_path, cwd), &old, new));
+    }
+    match fs::write(path, contents) {
+        Ok(()) => println!(
+            "Patch for {} dpr file(s) written to {}",
+            updated_paths.len(),
+            plain_path(path)
+        ),
+        Err(err) => warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::WriteFailed,
+                format!("failed to write patch {}: {err}", path.display()),
+            )
+            .at(path),
+        ),
+    }
+}
+
+/// `path` relative to `cwd` with `/` separators, as `git apply` expects.
+fn patch_path(path: &Path, cwd: &Path) -> String {
+    let path = unit_cache::strip_verbatim_prefix(path);
+    let cwd = unit_cache::strip_verbatim_prefix(&unit_cache::canonicalize_if_exists(cwd));
+    let relative = diff_paths(&path, &cwd).unwrap_or(path);
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_project_unit_cache(
+    common: &SharedArgs,
+    search_roots: &[PathBuf],
+    cwd: &Path,
+    pas_files: &[PathBuf],
+    inc_files: &[PathBuf],
+    prefetched: &unit_cache::Prefetched,
+    known_units: unit_cache::KnownUnits,
+    warnings: &mut Warnings,
+) -> (unit_cache::UnitCache, Option<cache_file::CacheFile>) {
+    let (unit_cache, cache_file) = load_project_unit_cache(
+        common,
+        search_roots,
+        cwd,
+        pas_files,
+        inc_files,
+        prefetched,
+        known_units,
+        &ConsoleEvents,
+        warnings,
+    );
+    if let Some(cache_file) = &cache_file {
+        println!(
+            "Cache file: {} ({} reused, {} parsed)",
+            plain_path(cache_file.path()),
+            cache_file.hits,
+            cache_file.misses
+        );
+    }
+    (unit_cache, cache_file)
+}
+
+fn load_known_units(
+    value: Option<&str>,
+    cwd: &Path,
+) -> Result<unit_cache::KnownUnits, FixdprError> {
+    let Some(value) = value else {
+        return Ok(unit_cache::KnownUnits::default());
+    };
+    let path = resolve_path_with_flag(value, cwd, "--known-units")?;
+    if !path.is_file() {
+        return Err(FixdprError::PathNotFound {
+            flag: "--known-units".to_string(),
+            path,
+        });
+    }
+    unit_cache::KnownUnits::load(&path).map_err(|err| FixdprError::io_at(&path, err))
+}
+
+fn print_known_units(value: Option<&str>, known_units: &unit_cache::KnownUnits) {
+    if let Some(value) = value {
+        println!("Known units: {} (built-in + {})", known_units.len(), value);
+    }
+}
+
+fn build_delphi_unit_cache(
+    delphi_roots: &[PathBuf],
+    max_depth: Option<usize>,
+    ignore: fs_walk::IgnoreMatcher,
+    source: unit_cache::SourceOptions,
+) -> Option<unit_cache::LazyUnitCache> {
+    let cache = project::lazy_delphi_unit_cache(delphi_roots, max_depth, ignore, source)?;
+    println!("Delphi fallback unit cache: deferred until a unit is not found in the project");
+    Some(cache)
+}
+
+fn report_delphi_unit_cache(
+    delphi_unit_cache: Option<&unit_cache::LazyUnitCache>,
+    warnings: &mut Warnings,
+) {
+    let Some(cache) = delphi_unit_cache else {
+        return;
+    };
+    warnings.extend(cache.take_warnings());
+    if cache.is_materialized() {
+        println!(
+            "Delphi fallback unit cache: materialized ({} indexed .pas, {} parsed)",
+            cache.indexed_count(),
+            cache.parsed_count()
+        );
+    } else {
+        println!("Delphi fallback unit cache: not materialized");
+    }
+}
+
 fn print_summary(summary: SummaryOutput<'_>) {
     let SummaryOutput {
         infos,
         warnings,
         show_infos,
-        show_warnings,
+        display,
         pas_scanned,
         dpr_summary,
+        staged_only,
         ignored_dpr,
-        search_roots,
+        filtered_dpr,
+        cached_unchanged_dpr,
+        roots,
+        report_unresolved,
+        timings,
     } = summary;
 
     let unchanged = dpr_summary
@@ -1216,51 +4057,283 @@ fn print_summary(summary: SummaryOutput<'_>) {
     if show_infos && !infos.is_empty() {
         println!("Infos list:");
         for info in infos {
-            println!("  {info}");
-        }
-    }
-    println!("Warnings: {}", warnings.len());
-    if show_warnings && !warnings.is_empty() {
-        println!("Warnings list:");
-        for warning in warnings {
-            println!("  {warning}");
+            println!("  {}", display.paths.text(info));
         }
     }
+    print_warnings(warnings, &display);
     println!();
     println!("Report:");
     println!("  pas scanned: {}", pas_scanned);
     println!("  dpr scanned: {}", dpr_summary.scanned);
     println!("  dpr ignored: {}", ignored_dpr);
+    if filtered_dpr > 0 {
+        println!("  dpr filtered: {}", filtered_dpr);
+    }
     println!("  dpr updated: {}", dpr_summary.updated);
     println!("  dpr unchanged: {}", unchanged);
+    if dpr_summary
+        .details
+        .iter()
+        .any(|detail| detail.dependency.is_some())
+    {
+        for (label, outcome) in [
+            (
+                "dpr with new dependency inserted",
+                dpr_edit::DependencyOutcome::Inserted,
+            ),
+            (
+                "dpr already listing new dependency",
+                dpr_edit::DependencyOutcome::AlreadyPresent,
+            ),
+            (
+                "dpr not needing new dependency",
+                dpr_edit::DependencyOutcome::NotNeeded,
+            ),
+        ] {
+            println!("  {label}: {}", dpr_summary.dependency_count(outcome));
+        }
+    }
+    if cached_unchanged_dpr > 0 {
+        println!("  dpr unchanged (cached): {}", cached_unchanged_dpr);
+    }
     println!("  dpr failures: {}", dpr_summary.failures);
-    println!("Updated dpr files ({}):", dpr_summary.updated);
+    let project_inserts = dpr_summary.inserted_from(dpr_edit::ResolutionSource::Project);
+    let delphi_inserts = dpr_summary.inserted_from(dpr_edit::ResolutionSource::Delphi);
+    if project_inserts + delphi_inserts > 0 {
+        println!("  units inserted from project: {project_inserts}");
+        println!("  units inserted from delphi: {delphi_inserts}");
+    }
+    if dpr_summary.suppressed_delphi_inserts > 0 {
+        println!(
+            "  delphi inserts suppressed: {}",
+            dpr_summary.suppressed_delphi_inserts
+        );
+    }
+    if roots.len() > 1 {
+        for line in root_lines(roots, &dpr_summary.updated_paths) {
+            println!("{line}");
+        }
+    }
+    let heading = if staged_only {
+        "Would update dpr files"
+    } else {
+        "Updated dpr files"
+    };
+    println!("{heading} ({}):", dpr_summary.updated);
     if dpr_summary.updated_paths.is_empty() {
         println!("  (none)");
     } else {
         for path in &dpr_summary.updated_paths {
-            println!("  {}", display_path(path, search_roots));
+            println!("  {}", display.paths.path(path));
+        }
+    }
+    for line in partial_lines(&dpr_summary.details, &display) {
+        println!("{line}");
+    }
+    for line in missing_entry_lines(&dpr_summary.details, &display) {
+        println!("{line}");
+    }
+    if report_unresolved {
+        for line in unresolved_lines(&dpr_summary.unresolved, &display) {
+            println!("{line}");
+        }
+    }
+    if display.show_details {
+        print_dpr_details(&dpr_summary.details, &display);
+    }
+    println!("{}", timings.completed_line());
+    if timings.detailed {
+        timings.print(&display);
+    }
+}
+
+/// The dpr entries pointing at unit files that no longer exist, by dpr;
+/// empty when there are none.
+fn missing_entry_lines(
+    details: &[dpr_edit::DprDetail],
+    display: &ReportDisplay<'_>,
+) -> Vec<String> {
+    let count = details
+        .iter()
+        .map(|detail| detail.missing_entries.len())
+        .sum::<usize>();
+    if count == 0 {
+        return Vec::new();
+    }
+    let mut lines = vec![format!("Missing units referenced by dpr ({count}):")];
+    for detail in details {
+        if detail.missing_entries.is_empty() {
+            continue;
+        }
+        lines.push(format!("  {}:", display.paths.path(&detail.path)));
+        for entry in &detail.missing_entries {
+            lines.push(format!(
+                "    {} in '{}'{}",
+                entry.name,
+                entry.in_path,
+                if entry.removed { " (removed)" } else { "" }
+            ));
+        }
+    }
+    lines
+}
+
+/// The dprs `--max-inserts` left missing units out of, with the inserted and
+/// total missing counts; empty when there are none.
+fn partial_lines(details: &[dpr_edit::DprDetail], display: &ReportDisplay<'_>) -> Vec<String> {
+    let partial: Vec<_> = details
+        .iter()
+        .filter(|detail| detail.not_inserted > 0)
+        .collect();
+    if partial.is_empty() {
+        return Vec::new();
+    }
+    let mut lines = vec![format!("Partially updated dpr files ({}):", partial.len())];
+    for detail in partial {
+        let inserted = detail.inserted.len();
+        lines.push(format!(
+            "  {}: inserted {inserted} of {} missing unit(s), {} left out",
+            display.paths.path(&detail.path),
+            inserted + detail.not_inserted,
+            detail.not_inserted
+        ));
+    }
+    lines
+}
+
+fn unresolved_lines(
+    unresolved: &[dpr_edit::UnresolvedReference],
+    display: &ReportDisplay<'_>,
+) -> Vec<String> {
+    let mut lines = vec![format!("Unresolved references ({}):", unresolved.len())];
+    if unresolved.is_empty() {
+        lines.push("  (none)".to_string());
+    }
+    for reference in unresolved {
+        lines.push(format!("  {}:", reference.name));
+        for path in &reference.referenced_by {
+            lines.push(format!("    {}", display.paths.path(path)));
+        }
+    }
+    lines
+}
+
+fn root_lines(roots: &[fs_walk::RootCounts], updated_paths: &[PathBuf]) -> Vec<String> {
+    let mut updated = vec![0; roots.len()];
+    for path in updated_paths {
+        if let Some(index) = fs_walk::root_index(roots, path) {
+            updated[index] += 1;
+        }
+    }
+    let mut lines = vec![format!("Search roots ({}):", roots.len())];
+    for (counts, updated) in roots.iter().zip(updated) {
+        let root = plain_path(&counts.root);
+        if counts.pas_files == 0 && counts.dpr_files == 0 {
+            lines.push(format!("  {root}: nothing found"));
+        } else {
+            lines.push(format!(
+                "  {root}: pas scanned {}, dpr scanned {}, dpr updated {updated}",
+                counts.pas_files, counts.dpr_files
+            ));
+        }
+    }
+    lines
+}
+
+fn print_dpr_details(details: &[dpr_edit::DprDetail], display: &ReportDisplay<'_>) {
+    println!("Dpr details ({}):", details.len());
+    if details.is_empty() {
+        println!("  (none)");
+    }
+    for detail in details {
+        let dependency = match detail.dependency {
+            Some(outcome) => format!(" (new dependency {})", outcome.label()),
+            None => String::new(),
+        };
+        println!(
+            "  {}: {}{dependency}",
+            display.paths.path(&detail.path),
+            detail.status.label()
+        );
+        for unit in &detail.inserted {
+            let guessed = if unit.name_from_stem {
+                " (name from file stem)"
+            } else {
+                ""
+            };
+            let source = match unit.source {
+                Some(source) => format!(" [{}]", source.label()),
+                None => String::new(),
+            };
+            match &unit.in_path {
+                Some(in_path) => {
+                    println!(
+                        "    inserted: {} in '{}'{source}{guessed}",
+                        unit.name, in_path
+                    )
+                }
+                None => println!("    inserted: {}{source}{guessed}", unit.name),
+            }
+        }
+        for warning in &detail.warnings {
+            if display.suppressed.contains(&warning.code) {
+                continue;
+            }
+            println!("    {}", display.paths.text(&warning.to_string()));
+        }
+    }
+}
+
+fn print_warnings(all_warnings: &Warnings, display: &ReportDisplay<'_>) {
+    for line in warning_lines(all_warnings, display) {
+        println!("{line}");
+    }
+}
+
+fn warning_lines(all_warnings: &Warnings, display: &ReportDisplay<'_>) -> Vec<String> {
+    let warnings = all_warnings.without(display.suppressed);
+    let mut lines = Vec::new();
+    if display.dedupe_warnings && warnings.unique_len() < warnings.len() {
+        lines.push(format!(
+            "Warnings: {} ({} unique)",
+            warnings.len(),
+            warnings.unique_len()
+        ));
+    } else {
+        lines.push(format!("Warnings: {}", warnings.len()));
+    }
+    let suppressed_count = all_warnings.len() - warnings.len();
+    if suppressed_count > 0 {
+        lines.push(format!("Suppressed warnings: {suppressed_count}"));
+    }
+    if display.show_warnings && !warnings.is_empty() {
+        lines.push("Warnings list:".to_string());
+        if display.dedupe_warnings {
+            let limit = (!display.show_all_referencers).then_some(warnings::EXAMPLE_REFERENCERS);
+            for (warning, count, referencers) in warnings.grouped_with_referencers() {
+                let line = warnings::format_referenced(warning, count, referencers, limit);
+                lines.push(format!("  {}", display.paths.text(&line)));
+            }
+        } else {
+            for warning in warnings.iter() {
+                lines.push(format!("  {}", display.paths.text(&warning.to_string())));
+            }
         }
     }
+    lines
 }
 
 fn print_conditionals_summary(summary: ConditionalsOutput<'_>) {
     let ConditionalsOutput {
         warnings,
-        show_warnings,
+        display,
         pas_scanned,
         dpr_scanned,
         buckets,
     } = summary;
 
     println!();
-    println!("Warnings: {}", warnings.len());
-    if show_warnings && !warnings.is_empty() {
-        println!("Warnings list:");
-        for warning in warnings {
-            println!("  {warning}");
-        }
-    }
+    print_warnings(warnings, &display);
 
     println!();
     println!("Report:");
@@ -1308,75 +4381,698 @@ fn print_conditionals_summary(summary: ConditionalsOutput<'_>) {
     }
 }
 
-fn resolve_new_dependency_path(value: &str, cwd: &Path) -> Result<PathBuf, String> {
-    resolve_path_with_flag(value, cwd, "NEW_DEPENDENCY")
-}
-
-fn resolve_dpr_file_path(value: &str, cwd: &Path) -> Result<PathBuf, String> {
-    resolve_path_with_flag(value, cwd, "DPR_FILE")
-}
+fn print_who_uses_report(
+    report: &dpr_edit::WhoUsesReport,
+    unit_name: &str,
+    transitive: bool,
+    pas_scanned: usize,
+    warnings: &Warnings,
+    display: &ReportDisplay<'_>,
+) {
+    println!();
+    print_warnings(warnings, display);
 
-fn resolve_target_dpr_paths(values: &[String], cwd: &Path) -> Result<Vec<PathBuf>, String> {
-    let mut paths = Vec::new();
-    for value in values {
-        let path = resolve_path_with_flag(value, cwd, "--target-dpr")?;
-        validate_dpr_file_path(&path, "--target-dpr")?;
-        paths.push(unit_cache::canonicalize_if_exists(&path));
+    println!();
+    println!("Report:");
+    println!("  pas scanned: {}", pas_scanned);
+    println!("  dpr scanned: {}", report.scanned);
+    println!();
+    print_path_list(
+        &format!("Dprs listing {unit_name}"),
+        &report.direct_dprs,
+        display,
+    );
+    if transitive {
+        print_path_list(
+            &format!("Dprs reaching {unit_name} through other units"),
+            &report.transitive_dprs,
+            display,
+        );
     }
-    Ok(dedupe_paths(paths))
+    print_path_list(&format!("Units using {unit_name}"), &report.units, display);
 }
 
-fn resolve_path_with_flag(value: &str, cwd: &Path, flag_name: &str) -> Result<PathBuf, String> {
-    let trimmed = value.trim();
-    if trimmed.is_empty() {
-        return Err(format!("{flag_name} cannot be empty"));
+fn print_path_list(title: &str, paths: &[PathBuf], display: &ReportDisplay<'_>) {
+    println!("{title} ({}):", paths.len());
+    if paths.is_empty() {
+        println!("  (none)");
     }
-
-    let mut path = PathBuf::from(trimmed);
-    if path.is_relative() {
-        path = cwd.join(path);
+    for path in paths {
+        println!("  {}", display.paths.path(path));
     }
-    Ok(path)
-}
-
-fn is_pas_file(path: &Path) -> bool {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| ext.eq_ignore_ascii_case("pas"))
-        .unwrap_or(false)
 }
 
-fn is_dpr_file(path: &Path) -> bool {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| ext.eq_ignore_ascii_case("dpr"))
-        .unwrap_or(false)
-}
+fn print_dependents_report(
+    report: &dpr_edit::DependentsReport,
+    unit_name: &str,
+    args: &DependentsArgs,
+    (pas_scanned, dpr_scanned): (usize, usize),
+    warnings: &Warnings,
+    display: &ReportDisplay<'_>,
+) {
+    println!();
+    print_warnings(warnings, display);
 
-fn validate_new_dependency_path(path: &Path) -> Result<(), String> {
-    if !path.is_file() {
-        return Err(format!("NEW_DEPENDENCY path not found: {}", path.display()));
-    }
-    if !is_pas_file(path) {
-        return Err(format!(
-            "NEW_DEPENDENCY must point to a .pas file: {}",
-            path.display()
+    println!();
+    println!("Report:");
+    println!("  pas scanned: {}", pas_scanned);
+    println!("  dpr scanned: {}", dpr_scanned);
+    println!();
+    let title = if args.direct_only {
+        format!("Units using {unit_name} directly")
+    } else {
+        format!("Units depending on {unit_name}")
+    };
+    if !args.group_by_dpr {
+        print_dependent_list(&title, report.units.iter(), !args.direct_only, display);
+        return;
+    }
+
+    let mut dprs: Vec<&PathBuf> = report.units.iter().flat_map(|unit| &unit.dprs).collect();
+    dprs.sort();
+    dprs.dedup();
+    println!("{title}, by dpr:");
+    for dpr in dprs {
+        print_dependent_list(
+            &display.paths.path(dpr),
+            report.units.iter().filter(|unit| unit.dprs.contains(dpr)),
+            !args.direct_only,
+            display,
+        );
+    }
+    print_dependent_list(
+        "Not listed in any dpr",
+        report.units.iter().filter(|unit| unit.dprs.is_empty()),
+        !args.direct_only,
+        display,
+    );
+}
+
+fn print_dependent_list<'u>(
+    title: &str,
+    units: impl Iterator<Item = &'u dpr_edit::DependentUnit>,
+    mark_direct: bool,
+    display: &ReportDisplay<'_>,
+) {
+    let units: Vec<_> = units.collect();
+    println!("{title} ({}):", units.len());
+    if units.is_empty() {
+        println!("  (none)");
+    }
+    for unit in units {
+        let marker = if mark_direct && unit.direct {
+            " [direct]"
+        } else {
+            ""
+        };
+        println!(
+            "  {} ({}){marker}",
+            unit.name,
+            display.paths.path(&unit.path)
+        );
+    }
+}
+
+fn print_fanout_report(
+    report: &dpr_edit::FanoutReport,
+    pas_scanned: usize,
+    warnings: &Warnings,
+    display: &ReportDisplay<'_>,
+) {
+    println!();
+    print_warnings(warnings, display);
+
+    println!();
+    println!("Report:");
+    println!("  pas scanned: {}", pas_scanned);
+    println!("  dpr scanned: {}", report.scanned);
+    println!();
+    for line in fanout_lines(report, display) {
+        println!("{line}");
+    }
+}
+
+/// The per-dpr table of `fanout`, heaviest dpr first.
+fn fanout_lines(report: &dpr_edit::FanoutReport, display: &ReportDisplay<'_>) -> Vec<String> {
+    let mut lines = vec![format!("Fan-out per dpr ({}):", report.dprs.len())];
+    if report.dprs.is_empty() {
+        lines.push("  (none)".to_string());
+        return lines;
+    }
+    lines.push(format!(
+        "  {:>7} {:>7} {:>7} {:>7}  dpr",
+        "closure", "project", "delphi", "direct"
+    ));
+    for dpr in &report.dprs {
+        lines.push(format!(
+            "  {:>7} {:>7} {:>7} {:>7}  {}",
+            dpr.closure(),
+            dpr.project_units,
+            dpr.delphi_units,
+            dpr.direct,
+            display.paths.path(&dpr.path)
         ));
     }
+    lines
+}
+
+fn fanout_json(
+    report: &dpr_edit::FanoutReport,
+    warnings: &Warnings,
+    display: &ReportDisplay<'_>,
+    timings: &Timings,
+) -> String {
+    let dprs: Vec<String> = report
+        .dprs
+        .iter()
+        .map(|dpr| {
+            format!(
+                "{{\"path\":{},\"direct\":{},\"closure\":{},\"project_units\":{},\"delphi_units\":{}}}",
+                json_string(&display.paths.path(&dpr.path)),
+                dpr.direct,
+                dpr.closure(),
+                dpr.project_units,
+                dpr.delphi_units
+            )
+        })
+        .collect();
+    format!(
+        "{{\"dprs\":[{}],\"scanned\":{},\"warnings\":{}{}}}",
+        dprs.join(","),
+        report.scanned,
+        json_warnings(warnings, display),
+        json_timings_field(timings, display)
+    )
+}
+
+fn dependents_json(
+    report: &dpr_edit::DependentsReport,
+    unit_name: &str,
+    warnings: &Warnings,
+    display: &ReportDisplay<'_>,
+    timings: &Timings,
+) -> String {
+    let units: Vec<String> = report
+        .units
+        .iter()
+        .map(|unit| {
+            format!(
+                "{{\"name\":{},\"path\":{},\"direct\":{},\"section\":{},\"dprs\":{}}}",
+                json_string(&unit.name),
+                json_string(&display.paths.path(&unit.path)),
+                unit.direct,
+                unit.section
+                    .map(|section| json_string(section.label()))
+                    .unwrap_or_else(|| "null".to_string()),
+                json_array(unit.dprs.iter().map(|dpr| display.paths.path(dpr)))
+            )
+        })
+        .collect();
+    format!(
+        "{{\"unit\":{},\"units\":[{}],\"warnings\":{}{}}}",
+        json_string(unit_name),
+        units.join(","),
+        json_warnings(warnings, display),
+        json_timings_field(timings, display)
+    )
+}
+
+fn who_uses_json(
+    report: &dpr_edit::WhoUsesReport,
+    unit_name: &str,
+    transitive: bool,
+    warnings: &Warnings,
+    display: &ReportDisplay<'_>,
+    timings: &Timings,
+) -> String {
+    format!(
+        "{{{}{}}}",
+        who_uses_fields(report, unit_name, transitive, warnings, display).join(","),
+        json_timings_field(timings, display)
+    )
+}
+
+/// The `,"elapsed_ms":{...}` member of JSON reports, followed by
+/// `,"timings":{...}` with `--timings`.
+fn json_timings_field(timings: &Timings, display: &ReportDisplay<'_>) -> String {
+    let mut field = format!(",\"elapsed_ms\":{}", timings.elapsed_json());
+    if timings.detailed {
+        field.push_str(&format!(",\"timings\":{}", timings.json(display)));
+    }
+    field
+}
+
+/// `--introduced-scope`, or `none` when `--disable-introduced-dependencies` is given.
+fn introduced_scope(scope: dpr_edit::IntroducedScope, disabled: bool) -> dpr_edit::IntroducedScope {
+    if disabled {
+        dpr_edit::IntroducedScope::None
+    } else {
+        scope
+    }
+}
+
+/// The `--namespace-prefix` values, or `None` to keep the Delphi defaults.
+fn namespace_prefixes(values: &[String]) -> Option<Vec<String>> {
+    let prefixes: Vec<String> = values
+        .iter()
+        .map(|value| value.trim().trim_end_matches('.').to_string())
+        .filter(|value| !value.is_empty())
+        .collect();
+    (!prefixes.is_empty()).then_some(prefixes)
+}
+
+fn resolve_new_dependency_path(value: &str, cwd: &Path) -> Result<PathBuf, FixdprError> {
+    resolve_path_with_flag(value, cwd, "NEW_DEPENDENCY")
+}
+
+fn resolve_dpr_file_path(value: &str, cwd: &Path) -> Result<PathBuf, FixdprError> {
+    resolve_path_with_flag(value, cwd, "DPR_FILE")
+}
+
+fn resolve_target_dpr_paths(values: &[String], cwd: &Path) -> Result<Vec<PathBuf>, FixdprError> {
+    let mut paths = Vec::new();
+    for value in values {
+        let path = resolve_path_with_flag(value, cwd, "--target-dpr")?;
+        validate_dpr_file_path(&path, "--target-dpr")?;
+        paths.push(unit_cache::canonicalize_if_exists(&path));
+    }
+    Ok(dedupe_paths(paths))
+}
+
+fn validate_new_dependency_path(path: &Path) -> Result<(), FixdprError> {
+    validate_pas_file_path(path, "NEW_DEPENDENCY")
+}
+
+/// Like [`validate_dpr_file_path`], but `path` may name a dpr that does not
+/// exist yet inside an existing folder; that path is returned with its folder
+/// canonicalized.
+fn validate_new_dpr_file_path(path: &Path, flag_name: &str) -> Result<PathBuf, FixdprError> {
+    if path.exists() {
+        validate_dpr_file_path(path, flag_name)?;
+        return Ok(unit_cache::canonicalize_if_exists(path));
+    }
+    if !fs_walk::is_dpr_file(path) {
+        return Err(FixdprError::WrongFileKind {
+            flag: flag_name.to_string(),
+            path: path.to_path_buf(),
+            expected: ".dpr or .lpr",
+        });
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if parent.is_dir() => {
+            Ok(unit_cache::canonicalize_if_exists(parent).join(name))
+        }
+        (parent, _) => Err(FixdprError::PathNotFound {
+            flag: flag_name.to_string(),
+            path: parent.unwrap_or(path).to_path_buf(),
+        }),
+    }
+}
+
+/// Maps changed unit names and `.pas` paths to cached unit names.
+fn changed_unit_names(
+    values: &[String],
+    unit_cache: &unit_cache::UnitCache,
+    cwd: &Path,
+    warnings: &mut Warnings,
+) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for value in values {
+        let value = value.trim();
+        let name = if is_pas_file(Path::new(value)) {
+            let path = unit_cache::canonicalize_if_exists(&cwd.join(value));
+            unit_cache.by_path.get(&path).map(|info| info.name.clone())
+        } else {
+            unit_cache
+                .by_name
+                .get(&value.to_ascii_lowercase())
+                .and_then(|paths| paths.first())
+                .and_then(|path| unit_cache.by_path.get(path))
+                .map(|info| info.name.clone())
+        };
+        let Some(name) = name else {
+            warnings.push(Diagnostic::warning(
+                DiagnosticCode::UnitNotFound,
+                format!("changed unit not found in the unit cache: {value}"),
+            ));
+            continue;
+        };
+        if !names.iter().any(|known| known.eq_ignore_ascii_case(&name)) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Lists the `.pas` files that differ from `reference`, relative to `cwd`.
+fn git_changed_pas_files(reference: &str, cwd: &Path) -> Result<Vec<PathBuf>, FixdprError> {
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(cwd)
+        .args(["diff", "--name-only", "--relative", reference, "--"])
+        .output()
+        .map_err(|err| FixdprError::Failed(format!("failed to run git: {err}")))?;
+    if !output.status.success() {
+        return Err(FixdprError::Failed(format!(
+            "git diff against --changed-since {reference} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(Path::new)
+        .filter(|path| is_pas_file(path))
+        .map(|path| cwd.join(path))
+        .collect())
+}
+
+fn shared_args_mut(command: &mut Commands) -> Option<&mut SharedArgs> {
+    match command {
+        Commands::AddDependency(args) => Some(&mut args.common),
+        Commands::InsertDependency(args) => Some(&mut args.common),
+        Commands::DeleteDependency(args) => Some(&mut args.common),
+        Commands::FixDpr(args) => Some(&mut args.common),
+        Commands::ListConditionals(args) => Some(&mut args.common),
+        Commands::WhoUses(args) => Some(&mut args.common),
+        Commands::Impact(args) => Some(&mut args.common),
+        Commands::Dependents(args) => Some(&mut args.common),
+        Commands::Fanout(args) => Some(&mut args.common),
+        Commands::Cache(args) => Some(match &mut args.action {
+            CacheAction::Build(args) => &mut args.common,
+            CacheAction::Stats(args) => &mut args.common,
+            CacheAction::Verify(args) => &mut args.common,
+        }),
+        Commands::Init(args) => Some(&mut args.common),
+        Commands::CopyUses(_) => None,
+        Commands::Serve(args) => Some(&mut args.common),
+    }
+}
+
+/// Appends the values listed in `--search-path-file` and `--ignore-path-file`
+/// to `--search-path` and `--ignore-path`.
+fn expand_path_files(common: &mut SharedArgs, cwd: &Path) -> Result<(), FixdprError> {
+    for file in &common.search_path_file {
+        let values = read_path_file("--search-path-file", file, cwd)?;
+        common.search_path.extend(values);
+    }
+    for file in &common.ignore_path_file {
+        let values = read_path_file("--ignore-path-file", file, cwd)?;
+        common.ignore_path.extend(values);
+    }
     Ok(())
 }
 
-fn validate_dpr_file_path(path: &Path, flag_name: &str) -> Result<(), String> {
+/// The non-empty lines of `file` that do not start with `#`, with relative
+/// paths and globs anchored at the file's folder.
+fn read_path_file(flag: &str, file: &str, cwd: &Path) -> Result<Vec<String>, FixdprError> {
+    let path = cwd.join(file.trim());
     if !path.is_file() {
-        return Err(format!("{flag_name} path not found: {}", path.display()));
+        return Err(FixdprError::PathNotFound {
+            flag: flag.to_string(),
+            path,
+        });
     }
-    if !is_dpr_file(path) {
-        return Err(format!(
-            "{flag_name} must point to a .dpr file: {}",
-            path.display()
+    let text = fs::read_to_string(&path).map_err(|err| FixdprError::io_at(&path, err))?;
+    let base = path.parent().unwrap_or(cwd);
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            if Path::new(line).is_absolute() {
+                line.to_string()
+            } else {
+                base.join(line).to_string_lossy().into_owned()
+            }
+        })
+        .collect())
+}
+
+fn resolve_search_roots(common: &SharedArgs, cwd: &Path) -> fs_walk::SearchRootsResolution {
+    let resolution = match fs_walk::resolve_search_roots(&common.search_path, cwd) {
+        Ok(resolution) => resolution,
+        Err(err) => exit_with(err),
+    };
+    if common.error_on_unmatched_search_path && !resolution.unmatched_patterns.is_empty() {
+        exit_with(FixdprError::invalid_argument(format!(
+            "--search-path pattern matched no directories: {}",
+            resolution.unmatched_patterns.join(", ")
+        )));
+    }
+    resolution
+}
+
+/// An `--indent` value: spaces and tabs, with `\t` standing for a tab.
+fn parse_indent(value: &str) -> Result<String, String> {
+    let indent = value.replace("\\t", "\t");
+    if indent.chars().all(|ch| ch == ' ' || ch == '\t') {
+        Ok(indent)
+    } else {
+        Err(format!(
+            "--indent may only contain spaces and tabs (\\t), got '{value}'"
+        ))
+    }
+}
+
+fn parse_file_size(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let upper = trimmed.to_ascii_uppercase();
+    let (digits, multiplier) = [("GB", 1u64 << 30), ("MB", 1 << 20), ("KB", 1 << 10)]
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            upper
+                .strip_suffix(suffix)
+                .map(|digits| (digits.trim().to_string(), *multiplier))
+        })
+        .unwrap_or((upper.clone(), 1));
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(multiplier))
+        .ok_or_else(|| format!("expected a size such as 512KB or 16MB, got '{trimmed}'"))
+}
+
+fn delphi_source_options(args: &DelphiArgs, cwd: &Path) -> delphi::SourceRootsOptions {
+    let bds_roots = match fs_walk::resolve_optional_roots(&args.delphi_root, cwd, "--delphi-root") {
+        Ok(roots) => roots,
+        Err(err) => exit_with(err),
+    };
+    let mut options = delphi::SourceRootsOptions::builder()
+        .roots_are_source(args.delphi_root_is_source)
+        .library_paths(args.delphi_library_paths);
+    for root in bds_roots {
+        options = options.bds_root(root);
+    }
+    for dir in &args.delphi_source_dirs {
+        options = options.source_dir(dir);
+    }
+    options.build()
+}
+
+fn scan_search_roots(
+    common: &SharedArgs,
+    search_roots: &[PathBuf],
+    cwd: &Path,
+    ignore_matcher: &fs_walk::IgnoreMatcher,
+    events: &dyn EventSink,
+    warnings: &mut Warnings,
+) -> fs_walk::FsScan {
+    let (scan, options) =
+        scan_search_roots_quiet(common, search_roots, cwd, ignore_matcher, events, warnings);
+    if scan.skipped_dirs > 0 {
+        println!(
+            "Skipped {} folder(s) named {}",
+            scan.skipped_dirs,
+            options.ignored_dir_names().join(", ")
+        );
+    }
+    scan
+}
+
+fn scan_search_roots_quiet(
+    common: &SharedArgs,
+    search_roots: &[PathBuf],
+    cwd: &Path,
+    ignore_matcher: &fs_walk::IgnoreMatcher,
+    events: &dyn EventSink,
+    warnings: &mut Warnings,
+) -> (fs_walk::FsScan, fs_walk::ScanOptions) {
+    let options = project_options(common, cwd);
+    match project::scan(
+        &options,
+        search_roots,
+        cwd,
+        ignore_matcher,
+        events,
+        warnings,
+    ) {
+        Ok(scan) => (scan, options.scan().clone()),
+        Err(err) => exit_with(err),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn load_project_unit_cache(
+    common: &SharedArgs,
+    search_roots: &[PathBuf],
+    cwd: &Path,
+    pas_files: &[PathBuf],
+    inc_files: &[PathBuf],
+    prefetched: &unit_cache::Prefetched,
+    known_units: unit_cache::KnownUnits,
+    events: &dyn EventSink,
+    warnings: &mut Warnings,
+) -> (unit_cache::UnitCache, Option<cache_file::CacheFile>) {
+    match project::load_unit_cache(
+        &project_options(common, cwd),
+        search_roots,
+        cwd,
+        pas_files,
+        inc_files,
+        prefetched,
+        known_units,
+        events,
+        warnings,
+    ) {
+        Ok(loaded) => loaded,
+        Err(err) => exit_with(err),
+    }
+}
+
+/// Saves the unit cache file, except the default one when `read_only` runs
+/// must leave the search roots untouched.
+fn save_project_cache_file(
+    common: &SharedArgs,
+    cwd: &Path,
+    read_only: bool,
+    cache_file: Option<&cache_file::CacheFile>,
+    warnings: &mut Warnings,
+) {
+    let options = project_options(common, cwd).read_only(read_only);
+    project::save_cache_file(&options, cache_file, warnings);
+}
+
+/// How `--jobs`, the cache flags, and the scan flags scan and cache the search roots.
+fn project_options(common: &SharedArgs, cwd: &Path) -> project::ProjectOptions {
+    let cache_file = common.cache_file.as_deref().map(|value| {
+        match resolve_path_with_flag(value, cwd, "--cache-file") {
+            Ok(path) => path,
+            Err(err) => exit_with(err),
+        }
+    });
+    project::ProjectOptions::builder()
+        .scan_options(scan_options(common, common.max_scan_depth))
+        .jobs(common.jobs)
+        .cache_file(cache_file)
+        .no_cache(common.no_cache)
+        .build()
+}
+
+/// How the relative path and layout flags of the dpr-editing commands edit dprs.
+fn edit_options(
+    relative_paths: &RelativePathArgs,
+    layout: &EntryLayoutArgs,
+) -> dpr_edit::EditOptions {
+    dpr_edit::EditOptions::builder()
+        .cross_drive(relative_paths.cross_drive)
+        .max_parent_dirs(
+            relative_paths.max_parent_dirs,
+            relative_paths.on_deep_relative,
+        )
+        .indent(layout.indent.clone())
+        .wrap_at(layout.wrap_at)
+        .insert_in_conditional(layout.insert_in_conditional)
+}
+
+/// How `--max-file-size` and `--no-stem-fallback` read source files.
+fn source_options(common: &SharedArgs) -> unit_cache::SourceOptions {
+    unit_cache::SourceOptions {
+        max_file_size: common.max_file_size,
+        stem_fallback: !common.no_stem_fallback,
+    }
+}
+
+fn scan_options(common: &SharedArgs, max_depth: Option<usize>) -> fs_walk::ScanOptions {
+    let mut options = fs_walk::ScanOptions::builder()
+        .follow_symlinks(common.follow_symlinks)
+        .max_file_size(common.max_file_size)
+        .stem_fallback(!common.no_stem_fallback)
+        .max_depth(max_depth)
+        .default_ignores(!common.no_default_ignores)
+        .strict(common.strict_scan);
+    for name in &common.default_ignore {
+        options = options.ignore_dir_name(name);
+    }
+    options.build()
+}
+
+/// Adds the units found under an implicit dependency root to the scanned files.
+fn merge_dependency_root(
+    pas_files: &[PathBuf],
+    dependency_root: Option<(PathBuf, Vec<PathBuf>)>,
+    infos: &mut Vec<String>,
+) -> Vec<PathBuf> {
+    let Some((root, extra)) = dependency_root else {
+        return pas_files.to_vec();
+    };
+    infos.push(format!(
+        "info: scanned {} because NEW_DEPENDENCY is outside every search root ({} .pas)",
+        root.display(),
+        extra.len()
+    ));
+    dedupe_paths([pas_files.to_vec(), extra].concat())
+}
+
+fn print_unmatched_search_paths(patterns: &[String]) {
+    if !patterns.is_empty() {
+        println!("Unmatched search path pattern(s): {}", patterns.join(", "));
+    }
+}
+
+fn print_delphi_ignores(raw_values: &[String]) {
+    let display = format_values(raw_values);
+    if !display.is_empty() {
+        println!("Delphi ignoring: {display}");
+    }
+}
+
+fn print_discovered_delphi_versions(versions: &[String]) {
+    if !versions.is_empty() {
+        println!("Delphi versions discovered: {}", versions.join(", "));
+    }
+}
+
+fn push_missing_delphi_source_dir_warnings(warnings: &mut Warnings, dirs: &[PathBuf]) {
+    for dir in dirs {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::MissingSourceDir,
+                format!(
+                    "--delphi-source-dirs entry does not exist: {}",
+                    plain_path(dir)
+                ),
+            )
+            .at(dir),
+        );
+    }
+}
+
+fn print_delphi_library_roots(roots: &[PathBuf]) {
+    if !roots.is_empty() {
+        println!("Delphi library paths ({}):", roots.len());
+        for root in roots {
+            println!("  {}", plain_path(root));
+        }
+    }
+}
+
+fn push_unmatched_search_path_warnings(warnings: &mut Warnings, patterns: &[String]) {
+    for pattern in patterns {
+        warnings.push(Diagnostic::warning(
+            DiagnosticCode::UnmatchedSearchPath,
+            format!("--search-path pattern matched no directories: {pattern}"),
         ));
     }
-    Ok(())
 }
 
 fn format_values(values: &[String]) -> String {
@@ -1411,19 +5107,35 @@ fn assumed_value_label(value: conditionals::AssumedValue) -> &'static str {
     }
 }
 
+fn uses_sections_label(sections: UsesSections) -> &'static str {
+    match sections {
+        UsesSections::Interface => "interface",
+        UsesSections::Implementation => "implementation",
+        UsesSections::Both => "both",
+    }
+}
+
+fn build_lookup_assumptions(
+    lookup: &DependencyLookupArgs,
+) -> Result<conditionals::Assumptions, FixdprError> {
+    let mut assumptions = build_dependency_assumptions(&lookup.assume)?;
+    assumptions.set_uses_sections(lookup.uses_sections);
+    Ok(assumptions)
+}
+
 fn build_dependency_assumptions(
     assume: &[DependencyAssumptionArg],
-) -> Result<conditionals::Assumptions, String> {
+) -> Result<conditionals::Assumptions, FixdprError> {
     let mut assumptions = conditionals::Assumptions::default();
     for entry in assume {
         if let Some(previous) = assumptions.get(&entry.symbol) {
             if previous != entry.value {
-                return Err(format!(
+                return Err(FixdprError::invalid_argument(format!(
                     "--assume {} conflicts with earlier {}={}",
                     entry,
                     entry.symbol,
                     assumed_value_label(previous)
-                ));
+                )));
             }
             continue;
         }
@@ -1432,26 +5144,19 @@ fn build_dependency_assumptions(
     Ok(assumptions)
 }
 
-fn contains_path(paths: &[PathBuf], target: &Path) -> bool {
-    let target_key = normalize_path_key(target);
-    paths
-        .iter()
-        .any(|path| normalize_path_key(path) == target_key)
-}
-
 fn ensure_paths_under_search_roots(
     paths: &[PathBuf],
     search_roots: &[PathBuf],
     flag_name: &str,
-) -> Result<(), String> {
+) -> Result<(), FixdprError> {
     for path in paths {
         if search_roots.iter().any(|root| path.starts_with(root)) {
             continue;
         }
-        return Err(format!(
+        return Err(FixdprError::invalid_argument(format!(
             "{flag_name} must be under --search-path: {}",
             path.display()
-        ));
+        )));
     }
     Ok(())
 }
@@ -1461,7 +5166,7 @@ fn select_target_dpr_files(
     target_paths: &[PathBuf],
     target_dprs: &[PathBuf],
     ignore_dpr_matcher: &fs_walk::DprIgnoreMatcher,
-) -> Result<(Vec<PathBuf>, Vec<PathBuf>), String> {
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>), FixdprError> {
     let mut selected = Vec::new();
     let mut ignored = Vec::new();
 
@@ -1474,228 +5179,1615 @@ fn select_target_dpr_files(
             continue;
         }
 
-        if ignore_dpr_matcher.is_ignored(&dpr_path.to_string_lossy()) {
+        if ignore_dpr_matcher.ignores_dpr(dpr_path) {
             ignored.push(dpr_path.clone());
         } else {
             selected.push(dpr_path.clone());
         }
     }
 
-    for target_dpr in target_dprs {
-        if contains_path(scanned_dprs, target_dpr) {
-            continue;
+    for target_dpr in target_dprs {
+        if contains_path(scanned_dprs, target_dpr) {
+            continue;
+        }
+        return Err(FixdprError::invalid_argument(format!(
+            "--target-dpr not found under --search-path after ignore filters: {}",
+            target_dpr.display()
+        )));
+    }
+
+    Ok((selected, ignored))
+}
+
+/// Renders pipeline events as the existing console output.
+struct ConsoleEvents;
+
+impl EventSink for ConsoleEvents {
+    fn on_scan_progress(&self, progress: ScanProgress) {
+        if let ScanProgress::WalkFinished {
+            pas_files,
+            dpr_files,
+        } = progress
+        {
+            println!("Found {pas_files} .pas, {dpr_files} .dpr");
+        }
+    }
+}
+
+/// Prints warnings on stderr, for `serve`, whose stdout carries only responses.
+struct StderrWarnings(DisplayPaths);
+
+impl EventSink for StderrWarnings {
+    fn on_warning(&self, warning: &Diagnostic) {
+        eprintln!("warning: {}", self.0.text(&warning.to_string()));
+    }
+}
+
+const SLOWEST_DPRS: usize = 10;
+
+/// Phase durations collected during a run and printed with `--timings`.
+struct Timings {
+    started: Instant,
+    /// Print the per-phase breakdown (`--timings`), not just the totals.
+    detailed: bool,
+    phases: Vec<(&'static str, Duration)>,
+    slowest_dprs: Vec<(PathBuf, Duration)>,
+    /// Hits and misses of the project's canonical path memo.
+    canonical_paths: (usize, usize),
+}
+
+/// Elapsed time split into the groups of the `completed in` line.
+struct PhaseTotals {
+    total: Duration,
+    scan: Duration,
+    cache: Duration,
+    /// Everything after the caches: dpr edits or analysis.
+    work: Duration,
+}
+
+impl Timings {
+    fn start(detailed: bool) -> Self {
+        Self {
+            started: Instant::now(),
+            detailed,
+            phases: Vec::new(),
+            slowest_dprs: Vec::new(),
+            canonical_paths: (0, 0),
+        }
+    }
+
+    fn totals(&self) -> PhaseTotals {
+        let phase = |name: &str| {
+            self.phases
+                .iter()
+                .filter(|(phase, _)| *phase == name)
+                .map(|(_, elapsed)| *elapsed)
+                .sum::<Duration>()
+        };
+        let delphi_cache = phase("delphi cache");
+        let work = self
+            .phases
+            .iter()
+            .filter(|(phase, _)| !TOTALS_EXCLUDED_PHASES.contains(phase))
+            .map(|(_, elapsed)| *elapsed)
+            .sum::<Duration>()
+            // The Delphi cache loads lazily while dprs are processed.
+            .saturating_sub(delphi_cache);
+        PhaseTotals {
+            total: self.started.elapsed(),
+            scan: phase("scan"),
+            cache: phase("project cache") + delphi_cache,
+            work,
+        }
+    }
+
+    /// `completed in 14.2s (scan 3.1s, cache 6.8s, edits 4.3s)`; the field
+    /// names are fixed so logs can be parsed.
+    fn completed_line(&self) -> String {
+        let totals = self.totals();
+        format!(
+            "completed in {} (scan {}, cache {}, edits {})",
+            format_seconds(totals.total),
+            format_seconds(totals.scan),
+            format_seconds(totals.cache),
+            format_seconds(totals.work)
+        )
+    }
+
+    fn elapsed_json(&self) -> String {
+        let totals = self.totals();
+        format!(
+            "{{\"total\":{},\"scan\":{},\"cache\":{},\"analysis\":{}}}",
+            json_millis(totals.total),
+            json_millis(totals.scan),
+            json_millis(totals.cache),
+            json_millis(totals.work)
+        )
+    }
+
+    fn record(&mut self, phase: &'static str, since: Instant) {
+        self.add(phase, since.elapsed());
+    }
+
+    fn add(&mut self, phase: &'static str, elapsed: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase, elapsed)),
+        }
+    }
+
+    /// Records the graph and write time of an edit pass and its slowest dprs.
+    fn record_dprs(&mut self, summary: &dpr_edit::DprUpdateSummary) {
+        self.add("dpr graphs", summary.graph_time);
+        self.add("dpr writes", summary.write_time);
+        let mut slowest: Vec<(PathBuf, Duration)> = summary
+            .details
+            .iter()
+            .filter(|detail| !detail.elapsed.is_zero())
+            .map(|detail| (detail.path.clone(), detail.elapsed))
+            .collect();
+        slowest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        slowest.truncate(SLOWEST_DPRS);
+        self.slowest_dprs = slowest;
+    }
+
+    fn record_canonical_paths(&mut self, cache: &unit_cache::UnitCache) {
+        self.canonical_paths = cache.canonical.stats();
+    }
+
+    fn record_delphi_cache(&mut self, cache: Option<&unit_cache::LazyUnitCache>) {
+        if let Some(cache) = cache {
+            self.add("delphi cache", cache.build_time());
+        }
+    }
+
+    fn lines(&self, display: &ReportDisplay<'_>) -> Vec<String> {
+        let mut lines = vec!["Timings:".to_string()];
+        for (phase, elapsed) in &self.phases {
+            lines.push(format!("  {phase}: {}", format_duration(*elapsed)));
+        }
+        lines.push(format!(
+            "  total: {}",
+            format_duration(self.started.elapsed())
+        ));
+        let (hits, misses) = self.canonical_paths;
+        lines.push(format!(
+            "  canonical paths: {hits} cached, {misses} resolved"
+        ));
+        if !self.slowest_dprs.is_empty() {
+            lines.push(format!("Slowest dprs ({}):", self.slowest_dprs.len()));
+            for (path, elapsed) in &self.slowest_dprs {
+                lines.push(format!(
+                    "  {}  {}",
+                    format_duration(*elapsed),
+                    display.paths.path(path)
+                ));
+            }
+        }
+        lines
+    }
+
+    fn print(&self, display: &ReportDisplay<'_>) {
+        println!();
+        for line in self.lines(display) {
+            println!("{line}");
+        }
+    }
+
+    fn json(&self, display: &ReportDisplay<'_>) -> String {
+        let mut phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(phase, elapsed)| format!("{}:{}", json_string(phase), json_millis(*elapsed)))
+            .collect();
+        phases.push(format!("\"total\":{}", json_millis(self.started.elapsed())));
+        let slowest: Vec<String> = self
+            .slowest_dprs
+            .iter()
+            .map(|(path, elapsed)| {
+                format!(
+                    "{{\"path\":{},\"ms\":{}}}",
+                    json_string(&display.paths.path(path)),
+                    json_millis(*elapsed)
+                )
+            })
+            .collect();
+        let (hits, misses) = self.canonical_paths;
+        format!(
+            "{{\"phases_ms\":{{{}}},\"slowest_dprs\":[{}],\"canonical_paths\":{{\"hits\":{hits},\"misses\":{misses}}}}}",
+            phases.join(","),
+            slowest.join(",")
+        )
+    }
+}
+
+/// Phases left out of [`PhaseTotals::work`]: the scan, the caches, and the
+/// parts of `dpr processing` that are already counted in it.
+const TOTALS_EXCLUDED_PHASES: [&str; 5] = [
+    "scan",
+    "project cache",
+    "delphi cache",
+    "dpr graphs",
+    "dpr writes",
+];
+
+fn format_seconds(elapsed: Duration) -> String {
+    format!("{:.1}s", elapsed.as_secs_f64())
+}
+
+fn format_duration(elapsed: Duration) -> String {
+    format!("{:.1} ms", elapsed.as_secs_f64() * 1000.0)
+}
+
+fn json_millis(elapsed: Duration) -> String {
+    format!("{:.3}", elapsed.as_secs_f64() * 1000.0)
+}
+
+fn report_display<'a>(common: &'a SharedArgs, search_roots: &[PathBuf]) -> ReportDisplay<'a> {
+    ReportDisplay {
+        show_warnings: common.show_warnings,
+        show_details: common.show_details,
+        dedupe_warnings: !common.no_dedupe,
+        show_all_referencers: common.show_all_referencers,
+        suppressed: &common.suppress,
+        paths: DisplayPaths::new(search_roots, common.absolute_display_paths),
+    }
+}
+
+fn exit_with(err: FixdprError) -> ! {
+    eprintln!(
+        "error: {}",
+        unit_cache::strip_verbatim_prefixes(&err.to_string())
+    );
+    process::exit(err.exit_code());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_dependency_assumptions, hook_command_line, introduced_scope, namespace_prefixes,
+        parse_file_size, patch_path, report_display, updated_list_contents, CacheAction, Cli,
+        Commands, DependencyAssumptionArg, DiagnosticCode, FixdprError, OutputFormat, Timings,
+        UsesSections,
+    };
+    use crate::conditionals::AssumedValue;
+    use crate::dpr_edit;
+    use crate::fs_walk;
+    use clap::Parser;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_add_dependency_with_positional_new_dependency() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "--search-path",
+            ".",
+            "./common/NewUnit.pas",
+        ]);
+
+        assert!(parsed.is_ok(), "{parsed:?}");
+    }
+
+    #[test]
+    fn reject_legacy_new_dependency_flag() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "--search-path",
+            ".",
+            "--new-dependency",
+            "./common/NewUnit.pas",
+        ]);
+
+        assert!(parsed.is_err(), "legacy flag should not parse");
+    }
+
+    #[test]
+    fn parse_add_dependency_with_assume() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "--search-path",
+            ".",
+            "--assume",
+            "DEBUG=on",
+            "./common/NewUnit.pas",
+        ]);
+
+        assert!(parsed.is_ok(), "{parsed:?}");
+    }
+
+    #[test]
+    fn reject_add_dependency_with_invalid_assume_value() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "--search-path",
+            ".",
+            "--assume",
+            "DEBUG=maybe",
+            "./common/NewUnit.pas",
+        ]);
+
+        assert!(parsed.is_err(), "invalid assume value should not parse");
+    }
+
+    #[test]
+    fn reject_add_dependency_with_missing_assume_assignment() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "--search-path",
+            ".",
+            "--assume",
+            "DEBUG",
+            "./common/NewUnit.pas",
+        ]);
+
+        assert!(
+            parsed.is_err(),
+            "missing assume assignment should not parse"
+        );
+    }
+
+    #[test]
+    fn parse_insert_dependency_with_target_path_and_target_dpr() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "insert-dependency",
+            "--search-path",
+            ".",
+            "--target-path",
+            "./apps",
+            "--target-dpr",
+            "./apps/App1.dpr",
+            "./common/NewUnit.pas",
+        ]);
+
+        assert!(parsed.is_ok(), "{parsed:?}");
+    }
+
+    #[test]
+    fn reject_insert_dependency_without_targets() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "insert-dependency",
+            "--search-path",
+            ".",
+            "./common/NewUnit.pas",
+        ]);
+
+        assert!(parsed.is_err(), "insert-dependency should require a target");
+    }
+
+    #[test]
+    fn parse_fix_dpr_with_positional_dpr_file() {
+        let parsed =
+            Cli::try_parse_from(["fixdpr", "fix-dpr", "--search-path", ".", "./app1/App1.dpr"]);
+
+        assert!(parsed.is_ok(), "{parsed:?}");
+    }
+
+    #[test]
+    fn parse_fix_dpr_with_assume() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "--search-path",
+            ".",
+            "--assume",
+            "TRACE=off",
+            "./app1/App1.dpr",
+        ]);
+
+        assert!(parsed.is_ok(), "{parsed:?}");
+    }
+
+    #[test]
+    fn reject_ignore_dpr_in_fix_dpr_mode() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "--search-path",
+            ".",
+            "./app1/App1.dpr",
+            "--ignore-dpr",
+            "./app1/App1.dpr",
+        ]);
+
+        assert!(
+            parsed.is_err(),
+            "--ignore-dpr should not parse in fix-dpr mode"
+        );
+    }
+
+    #[test]
+    fn timings_lines_sum_repeated_phases_and_list_slowest_dprs() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "/src/App.dpr",
+            "--search-path",
+            "/src",
+            "--timings",
+        ])
+        .expect("parse cli");
+        let Commands::FixDpr(args) = cli.command else {
+            panic!("unexpected command: {:?}", cli.command);
+        };
+        assert!(args.common.timings);
+        let display = report_display(&args.common, &[PathBuf::from("/src")]);
+
+        let mut timings = Timings::start(true);
+        timings.add("scan", Duration::from_micros(1500));
+        timings.add("dpr writes", Duration::from_millis(2));
+        timings.add("scan", Duration::from_micros(500));
+        timings.slowest_dprs = vec![(PathBuf::from("/src/App.dpr"), Duration::from_millis(3))];
+
+        let lines = timings.lines(&display);
+        assert_eq!(
+            lines[..3],
+            ["Timings:", "  scan: 2.0 ms", "  dpr writes: 2.0 ms"]
+        );
+        assert!(lines[3].starts_with("  total: "), "{lines:?}");
+        assert_eq!(lines[4], "  canonical paths: 0 cached, 0 resolved");
+        assert_eq!(lines[5..], ["Slowest dprs (1):", "  3.0 ms  App.dpr"]);
+    }
+
+    #[test]
+    fn timings_totals_group_phases_for_the_completed_line() {
+        let mut timings = Timings::start(false);
+        timings.add("scan", Duration::from_millis(3100));
+        timings.add("project cache", Duration::from_millis(5000));
+        timings.add("dpr processing", Duration::from_millis(6100));
+        timings.add("dpr graphs", Duration::from_millis(900));
+        timings.add("dpr writes", Duration::from_millis(400));
+        timings.add("delphi cache", Duration::from_millis(1800));
+        timings.add("fix pass", Duration::from_millis(0));
+
+        let totals = timings.totals();
+        assert_eq!(totals.scan, Duration::from_millis(3100));
+        assert_eq!(totals.cache, Duration::from_millis(6800));
+        assert_eq!(totals.work, Duration::from_millis(4300));
+
+        let line = timings.completed_line();
+        assert!(line.starts_with("completed in "), "{line}");
+        assert!(
+            line.ends_with(" (scan 3.1s, cache 6.8s, edits 4.3s)"),
+            "{line}"
+        );
+        let json = timings.elapsed_json();
+        assert!(json.starts_with("{\"total\":"), "{json}");
+        assert!(
+            json.ends_with(",\"scan\":3100.000,\"cache\":6800.000,\"analysis\":4300.000}"),
+            "{json}"
+        );
+    }
+
+    #[test]
+    fn parse_list_conditionals_with_positional_dpr_file() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "list-conditionals",
+            "--search-path",
+            ".",
+            "./app1/App1.dpr",
+        ]);
+
+        assert!(parsed.is_ok(), "{parsed:?}");
+    }
+
+    #[test]
+    fn parse_cache_verify_with_sample() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "cache",
+            "verify",
+            "--search-path",
+            ".",
+            "--sample",
+            "20",
+        ])
+        .expect("parse cache verify");
+
+        match parsed.command {
+            Commands::Cache(args) => match args.action {
+                CacheAction::Verify(action) => assert_eq!(action.sample, Some(20)),
+                other => panic!("unexpected cache action: {other:?}"),
+            },
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reject_cache_file_with_no_cache() {
+        let parsed = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "--search-path",
+            ".",
+            "--cache-file",
+            "x.cache",
+            "--no-cache",
+            "./app1/App1.dpr",
+        ]);
+
+        assert!(parsed.is_err(), "{parsed:?}");
+    }
+
+    #[test]
+    fn parse_fix_dpr_uses_sections() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "--search-path",
+            ".",
+            "--uses-sections",
+            "interface",
+            "./app1/App1.dpr",
+        ])
+        .expect("parse cli");
+
+        match cli.command {
+            Commands::FixDpr(args) => {
+                assert_eq!(
+                    args.dependency_lookup.uses_sections,
+                    UsesSections::Interface
+                );
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let invalid = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "--search-path",
+            ".",
+            "--uses-sections",
+            "public",
+            "./app1/App1.dpr",
+        ]);
+        assert!(invalid.is_err(), "{invalid:?}");
+    }
+
+    #[test]
+    fn parse_closure_as_uses_sections_alias() {
+        for (value, expected) in [
+            ("interface", UsesSections::Interface),
+            ("all", UsesSections::Both),
+        ] {
+            let cli = Cli::try_parse_from([
+                "fixdpr",
+                "add-dependency",
+                "./common/NewUnit.pas",
+                "--search-path",
+                ".",
+                "--closure",
+                value,
+            ])
+            .expect("parse cli");
+            match cli.command {
+                Commands::AddDependency(args) => {
+                    assert_eq!(args.dependency_lookup.uses_sections, expected)
+                }
+                other => panic!("unexpected command: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_delphi_source_dirs_splits_commas() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "list-conditionals",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--delphi-source-dirs",
+            "rtl,vcl",
+            "--delphi-source-dirs",
+            "vcl/imaging",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::ListConditionals(args) => {
+                assert_eq!(
+                    args.delphi.delphi_source_dirs,
+                    vec!["rtl", "vcl", "vcl/imaging"]
+                )
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_fix_dpr_with_auto_root() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "../stray/App.dpr",
+            "--search-path",
+            ".",
+            "--auto-root",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert!(args.auto_root),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_fix_dpr_namespace_prefixes_split_on_commas() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--namespace-prefix",
+            "Vcl,Fmx.",
+            "--namespace-prefix",
+            "System",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert_eq!(
+                namespace_prefixes(&args.namespace_prefix),
+                Some(vec![
+                    "Vcl".to_string(),
+                    "Fmx".to_string(),
+                    "System".to_string()
+                ])
+            ),
+            other => panic!("unexpected command: {other:?}"),
+        }
+        assert_eq!(namespace_prefixes(&[]), None);
+    }
+
+    #[test]
+    fn parse_fix_dpr_no_exit_on_changes_requires_dry_run() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--dry-run",
+            "--no-exit-on-changes",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert!(args.dry_run && args.no_exit_on_changes),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let err = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--no-exit-on-changes",
+        ])
+        .expect_err("--no-exit-on-changes without --dry-run should fail");
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn parse_fix_dpr_indent_accepts_tab_escape_and_rejects_other_text() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--indent",
+            "\\t",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert_eq!(args.layout.indent.as_deref(), Some("\t")),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let err = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--indent",
+            "x",
+        ])
+        .expect_err("non-whitespace indent should be rejected");
+        assert!(err.to_string().contains("spaces and tabs"), "{err}");
+    }
+
+    #[test]
+    fn parse_fix_dpr_strict_requires_report_unresolved() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--report-unresolved",
+            "--strict",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert!(args.report_unresolved && args.strict),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let err = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--strict",
+        ])
+        .expect_err("--strict needs --report-unresolved");
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn parse_dpr_write_arguments() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--write-updated-list",
+            "updated.txt",
+            "--forward-slashes",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => {
+                assert_eq!(
+                    args.dpr_write.write_updated_list.as_deref(),
+                    Some("updated.txt")
+                );
+                assert!(args.dpr_write.forward_slashes);
+                assert_eq!(args.dpr_write.lock_timeout(), Duration::from_secs(30));
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let err = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "./common/NewUnit.pas",
+            "--search-path",
+            ".",
+            "--forward-slashes",
+        ])
+        .expect_err("--forward-slashes needs --write-updated-list");
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "delete-dependency",
+            "./common/OldUnit.pas",
+            "--search-path",
+            ".",
+            "--target-path",
+            "./app1",
+            "--lock-timeout",
+            "0",
+            "--emit-patch",
+            "changes.patch",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::DeleteDependency(args) => {
+                assert_eq!(args.dpr_write.lock_timeout(), Duration::ZERO);
+                assert_eq!(args.dpr_write.emit_patch.as_deref(), Some("changes.patch"));
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn patch_path_is_relative_with_forward_slashes() {
+        let cwd = PathBuf::from("/fixdpr-missing/repo");
+        assert_eq!(
+            patch_path(&cwd.join("app1").join("App1.dpr"), &cwd),
+            "app1/App1.dpr"
+        );
+    }
+
+    #[test]
+    fn parse_on_update_and_reject_strict_without_command() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--on-update",
+            "fmt {}",
+            "--on-update-strict",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => {
+                assert_eq!(args.dpr_write.on_update.as_deref(), Some("fmt {}"));
+                assert!(args.dpr_write.on_update_strict);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let err = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--on-update-strict",
+        ])
+        .expect_err("--on-update-strict needs --on-update");
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+
+        let err = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--on-update",
+            "fmt",
+            "--emit-patch",
+            "changes.patch",
+        ])
+        .expect_err("--on-update conflicts with --emit-patch");
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn hook_command_line_substitutes_or_appends_quoted_path() {
+        assert_eq!(
+            hook_command_line("fmt --in-place {}", "/repo/App 1.dpr"),
+            "fmt --in-place '/repo/App 1.dpr'"
+        );
+        assert_eq!(
+            hook_command_line("cp {} {}.bak", "/repo/App.dpr"),
+            "cp '/repo/App.dpr' '/repo/App.dpr'.bak"
+        );
+        assert_eq!(
+            hook_command_line("fmt", "/repo/It's.dpr"),
+            r"fmt '/repo/It'\''s.dpr'"
+        );
+    }
+
+    #[test]
+    fn updated_list_has_one_path_per_line() {
+        let paths = vec![
+            PathBuf::from(r"C:\repo\app1\App1.dpr"),
+            PathBuf::from(r"\\?\C:\repo\App2.dpr"),
+        ];
+        assert_eq!(
+            updated_list_contents(&paths, false),
+            "C:\\repo\\app1\\App1.dpr\nC:\\repo\\App2.dpr\n"
+        );
+        assert_eq!(
+            updated_list_contents(&paths, true),
+            "C:/repo/app1/App1.dpr\nC:/repo/App2.dpr\n"
+        );
+        assert_eq!(updated_list_contents(&[], true), "");
+    }
+
+    #[test]
+    fn parse_fix_duplicates_policy() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--fix-duplicates",
+            "keep-resolving",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert_eq!(
+                args.fix_duplicates,
+                Some(dpr_edit::DuplicatePolicy::KeepResolving)
+            ),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let err = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--fix-duplicates",
+            "newest",
+        ])
+        .expect_err("unknown policy");
+        assert!(
+            err.to_string()
+                .contains("--fix-duplicates must be keep-first, keep-resolving, or remove-extra"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn parse_add_dependency_with_after_unit() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "./common/NewUnit.pas",
+            "--search-path",
+            ".",
+            "--after-unit",
+            "ThirdPartyMarker",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::AddDependency(args) => {
+                assert_eq!(args.after_unit.as_deref(), Some("ThirdPartyMarker"))
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reject_add_dependency_with_append_and_after_unit() {
+        let err = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "./common/NewUnit.pas",
+            "--search-path",
+            ".",
+            "--append",
+            "--after-unit",
+            "Marker",
+        ])
+        .expect_err("append conflicts with after-unit");
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn parse_fix_dpr_max_file_size_with_suffix() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--max-file-size",
+            "512kb",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert_eq!(args.common.max_file_size, 512 * 1024),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        assert_eq!(parse_file_size("16MB"), Ok(16 * 1024 * 1024));
+        assert_eq!(parse_file_size("0"), Ok(0));
+        assert!(parse_file_size("big").is_err());
+    }
+
+    #[test]
+    fn parse_jobs_requires_at_least_one_thread() {
+        let parse = |jobs: &str| {
+            Cli::try_parse_from([
+                "fixdpr",
+                "fix-dpr",
+                "./app1/App1.dpr",
+                "--search-path",
+                ".",
+                "--jobs",
+                jobs,
+            ])
+        };
+        match parse("4").expect("parse cli").command {
+            Commands::FixDpr(args) => assert_eq!(args.common.jobs, 4),
+            other => panic!("unexpected command: {other:?}"),
         }
-        return Err(format!(
-            "--target-dpr not found under --search-path after ignore filters: {}",
-            target_dpr.display()
-        ));
+        assert!(parse("0").is_err());
     }
 
-    Ok((selected, ignored))
-}
+    #[test]
+    fn parse_fix_dpr_partial_is_failure_requires_max_inserts() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec!["fixdpr", "fix-dpr", "./app1/App1.dpr", "--search-path", "."];
+            argv.extend_from_slice(extra);
+            Cli::try_parse_from(argv)
+        };
+        match parse(&["--max-inserts", "20", "--partial-is-failure"])
+            .expect("parse cli")
+            .command
+        {
+            Commands::FixDpr(args) => {
+                assert_eq!(args.max_inserts, Some(20));
+                assert!(args.partial_is_failure);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+        assert!(parse(&["--partial-is-failure"]).is_err());
+        assert!(parse(&["--max-inserts", "0"]).is_err());
+    }
 
-fn normalize_path_key(path: &Path) -> String {
-    path.to_string_lossy()
-        .replace('/', "\\")
-        .to_ascii_lowercase()
-}
+    #[test]
+    fn parse_cache_build_with_no_stem_fallback() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "cache",
+            "build",
+            "--search-path",
+            ".",
+            "--no-stem-fallback",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::Cache(args) => match args.action {
+                CacheAction::Build(build) => assert!(build.common.no_stem_fallback),
+                other => panic!("unexpected cache action: {other:?}"),
+            },
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
 
-fn display_path(path: &Path, roots: &[PathBuf]) -> String {
-    for root in roots {
-        if path.starts_with(root) {
-            return diff_paths(path, root)
-                .unwrap_or_else(|| path.to_path_buf())
-                .to_string_lossy()
-                .to_string();
+    #[test]
+    fn parse_who_uses_with_transitive_and_json_format() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "who-uses",
+            "UnitA",
+            "--search-path",
+            ".",
+            "--transitive",
+            "--format",
+            "JSON",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::WhoUses(args) => {
+                assert_eq!(args.unit.as_deref(), Some("UnitA"));
+                assert!(args.transitive);
+                assert_eq!(args.format, OutputFormat::Json);
+            }
+            other => panic!("unexpected command: {other:?}"),
         }
+
+        let err = Cli::try_parse_from(["fixdpr", "who-uses", "UnitA", "--format", "xml"])
+            .expect_err("unknown format");
+        assert!(err.to_string().contains("text or json"), "{err}");
     }
 
-    path.to_string_lossy().to_string()
-}
+    #[test]
+    fn parse_who_uses_include_replaces_unit() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "who-uses",
+            "--search-path",
+            ".",
+            "--include",
+            "shared.inc",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::WhoUses(args) => {
+                assert_eq!(args.include.as_deref(), Some("shared.inc"));
+                assert_eq!(args.unit, None);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
 
-fn dedupe_paths(paths: Vec<PathBuf>) -> Vec<PathBuf> {
-    let mut deduped = Vec::new();
-    let mut seen = HashSet::new();
+        assert!(Cli::try_parse_from(["fixdpr", "who-uses", "--search-path", "."]).is_err());
+        assert!(Cli::try_parse_from([
+            "fixdpr",
+            "who-uses",
+            "UnitA",
+            "--search-path",
+            ".",
+            "--include",
+            "shared.inc",
+        ])
+        .is_err());
+    }
 
-    for path in paths {
-        let key = normalize_path_key(&path);
-        if seen.insert(key) {
-            deduped.push(path);
+    #[test]
+    fn parse_dependents_with_direct_only_and_grouping() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "dependents",
+            "--search-path",
+            ".",
+            "MyUnit.pas",
+            "--direct-only",
+            "--group-by-dpr",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::Dependents(args) => {
+                assert_eq!(args.unit, "MyUnit.pas");
+                assert!(args.direct_only);
+                assert!(args.group_by_dpr);
+                assert_eq!(args.format, OutputFormat::Text);
+            }
+            other => panic!("unexpected command: {other:?}"),
         }
     }
 
-    deduped.sort_by_key(|path| normalize_path_key(path));
-    deduped
-}
+    #[test]
+    fn parse_fanout_with_delphi_path_and_json_format() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "fanout",
+            "--search-path",
+            ".",
+            "--delphi-path",
+            "./delphi",
+            "--format",
+            "json",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::Fanout(args) => {
+                assert_eq!(args.delphi.delphi_path, vec!["./delphi".to_string()]);
+                assert_eq!(args.format, OutputFormat::Json);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
 
-fn exit_with_error(message: impl AsRef<str>, code: i32) -> ! {
-    eprintln!("error: {}", message.as_ref());
-    process::exit(code);
-}
+    #[test]
+    fn parse_copy_uses_requires_from_and_to() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "copy-uses",
+            "--from",
+            "App.dpr",
+            "--to",
+            "AppTest.dpr",
+            "--sync",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::CopyUses(args) => {
+                assert_eq!(args.from, "App.dpr");
+                assert_eq!(args.to, "AppTest.dpr");
+                assert!(args.sync);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::{build_dependency_assumptions, Cli, DependencyAssumptionArg};
-    use crate::conditionals::AssumedValue;
-    use clap::Parser;
+        assert!(Cli::try_parse_from(["fixdpr", "copy-uses", "--from", "App.dpr"]).is_err());
+    }
 
     #[test]
-    fn parse_add_dependency_with_positional_new_dependency() {
-        let parsed = Cli::try_parse_from([
+    fn parse_impact_requires_units_or_changed_since() {
+        let cli = Cli::try_parse_from([
             "fixdpr",
-            "add-dependency",
+            "impact",
             "--search-path",
             ".",
-            "./common/NewUnit.pas",
-        ]);
+            "UnitA.pas",
+            "UnitB",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::Impact(args) => {
+                assert_eq!(args.units, vec!["UnitA.pas", "UnitB"]);
+                assert_eq!(args.changed_since, None);
+                assert_eq!(args.format, OutputFormat::Text);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
 
-        assert!(parsed.is_ok(), "{parsed:?}");
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "impact",
+            "--search-path",
+            ".",
+            "--changed-since",
+            "origin/main",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::Impact(args) => {
+                assert!(args.units.is_empty());
+                assert_eq!(args.changed_since.as_deref(), Some("origin/main"));
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        assert!(Cli::try_parse_from(["fixdpr", "impact", "--search-path", "."]).is_err());
     }
 
     #[test]
-    fn reject_legacy_new_dependency_flag() {
-        let parsed = Cli::try_parse_from([
+    fn parse_add_dependency_with_direct_only() {
+        let cli = Cli::try_parse_from([
             "fixdpr",
             "add-dependency",
+            "./common/NewUnit.pas",
             "--search-path",
             ".",
-            "--new-dependency",
-            "./common/NewUnit.pas",
-        ]);
+            "--direct-only",
+            "--disable-introduced-dependencies",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::AddDependency(args) => {
+                assert!(args.direct_only);
+                assert!(args.disable_introduced_dependencies);
+                assert!(!args.retarget_existing);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
 
-        assert!(parsed.is_err(), "legacy flag should not parse");
+    #[test]
+    fn parse_add_dependency_introduced_scope() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec![
+                "fixdpr",
+                "add-dependency",
+                "./common/NewUnit.pas",
+                "--search-path",
+                ".",
+            ];
+            argv.extend_from_slice(extra);
+            Cli::try_parse_from(argv).map(|cli| match cli.command {
+                Commands::AddDependency(args) => {
+                    introduced_scope(args.introduced_scope, args.disable_introduced_dependencies)
+                }
+                other => panic!("unexpected command: {other:?}"),
+            })
+        };
+
+        assert_eq!(parse(&[]).unwrap(), dpr_edit::IntroducedScope::All);
+        assert_eq!(
+            parse(&["--introduced-scope", "project-only"]).unwrap(),
+            dpr_edit::IntroducedScope::ProjectOnly
+        );
+        assert_eq!(
+            parse(&["--introduced-scope", "none"]).unwrap(),
+            dpr_edit::IntroducedScope::None
+        );
+        assert_eq!(
+            parse(&["--disable-introduced-dependencies"]).unwrap(),
+            dpr_edit::IntroducedScope::None
+        );
+        assert!(parse(&["--introduced-scope", "rtl"]).is_err());
+        assert!(parse(&[
+            "--introduced-scope",
+            "all",
+            "--disable-introduced-dependencies"
+        ])
+        .is_err());
     }
 
     #[test]
-    fn parse_add_dependency_with_assume() {
-        let parsed = Cli::try_parse_from([
+    fn parse_add_dependency_with_repeated_dpr_glob() {
+        let cli = Cli::try_parse_from([
             "fixdpr",
             "add-dependency",
+            "./common/NewUnit.pas",
             "--search-path",
             ".",
-            "--assume",
-            "DEBUG=on",
-            "./common/NewUnit.pas",
-        ]);
-
-        assert!(parsed.is_ok(), "{parsed:?}");
+            "--dpr-glob",
+            "apps/billing/**/*.dpr",
+            "--dpr-glob",
+            "apps/hr/*.dpr",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::AddDependency(args) => assert_eq!(
+                args.dpr_glob,
+                vec!["apps/billing/**/*.dpr", "apps/hr/*.dpr"]
+            ),
+            other => panic!("unexpected command: {other:?}"),
+        }
     }
 
     #[test]
-    fn reject_add_dependency_with_invalid_assume_value() {
-        let parsed = Cli::try_parse_from([
+    fn parse_add_dependency_with_dependency_root_depth() {
+        let cli = Cli::try_parse_from([
             "fixdpr",
             "add-dependency",
+            "../shared/Shared.pas",
             "--search-path",
             ".",
-            "--assume",
-            "DEBUG=maybe",
-            "./common/NewUnit.pas",
-        ]);
+            "--dependency-root-depth",
+            "2",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::AddDependency(args) => assert_eq!(args.dependency_root_depth, Some(2)),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
 
-        assert!(parsed.is_err(), "invalid assume value should not parse");
+    #[test]
+    fn parse_delphi_args_on_every_command_with_fallback_roots() {
+        let commands: [&[&str]; 8] = [
+            &["add-dependency", "../shared/Shared.pas"],
+            &["insert-dependency", "Shared.pas", "--target-path", "."],
+            &["delete-dependency", "Shared", "--target-path", "."],
+            &["fix-dpr", "./app1/App1.dpr"],
+            &["init", "App.dpr", "--seed", "Main.pas"],
+            &["serve"],
+            &["list-conditionals", "./app1/App1.dpr"],
+            &["fanout"],
+        ];
+        for command in commands {
+            let mut argv = vec!["fixdpr"];
+            argv.extend_from_slice(command);
+            argv.extend([
+                "--delphi-path",
+                "./delphi",
+                "--delphi-root",
+                "./bds",
+                "--delphi-root-is-source",
+                "--delphi-source-dirs",
+                "rtl,vcl",
+                "--delphi-max-depth",
+                "3",
+                "--delphi-ignore-path",
+                "**/Samples",
+            ]);
+            let cli = Cli::try_parse_from(&argv).expect("parse cli");
+            let delphi = match cli.command {
+                Commands::AddDependency(args) => args.delphi,
+                Commands::InsertDependency(args) => args.delphi,
+                Commands::DeleteDependency(args) => args.delphi,
+                Commands::FixDpr(args) => args.delphi,
+                Commands::Init(args) => args.delphi,
+                Commands::Serve(args) => args.delphi,
+                Commands::ListConditionals(args) => args.delphi,
+                Commands::Fanout(args) => args.delphi,
+                other => panic!("unexpected command: {other:?}"),
+            };
+            assert_eq!(delphi.delphi_path, vec!["./delphi"], "{command:?}");
+            assert_eq!(delphi.delphi_root, vec!["./bds"], "{command:?}");
+            assert!(delphi.delphi_root_is_source, "{command:?}");
+            assert_eq!(delphi.delphi_source_dirs, vec!["rtl", "vcl"], "{command:?}");
+            assert_eq!(delphi.delphi_max_depth, Some(3), "{command:?}");
+            assert_eq!(delphi.delphi_ignore_path, vec!["**/Samples"], "{command:?}");
+        }
     }
 
     #[test]
-    fn reject_add_dependency_with_missing_assume_assignment() {
-        let parsed = Cli::try_parse_from([
+    fn parse_delphi_library_paths_requires_delphi_version() {
+        let cli = Cli::try_parse_from([
             "fixdpr",
-            "add-dependency",
+            "fix-dpr",
+            "./app1/App1.dpr",
             "--search-path",
             ".",
-            "--assume",
-            "DEBUG",
-            "./common/NewUnit.pas",
-        ]);
+            "--delphi-version",
+            "22",
+            "--delphi-library-paths",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert!(args.delphi.delphi_library_paths),
+            other => panic!("unexpected command: {other:?}"),
+        }
 
-        assert!(
-            parsed.is_err(),
-            "missing assume assignment should not parse"
-        );
+        let invalid = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
+            "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--delphi-library-paths",
+        ]);
+        assert!(invalid.is_err(), "{invalid:?}");
     }
 
     #[test]
-    fn parse_insert_dependency_with_target_path_and_target_dpr() {
-        let parsed = Cli::try_parse_from([
+    fn parse_relative_path_limits() {
+        let cli = Cli::try_parse_from([
             "fixdpr",
-            "insert-dependency",
+            "fix-dpr",
+            "./app1/App1.dpr",
             "--search-path",
             ".",
-            "--target-path",
-            "./apps",
-            "--target-dpr",
-            "./apps/App1.dpr",
-            "./common/NewUnit.pas",
-        ]);
+            "--max-parent-dirs",
+            "2",
+            "--on-deep-relative",
+            "skip",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => {
+                assert_eq!(args.relative_paths.max_parent_dirs, Some(2));
+                assert_eq!(
+                    args.relative_paths.on_deep_relative,
+                    dpr_edit::DeepRelative::Skip
+                );
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
 
-        assert!(parsed.is_ok(), "{parsed:?}");
-    }
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "./common/NewUnit.pas",
+            "--search-path",
+            ".",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::AddDependency(args) => {
+                assert_eq!(args.relative_paths.max_parent_dirs, None);
+                assert_eq!(
+                    args.relative_paths.on_deep_relative,
+                    dpr_edit::DeepRelative::Absolute
+                );
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
 
-    #[test]
-    fn reject_insert_dependency_without_targets() {
-        let parsed = Cli::try_parse_from([
+        let invalid = Cli::try_parse_from([
             "fixdpr",
             "insert-dependency",
+            "./common/NewUnit.pas",
             "--search-path",
             ".",
-            "./common/NewUnit.pas",
+            "--target-dpr",
+            "./app1/App1.dpr",
+            "--on-deep-relative",
+            "error",
         ]);
-
-        assert!(parsed.is_err(), "insert-dependency should require a target");
+        assert!(invalid.is_err(), "{invalid:?}");
     }
 
     #[test]
-    fn parse_fix_dpr_with_positional_dpr_file() {
-        let parsed =
-            Cli::try_parse_from(["fixdpr", "fix-dpr", "--search-path", ".", "./app1/App1.dpr"]);
+    fn parse_ignore_dpr_relative_to() {
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "delete-dependency",
+            "./common/OldUnit.pas",
+            "--search-path",
+            ".",
+            "--target-path",
+            ".",
+            "--ignore-dpr",
+            "app4/*.dpr",
+            "--ignore-dpr-relative-to",
+            "search-root",
+        ])
+        .expect("parse cli");
+
+        match cli.command {
+            Commands::DeleteDependency(args) => {
+                assert_eq!(
+                    args.dpr_filter.ignore_dpr_relative_to,
+                    fs_walk::DprGlobAnchor::SearchRoot
+                );
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
 
-        assert!(parsed.is_ok(), "{parsed:?}");
+        let cli = Cli::try_parse_from([
+            "fixdpr",
+            "add-dependency",
+            "./common/NewUnit.pas",
+            "--search-path",
+            ".",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::AddDependency(args) => {
+                assert_eq!(
+                    args.dpr_filter.ignore_dpr_relative_to,
+                    fs_walk::DprGlobAnchor::Cwd
+                );
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
     }
 
     #[test]
-    fn parse_fix_dpr_with_assume() {
-        let parsed = Cli::try_parse_from([
+    fn parse_cross_drive_for_every_dpr_editing_command() {
+        let cli = Cli::try_parse_from([
             "fixdpr",
-            "fix-dpr",
+            "insert-dependency",
+            "./common/NewUnit.pas",
             "--search-path",
             ".",
-            "--assume",
-            "TRACE=off",
+            "--target-dpr",
             "./app1/App1.dpr",
-        ]);
+            "--cross-drive",
+            "skip",
+        ])
+        .expect("parse cli");
 
-        assert!(parsed.is_ok(), "{parsed:?}");
-    }
+        match cli.command {
+            Commands::InsertDependency(args) => {
+                assert_eq!(args.relative_paths.cross_drive, dpr_edit::CrossDrive::Skip);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
 
-    #[test]
-    fn reject_ignore_dpr_in_fix_dpr_mode() {
-        let parsed = Cli::try_parse_from([
+        for command in [
+            &["fix-dpr", "./app1/App1.dpr", "--search-path", "."][..],
+            &[
+                "init",
+                "./app1/App1.dpr",
+                "--search-path",
+                ".",
+                "--seed",
+                "a.pas",
+            ],
+            &["copy-uses", "--from", "a.dpr", "--to", "b.dpr"],
+        ] {
+            let cli = Cli::try_parse_from(
+                ["fixdpr"]
+                    .iter()
+                    .chain(command)
+                    .chain(&["--cross-drive", "error"]),
+            )
+            .expect("parse cli");
+            let relative_paths = match cli.command {
+                Commands::FixDpr(args) => args.relative_paths,
+                Commands::Init(args) => args.relative_paths,
+                Commands::CopyUses(args) => args.relative_paths,
+                other => panic!("unexpected command: {other:?}"),
+            };
+            assert_eq!(relative_paths.cross_drive, dpr_edit::CrossDrive::Error);
+        }
+
+        let invalid = Cli::try_parse_from([
             "fixdpr",
-            "fix-dpr",
+            "add-dependency",
+            "./common/NewUnit.pas",
             "--search-path",
             ".",
-            "./app1/App1.dpr",
-            "--ignore-dpr",
-            "./app1/App1.dpr",
+            "--cross-drive",
+            "relative",
         ]);
-
-        assert!(
-            parsed.is_err(),
-            "--ignore-dpr should not parse in fix-dpr mode"
-        );
+        assert!(invalid.is_err(), "{invalid:?}");
     }
 
     #[test]
-    fn parse_list_conditionals_with_positional_dpr_file() {
-        let parsed = Cli::try_parse_from([
+    fn parse_suppress_codes_and_reject_unknown_code() {
+        let cli = Cli::try_parse_from([
             "fixdpr",
-            "list-conditionals",
+            "fix-dpr",
+            "./app1/App1.dpr",
             "--search-path",
             ".",
+            "--suppress",
+            "MissingInPath",
+            "--suppress",
+            "ambiguous-unit",
+        ])
+        .expect("parse cli");
+        match cli.command {
+            Commands::FixDpr(args) => assert_eq!(
+                args.common.suppress,
+                vec![DiagnosticCode::MissingInPath, DiagnosticCode::AmbiguousUnit]
+            ),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let invalid = Cli::try_parse_from([
+            "fixdpr",
+            "fix-dpr",
             "./app1/App1.dpr",
+            "--search-path",
+            ".",
+            "--suppress",
+            "NoSuchCode",
         ]);
-
-        assert!(parsed.is_ok(), "{parsed:?}");
+        assert!(invalid.is_err(), "{invalid:?}");
     }
 
     #[test]
@@ -1762,7 +6854,8 @@ mod tests {
         ])
         .expect_err("conflicting duplicate assumptions should fail");
 
-        assert!(err.contains("conflicts"), "{err}");
+        assert!(matches!(err, FixdprError::InvalidArgument(_)), "{err}");
+        assert!(err.to_string().contains("conflicts"), "{err}");
     }
 
     #[test]
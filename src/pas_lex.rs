@@ -45,6 +45,70 @@ pub fn skip_string(bytes: &[u8], mut i: usize) -> usize {
     bytes.len()
 }
 
+/// Where scanning continues after a comment or string literal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Skipped {
+    Closed(usize),
+    /// The construct never closed; scanning resumes at the start of the next line.
+    Unterminated(usize),
+}
+
+impl Skipped {
+    pub fn next(self) -> usize {
+        match self {
+            Self::Closed(next) | Self::Unterminated(next) => next,
+        }
+    }
+}
+
+/// Skips the `{ }` or `(* *)` comment at `start`, giving up at the next line if it never closes.
+pub fn skip_comment_or_recover(bytes: &[u8], start: usize) -> Skipped {
+    let (end, closed) = if bytes[start] == b'{' {
+        let end = skip_brace_comment(bytes, start + 1);
+        (end, end > start + 1 && bytes[end - 1] == b'}')
+    } else {
+        let end = skip_paren_comment(bytes, start + 2);
+        (end, end >= start + 4 && bytes[..end].ends_with(b"*)"))
+    };
+    if closed {
+        Skipped::Closed(end)
+    } else {
+        Skipped::Unterminated(next_line_start(bytes, start))
+    }
+}
+
+/// Skips the string literal at `start`, giving up at the next line if it crosses a line break.
+///
+/// `'''` followed by a line break opens a multiline literal that ends at the next `'''`.
+pub fn skip_string_or_recover(bytes: &[u8], start: usize) -> Skipped {
+    if bytes[start..].starts_with(b"'''") && matches!(bytes.get(start + 3), Some(b'\r' | b'\n')) {
+        return match bytes[start + 3..]
+            .windows(3)
+            .position(|window| window == b"'''")
+        {
+            Some(pos) => Skipped::Closed(start + 3 + pos + 3),
+            None => Skipped::Unterminated(next_line_start(bytes, start)),
+        };
+    }
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' if bytes.get(i + 1) == Some(&b'\'') => i += 2,
+            b'\'' => return Skipped::Closed(i + 1),
+            b'\r' | b'\n' => return Skipped::Unterminated(next_line_start(bytes, start)),
+            _ => i += 1,
+        }
+    }
+    Skipped::Unterminated(bytes.len())
+}
+
+fn next_line_start(bytes: &[u8], i: usize) -> usize {
+    bytes[i..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |pos| i + pos + 1)
+}
+
 pub fn read_string_literal(bytes: &[u8], start: usize) -> Option<(String, usize)> {
     if bytes.get(start) != Some(&b'\'') {
         return None;
@@ -147,7 +211,8 @@ fn parse_compiler_directive_inner(
         "I" | "INCLUDE" => {
             i = skip_ws(bytes, i);
             let (filename, next) = read_directive_filename(bytes, i, end)?;
-            i = skip_ws(bytes, next);
+            // Anything after the filename, such as a trailing comment, is ignored.
+            i = skip_to_comment_end(bytes, next, end);
             CompilerDirective::Include(filename)
         }
         "IFDEF" => {
@@ -225,7 +290,8 @@ fn read_directive_filename(bytes: &[u8], mut i: usize, end: CommentEnd) -> Optio
     if value.is_empty() {
         return None;
     }
-    if value == "+" || value == "-" {
+    // `{$I+}`, `{$I-}` and `{$I+,R-}` are the I/O checking switch, not an include.
+    if value.starts_with(['+', '-']) {
         return None;
     }
     Some((value, i))
@@ -287,10 +353,7 @@ fn read_directive_symbol(bytes: &[u8], i: usize, _end: CommentEnd) -> Option<(St
     }
 
     let start = i;
-    let mut next = i + 1;
-    while next < bytes.len() && is_ident_continue(bytes[next]) {
-        next += 1;
-    }
+    let next = ident_end(bytes, i);
     if next == start {
         return None;
     }
@@ -300,23 +363,130 @@ fn read_directive_symbol(bytes: &[u8], i: usize, _end: CommentEnd) -> Option<(St
     ))
 }
 
-pub fn read_ident(bytes: &[u8], mut i: usize) -> (String, usize) {
-    let start = i;
-    i += 1;
-    while i < bytes.len() && is_ident_continue(bytes[i]) {
-        i += 1;
-    }
-    (String::from_utf8_lossy(&bytes[start..i]).to_string(), i)
+pub fn read_ident(bytes: &[u8], i: usize) -> (String, usize) {
+    let end = ident_end(bytes, i);
+    (String::from_utf8_lossy(&bytes[i..end]).to_string(), end)
 }
 
+/// Reads a dotted name such as `System.SysUtils`; a trailing dot is never part of it.
 pub fn read_ident_with_dots(bytes: &[u8], i: usize) -> (String, usize) {
-    read_ident(bytes, i)
+    let (name, next) = read_ident(bytes, i);
+    let trimmed = name.trim_end_matches('.');
+    let next = next - (name.len() - trimmed.len());
+    (trimmed.to_string(), next)
 }
 
+/// End of the identifier starting at `i`. A dot is only taken when an
+/// identifier starts right after it, so `end.` and `Foo..Bar` stop at the dot.
+fn ident_end(bytes: &[u8], mut i: usize) -> usize {
+    i += 1;
+    while i < bytes.len() {
+        if bytes[i] == b'.' {
+            match bytes.get(i + 1) {
+                Some(&next) if is_ident_start(next) => i += 2,
+                _ => break,
+            }
+        } else if is_ident_continue(bytes[i]) {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+/// Every non-ASCII byte counts as an identifier byte, so UTF-8 encoded
+/// Unicode identifiers are read whole.
 pub fn is_ident_start(byte: u8) -> bool {
-    byte.is_ascii_alphabetic() || byte == b'_'
+    byte.is_ascii_alphabetic() || byte == b'_' || !byte.is_ascii()
 }
 
 pub fn is_ident_continue(byte: u8) -> bool {
-    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.'
+    byte.is_ascii_alphanumeric() || byte == b'_' || !byte.is_ascii()
+}
+
+/// Offset of the first byte after a leading UTF-8 byte order mark.
+///
+/// Scanners start here so the mark is not read as part of the first identifier.
+pub fn skip_bom(bytes: &[u8]) -> usize {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        3
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_ident_stops_at_a_statement_terminator() {
+        assert_eq!(read_ident(b"end.", 0), ("end".to_string(), 3));
+        assert_eq!(read_ident(b"end.\n", 0), ("end".to_string(), 3));
+        assert_eq!(read_ident_with_dots(b"Foo.;", 0), ("Foo".to_string(), 3));
+        assert_eq!(read_ident_with_dots(b"Foo.", 0), ("Foo".to_string(), 3));
+    }
+
+    #[test]
+    fn read_ident_with_dots_reads_namespaced_names() {
+        assert_eq!(
+            read_ident_with_dots(b"System.SysUtils, Foo", 0),
+            ("System.SysUtils".to_string(), 15)
+        );
+        assert_eq!(
+            read_ident_with_dots(b"Vcl.Forms.Controls in", 0),
+            ("Vcl.Forms.Controls".to_string(), 18)
+        );
+    }
+
+    #[test]
+    fn read_ident_does_not_join_names_across_two_dots() {
+        assert_eq!(read_ident_with_dots(b"Foo..Bar", 0), ("Foo".to_string(), 3));
+        assert_eq!(read_ident_with_dots(b"Foo.1", 0), ("Foo".to_string(), 3));
+    }
+
+    fn include_name(text: &[u8]) -> Option<String> {
+        parse_include_directive(text, 0).map(|(name, end)| {
+            assert_eq!(end, text.len());
+            name
+        })
+    }
+
+    #[test]
+    fn include_directive_accepts_quoted_filenames_with_spaces() {
+        assert_eq!(
+            include_name(b"{$INCLUDE 'uses list.inc'}").as_deref(),
+            Some("uses list.inc")
+        );
+        assert_eq!(
+            include_name(b"(*$I 'uses list.inc'*)").as_deref(),
+            Some("uses list.inc")
+        );
+    }
+
+    #[test]
+    fn include_directive_ignores_text_after_the_filename() {
+        assert_eq!(
+            include_name(b"{$I uses.inc // shared units}").as_deref(),
+            Some("uses.inc")
+        );
+        assert_eq!(
+            include_name(b"{$INCLUDE 'uses list.inc' shared units}").as_deref(),
+            Some("uses list.inc")
+        );
+        assert_eq!(
+            include_name(b"(*$I uses.inc extra *)").as_deref(),
+            Some("uses.inc")
+        );
+    }
+
+    #[test]
+    fn include_directive_rejects_io_checking_switches() {
+        assert_eq!(include_name(b"{$I+}"), None);
+        assert_eq!(include_name(b"{$I-}"), None);
+        assert_eq!(include_name(b"{$I- }"), None);
+        assert_eq!(include_name(b"{$I+,R-}"), None);
+        assert_eq!(include_name(b"(*$I-*)"), None);
+    }
 }
@@ -0,0 +1,786 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::pas_lex;
+use crate::unit_cache::canonicalize_if_exists;
+use crate::uses_include;
+use crate::warnings::Warnings;
+
+/// Part of a unit a scanner is in, switched by the `interface` and
+/// `implementation` keywords.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Section {
+    None,
+    Interface,
+    Implementation,
+}
+
+impl Section {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Interface => "interface",
+            Self::Implementation => "implementation",
+        }
+    }
+}
+
+/// A unit name with its lowercase lookup key, computed once. Clones share
+/// both strings, and [`UnitNames`] makes every occurrence of a spelling in a
+/// cache share them too.
+///
+/// Names compare by spelling, like the strings they stand for; compare
+/// [`UnitName::key`] to match them regardless of case.
+#[derive(Clone)]
+pub struct UnitName {
+    spelling: Arc<str>,
+    /// The same allocation as `spelling` when the name has no uppercase letters.
+    key: Arc<str>,
+}
+
+impl UnitName {
+    pub fn new(spelling: &str) -> Self {
+        let spelling: Arc<str> = Arc::from(spelling);
+        let key = if spelling.bytes().any(|byte| byte.is_ascii_uppercase()) {
+            Arc::from(spelling.to_ascii_lowercase())
+        } else {
+            Arc::clone(&spelling)
+        };
+        Self { spelling, key }
+    }
+
+    /// The name as written.
+    pub fn as_str(&self) -> &str {
+        &self.spelling
+    }
+
+    /// The lowercase form used for case-insensitive lookups.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl PartialEq for UnitName {
+    fn eq(&self, other: &Self) -> bool {
+        self.spelling == other.spelling
+    }
+}
+
+impl Eq for UnitName {}
+
+impl Hash for UnitName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.spelling.hash(state);
+    }
+}
+
+impl Borrow<str> for UnitName {
+    fn borrow(&self) -> &str {
+        &self.spelling
+    }
+}
+
+/// The unit names of one cache, each spelling stored once.
+#[derive(Debug, Default)]
+pub struct UnitNames {
+    names: HashSet<UnitName>,
+}
+
+impl UnitNames {
+    /// The stored name spelled like `name`, which is stored on first sight.
+    pub fn intern(&mut self, name: &UnitName) -> UnitName {
+        if let Some(known) = self.names.get(name.as_str()) {
+            return known.clone();
+        }
+        self.names.insert(name.clone());
+        name.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl From<&str> for UnitName {
+    fn from(spelling: &str) -> Self {
+        Self::new(spelling)
+    }
+}
+
+impl From<String> for UnitName {
+    fn from(spelling: String) -> Self {
+        Self::new(&spelling)
+    }
+}
+
+impl PartialEq<str> for UnitName {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for UnitName {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for UnitName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for UnitName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// A unit named by a `uses` clause of a unit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsesRef {
+    pub name: UnitName,
+    pub section: Section,
+    /// The `in` path literal as written, without quotes.
+    pub in_path: Option<String>,
+}
+
+impl UsesRef {
+    pub fn new(name: impl Into<UnitName>, section: Section) -> Self {
+        Self {
+            name: name.into(),
+            section,
+            in_path: None,
+        }
+    }
+}
+
+/// The section `token` starts, if it is a section keyword.
+pub fn section_keyword(token: &str) -> Option<Section> {
+    if token.eq_ignore_ascii_case("interface") {
+        Some(Section::Interface)
+    } else if token.eq_ignore_ascii_case("implementation") {
+        Some(Section::Implementation)
+    } else {
+        None
+    }
+}
+
+/// Supplies the unit names listed in a `{$I}` file met inside a uses clause.
+pub trait IncludeSource {
+    fn include_entries(
+        &mut self,
+        include_name: &str,
+        source_path: &Path,
+        directive_offset: usize,
+        warnings: &mut Warnings,
+    ) -> Vec<String>;
+}
+
+/// Treats every `{$I}` directive as a comment.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoIncludes;
+
+impl IncludeSource for NoIncludes {
+    fn include_entries(
+        &mut self,
+        _include_name: &str,
+        _source_path: &Path,
+        _directive_offset: usize,
+        _warnings: &mut Warnings,
+    ) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Reads `{$I}` files through [`uses_include`], warning about missing files and cycles.
+#[derive(Debug)]
+pub struct ResolveIncludes<'a> {
+    includes: &'a uses_include::Includes,
+    include_stack: Vec<PathBuf>,
+}
+
+impl<'a> ResolveIncludes<'a> {
+    pub fn new(source_path: &Path, includes: &'a uses_include::Includes) -> Self {
+        Self {
+            includes,
+            include_stack: vec![canonicalize_if_exists(source_path)],
+        }
+    }
+}
+
+impl IncludeSource for ResolveIncludes<'_> {
+    fn include_entries(
+        &mut self,
+        include_name: &str,
+        source_path: &Path,
+        directive_offset: usize,
+        warnings: &mut Warnings,
+    ) -> Vec<String> {
+        uses_include::with_memoized_include(
+            include_name,
+            source_path,
+            directive_offset,
+            self.includes,
+            warnings,
+            &mut self.include_stack,
+            "uses-names",
+            (),
+            |include_path, bytes, warnings, include_stack| {
+                let mut nested = ResolveIncludes {
+                    includes: self.includes,
+                    include_stack: std::mem::take(include_stack),
+                };
+                let mut entries = Vec::new();
+                let mut fragment = UsesFragment {
+                    source_path: include_path,
+                    section: Section::None,
+                    includes: &mut nested,
+                    warnings,
+                    deps: &mut entries,
+                };
+                let _ = parse_uses_fragment(bytes, pas_lex::skip_bom(bytes), &mut fragment);
+                *include_stack = nested.include_stack;
+                entries
+                    .into_iter()
+                    .map(|entry| entry.name.to_string())
+                    .collect()
+            },
+        )
+        .unwrap_or_default()
+    }
+}
+
+/// Name declared by the `unit` header, skipping comments and strings.
+pub fn parse_unit_name(bytes: &[u8]) -> Option<String> {
+    let mut i = pas_lex::skip_bom(bytes);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => {
+                i = pas_lex::skip_comment_or_recover(bytes, i).next();
+            }
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_comment_or_recover(bytes, i).next();
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = pas_lex::skip_line_comment(bytes, i + 2);
+            }
+            b'\'' => {
+                i = pas_lex::skip_string_or_recover(bytes, i).next();
+            }
+            byte if pas_lex::is_ident_start(byte) => {
+                let (token, next) = pas_lex::read_ident(bytes, i);
+                if token.eq_ignore_ascii_case("unit") {
+                    if let Some(name) = parse_unit_name_after(bytes, next) {
+                        return Some(name);
+                    }
+                }
+                i = next;
+            }
+            _ => {
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+fn parse_unit_name_after(bytes: &[u8], mut i: usize) -> Option<String> {
+    i = pas_lex::skip_ws_and_comments(bytes, i);
+    if i >= bytes.len() || !pas_lex::is_ident_start(bytes[i]) {
+        return None;
+    }
+    let (name, _) = pas_lex::read_ident_with_dots(bytes, i);
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+pub fn unit_name_from_stem(path: &Path) -> Option<String> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(|stem| stem.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Units named by every `uses` clause in the interface and implementation
+/// sections, ignoring compiler conditions.
+pub fn parse_unit_uses(
+    path: &Path,
+    bytes: &[u8],
+    includes: &mut dyn IncludeSource,
+    warnings: &mut Warnings,
+) -> Vec<UsesRef> {
+    let mut deps = Vec::new();
+    let mut i = pas_lex::skip_bom(bytes);
+    let mut section = Section::None;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => {
+                i = pas_lex::skip_brace_comment(bytes, i + 1);
+            }
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_paren_comment(bytes, i + 2);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = pas_lex::skip_line_comment(bytes, i + 2);
+            }
+            b'\'' => {
+                i = pas_lex::skip_string(bytes, i + 1);
+            }
+            byte if pas_lex::is_ident_start(byte) => {
+                let (token, next) = pas_lex::read_ident(bytes, i);
+                if let Some(keyword) = section_keyword(&token) {
+                    section = keyword;
+                } else if token.eq_ignore_ascii_case("uses") && section != Section::None {
+                    let mut fragment = UsesFragment {
+                        source_path: path,
+                        section,
+                        includes: &mut *includes,
+                        warnings: &mut *warnings,
+                        deps: &mut deps,
+                    };
+                    let (next_i, _) = parse_uses_fragment(bytes, next, &mut fragment);
+                    i = next_i;
+                    continue;
+                }
+                i = next;
+            }
+            _ => {
+                i += 1;
+            }
+        }
+    }
+
+    deps
+}
+
+/// Where [`parse_uses_fragment`] is reading and what it collects into.
+struct UsesFragment<'a> {
+    source_path: &'a Path,
+    section: Section,
+    includes: &'a mut dyn IncludeSource,
+    warnings: &'a mut Warnings,
+    deps: &'a mut Vec<UsesRef>,
+}
+
+fn parse_uses_fragment(
+    bytes: &[u8],
+    mut i: usize,
+    fragment: &mut UsesFragment<'_>,
+) -> (usize, bool) {
+    loop {
+        i = skip_ws_comments_and_includes(bytes, i, fragment);
+        if i >= bytes.len() {
+            return (i, false);
+        }
+        if bytes[i] == b';' {
+            return (i + 1, true);
+        }
+        if !pas_lex::is_ident_start(bytes[i]) {
+            i += 1;
+            continue;
+        }
+        let (name, next) = pas_lex::read_ident_with_dots(bytes, i);
+        i = next;
+        i = pas_lex::skip_ws_and_comments(bytes, i);
+
+        let mut in_path = None;
+        if let Some((token, next_token)) = peek_ident(bytes, i) {
+            if token.eq_ignore_ascii_case("in") {
+                i = skip_to_in_path(bytes, next_token);
+                if let Some((path, end)) = pas_lex::read_string_literal(bytes, i) {
+                    in_path = Some(path);
+                    i = end;
+                }
+            }
+        }
+        if !name.is_empty() {
+            fragment.deps.push(UsesRef {
+                name: UnitName::new(&name),
+                section: fragment.section,
+                in_path,
+            });
+        }
+
+        let (pos, delim) = scan_to_delimiter_with_includes(bytes, i, fragment);
+        i = pos;
+        match delim {
+            Some(b',') => i += 1,
+            Some(b';') => return (i + 1, true),
+            _ => return (i, false),
+        }
+    }
+}
+
+/// Like [`pas_lex::skip_ws_and_comments`], but stops at the `'` opening the path.
+fn skip_to_in_path(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
+            b'{' => i = pas_lex::skip_brace_comment(bytes, i + 1),
+            b'(' if bytes.get(i + 1) == Some(&b'*') => {
+                i = pas_lex::skip_paren_comment(bytes, i + 2)
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            _ => break,
+        }
+    }
+    i
+}
+
+fn peek_ident(bytes: &[u8], i: usize) -> Option<(String, usize)> {
+    if i < bytes.len() && pas_lex::is_ident_start(bytes[i]) {
+        let (token, next) = pas_lex::read_ident(bytes, i);
+        return Some((token, next));
+    }
+    None
+}
+
+fn scan_to_delimiter_with_includes(
+    bytes: &[u8],
+    mut i: usize,
+    fragment: &mut UsesFragment<'_>,
+) -> (usize, Option<u8>) {
+    while i < bytes.len() {
+        match bytes[i] {
+            b',' | b';' => return (i, Some(bytes[i])),
+            b'{' | b'(' => i = skip_comment_or_include(bytes, i, fragment),
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            _ => i += 1,
+        }
+    }
+    (i, None)
+}
+
+fn skip_ws_comments_and_includes(
+    bytes: &[u8],
+    mut i: usize,
+    fragment: &mut UsesFragment<'_>,
+) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
+            b'{' | b'(' => i = skip_comment_or_include(bytes, i, fragment),
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            _ => break,
+        }
+    }
+    i
+}
+
+/// Skips the comment or `(` at `i`, adding the entries of a `{$I}` directive.
+fn skip_comment_or_include(bytes: &[u8], i: usize, fragment: &mut UsesFragment<'_>) -> usize {
+    if let Some((include_name, end)) = pas_lex::parse_include_directive(bytes, i) {
+        let names = fragment.includes.include_entries(
+            &include_name,
+            fragment.source_path,
+            i,
+            fragment.warnings,
+        );
+        let section = fragment.section;
+        fragment
+            .deps
+            .extend(names.into_iter().map(|name| UsesRef::new(name, section)));
+        return end;
+    }
+    if bytes[i] == b'{' {
+        pas_lex::skip_brace_comment(bytes, i + 1)
+    } else if bytes.get(i + 1) == Some(&b'*') {
+        pas_lex::skip_paren_comment(bytes, i + 2)
+    } else {
+        i + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn unit_names_carry_their_lowercase_key() {
+        let mixed = UnitName::new("System.SysUtils");
+        let lower = UnitName::new("system.sysutils");
+
+        assert_eq!(mixed, UnitName::new("System.SysUtils"));
+        assert_ne!(mixed, lower);
+        assert_eq!(mixed.as_str(), "System.SysUtils");
+        assert_eq!(mixed.key(), "system.sysutils");
+        assert_eq!(mixed.key(), lower.key());
+        assert_eq!(lower.to_string(), "system.sysutils");
+        assert_eq!(format!("{mixed:?}"), "\"System.SysUtils\"");
+    }
+
+    #[test]
+    fn unit_names_share_allocations_between_clones_and_lowercase_keys() {
+        let mixed = UnitName::new("System.SysUtils");
+        let lower = UnitName::new("system.sysutils");
+
+        assert!(Arc::ptr_eq(&lower.spelling, &lower.key));
+        assert!(!Arc::ptr_eq(&mixed.spelling, &mixed.key));
+        assert!(Arc::ptr_eq(&mixed.clone().key, &mixed.key));
+    }
+
+    #[test]
+    fn section_keyword_matches_case_insensitively() {
+        assert_eq!(section_keyword("INTERFACE"), Some(Section::Interface));
+        assert_eq!(
+            section_keyword("Implementation"),
+            Some(Section::Implementation)
+        );
+        assert_eq!(section_keyword("uses"), None);
+    }
+
+    #[test]
+    fn parse_unit_name_basic() {
+        let src = b"unit Foo.Bar;\ninterface\nimplementation\nend.";
+        assert_eq!(parse_unit_name(src), Some("Foo.Bar".to_string()));
+    }
+
+    #[test]
+    fn parse_unit_name_reads_unicode_identifiers_after_a_bom() {
+        let src = "\u{FEFF}unit 数据.模块;\ninterface\nimplementation\nend.";
+        assert_eq!(
+            parse_unit_name(src.as_bytes()),
+            Some("数据.模块".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_unit_name_ignores_comments() {
+        let src = br#"
+{ unit Wrong; }
+(* unit AlsoWrong; *)
+// unit NoWay;
+unit RealUnit;
+interface
+implementation
+end.
+"#;
+        assert_eq!(parse_unit_name(src), Some("RealUnit".to_string()));
+    }
+
+    #[test]
+    fn parse_unit_name_ignores_strings() {
+        let src = b"const S = 'unit Fake;';\nunit Real;\ninterface\nend.";
+        assert_eq!(parse_unit_name(src), Some("Real".to_string()));
+    }
+
+    #[test]
+    fn parse_unit_name_skips_unterminated_comment_lines() {
+        let src = b"{ header without end\nunit Real;\ninterface\nend.";
+        assert_eq!(parse_unit_name(src), Some("Real".to_string()));
+    }
+
+    #[test]
+    fn parse_unit_name_allows_ifdef_blocks_braces() {
+        let src = br#"
+{$IFDEF FOO}
+{$IFDEF BAR}
+{$ENDIF}
+{$ENDIF}
+unit Real;
+"#;
+        assert_eq!(parse_unit_name(src), Some("Real".to_string()));
+    }
+
+    #[test]
+    fn parse_unit_name_allows_ifdef_blocks_paren() {
+        let src = br#"
+(*$IFDEF FOO*)
+unit Conditional;
+(*$ENDIF*)
+"#;
+        assert_eq!(parse_unit_name(src), Some("Conditional".to_string()));
+    }
+
+    #[test]
+    fn parse_unit_name_allows_nested_ifdefs() {
+        let src = br#"
+{$IFDEF OUTER}
+{$IFNDEF INNER}
+unit NestedUnit;
+{$ENDIF}
+{$ENDIF}
+"#;
+        assert_eq!(parse_unit_name(src), Some("NestedUnit".to_string()));
+    }
+
+    #[test]
+    fn parse_unit_name_allows_if_and_ifopt_markers() {
+        let src = br#"
+{$IFDEF ENABLED}
+{$IF 1}
+{$IFOPT N+}
+unit OptUnit;
+{$ENDIF}
+{$ENDIF}
+{$ENDIF}
+"#;
+        assert_eq!(parse_unit_name(src), Some("OptUnit".to_string()));
+    }
+
+    #[test]
+    fn unit_name_from_stem_trims_and_rejects_empty_stems() {
+        assert_eq!(
+            unit_name_from_stem(Path::new("dir/ Demo .pas")),
+            Some("Demo".to_string())
+        );
+        assert_eq!(unit_name_from_stem(Path::new("dir/ .pas")), None);
+    }
+
+    #[test]
+    fn parse_unit_uses_in_interface_and_implementation() {
+        let src = br#"
+unit Demo;
+interface
+uses Foo, Bar;
+implementation
+uses Baz;
+end.
+"#;
+        let deps = parse_unit_uses(
+            &temp_dir().join("Demo.pas"),
+            src,
+            &mut NoIncludes,
+            &mut Warnings::new(),
+        );
+        assert_eq!(
+            deps,
+            vec![
+                UsesRef::new("Foo", Section::Interface),
+                UsesRef::new("Bar", Section::Interface),
+                UsesRef::new("Baz", Section::Implementation),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_unit_uses_keeps_unicode_names_mid_list() {
+        let src =
+            "unit Foo;\ninterface\nuses Alpha, 数据模块, Ünits.Größe, Omega;\nimplementation\nend.";
+        assert_eq!(
+            parse_uses_for_test(src.as_bytes()),
+            vec!["Alpha", "数据模块", "Ünits.Größe", "Omega"]
+        );
+    }
+
+    #[test]
+    fn parse_unit_uses_ignores_comments_and_strings() {
+        let src = br#"
+unit Demo;
+interface
+uses Foo, {Bar}, (*Baz*), // Quux
+  'NotAUnit', RealUnit;
+implementation
+uses ImplUnit;
+end.
+"#;
+        let deps = parse_uses_for_test(src);
+        assert_eq!(deps, vec!["Foo", "RealUnit", "ImplUnit"]);
+    }
+
+    #[test]
+    fn parse_unit_uses_allows_directives_inside_list() {
+        let src = br#"
+unit Demo;
+interface
+uses Foo, {$IFDEF X} Bar, {$ENDIF} Baz;
+implementation
+uses Qux;
+end.
+"#;
+        let deps = parse_uses_for_test(src);
+        assert_eq!(deps, vec!["Foo", "Bar", "Baz", "Qux"]);
+    }
+
+    #[test]
+    fn parse_unit_uses_with_in_paths() {
+        let src = br#"
+unit Demo;
+interface
+uses Foo in 'Foo.pas', Bar in 'path\Bar.pas';
+implementation
+end.
+"#;
+        let deps = parse_unit_uses(
+            &temp_dir().join("Demo.pas"),
+            src,
+            &mut NoIncludes,
+            &mut Warnings::new(),
+        );
+        let in_paths: Vec<_> = deps.iter().map(|dep| dep.in_path.as_deref()).collect();
+        assert_eq!(in_paths, vec![Some("Foo.pas"), Some("path\\Bar.pas")]);
+    }
+
+    #[test]
+    fn parse_unit_uses_supports_include_fragments() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        let include_path = root.join("Uses.inc");
+        fs::write(&include_path, "Foo,\nBar in 'lib\\\\Bar.pas',\nBaz,").unwrap();
+        let src = br#"
+unit Demo;
+interface
+uses {$I Uses.inc} Qux;
+implementation
+end.
+"#;
+        let mut warnings = Warnings::new();
+        let deps = parse_unit_uses(
+            &unit_path,
+            src,
+            &mut ResolveIncludes::new(&unit_path, &uses_include::Includes::default()),
+            &mut warnings,
+        );
+        let names: Vec<&str> = deps.iter().map(|dep| dep.name.as_str()).collect();
+        assert_eq!(names, vec!["Foo", "Bar", "Baz", "Qux"]);
+        assert!(deps.iter().all(|dep| dep.section == Section::Interface));
+
+        let deps = parse_unit_uses(&unit_path, src, &mut NoIncludes, &mut warnings);
+        assert_eq!(deps, vec![UsesRef::new("Qux", Section::Interface)]);
+        assert!(warnings.is_empty());
+    }
+
+    fn temp_dir() -> PathBuf {
+        let mut root = env::temp_dir();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        root.push(format!("fixdpr_pas_parse_{nanos}"));
+        fs::create_dir_all(&root).expect("create temp dir");
+        root
+    }
+
+    fn parse_uses_for_test(src: &[u8]) -> Vec<String> {
+        let unit_path = temp_dir().join("Demo.pas");
+        let mut warnings = Warnings::new();
+        parse_unit_uses(
+            &unit_path,
+            src,
+            &mut ResolveIncludes::new(&unit_path, &uses_include::Includes::default()),
+            &mut warnings,
+        )
+        .into_iter()
+        .map(|dep| dep.name.to_string())
+        .collect()
+    }
+}
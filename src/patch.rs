@@ -0,0 +1,221 @@
+use std::ops::Range;
+
+/// Lines of unchanged context around each hunk, as `git diff` writes them.
+const CONTEXT: usize = 3;
+
+/// Largest number of line pairs compared exactly; bigger changes are written
+/// as one replaced block, which is still a valid patch.
+const MAX_COMPARED_PAIRS: usize = 4_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Git-style unified diff turning `old` into `new` for the file at `path`.
+///
+/// `path` is written as given after `a/` and `b/`, so pass it relative to the
+/// folder `git apply` runs in, with `/` separators. Bytes are copied as they
+/// are, so CRLF line ends and non-UTF-8 text survive the round trip. Returns
+/// nothing when both sides are equal.
+pub fn file_diff(path: &str, old: &[u8], new: &[u8]) -> Vec<u8> {
+    if old == new {
+        return Vec::new();
+    }
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(format!("diff --git a/{path} b/{path}\n").as_bytes());
+    out.extend_from_slice(format!("--- a/{path}\n+++ b/{path}\n").as_bytes());
+    for hunk in hunks(&ops) {
+        write_hunk(&mut out, &ops[hunk], &old_lines, &new_lines);
+    }
+    out
+}
+
+/// Splits `bytes` after each `\n`; the last line may lack one.
+fn split_lines(bytes: &[u8]) -> Vec<&[u8]> {
+    bytes.split_inclusive(|byte| *byte == b'\n').collect()
+}
+
+/// Edit script between the two line lists, with their position before each op.
+fn diff_lines(old: &[&[u8]], new: &[&[u8]]) -> Vec<(Op, usize, usize)> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(left, right)| left == right)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(left, right)| left == right)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    for index in 0..prefix {
+        ops.push((Op::Equal, index, index));
+    }
+    for (op, old_index, new_index) in diff_middle(old_mid, new_mid) {
+        ops.push((op, prefix + old_index, prefix + new_index));
+    }
+    for offset in 0..suffix {
+        ops.push((
+            Op::Equal,
+            old.len() - suffix + offset,
+            new.len() - suffix + offset,
+        ));
+    }
+    ops
+}
+
+/// Longest common subsequence diff of the changed middle part.
+fn diff_middle(old: &[&[u8]], new: &[&[u8]]) -> Vec<(Op, usize, usize)> {
+    let (rows, cols) = (old.len(), new.len());
+    if rows.saturating_mul(cols) > MAX_COMPARED_PAIRS {
+        let deletes = (0..rows).map(|index| (Op::Delete, index, 0));
+        let inserts = (0..cols).map(|index| (Op::Insert, rows, index));
+        return deletes.chain(inserts).collect();
+    }
+    // common[i][j] is the LCS length of old[i..] and new[j..].
+    let mut common = vec![0u32; (rows + 1) * (cols + 1)];
+    let at = |i: usize, j: usize| i * (cols + 1) + j;
+    for i in (0..rows).rev() {
+        for j in (0..cols).rev() {
+            common[at(i, j)] = if old[i] == new[j] {
+                common[at(i + 1, j + 1)] + 1
+            } else {
+                common[at(i + 1, j)].max(common[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(rows + cols);
+    let (mut i, mut j) = (0, 0);
+    while i < rows || j < cols {
+        if i < rows && j < cols && old[i] == new[j] {
+            ops.push((Op::Equal, i, j));
+            i += 1;
+            j += 1;
+        } else if j == cols || (i < rows && common[at(i + 1, j)] >= common[at(i, j + 1)]) {
+            ops.push((Op::Delete, i, j));
+            i += 1;
+        } else {
+            ops.push((Op::Insert, i, j));
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// Ranges of `ops` that form one hunk each, changes plus their context.
+fn hunks(ops: &[(Op, usize, usize)]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for (index, (op, _, _)) in ops.iter().enumerate() {
+        if *op == Op::Equal {
+            continue;
+        }
+        let start = index.saturating_sub(CONTEXT);
+        let end = (index + 1 + CONTEXT).min(ops.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => ranges.push(start..end),
+        }
+    }
+    ranges
+}
+
+fn write_hunk(out: &mut Vec<u8>, ops: &[(Op, usize, usize)], old: &[&[u8]], new: &[&[u8]]) {
+    let (_, old_start, new_start) = ops[0];
+    let old_len = ops.iter().filter(|(op, _, _)| *op != Op::Insert).count();
+    let new_len = ops.iter().filter(|(op, _, _)| *op != Op::Delete).count();
+    out.extend_from_slice(
+        format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        )
+        .as_bytes(),
+    );
+    for &(op, old_index, new_index) in ops {
+        let (marker, line) = match op {
+            Op::Equal => (b' ', old[old_index]),
+            Op::Delete => (b'-', old[old_index]),
+            Op::Insert => (b'+', new[new_index]),
+        };
+        out.push(marker);
+        out.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            out.extend_from_slice(b"\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+/// `start,len` with a 1-based start; an empty range names the line before it.
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str) -> String {
+        String::from_utf8(file_diff("app/App.dpr", old.as_bytes(), new.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn file_diff_writes_git_headers_and_context() {
+        let old = "program App;\nuses\n  A,\n  B;\nbegin\nend.\n";
+        let new = "program App;\nuses\n  A,\n  C,\n  B;\nbegin\nend.\n";
+        assert_eq!(
+            diff(old, new),
+            "diff --git a/app/App.dpr b/app/App.dpr\n\
+             --- a/app/App.dpr\n\
+             +++ b/app/App.dpr\n\
+             @@ -1,6 +1,7 @@\n \
+             program App;\n \
+             uses\n   \
+             A,\n\
+             +  C,\n   \
+             B;\n \
+             begin\n \
+             end.\n"
+        );
+        assert!(diff(old, old).is_empty());
+    }
+
+    #[test]
+    fn file_diff_splits_distant_changes_into_hunks() {
+        let old: String = (1..=20).map(|line| format!("line {line}\n")).collect();
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 18\n", "line eighteen\n");
+        let patch = diff(&old, &new);
+        let headers: Vec<&str> = patch
+            .lines()
+            .filter(|line| line.starts_with("@@"))
+            .collect();
+        assert_eq!(headers, vec!["@@ -1,5 +1,5 @@", "@@ -15,6 +15,6 @@"]);
+    }
+
+    #[test]
+    fn file_diff_marks_a_missing_final_newline() {
+        let patch = diff("uses A;\r\nbegin end.", "uses A, B;\r\nbegin end.");
+        assert!(patch.contains("-uses A;\r\n+uses A, B;\r\n"), "{patch:?}");
+        assert!(
+            patch.ends_with(" begin end.\n\\ No newline at end of file\n"),
+            "{patch:?}"
+        );
+    }
+}
@@ -0,0 +1,827 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::cache_file::{self, CacheFile};
+use crate::conditionals::Assumptions;
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::dpr_edit::{self, DprUpdateSummary, FixOptions, UpdateOptions, WhoUsesReport};
+use crate::error::FixdprError;
+use crate::events::{EventSink, NoEvents};
+use crate::fs_walk::{self, FsScan, IgnoreMatcher, ScanOptions};
+use crate::unit_cache::{
+    self, KnownUnits, LazyUnitCache, Prefetched, SourceOptions, UnitCache, UnitFileInfo,
+};
+use crate::uses_include::{self, Includes};
+use crate::warnings::Warnings;
+
+/// How the search roots are scanned and their units cached.
+#[derive(Clone, Debug)]
+pub struct ProjectOptions {
+    scan: ScanOptions,
+    jobs: usize,
+    cache_file: Option<PathBuf>,
+    no_cache: bool,
+    read_only: bool,
+}
+
+impl Default for ProjectOptions {
+    fn default() -> Self {
+        Self {
+            scan: ScanOptions::default(),
+            jobs: 1,
+            cache_file: None,
+            no_cache: false,
+            read_only: false,
+        }
+    }
+}
+
+impl ProjectOptions {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    pub fn scan_options(mut self, value: ScanOptions) -> Self {
+        self.scan = value;
+        self
+    }
+
+    /// Parse units on this many threads while the scan is still walking.
+    pub fn jobs(mut self, value: usize) -> Self {
+        self.jobs = value;
+        self
+    }
+
+    /// Keep the persistent unit cache here instead of in the first search root.
+    pub fn cache_file(mut self, value: Option<PathBuf>) -> Self {
+        self.cache_file = value;
+        self
+    }
+
+    /// Parse every unit instead of reusing the persistent unit cache.
+    pub fn no_cache(mut self, value: bool) -> Self {
+        self.no_cache = value;
+        self
+    }
+
+    /// The run modifies nothing on disk, so [`save_cache_file`] leaves the
+    /// default cache file alone. A [`ProjectOptions::cache_file`] is still saved.
+    pub fn read_only(mut self, value: bool) -> Self {
+        self.read_only = value;
+        self
+    }
+
+    pub fn build(self) -> Self {
+        self
+    }
+
+    pub fn scan(&self) -> &ScanOptions {
+        &self.scan
+    }
+
+    pub fn source(&self) -> SourceOptions {
+        self.scan.source()
+    }
+
+    /// The persistent unit cache file, even when [`ProjectOptions::no_cache`] is set.
+    pub fn cache_path(&self, search_roots: &[PathBuf], cwd: &Path) -> PathBuf {
+        match &self.cache_file {
+            Some(path) => path.clone(),
+            None => search_roots
+                .first()
+                .map_or(cwd, PathBuf::as_path)
+                .join(cache_file::DEFAULT_CACHE_FILE_NAME),
+        }
+    }
+
+    /// Stamps of the units the persistent cache would reuse, so the parse
+    /// workers skip them.
+    fn cached_unit_stamps(
+        &self,
+        search_roots: &[PathBuf],
+        cwd: &Path,
+    ) -> HashMap<PathBuf, (u64, u128)> {
+        if self.no_cache {
+            return HashMap::new();
+        }
+        // Load warnings are reported when the cache build reads the file again.
+        CacheFile::load(&self.cache_path(search_roots, cwd), &mut Warnings::new())
+            .unit_stamps(self.source())
+    }
+}
+
+/// Walks `search_roots` for `.pas`, `.dpr`, and `.inc` files.
+///
+/// With more than one job, units are parsed in the background while the walk
+/// runs and returned in [`FsScan::prefetched`] for the unit cache build.
+pub fn scan(
+    options: &ProjectOptions,
+    search_roots: &[PathBuf],
+    cwd: &Path,
+    ignore: &IgnoreMatcher,
+    events: &dyn EventSink,
+    warnings: &mut Warnings,
+) -> Result<FsScan, FixdprError> {
+    let pool = (options.jobs > 1).then(|| {
+        unit_cache::ParsePool::start(
+            options.jobs,
+            options.source(),
+            options.cached_unit_stamps(search_roots, cwd),
+        )
+    });
+    let scanned =
+        fs_walk::scan_files_streaming(search_roots, ignore, &options.scan, events, &mut |path| {
+            if let Some(pool) = &pool {
+                pool.submit(path);
+            }
+        });
+    let prefetched = pool.map(unit_cache::ParsePool::finish);
+    let mut scan = scanned?;
+    warnings.extend(std::mem::take(&mut scan.warnings));
+    if let Some(prefetched) = prefetched {
+        scan.prefetched = prefetched;
+    }
+    Ok(scan)
+}
+
+/// Scans the folder of a new dependency that lies outside every known root,
+/// returning that folder and its units.
+pub fn scan_dependency_root(
+    options: &ProjectOptions,
+    known_roots: &[PathBuf],
+    new_dependency_path: &Path,
+    max_depth: Option<usize>,
+    ignore: &IgnoreMatcher,
+    warnings: &mut Warnings,
+) -> Result<Option<(PathBuf, Vec<PathBuf>)>, FixdprError> {
+    let path = unit_cache::canonicalize_if_exists(new_dependency_path);
+    if known_roots.iter().any(|root| path.starts_with(root)) {
+        return Ok(None);
+    }
+    let Some(root) = path.parent().map(Path::to_path_buf) else {
+        return Ok(None);
+    };
+    let scan_options = options.scan.clone().max_depth(max_depth);
+    let mut scan = fs_walk::scan_files_with(
+        std::slice::from_ref(&root),
+        ignore,
+        &scan_options,
+        &NoEvents,
+    )?;
+    warnings.extend(std::mem::take(&mut scan.warnings));
+    Ok(Some((root, scan.pas_files)))
+}
+
+/// Parses `pas_files` into the project unit cache, reusing the persistent
+/// cache file unless [`ProjectOptions::no_cache`] is set.
+///
+/// `{$I}` directives that name no file next to their source resolve by name
+/// among `inc_files`, and units in `prefetched` are not parsed again. The
+/// cache file is returned unsaved so callers can record more in it first.
+#[allow(clippy::too_many_arguments)]
+pub fn load_unit_cache(
+    options: &ProjectOptions,
+    search_roots: &[PathBuf],
+    cwd: &Path,
+    pas_files: &[PathBuf],
+    inc_files: &[PathBuf],
+    prefetched: &Prefetched,
+    known_units: KnownUnits,
+    events: &dyn EventSink,
+    warnings: &mut Warnings,
+) -> Result<(UnitCache, Option<CacheFile>), FixdprError> {
+    let includes = Includes::from_paths(inc_files);
+    if options.no_cache {
+        let mut unit_cache = unit_cache::build_unit_cache_with_events(
+            pas_files,
+            options.source(),
+            includes,
+            prefetched,
+            warnings,
+            events,
+        )?;
+        unit_cache.known_units = known_units;
+        return Ok((unit_cache, None));
+    }
+
+    let cache_path = options.cache_path(search_roots, cwd);
+    let mut cache_file = CacheFile::load(&cache_path, warnings);
+    let mut unit_cache = cache_file.build_unit_cache_with_events(
+        pas_files,
+        options.source(),
+        includes,
+        prefetched,
+        warnings,
+        events,
+    )?;
+    unit_cache.known_units = known_units;
+    Ok((unit_cache, Some(cache_file)))
+}
+
+/// Writes `cache_file` back, warning instead of failing when that is not possible.
+///
+/// Nothing is written for a [`ProjectOptions::read_only`] run that did not
+/// name its cache file.
+pub fn save_cache_file(
+    options: &ProjectOptions,
+    cache_file: Option<&CacheFile>,
+    warnings: &mut Warnings,
+) {
+    let Some(cache_file) = cache_file else {
+        return;
+    };
+    if options.read_only && options.cache_file.is_none() {
+        return;
+    }
+    if let Err(err) = cache_file.save() {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::WriteFailed,
+                format!(
+                    "failed to write cache file {}: {err}",
+                    cache_file.path().display()
+                ),
+            )
+            .at(cache_file.path()),
+        );
+    }
+}
+
+/// The deferred cache of the Delphi fallback roots, skipping `ignore` and
+/// the default junk folders.
+pub fn lazy_delphi_unit_cache(
+    delphi_roots: &[PathBuf],
+    max_depth: Option<usize>,
+    ignore: IgnoreMatcher,
+    source: SourceOptions,
+) -> Option<LazyUnitCache> {
+    if delphi_roots.is_empty() {
+        return None;
+    }
+    let scan_options = ScanOptions::builder()
+        .max_depth(max_depth)
+        .max_file_size(source.max_file_size)
+        .stem_fallback(source.stem_fallback)
+        .build();
+    Some(
+        LazyUnitCache::new(delphi_roots.to_vec())
+            .with_scan_options(scan_options)
+            .with_ignore(ignore),
+    )
+}
+
+/// Treats a `.pas` argument as a file and anything else as a unit name.
+pub fn resolve_unit_name(
+    value: &str,
+    cwd: &Path,
+    source: SourceOptions,
+    warnings: &mut Warnings,
+) -> Result<String, FixdprError> {
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Err(FixdprError::invalid_argument("UNIT cannot be empty"));
+    }
+    if !fs_walk::is_pas_file(Path::new(trimmed)) {
+        return Ok(trimmed.to_string());
+    }
+    let path = fs_walk::resolve_path_with_flag(trimmed, cwd, "UNIT")?;
+    if !path.is_file() {
+        return Err(FixdprError::PathNotFound {
+            flag: "UNIT".to_string(),
+            path,
+        });
+    }
+    match unit_cache::load_unit_file_with(&path, source, warnings) {
+        Ok(Some(info)) => Ok(info.name),
+        Ok(None) => Err(FixdprError::Failed(format!(
+            "could not determine the unit name of {}",
+            path.display()
+        ))),
+        Err(err) => Err(FixdprError::io_at(&path, err)),
+    }
+}
+
+/// The files that pull in one include file through `{$I}` directives, by kind.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct IncludeUsers {
+    pub dprs: Vec<PathBuf>,
+    pub units: Vec<PathBuf>,
+    pub includes: Vec<PathBuf>,
+}
+
+impl IncludeUsers {
+    pub fn is_empty(&self) -> bool {
+        self.dprs.is_empty() && self.units.is_empty() && self.includes.is_empty()
+    }
+}
+
+/// Lists the scanned files that pull in `include_path` through `{$I}` directives.
+pub fn include_users(scan: &FsScan, include_path: &Path) -> IncludeUsers {
+    let sources: Vec<PathBuf> = scan
+        .dpr_files
+        .iter()
+        .chain(&scan.pas_files)
+        .chain(&scan.inc_files)
+        .cloned()
+        .collect();
+    let includes = Includes::from_paths(&scan.inc_files);
+    let users = uses_include::IncludeUsers::build(&sources, &includes).users(include_path);
+    let of_kind = |extension: &str| -> Vec<PathBuf> {
+        users
+            .iter()
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+            })
+            .cloned()
+            .collect()
+    };
+    IncludeUsers {
+        dprs: of_kind("dpr"),
+        units: of_kind("pas"),
+        includes: of_kind("inc"),
+    }
+}
+
+/// How [`Project::reload`] changed the known files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReloadStatus {
+    /// The file was known and read again.
+    Reloaded,
+    Added,
+    Removed,
+    /// The file was neither known nor present.
+    Unchanged,
+}
+
+impl ReloadStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Reloaded => "reloaded",
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// Totals of [`Project::fix_updated_dprs`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FixPass {
+    pub scanned: usize,
+    pub updated: usize,
+    pub failures: usize,
+}
+
+/// The dprs an add-dependency run can skip because the cache file saw the
+/// same dpr contents processed with the same unit, options, and units before.
+pub struct UnchangedDprs {
+    params_hash: Option<u64>,
+    max_file_size: u64,
+    record: bool,
+}
+
+impl UnchangedDprs {
+    /// Hashes everything besides the dpr contents that decides how
+    /// [`Project::update`] and the optional fix pass change a dpr.
+    ///
+    /// Without a cache file nothing is skipped or recorded, and a dry run
+    /// records nothing since its dprs are still in their old state.
+    pub fn new(
+        project: &Project,
+        cache_file: Option<&CacheFile>,
+        new_unit: &UnitFileInfo,
+        options: &UpdateOptions,
+        fix_pass: Option<&FixOptions>,
+    ) -> Self {
+        let params_hash = cache_file.map(|cache_file| {
+            cache_file::content_hash(
+                format!(
+                    "{}\n{}\n{}\n{:?}\n{}\n{}\n{}\n{:?}\n{:?}\n{:?}\n{}",
+                    env!("CARGO_PKG_VERSION"),
+                    new_unit.name,
+                    new_unit.path.display(),
+                    new_unit.conditional_uses,
+                    options.outcome_key(),
+                    fix_pass.map(FixOptions::outcome_key).unwrap_or_default(),
+                    project.assumptions.outcome_key(),
+                    project.unit_cache.known_units.names(),
+                    project
+                        .delphi_cache
+                        .as_ref()
+                        .map(LazyUnitCache::outcome_key),
+                    project.unit_cache.source,
+                    cache_file.unit_fingerprint()
+                )
+                .as_bytes(),
+            )
+        });
+        Self {
+            params_hash,
+            max_file_size: project.unit_cache.source.max_file_size,
+            record: !options.is_dry_run(),
+        }
+    }
+
+    /// Splits `dprs` into the ones to update and the number skipped.
+    ///
+    /// Oversized and unreadable dprs are never skipped; the update pass
+    /// reports them.
+    pub fn skip(&self, cache_file: Option<&CacheFile>, dprs: &[PathBuf]) -> (Vec<PathBuf>, usize) {
+        let (Some(cache_file), Some(params_hash)) = (cache_file, self.params_hash) else {
+            return (dprs.to_vec(), 0);
+        };
+        let mut skipped = 0;
+        let mut to_update = Vec::new();
+        for path in dprs {
+            if let Ok(Some(bytes)) =
+                unit_cache::read_source_file(path, self.max_file_size, &mut Warnings::new())
+            {
+                if cache_file.is_dpr_unchanged(path, cache_file::content_hash(&bytes), params_hash)
+                {
+                    skipped += 1;
+                    continue;
+                }
+            }
+            to_update.push(path.clone());
+        }
+        (to_update, skipped)
+    }
+
+    /// Records the current contents of every dpr in `dprs` that `summary`
+    /// did not fail on, so the next identical run skips it.
+    pub fn record(
+        &self,
+        cache_file: Option<&mut CacheFile>,
+        dprs: &[PathBuf],
+        summary: &DprUpdateSummary,
+    ) {
+        let (Some(cache_file), Some(params_hash), true) =
+            (cache_file, self.params_hash, self.record)
+        else {
+            return;
+        };
+        for path in dprs {
+            if fs_walk::contains_path(&summary.failed_paths, path) {
+                continue;
+            }
+            if let Ok(bytes) = std::fs::read(path) {
+                cache_file.record_dpr_unchanged(
+                    path,
+                    cache_file::content_hash(&bytes),
+                    params_hash,
+                );
+            }
+        }
+    }
+}
+
+/// A scanned project with its unit caches, ready for the dpr operations.
+pub struct Project {
+    pub cwd: PathBuf,
+    pub search_roots: Vec<PathBuf>,
+    /// The Delphi fallback roots, including library search paths.
+    pub delphi_roots: Vec<PathBuf>,
+    pub ignore: IgnoreMatcher,
+    pub scan: FsScan,
+    pub unit_cache: UnitCache,
+    pub delphi_cache: Option<LazyUnitCache>,
+    pub assumptions: Assumptions,
+}
+
+impl Project {
+    /// The search roots followed by the Delphi fallback roots.
+    pub fn known_roots(&self) -> Vec<PathBuf> {
+        [self.search_roots.as_slice(), self.delphi_roots.as_slice()].concat()
+    }
+
+    /// Fails unless the scan found `dpr`, naming it after `flag`.
+    pub fn check_dpr(&self, dpr: &Path, flag: &str) -> Result<(), FixdprError> {
+        if fs_walk::contains_path(&self.scan.dpr_files, dpr) {
+            return Ok(());
+        }
+        Err(FixdprError::invalid_argument(format!(
+            "{flag} not found under --search-path after ignore filters: {}",
+            dpr.display()
+        )))
+    }
+
+    /// Inserts the units `dpr` is missing; see [`dpr_edit::fix_dpr_file_with`].
+    pub fn fix(&self, dpr: &Path, options: &FixOptions) -> Result<DprUpdateSummary, FixdprError> {
+        Ok(dpr_edit::fix_dpr_file_with(
+            dpr,
+            &self.unit_cache,
+            self.delphi_cache.as_ref(),
+            &self.assumptions,
+            options,
+        )?)
+    }
+
+    /// Adds `new_unit` to `dprs`; see [`dpr_edit::update_dpr_files_with`].
+    pub fn update(
+        &mut self,
+        dprs: &[PathBuf],
+        new_unit: &UnitFileInfo,
+        options: &UpdateOptions,
+    ) -> Result<DprUpdateSummary, FixdprError> {
+        Ok(dpr_edit::update_dpr_files_with(
+            dprs,
+            &mut self.unit_cache,
+            self.delphi_cache.as_ref(),
+            new_unit,
+            &self.assumptions,
+            options,
+        )?)
+    }
+
+    /// Runs [`Project::fix`] on every dpr `summary` updated, starting from the
+    /// staged contents of a dry run, and merges the results into `summary`.
+    ///
+    /// A dpr that cannot be fixed counts as a failure with a
+    /// [`DiagnosticCode::FixFailed`] warning instead of stopping the pass.
+    pub fn fix_updated_dprs(&self, summary: &mut DprUpdateSummary, options: FixOptions) -> FixPass {
+        let mut pass = FixPass::default();
+        let updated_paths = summary.updated_paths.clone();
+        let options = options.staged(summary.staged.clone());
+        for dpr_path in &updated_paths {
+            let fix_summary = match self.fix(dpr_path, &options) {
+                Ok(fix_summary) => fix_summary,
+                Err(err) => {
+                    let failure = Diagnostic::warning(
+                        DiagnosticCode::FixFailed,
+                        format!("failed to run fix-dpr on {}: {err}", dpr_path.display()),
+                    )
+                    .at(dpr_path);
+                    let mut detail =
+                        dpr_edit::DprDetail::new(dpr_path, dpr_edit::DprStatus::Failed);
+                    detail.warnings.push(failure.clone());
+                    summary.merge_detail(detail);
+                    summary.warnings.push(failure);
+                    pass.failures += 1;
+                    summary.failed_paths.push(dpr_path.clone());
+                    continue;
+                }
+            };
+            pass.scanned += fix_summary.scanned;
+            pass.updated += fix_summary.updated;
+            pass.failures += fix_summary.failures;
+            summary.suppressed_delphi_inserts += fix_summary.suppressed_delphi_inserts;
+            summary.graph_time += fix_summary.graph_time;
+            summary.write_time += fix_summary.write_time;
+            summary.staged.extend(fix_summary.staged);
+            summary.warnings.extend(fix_summary.warnings);
+            summary.infos.extend(fix_summary.infos);
+            for detail in fix_summary.details {
+                summary.merge_detail(detail);
+            }
+            if fix_summary.failures > 0 {
+                summary.failed_paths.push(dpr_path.clone());
+            }
+            for path in fix_summary.updated_paths {
+                if !fs_walk::contains_path(&summary.updated_paths, &path) {
+                    summary.updated_paths.push(path);
+                }
+            }
+        }
+        summary.updated = summary.updated_paths.len();
+        summary.failures += pass.failures;
+        summary.sort_paths();
+        pass
+    }
+
+    /// The dprs that use `unit_name`; see [`dpr_edit::who_uses`].
+    pub fn who_uses(
+        &mut self,
+        unit_name: &str,
+        transitive: bool,
+    ) -> Result<WhoUsesReport, FixdprError> {
+        Ok(dpr_edit::who_uses(
+            &self.scan.dpr_files,
+            &mut self.unit_cache,
+            None,
+            unit_name,
+            &self.assumptions,
+            transitive,
+        )?)
+    }
+
+    /// Brings the scan and the unit cache up to date after `path` changed,
+    /// was added, or was deleted.
+    ///
+    /// A `.pas` file is parsed again, a `.dpr` file is added to or dropped
+    /// from the known dprs, and an `.inc` file re-parses the units that
+    /// include it.
+    pub fn reload(
+        &mut self,
+        path: &Path,
+        warnings: &mut Warnings,
+    ) -> Result<ReloadStatus, FixdprError> {
+        self.unit_cache.canonical.forget(path);
+        let path = self.unit_cache.canonical.canonicalize(path);
+        if !self.search_roots.iter().any(|root| path.starts_with(root)) {
+            return Err(FixdprError::invalid_argument(format!(
+                "{} is outside every --search-path",
+                path.display()
+            )));
+        }
+        let exists = path.is_file() && !self.ignore.is_ignored(&path);
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "pas" => Ok(self.reload_unit(&path, exists, warnings)?),
+            "dpr" => Ok(track_file(&mut self.scan.dpr_files, &path, exists)),
+            "inc" => {
+                let status = track_file(&mut self.scan.inc_files, &path, exists);
+                self.reload_include_users(&path, warnings)?;
+                Ok(status)
+            }
+            _ => Err(FixdprError::invalid_argument(
+                "reload supports .pas, .dpr, and .inc files",
+            )),
+        }
+    }
+
+    fn reload_unit(
+        &mut self,
+        path: &Path,
+        exists: bool,
+        warnings: &mut Warnings,
+    ) -> io::Result<ReloadStatus> {
+        let known = unit_cache::remove_unit(&mut self.unit_cache, path).is_some();
+        let info = if exists {
+            self.unit_cache.load_unit(path, warnings)?
+        } else {
+            None
+        };
+        track_file(&mut self.scan.pas_files, path, info.is_some());
+        Ok(match info {
+            Some(info) => {
+                unit_cache::insert_unit(&mut self.unit_cache, path.to_path_buf(), info);
+                if known {
+                    ReloadStatus::Reloaded
+                } else {
+                    ReloadStatus::Added
+                }
+            }
+            None if known => ReloadStatus::Removed,
+            None => ReloadStatus::Unchanged,
+        })
+    }
+
+    /// Re-parses the units that pull in `include_path` through `{$I}`.
+    fn reload_include_users(
+        &mut self,
+        include_path: &Path,
+        warnings: &mut Warnings,
+    ) -> io::Result<()> {
+        self.unit_cache.includes = Rc::new(Includes::from_paths(&self.scan.inc_files));
+        let sources = [self.scan.pas_files.as_slice(), &self.scan.inc_files].concat();
+        let users = uses_include::IncludeUsers::build(&sources, &self.unit_cache.includes)
+            .users(include_path);
+        for unit in users.into_iter().filter(|path| fs_walk::is_pas_file(path)) {
+            self.reload_unit(&unit, unit.is_file(), warnings)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds `path` to `files` when it exists and drops it otherwise.
+fn track_file(files: &mut Vec<PathBuf>, path: &Path, exists: bool) -> ReloadStatus {
+    let known = files.iter().position(|known| known == path);
+    match (known, exists) {
+        (Some(_), true) => ReloadStatus::Reloaded,
+        (None, true) => {
+            files.push(path.to_path_buf());
+            files.sort();
+            ReloadStatus::Added
+        }
+        (Some(index), false) => {
+            files.remove(index);
+            ReloadStatus::Removed
+        }
+        (None, false) => ReloadStatus::Unchanged,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_file_reports_how_the_known_files_changed() {
+        let mut files = vec![PathBuf::from("b.dpr")];
+        assert_eq!(
+            track_file(&mut files, Path::new("a.dpr"), true),
+            ReloadStatus::Added
+        );
+        assert_eq!(files, vec![PathBuf::from("a.dpr"), PathBuf::from("b.dpr")]);
+        assert_eq!(
+            track_file(&mut files, Path::new("a.dpr"), true),
+            ReloadStatus::Reloaded
+        );
+        assert_eq!(
+            track_file(&mut files, Path::new("b.dpr"), false),
+            ReloadStatus::Removed
+        );
+        assert_eq!(
+            track_file(&mut files, Path::new("c.dpr"), false),
+            ReloadStatus::Unchanged
+        );
+        assert_eq!(files, vec![PathBuf::from("a.dpr")]);
+    }
+
+    #[test]
+    fn cache_path_defaults_to_the_first_search_root() {
+        let cwd = PathBuf::from("work");
+        let roots = [PathBuf::from("a"), PathBuf::from("b")];
+        let options = ProjectOptions::builder().build();
+        assert_eq!(
+            options.cache_path(&roots, &cwd),
+            roots[0].join(cache_file::DEFAULT_CACHE_FILE_NAME)
+        );
+        assert_eq!(
+            options.cache_path(&[], &cwd),
+            cwd.join(cache_file::DEFAULT_CACHE_FILE_NAME)
+        );
+        let explicit = ProjectOptions::builder()
+            .cache_file(Some(PathBuf::from("units.cache")))
+            .no_cache(true)
+            .build();
+        assert_eq!(
+            explicit.cache_path(&roots, &cwd),
+            PathBuf::from("units.cache")
+        );
+    }
+
+    #[test]
+    fn unchanged_dprs_skip_what_an_identical_run_recorded() {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let root = std::env::temp_dir().join(format!("fixdpr_project_test_{nanos}"));
+        std::fs::create_dir_all(&root).unwrap();
+        let dprs = [root.join("a.dpr"), root.join("b.dpr")];
+        for dpr in &dprs {
+            std::fs::write(dpr, "program P;\nbegin\nend.\n").unwrap();
+        }
+        let ignore = IgnoreMatcher::default();
+        let project = Project {
+            cwd: root.clone(),
+            search_roots: vec![root.clone()],
+            delphi_roots: Vec::new(),
+            scan: fs_walk::scan_files(std::slice::from_ref(&root), &ignore).unwrap(),
+            ignore,
+            unit_cache: UnitCache::default(),
+            delphi_cache: None,
+            assumptions: Assumptions::default(),
+        };
+        let new_unit = UnitFileInfo {
+            name: "NewUnit".to_string(),
+            path: root.join("NewUnit.pas"),
+            uses: Vec::new(),
+            conditional_uses: Vec::new(),
+            interface_uses: 0,
+            name_from_stem: false,
+        };
+        let mut cache_file = CacheFile::load(&root.join("units.cache"), &mut Warnings::new());
+        let options = UpdateOptions::builder().build();
+        let unchanged = UnchangedDprs::new(&project, Some(&cache_file), &new_unit, &options, None);
+        assert_eq!(unchanged.skip(Some(&cache_file), &dprs), (dprs.to_vec(), 0));
+
+        let mut summary = DprUpdateSummary::new();
+        summary.failed_paths.push(dprs[1].clone());
+        unchanged.record(Some(&mut cache_file), &dprs, &summary);
+        assert_eq!(
+            unchanged.skip(Some(&cache_file), &dprs),
+            (vec![dprs[1].clone()], 1)
+        );
+
+        let with_fix_pass = UnchangedDprs::new(
+            &project,
+            Some(&cache_file),
+            &new_unit,
+            &options,
+            Some(&FixOptions::for_updated_dprs(&options)),
+        );
+        assert_eq!(
+            with_fix_pass.skip(Some(&cache_file), &dprs),
+            (dprs.to_vec(), 0)
+        );
+
+        std::fs::write(&dprs[0], "program P;\nuses NewUnit;\nbegin\nend.\n").unwrap();
+        assert_eq!(unchanged.skip(Some(&cache_file), &dprs), (dprs.to_vec(), 0));
+
+        let dry_run = UpdateOptions::builder()
+            .edit(dpr_edit::EditOptions::builder().dry_run(true))
+            .build();
+        let staged = UnchangedDprs::new(&project, Some(&cache_file), &new_unit, &dry_run, None);
+        staged.record(Some(&mut cache_file), &dprs, &DprUpdateSummary::new());
+        assert_eq!(unchanged.skip(Some(&cache_file), &dprs), (dprs.to_vec(), 0));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
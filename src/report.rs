@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+
+use pathdiff::diff_paths;
+
+use crate::diagnostic::DiagnosticCode;
+use crate::dpr_edit::{DprDetail, WhoUsesReport};
+use crate::unit_cache;
+use crate::warnings::Warnings;
+
+/// How a report shows warnings, details, and paths.
+pub struct ReportDisplay<'a> {
+    pub show_warnings: bool,
+    pub show_details: bool,
+    pub dedupe_warnings: bool,
+    pub show_all_referencers: bool,
+    pub suppressed: &'a [DiagnosticCode],
+    pub paths: DisplayPaths,
+}
+
+/// Shortens paths under the search roots to paths relative to their root.
+#[derive(Clone, Debug)]
+pub struct DisplayPaths {
+    roots: Vec<PathBuf>,
+    prefixes: Vec<String>,
+}
+
+impl DisplayPaths {
+    /// With `absolute`, every path is shown in full.
+    pub fn new(search_roots: &[PathBuf], absolute: bool) -> Self {
+        if absolute {
+            return Self {
+                roots: Vec::new(),
+                prefixes: Vec::new(),
+            };
+        }
+        let mut roots = Vec::new();
+        for root in search_roots {
+            roots.push(root.clone());
+            let canonical = unit_cache::canonicalize_if_exists(root);
+            if canonical != *root {
+                roots.push(canonical);
+            }
+        }
+        let mut prefixes: Vec<String> = roots
+            .iter()
+            .map(|root| {
+                let mut prefix = root.to_string_lossy().to_string();
+                if !prefix.ends_with(std::path::MAIN_SEPARATOR) {
+                    prefix.push(std::path::MAIN_SEPARATOR);
+                }
+                prefix
+            })
+            .collect();
+        prefixes.sort_by_key(|prefix| std::cmp::Reverse(prefix.len()));
+        Self { roots, prefixes }
+    }
+
+    pub fn path(&self, path: &Path) -> String {
+        plain_path(Path::new(&display_path(path, &self.roots)))
+    }
+
+    /// Shortens every search root path that appears in `text`, such as a warning message.
+    pub fn text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for prefix in &self.prefixes {
+            text = text.replace(prefix.as_str(), "");
+        }
+        unit_cache::strip_verbatim_prefixes(&text)
+    }
+}
+
+/// `path` without the Windows verbatim prefix.
+pub fn plain_path(path: &Path) -> String {
+    unit_cache::strip_verbatim_prefix(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn display_path(path: &Path, roots: &[PathBuf]) -> String {
+    for root in roots {
+        if path.starts_with(root) {
+            return diff_paths(path, root)
+                .unwrap_or_else(|| path.to_path_buf())
+                .to_string_lossy()
+                .to_string();
+        }
+    }
+
+    path.to_string_lossy().to_string()
+}
+
+/// One `fix-dpr --format jsonl` object, also the result of the `serve` fix methods.
+/// With `staged_only` nothing was written, so updated dprs are `would-update`.
+pub fn dpr_detail_json(
+    detail: &DprDetail,
+    staged_only: bool,
+    display: &ReportDisplay<'_>,
+) -> String {
+    let detail_warnings = detail
+        .warnings
+        .iter()
+        .filter(|warning| !display.suppressed.contains(&warning.code))
+        .map(|warning| display.paths.text(&warning.to_string()));
+    let missing: Vec<String> = detail
+        .inserted
+        .iter()
+        .map(|unit| {
+            format!(
+                "{{\"unit\":{},\"path\":{},\"in_path\":{},\"source\":{},\"chain\":{}}}",
+                json_string(&unit.name),
+                unit.path
+                    .as_deref()
+                    .map_or("null".to_string(), |path| json_string(
+                        &display.paths.path(path)
+                    )),
+                unit.in_path
+                    .as_deref()
+                    .map_or("null".to_string(), json_string),
+                unit.source
+                    .map_or("null".to_string(), |source| json_string(source.label())),
+                json_array(unit.chain.iter().cloned())
+            )
+        })
+        .collect();
+    let missing_entries: Vec<String> = detail
+        .missing_entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{{\"unit\":{},\"in_path\":{},\"removed\":{}}}",
+                json_string(&entry.name),
+                json_string(&entry.in_path),
+                entry.removed
+            )
+        })
+        .collect();
+    format!(
+        "{{\"dpr\":{},\"status\":{},\"missing\":[{}],\"not_inserted\":{},\"missing_entries\":[{}],\"warnings\":{}}}",
+        json_string(&display.paths.path(&detail.path)),
+        json_string(if staged_only {
+            detail.status.staged_label()
+        } else {
+            detail.status.label()
+        }),
+        missing.join(","),
+        detail.not_inserted,
+        missing_entries.join(","),
+        json_array(detail_warnings)
+    )
+}
+
+/// The members of a `who-uses --format json` report, also the result of the
+/// `serve` whoUses method.
+pub fn who_uses_fields(
+    report: &WhoUsesReport,
+    unit_name: &str,
+    transitive: bool,
+    warnings: &Warnings,
+    display: &ReportDisplay<'_>,
+) -> Vec<String> {
+    let paths = |paths: &[PathBuf]| json_array(paths.iter().map(|path| display.paths.path(path)));
+    let mut fields = vec![
+        format!("\"unit\":{}", json_string(unit_name)),
+        format!("\"direct_dprs\":{}", paths(&report.direct_dprs)),
+    ];
+    if transitive {
+        fields.push(format!(
+            "\"transitive_dprs\":{}",
+            paths(&report.transitive_dprs)
+        ));
+    }
+    fields.push(format!("\"units\":{}", paths(&report.units)));
+    fields.push(format!("\"warnings\":{}", json_warnings(warnings, display)));
+    fields
+}
+
+/// The warnings `display` does not suppress, as a JSON array of strings.
+pub fn json_warnings(warnings: &Warnings, display: &ReportDisplay<'_>) -> String {
+    json_array(
+        warnings
+            .without(display.suppressed)
+            .iter()
+            .map(|warning| display.paths.text(&warning.to_string())),
+    )
+}
+
+pub fn json_array(values: impl IntoIterator<Item = String>) -> String {
+    let items: Vec<String> = values
+        .into_iter()
+        .map(|value| json_string(&value))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if u32::from(ch) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(ch))),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_string_escapes_quotes_backslashes_and_controls() {
+        assert_eq!(
+            json_string("C:\\src\\\"a\"\n\u{1}"),
+            "\"C:\\\\src\\\\\\\"a\\\"\\n\\u0001\""
+        );
+    }
+
+    #[test]
+    fn display_paths_shortens_paths_under_search_roots() {
+        let root = std::env::temp_dir();
+        let inside = root.join("repo").join("App.dpr");
+        let text = format!("warning: no uses list found in {}", inside.display());
+
+        let relative = DisplayPaths::new(std::slice::from_ref(&root), false);
+        assert_eq!(
+            relative.text(&text),
+            format!(
+                "warning: no uses list found in {}",
+                std::path::Path::new("repo").join("App.dpr").display()
+            )
+        );
+        assert_eq!(
+            relative.path(&inside),
+            std::path::Path::new("repo")
+                .join("App.dpr")
+                .display()
+                .to_string()
+        );
+
+        let absolute = DisplayPaths::new(std::slice::from_ref(&root), true);
+        assert_eq!(absolute.text(&text), text);
+    }
+}
@@ -0,0 +1,590 @@
+//! `fixdpr serve`: fixdpr operations over newline-delimited JSON on stdio.
+//!
+//! The search roots are scanned and the unit caches built once at start-up.
+//! After that every line read from stdin is one request object and every line
+//! written to stdout is the response to it, in order. Start-up progress and
+//! warnings go to stderr, so stdout only ever carries responses.
+//!
+//! A request names its method and passes its parameters either next to it or
+//! in a `params` object; an `id`, when given, is echoed back unchanged:
+//!
+//! ```text
+//! {"id":1,"method":"fix","dpr":"app/App.dpr"}
+//! {"id":2,"method":"whoUses","params":{"unit":"UnitB","transitive":true}}
+//! ```
+//!
+//! Methods:
+//!
+//! - `fix` with `dpr` and optional `dryRun`: repairs the dpr like `fix-dpr` and
+//!   returns the same object as one line of `fix-dpr --format jsonl`.
+//! - `missing` with `dpr`: `fix` as a dry run, so nothing is written and an
+//!   updated dpr is reported as `would-update`.
+//! - `whoUses` with `unit` and optional `transitive`: the members of
+//!   `who-uses --format json`, without timings.
+//! - `reload` with `path`: tells the server a file changed, was added, or was
+//!   deleted. A `.pas` file is parsed again, a `.dpr` file is added to or
+//!   dropped from the known dprs, and an `.inc` file re-parses the units that
+//!   include it. Returns `{"path":...,"status":...}` with status `reloaded`,
+//!   `added`, `removed`, or `unchanged`.
+//! - `shutdown`: answers with a `null` result and exits. End of input does the same.
+//!
+//! Paths are resolved against the current folder of the server and shown as in
+//! the other reports. Responses follow JSON-RPC 2.0:
+//!
+//! ```text
+//! {"jsonrpc":"2.0","id":1,"result":{...}}
+//! {"jsonrpc":"2.0","id":2,"error":{"code":-32602,"message":"..."}}
+//! ```
+//!
+//! Error codes are -32700 for a line that is not UTF-8 JSON, -32600 for a request
+//! without a method, -32601 for an unknown method, -32602 for missing or
+//! invalid parameters, and -32000 when the operation itself fails.
+
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::rc::Rc;
+
+use crate::dpr_edit::{self, FixOptions};
+use crate::error::FixdprError;
+use crate::events::EventSink;
+use crate::fs_walk;
+use crate::project::{self, Project};
+use crate::report::{dpr_detail_json, json_string, who_uses_fields, ReportDisplay};
+use crate::unit_cache;
+use crate::warnings::Warnings;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const FAILED: i64 = -32000;
+
+/// The warm state the requests of one `serve` run share.
+pub struct Session<'a> {
+    pub project: Project,
+    /// Options of the fix methods; each request sets the dry run itself.
+    pub fix_options: FixOptions,
+    pub display: ReportDisplay<'a>,
+    /// Receives the warnings of `reload`, whose response has no room for them.
+    pub events: Rc<dyn EventSink>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<FixdprError> for RpcError {
+    fn from(err: FixdprError) -> Self {
+        let code = if err.exit_code() == 2 {
+            INVALID_PARAMS
+        } else {
+            FAILED
+        };
+        Self::new(code, err.to_string())
+    }
+}
+
+impl Session<'_> {
+    /// Answers requests from `input` until `shutdown` or the end of input.
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        loop {
+            bytes.clear();
+            if input.read_until(b'\n', &mut bytes)? == 0 {
+                break;
+            }
+            // Bytes that are not UTF-8 fail this request, not the whole server.
+            let parsed = match std::str::from_utf8(&bytes) {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => Json::parse(line),
+                Err(err) => Err(format!("invalid UTF-8 at byte {}", err.valid_up_to())),
+            };
+            let (id, outcome, shutdown) = match parsed {
+                Ok(request) => {
+                    let id = request.get("id").map_or("null".to_string(), Json::to_text);
+                    let method = request.get("method").and_then(Json::as_str);
+                    let shutdown = method == Some("shutdown");
+                    let outcome = match method {
+                        Some(method) => self.handle(method, &Params(&request)),
+                        None => Err(RpcError::new(
+                            INVALID_REQUEST,
+                            "request has no method string",
+                        )),
+                    };
+                    (id, outcome, shutdown)
+                }
+                Err(message) => (
+                    "null".to_string(),
+                    Err(RpcError::new(PARSE_ERROR, message)),
+                    false,
+                ),
+            };
+            let body = match outcome {
+                Ok(result) => format!("\"result\":{result}"),
+                Err(err) => format!(
+                    "\"error\":{{\"code\":{},\"message\":{}}}",
+                    err.code,
+                    json_string(&self.display.paths.text(&err.message))
+                ),
+            };
+            writeln!(output, "{{\"jsonrpc\":\"2.0\",\"id\":{id},{body}}}")?;
+            output.flush()?;
+            if shutdown {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, method: &str, params: &Params<'_>) -> Result<String, RpcError> {
+        match method {
+            "fix" => self.fix(params.string("dpr")?, params.flag("dryRun")?),
+            "missing" => self.fix(params.string("dpr")?, true),
+            "whoUses" => self.who_uses(params.string("unit")?, params.flag("transitive")?),
+            "reload" => self.reload(params.string("path")?),
+            "shutdown" => Ok("null".to_string()),
+            other => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {other}"),
+            )),
+        }
+    }
+
+    fn fix(&mut self, dpr: &str, dry_run: bool) -> Result<String, RpcError> {
+        let path = fs_walk::resolve_path_with_flag(dpr, &self.project.cwd, "dpr")?;
+        fs_walk::validate_dpr_file_path(&path, "dpr")?;
+        let path = unit_cache::canonicalize_if_exists(&path);
+        self.project.check_dpr(&path, "dpr")?;
+        self.fix_options.set_dry_run(dry_run);
+        let summary = self.project.fix(&path, &self.fix_options)?;
+        let mut detail = summary
+            .details
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| dpr_edit::DprDetail::new(&path, dpr_edit::DprStatus::Unchanged));
+        if let Some(cache) = &self.project.delphi_cache {
+            detail
+                .warnings
+                .extend(cache.take_warnings().iter().cloned());
+        }
+        Ok(dpr_detail_json(&detail, dry_run, &self.display))
+    }
+
+    fn who_uses(&mut self, unit: &str, transitive: bool) -> Result<String, RpcError> {
+        let mut warnings = Warnings::new();
+        let unit_name = project::resolve_unit_name(
+            unit,
+            &self.project.cwd,
+            self.project.unit_cache.source,
+            &mut warnings,
+        )?;
+        let mut report = self.project.who_uses(&unit_name, transitive)?;
+        warnings.extend(mem::take(&mut report.warnings));
+        let fields = who_uses_fields(&report, &unit_name, transitive, &warnings, &self.display);
+        Ok(format!("{{{}}}", fields.join(",")))
+    }
+
+    fn reload(&mut self, value: &str) -> Result<String, RpcError> {
+        let path = fs_walk::resolve_path_with_flag(value, &self.project.cwd, "path")?;
+        let mut warnings = Warnings::new();
+        let status = self.project.reload(&path, &mut warnings);
+        for warning in warnings.without(self.display.suppressed).iter() {
+            self.events.on_warning(warning);
+        }
+        let status = status?;
+        Ok(format!(
+            "{{\"path\":{},\"status\":{}}}",
+            json_string(
+                &self
+                    .display
+                    .paths
+                    .path(&unit_cache::canonicalize_if_exists(&path))
+            ),
+            json_string(status.label())
+        ))
+    }
+}
+
+/// Request parameters, read from `params` when the request has one and from
+/// the request object itself otherwise.
+struct Params<'a>(&'a Json);
+
+impl Params<'_> {
+    fn value(&self, name: &str) -> Option<&Json> {
+        match self.0.get("params") {
+            Some(params) => params.get(name),
+            None => self.0.get(name),
+        }
+    }
+
+    fn string(&self, name: &str) -> Result<&str, RpcError> {
+        match self.value(name) {
+            Some(Json::String(value)) if !value.trim().is_empty() => Ok(value),
+            Some(_) => Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("{name} must be a non-empty string"),
+            )),
+            None => Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("missing parameter {name}"),
+            )),
+        }
+    }
+
+    fn flag(&self, name: &str) -> Result<bool, RpcError> {
+        match self.value(name) {
+            None | Some(Json::Null) => Ok(false),
+            Some(Json::Bool(value)) => Ok(*value),
+            Some(_) => Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("{name} must be true or false"),
+            )),
+        }
+    }
+}
+
+/// A parsed JSON value. Numbers keep their text, since requests only echo them.
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = JsonParser {
+            bytes: text.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Self::Object(members) => members
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Self::Null => "null".to_string(),
+            Self::Bool(value) => value.to_string(),
+            Self::Number(text) => text.clone(),
+            Self::String(value) => json_string(value),
+            Self::Array(items) => {
+                let items: Vec<String> = items.iter().map(Json::to_text).collect();
+                format!("[{}]", items.join(","))
+            }
+            Self::Object(members) => {
+                let members: Vec<String> = members
+                    .iter()
+                    .map(|(key, value)| format!("{}:{}", json_string(key), value.to_text()))
+                    .collect();
+                format!("{{{}}}", members.join(","))
+            }
+        }
+    }
+}
+
+/// Arrays and objects nested deeper than this are rejected, so one bad
+/// request cannot exhaust the stack of the server.
+const MAX_JSON_DEPTH: usize = 128;
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Arrays and objects open at `pos`.
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("invalid JSON at byte {}: {message}", self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| matches!(byte, b' ' | b'\t' | b'\r' | b'\n'))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn literal(&mut self, text: &str, value: Json) -> Result<Json, String> {
+        if self.bytes[self.pos..].starts_with(text.as_bytes()) {
+            self.pos += text.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'{' | b'[') if self.depth == MAX_JSON_DEPTH => {
+                Err(self.error(&format!("nested deeper than {MAX_JSON_DEPTH} levels")))
+            }
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, String>) -> Result<Json, String> {
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.bytes.get(self.pos) != Some(&b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        let text = String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned();
+        if text.parse::<f64>().is_err() {
+            self.pos = start;
+            return Err(self.error("malformed number"));
+        }
+        Ok(Json::Number(text))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.pos) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.bytes.get(self.pos) else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.pos += 1;
+                    let ch = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("unknown escape")),
+                    };
+                    let mut buffer = [0; 4];
+                    out.extend_from_slice(ch.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte if byte < 0x20 => return Err(self.error("control character in string")),
+                byte => out.push(byte),
+            }
+        }
+        String::from_utf8(out).map_err(|_| self.error("string is not UTF-8"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("malformed \\u escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_parse_reads_requests_with_escapes_and_nesting() {
+        let request = Json::parse(
+            r#" {"id": 7, "method": "fix", "params": {"dpr": "C:\\src\\App.dpr", "dryRun": true, "tags": [1, -2.5e3, null]}, "note": "\u00e9\ud83d\ude00"} "#,
+        )
+        .unwrap();
+        assert_eq!(request.get("id"), Some(&Json::Number("7".to_string())));
+        assert_eq!(request.get("method").and_then(Json::as_str), Some("fix"));
+        let params = Params(&request);
+        assert_eq!(params.string("dpr"), Ok(r"C:\src\App.dpr"));
+        assert_eq!(params.flag("dryRun"), Ok(true));
+        assert_eq!(params.flag("transitive"), Ok(false));
+        assert_eq!(
+            request.get("note").and_then(Json::as_str),
+            Some("\u{e9}\u{1f600}")
+        );
+        assert_eq!(
+            request.get("params").and_then(|params| params.get("tags")),
+            Some(&Json::Array(vec![
+                Json::Number("1".to_string()),
+                Json::Number("-2.5e3".to_string()),
+                Json::Null
+            ]))
+        );
+    }
+
+    #[test]
+    fn json_parse_rejects_malformed_input() {
+        for text in [
+            "",
+            "{",
+            "{\"a\" 1}",
+            "[1,]",
+            "\"abc",
+            "tru",
+            "{} x",
+            "\"\\ud800\"",
+        ] {
+            assert!(Json::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn json_parse_caps_nesting_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_JSON_DEPTH)).is_ok());
+        let err = Json::parse(&nested(MAX_JSON_DEPTH + 1)).unwrap_err();
+        assert!(err.contains("nested deeper than 128 levels"), "{err}");
+        assert!(Json::parse(&"[".repeat(200_000)).is_err());
+        assert!(Json::parse(&"{\"a\":".repeat(200_000)).is_err());
+    }
+
+    #[test]
+    fn params_read_top_level_members_without_a_params_object() {
+        let request =
+            Json::parse(r#"{"method":"reload","path":"Unit.pas","dryRun":"yes"}"#).unwrap();
+        let params = Params(&request);
+        assert_eq!(params.string("path"), Ok("Unit.pas"));
+        assert_eq!(params.flag("dryRun").unwrap_err().code, INVALID_PARAMS);
+        assert_eq!(params.string("dpr").unwrap_err().code, INVALID_PARAMS);
+    }
+}
@@ -1,371 +1,856 @@
-use std::collections::HashMap;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+use crate::cache_file;
 use crate::conditionals::{self, Assumptions, ConditionalUse};
-use crate::pas_lex;
-#[cfg(test)]
-use crate::uses_include;
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::events::{self, EventSink, NoEvents, ScanProgress};
+use crate::fs_walk;
+pub use crate::pas_parse::parse_unit_name;
+use crate::pas_parse::{unit_name_from_stem, Section, UnitName, UnitNames, UsesRef};
+use crate::uses_include::{self, Includes};
+use crate::warnings::Warnings;
 
 #[derive(Debug, Clone)]
 pub struct UnitFileInfo {
     pub name: String,
     pub path: PathBuf,
-    #[allow(dead_code)]
-    pub uses: Vec<String>,
+    pub uses: Vec<UsesRef>,
     pub conditional_uses: Vec<ConditionalUse>,
+    pub interface_uses: usize,
+    /// The name is the file stem because no `unit` header could be parsed.
+    pub name_from_stem: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UsesSections {
+    Interface,
+    Implementation,
+    #[default]
+    Both,
+}
+
+impl FromStr for UsesSections {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "interface" => Ok(Self::Interface),
+            "implementation" => Ok(Self::Implementation),
+            "both" | "all" => Ok(Self::Both),
+            other => Err(format!(
+                "--uses-sections must be interface, implementation, both, or all, got '{other}'"
+            )),
+        }
+    }
+}
+
+impl UnitFileInfo {
+    pub fn section_uses(&self, sections: UsesSections) -> &[ConditionalUse] {
+        let split = self.interface_uses.min(self.conditional_uses.len());
+        match sections {
+            UsesSections::Interface => &self.conditional_uses[..split],
+            UsesSections::Implementation => &self.conditional_uses[split..],
+            UsesSections::Both => &self.conditional_uses,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct UnitCache {
     pub by_path: HashMap<PathBuf, UnitFileInfo>,
     pub by_name: HashMap<String, Vec<PathBuf>>,
+    pub known_units: KnownUnits,
+    /// How the cached units were read; units loaded later from outside the cache use it too.
+    pub source: SourceOptions,
+    /// How the `{$I}` directives of the cached units resolve; dprs read with
+    /// the cache resolve theirs the same way.
+    pub includes: Rc<Includes>,
+    /// Canonical forms of the paths resolved while building and using the cache.
+    pub canonical: Rc<CanonicalPaths>,
+    /// The names in the uses clauses of the cached units.
+    pub names: UnitNames,
 }
 
-pub fn build_unit_cache(paths: &[PathBuf], warnings: &mut Vec<String>) -> io::Result<UnitCache> {
-    let mut cache = UnitCache::default();
+impl UnitCache {
+    /// Reads the unit at `path` the way the cached units were read.
+    pub fn load_unit(
+        &self,
+        path: &Path,
+        warnings: &mut Warnings,
+    ) -> io::Result<Option<UnitFileInfo>> {
+        read_unit_file(path, self.source, &self.includes, warnings)
+    }
+}
 
-    for path in paths {
-        let canonical = canonicalize_if_exists(path);
-        if cache.by_path.contains_key(&canonical) {
-            continue;
-        }
-        if let Some(info) = load_unit_file(&canonical, warnings)? {
-            insert_unit(&mut cache, canonical, info);
+/// How source files are read and how units without a `unit` header are named.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceOptions {
+    /// Skip `.pas` and `.dpr` files larger than this many bytes; 0 removes the limit.
+    pub max_file_size: u64,
+    /// Name units without a parsable `unit` header after their file stem.
+    pub stem_fallback: bool,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        Self {
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            stem_fallback: true,
         }
     }
+}
 
-    Ok(cache)
+const BUILTIN_KNOWN_UNITS: &[&str] = &["System", "SysInit"];
+
+/// Source files larger than this are skipped unless [`SourceOptions::max_file_size`] changes it.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// Canonical forms of the paths resolved during one run, so resolving a path
+/// again skips the filesystem.
+///
+/// Paths are assumed not to move during the run; a path that was written,
+/// moved, or deleted is dropped with [`CanonicalPaths::forget`].
+#[derive(Debug, Default)]
+pub struct CanonicalPaths {
+    resolved: RefCell<HashMap<PathBuf, PathBuf>>,
+    hits: Cell<usize>,
+    misses: Cell<usize>,
 }
 
-pub fn load_unit_file(path: &Path, warnings: &mut Vec<String>) -> io::Result<Option<UnitFileInfo>> {
-    let bytes = fs::read(path)?;
-    let name = match determine_unit_name(path, &bytes, warnings) {
-        Some(value) => value,
-        None => return Ok(None),
-    };
-    let conditional_uses = conditionals::parse_unit_conditional_uses(path, &bytes, warnings);
-    let uses = conditionals::flatten_conditional_uses(&conditional_uses, &Assumptions::default());
-    Ok(Some(UnitFileInfo {
-        name,
-        path: path.to_path_buf(),
-        uses,
-        conditional_uses,
-    }))
+impl CanonicalPaths {
+    /// [`canonicalize_if_exists`], answered from earlier calls when possible.
+    pub fn canonicalize(&self, path: &Path) -> PathBuf {
+        if let Some(canonical) = self.resolved.borrow().get(path) {
+            self.hits.set(self.hits.get() + 1);
+            return canonical.clone();
+        }
+        self.misses.set(self.misses.get() + 1);
+        let canonical = canonicalize_if_exists(path);
+        self.resolved
+            .borrow_mut()
+            .insert(path.to_path_buf(), canonical.clone());
+        canonical
+    }
+
+    /// Drops every path that resolved to the file at `path`, which was just
+    /// written, moved, or deleted, along with `path` itself.
+    pub fn forget(&self, path: &Path) {
+        let mut resolved = self.resolved.borrow_mut();
+        let mut targets = vec![path.to_path_buf(), canonicalize_if_exists(path)];
+        targets.extend(resolved.get(path).cloned());
+        resolved.retain(|input, canonical| input != path && !targets.contains(canonical));
+    }
+
+    /// Hits and misses so far.
+    pub fn stats(&self) -> (usize, usize) {
+        (self.hits.get(), self.misses.get())
+    }
 }
 
-fn insert_unit(cache: &mut UnitCache, path: PathBuf, info: UnitFileInfo) {
-    let key = info.name.to_ascii_lowercase();
-    cache.by_path.insert(path.clone(), info);
-    cache.by_name.entry(key).or_default().push(path);
+/// A unit parsed by a [`ParsePool`] worker, with the warnings the parse raised.
+#[derive(Debug)]
+struct PreparsedUnit {
+    info: io::Result<Option<UnitFileInfo>>,
+    warnings: Warnings,
 }
 
-pub fn canonicalize_if_exists(path: &Path) -> PathBuf {
-    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+/// Units a [`ParsePool`] parsed, by canonical path, until a unit cache build takes them.
+#[derive(Debug, Default)]
+pub struct Prefetched {
+    units: RefCell<HashMap<PathBuf, PreparsedUnit>>,
 }
 
-fn determine_unit_name(path: &Path, bytes: &[u8], warnings: &mut Vec<String>) -> Option<String> {
-    if let Some(value) = parse_unit_name(bytes) {
-        return Some(value);
+impl Prefetched {
+    pub fn len(&self) -> usize {
+        self.units.borrow().len()
     }
 
-    let fallback = unit_name_from_stem(path);
-    if let Some(value) = fallback {
-        warnings.push(format!(
-            "warning: fallback to filename stem for unit name: {}",
-            path.display()
-        ));
-        return Some(value);
+    pub fn is_empty(&self) -> bool {
+        self.units.borrow().is_empty()
     }
 
-    warnings.push(format!(
-        "warning: unable to determine unit name: {}",
-        path.display()
-    ));
-    None
+    fn take(&self, path: &Path) -> Option<PreparsedUnit> {
+        self.units.borrow_mut().remove(path)
+    }
+
+    /// Drops the units no unit cache build has used.
+    pub fn clear(&self) {
+        self.units.borrow_mut().clear();
+    }
 }
 
-fn unit_name_from_stem(path: &Path) -> Option<String> {
-    path.file_stem()
-        .and_then(|stem| stem.to_str())
-        .map(|stem| stem.trim().to_string())
-        .filter(|value| !value.is_empty())
+/// Parses `.pas` files on worker threads while the caller is still finding them.
+///
+/// Workers read files with the given [`SourceOptions`]. Files with
+/// `{$I}` directives are left to the calling thread, because resolving includes needs
+/// the finished scan. A unit cache build given the [`Prefetched`] units from
+/// [`ParsePool::finish`] produces the same cache as a sequential parse.
+pub struct ParsePool {
+    sender: mpsc::Sender<PathBuf>,
+    workers: Vec<JoinHandle<Vec<(PathBuf, PreparsedUnit)>>>,
 }
 
-pub fn parse_unit_name(bytes: &[u8]) -> Option<String> {
-    let mut i = 0;
-    while i < bytes.len() {
-        match bytes[i] {
-            b'{' => {
-                i = pas_lex::skip_brace_comment(bytes, i + 1);
-            }
-            b'(' if bytes.get(i + 1) == Some(&b'*') => {
-                i = pas_lex::skip_paren_comment(bytes, i + 2);
-            }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => {
-                i = pas_lex::skip_line_comment(bytes, i + 2);
-            }
-            b'\'' => {
-                i = pas_lex::skip_string(bytes, i + 1);
-            }
-            byte if pas_lex::is_ident_start(byte) => {
-                let (token, next) = pas_lex::read_ident(bytes, i);
-                if token.eq_ignore_ascii_case("unit") {
-                    if let Some(name) = parse_unit_name_after(bytes, next) {
-                        return Some(name);
+impl ParsePool {
+    /// Starts `jobs` workers; files whose stamp matches `cached_stamps` are not parsed.
+    pub fn start(
+        jobs: usize,
+        source: SourceOptions,
+        cached_stamps: HashMap<PathBuf, (u64, u128)>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel::<PathBuf>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let cached_stamps = Arc::new(cached_stamps);
+        let workers = (0..jobs.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let cached_stamps = Arc::clone(&cached_stamps);
+                thread::spawn(move || {
+                    let mut parsed = Vec::new();
+                    loop {
+                        let next = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => break,
+                        };
+                        let Ok(path) = next else {
+                            break;
+                        };
+                        if let Some(unit) = preparse_unit(&path, source, &cached_stamps) {
+                            parsed.push(unit);
+                        }
                     }
-                }
-                i = next;
-            }
-            _ => {
-                i += 1;
+                    parsed
+                })
+            })
+            .collect();
+        Self { sender, workers }
+    }
+
+    pub fn submit(&self, path: &Path) {
+        // Workers only stop early by panicking; their files are then parsed on this thread.
+        let _ = self.sender.send(path.to_path_buf());
+    }
+
+    /// Waits for the workers and returns what they parsed.
+    pub fn finish(self) -> Prefetched {
+        let Self { sender, workers } = self;
+        drop(sender);
+        let mut units = HashMap::new();
+        for worker in workers {
+            if let Ok(parsed) = worker.join() {
+                units.extend(parsed);
             }
         }
+        Prefetched {
+            units: RefCell::new(units),
+        }
     }
-    None
 }
 
-fn parse_unit_name_after(bytes: &[u8], mut i: usize) -> Option<String> {
-    i = pas_lex::skip_ws_and_comments(bytes, i);
-    if i >= bytes.len() || !pas_lex::is_ident_start(bytes[i]) {
-        return None;
-    }
-    let (name, _) = pas_lex::read_ident_with_dots(bytes, i);
-    if name.is_empty() {
-        None
-    } else {
-        Some(name)
+fn preparse_unit(
+    path: &Path,
+    source: SourceOptions,
+    cached_stamps: &HashMap<PathBuf, (u64, u128)>,
+) -> Option<(PathBuf, PreparsedUnit)> {
+    let canonical = canonicalize_if_exists(path);
+    if let Some(stamp) = cached_stamps.get(&canonical) {
+        if cache_file::file_stamp(&canonical).ok().as_ref() == Some(stamp) {
+            return None;
+        }
     }
+    let mut warnings = Warnings::new();
+    let info = match read_source_file(&canonical, source.max_file_size, &mut warnings) {
+        Ok(Some(bytes)) if uses_include::has_include_directive(&bytes) => return None,
+        // Without includes, the parse does not depend on the project's include index.
+        Ok(Some(bytes)) => Ok(parse_unit_bytes(
+            &canonical,
+            &bytes,
+            source.stem_fallback,
+            &Includes::default(),
+            &mut warnings,
+        )),
+        Ok(None) => Ok(None),
+        Err(err) => Err(err),
+    };
+    Some((canonical, PreparsedUnit { info, warnings }))
 }
 
-#[cfg(test)]
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum Section {
-    None,
-    Interface,
-    Implementation,
+/// Units that resolve without a `.pas` file, such as RTL units shipped only as DCUs.
+///
+/// Lookups by name treat them as found, so they are never inserted and never warned about.
+#[derive(Clone, Debug)]
+pub struct KnownUnits {
+    names: HashSet<String>,
 }
 
-#[cfg(test)]
-pub fn parse_unit_uses(path: &Path, bytes: &[u8], warnings: &mut Vec<String>) -> Vec<String> {
-    let mut deps = Vec::new();
-    let mut i = 0;
-    let mut section = Section::None;
-
-    while i < bytes.len() {
-        match bytes[i] {
-            b'{' => {
-                i = pas_lex::skip_brace_comment(bytes, i + 1);
-            }
-            b'(' if bytes.get(i + 1) == Some(&b'*') => {
-                i = pas_lex::skip_paren_comment(bytes, i + 2);
-            }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => {
-                i = pas_lex::skip_line_comment(bytes, i + 2);
-            }
-            b'\'' => {
-                i = pas_lex::skip_string(bytes, i + 1);
-            }
-            byte if pas_lex::is_ident_start(byte) => {
-                let (token, next) = pas_lex::read_ident(bytes, i);
-                if token.eq_ignore_ascii_case("interface") {
-                    section = Section::Interface;
-                } else if token.eq_ignore_ascii_case("implementation") {
-                    section = Section::Implementation;
-                } else if token.eq_ignore_ascii_case("uses") && section != Section::None {
-                    let mut include_stack = Vec::new();
-                    include_stack.push(canonicalize_if_exists(path));
-                    let (next_i, _) = parse_uses_fragment_with_includes(
-                        bytes,
-                        next,
-                        path,
-                        warnings,
-                        &mut deps,
-                        &mut include_stack,
-                    );
-                    i = next_i;
-                    continue;
-                }
-                i = next;
+impl Default for KnownUnits {
+    fn default() -> Self {
+        let mut known = Self {
+            names: HashSet::new(),
+        };
+        for name in BUILTIN_KNOWN_UNITS {
+            known.insert(name);
+        }
+        known
+    }
+}
+
+impl KnownUnits {
+    pub fn contains(&self, unit_name: &str) -> bool {
+        self.names.contains(&unit_name.to_ascii_lowercase())
+    }
+
+    pub fn contains_name(&self, unit_name: &UnitName) -> bool {
+        self.names.contains(unit_name.key())
+    }
+
+    pub fn insert(&mut self, unit_name: &str) {
+        self.names.insert(unit_name.to_ascii_lowercase());
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// The known names, lowercased and sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.names.iter().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Adds one unit name per line; text after `#` is a comment.
+    pub fn extend_from_list(&mut self, text: &str) {
+        for line in text.lines() {
+            let name = line.split('#').next().unwrap_or_default().trim();
+            if !name.is_empty() {
+                self.insert(name);
             }
-            _ => {
-                i += 1;
+        }
+    }
+
+    /// The built-in list extended with the names listed in `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut known = Self::default();
+        known.extend_from_list(&fs::read_to_string(path)?);
+        Ok(known)
+    }
+}
+
+pub fn build_unit_cache(paths: &[PathBuf], warnings: &mut Warnings) -> io::Result<UnitCache> {
+    build_unit_cache_with_events(
+        paths,
+        SourceOptions::default(),
+        Includes::default(),
+        &Prefetched::default(),
+        warnings,
+        &NoEvents,
+    )
+}
+
+/// Parses `paths` into a unit cache, taking the units in `prefetched` instead
+/// of parsing them again.
+pub fn build_unit_cache_with_events(
+    paths: &[PathBuf],
+    source: SourceOptions,
+    includes: Includes,
+    prefetched: &Prefetched,
+    warnings: &mut Warnings,
+    events: &dyn EventSink,
+) -> io::Result<UnitCache> {
+    let mut cache = UnitCache {
+        source,
+        includes: Rc::new(includes),
+        ..UnitCache::default()
+    };
+    let mut forwarded = warnings.len();
+
+    for (index, path) in paths.iter().enumerate() {
+        let canonical = cache.canonical.canonicalize(path);
+        if !cache.by_path.contains_key(&canonical) {
+            if let Some(info) = load_unit_file_prefetched(&canonical, &cache, prefetched, warnings)?
+            {
+                insert_unit(&mut cache, canonical, info);
             }
+            events::forward_warnings(events, warnings, &mut forwarded);
+        }
+        if events::should_report(index + 1, paths.len()) {
+            events.on_scan_progress(ScanProgress::ParsingUnits {
+                done: index + 1,
+                total: paths.len(),
+            });
         }
     }
+    prefetched.clear();
 
-    deps
+    Ok(cache)
 }
 
-#[cfg(test)]
-fn parse_uses_fragment_with_includes(
+/// [`load_unit_file_with`] the default [`SourceOptions`].
+pub fn load_unit_file(path: &Path, warnings: &mut Warnings) -> io::Result<Option<UnitFileInfo>> {
+    load_unit_file_with(path, SourceOptions::default(), warnings)
+}
+
+/// Reads a unit whose `{$I}` directives resolve only next to their source.
+pub fn load_unit_file_with(
+    path: &Path,
+    source: SourceOptions,
+    warnings: &mut Warnings,
+) -> io::Result<Option<UnitFileInfo>> {
+    read_unit_file(path, source, &Includes::default(), warnings)
+}
+
+fn read_unit_file(
+    path: &Path,
+    source: SourceOptions,
+    includes: &Includes,
+    warnings: &mut Warnings,
+) -> io::Result<Option<UnitFileInfo>> {
+    let Some(bytes) = read_source_file(path, source.max_file_size, warnings)? else {
+        return Ok(None);
+    };
+    Ok(parse_unit_bytes(
+        path,
+        &bytes,
+        source.stem_fallback,
+        includes,
+        warnings,
+    ))
+}
+
+/// [`UnitCache::load_unit`], answered from `prefetched` when a [`ParsePool`] parsed `path` already.
+pub fn load_unit_file_prefetched(
+    path: &Path,
+    cache: &UnitCache,
+    prefetched: &Prefetched,
+    warnings: &mut Warnings,
+) -> io::Result<Option<UnitFileInfo>> {
+    match prefetched.take(path) {
+        Some(unit) => {
+            warnings.extend(unit.warnings);
+            unit.info
+        }
+        None => cache.load_unit(path, warnings),
+    }
+}
+
+fn parse_unit_bytes(
+    path: &Path,
     bytes: &[u8],
-    mut i: usize,
-    source_path: &Path,
-    warnings: &mut Vec<String>,
-    deps: &mut Vec<String>,
-    include_stack: &mut Vec<PathBuf>,
-) -> (usize, bool) {
-    loop {
-        i = skip_ws_comments_and_includes(bytes, i, source_path, warnings, deps, include_stack);
-        if i >= bytes.len() {
-            return (i, false);
+    stem_fallback: bool,
+    includes: &Includes,
+    warnings: &mut Warnings,
+) -> Option<UnitFileInfo> {
+    let (name, name_from_stem) = determine_unit_name(path, bytes, stem_fallback, warnings)?;
+    let (conditional_uses, interface_uses) =
+        conditionals::parse_unit_conditional_uses_by_section(path, bytes, includes, warnings);
+    let uses = uses_refs(&conditional_uses, interface_uses);
+    Some(UnitFileInfo {
+        name,
+        path: path.to_path_buf(),
+        uses,
+        conditional_uses,
+        interface_uses,
+        name_from_stem,
+    })
+}
+
+/// The uses a unit has under the default assumptions, tagged with their section;
+/// the first `interface_uses` conditional uses come from the interface.
+pub fn uses_refs(conditional_uses: &[ConditionalUse], interface_uses: usize) -> Vec<UsesRef> {
+    let assumptions = Assumptions::default();
+    conditional_uses
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            conditionals::evaluate_condition(&entry.condition, &assumptions)
+                != conditionals::EvalResult::Never
+        })
+        .map(|(index, entry)| UsesRef {
+            name: entry.unit_name.clone(),
+            section: if index < interface_uses {
+                Section::Interface
+            } else {
+                Section::Implementation
+            },
+            in_path: entry.in_path.clone(),
+        })
+        .collect()
+}
+
+/// Reads a source file, or warns and returns `None` when it is larger than
+/// `limit` bytes or looks binary. A `limit` of 0 reads files of any size.
+pub fn read_source_file(
+    path: &Path,
+    limit: u64,
+    warnings: &mut Warnings,
+) -> io::Result<Option<Vec<u8>>> {
+    let size = fs::metadata(path)?.len();
+    if limit > 0 && size > limit {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::FileTooLarge,
+                format!(
+                    "skipped {} ({size} bytes, above --max-file-size {limit})",
+                    path.display()
+                ),
+            )
+            .at(path),
+        );
+        return Ok(None);
+    }
+    let bytes = fs::read(path)?;
+    if looks_binary(&bytes) {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::BinaryFile,
+                format!(
+                    "skipped {} (NUL byte in the first {BINARY_SNIFF_LEN} bytes)",
+                    path.display()
+                ),
+            )
+            .at(path),
+        );
+        return Ok(None);
+    }
+    Ok(Some(bytes))
+}
+
+/// UTF-16 files carry NUL bytes too, so a UTF-16 BOM marks the file as text.
+fn looks_binary(bytes: &[u8]) -> bool {
+    if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) {
+        return false;
+    }
+    bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0)
+}
+
+pub fn insert_unit(cache: &mut UnitCache, path: PathBuf, mut info: UnitFileInfo) {
+    intern_names(&mut cache.names, &mut info);
+    let key = info.name.to_ascii_lowercase();
+    cache.by_path.insert(path.clone(), info);
+    cache.by_name.entry(key).or_default().push(path);
+}
+
+/// Points the uses of `info` at the names already in `names`, so each
+/// spelling is stored once however many units use it.
+fn intern_names(names: &mut UnitNames, info: &mut UnitFileInfo) {
+    for entry in &mut info.conditional_uses {
+        entry.unit_name = names.intern(&entry.unit_name);
+    }
+    for uses in &mut info.uses {
+        uses.name = names.intern(&uses.name);
+    }
+}
+
+/// Drops the unit at `path` from both indexes, returning what was cached for it.
+pub fn remove_unit(cache: &mut UnitCache, path: &Path) -> Option<UnitFileInfo> {
+    let info = cache.by_path.remove(path)?;
+    let key = info.name.to_ascii_lowercase();
+    if let Some(paths) = cache.by_name.get_mut(&key) {
+        paths.retain(|known| known != path);
+        if paths.is_empty() {
+            cache.by_name.remove(&key);
         }
-        if bytes[i] == b';' {
-            return (i + 1, true);
+    }
+    Some(info)
+}
+
+#[derive(Debug, Default)]
+struct LazyIndex {
+    /// Files by lowercased stem, the likely declared name.
+    by_name: HashMap<String, Vec<PathBuf>>,
+    units: HashMap<PathBuf, OnceCell<Option<UnitFileInfo>>>,
+    /// Files in scan order.
+    order: Vec<PathBuf>,
+    /// Files by lowercased declared name, built by parsing every file the
+    /// first time a name has no file with a matching stem.
+    declared: OnceCell<HashMap<String, Vec<PathBuf>>>,
+}
+
+#[derive(Debug, Default)]
+pub struct LazyUnitCache {
+    roots: Vec<PathBuf>,
+    scan_options: fs_walk::ScanOptions,
+    ignore: fs_walk::IgnoreMatcher,
+    index: OnceCell<LazyIndex>,
+    parsed: Cell<usize>,
+    build_time: Cell<Duration>,
+    warnings: RefCell<Warnings>,
+    names: RefCell<UnitNames>,
+}
+
+impl LazyUnitCache {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            roots,
+            ..Self::default()
         }
-        if !pas_lex::is_ident_start(bytes[i]) {
-            i += 1;
-            continue;
+    }
+
+    /// Options for the deferred scan of the fallback roots.
+    pub fn with_scan_options(mut self, options: fs_walk::ScanOptions) -> Self {
+        self.scan_options = options;
+        self
+    }
+
+    /// Paths left out of the deferred scan of the fallback roots.
+    pub fn with_ignore(mut self, ignore: fs_walk::IgnoreMatcher) -> Self {
+        self.ignore = ignore;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn from_cache(cache: UnitCache) -> Self {
+        let parsed = cache.by_path.len();
+        let mut order: Vec<PathBuf> = cache.by_path.keys().cloned().collect();
+        order.sort();
+        let units = cache
+            .by_path
+            .into_iter()
+            .map(|(path, info)| (path, OnceCell::from(Some(info))))
+            .collect();
+        Self {
+            roots: Vec::new(),
+            scan_options: fs_walk::ScanOptions::default(),
+            ignore: fs_walk::IgnoreMatcher::default(),
+            index: OnceCell::from(LazyIndex {
+                by_name: cache.by_name,
+                units,
+                order,
+                declared: OnceCell::new(),
+            }),
+            parsed: Cell::new(parsed),
+            build_time: Cell::default(),
+            warnings: RefCell::default(),
+            names: RefCell::new(cache.names),
         }
-        let (name, next) = pas_lex::read_ident_with_dots(bytes, i);
-        if !name.is_empty() {
-            deps.push(name);
+    }
+
+    pub fn is_materialized(&self) -> bool {
+        self.index.get().is_some()
+    }
+
+    /// The roots, scan options, and ignore rules that decide which units the
+    /// cache finds, as text, for callers that skip work done with the same cache.
+    pub fn outcome_key(&self) -> String {
+        format!("{:?} {:?} {:?}", self.roots, self.scan_options, self.ignore)
+    }
+
+    pub fn indexed_count(&self) -> usize {
+        self.index.get().map(|index| index.units.len()).unwrap_or(0)
+    }
+
+    pub fn parsed_count(&self) -> usize {
+        self.parsed.get()
+    }
+
+    /// Time spent scanning the fallback roots and parsing units so far.
+    pub fn build_time(&self) -> Duration {
+        self.build_time.get()
+    }
+
+    fn add_build_time(&self, started: Instant) {
+        self.build_time
+            .set(self.build_time.get() + started.elapsed());
+    }
+
+    pub fn take_warnings(&self) -> Warnings {
+        std::mem::take(&mut *self.warnings.borrow_mut())
+    }
+
+    pub fn lookup_name(&self, unit_name: &str) -> Vec<PathBuf> {
+        let index = self.index();
+        let key = unit_name.to_ascii_lowercase();
+        if let Some(declared) = index.declared.get() {
+            return declared.get(&key).cloned().unwrap_or_default();
         }
-        i = next;
-        i = pas_lex::skip_ws_and_comments(bytes, i);
-
-        if let Some((token, next_token)) = peek_ident(bytes, i) {
-            if token.eq_ignore_ascii_case("in") {
-                i = next_token;
-                i = pas_lex::skip_ws_and_comments(bytes, i);
-                if i < bytes.len() && bytes[i] == b'\'' {
-                    i = pas_lex::skip_string(bytes, i + 1);
+        let found: Vec<PathBuf> = index
+            .by_name
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .filter(|path| {
+                // Unparsed files only match by stem while the stem fallback is on.
+                self.unit_in(index, path)
+                    .map(|info| info.name.eq_ignore_ascii_case(unit_name))
+                    .unwrap_or(self.scan_options.source().stem_fallback)
+            })
+            .cloned()
+            .collect();
+        if !found.is_empty() {
+            return found;
+        }
+        // A unit may declare a name that differs from its file name.
+        self.declared(index).get(&key).cloned().unwrap_or_default()
+    }
+
+    fn declared<'a>(&'a self, index: &'a LazyIndex) -> &'a HashMap<String, Vec<PathBuf>> {
+        index.declared.get_or_init(|| {
+            let mut declared: HashMap<String, Vec<PathBuf>> = HashMap::new();
+            for path in &index.order {
+                if let Some(info) = self.unit_in(index, path) {
+                    declared
+                        .entry(info.name.to_ascii_lowercase())
+                        .or_default()
+                        .push(path.clone());
                 }
             }
-        }
+            declared
+        })
+    }
 
-        let (pos, delim) =
-            scan_to_delimiter_with_includes(bytes, i, source_path, warnings, deps, include_stack);
-        i = pos;
-        match delim {
-            Some(b',') => i += 1,
-            Some(b';') => return (i + 1, true),
-            _ => return (i, false),
+    pub fn unit(&self, path: &Path) -> Option<&UnitFileInfo> {
+        if !self.is_materialized() && !self.covers(path) {
+            return None;
         }
+        let index = self.index();
+        self.unit_in(index, path)
     }
-}
 
-#[cfg(test)]
-fn peek_ident(bytes: &[u8], i: usize) -> Option<(String, usize)> {
-    if i < bytes.len() && pas_lex::is_ident_start(bytes[i]) {
-        let (token, next) = pas_lex::read_ident(bytes, i);
-        return Some((token, next));
+    fn covers(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
     }
-    None
-}
 
-#[cfg(test)]
-fn scan_to_delimiter_with_includes(
-    bytes: &[u8],
-    mut i: usize,
-    source_path: &Path,
-    warnings: &mut Vec<String>,
-    deps: &mut Vec<String>,
-    include_stack: &mut Vec<PathBuf>,
-) -> (usize, Option<u8>) {
-    while i < bytes.len() {
-        match bytes[i] {
-            b',' | b';' => return (i, Some(bytes[i])),
-            b'{' | b'(' => {
-                if let Some((include_name, end)) = pas_lex::parse_include_directive(bytes, i) {
-                    let include_entries = parse_include_entries_for_unit(
-                        include_name.as_str(),
-                        source_path,
-                        warnings,
-                        include_stack,
-                    );
-                    if !include_entries.is_empty() {
-                        deps.extend(include_entries);
+    fn unit_in<'a>(&'a self, index: &'a LazyIndex, path: &Path) -> Option<&'a UnitFileInfo> {
+        index
+            .units
+            .get(path)?
+            .get_or_init(|| {
+                self.parsed.set(self.parsed.get() + 1);
+                let started = Instant::now();
+                let mut warnings = self.warnings.borrow_mut();
+                let loaded = load_unit_file_with(path, self.scan_options.source(), &mut warnings);
+                self.add_build_time(started);
+                match loaded {
+                    Ok(mut info) => {
+                        if let Some(info) = &mut info {
+                            intern_names(&mut self.names.borrow_mut(), info);
+                        }
+                        info
+                    }
+                    Err(err) => {
+                        warnings.push(
+                            Diagnostic::warning(
+                                DiagnosticCode::ReadFailed,
+                                format!("failed to read unit at {}: {err}", path.display()),
+                            )
+                            .at(path),
+                        );
+                        None
                     }
-                    i = end;
-                    continue;
                 }
-                i = if bytes[i] == b'{' {
-                    pas_lex::skip_brace_comment(bytes, i + 1)
-                } else if bytes.get(i + 1) == Some(&b'*') {
-                    pas_lex::skip_paren_comment(bytes, i + 2)
-                } else {
-                    i + 1
-                };
-            }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
-            _ => i += 1,
-        }
+            })
+            .as_ref()
     }
-    (i, None)
-}
 
-#[cfg(test)]
-fn skip_ws_comments_and_includes(
-    bytes: &[u8],
-    mut i: usize,
-    source_path: &Path,
-    warnings: &mut Vec<String>,
-    deps: &mut Vec<String>,
-    include_stack: &mut Vec<PathBuf>,
-) -> usize {
-    while i < bytes.len() {
-        match bytes[i] {
-            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
-            b'{' | b'(' => {
-                if let Some((include_name, end)) = pas_lex::parse_include_directive(bytes, i) {
-                    let include_entries = parse_include_entries_for_unit(
-                        include_name.as_str(),
-                        source_path,
-                        warnings,
-                        include_stack,
-                    );
-                    if !include_entries.is_empty() {
-                        deps.extend(include_entries);
-                    }
-                    i = end;
+    fn index(&self) -> &LazyIndex {
+        self.index.get_or_init(|| {
+            let started = Instant::now();
+            let mut index = LazyIndex::default();
+            let scan = match fs_walk::scan_files_with(
+                &self.roots,
+                &self.ignore,
+                &self.scan_options,
+                &NoEvents,
+            ) {
+                Ok(scan) => scan,
+                Err(err) => {
+                    self.warnings.borrow_mut().push(Diagnostic::warning(
+                        DiagnosticCode::ScanFailed,
+                        format!("failed to scan Delphi fallback roots: {err}"),
+                    ));
+                    self.add_build_time(started);
+                    return index;
+                }
+            };
+            self.warnings.borrow_mut().extend(scan.warnings);
+            for path in scan.pas_files {
+                let canonical = canonicalize_if_exists(&path);
+                if index.units.contains_key(&canonical) {
                     continue;
                 }
-                i = if bytes[i] == b'{' {
-                    pas_lex::skip_brace_comment(bytes, i + 1)
-                } else if bytes.get(i + 1) == Some(&b'*') {
-                    pas_lex::skip_paren_comment(bytes, i + 2)
-                } else {
-                    i + 1
-                };
+                if let Some(stem) = unit_name_from_stem(&canonical) {
+                    index
+                        .by_name
+                        .entry(stem.to_ascii_lowercase())
+                        .or_default()
+                        .push(canonical.clone());
+                }
+                index.order.push(canonical.clone());
+                index.units.insert(canonical, OnceCell::new());
             }
-            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
-            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
-            _ => break,
-        }
+            self.add_build_time(started);
+            index
+        })
     }
-    i
 }
 
-#[cfg(test)]
-fn parse_include_entries_for_unit(
-    include_name: &str,
-    source_path: &Path,
-    warnings: &mut Vec<String>,
-    include_stack: &mut Vec<PathBuf>,
-) -> Vec<String> {
-    uses_include::with_include_bytes(
-        include_name,
-        source_path,
-        warnings,
-        include_stack,
-        |include_path, bytes, warnings, include_stack| {
-            let mut entries = Vec::new();
-            let _ = parse_uses_fragment_with_includes(
-                bytes,
-                0,
-                include_path,
-                warnings,
-                &mut entries,
-                include_stack,
-            );
-            entries
-        },
-    )
-    .unwrap_or_default()
+pub fn canonicalize_if_exists(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Removes the `\\?\` and `\\?\UNC\` prefixes that `fs::canonicalize` adds on Windows.
+pub fn strip_verbatim_prefix(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if text.contains(r"\\?\") {
+        PathBuf::from(strip_verbatim_prefixes(&text))
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// [`strip_verbatim_prefix`] for every path embedded in `text`, such as a message.
+pub fn strip_verbatim_prefixes(text: &str) -> String {
+    text.replace(r"\\?\UNC\", r"\\").replace(r"\\?\", "")
+}
+
+/// Returns the unit name and whether it was taken from the file stem.
+fn determine_unit_name(
+    path: &Path,
+    bytes: &[u8],
+    stem_fallback: bool,
+    warnings: &mut Warnings,
+) -> Option<(String, bool)> {
+    if let Some(value) = parse_unit_name(bytes) {
+        return Some((value, false));
+    }
+
+    let fallback = unit_name_from_stem(path).filter(|_| stem_fallback);
+    if let Some(value) = fallback {
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::UnitNameFallback,
+                format!(
+                    "fallback to filename stem for unit name: {}",
+                    path.display()
+                ),
+            )
+            .at(path),
+        );
+        return Some((value, true));
+    }
+
+    warnings.push(
+        Diagnostic::warning(
+            DiagnosticCode::UnitNameFallback,
+            format!("unable to determine unit name: {}", path.display()),
+        )
+        .at(path),
+    );
+    None
 }
 
 #[cfg(test)]
@@ -376,162 +861,375 @@ mod tests {
     use std::time::{SystemTime, UNIX_EPOCH};
 
     #[test]
-    fn parse_unit_name_basic() {
-        let src = b"unit Foo.Bar;\ninterface\nimplementation\nend.";
-        assert_eq!(parse_unit_name(src), Some("Foo.Bar".to_string()));
+    fn strip_verbatim_prefix_handles_drive_and_unc_paths() {
+        assert_eq!(
+            strip_verbatim_prefix(Path::new(r"\\?\UNC\server\share\App.dpr")),
+            PathBuf::from(r"\\server\share\App.dpr")
+        );
+        assert_eq!(
+            strip_verbatim_prefix(Path::new("src/App.dpr")),
+            PathBuf::from("src/App.dpr")
+        );
+        assert_eq!(
+            strip_verbatim_prefixes(r"in \\?\C:\repo\App.dpr and \\?\UNC\server\share\B.pas"),
+            r"in C:\repo\App.dpr and \\server\share\B.pas"
+        );
+    }
+
+    #[test]
+    fn known_units_extend_builtins_and_skip_comments() {
+        let mut known = KnownUnits::default();
+        assert!(known.contains("system"));
+        assert!(known.contains("SYSINIT"));
+
+        known.extend_from_list("# DCU-only units\nSystem.Win.Crtl\n\n  Winapi.Foo  # trailing\n");
+        assert!(known.contains("system.win.crtl"));
+        assert!(known.contains("Winapi.Foo"));
+        assert!(!known.contains("DCU-only"));
+        assert_eq!(known.len(), 4);
+    }
+
+    #[test]
+    fn section_uses_splits_interface_and_implementation() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        fs::write(
+            &unit_path,
+            "unit Demo;\ninterface\nuses Foo;\nimplementation\nuses Bar, Baz;\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let info = load_unit_file(&unit_path, &mut warnings)
+            .unwrap()
+            .expect("unit info");
+        let names = |sections| {
+            info.section_uses(sections)
+                .iter()
+                .map(|entry| entry.unit_name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(UsesSections::Interface), vec!["Foo"]);
+        assert_eq!(names(UsesSections::Implementation), vec!["Bar", "Baz"]);
+        assert_eq!(names(UsesSections::Both), vec!["Foo", "Bar", "Baz"]);
     }
 
     #[test]
-    fn parse_unit_name_ignores_comments() {
-        let src = br#"
-{ unit Wrong; }
-(* unit AlsoWrong; *)
-// unit NoWay;
-unit RealUnit;
-interface
-implementation
-end.
-"#;
-        assert_eq!(parse_unit_name(src), Some("RealUnit".to_string()));
+    fn load_unit_file_tags_uses_with_their_section() {
+        let root = temp_dir();
+        let unit_path = root.join("Demo.pas");
+        fs::write(
+            &unit_path,
+            "unit Demo;\ninterface\nuses Foo in 'Foo.pas', Qux;\nimplementation\nuses Bar;\nend.\n",
+        )
+        .unwrap();
+
+        let mut warnings = Warnings::new();
+        let info = load_unit_file(&unit_path, &mut warnings)
+            .unwrap()
+            .expect("unit info");
+        let mut foo = UsesRef::new("Foo", Section::Interface);
+        foo.in_path = Some("Foo.pas".to_string());
+        assert_eq!(
+            info.uses,
+            vec![
+                foo,
+                UsesRef::new("Qux", Section::Interface),
+                UsesRef::new("Bar", Section::Implementation),
+            ]
+        );
     }
 
     #[test]
-    fn parse_unit_name_ignores_strings() {
-        let src = b"const S = 'unit Fake;';\nunit Real;\ninterface\nend.";
-        assert_eq!(parse_unit_name(src), Some("Real".to_string()));
+    fn remove_unit_forgets_the_path_under_its_name() {
+        let root = temp_dir();
+        let first = root.join("Demo.pas");
+        let second = root.join("other").join("Demo.pas");
+        fs::create_dir_all(second.parent().unwrap()).unwrap();
+        for path in [&first, &second] {
+            fs::write(path, "unit Demo;\ninterface\nimplementation\nend.\n").unwrap();
+        }
+        let mut warnings = Warnings::new();
+        let mut cache = build_unit_cache(&[first.clone(), second.clone()], &mut warnings).unwrap();
+        let first = canonicalize_if_exists(&first);
+        let second = canonicalize_if_exists(&second);
+
+        assert_eq!(
+            remove_unit(&mut cache, &first).map(|info| info.name),
+            Some("Demo".to_string())
+        );
+        assert_eq!(cache.by_name.get("demo"), Some(&vec![second.clone()]));
+        assert!(remove_unit(&mut cache, &first).is_none());
+        remove_unit(&mut cache, &second);
+        assert!(cache.by_name.is_empty());
+        assert!(cache.by_path.is_empty());
     }
 
     #[test]
-    fn parse_unit_name_allows_ifdef_blocks_braces() {
-        let src = br#"
-{$IFDEF FOO}
-{$IFDEF BAR}
-{$ENDIF}
-{$ENDIF}
-unit Real;
-"#;
-        assert_eq!(parse_unit_name(src), Some("Real".to_string()));
+    fn uses_sections_parses_case_insensitive_values() {
+        assert_eq!(
+            "Interface".parse::<UsesSections>(),
+            Ok(UsesSections::Interface)
+        );
+        assert_eq!(
+            "implementation".parse::<UsesSections>(),
+            Ok(UsesSections::Implementation)
+        );
+        assert_eq!("BOTH".parse::<UsesSections>(), Ok(UsesSections::Both));
+        assert_eq!("all".parse::<UsesSections>(), Ok(UsesSections::Both));
+        assert!("closure".parse::<UsesSections>().is_err());
     }
 
     #[test]
-    fn parse_unit_name_allows_ifdef_blocks_paren() {
-        let src = br#"
-(*$IFDEF FOO*)
-unit Conditional;
-(*$ENDIF*)
-"#;
-        assert_eq!(parse_unit_name(src), Some("Conditional".to_string()));
+    fn load_unit_file_uses_fallback_name() {
+        let root = temp_dir();
+        let path = root.join("Fallback.pas");
+        fs::write(&path, "const X = 1;").unwrap();
+        let mut warnings = Warnings::new();
+        let info = load_unit_file(&path, &mut warnings).unwrap().expect("unit");
+        assert_eq!(info.name, "Fallback");
+        assert!(info.name_from_stem);
+        assert!(!warnings.is_empty());
     }
 
     #[test]
-    fn parse_unit_name_allows_nested_ifdefs() {
-        let src = br#"
-{$IFDEF OUTER}
-{$IFNDEF INNER}
-unit NestedUnit;
-{$ENDIF}
-{$ENDIF}
-"#;
-        assert_eq!(parse_unit_name(src), Some("NestedUnit".to_string()));
+    fn load_unit_file_skips_headerless_files_without_stem_fallback() {
+        let root = temp_dir();
+        let path = root.join("Scratch.pas");
+        fs::write(&path, "const X = 1;").unwrap();
+        let mut warnings = Warnings::new();
+        let source = SourceOptions {
+            stem_fallback: false,
+            ..SourceOptions::default()
+        };
+        let info = load_unit_file_with(&path, source, &mut warnings).unwrap();
+        assert!(info.is_none());
+        assert!(warnings
+            .iter()
+            .any(|warning| warning.message.starts_with("unable to determine unit name")));
+
+        let declared = root.join("Declared.pas");
+        fs::write(&declared, "unit Declared;\ninterface\nend.").unwrap();
+        let info = load_unit_file_with(&declared, source, &mut warnings)
+            .unwrap()
+            .expect("unit");
+        assert!(!info.name_from_stem);
     }
 
     #[test]
-    fn parse_unit_name_allows_if_and_ifopt_markers() {
-        let src = br#"
-{$IFDEF ENABLED}
-{$IF 1}
-{$IFOPT N+}
-unit OptUnit;
-{$ENDIF}
-{$ENDIF}
-{$ENDIF}
-"#;
-        assert_eq!(parse_unit_name(src), Some("OptUnit".to_string()));
+    fn lazy_unit_cache_reads_with_its_scan_options() {
+        let root = temp_dir();
+        fs::write(root.join("Scratch.pas"), "const X = 1;").unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+        let lookup = |options: fs_walk::ScanOptions| {
+            LazyUnitCache::new(vec![root.clone()])
+                .with_scan_options(options)
+                .lookup_name("Scratch")
+                .len()
+        };
+
+        assert_eq!(lookup(fs_walk::ScanOptions::default()), 1);
+        assert_eq!(
+            lookup(fs_walk::ScanOptions::default().stem_fallback(false)),
+            0
+        );
     }
 
     #[test]
-    fn parse_unit_uses_in_interface_and_implementation() {
-        let src = br#"
-unit Demo;
-interface
-uses Foo, Bar;
-implementation
-uses Baz;
-end.
-"#;
-        let deps = parse_uses_for_test(src);
-        assert_eq!(deps, vec!["Foo", "Bar", "Baz"]);
+    fn lazy_unit_cache_finds_units_whose_name_differs_from_the_file_name() {
+        let root = temp_dir();
+        fs::write(
+            root.join("Renamed.pas"),
+            "unit Vendor.Actual;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("Other.pas"),
+            "unit Other;\ninterface\nimplementation\nend.\n",
+        )
+        .unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+        let cache = LazyUnitCache::new(vec![root.clone()]);
+
+        assert_eq!(cache.lookup_name("Other"), vec![root.join("Other.pas")]);
+        assert_eq!(cache.parsed_count(), 1);
+        assert_eq!(
+            cache.lookup_name("vendor.actual"),
+            vec![root.join("Renamed.pas")]
+        );
+        assert!(cache.lookup_name("Renamed").is_empty());
+        assert!(cache.lookup_name("Missing").is_empty());
+        assert_eq!(cache.parsed_count(), 2);
     }
 
     #[test]
-    fn parse_unit_uses_ignores_comments_and_strings() {
-        let src = br#"
-unit Demo;
-interface
-uses Foo, {Bar}, (*Baz*), // Quux
-  'NotAUnit', RealUnit;
-implementation
-uses ImplUnit;
-end.
-"#;
-        let deps = parse_uses_for_test(src);
-        assert_eq!(deps, vec!["Foo", "RealUnit", "ImplUnit"]);
+    fn load_unit_file_skips_binary_and_oversized_files() {
+        let root = temp_dir();
+        let binary = root.join("Binary.pas");
+        fs::write(&binary, b"unit Binary;\0\x01\x02").unwrap();
+        let utf16 = root.join("Wide.pas");
+        fs::write(&utf16, [0xFF, 0xFE, b'u', 0]).unwrap();
+        let large = root.join("Large.pas");
+        fs::write(&large, "unit Large;\ninterface\nimplementation\nend.\n").unwrap();
+
+        let mut warnings = Warnings::new();
+        assert!(load_unit_file(&binary, &mut warnings).unwrap().is_none());
+        assert!(
+            read_source_file(&utf16, DEFAULT_MAX_FILE_SIZE, &mut warnings)
+                .unwrap()
+                .is_some()
+        );
+        let limited = |max_file_size| SourceOptions {
+            max_file_size,
+            ..SourceOptions::default()
+        };
+        let skipped = load_unit_file_with(&large, limited(16), &mut warnings).unwrap();
+        let unlimited = load_unit_file_with(&large, limited(0), &mut warnings).unwrap();
+
+        assert!(skipped.is_none());
+        assert_eq!(unlimited.expect("unit").name, "Large");
+        let codes: Vec<DiagnosticCode> = warnings.iter().map(|warning| warning.code).collect();
+        assert_eq!(
+            codes,
+            vec![DiagnosticCode::BinaryFile, DiagnosticCode::FileTooLarge]
+        );
     }
 
     #[test]
-    fn parse_unit_uses_allows_directives_inside_list() {
-        let src = br#"
-unit Demo;
-interface
-uses Foo, {$IFDEF X} Bar, {$ENDIF} Baz;
-implementation
-uses Qux;
-end.
-"#;
-        let deps = parse_uses_for_test(src);
-        assert_eq!(deps, vec!["Foo", "Bar", "Baz", "Qux"]);
+    fn cached_units_share_one_copy_of_each_used_name() {
+        let root = temp_dir();
+        for (name, uses) in [
+            ("Alpha", "Shared, Beta"),
+            ("Beta", "shared"),
+            ("Gamma", "Shared"),
+        ] {
+            fs::write(
+                root.join(format!("{name}.pas")),
+                format!("unit {name};\ninterface\nuses {uses};\nimplementation\nend.\n"),
+            )
+            .unwrap();
+        }
+        let paths: Vec<PathBuf> = ["Alpha", "Beta", "Gamma"]
+            .iter()
+            .map(|name| root.join(format!("{name}.pas")))
+            .collect();
+
+        let cache = build_unit_cache(&paths, &mut Warnings::new()).unwrap();
+
+        assert_eq!(cache.names.len(), 3);
+        let shared: Vec<&UnitName> = cache
+            .by_path
+            .values()
+            .flat_map(|info| &info.uses)
+            .map(|uses| &uses.name)
+            .filter(|name| *name == "Shared")
+            .collect();
+        assert_eq!(shared.len(), 2);
+        assert_eq!(shared[0].as_str().as_ptr(), shared[1].as_str().as_ptr());
+        let alpha = &cache.by_path[&canonicalize_if_exists(&paths[0])];
+        assert_eq!(
+            alpha.uses[0].name.as_str().as_ptr(),
+            alpha.conditional_uses[0].unit_name.as_str().as_ptr()
+        );
     }
 
     #[test]
-    fn parse_unit_uses_with_in_paths() {
-        let src = br#"
-unit Demo;
-interface
-uses Foo in 'Foo.pas', Bar in 'path\Bar.pas';
-implementation
-end.
-"#;
-        let deps = parse_uses_for_test(src);
-        assert_eq!(deps, vec!["Foo", "Bar"]);
+    fn parse_pool_builds_the_same_cache_as_a_sequential_parse() {
+        let root = temp_dir();
+        fs::write(
+            root.join("Alpha.pas"),
+            "unit Alpha;\ninterface\nuses Beta;\nimplementation\nuses Gamma;\nend.\n",
+        )
+        .unwrap();
+        fs::write(root.join("Beta.pas"), "interface\nuses Alpha;\nend.\n").unwrap();
+        fs::write(root.join("Binary.pas"), b"unit Binary;\0").unwrap();
+        fs::write(root.join("Uses.inc"), "Alpha,\n").unwrap();
+        fs::write(
+            root.join("Gamma.pas"),
+            "unit Gamma;\ninterface\nuses {$I Uses.inc} Beta;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        let paths: Vec<PathBuf> = ["Alpha", "Beta", "Binary", "Gamma"]
+            .iter()
+            .map(|name| root.join(format!("{name}.pas")))
+            .collect();
+
+        let mut sequential_warnings = Warnings::new();
+        let sequential = build_unit_cache(&paths, &mut sequential_warnings).unwrap();
+        let pool = ParsePool::start(3, SourceOptions::default(), HashMap::new());
+        for path in &paths {
+            pool.submit(path);
+        }
+        let prefetched = pool.finish();
+        assert_eq!(
+            prefetched.len(),
+            3,
+            "the unit with an include is left to the build"
+        );
+        let mut pooled_warnings = Warnings::new();
+        let pooled = build_unit_cache_with_events(
+            &paths,
+            SourceOptions::default(),
+            Includes::default(),
+            &prefetched,
+            &mut pooled_warnings,
+            &NoEvents,
+        )
+        .unwrap();
+
+        let summary = |cache: &UnitCache| {
+            let mut units: Vec<(PathBuf, String, Vec<UsesRef>)> = cache
+                .by_path
+                .iter()
+                .map(|(path, info)| (path.clone(), info.name.clone(), info.uses.clone()))
+                .collect();
+            units.sort_by(|left, right| left.0.cmp(&right.0));
+            units
+        };
+        assert_eq!(summary(&pooled), summary(&sequential));
+        assert_eq!(pooled.by_path.len(), 3);
+        assert_eq!(pooled_warnings.into_vec(), sequential_warnings.into_vec());
+        assert!(prefetched.is_empty());
     }
 
     #[test]
-    fn parse_unit_uses_supports_include_fragments() {
+    fn canonical_paths_count_hits_and_forget_written_paths() {
         let root = temp_dir();
-        let unit_path = root.join("Demo.pas");
-        let include_path = root.join("Uses.inc");
-        fs::write(&include_path, "Foo,\nBar in 'lib\\\\Bar.pas',\nBaz,").unwrap();
-        let src = br#"
-unit Demo;
-interface
-uses {$I Uses.inc} Qux;
-implementation
-end.
-"#;
-        let mut warnings = Vec::new();
-        let deps = parse_unit_uses(&unit_path, src, &mut warnings);
-        assert_eq!(deps, vec!["Foo", "Bar", "Baz", "Qux"]);
+        let created = root.join("Created.dpr");
+
+        let canonical = CanonicalPaths::default();
+        let before = canonical.canonicalize(&created);
+        canonical.canonicalize(&created);
+        fs::write(&created, "program Created;\nbegin\nend.\n").unwrap();
+        let stale = canonical.canonicalize(&created);
+        canonical.forget(&created);
+        let after = canonical.canonicalize(&created);
+
+        assert_eq!(before, created);
+        assert_eq!(stale, created);
+        assert_eq!(after, fs::canonicalize(&created).unwrap());
+        assert_eq!(canonical.stats(), (2, 2));
+        assert_eq!(CanonicalPaths::default().stats(), (0, 0));
     }
 
     #[test]
-    fn load_unit_file_uses_fallback_name() {
+    fn canonical_paths_forget_every_path_to_a_deleted_file() {
         let root = temp_dir();
-        let path = root.join("Fallback.pas");
-        fs::write(&path, "const X = 1;").unwrap();
-        let mut warnings = Vec::new();
-        let info = load_unit_file(&path, &mut warnings).unwrap().expect("unit");
-        assert_eq!(info.name, "Fallback");
-        assert!(!warnings.is_empty());
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::create_dir_all(root.join("lib")).unwrap();
+        let unit = root.join("lib/X.pas");
+        fs::write(&unit, "unit X;\ninterface\nimplementation\nend.\n").unwrap();
+        let unit = fs::canonicalize(&unit).unwrap();
+        let dotted = root.join("app/../lib/X.pas");
+
+        let canonical = CanonicalPaths::default();
+        assert_eq!(canonical.canonicalize(&dotted), unit);
+        assert_eq!(canonical.canonicalize(&unit), unit);
+        fs::remove_file(&unit).unwrap();
+        canonical.forget(&unit);
+
+        assert_eq!(canonical.canonicalize(&dotted), dotted);
+        assert_eq!(canonical.stats(), (0, 3));
     }
 
     fn temp_dir() -> PathBuf {
@@ -544,11 +1242,4 @@ end.
         fs::create_dir_all(&root).expect("create temp dir");
         root
     }
-
-    fn parse_uses_for_test(src: &[u8]) -> Vec<String> {
-        let root = temp_dir();
-        let unit_path = root.join("Demo.pas");
-        let mut warnings = Vec::new();
-        parse_unit_uses(&unit_path, src, &mut warnings)
-    }
 }
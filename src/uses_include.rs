@@ -1,44 +1,456 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::pas_lex;
+use crate::warnings::Warnings;
+
+/// Include parse results of one run, by canonical include path and parse mode.
+#[derive(Default)]
+struct IncludeMemo {
+    parsed: HashMap<(PathBuf, &'static str), Vec<MemoEntry>>,
+    hits: usize,
+}
+
+impl fmt::Debug for IncludeMemo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncludeMemo")
+            .field("includes", &self.parsed.len())
+            .field("hits", &self.hits)
+            .finish()
+    }
+}
+
+/// One parse of an include: the caller's key, its result, and the warnings it raised.
+struct MemoEntry {
+    key: Rc<dyn Any>,
+    value: Rc<dyn Any>,
+    warnings: Vec<Diagnostic>,
+}
+
+/// Scanned `.inc` files by lowercase file name.
+#[derive(Debug, Default)]
+pub struct IncludeIndex {
+    by_name: HashMap<String, Vec<PathBuf>>,
+}
+
+impl IncludeIndex {
+    pub fn from_paths(paths: &[PathBuf]) -> Self {
+        let mut by_name: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            if let Some(name) = path.file_name() {
+                by_name
+                    .entry(name.to_string_lossy().to_ascii_lowercase())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+        Self { by_name }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    /// Indexed files whose name matches the last component of `include_name`.
+    pub fn lookup(&self, include_name: &str) -> &[PathBuf] {
+        let name = include_name
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or(include_name)
+            .to_ascii_lowercase();
+        self.by_name.get(&name).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+/// How the `{$I}` directives of one project resolve.
+///
+/// An include that does not exist next to its source is looked up by name
+/// in the index of the project's scanned `.inc` files.
+#[derive(Debug, Default)]
+pub struct Includes {
+    index: Option<IncludeIndex>,
+    memo: Option<RefCell<IncludeMemo>>,
+    reads: Cell<usize>,
+    /// Canonical paths of the includes resolved while [`Includes::track`] runs.
+    tracked: RefCell<Option<BTreeSet<PathBuf>>>,
+}
+
+impl Includes {
+    /// Includes that fall back to the `.inc` files in `paths`, with the memo on.
+    pub fn from_paths(paths: &[PathBuf]) -> Self {
+        Self {
+            index: Some(IncludeIndex::from_paths(paths)),
+            ..Self::default().memoized()
+        }
+    }
+
+    /// Reuse include parse results for as long as these includes live.
+    ///
+    /// Files are assumed not to change meanwhile, so build new includes after an edit.
+    pub fn memoized(mut self) -> Self {
+        self.memo = Some(RefCell::default());
+        self
+    }
+
+    pub fn index(&self) -> Option<&IncludeIndex> {
+        self.index.as_ref()
+    }
+
+    /// Includes answered from the memo so far.
+    pub fn memo_hits(&self) -> usize {
+        self.memo.as_ref().map_or(0, |memo| memo.borrow().hits)
+    }
+
+    /// Runs `f`, also returning the canonical paths of the includes it
+    /// resolved, memo hits included, in sorted order.
+    pub fn track<T>(&self, f: impl FnOnce() -> T) -> (T, Vec<PathBuf>) {
+        let outer = self.tracked.replace(Some(BTreeSet::new()));
+        let value = f();
+        let inner = self.tracked.replace(outer).unwrap_or_default();
+        if let Some(outer) = self.tracked.borrow_mut().as_mut() {
+            outer.extend(inner.iter().cloned());
+        }
+        (value, inner.into_iter().collect())
+    }
+}
 
 pub fn with_include_bytes<T, F>(
     include_name: &str,
     source_path: &Path,
-    warnings: &mut Vec<String>,
+    directive_offset: usize,
+    includes: &Includes,
+    warnings: &mut Warnings,
     include_stack: &mut Vec<PathBuf>,
     f: F,
 ) -> Option<T>
 where
-    F: FnOnce(&Path, &[u8], &mut Vec<String>, &mut Vec<PathBuf>) -> T,
+    F: FnOnce(&Path, &[u8], &mut Warnings, &mut Vec<PathBuf>) -> T,
 {
-    let include_path = resolve_include_path(source_path, include_name);
+    let (include_path, canonical) = resolve_include(
+        include_name,
+        source_path,
+        directive_offset,
+        includes,
+        warnings,
+        include_stack,
+    )?;
+    read_and_parse(
+        &include_path,
+        canonical,
+        source_path,
+        directive_offset,
+        includes,
+        warnings,
+        include_stack,
+        f,
+    )
+}
+
+/// Like [`with_include_bytes`], but with the memo on, returns the result of an
+/// earlier parse of the same include in the same `mode` with an equal `key`,
+/// replaying its warnings instead of reading the file again.
+///
+/// `key` holds whatever caller state the parse depends on. Includes that pull
+/// in further includes are never memoized, since cycle checks depend on the
+/// includer chain.
+#[allow(clippy::too_many_arguments)]
+pub fn with_memoized_include<K, T, F>(
+    include_name: &str,
+    source_path: &Path,
+    directive_offset: usize,
+    includes: &Includes,
+    warnings: &mut Warnings,
+    include_stack: &mut Vec<PathBuf>,
+    mode: &'static str,
+    key: K,
+    f: F,
+) -> Option<T>
+where
+    K: PartialEq + 'static,
+    T: Clone + 'static,
+    F: FnOnce(&Path, &[u8], &mut Warnings, &mut Vec<PathBuf>) -> T,
+{
+    let (include_path, canonical) = resolve_include(
+        include_name,
+        source_path,
+        directive_offset,
+        includes,
+        warnings,
+        include_stack,
+    )?;
+    let Some(memo) = &includes.memo else {
+        return read_and_parse(
+            &include_path,
+            canonical,
+            source_path,
+            directive_offset,
+            includes,
+            warnings,
+            include_stack,
+            f,
+        );
+    };
+    let memo_key = (canonical.clone(), mode);
+    let cached = {
+        let mut memo = memo.borrow_mut();
+        let found = memo.parsed.get(&memo_key).and_then(|entries| {
+            let entry = entries.iter().find(|entry| {
+                entry
+                    .key
+                    .downcast_ref::<K>()
+                    .is_some_and(|known| *known == key)
+            })?;
+            Some((
+                entry.value.downcast_ref::<T>()?.clone(),
+                entry.warnings.clone(),
+            ))
+        });
+        if found.is_some() {
+            memo.hits += 1;
+        }
+        found
+    };
+    if let Some((value, replayed)) = cached {
+        warnings.extend(replayed);
+        return Some(value);
+    }
+
+    let reads_before = includes.reads.get();
+    let mut parse_warnings = Warnings::new();
+    let value = read_and_parse(
+        &include_path,
+        canonical,
+        source_path,
+        directive_offset,
+        includes,
+        &mut parse_warnings,
+        include_stack,
+        f,
+    );
+    let parse_warnings = parse_warnings.into_vec();
+    // Only this include was read, so nothing in the result depends on the includer chain.
+    let leaf = includes.reads.get() == reads_before + 1;
+    if let (Some(value), true) = (&value, leaf) {
+        memo.borrow_mut()
+            .parsed
+            .entry(memo_key)
+            .or_default()
+            .push(MemoEntry {
+                key: Rc::new(key),
+                value: Rc::new(value.clone()),
+                warnings: parse_warnings.clone(),
+            });
+    }
+    warnings.extend(parse_warnings);
+    value
+}
+
+/// Finds the file a `{$I}` directive names and rejects include cycles,
+/// returning the path to read and its canonical form.
+fn resolve_include(
+    include_name: &str,
+    source_path: &Path,
+    directive_offset: usize,
+    includes: &Includes,
+    warnings: &mut Warnings,
+    include_stack: &[PathBuf],
+) -> Option<(PathBuf, PathBuf)> {
+    let mut include_path = resolve_include_path(source_path, include_name);
+    if !include_path.exists() {
+        if let Some(index) = includes.index() {
+            match index.lookup(include_name) {
+                [] => {
+                    warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::IncludeNotFound,
+                            format!("include {include_name} exists nowhere under the search roots"),
+                        )
+                        .referenced_from(source_path, Some(directive_offset)),
+                    );
+                    return None;
+                }
+                [single] => include_path = single.clone(),
+                matches => {
+                    warnings.push(
+                        Diagnostic::warning(
+                            DiagnosticCode::IncludeAmbiguous,
+                            format!(
+                                "include {include_name} referenced by {} is ambiguous ({} indexed matches)",
+                                source_path.display(),
+                                matches.len()
+                            ),
+                        )
+                        .at_offset(source_path, Some(directive_offset)),
+                    );
+                    return None;
+                }
+            }
+        }
+    }
     let canonical = canonicalize_if_exists(&include_path);
     if include_stack.contains(&canonical) {
-        warnings.push(format!(
-            "warning: include cycle detected for {} (from {})",
-            include_path.display(),
-            source_path.display()
-        ));
+        warnings.push(
+            Diagnostic::warning(
+                DiagnosticCode::IncludeCycle,
+                format!(
+                    "include cycle detected for {} (from {})",
+                    include_path.display(),
+                    source_path.display()
+                ),
+            )
+            .at_offset(source_path, Some(directive_offset)),
+        );
         return None;
     }
-    let bytes = match fs::read(&include_path) {
+    if let Some(tracked) = includes.tracked.borrow_mut().as_mut() {
+        tracked.insert(canonical.clone());
+    }
+    Some((include_path, canonical))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn read_and_parse<T, F>(
+    include_path: &Path,
+    canonical: PathBuf,
+    source_path: &Path,
+    directive_offset: usize,
+    includes: &Includes,
+    warnings: &mut Warnings,
+    include_stack: &mut Vec<PathBuf>,
+    f: F,
+) -> Option<T>
+where
+    F: FnOnce(&Path, &[u8], &mut Warnings, &mut Vec<PathBuf>) -> T,
+{
+    includes.reads.set(includes.reads.get() + 1);
+    let bytes = match fs::read(include_path) {
         Ok(data) => data,
         Err(err) => {
-            warnings.push(format!(
-                "warning: failed to read include {} referenced by {}: {err}",
-                include_path.display(),
-                source_path.display()
-            ));
+            warnings.push(
+                Diagnostic::warning(
+                    DiagnosticCode::IncludeNotFound,
+                    format!("failed to read include {}: {err}", include_path.display()),
+                )
+                .referenced_from(source_path, Some(directive_offset)),
+            );
             return None;
         }
     };
 
     include_stack.push(canonical);
-    let result = f(&include_path, &bytes, warnings, include_stack);
+    let result = f(include_path, &bytes, warnings, include_stack);
     include_stack.pop();
     Some(result)
 }
 
+/// Which files name each include file in a `{$I}` directive, so the users of
+/// a shared include can be listed before it is changed.
+#[derive(Debug, Default)]
+pub struct IncludeUsers {
+    by_include: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl IncludeUsers {
+    /// Reads every `{$I}` directive in `sources` outside strings and comments.
+    ///
+    /// Includes are resolved like [`with_include_bytes`] does, including the
+    /// index fallback; directives that resolve to no single file are skipped,
+    /// as are sources that cannot be read.
+    pub fn build(sources: &[PathBuf], includes: &Includes) -> Self {
+        let mut users = Self::default();
+        for source in sources {
+            let Ok(bytes) = fs::read(source) else {
+                continue;
+            };
+            let source = canonicalize_if_exists(source);
+            for include_name in include_directives(&bytes) {
+                let Some(include_path) = locate_include(&source, &include_name, includes) else {
+                    continue;
+                };
+                let entry = users
+                    .by_include
+                    .entry(canonicalize_if_exists(&include_path))
+                    .or_default();
+                if !entry.contains(&source) {
+                    entry.push(source.clone());
+                }
+            }
+        }
+        users
+    }
+
+    /// Files that include `include_path`, directly or through other include
+    /// files, sorted; the include files in between are listed too.
+    pub fn users(&self, include_path: &Path) -> Vec<PathBuf> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![canonicalize_if_exists(include_path)];
+        let mut found = Vec::new();
+        while let Some(include) = pending.pop() {
+            for user in self.by_include.get(&include).into_iter().flatten() {
+                if seen.insert(user.clone()) {
+                    found.push(user.clone());
+                    pending.push(user.clone());
+                }
+            }
+        }
+        found.sort();
+        found
+    }
+}
+
+/// Whether `bytes` holds any `{$I}` directive outside comments and strings.
+pub fn has_include_directive(bytes: &[u8]) -> bool {
+    !include_directives(bytes).is_empty()
+}
+
+/// Names of the `{$I}` directives in `bytes`, in file order.
+fn include_directives(bytes: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut i = pas_lex::skip_bom(bytes);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'(' => {
+                if let Some((name, end)) = pas_lex::parse_include_directive(bytes, i) {
+                    names.push(name);
+                    i = end;
+                } else if bytes[i] == b'{' {
+                    i = pas_lex::skip_brace_comment(bytes, i + 1);
+                } else if bytes.get(i + 1) == Some(&b'*') {
+                    i = pas_lex::skip_paren_comment(bytes, i + 2);
+                } else {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = pas_lex::skip_line_comment(bytes, i + 2),
+            b'\'' => i = pas_lex::skip_string(bytes, i + 1),
+            _ => i += 1,
+        }
+    }
+    names
+}
+
+/// The file `include_name` refers to from `source_path`, without warnings.
+fn locate_include(source_path: &Path, include_name: &str, includes: &Includes) -> Option<PathBuf> {
+    let include_path = resolve_include_path(source_path, include_name);
+    if include_path.exists() {
+        return Some(include_path);
+    }
+    match includes.index()?.lookup(include_name) {
+        [single] => Some(single.clone()),
+        _ => None,
+    }
+}
+
 pub fn resolve_include_path(source_path: &Path, include: &str) -> PathBuf {
     let candidate = PathBuf::from(include);
     if candidate.is_absolute() {
@@ -52,5 +464,242 @@ pub fn resolve_include_path(source_path: &Path, include: &str) -> PathBuf {
 }
 
 fn canonicalize_if_exists(path: &Path) -> PathBuf {
-    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    crate::unit_cache::canonicalize_if_exists(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn read_include(
+        source: &Path,
+        name: &str,
+        includes: &Includes,
+        warnings: &mut Warnings,
+    ) -> Option<String> {
+        with_include_bytes(
+            name,
+            source,
+            0,
+            includes,
+            warnings,
+            &mut Vec::new(),
+            |_, bytes, _, _| String::from_utf8_lossy(bytes).into_owned(),
+        )
+    }
+
+    #[test]
+    fn index_resolves_missing_relative_include_and_reports_problems() {
+        let root = temp_dir();
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::create_dir_all(root.join("inc/a")).unwrap();
+        fs::create_dir_all(root.join("inc/b")).unwrap();
+        fs::write(root.join("inc/a/Uses.inc"), "Foo,").unwrap();
+        fs::write(root.join("inc/a/Twice.inc"), "A,").unwrap();
+        fs::write(root.join("inc/b/Twice.inc"), "B,").unwrap();
+        let source = root.join("app/App.dpr");
+        let mut warnings = Warnings::new();
+
+        assert_eq!(
+            read_include(&source, "Uses.inc", &Includes::default(), &mut warnings),
+            None
+        );
+        assert_eq!(warnings.len(), 1);
+
+        let includes = Includes::from_paths(&[
+            root.join("inc/a/Uses.inc"),
+            root.join("inc/a/Twice.inc"),
+            root.join("inc/b/Twice.inc"),
+        ]);
+        let mut warnings = Warnings::new();
+        let found = read_include(&source, "shared\\USES.inc", &includes, &mut warnings);
+        let twice = read_include(&source, "Twice.inc", &includes, &mut warnings);
+        let typo = read_include(&source, "Usse.inc", &includes, &mut warnings);
+
+        assert_eq!(found.as_deref(), Some("Foo,"));
+        assert_eq!(twice, None);
+        assert_eq!(typo, None);
+        let codes: Vec<_> = warnings.iter().map(|warning| warning.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                DiagnosticCode::IncludeAmbiguous,
+                DiagnosticCode::IncludeNotFound
+            ]
+        );
+        assert!(
+            warnings.iter().any(|warning| warning.message
+                == "include Usse.inc exists nowhere under the search roots"
+                && warning.referenced_by.as_deref() == Some(source.as_path())),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn memo_reuses_leaf_includes_and_replays_their_warnings() {
+        let root = temp_dir();
+        fs::write(root.join("Shared.inc"), "Foo,").unwrap();
+        fs::write(root.join("Outer.inc"), "{$I Shared.inc}").unwrap();
+        let includes = Includes::default().memoized();
+        let parse = |source: &Path, name: &str, key: u8, warnings: &mut Warnings| {
+            with_memoized_include(
+                name,
+                source,
+                0,
+                &includes,
+                warnings,
+                &mut vec![source.to_path_buf()],
+                "test",
+                key,
+                |include_path, bytes, warnings, _| {
+                    warnings.push(
+                        Diagnostic::warning(DiagnosticCode::IncludeNotFound, "parsed")
+                            .at(include_path),
+                    );
+                    String::from_utf8_lossy(bytes).into_owned()
+                },
+            )
+        };
+
+        let mut warnings = Warnings::new();
+        let first = parse(&root.join("A.pas"), "Shared.inc", 0, &mut warnings);
+        fs::write(root.join("Shared.inc"), "Changed,").unwrap();
+        let second = parse(&root.join("B.pas"), "Shared.inc", 0, &mut warnings);
+        let other_key = parse(&root.join("B.pas"), "Shared.inc", 1, &mut warnings);
+        let hits = includes.memo_hits();
+
+        assert_eq!(first.as_deref(), Some("Foo,"));
+        assert_eq!(second.as_deref(), Some("Foo,"));
+        assert_eq!(other_key.as_deref(), Some("Changed,"));
+        assert_eq!(hits, 1);
+        assert_eq!(warnings.len(), 3);
+
+        let mut warnings = Warnings::new();
+        let includes = Includes::default().memoized();
+        let nested = |warnings: &mut Warnings| {
+            with_memoized_include(
+                "Outer.inc",
+                &root.join("A.pas"),
+                0,
+                &includes,
+                warnings,
+                &mut Vec::new(),
+                "test",
+                (),
+                |include_path, _, warnings, include_stack| {
+                    with_include_bytes(
+                        "Shared.inc",
+                        include_path,
+                        0,
+                        &includes,
+                        warnings,
+                        include_stack,
+                        |_, bytes, _, _| bytes.len(),
+                    )
+                },
+            )
+        };
+        nested(&mut warnings);
+        nested(&mut warnings);
+        let hits = includes.memo_hits();
+        assert_eq!(hits, 0, "includes with nested includes are not memoized");
+    }
+
+    #[test]
+    fn track_reports_the_includes_resolved_while_it_runs() {
+        let root = temp_dir();
+        fs::write(root.join("Shared.inc"), "Foo,").unwrap();
+        fs::write(root.join("Outer.inc"), "{$I Shared.inc}").unwrap();
+        let includes = Includes::default().memoized();
+        let source = root.join("A.pas");
+        let read = |name: &str, warnings: &mut Warnings| {
+            with_memoized_include(
+                name,
+                &source,
+                0,
+                &includes,
+                warnings,
+                &mut Vec::new(),
+                "test",
+                (),
+                |include_path, bytes, warnings, include_stack| {
+                    if bytes.starts_with(b"{$I") {
+                        with_include_bytes(
+                            "Shared.inc",
+                            include_path,
+                            0,
+                            &includes,
+                            warnings,
+                            include_stack,
+                            |_, _, _, _| (),
+                        );
+                    }
+                },
+            )
+        };
+        let mut warnings = Warnings::new();
+
+        let (_, outer) = includes.track(|| read("Outer.inc", &mut warnings));
+        read("Shared.inc", &mut warnings);
+        let (_, memoized) = includes.track(|| {
+            let (_, nested) = includes.track(|| read("Shared.inc", &mut warnings));
+            assert_eq!(nested.len(), 1);
+        });
+
+        let canonical = |name: &str| fs::canonicalize(root.join(name)).unwrap();
+        assert_eq!(outer, {
+            let mut expected = vec![canonical("Outer.inc"), canonical("Shared.inc")];
+            expected.sort();
+            expected
+        });
+        assert_eq!(includes.memo_hits(), 1);
+        assert_eq!(memoized, vec![canonical("Shared.inc")]);
+    }
+
+    #[test]
+    fn include_users_follow_nested_includes_and_skip_strings() {
+        let root = temp_dir();
+        fs::create_dir_all(root.join("inc")).unwrap();
+        fs::write(root.join("inc/Shared.inc"), "Foo,").unwrap();
+        fs::write(root.join("inc/Outer.inc"), "{$I Shared.inc} Bar,").unwrap();
+        fs::write(
+            root.join("App.dpr"),
+            "program App;\nuses {$I inc/Outer.inc} Baz;\nbegin\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("Direct.pas"),
+            "unit Direct;\ninterface\nuses {$INCLUDE inc/Shared.inc} Baz;\nimplementation\nend.\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("Quoted.pas"),
+            "unit Quoted;\ninterface\nconst S = '{$I inc/Shared.inc}';\n// {$I inc/Shared.inc}\nimplementation\nend.\n",
+        )
+        .unwrap();
+        let sources: Vec<PathBuf> = ["App.dpr", "Direct.pas", "Quoted.pas", "inc/Outer.inc"]
+            .iter()
+            .map(|name| root.join(name))
+            .collect();
+
+        let users =
+            IncludeUsers::build(&sources, &Includes::default()).users(&root.join("inc/Shared.inc"));
+        let names: Vec<String> = users
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["App.dpr", "Direct.pas", "Outer.inc"]);
+    }
+
+    fn temp_dir() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let root = env::temp_dir().join(format!("fixdpr_include_index_{nanos}"));
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
 }
@@ -0,0 +1,250 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+
+#[derive(Clone, Debug, Default)]
+pub struct Warnings {
+    entries: Vec<(Diagnostic, usize)>,
+    index: HashMap<(DiagnosticCode, String), usize>,
+    /// Each occurrence's group, and its own copy when it names a referencer.
+    occurrences: Vec<(usize, Option<usize>)>,
+    referenced: Vec<Diagnostic>,
+    referencers: Vec<Vec<PathBuf>>,
+    seen_referencers: HashSet<(usize, PathBuf)>,
+}
+
+/// Referencers a grouped warning names unless `--show-all-referencers` is given.
+pub const EXAMPLE_REFERENCERS: usize = 3;
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        let key = (diagnostic.code, diagnostic.message.clone());
+        let slot = match self.index.get(&key) {
+            Some(&slot) => slot,
+            None => {
+                let slot = self.entries.len();
+                self.index.insert(key, slot);
+                self.entries.push((diagnostic.clone(), 0));
+                self.referencers.push(Vec::new());
+                slot
+            }
+        };
+        let mut copy = None;
+        if let Some(referencer) = &diagnostic.referenced_by {
+            if self.seen_referencers.insert((slot, referencer.clone())) {
+                self.referencers[slot].push(referencer.clone());
+            }
+            copy = Some(self.referenced.len());
+            self.referenced.push(diagnostic);
+        }
+        self.entries[slot].1 += 1;
+        self.occurrences.push((slot, copy));
+    }
+
+    pub fn len(&self) -> usize {
+        self.occurrences.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.occurrences.is_empty()
+    }
+
+    pub fn unique_len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.occurrences.iter().map(|&(slot, copy)| match copy {
+            Some(index) => &self.referenced[index],
+            None => &self.entries[slot].0,
+        })
+    }
+
+    pub fn grouped(&self) -> impl Iterator<Item = (&Diagnostic, usize)> {
+        self.entries
+            .iter()
+            .map(|(diagnostic, count)| (diagnostic, *count))
+    }
+
+    /// [`Warnings::grouped`] with the distinct files that raised each warning, in first-seen order.
+    pub fn grouped_with_referencers(
+        &self,
+    ) -> impl Iterator<Item = (&Diagnostic, usize, &[PathBuf])> {
+        self.grouped()
+            .zip(&self.referencers)
+            .map(|((diagnostic, count), referencers)| (diagnostic, count, referencers.as_slice()))
+    }
+
+    pub fn without(&self, suppressed: &[DiagnosticCode]) -> Warnings {
+        let mut kept = Warnings::new();
+        kept.extend(
+            self.iter()
+                .filter(|diagnostic| !suppressed.contains(&diagnostic.code))
+                .cloned(),
+        );
+        kept
+    }
+
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl Extend<Diagnostic> for Warnings {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, iter: I) {
+        for diagnostic in iter {
+            self.push(diagnostic);
+        }
+    }
+}
+
+impl IntoIterator for Warnings {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+pub fn format_grouped(diagnostic: &Diagnostic, count: usize) -> String {
+    if count > 1 {
+        format!("{diagnostic} ({count} occurrences)")
+    } else {
+        diagnostic.to_string()
+    }
+}
+
+/// [`format_grouped`] for a warning raised by several referencers, naming up to
+/// `limit` of them; `None` names every one.
+pub fn format_referenced(
+    diagnostic: &Diagnostic,
+    count: usize,
+    referencers: &[PathBuf],
+    limit: Option<usize>,
+) -> String {
+    if referencers.len() < 2 {
+        return format_grouped(diagnostic, count);
+    }
+    let shown = limit.map_or(referencers.len(), |limit| limit.min(referencers.len()));
+    let names: Vec<String> = referencers[..shown]
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    let mut listed = names.join(", ");
+    if shown < referencers.len() {
+        listed = format!("{listed} and {} more", referencers.len() - shown);
+    }
+    format!(
+        "{}: {} (referenced by {} files: {listed})",
+        diagnostic.severity.label(),
+        diagnostic.message,
+        referencers.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warning(code: DiagnosticCode, message: &str) -> Diagnostic {
+        Diagnostic::warning(code, message)
+    }
+
+    fn texts<'a>(items: impl Iterator<Item = &'a Diagnostic>) -> Vec<String> {
+        items.map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn push_counts_identical_code_and_message_once() {
+        let mut warnings = Warnings::new();
+        warnings.push(warning(DiagnosticCode::AmbiguousUnit, "a"));
+        warnings.push(warning(DiagnosticCode::AmbiguousUnit, "b"));
+        warnings.push(warning(DiagnosticCode::AmbiguousUnit, "a"));
+        warnings.push(warning(DiagnosticCode::MissingInPath, "a"));
+
+        assert_eq!(warnings.len(), 4);
+        assert_eq!(warnings.unique_len(), 3);
+        let counts: Vec<usize> = warnings.grouped().map(|(_, count)| count).collect();
+        assert_eq!(counts, vec![2, 1, 1]);
+        assert_eq!(
+            texts(warnings.iter()),
+            vec!["warning: a", "warning: b", "warning: a", "warning: a"]
+        );
+    }
+
+    #[test]
+    fn extend_keeps_counts_across_collectors() {
+        let mut first = Warnings::new();
+        first.push(warning(DiagnosticCode::ReadFailed, "a"));
+        let mut second = Warnings::new();
+        second.push(warning(DiagnosticCode::ReadFailed, "a"));
+        second.push(warning(DiagnosticCode::ReadFailed, "c"));
+
+        first.extend(second);
+        let grouped: Vec<(String, usize)> = first
+            .grouped()
+            .map(|(diagnostic, count)| (diagnostic.message.clone(), count))
+            .collect();
+        assert_eq!(grouped, vec![("a".to_string(), 2), ("c".to_string(), 1)]);
+    }
+
+    #[test]
+    fn without_drops_suppressed_codes() {
+        let mut warnings = Warnings::new();
+        warnings.push(warning(DiagnosticCode::ReadFailed, "a"));
+        warnings.push(warning(DiagnosticCode::AmbiguousUnit, "b"));
+
+        let kept = warnings.without(&[DiagnosticCode::AmbiguousUnit]);
+        assert_eq!(texts(kept.iter()), vec!["warning: a"]);
+    }
+
+    #[test]
+    fn include_warnings_group_by_include_across_referencers() {
+        let mut warnings = Warnings::new();
+        for name in ["A.pas", "B.pas", "A.pas", "C.pas", "D.pas"] {
+            warnings.push(
+                warning(
+                    DiagnosticCode::IncludeNotFound,
+                    "include X.inc exists nowhere",
+                )
+                .referenced_from(&PathBuf::from(name), None),
+            );
+        }
+
+        assert_eq!(warnings.unique_len(), 1);
+        assert_eq!(
+            texts(warnings.iter().skip(3)),
+            vec![
+                "warning: include X.inc exists nowhere (referenced by C.pas)",
+                "warning: include X.inc exists nowhere (referenced by D.pas)"
+            ]
+        );
+        let (diagnostic, count, referencers) =
+            warnings.grouped_with_referencers().next().expect("group");
+        assert_eq!(count, 5);
+        assert_eq!(
+            format_referenced(diagnostic, count, referencers, Some(EXAMPLE_REFERENCERS)),
+            "warning: include X.inc exists nowhere (referenced by 4 files: A.pas, B.pas, C.pas and 1 more)"
+        );
+        assert_eq!(
+            format_referenced(diagnostic, count, referencers, None),
+            "warning: include X.inc exists nowhere (referenced by 4 files: A.pas, B.pas, C.pas, D.pas)"
+        );
+    }
+
+    #[test]
+    fn format_grouped_appends_count_only_for_repeats() {
+        let diagnostic = warning(DiagnosticCode::ReadFailed, "a");
+        assert_eq!(format_grouped(&diagnostic, 1), "warning: a");
+        assert_eq!(
+            format_grouped(&diagnostic, 912),
+            "warning: a (912 occurrences)"
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use std::env;
 use std::fs;
+use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[test]
@@ -40,15 +41,31 @@ fn end_to_end_updates_expected_dprs() {
         .arg(&new_dependency)
         .arg("--ignore-path")
         .arg(temp_root.join("ignored"))
+        .arg("--show-warnings")
         .output()
         .expect("run fixdpr");
 
+    let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(
         output.status.success(),
         "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&output.stdout),
+        stdout,
         String::from_utf8_lossy(&output.stderr)
     );
+    assert!(
+        stdout.contains("Skipped 1 folder(s) named .git, .svn, __history, __recovery, backup"),
+        "{stdout}"
+    );
+    assert!(
+        !stdout.contains("is ambiguous (2 project matches)"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "  dpr with new dependency inserted: 2\n  dpr already listing new dependency: 1\n  dpr not needing new dependency: 1\n"
+        ),
+        "{stdout}"
+    );
 
     let expected_root = repo_root
         .join("tests")
@@ -77,6 +94,204 @@ fn end_to_end_updates_expected_dprs() {
     }
 }
 
+#[test]
+fn end_to_end_jobs_parses_units_while_walking_with_the_same_result() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_expected");
+    let temp_root = temp_dir("fixdpr_e2e_jobs_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--ignore-path")
+        .arg(temp_root.join("ignored"))
+        .arg("--jobs")
+        .arg("4")
+        .output()
+        .expect("run fixdpr");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    for rel_path in [
+        PathBuf::from("app1").join("App1.dpr"),
+        PathBuf::from("app2").join("App2.dpr"),
+        PathBuf::from("app3").join("App3.dpr"),
+        PathBuf::from("app4").join("App4.dpr"),
+        PathBuf::from("ignored").join("Ignored.dpr"),
+    ] {
+        let actual =
+            normalize_newlines(fs::read_to_string(temp_root.join(&rel_path)).expect("read actual"));
+        let expected = normalize_newlines(
+            fs::read_to_string(expected_root.join(&rel_path)).expect("read expected"),
+        );
+        assert_eq!(actual, expected, "mismatch for {}", rel_path.display());
+    }
+}
+
+#[test]
+fn end_to_end_backslash_in_path_is_not_ambiguous_with_history_copies() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_backslash_history_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg("--ignore-path")
+        .arg(temp_root.join("ignored"))
+        .arg("--no-default-ignores")
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    // App3 lists NewUnit in '..\common\NewUnit.pas', which resolves on every
+    // platform, so the __history copy of NewUnit is never looked up by name.
+    assert!(!stdout.contains("is ambiguous"), "{stdout}");
+    assert!(
+        stdout.contains("  dpr already listing new dependency: 1\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_no_default_ignores_scans_history_folders() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_no_default_ignores_");
+    copy_dir(&fixture_root, &temp_root);
+    // A broken in-path makes NewUnit resolve by name, which sees the
+    // __history copy as a second match once that folder is scanned.
+    fs::create_dir_all(temp_root.join("app5")).expect("create app5");
+    fs::write(
+        temp_root.join("app5").join("App5.dpr"),
+        "program App5;\nuses\n  NewUnit in 'moved\\NewUnit.pas';\nbegin\nend.\n",
+    )
+    .expect("write App5.dpr");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg("--ignore-path")
+        .arg(temp_root.join("ignored"))
+        .arg("--no-default-ignores")
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipped "), "{stdout}");
+    assert!(
+        stdout.contains("is ambiguous (2 project matches)"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_updates_lazarus_lpr() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("lazarus_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("lazarus_expected");
+    let temp_root = temp_dir("fixdpr_e2e_lazarus_add_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--search-path")
+        .arg(&temp_root)
+        .output()
+        .expect("run fixdpr add-dependency on lpr");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Updated dpr files (1):"), "{stdout}");
+
+    let actual =
+        fs::read_to_string(temp_root.join("app").join("LazApp.lpr")).expect("read actual lpr");
+    let expected = fs::read_to_string(expected_root.join("app").join("LazApp.lpr"))
+        .expect("read expected lpr");
+    assert!(
+        !actual.contains('\r'),
+        "LF endings should be kept:\n{actual}"
+    );
+    assert_eq!(normalize_newlines(actual), normalize_newlines(expected));
+}
+
+#[test]
+fn end_to_end_fix_dpr_repairs_lazarus_lpr() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("lazarus_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("lazarus_expected");
+    let temp_root = temp_dir("fixdpr_e2e_lazarus_fix_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let target = temp_root.join("app").join("LazApp.lpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(&target)
+        .arg("--search-path")
+        .arg(&temp_root)
+        .output()
+        .expect("run fixdpr fix-dpr on lpr");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let actual = normalize_newlines(fs::read_to_string(&target).expect("read actual lpr"));
+    let expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app").join("LazApp.lpr"))
+            .expect("read expected lpr"),
+    );
+    assert_eq!(actual, expected);
+}
+
 #[test]
 fn end_to_end_add_dependency_uses_conditional_dependents_by_default() {
     let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -267,217 +482,621 @@ fn end_to_end_search_path_can_be_repeated_for_multiple_roots() {
 }
 
 #[test]
-fn end_to_end_search_path_dedupes_overlapping_roots() {
+fn end_to_end_report_breaks_counts_down_per_search_root() {
     let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let fixture_root = repo_root
         .join("tests")
         .join("fixtures")
         .join("synthetic_repo");
-    let expected_root = repo_root
-        .join("tests")
-        .join("fixtures")
-        .join("synthetic_expected");
-    let temp_root = temp_dir("fixdpr_e2e_glob_search_");
+    let temp_root = temp_dir("fixdpr_e2e_root_breakdown_");
     copy_dir(&fixture_root, &temp_root);
+    let empty_root = temp_root.join("empty");
+    fs::create_dir_all(&empty_root).expect("create empty root");
 
-    let new_dependency = temp_root.join("common").join("NewUnit.pas");
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
         .arg("add-dependency")
         .arg("--search-path")
-        .arg(&temp_root)
-        .arg("--search-path")
         .arg(temp_root.join("app1"))
-        .arg(&new_dependency)
-        .arg("--ignore-path")
-        .arg(temp_root.join("ignored"))
+        .arg("--search-path")
+        .arg(temp_root.join("app2"))
+        .arg("--search-path")
+        .arg(&empty_root)
+        .arg(temp_root.join("common").join("NewUnit.pas"))
         .output()
         .expect("run fixdpr");
 
-    assert!(
-        output.status.success(),
-        "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&output.stdout),
-        String::from_utf8_lossy(&output.stderr)
-    );
-
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("dpr scanned: 4"), "{stdout}");
-
-    let app1_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1 actual"),
-    );
-    let app1_expected = normalize_newlines(
-        fs::read_to_string(expected_root.join("app1").join("App1.dpr"))
-            .expect("read app1 expected"),
-    );
-    assert_eq!(app1_actual, app1_expected, "app1 should be updated");
-
-    let app2_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app2").join("App2.dpr")).expect("read app2 actual"),
-    );
-    let app2_expected = normalize_newlines(
-        fs::read_to_string(expected_root.join("app2").join("App2.dpr"))
-            .expect("read app2 expected"),
-    );
-    assert_eq!(app2_actual, app2_expected, "app2 should be updated");
-
-    let app3_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app3").join("App3.dpr")).expect("read app3 actual"),
-    );
-    let app3_expected = normalize_newlines(
-        fs::read_to_string(expected_root.join("app3").join("App3.dpr"))
-            .expect("read app3 expected"),
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("Search roots (3):"), "{stdout}");
+    assert!(
+        stdout.contains(&format!(
+            "  {}: pas scanned 2, dpr scanned 1, dpr updated 1",
+            temp_root.join("app1").display()
+        )),
+        "{stdout}"
     );
-    assert_eq!(app3_actual, app3_expected, "app3 should be updated");
-
-    let app4_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4 actual"),
+    assert!(
+        stdout.contains(&format!(
+            "  {}: pas scanned 1, dpr scanned 1, dpr updated 0",
+            temp_root.join("app2").display()
+        )),
+        "{stdout}"
     );
-    let app4_expected = normalize_newlines(
-        fs::read_to_string(expected_root.join("app4").join("App4.dpr"))
-            .expect("read app4 expected"),
+    assert!(
+        stdout.contains(&format!("  {}: nothing found", empty_root.display())),
+        "{stdout}"
     );
-    assert_eq!(app4_actual, app4_expected, "app4 should be updated");
 }
 
 #[test]
-fn end_to_end_search_path_requires_existing_directory() {
+fn end_to_end_write_updated_list_names_each_updated_dpr() {
     let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let fixture_root = repo_root
         .join("tests")
         .join("fixtures")
         .join("synthetic_repo");
-    let temp_root = temp_dir("fixdpr_e2e_search_warn_");
+    let temp_root = temp_dir("fixdpr_e2e_updated_list_");
     copy_dir(&fixture_root, &temp_root);
+    let list_path = temp_root.join("updated.txt");
+
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg("--search-path")
+            .arg(temp_root.join("app1"))
+            .arg("--search-path")
+            .arg(temp_root.join("app2"))
+            .arg(temp_root.join("common").join("NewUnit.pas"))
+            .arg("--write-updated-list")
+            .arg(&list_path)
+            .arg("--no-cache")
+            .output()
+            .expect("run fixdpr")
+    };
+
+    let output = run();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    let list = fs::read_to_string(&list_path).expect("read updated list");
+    assert_eq!(
+        list,
+        format!("{}\n", temp_root.join("app1").join("App1.dpr").display())
+    );
+    assert!(
+        !temp_root.join("app1").join("App1.dpr.lock").exists(),
+        "the dpr lock file is removed after the edit"
+    );
+
+    let output = run();
+    assert!(output.status.success());
+    let list = fs::read_to_string(&list_path).expect("read updated list");
+    assert_eq!(list, "", "a run that changes nothing writes an empty list");
 
-    let matched_root = temp_root.clone();
-    let missing_path = temp_root.join("missing");
-    let new_dependency = temp_root.join("common").join("NewUnit.pas");
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
         .arg("add-dependency")
         .arg("--search-path")
-        .arg(&matched_root)
-        .arg("--search-path")
-        .arg(&missing_path)
-        .arg(&new_dependency)
+        .arg(temp_root.join("app1"))
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--write-updated-list")
+        .arg(temp_root.join("app1"))
+        .arg("--show-warnings")
         .output()
         .expect("run fixdpr");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("failed to write updated list"), "{stdout}");
+}
 
+#[test]
+fn end_to_end_add_dependency_retargets_a_differently_pathed_entry() {
+    let root = temp_dir("fixdpr_e2e_retarget_");
+    fs::create_dir_all(root.join("legacy")).expect("create legacy dir");
+    fs::create_dir_all(root.join("common")).expect("create common dir");
+    let source = "program App;\nuses\n  NewUnit in 'legacy\\NewUnit.pas';\nbegin\nend.\n";
+    write_file(&root, "App.dpr", source);
+    let unit = "unit NewUnit;\ninterface\nimplementation\nend.\n";
+    write_file(&root, "legacy/NewUnit.pas", unit);
+    write_file(&root, "common/NewUnit.pas", unit);
+
+    let run = |retarget: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .arg("add-dependency")
+            .arg(root.join("common").join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--show-warnings")
+            .arg("--no-cache");
+        if retarget {
+            command.arg("--retarget-existing");
+        }
+        command.output().expect("run fixdpr")
+    };
+
+    let output = run(false);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
     assert!(
-        !output.status.success(),
-        "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&output.stdout),
-        String::from_utf8_lossy(&output.stderr)
+        stdout.contains("App.dpr lists NewUnit in 'legacy\\NewUnit.pas'"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("--retarget-existing"), "{stdout}");
+    assert_eq!(
+        normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr")),
+        source
     );
 
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    assert!(stderr.contains("--search-path does not exist"), "{stderr}");
+    let output = run(true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("dpr updated: 1"), "{stdout}");
+    assert!(
+        stdout.contains("rewrote the path to 'common\\NewUnit.pas'"),
+        "{stdout}"
+    );
+    assert_eq!(
+        normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr")),
+        "program App;\nuses\n  NewUnit in 'common\\NewUnit.pas';\nbegin\nend.\n"
+    );
 }
 
 #[test]
-fn end_to_end_delete_dependency_removes_orphaned_dependencies() {
-    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    let fixture_root = repo_root.join("tests").join("fixtures").join("delete_repo");
-    let expected_root = repo_root
-        .join("tests")
-        .join("fixtures")
-        .join("delete_expected");
-    let temp_root = temp_dir("fixdpr_e2e_delete_");
-    copy_dir(&fixture_root, &temp_root);
+fn end_to_end_add_dependency_skip_cache_reruns_dprs_after_an_option_change() {
+    let root = temp_dir("fixdpr_e2e_skip_cache_options_");
+    fs::create_dir_all(root.join("legacy")).expect("create legacy dir");
+    fs::create_dir_all(root.join("common")).expect("create common dir");
+    let source = "program App;\nuses\n  NewUnit in 'legacy\\NewUnit.pas';\nbegin\nend.\n";
+    write_file(&root, "App.dpr", source);
+    let unit = "unit NewUnit;\ninterface\nimplementation\nend.\n";
+    write_file(&root, "legacy/NewUnit.pas", unit);
+    write_file(&root, "common/NewUnit.pas", unit);
+
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg(root.join("common").join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&root)
+            .args(extra)
+            .output()
+            .expect("run fixdpr")
+    };
+
+    let output = run(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipping 1 dpr"), "{stdout}");
 
-    let old_dependency = temp_root.join("common").join("OldUnit.pas");
-    let target_dpr = temp_root.join("app").join("App.dpr");
-    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("delete-dependency")
-        .arg("--search-path")
-        .arg(&temp_root)
-        .arg("--target-dpr")
-        .arg(&target_dpr)
-        .arg(&old_dependency)
-        .output()
-        .expect("run fixdpr");
+    let output = run(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("Skipping 1 dpr file(s) unchanged since the last identical run"),
+        "{stdout}"
+    );
+
+    let output = run(&["--retarget-existing"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipping 1 dpr"), "{stdout}");
+    assert!(stdout.contains("dpr updated: 1"), "{stdout}");
+    assert_eq!(
+        normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr")),
+        "program App;\nuses\n  NewUnit in 'common\\NewUnit.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_limits_parent_dirs() {
+    let root = temp_dir("fixdpr_e2e_parent_dirs_");
+    let app_dir = root.join("apps").join("group").join("app");
+    fs::create_dir_all(&app_dir).expect("create app dir");
+    fs::create_dir_all(root.join("lib")).expect("create lib dir");
+    let source = "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n";
+    write_file(&app_dir, "App.dpr", source);
+    write_file(
+        &app_dir,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "lib/NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
+    );
 
+    let run = |policy: &str| {
+        fs::write(app_dir.join("App.dpr"), source).expect("reset dpr");
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg(root.join("lib").join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--max-parent-dirs")
+            .arg("2")
+            .arg("--on-deep-relative")
+            .arg(policy)
+            .arg("--show-warnings")
+            .arg("--no-cache")
+            .output()
+            .expect("run fixdpr")
+    };
+
+    let output = run("skip");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
     assert!(
-        output.status.success(),
-        "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&output.stdout),
-        String::from_utf8_lossy(&output.stderr)
+        stdout.contains("climbs 3 parent folders, more than 2; skipped it"),
+        "{stdout}"
+    );
+    assert_eq!(
+        normalize_newlines(fs::read_to_string(app_dir.join("App.dpr")).expect("read dpr")),
+        source
     );
 
-    let actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app").join("App.dpr")).expect("read app actual"),
+    let output = run("allow");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("kept it"), "{stdout}");
+    assert!(
+        normalize_newlines(fs::read_to_string(app_dir.join("App.dpr")).expect("read dpr"))
+            .contains("NewUnit in '..\\..\\..\\lib\\NewUnit.pas'"),
+        "{stdout}"
     );
-    let expected = normalize_newlines(
-        fs::read_to_string(expected_root.join("app").join("App.dpr")).expect("read app expected"),
+
+    let output = run("absolute");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("wrote an absolute path"), "{stdout}");
+    let updated =
+        normalize_newlines(fs::read_to_string(app_dir.join("App.dpr")).expect("read dpr"));
+    assert!(updated.contains("lib\\NewUnit.pas'"), "{updated}");
+    assert!(!updated.contains(".."), "{updated}");
+}
+
+#[test]
+fn end_to_end_add_dependency_skip_cache_keys_on_parent_dir_limits() {
+    let root = temp_dir("fixdpr_e2e_skip_cache_parent_dirs_");
+    let app_dir = root.join("apps").join("group").join("app");
+    fs::create_dir_all(&app_dir).expect("create app dir");
+    fs::create_dir_all(root.join("lib")).expect("create lib dir");
+    let source = "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n";
+    write_file(&app_dir, "App.dpr", source);
+    write_file(
+        &app_dir,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "lib/NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
     );
+
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg(root.join("lib").join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&root)
+            .args(extra)
+            .output()
+            .expect("run fixdpr")
+    };
+
+    let output = run(&["--max-parent-dirs", "1", "--on-deep-relative", "skip"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
     assert_eq!(
-        actual, expected,
-        "delete-dependency should remove OldUnit and LeafOnly only"
+        normalize_newlines(fs::read_to_string(app_dir.join("App.dpr")).expect("read dpr")),
+        source
+    );
+
+    let output = run(&["--max-parent-dirs", "1", "--on-deep-relative", "allow"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipping 1 dpr"), "{stdout}");
+    assert!(stdout.contains("dpr updated: 1"), "{stdout}");
+    fs::write(app_dir.join("App.dpr"), source).expect("reset dpr");
+    run(&["--max-parent-dirs", "1", "--on-deep-relative", "skip"]);
+
+    let output = run(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipping 1 dpr"), "{stdout}");
+    assert!(
+        normalize_newlines(fs::read_to_string(app_dir.join("App.dpr")).expect("read dpr"))
+            .contains("NewUnit in '..\\..\\..\\lib\\NewUnit.pas'"),
+        "{stdout}"
     );
 }
 
 #[test]
-fn end_to_end_ignores_dpr_with_absolute_pattern_and_reports_info() {
-    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    let fixture_root = repo_root
+fn end_to_end_add_dependency_skip_cache_keys_on_known_units_and_prefixes() {
+    let root = temp_dir("fixdpr_e2e_skip_cache_known_units_");
+    let project = root.join("project");
+    fs::create_dir_all(&project).expect("create project dir");
+    write_file(
+        &project,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &project,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &project,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nuses Helper;\nimplementation\nend.\n",
+    );
+    write_file(
+        &project,
+        "Helper.pas",
+        "unit Helper;\ninterface\nimplementation\nend.\n",
+    );
+    let known = root.join("known.txt");
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg(project.join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&project)
+            .arg("--known-units")
+            .arg(&known)
+            .args(extra)
+            .output()
+            .expect("run fixdpr")
+    };
+    let read_dpr = || normalize_newlines(fs::read_to_string(project.join("App.dpr")).unwrap());
+
+    fs::write(&known, "Helper\n").expect("write known units");
+    let output = run(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!read_dpr().contains("Helper"), "{}", read_dpr());
+
+    let output = run(&["--namespace-prefix", "Vcl"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipping 1 dpr"), "{stdout}");
+
+    fs::write(&known, "").expect("clear known units");
+    let output = run(&["--namespace-prefix", "Vcl"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipping 1 dpr"), "{stdout}");
+    assert!(
+        read_dpr().contains("Helper in 'Helper.pas'"),
+        "{}",
+        read_dpr()
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_skip_cache_keys_on_delphi_options() {
+    let root = temp_dir("fixdpr_e2e_skip_cache_delphi_");
+    let project = root.join("project");
+    let delphi = root.join("delphi");
+    fs::create_dir_all(&project).expect("create project dir");
+    fs::create_dir_all(delphi.join("ext")).expect("create delphi dir");
+    write_file(
+        &project,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &project,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &project,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nuses ExtUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &delphi,
+        "ext/ExtUnit.pas",
+        "unit ExtUnit;\ninterface\nimplementation\nend.\n",
+    );
+    let run = |extra: &[&std::ffi::OsStr]| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg(project.join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&project)
+            .arg("--delphi-path")
+            .arg(&delphi)
+            .args(extra)
+            .output()
+            .expect("run fixdpr")
+    };
+    let read_dpr = || normalize_newlines(fs::read_to_string(project.join("App.dpr")).unwrap());
+
+    let ignored = delphi.join("ext");
+    let output = run(&["--delphi-ignore-path".as_ref(), ignored.as_os_str()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!read_dpr().contains("ExtUnit"), "{}", read_dpr());
+
+    let output = run(&[
+        "--delphi-ignore-path".as_ref(),
+        ignored.as_os_str(),
+        "--cross-drive".as_ref(),
+        "skip".as_ref(),
+    ]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipping 1 dpr"), "{stdout}");
+
+    let output = run(&["--cross-drive".as_ref(), "skip".as_ref()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(!stdout.contains("Skipping 1 dpr"), "{stdout}");
+    assert!(read_dpr().contains("ExtUnit in '"), "{}", read_dpr());
+}
+
+#[test]
+fn end_to_end_emit_patch_applies_to_the_same_result_as_a_real_run() {
+    let fixture_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .join("tests")
         .join("fixtures")
         .join("synthetic_repo");
-    let expected_root = repo_root
+    let patched_root = temp_dir("fixdpr_e2e_patch_");
+    let real_root = temp_dir("fixdpr_e2e_patch_real_");
+    copy_dir(&fixture_root, &patched_root);
+    copy_dir(&fixture_root, &real_root);
+    let patch_file = temp_dir("fixdpr_e2e_patch_out_").join("changes.patch");
+    let dprs = [
+        PathBuf::from("app1").join("App1.dpr"),
+        PathBuf::from("app2").join("App2.dpr"),
+        PathBuf::from("app3").join("App3.dpr"),
+        PathBuf::from("app4").join("App4.dpr"),
+    ];
+
+    let run = |root: &Path, emit_patch: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .current_dir(root)
+            .arg("add-dependency")
+            .arg(root.join("common").join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(root)
+            .arg("--ignore-path")
+            .arg(root.join("ignored"))
+            .arg("--fix-updated-dprs")
+            .arg("--no-cache");
+        if emit_patch {
+            command.arg("--emit-patch").arg(&patch_file);
+        }
+        let output = command.output().expect("run fixdpr");
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        assert!(output.status.success(), "{stdout}");
+        stdout
+    };
+
+    let stdout = run(&patched_root, true);
+    assert!(
+        stdout.contains("Patch for 2 dpr file(s) written to"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Would update dpr files (2):"), "{stdout}");
+    assert!(!stdout.contains("Updated dpr files"), "{stdout}");
+    for rel_path in &dprs {
+        assert_eq!(
+            fs::read(patched_root.join(rel_path)).expect("read dpr"),
+            fs::read(fixture_root.join(rel_path)).expect("read fixture"),
+            "{} changed on disk",
+            rel_path.display()
+        );
+    }
+    let patch = fs::read_to_string(&patch_file).expect("read patch");
+    assert!(
+        patch.contains("diff --git a/app1/App1.dpr b/app1/App1.dpr\n"),
+        "{patch}"
+    );
+
+    let applied = Command::new("git")
+        .current_dir(&patched_root)
+        .arg("apply")
+        .arg(&patch_file)
+        .output()
+        .expect("run git apply");
+    assert!(
+        applied.status.success(),
+        "{}",
+        String::from_utf8_lossy(&applied.stderr)
+    );
+
+    let stdout = run(&real_root, false);
+    assert!(stdout.contains("Updated dpr files (2):"), "{stdout}");
+    for rel_path in &dprs {
+        assert_eq!(
+            fs::read(patched_root.join(rel_path)).expect("read patched dpr"),
+            fs::read(real_root.join(rel_path)).expect("read updated dpr"),
+            "mismatch for {}",
+            rel_path.display()
+        );
+    }
+}
+
+#[cfg(not(windows))]
+#[test]
+fn end_to_end_on_update_runs_once_per_updated_dpr() {
+    let fixture_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .join("tests")
         .join("fixtures")
-        .join("synthetic_expected");
-    let temp_root = temp_dir("fixdpr_e2e_ignore_dpr_");
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_on_update_");
     copy_dir(&fixture_root, &temp_root);
+    let log_path = temp_root.join("hook.log");
 
-    let ignored_dpr = temp_root.join("app4").join("App4.dpr");
-    let new_dependency = temp_root.join("common").join("NewUnit.pas");
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
         .arg("add-dependency")
-        .current_dir(&repo_root)
+        .arg(temp_root.join("common").join("NewUnit.pas"))
         .arg("--search-path")
-        .arg(&temp_root)
-        .arg(&new_dependency)
-        .arg("--ignore-path")
-        .arg(temp_root.join("ignored"))
-        .arg("--ignore-dpr")
-        .arg(&ignored_dpr)
-        .arg("--show-infos")
+        .arg(temp_root.join("app1"))
+        .arg("--search-path")
+        .arg(temp_root.join("app2"))
+        .arg("--on-update")
+        .arg(format!(
+            "grep -c NewUnit {{}} >> '{}'; echo {{}} >> '{}'",
+            log_path.display(),
+            log_path.display()
+        ))
+        .arg("--no-cache")
         .output()
         .expect("run fixdpr");
-
-    assert!(
-        output.status.success(),
-        "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&output.stdout),
-        String::from_utf8_lossy(&output.stderr)
-    );
-
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("Infos: 1"), "{stdout}");
-    assert!(stdout.contains("Infos list:"), "{stdout}");
-    assert!(stdout.contains("dpr ignored: 1"), "{stdout}");
+    assert!(output.status.success(), "{stdout}");
+    let log = fs::read_to_string(&log_path).expect("read hook log");
+    assert_eq!(
+        log,
+        format!("1\n{}\n", temp_root.join("app1").join("App1.dpr").display()),
+        "the hook sees the edited dpr"
+    );
+
+    let run_failing_hook = |strict: bool| {
+        fs::copy(
+            fixture_root.join("app1").join("App1.dpr"),
+            temp_root.join("app1").join("App1.dpr"),
+        )
+        .expect("restore dpr");
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .arg("add-dependency")
+            .arg(temp_root.join("common").join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(temp_root.join("app1"))
+            .arg("--on-update")
+            .arg("echo formatter broke >&2; exit 3")
+            .arg("--show-warnings")
+            .arg("--no-cache");
+        if strict {
+            command.arg("--on-update-strict");
+        }
+        command.output().expect("run fixdpr")
+    };
 
-    let app4_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4 actual"),
-    );
-    let app4_expected = normalize_newlines(
-        fs::read_to_string(fixture_root.join("app4").join("App4.dpr")).expect("read app4 expected"),
-    );
-    assert_eq!(app4_actual, app4_expected, "app4 should be ignored");
+    let output = run_failing_hook(false);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("exited with"), "{stdout}");
+    assert!(stdout.contains("formatter broke"), "{stdout}");
 
-    let app1_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1 actual"),
-    );
-    let app1_expected = normalize_newlines(
-        fs::read_to_string(expected_root.join("app1").join("App1.dpr"))
-            .expect("read app1 expected"),
-    );
-    assert_eq!(app1_actual, app1_expected, "app1 should still be updated");
+    let output = run_failing_hook(true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(stdout.contains("dpr failures: 1"), "{stdout}");
 }
 
 #[test]
-fn end_to_end_relative_ignore_pattern_from_repo_root_does_not_match_temp_repo() {
+fn end_to_end_search_path_dedupes_overlapping_roots() {
     let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let fixture_root = repo_root
         .join("tests")
@@ -487,21 +1106,19 @@ fn end_to_end_relative_ignore_pattern_from_repo_root_does_not_match_temp_repo()
         .join("tests")
         .join("fixtures")
         .join("synthetic_expected");
-    let temp_root = temp_dir("fixdpr_e2e_ignore_rel_repo_root_");
+    let temp_root = temp_dir("fixdpr_e2e_glob_search_");
     copy_dir(&fixture_root, &temp_root);
 
     let new_dependency = temp_root.join("common").join("NewUnit.pas");
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
         .arg("add-dependency")
-        .current_dir(&repo_root)
         .arg("--search-path")
         .arg(&temp_root)
+        .arg("--search-path")
+        .arg(temp_root.join("app1"))
         .arg(&new_dependency)
         .arg("--ignore-path")
         .arg(temp_root.join("ignored"))
-        .arg("--ignore-dpr")
-        .arg("app4/*.dpr")
-        .arg("--show-infos")
         .output()
         .expect("run fixdpr");
 
@@ -513,8 +1130,34 @@ fn end_to_end_relative_ignore_pattern_from_repo_root_does_not_match_temp_repo()
     );
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("Infos: 0"), "{stdout}");
-    assert!(stdout.contains("dpr ignored: 0"), "{stdout}");
+    assert!(stdout.contains("dpr scanned: 4"), "{stdout}");
+
+    let app1_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1 actual"),
+    );
+    let app1_expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app1").join("App1.dpr"))
+            .expect("read app1 expected"),
+    );
+    assert_eq!(app1_actual, app1_expected, "app1 should be updated");
+
+    let app2_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app2").join("App2.dpr")).expect("read app2 actual"),
+    );
+    let app2_expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app2").join("App2.dpr"))
+            .expect("read app2 expected"),
+    );
+    assert_eq!(app2_actual, app2_expected, "app2 should be updated");
+
+    let app3_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app3").join("App3.dpr")).expect("read app3 actual"),
+    );
+    let app3_expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app3").join("App3.dpr"))
+            .expect("read app3 expected"),
+    );
+    assert_eq!(app3_actual, app3_expected, "app3 should be updated");
 
     let app4_actual = normalize_newlines(
         fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4 actual"),
@@ -523,333 +1166,3902 @@ fn end_to_end_relative_ignore_pattern_from_repo_root_does_not_match_temp_repo()
         fs::read_to_string(expected_root.join("app4").join("App4.dpr"))
             .expect("read app4 expected"),
     );
-    assert_eq!(app4_actual, app4_expected, "app4 should not be ignored");
+    assert_eq!(app4_actual, app4_expected, "app4 should be updated");
 }
 
 #[test]
-fn end_to_end_relative_ignore_pattern_from_search_root_matches() {
+fn end_to_end_search_path_requires_existing_directory() {
     let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let fixture_root = repo_root
         .join("tests")
         .join("fixtures")
         .join("synthetic_repo");
-    let expected_root = repo_root
-        .join("tests")
-        .join("fixtures")
-        .join("synthetic_expected");
-    let temp_root = temp_dir("fixdpr_e2e_ignore_rel_search_root_");
+    let temp_root = temp_dir("fixdpr_e2e_search_warn_");
     copy_dir(&fixture_root, &temp_root);
 
+    let matched_root = temp_root.clone();
+    let missing_path = temp_root.join("missing");
     let new_dependency = temp_root.join("common").join("NewUnit.pas");
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
         .arg("add-dependency")
-        .current_dir(&temp_root)
         .arg("--search-path")
-        .arg(&temp_root)
+        .arg(&matched_root)
+        .arg("--search-path")
+        .arg(&missing_path)
         .arg(&new_dependency)
-        .arg("--ignore-path")
-        .arg("ignored")
-        .arg("--ignore-dpr")
-        .arg("app4/*.dpr")
-        .arg("--show-infos")
         .output()
         .expect("run fixdpr");
 
     assert!(
-        output.status.success(),
+        !output.status.success(),
         "stdout:\n{}\nstderr:\n{}",
         String::from_utf8_lossy(&output.stdout),
         String::from_utf8_lossy(&output.stderr)
     );
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("Infos: 1"), "{stdout}");
-    assert!(stdout.contains("dpr ignored: 1"), "{stdout}");
-
-    let app4_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4 actual"),
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    assert!(
+        stderr.contains(&format!(
+            "error: --search-path does not exist: {}",
+            missing_path.display()
+        )),
+        "{stderr}"
     );
-    let app4_expected = normalize_newlines(
-        fs::read_to_string(fixture_root.join("app4").join("App4.dpr")).expect("read app4 expected"),
+}
+
+#[test]
+fn end_to_end_search_and_ignore_paths_can_be_read_from_files() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_expected");
+    let temp_root = temp_dir("fixdpr_e2e_path_files_");
+    copy_dir(&fixture_root, &temp_root);
+    let config = temp_root.join("config");
+    fs::create_dir_all(&config).expect("create config dir");
+    write_file(&config, "paths.txt", "# generated\n\n..\n");
+    write_file(&config, "ignores.txt", "../app4\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .current_dir(&config)
+        .arg("add-dependency")
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--search-path-file")
+        .arg("paths.txt")
+        .arg("--ignore-path-file")
+        .arg(config.join("ignores.txt"))
+        .output()
+        .expect("run fixdpr");
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
     );
-    assert_eq!(app4_actual, app4_expected, "app4 should be ignored");
 
-    let app1_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1 actual"),
+    let actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1"),
     );
-    let app1_expected = normalize_newlines(
-        fs::read_to_string(expected_root.join("app1").join("App1.dpr"))
-            .expect("read app1 expected"),
+    let expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app1").join("App1.dpr")).expect("read expected"),
+    );
+    assert_eq!(actual, expected);
+    let app4 = normalize_newlines(
+        fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4"),
+    );
+    let original = normalize_newlines(
+        fs::read_to_string(fixture_root.join("app4").join("App4.dpr")).expect("read original"),
+    );
+    assert_eq!(app4, original, "app4 is ignored");
+
+    let missing = temp_root.join("missing.txt");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--search-path-file")
+        .arg(&missing)
+        .output()
+        .expect("run fixdpr");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    assert!(
+        stderr.contains(&format!(
+            "error: --search-path-file does not exist: {}",
+            missing.display()
+        )),
+        "{stderr}"
     );
-    assert_eq!(app1_actual, app1_expected, "app1 should still be updated");
 }
 
 #[test]
-fn end_to_end_delphi_path_enables_transitive_external_resolution() {
-    let without_root = temp_dir("fixdpr_e2e_delphi_path_without_");
-    let without_project = without_root.join("project");
-    let without_delphi = without_root.join("delphi");
-    create_delphi_path_fixture(&without_project, &without_delphi);
+fn end_to_end_search_path_glob_warns_when_pattern_matches_nothing() {
+    let temp_root = temp_dir("fixdpr_e2e_unmatched_glob_");
+    let app_root = temp_root.join("apps").join("one");
+    fs::create_dir_all(&app_root).expect("create app root");
+    write_file(
+        &app_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &app_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nimplementation\nend.\n",
+    );
 
-    let without_output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("add-dependency")
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .current_dir(&temp_root)
+        .arg("fix-dpr")
+        .arg(app_root.join("App.dpr"))
         .arg("--search-path")
-        .arg(&without_project)
-        .arg(without_delphi.join("NewUnit.pas"))
+        .arg("apps/*")
+        .arg("--search-path")
+        .arg("aps/*")
+        .arg("--show-warnings")
+        .arg("--no-cache")
         .output()
-        .expect("run fixdpr without delphi path");
+        .expect("run fixdpr fix-dpr mode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("Scanning 1 root(s):"), "{stdout}");
+    assert!(
+        stdout.contains("Unmatched search path pattern(s): aps/*"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("warning: --search-path pattern matched no directories: aps/*"),
+        "{stdout}"
+    );
 
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .current_dir(&temp_root)
+        .arg("fix-dpr")
+        .arg(app_root.join("App.dpr"))
+        .arg("--search-path")
+        .arg("apps/*")
+        .arg("--search-path")
+        .arg("aps/*")
+        .arg("--error-on-unmatched-search-path")
+        .arg("--no-cache")
+        .output()
+        .expect("run fixdpr fix-dpr mode");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
     assert!(
-        without_output.status.success(),
-        "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&without_output.stdout),
-        String::from_utf8_lossy(&without_output.stderr)
+        stderr.contains("error: --search-path pattern matched no directories: aps/*"),
+        "{stderr}"
     );
+}
 
-    let without_dpr = normalize_newlines(
-        fs::read_to_string(without_project.join("App.dpr")).expect("read dpr without delphi path"),
+#[test]
+fn end_to_end_max_scan_depth_reports_unscanned_directories() {
+    let temp_root = temp_dir("fixdpr_e2e_max_scan_depth_");
+    let mirror = temp_root.join("third_party").join("mirror");
+    fs::create_dir_all(&mirror).expect("create mirror");
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
     );
+    write_file(
+        &mirror,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(temp_root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg("--max-scan-depth")
+        .arg("1")
+        .arg("--show-warnings")
+        .arg("--no-cache")
+        .output()
+        .expect("run fixdpr fix-dpr mode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("Found 1 .pas, 1 .dpr"), "{stdout}");
     assert!(
-        !without_dpr.contains("NewUnit in "),
-        "dpr should stay unchanged without --delphi-path:\n{without_dpr}"
+        stdout.contains("warning: scan depth limit 1 left 1 directories unscanned under"),
+        "{stdout}"
     );
+    let contents = fs::read_to_string(temp_root.join("App.dpr")).expect("read dpr");
+    assert!(!contents.contains("UnitB"), "{contents}");
+}
 
-    let with_root = temp_dir("fixdpr_e2e_delphi_path_with_");
-    let with_project = with_root.join("project");
-    let with_delphi = with_root.join("delphi");
-    create_delphi_path_fixture(&with_project, &with_delphi);
+#[test]
+fn end_to_end_reports_argument_errors_with_path_context_and_exit_code() {
+    let temp_root = temp_dir("fixdpr_e2e_argument_errors_");
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nimplementation\nend.\n",
+    );
 
-    let with_output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("add-dependency")
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(temp_root.join("UnitA.pas"))
         .arg("--search-path")
-        .arg(&with_project)
-        .arg(with_delphi.join("NewUnit.pas"))
-        .arg("--delphi-path")
-        .arg(&with_delphi)
+        .arg(&temp_root)
         .output()
-        .expect("run fixdpr with delphi path");
-
+        .expect("run fixdpr fix-dpr mode");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
     assert!(
-        with_output.status.success(),
-        "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&with_output.stdout),
-        String::from_utf8_lossy(&with_output.stderr)
+        stderr.contains(&format!(
+            "error: DPR_FILE must point to a .dpr or .lpr file: {}",
+            temp_root.join("UnitA.pas").display()
+        )),
+        "{stderr}"
     );
 
-    let with_dpr =
-        normalize_newlines(fs::read_to_string(with_project.join("App.dpr")).expect("read dpr"));
+    let missing = temp_root.join("Missing.pas");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(&missing)
+        .arg("--search-path")
+        .arg(&temp_root)
+        .output()
+        .expect("run fixdpr add-dependency mode");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
     assert!(
-        with_dpr.contains("NewUnit in '..\\delphi\\NewUnit.pas'"),
-        "dpr should include NewUnit via transitive external dependency:\n{with_dpr}"
+        stderr.contains(&format!(
+            "error: NEW_DEPENDENCY does not exist: {}",
+            missing.display()
+        )),
+        "{stderr}"
     );
 }
 
 #[test]
-fn end_to_end_fix_dpr_delphi_path_enables_transitive_external_resolution() {
-    let without_root = temp_dir("fixdpr_e2e_fix_dpr_delphi_path_without_");
-    let without_project = without_root.join("project");
-    let without_delphi = without_root.join("delphi");
-    create_delphi_path_fixture(&without_project, &without_delphi);
+fn end_to_end_delete_dependency_removes_orphaned_dependencies() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root.join("tests").join("fixtures").join("delete_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("delete_expected");
+    let temp_root = temp_dir("fixdpr_e2e_delete_");
+    copy_dir(&fixture_root, &temp_root);
 
-    let without_target = without_project.join("App.dpr");
-    let without_output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("fix-dpr")
+    let old_dependency = temp_root.join("common").join("OldUnit.pas");
+    let target_dpr = temp_root.join("app").join("App.dpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("delete-dependency")
         .arg("--search-path")
-        .arg(&without_project)
-        .arg(&without_target)
+        .arg(&temp_root)
+        .arg("--target-dpr")
+        .arg(&target_dpr)
+        .arg(&old_dependency)
         .output()
-        .expect("run fixdpr fix-dpr without delphi path");
+        .expect("run fixdpr");
 
     assert!(
-        without_output.status.success(),
+        output.status.success(),
         "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&without_output.stdout),
-        String::from_utf8_lossy(&without_output.stderr)
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
     );
 
-    let without_dpr = normalize_newlines(
-        fs::read_to_string(&without_target).expect("read dpr without delphi path"),
+    let actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app").join("App.dpr")).expect("read app actual"),
     );
-    assert!(
-        !without_dpr.contains("ExtMid in "),
-        "dpr should stay unchanged without --delphi-path:\n{without_dpr}"
+    let expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app").join("App.dpr")).expect("read app expected"),
     );
-    assert!(
-        !without_dpr.contains("NewUnit in "),
-        "dpr should stay unchanged without --delphi-path:\n{without_dpr}"
+    assert_eq!(
+        actual, expected,
+        "delete-dependency should remove OldUnit and LeafOnly only"
     );
+}
 
-    let with_root = temp_dir("fixdpr_e2e_fix_dpr_delphi_path_with_");
-    let with_project = with_root.join("project");
-    let with_delphi = with_root.join("delphi");
-    create_delphi_path_fixture(&with_project, &with_delphi);
+#[test]
+fn end_to_end_delete_dependency_drops_dangling_comma_and_keeps_comments() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("delete_dangling_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("delete_dangling_expected");
+    let temp_root = temp_dir("fixdpr_e2e_delete_dangling_");
+    copy_dir(&fixture_root, &temp_root);
 
-    let with_target = with_project.join("App.dpr");
-    let with_output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("fix-dpr")
+    let old_dependency = temp_root.join("common").join("OldUnit.pas");
+    let target_dpr = temp_root.join("app").join("App.dpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("delete-dependency")
         .arg("--search-path")
-        .arg(&with_project)
-        .arg(&with_target)
-        .arg("--delphi-path")
-        .arg(&with_delphi)
+        .arg(&temp_root)
+        .arg("--target-dpr")
+        .arg(&target_dpr)
+        .arg(&old_dependency)
         .output()
-        .expect("run fixdpr fix-dpr with delphi path");
+        .expect("run fixdpr");
 
     assert!(
-        with_output.status.success(),
+        output.status.success(),
         "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&with_output.stdout),
-        String::from_utf8_lossy(&with_output.stderr)
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
     );
 
-    let with_dpr = normalize_newlines(fs::read_to_string(&with_target).expect("read dpr"));
-    assert!(
-        with_dpr.contains("ExtMid in '..\\delphi\\ExtMid.pas'"),
-        "dpr should include ExtMid via external dependency:\n{with_dpr}"
+    let actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app").join("App.dpr")).expect("read app actual"),
     );
-    assert!(
-        with_dpr.contains("NewUnit in '..\\delphi\\NewUnit.pas'"),
-        "dpr should include NewUnit via transitive external dependency:\n{with_dpr}"
+    let expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app").join("App.dpr")).expect("read app expected"),
+    );
+    assert_eq!(
+        actual, expected,
+        "delete-dependency should drop the dangling comma and keep the comments"
     );
 }
 
 #[test]
-fn end_to_end_delphi_version_reports_error_for_unknown_version() {
+fn end_to_end_ignores_dpr_with_absolute_pattern_and_reports_info() {
     let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let fixture_root = repo_root
         .join("tests")
         .join("fixtures")
         .join("synthetic_repo");
-    let temp_root = temp_dir("fixdpr_e2e_delphi_version_unknown_");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_expected");
+    let temp_root = temp_dir("fixdpr_e2e_ignore_dpr_");
     copy_dir(&fixture_root, &temp_root);
 
+    let ignored_dpr = temp_root.join("app4").join("App4.dpr");
     let new_dependency = temp_root.join("common").join("NewUnit.pas");
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
         .arg("add-dependency")
+        .current_dir(&repo_root)
         .arg("--search-path")
         .arg(&temp_root)
         .arg(&new_dependency)
-        .arg("--delphi-version")
-        .arg("9999.9999")
+        .arg("--ignore-path")
+        .arg(temp_root.join("ignored"))
+        .arg("--ignore-dpr")
+        .arg(&ignored_dpr)
+        .arg("--show-infos")
+        .output()
+        .expect("run fixdpr");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Infos: 1"), "{stdout}");
+    assert!(stdout.contains("Infos list:"), "{stdout}");
+    assert!(stdout.contains("dpr ignored: 1"), "{stdout}");
+
+    let app4_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4 actual"),
+    );
+    let app4_expected = normalize_newlines(
+        fs::read_to_string(fixture_root.join("app4").join("App4.dpr")).expect("read app4 expected"),
+    );
+    assert_eq!(app4_actual, app4_expected, "app4 should be ignored");
+
+    let app1_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1 actual"),
+    );
+    let app1_expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app1").join("App1.dpr"))
+            .expect("read app1 expected"),
+    );
+    assert_eq!(app1_actual, app1_expected, "app1 should still be updated");
+}
+
+#[test]
+fn end_to_end_relative_ignore_pattern_from_repo_root_does_not_match_temp_repo() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_expected");
+    let temp_root = temp_dir("fixdpr_e2e_ignore_rel_repo_root_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let new_dependency = temp_root.join("common").join("NewUnit.pas");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .current_dir(&repo_root)
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(&new_dependency)
+        .arg("--ignore-path")
+        .arg(temp_root.join("ignored"))
+        .arg("--ignore-dpr")
+        .arg("app4/*.dpr")
+        .arg("--show-infos")
+        .output()
+        .expect("run fixdpr");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Infos: 0"), "{stdout}");
+    assert!(stdout.contains("dpr ignored: 0"), "{stdout}");
+
+    let app4_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4 actual"),
+    );
+    let app4_expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app4").join("App4.dpr"))
+            .expect("read app4 expected"),
+    );
+    assert_eq!(app4_actual, app4_expected, "app4 should not be ignored");
+}
+
+#[test]
+fn end_to_end_relative_ignore_pattern_from_search_root_matches() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_expected");
+    let temp_root = temp_dir("fixdpr_e2e_ignore_rel_search_root_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let new_dependency = temp_root.join("common").join("NewUnit.pas");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .current_dir(&temp_root)
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(&new_dependency)
+        .arg("--ignore-path")
+        .arg("ignored")
+        .arg("--ignore-dpr")
+        .arg("app4/*.dpr")
+        .arg("--show-infos")
+        .output()
+        .expect("run fixdpr");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Infos: 1"), "{stdout}");
+    assert!(stdout.contains("dpr ignored: 1"), "{stdout}");
+
+    let app4_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4 actual"),
+    );
+    let app4_expected = normalize_newlines(
+        fs::read_to_string(fixture_root.join("app4").join("App4.dpr")).expect("read app4 expected"),
+    );
+    assert_eq!(app4_actual, app4_expected, "app4 should be ignored");
+
+    let app1_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1 actual"),
+    );
+    let app1_expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app1").join("App1.dpr"))
+            .expect("read app1 expected"),
+    );
+    assert_eq!(app1_actual, app1_expected, "app1 should still be updated");
+}
+
+#[test]
+fn end_to_end_ignore_dpr_relative_to_search_root_matches_from_any_cwd() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_expected");
+
+    for (mode, ignored) in [("cwd", false), ("search-root", true)] {
+        let temp_root = temp_dir("fixdpr_e2e_ignore_dpr_relative_to_");
+        copy_dir(&fixture_root, &temp_root);
+
+        let new_dependency = temp_root.join("common").join("NewUnit.pas");
+        let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .current_dir(&repo_root)
+            .arg("--search-path")
+            .arg(&temp_root)
+            .arg(&new_dependency)
+            .arg("--ignore-path")
+            .arg(temp_root.join("ignored"))
+            .arg("--ignore-dpr")
+            .arg("app4/*.dpr")
+            .arg("--ignore-dpr-relative-to")
+            .arg(mode)
+            .output()
+            .expect("run fixdpr");
+
+        assert!(
+            output.status.success(),
+            "stdout:\n{}\nstderr:\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let expected_count = if ignored { 1 } else { 0 };
+        assert!(
+            stdout.contains(&format!("dpr ignored: {expected_count}")),
+            "{mode}: {stdout}"
+        );
+
+        let app4_actual = normalize_newlines(
+            fs::read_to_string(temp_root.join("app4").join("App4.dpr")).expect("read app4 actual"),
+        );
+        let app4_expected_root = if ignored {
+            &fixture_root
+        } else {
+            &expected_root
+        };
+        let app4_expected = normalize_newlines(
+            fs::read_to_string(app4_expected_root.join("app4").join("App4.dpr"))
+                .expect("read app4 expected"),
+        );
+        assert_eq!(app4_actual, app4_expected, "{mode}: unexpected app4 result");
+    }
+}
+
+#[test]
+fn end_to_end_invalid_glob_is_an_argument_error_naming_the_position() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_invalid_glob_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .current_dir(&temp_root)
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--ignore-dpr")
+        .arg("{app4,app5/*.dpr")
+        .output()
+        .expect("run fixdpr");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    assert!(
+        stderr.contains("error: invalid pattern '{app4,app5/*.dpr': unclosed '{' at position 1"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn end_to_end_ignore_program_skips_dprs_by_declared_name() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_expected");
+    let temp_root = temp_dir("fixdpr_e2e_ignore_program_");
+    copy_dir(&fixture_root, &temp_root);
+    // The file moves, but the declared program name stays.
+    fs::rename(
+        temp_root.join("app4").join("App4.dpr"),
+        temp_root.join("app4").join("Moved.dpr"),
+    )
+    .expect("rename app4");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .current_dir(&repo_root)
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(temp_root.join("common").join("NewUnit.pas"))
+        .arg("--ignore-path")
+        .arg(temp_root.join("ignored"))
+        .arg("--ignore-program")
+        .arg("App[4-9]")
+        .arg("--show-infos")
+        .output()
+        .expect("run fixdpr");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Ignoring programs: app[4-9]"), "{stdout}");
+    assert!(
+        stdout.contains("\ncompleted in ") && stdout.contains("s (scan "),
+        "{stdout}"
+    );
+    assert!(stdout.contains("dpr ignored: 1"), "{stdout}");
+    assert!(stdout.contains("info: ignored dpr "), "{stdout}");
+
+    let app4_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app4").join("Moved.dpr")).expect("read app4 actual"),
+    );
+    let app4_expected = normalize_newlines(
+        fs::read_to_string(fixture_root.join("app4").join("App4.dpr")).expect("read app4 expected"),
+    );
+    assert_eq!(app4_actual, app4_expected, "app4 should be ignored");
+
+    let app1_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1 actual"),
+    );
+    let app1_expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app1").join("App1.dpr"))
+            .expect("read app1 expected"),
+    );
+    assert_eq!(app1_actual, app1_expected, "app1 should still be updated");
+}
+
+#[test]
+fn end_to_end_delphi_path_enables_transitive_external_resolution() {
+    let without_root = temp_dir("fixdpr_e2e_delphi_path_without_");
+    let without_project = without_root.join("project");
+    let without_delphi = without_root.join("delphi");
+    create_delphi_path_fixture(&without_project, &without_delphi);
+    let without_new_unit = without_root.join("new").join("NewUnit.pas");
+    fs::create_dir_all(without_root.join("new")).expect("create new unit dir");
+    fs::rename(without_delphi.join("NewUnit.pas"), &without_new_unit).expect("move new unit");
+
+    let without_output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&without_project)
+        .arg(&without_new_unit)
+        .output()
+        .expect("run fixdpr without delphi path");
+
+    assert!(
+        without_output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&without_output.stdout),
+        String::from_utf8_lossy(&without_output.stderr)
+    );
+
+    let without_dpr = normalize_newlines(
+        fs::read_to_string(without_project.join("App.dpr")).expect("read dpr without delphi path"),
+    );
+    assert!(
+        !without_dpr.contains("NewUnit in "),
+        "dpr should stay unchanged without --delphi-path:\n{without_dpr}"
+    );
+
+    let with_root = temp_dir("fixdpr_e2e_delphi_path_with_");
+    let with_project = with_root.join("project");
+    let with_delphi = with_root.join("delphi");
+    create_delphi_path_fixture(&with_project, &with_delphi);
+
+    let with_output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&with_project)
+        .arg(with_delphi.join("NewUnit.pas"))
+        .arg("--delphi-path")
+        .arg(&with_delphi)
+        .output()
+        .expect("run fixdpr with delphi path");
+
+    assert!(
+        with_output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&with_output.stdout),
+        String::from_utf8_lossy(&with_output.stderr)
+    );
+
+    let with_dpr =
+        normalize_newlines(fs::read_to_string(with_project.join("App.dpr")).expect("read dpr"));
+    assert!(
+        with_dpr.contains("NewUnit in '..\\delphi\\NewUnit.pas'"),
+        "dpr should include NewUnit via transitive external dependency:\n{with_dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_delphi_path_enables_transitive_external_resolution() {
+    let without_root = temp_dir("fixdpr_e2e_fix_dpr_delphi_path_without_");
+    let without_project = without_root.join("project");
+    let without_delphi = without_root.join("delphi");
+    create_delphi_path_fixture(&without_project, &without_delphi);
+
+    let without_target = without_project.join("App.dpr");
+    let without_output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg("--search-path")
+        .arg(&without_project)
+        .arg(&without_target)
+        .output()
+        .expect("run fixdpr fix-dpr without delphi path");
+
+    assert!(
+        without_output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&without_output.stdout),
+        String::from_utf8_lossy(&without_output.stderr)
+    );
+
+    let without_dpr = normalize_newlines(
+        fs::read_to_string(&without_target).expect("read dpr without delphi path"),
+    );
+    assert!(
+        !without_dpr.contains("ExtMid in "),
+        "dpr should stay unchanged without --delphi-path:\n{without_dpr}"
+    );
+    assert!(
+        !without_dpr.contains("NewUnit in "),
+        "dpr should stay unchanged without --delphi-path:\n{without_dpr}"
+    );
+
+    let with_root = temp_dir("fixdpr_e2e_fix_dpr_delphi_path_with_");
+    let with_project = with_root.join("project");
+    let with_delphi = with_root.join("delphi");
+    create_delphi_path_fixture(&with_project, &with_delphi);
+
+    let with_target = with_project.join("App.dpr");
+    let with_output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg("--search-path")
+        .arg(&with_project)
+        .arg(&with_target)
+        .arg("--delphi-path")
+        .arg(&with_delphi)
+        .output()
+        .expect("run fixdpr fix-dpr with delphi path");
+
+    assert!(
+        with_output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&with_output.stdout),
+        String::from_utf8_lossy(&with_output.stderr)
+    );
+
+    let with_dpr = normalize_newlines(fs::read_to_string(&with_target).expect("read dpr"));
+    assert!(
+        with_dpr.contains("ExtMid in '..\\delphi\\ExtMid.pas'"),
+        "dpr should include ExtMid via external dependency:\n{with_dpr}"
+    );
+    assert!(
+        with_dpr.contains("NewUnit in '..\\delphi\\NewUnit.pas'"),
+        "dpr should include NewUnit via transitive external dependency:\n{with_dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_rewrites_mismatched_in_paths() {
+    let root = temp_dir("fixdpr_e2e_mismatched_paths_");
+    fs::create_dir_all(root.join("libs")).expect("create libs");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'libs/UnitB.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root.join("libs"),
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--fix-mismatched-paths")
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr fix-dpr with --fix-mismatched-paths");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("which declares unit UnitB"), "{stdout}");
+    assert!(stdout.contains("dpr updated: 1"), "{stdout}");
+
+    let dpr = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+    assert!(dpr.contains("UnitA in 'UnitA.pas';"), "{dpr}");
+}
+
+#[test]
+fn end_to_end_timings_report_phases_and_slowest_dprs() {
+    let root = temp_dir("fixdpr_e2e_timings_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--timings")
+        .output()
+        .expect("run fixdpr fix-dpr with --timings");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    let timings = &stdout[stdout.find("\nTimings:\n").expect("timings section")..];
+    for phase in [
+        "  scan: ",
+        "  project cache: ",
+        "  dpr processing: ",
+        "  dpr graphs: ",
+        "  dpr writes: ",
+        "  total: ",
+        "  canonical paths: ",
+    ] {
+        assert!(timings.contains(phase), "{timings}");
+    }
+    assert!(!timings.contains("canonical paths: 0 cached"), "{timings}");
+    assert!(timings.contains("Slowest dprs (1):\n  "), "{timings}");
+    assert!(timings.trim_end().ends_with(" ms  App.dpr"), "{timings}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .output()
+        .expect("run fixdpr fix-dpr without --timings");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("Timings:"), "{stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("who-uses")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--format")
+        .arg("json")
+        .arg("--timings")
+        .arg("UnitB")
+        .output()
+        .expect("run fixdpr who-uses json with --timings");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains(",\"timings\":{\"phases_ms\":{\"scan\":"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("\"slowest_dprs\":[],\"canonical_paths\":{\"hits\":"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_explains_truncated_uses_list() {
+    let root = temp_dir("fixdpr_e2e_truncated_uses_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA,\n  UnitB in 'UnitB.pas'\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr fix-dpr on a truncated dpr");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(
+            "gave up at byte 29 near \"UnitB in 'UnitB.pas'\\n\" (50 bytes, UTF-8) (App.dpr:4:3)"
+        ),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_recovers_from_unterminated_comment_in_unit() {
+    let root = temp_dir("fixdpr_e2e_unterminated_comment_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "{ header without end\nunit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr fix-dpr with an unterminated comment");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("is never closed; resuming at the next line (UnitA.pas:1:1)"),
+        "{stdout}"
+    );
+    let dpr = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+    assert!(dpr.contains("UnitB in 'UnitB.pas';"), "{dpr}");
+}
+
+#[test]
+fn end_to_end_fix_dpr_skips_binary_and_oversized_units() {
+    let root = temp_dir("fixdpr_e2e_file_guards_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        &format!(
+            "unit UnitB;\ninterface\nimplementation\n{{{}}}\nend.\n",
+            "x".repeat(2048)
+        ),
+    );
+    fs::write(root.join("Trace.pas"), b"MZ\0\0binary").expect("write binary file");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--max-file-size")
+        .arg("1KB")
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr fix-dpr with --max-file-size");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("above --max-file-size 1024)"), "{stdout}");
+    assert!(
+        stdout.contains("Trace.pas (NUL byte in the first 8192 bytes)"),
+        "{stdout}"
+    );
+    let dpr = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+    assert!(!dpr.contains("UnitB"), "{dpr}");
+}
+
+#[test]
+fn end_to_end_fix_dpr_no_stem_fallback_skips_headerless_units() {
+    let root = temp_dir("fixdpr_e2e_stem_fallback_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses Helper;\nimplementation\nend.\n",
+    );
+    write_file(&root, "Helper.pas", "const HelperValue = 1;\n");
+    let dpr_before = fs::read_to_string(root.join("App.dpr")).expect("read dpr");
+
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("fix-dpr")
+            .arg(root.join("App.dpr"))
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--show-details")
+            .args(extra)
+            .output()
+            .expect("run fixdpr fix-dpr")
+    };
+
+    let output = run(&["--no-stem-fallback"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("dpr updated: 0"), "{stdout}");
+    assert_eq!(
+        fs::read_to_string(root.join("App.dpr")).expect("read dpr"),
+        dpr_before
+    );
+
+    let output = run(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("inserted: Helper in 'Helper.pas' [project] (name from file stem)"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_who_uses_lists_direct_and_transitive_consumers() {
+    let root = temp_dir("fixdpr_e2e_who_uses_");
+    write_file(
+        &root,
+        "Direct.dpr",
+        "program Direct;\nuses\n  Target in 'Target.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Indirect.dpr",
+        "program Indirect;\nuses\n  Middle in 'Middle.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Target.pas",
+        "unit Target;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Middle.pas",
+        "unit Middle;\ninterface\nuses Target;\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("who-uses")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--transitive")
+        .arg(root.join("Target.pas"))
+        .output()
+        .expect("run fixdpr who-uses");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("Mode: who-uses\nUnit: Target\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Dprs listing Target (1):\n  Direct.dpr\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Dprs reaching Target through other units (1):\n  Indirect.dpr\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Units using Target (1):\n  Middle.pas\n"),
+        "{stdout}"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("who-uses")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--format")
+        .arg("json")
+        .arg("target")
+        .output()
+        .expect("run fixdpr who-uses json");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert_eq!(
+        strip_elapsed(&stdout),
+        "{\"unit\":\"target\",\"direct_dprs\":[\"Direct.dpr\"],\"units\":[\"Middle.pas\"],\"warnings\":[]}\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("who-uses")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("Unused")
+        .output()
+        .expect("run fixdpr who-uses without consumers");
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(
+        stdout.contains("Dprs listing Unused (0):\n  (none)\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_missing_include_is_reported_once_with_its_referencers() {
+    let root = temp_dir("fixdpr_e2e_missing_include_");
+    write_file(
+        &root,
+        "Foo.pas",
+        "unit Foo;\ninterface\nimplementation\nend.\n",
+    );
+    for name in ["A", "B", "C", "D"] {
+        write_file(
+            &root,
+            &format!("{name}.pas"),
+            &format!(
+                "unit {name};\ninterface\nuses {{$I Missing.inc}} Foo;\nimplementation\nend.\n"
+            ),
+        );
+    }
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("who-uses")
+            .arg("Foo")
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--show-warnings")
+            .args(extra)
+            .output()
+            .expect("run fixdpr who-uses");
+        let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+        assert!(output.status.success(), "{stdout}");
+        stdout
+    };
+
+    let stdout = run(&[]);
+    assert!(stdout.contains("Warnings: 4 (1 unique)"), "{stdout}");
+    assert!(
+        stdout.contains(
+            "  warning: include Missing.inc exists nowhere under the search roots (referenced by 4 files: A.pas, B.pas, C.pas and 1 more)\n"
+        ),
+        "{stdout}"
+    );
+
+    // The second run reads the warnings back from the unit cache.
+    let stdout = run(&["--show-all-referencers"]);
+    assert!(
+        stdout.contains("(referenced by 4 files: A.pas, B.pas, C.pas, D.pas)\n"),
+        "{stdout}"
+    );
+
+    let stdout = run(&["--no-dedupe"]);
+    assert!(
+        stdout.contains(
+            "  warning: include Missing.inc exists nowhere under the search roots (referenced by D.pas:3:6)\n"
+        ),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_who_uses_include_lists_files_pulling_in_an_include() {
+    let root = temp_dir("fixdpr_e2e_who_uses_include_");
+    fs::create_dir_all(root.join("inc")).expect("create inc dir");
+    write_file(&root.join("inc"), "Shared.inc", "Foo,");
+    write_file(&root.join("inc"), "Outer.inc", "{$I Shared.inc}");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  {$I inc/Outer.inc}\n  Foo in 'Foo.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Other.dpr",
+        "program Other;\nuses\n  Foo in 'Foo.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Foo.pas",
+        "unit Foo;\ninterface\nuses {$I inc/Shared.inc} SysUtils;\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("who-uses")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--include")
+        .arg(root.join("inc").join("Shared.inc"))
+        .output()
+        .expect("run fixdpr who-uses --include");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("Dprs including inc/Shared.inc (1):\n  App.dpr\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Units including inc/Shared.inc (1):\n  Foo.pas\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Include files including inc/Shared.inc (1):\n  inc/Outer.inc\n"),
+        "{stdout}"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("who-uses")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--format")
+        .arg("json")
+        .arg("--include")
+        .arg(root.join("inc").join("Outer.inc"))
+        .output()
+        .expect("run fixdpr who-uses --include json");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert_eq!(
+        strip_elapsed(&stdout),
+        "{\"include\":\"inc/Outer.inc\",\"dprs\":[\"App.dpr\"],\"units\":[],\"includes\":[],\"warnings\":[]}\n"
+    );
+}
+
+#[test]
+fn end_to_end_dependents_lists_units_reaching_a_unit() {
+    let root = temp_dir("fixdpr_e2e_dependents_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  Outer in 'Outer.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Leaf.pas",
+        "unit Leaf;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Direct.pas",
+        "unit Direct;\ninterface\nuses Leaf;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Outer.pas",
+        "unit Outer;\ninterface\nimplementation\nuses Direct;\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("dependents")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg(root.join("Leaf.pas"))
+        .output()
+        .expect("run fixdpr dependents");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("Mode: dependents\nUnit: Leaf\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "Units depending on Leaf (2):\n  Direct (Direct.pas) [direct]\n  Outer (Outer.pas)\n"
+        ),
+        "{stdout}"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("dependents")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--group-by-dpr")
+        .arg("Leaf")
+        .output()
+        .expect("run fixdpr dependents grouped");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains(
+            "Units depending on Leaf, by dpr:\nApp.dpr (1):\n  Outer (Outer.pas)\nNot listed in any dpr (1):\n  Direct (Direct.pas) [direct]\n"
+        ),
+        "{stdout}"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("dependents")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--direct-only")
+        .arg("--format")
+        .arg("json")
+        .arg("leaf")
+        .output()
+        .expect("run fixdpr dependents json");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert_eq!(
+        strip_elapsed(&stdout),
+        "{\"unit\":\"leaf\",\"units\":[{\"name\":\"Direct\",\"path\":\"Direct.pas\",\"direct\":true,\"section\":\"interface\",\"dprs\":[]}],\"warnings\":[]}\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("dependents")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("Outer")
+        .output()
+        .expect("run fixdpr dependents without dependents");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn end_to_end_impact_lists_dprs_reaching_changed_units() {
+    let root = temp_dir("fixdpr_e2e_impact_");
+    write_file(
+        &root,
+        "Direct.dpr",
+        "program Direct;\nuses\n  Target in 'Target.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Indirect.dpr",
+        "program Indirect;\nuses\n  Middle in 'Middle.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Other.dpr",
+        "program Other;\nuses\n  Other in 'Other.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Target.pas",
+        "unit Target;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Middle.pas",
+        "unit Middle;\ninterface\nuses Target;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Other.pas",
+        "unit Other;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .current_dir(&root)
+        .arg("impact")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("Target.pas")
+        .arg("Ghost")
+        .output()
+        .expect("run fixdpr impact");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    let stderr = normalize_newlines(String::from_utf8_lossy(&output.stderr).into_owned());
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(stdout, "Direct.dpr\nIndirect.dpr\n");
+    assert!(
+        stderr.contains("Affected dprs: 2 of 3 (1 changed unit(s))\nWarnings: 1\n"),
+        "{stderr}"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("impact")
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--no-cache")
+        .arg("--format")
+        .arg("json")
+        .arg("other")
+        .output()
+        .expect("run fixdpr impact json");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert_eq!(
+        strip_elapsed(&stdout),
+        "{\"units\":[\"Other\"],\"dprs\":[\"Other.dpr\"],\"scanned\":3,\"warnings\":[]}\n"
+    );
+}
+
+#[test]
+fn end_to_end_fanout_sorts_dprs_by_closure_size() {
+    let root = temp_dir("fixdpr_e2e_fanout_");
+    let project = root.join("project");
+    let delphi = root.join("delphi");
+    fs::create_dir_all(&project).expect("create project dir");
+    fs::create_dir_all(&delphi).expect("create delphi dir");
+    write_file(
+        &project,
+        "Heavy.dpr",
+        "program Heavy;\nuses\n  Middle in 'Middle.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &project,
+        "Light.dpr",
+        "program Light;\nuses\n  Leaf in 'Leaf.pas',\n  Other in 'Other.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &project,
+        "Middle.pas",
+        "unit Middle;\ninterface\nuses Leaf, SysUtils;\nimplementation\nend.\n",
+    );
+    write_file(
+        &project,
+        "Leaf.pas",
+        "unit Leaf;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &project,
+        "Other.pas",
+        "unit Other;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &delphi,
+        "SysUtils.pas",
+        "unit SysUtils;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fanout")
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--delphi-path")
+        .arg(&delphi)
+        .arg("--no-cache")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .expect("run fixdpr fanout json");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert_eq!(
+        strip_elapsed(&stdout),
+        "{\"dprs\":[\
+         {\"path\":\"Heavy.dpr\",\"direct\":1,\"closure\":3,\"project_units\":2,\"delphi_units\":1},\
+         {\"path\":\"Light.dpr\",\"direct\":2,\"closure\":2,\"project_units\":2,\"delphi_units\":0}\
+         ],\"scanned\":2,\"warnings\":[]}\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fanout")
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--no-cache")
+        .output()
+        .expect("run fixdpr fanout");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains(
+            "Fan-out per dpr (2):\n  closure project  delphi  direct  dpr\n        \
+             2       2       0       1  Heavy.dpr\n        2       2       0       2  Light.dpr\n"
+        ),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_impact_reads_changed_units_from_git() {
+    let root = temp_dir("fixdpr_e2e_impact_git_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  Middle in 'Middle.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Tool.dpr",
+        "program Tool;\nuses\n  Other in 'Other.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Middle.pas",
+        "unit Middle;\ninterface\nuses Target;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Target.pas",
+        "unit Target;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Other.pas",
+        "unit Other;\ninterface\nimplementation\nend.\n",
+    );
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .current_dir(&root)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .expect("run git")
+            .status;
+        assert!(status.success(), "git {args:?}");
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "base"]);
+    write_file(
+        &root,
+        "Target.pas",
+        "unit Target;\ninterface\nconst X = 1;\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .current_dir(&root)
+        .arg("impact")
+        .arg("--search-path")
+        .arg(".")
+        .arg("--no-cache")
+        .arg("--changed-since")
+        .arg("HEAD")
+        .output()
+        .expect("run fixdpr impact --changed-since");
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    let stderr = normalize_newlines(String::from_utf8_lossy(&output.stderr).into_owned());
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(stdout, "App.dpr\n");
+    assert!(
+        stderr.contains("Affected dprs: 1 of 2 (1 changed unit(s))"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_fix_duplicates_keep_resolving() {
+    let fixture_root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("duplicates_repo");
+    let expected_root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("duplicates_expected_keep_resolving");
+    let temp_root = temp_dir("fixdpr_e2e_duplicates_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(temp_root.join("app").join("App.dpr"))
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg("--fix-duplicates")
+        .arg("keep-resolving")
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr fix-dpr with --fix-duplicates");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("removed duplicate unit UnitA with '..\\old\\UnitA.pas'"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("dpr updated: 1"), "{stdout}");
+
+    let actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app").join("App.dpr")).expect("read dpr"),
+    );
+    let expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app").join("App.dpr")).expect("read expected"),
+    );
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn end_to_end_fix_dpr_reports_unresolved_references() {
+    let root = temp_dir("fixdpr_e2e_unresolved_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  Middle in 'Middle.pas',\n  Ghost;\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Middle.pas",
+        "unit Middle;\ninterface\nuses Leaf, SomeTypoUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Leaf.pas",
+        "unit Leaf;\ninterface\nimplementation\nuses sometypounit;\nend.\n",
+    );
+
+    let run = |strict: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .arg("fix-dpr")
+            .arg(root.join("App.dpr"))
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--report-unresolved")
+            .arg("--no-cache");
+        if strict {
+            command.arg("--strict");
+        }
+        command.output().expect("run fixdpr")
+    };
+
+    let output = run(false);
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains(
+            "Unresolved references (2):\n  Ghost:\n    App.dpr\n  SomeTypoUnit:\n    Leaf.pas\n    Middle.pas\n"
+        ),
+        "{stdout}"
+    );
+    let dpr = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+    assert!(dpr.contains("Leaf in 'Leaf.pas'"), "{dpr}");
+
+    let output = run(true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+}
+
+#[test]
+fn end_to_end_fix_dpr_skips_unit_whose_scoped_variant_is_listed() {
+    let root = temp_dir("fixdpr_e2e_scoped_variant_");
+    fs::create_dir_all(root.join("app")).expect("create app dir");
+    fs::create_dir_all(root.join("delphi")).expect("create delphi dir");
+    let original = "program App;\nuses\n  Vcl.Dialogs,\n  Main in 'Main.pas';\nbegin\nend.\n";
+    write_file(&root, "app/App.dpr", original);
+    write_file(
+        &root,
+        "app/Main.pas",
+        "unit Main;\ninterface\nuses Dialogs;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "delphi/Dialogs.pas",
+        "unit Dialogs;\ninterface\nimplementation\nend.\n",
+    );
+
+    let run = |prefixes: Option<&str>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .arg("fix-dpr")
+            .arg(root.join("app").join("App.dpr"))
+            .arg("--search-path")
+            .arg(root.join("app"))
+            .arg("--delphi-path")
+            .arg(root.join("delphi"))
+            .arg("--show-infos")
+            .arg("--no-cache");
+        if let Some(prefixes) = prefixes {
+            command.arg("--namespace-prefix").arg(prefixes);
+        }
+        command.output().expect("run fixdpr")
+    };
+
+    let output = run(None);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("did not insert Dialogs into"), "{stdout}");
+    let dpr = fs::read_to_string(root.join("app").join("App.dpr")).expect("read dpr");
+    assert_eq!(dpr, original);
+
+    let output = run(Some("Fmx,Winapi"));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    let dpr = fs::read_to_string(root.join("app").join("App.dpr")).expect("read dpr");
+    assert!(
+        dpr.contains("Dialogs in '..\\delphi\\Dialogs.pas'"),
+        "{dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_init_writes_seed_closure_into_new_dpr() {
+    let root = temp_dir("fixdpr_e2e_init_");
+    fs::create_dir_all(root.join("src").join("sub")).expect("create src dir");
+    fs::create_dir_all(root.join("app")).expect("create app dir");
+    write_file(
+        &root,
+        "src/MainForm.pas",
+        "unit MainForm;\ninterface\nuses SysUtils, Helper;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "src/sub/Helper.pas",
+        "unit Helper;\ninterface\nimplementation\nuses Dep;\nend.\n",
+    );
+    write_file(
+        &root,
+        "src/sub/Dep.pas",
+        "unit Dep;\ninterface\nimplementation\nend.\n",
+    );
+
+    let run = |force: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .current_dir(&root)
+            .arg("init")
+            .arg(Path::new("app").join("NewApp.dpr"))
+            .arg("--search-path")
+            .arg("src")
+            .arg("--seed")
+            .arg(Path::new("src").join("MainForm.pas"))
+            .arg("--show-warnings")
+            .arg("--no-cache");
+        if force {
+            command.arg("--force");
+        }
+        command.output().expect("run fixdpr init")
+    };
+
+    let output = run(false);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    let dpr = normalize_newlines(
+        fs::read_to_string(root.join("app").join("NewApp.dpr")).expect("read dpr"),
+    );
+    assert_eq!(
+        dpr,
+        "program NewApp;\nuses\n  MainForm in '..\\src\\MainForm.pas',\n  Helper in '..\\src\\sub\\Helper.pas',\n  Dep in '..\\src\\sub\\Dep.pas';\n\nbegin\nend.\n"
+    );
+
+    let output = run(false);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(stdout.contains("pass --force to replace it"), "{stdout}");
+
+    let output = run(true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("dpr unchanged: 1"), "{stdout}");
+}
+
+#[test]
+fn end_to_end_copy_uses_mirrors_template_entries() {
+    let root = temp_dir("fixdpr_e2e_copy_uses_");
+    fs::create_dir_all(root.join("libs")).expect("create libs dir");
+    fs::create_dir_all(root.join("test")).expect("create test dir");
+    write_file(
+        &root,
+        "libs/Core.pas",
+        "unit Core;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "libs/Extra.pas",
+        "unit Extra;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  Core in 'libs\\Core.pas',\n  Extra in 'libs\\Extra.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "test/AppTest.dpr",
+        "program AppTest;\nuses\n  Core in '..\\libs\\Core.pas',\n  Old in 'Old.pas',\n  TestRunner in 'TestRunner.pas'; {fixdpr:pin}\nbegin\nend.\n",
+    );
+
+    let run = |sync: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .current_dir(&root)
+            .arg("copy-uses")
+            .arg("--from")
+            .arg("App.dpr")
+            .arg("--to")
+            .arg(Path::new("test").join("AppTest.dpr"))
+            .arg("--show-infos");
+        if sync {
+            command.arg("--sync");
+        }
+        command.output().expect("run fixdpr copy-uses")
+    };
+
+    let output = run(false);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("dpr updated: 1"), "{stdout}");
+    let dpr = fs::read_to_string(root.join("test").join("AppTest.dpr")).expect("read dpr");
+    assert_eq!(
+        dpr,
+        "program AppTest;\nuses\n  Core in '..\\libs\\Core.pas',\n  Extra in '..\\libs\\Extra.pas',\n  Old in 'Old.pas',\n  TestRunner in 'TestRunner.pas'; {fixdpr:pin}\nbegin\nend.\n"
+    );
+
+    let output = run(true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("removed unit Old from"), "{stdout}");
+    let dpr = fs::read_to_string(root.join("test").join("AppTest.dpr")).expect("read dpr");
+    assert_eq!(
+        dpr,
+        "program AppTest;\nuses\n  Core in '..\\libs\\Core.pas',\n  Extra in '..\\libs\\Extra.pas',\n  TestRunner in 'TestRunner.pas'; {fixdpr:pin}\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_known_units_are_never_inserted() {
+    let root = temp_dir("fixdpr_e2e_known_units_");
+    let project = root.join("project");
+    let delphi = root.join("delphi");
+    create_delphi_path_fixture(&project, &delphi);
+    write_file(
+        &project,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses System, ExtMid;\nimplementation\nend.\n",
+    );
+    write_file(
+        &delphi,
+        "System.pas",
+        "unit System;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(&root, "known.txt", "# DCU-only units\nNewUnit\n");
+
+    let target = project.join("App.dpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(&target)
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--delphi-path")
+        .arg(&delphi)
+        .arg("--known-units")
+        .arg(root.join("known.txt"))
+        .output()
+        .expect("run fixdpr fix-dpr with known units");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Known units: 3"), "{stdout}");
+
+    let dpr = normalize_newlines(fs::read_to_string(&target).expect("read dpr"));
+    assert!(dpr.contains("ExtMid in "), "{dpr}");
+    assert!(!dpr.contains("System in "), "{dpr}");
+    assert!(!dpr.contains("NewUnit in "), "{dpr}");
+}
+
+#[test]
+fn end_to_end_fix_dpr_no_delphi_inserts_reaches_own_units_through_rtl() {
+    let root = temp_dir("fixdpr_e2e_no_delphi_inserts_");
+    let project = root.join("project");
+    let delphi = root.join("delphi");
+    create_delphi_path_fixture(&project, &delphi);
+    fs::rename(delphi.join("NewUnit.pas"), project.join("NewUnit.pas")).expect("move new unit");
+
+    let target = project.join("App.dpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(&target)
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--delphi-path")
+        .arg(&delphi)
+        .arg("--no-delphi-inserts")
+        .output()
+        .expect("run fixdpr fix-dpr with --no-delphi-inserts");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("delphi inserts suppressed: 1"), "{stdout}");
+    assert!(
+        stdout.contains("units inserted from project: 1\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("units inserted from delphi: 0\n"),
+        "{stdout}"
+    );
+
+    let dpr = normalize_newlines(fs::read_to_string(&target).expect("read dpr"));
+    assert!(dpr.contains("NewUnit in 'NewUnit.pas'"), "{dpr}");
+    assert!(!dpr.contains("ExtMid"), "{dpr}");
+}
+
+#[test]
+fn end_to_end_fix_dpr_delphi_ignore_path_skips_fallback_folders() {
+    let root = temp_dir("fixdpr_e2e_delphi_ignore_");
+    let project = root.join("project");
+    let delphi = root.join("delphi");
+    fs::create_dir_all(&project).expect("create project dir");
+    fs::create_dir_all(delphi.join("demos")).expect("create delphi dir");
+    write_file(
+        &project,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &project,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses DemoUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &delphi.join("demos"),
+        "DemoUnit.pas",
+        "unit DemoUnit;\ninterface\nimplementation\nend.\n",
+    );
+
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("fix-dpr")
+            .arg(project.join("App.dpr"))
+            .arg("--search-path")
+            .arg(&project)
+            .arg("--delphi-path")
+            .arg(&delphi)
+            .arg("--dry-run")
+            .arg("--no-exit-on-changes")
+            .args(extra)
+            .output()
+            .expect("run fixdpr fix-dpr")
+    };
+
+    let output = run(&[]);
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("DemoUnit"), "{stdout}");
+
+    let ignored = delphi.join("demos");
+    let output = run(&["--delphi-ignore-path", ignored.to_str().unwrap()]);
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains(&format!("Delphi ignoring: {}", ignored.display())),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Dry run: no units would be inserted"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_reports_project_and_delphi_inserts() {
+    let root = temp_dir("fixdpr_e2e_insert_sources_");
+    let project = root.join("project");
+    let delphi = root.join("delphi");
+    create_delphi_path_fixture(&project, &delphi);
+    fs::rename(delphi.join("NewUnit.pas"), project.join("NewUnit.pas")).expect("move new unit");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(project.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--delphi-path")
+        .arg(&delphi)
+        .arg("--show-details")
+        .output()
+        .expect("run fixdpr fix-dpr with --show-details");
+
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("  units inserted from project: 1\n  units inserted from delphi: 1\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("    inserted: ExtMid in '..\\delphi\\ExtMid.pas' [delphi]\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("    inserted: NewUnit in 'NewUnit.pas' [project]\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_wrap_at_splits_long_single_line_list() {
+    let root = temp_dir("fixdpr_e2e_wrap_at_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB, UnitC;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitC.pas",
+        "unit UnitC;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--wrap-at")
+        .arg("40")
+        .output()
+        .expect("run fixdpr fix-dpr with --wrap-at");
+    assert!(
+        output.status.success(),
+        "stderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let updated = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+    assert_eq!(
+        updated,
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  UnitB in 'UnitB.pas',\n  UnitC in 'UnitC.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_indent_overrides_inferred_indent() {
+    let root = temp_dir("fixdpr_e2e_indent_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses UnitA in 'UnitA.pas',\n  UnitC in 'UnitC.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitC.pas",
+        "unit UnitC;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--indent")
+        .arg("\\t")
+        .output()
+        .expect("run fixdpr fix-dpr with --indent");
+    assert!(
+        output.status.success(),
+        "stderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let updated = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+    assert_eq!(
+        updated,
+        "program App;\nuses UnitA in 'UnitA.pas',\n  UnitC in 'UnitC.pas',\n\tUnitB in 'UnitB.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_dry_run_exits_4_when_units_are_missing() {
+    let root = temp_dir("fixdpr_e2e_fix_dry_run_");
+    let original = "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n";
+    write_file(&root, "App.dpr", original);
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("fix-dpr")
+            .arg(root.join("App.dpr"))
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--dry-run")
+            .args(extra)
+            .output()
+            .expect("run fixdpr fix-dpr --dry-run")
+    };
+
+    let output = run(&[]);
+    let stdout = normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned());
+    assert_eq!(output.status.code(), Some(4), "{stdout}");
+    assert!(
+        stdout.contains(
+            "Dry run: 1 unit(s) would be inserted; run without --dry-run to apply:\n  App.dpr\n    UnitB in 'UnitB.pas'\n"
+        ),
+        "{stdout}"
+    );
+    assert_eq!(
+        normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr")),
+        original
+    );
+
+    let output = run(&["--no-exit-on-changes"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  UnitB in 'UnitB.pas';\nbegin\nend.\n",
+    );
+    let output = run(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert!(
+        stdout.contains("Dry run: no units would be inserted"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_jsonl_lists_missing_units_with_their_chain() {
+    let root = temp_dir("fixdpr_e2e_fix_jsonl_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nuses UnitC;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitC.pas",
+        "unit UnitC;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--dry-run")
+        .arg("--format")
+        .arg("jsonl")
+        .output()
+        .expect("run fixdpr fix-dpr --format jsonl");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(4), "{stdout}");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "{stdout}");
+    assert_eq!(
+        lines[0],
+        "{\"dpr\":\"App.dpr\",\"status\":\"would-update\",\"missing\":[{\"unit\":\"UnitB\",\"path\":\"UnitB.pas\",\"in_path\":\"UnitB.pas\",\"source\":\"project\",\"chain\":[\"UnitA\"]},{\"unit\":\"UnitC\",\"path\":\"UnitC.pas\",\"in_path\":\"UnitC.pas\",\"source\":\"project\",\"chain\":[\"UnitA\",\"UnitB\"]}],\"not_inserted\":0,\"missing_entries\":[],\"warnings\":[]}"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("fix-dpr: 2 unit(s) would be inserted across 1 dpr(s), 0 warning(s)"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_max_inserts_reports_a_partial_update() {
+    let root = temp_dir("fixdpr_e2e_fix_max_inserts_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB, UnitC, UnitD;\nimplementation\nend.\n",
+    );
+    for name in ["UnitB", "UnitC", "UnitD"] {
+        write_file(
+            &root,
+            &format!("{name}.pas"),
+            &format!("unit {name};\ninterface\nimplementation\nend.\n"),
+        );
+    }
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("fix-dpr")
+            .arg(root.join("App.dpr"))
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--max-inserts")
+            .arg("1")
+            .args(extra)
+            .output()
+            .expect("run fixdpr fix-dpr --max-inserts")
+    };
+
+    let output = run(&["--partial-is-failure"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(
+        stdout.contains("Partially updated dpr files (1):\n  App.dpr: inserted 1 of 3 missing unit(s), 2 left out"),
+        "{stdout}"
+    );
+    let dpr = fs::read_to_string(root.join("App.dpr")).expect("read dpr");
+    assert!(dpr.contains("UnitB in 'UnitB.pas'"), "{dpr}");
+    assert!(!dpr.contains("UnitC"), "{dpr}");
+
+    let output = run(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert!(
+        stdout.contains("App.dpr: inserted 1 of 2 missing unit(s), 1 left out"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_remove_missing_drops_dead_entries_while_inserting() {
+    let root = temp_dir("fixdpr_e2e_fix_remove_missing_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  Gone in 'old\\Gone.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--remove-missing")
+        .output()
+        .expect("run fixdpr fix-dpr --remove-missing");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert!(
+        stdout.contains(
+            "Missing units referenced by dpr (1):\n  App.dpr:\n    Gone in 'old\\Gone.pas' (removed)"
+        ),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("BrokenUsesPath"), "{stdout}");
+    assert_eq!(
+        fs::read_to_string(root.join("App.dpr")).expect("read dpr"),
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  UnitB in 'UnitB.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_serve_answers_requests_and_reloads_changed_units() {
+    let root = temp_dir("fixdpr_e2e_serve_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("serve")
+        .arg("--search-path")
+        .arg(&root)
+        .current_dir(&root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run fixdpr serve");
+    let mut stdin = child.stdin.take().expect("serve stdin");
+    let mut stdout = BufReader::new(child.stdout.take().expect("serve stdout"));
+    let mut request = |line: &[u8]| {
+        stdin.write_all(line).expect("write request");
+        stdin.write_all(b"\n").expect("write request");
+        stdin.flush().expect("flush request");
+        let mut response = String::new();
+        stdout.read_line(&mut response).expect("read response");
+        response.trim_end().to_string()
+    };
+
+    assert_eq!(
+        request(br#"{"id":1,"method":"missing","dpr":"App.dpr"}"#),
+        r#"{"jsonrpc":"2.0","id":1,"result":{"dpr":"App.dpr","status":"would-update","missing":[{"unit":"UnitB","path":"UnitB.pas","in_path":"UnitB.pas","source":"project","chain":["UnitA"]}],"not_inserted":0,"missing_entries":[],"warnings":[]}}"#
+    );
+    assert_eq!(
+        request(br#"{"id":"w","method":"whoUses","params":{"unit":"UnitB"}}"#),
+        r#"{"jsonrpc":"2.0","id":"w","result":{"unit":"UnitB","direct_dprs":[],"units":["UnitA.pas"],"warnings":[]}}"#
+    );
+
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB, UnitC;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitC.pas",
+        "unit UnitC;\ninterface\nimplementation\nend.\n",
+    );
+    assert_eq!(
+        request(br#"{"id":2,"method":"reload","path":"UnitC.pas"}"#),
+        r#"{"jsonrpc":"2.0","id":2,"result":{"path":"UnitC.pas","status":"added"}}"#
+    );
+    assert_eq!(
+        request(br#"{"id":3,"method":"reload","path":"UnitA.pas"}"#),
+        r#"{"jsonrpc":"2.0","id":3,"result":{"path":"UnitA.pas","status":"reloaded"}}"#
+    );
+    let fixed = request(br#"{"id":4,"method":"fix","dpr":"App.dpr"}"#);
+    assert!(
+        fixed.contains(r#""missing":[{"unit":"UnitB""#) && fixed.contains(r#"{"unit":"UnitC""#),
+        "{fixed}"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("App.dpr")).expect("read dpr"),
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  UnitB in 'UnitB.pas',\n  UnitC in 'UnitC.pas';\nbegin\nend.\n"
+    );
+
+    assert_eq!(
+        request(br#"{"id":5,"method":"rebuild"}"#),
+        r#"{"jsonrpc":"2.0","id":5,"error":{"code":-32601,"message":"unknown method rebuild"}}"#
+    );
+    assert_eq!(
+        request(br#"{"id":6,"method":"fix"}"#),
+        r#"{"jsonrpc":"2.0","id":6,"error":{"code":-32602,"message":"missing parameter dpr"}}"#
+    );
+    assert!(
+        request(b"{not json").starts_with(r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"#)
+    );
+    assert!(request(b"{\"id\":8,\"method\":\"\xff\"}")
+        .starts_with(r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"#));
+    assert!(request("[".repeat(200_000).as_bytes())
+        .starts_with(r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"#));
+    assert_eq!(
+        request(br#"{"id":7,"method":"shutdown"}"#),
+        r#"{"jsonrpc":"2.0","id":7,"result":null}"#
+    );
+    let status = child.wait().expect("wait for serve");
+    assert!(status.success(), "{status:?}");
+}
+
+#[test]
+fn end_to_end_fix_dpr_auto_root_adds_repo_of_target() {
+    let root = temp_dir("fixdpr_e2e_auto_root_");
+    let repo = root.join("repo");
+    let other = root.join("other");
+    fs::create_dir_all(repo.join(".git")).expect("create .git");
+    fs::create_dir_all(repo.join("stray")).expect("create stray");
+    fs::create_dir_all(repo.join("lib")).expect("create lib");
+    fs::create_dir_all(&other).expect("create other");
+    write_file(
+        &repo.join("stray"),
+        "App.dpr",
+        "program App;\nuses\n  UnitA in '..\\lib\\UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &repo.join("lib"),
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &repo.join("lib"),
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+    let target = repo.join("stray").join("App.dpr");
+
+    let run = |auto_root: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .arg("fix-dpr")
+            .arg(&target)
+            .arg("--search-path")
+            .arg(&other)
+            .arg("--show-infos");
+        if auto_root {
+            command.arg("--auto-root");
+        }
+        command.output().expect("run fixdpr fix-dpr")
+    };
+
+    let output = run(false);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("DPR_FILE not found under --search-path"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = run(true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Scanning 2 root(s):"), "{stdout}");
+    assert!(
+        stdout.contains("because DPR_FILE is outside every --search-path"),
+        "{stdout}"
+    );
+    let dpr = normalize_newlines(fs::read_to_string(&target).expect("read dpr"));
+    assert!(dpr.contains("UnitB in '..\\lib\\UnitB.pas'"), "{dpr}");
+}
+
+#[test]
+fn end_to_end_missing_known_units_file_fails() {
+    let root = temp_dir("fixdpr_e2e_known_units_missing_");
+    let project = root.join("project");
+    create_delphi_path_fixture(&project, &root.join("delphi"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(project.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--known-units")
+        .arg(root.join("missing.txt"))
+        .output()
+        .expect("run fixdpr fix-dpr with missing known units file");
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--known-units does not exist"), "{stderr}");
+}
+
+#[test]
+fn end_to_end_fix_dpr_delphi_root_uses_source_folder() {
+    let root = temp_dir("fixdpr_e2e_fix_dpr_delphi_root_");
+    let project = root.join("project");
+    let bds_root = root.join("bds");
+    create_delphi_path_fixture(&project, &bds_root.join("source"));
+
+    let target = project.join("App.dpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg("--search-path")
+        .arg(&project)
+        .arg(&target)
+        .arg("--delphi-root")
+        .arg(&bds_root)
+        .output()
+        .expect("run fixdpr fix-dpr with delphi root");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Delphi fallback roots (1):"), "{stdout}");
+
+    let dpr = normalize_newlines(fs::read_to_string(&target).expect("read dpr"));
+    assert!(
+        dpr.contains("ExtMid in '..\\bds\\source\\ExtMid.pas'"),
+        "dpr should include ExtMid from the delphi root source folder:\n{dpr}"
+    );
+    assert!(
+        dpr.contains("NewUnit in '..\\bds\\source\\NewUnit.pas'"),
+        "dpr should include NewUnit via transitive external dependency:\n{dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_delphi_root_is_source_enables_transitive_external_resolution() {
+    let root = temp_dir("fixdpr_e2e_delphi_root_is_source_");
+    let project = root.join("project");
+    let delphi = root.join("delphi");
+    create_delphi_path_fixture(&project, &delphi);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&project)
+        .arg(delphi.join("NewUnit.pas"))
+        .arg("--delphi-root")
+        .arg(&delphi)
+        .arg("--delphi-root-is-source")
+        .output()
+        .expect("run fixdpr with delphi root as source");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let dpr = normalize_newlines(fs::read_to_string(project.join("App.dpr")).expect("read dpr"));
+    assert!(
+        dpr.contains("NewUnit in '..\\delphi\\NewUnit.pas'"),
+        "dpr should include NewUnit via transitive external dependency:\n{dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_delphi_source_dirs_limit_fallback_scan() {
+    let root = temp_dir("fixdpr_e2e_delphi_source_dirs_");
+    let project = root.join("project");
+    let source = root.join("bds").join("source");
+    create_delphi_path_fixture(&project, &source.join("Rtl"));
+    fs::create_dir_all(source.join("vcl")).expect("create vcl dir");
+    write_file(
+        &source.join("vcl"),
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
+    );
+
+    let target = project.join("App.dpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(&target)
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--delphi-root")
+        .arg(root.join("bds"))
+        .arg("--delphi-source-dirs")
+        .arg("rtl,missing")
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr with delphi source dirs");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Delphi fallback roots (1):"), "{stdout}");
+    assert!(
+        stdout.contains("--delphi-source-dirs entry does not exist"),
+        "{stdout}"
+    );
+
+    let dpr = normalize_newlines(fs::read_to_string(&target).expect("read dpr"));
+    assert!(
+        dpr.contains("NewUnit in '..\\bds\\source\\Rtl\\NewUnit.pas'"),
+        "dpr should only use units from the selected source dir:\n{dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_delphi_root_without_source_folder_fails() {
+    let root = temp_dir("fixdpr_e2e_delphi_root_missing_source_");
+    let project = root.join("project");
+    let delphi = root.join("delphi");
+    create_delphi_path_fixture(&project, &delphi);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg("--search-path")
+        .arg(&project)
+        .arg(project.join("App.dpr"))
+        .arg("--delphi-root")
+        .arg(&delphi)
+        .output()
+        .expect("run fixdpr with delphi root lacking source");
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Delphi source path for --delphi-root")
+            && stderr.contains("does not exist"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn end_to_end_delphi_version_reports_error_for_unknown_version() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_delphi_version_unknown_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let new_dependency = temp_root.join("common").join("NewUnit.pas");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(&new_dependency)
+        .arg("--delphi-version")
+        .arg("9999.9999")
+        .output()
+        .expect("run fixdpr with invalid delphi version");
+
+    assert!(
+        !output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    #[cfg(windows)]
+    assert!(
+        stderr.contains("--delphi-version not found in registry"),
+        "{stderr}"
+    );
+    #[cfg(not(windows))]
+    assert!(
+        stderr.contains("--delphi-version is only supported on Windows"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_delphi_version_reports_error_for_unknown_version() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_fix_dpr_delphi_version_unknown_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let target_dpr = temp_root.join("app1").join("App1.dpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(&target_dpr)
+        .arg("--delphi-version")
+        .arg("9999.9999")
+        .output()
+        .expect("run fixdpr fix-dpr with invalid delphi version");
+
+    assert!(
+        !output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    #[cfg(windows)]
+    assert!(
+        stderr.contains("--delphi-version not found in registry"),
+        "{stderr}"
+    );
+    #[cfg(not(windows))]
+    assert!(
+        stderr.contains("--delphi-version is only supported on Windows"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn end_to_end_adds_introduced_dependencies_by_default() {
+    let root = temp_dir("fixdpr_e2e_introduced_default_");
+    let project_root = root.join("app");
+    let shared_root = root.join("shared");
+    create_introduced_dependency_fixture(&project_root, &shared_root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&root)
+        .arg(shared_root.join("NewUnit.pas"))
+        .output()
+        .expect("run fixdpr with introduced dependencies enabled");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let dpr = normalize_newlines(
+        fs::read_to_string(project_root.join("App.dpr")).expect("read updated dpr"),
+    );
+    assert!(
+        dpr.contains("NewUnit in '..\\shared\\NewUnit.pas'"),
+        "missing NewUnit entry:\n{dpr}"
+    );
+    assert!(
+        dpr.contains("MidUnit in '..\\shared\\MidUnit.pas'"),
+        "missing MidUnit entry:\n{dpr}"
+    );
+    assert!(
+        dpr.contains("BaseUnit in '..\\shared\\BaseUnit.pas'"),
+        "missing BaseUnit entry:\n{dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_output_is_identical_across_runs() {
+    let root = temp_dir("fixdpr_e2e_deterministic_");
+    let write_fixture = || {
+        for app in ["zeta", "alpha", "mid"] {
+            let dir = root.join(app);
+            fs::create_dir_all(&dir).expect("create app dir");
+            write_file(
+                &dir,
+                "App.dpr",
+                "program App;\nuses\n  UnitA in '..\\lib\\UnitA.pas',\n  Gone in 'Gone.pas';\nbegin\nend.\n",
+            );
+        }
+        let lib = root.join("lib");
+        fs::create_dir_all(&lib).expect("create lib dir");
+        write_file(
+            &lib,
+            "UnitA.pas",
+            "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+        );
+        write_file(
+            &lib,
+            "NewUnit.pas",
+            "unit NewUnit;\ninterface\nuses Zed, Able, Mid;\nimplementation\nend.\n",
+        );
+        write_file(
+            &lib,
+            "Mid.pas",
+            "unit Mid;\ninterface\nuses Deep;\nimplementation\nend.\n",
+        );
+        for name in ["Zed", "Able", "Deep"] {
+            write_file(
+                &lib,
+                &format!("{name}.pas"),
+                &format!("unit {name};\ninterface\nimplementation\nend.\n"),
+            );
+        }
+    };
+
+    let run = || {
+        write_fixture();
+        let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg(root.join("lib").join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--show-details")
+            .arg("--show-warnings")
+            .arg("--no-cache")
+            .output()
+            .expect("run fixdpr add-dependency");
+        assert!(
+            output.status.success(),
+            "stderr:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        normalize_newlines(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    let first = run();
+    let second = run();
+    assert_eq!(first, second);
+
+    let alpha = first.find("alpha").expect("alpha dpr in output");
+    let mid = first.find("mid").expect("mid dpr in output");
+    let zeta = first.find("zeta").expect("zeta dpr in output");
+    assert!(alpha < mid && mid < zeta, "{first}");
+    let dpr = normalize_newlines(
+        fs::read_to_string(root.join("alpha").join("App.dpr")).expect("read dpr"),
+    );
+    assert!(
+        dpr.contains("NewUnit in '..\\lib\\NewUnit.pas',\n  Able in '..\\lib\\Able.pas',\n  Mid in '..\\lib\\Mid.pas',\n  Zed in '..\\lib\\Zed.pas',\n  Deep in '..\\lib\\Deep.pas',"),
+        "{dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_disable_introduced_dependencies_flag_restores_single_insert_behavior() {
+    let root = temp_dir("fixdpr_e2e_introduced_disabled_");
+    let project_root = root.join("app");
+    let shared_root = root.join("shared");
+    create_introduced_dependency_fixture(&project_root, &shared_root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&root)
+        .arg(shared_root.join("NewUnit.pas"))
+        .arg("--disable-introduced-dependencies")
+        .output()
+        .expect("run fixdpr with introduced dependencies disabled");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let dpr = normalize_newlines(
+        fs::read_to_string(project_root.join("App.dpr")).expect("read updated dpr"),
+    );
+    assert!(
+        dpr.contains("NewUnit in '..\\shared\\NewUnit.pas'"),
+        "missing NewUnit entry:\n{dpr}"
+    );
+    assert!(
+        !dpr.contains("MidUnit in '..\\shared\\MidUnit.pas'"),
+        "MidUnit should not be inserted when disabled:\n{dpr}"
+    );
+    assert!(
+        !dpr.contains("BaseUnit in '..\\shared\\BaseUnit.pas'"),
+        "BaseUnit should not be inserted when disabled:\n{dpr}"
+    );
+}
+
+#[test]
+fn end_to_end_introduced_scope_project_only_skips_delphi_units() {
+    let root = temp_dir("fixdpr_e2e_introduced_project_only_");
+    let project_root = root.join("app");
+    let shared_root = root.join("shared");
+    let delphi_root = root.join("delphi");
+    create_introduced_dependency_fixture(&project_root, &shared_root);
+    fs::create_dir_all(&delphi_root).expect("create delphi root");
+    fs::write(
+        shared_root.join("NewUnit.pas"),
+        "unit NewUnit;\ninterface\nuses MidUnit, RtlUnit;\nimplementation\nend.\n",
+    )
+    .expect("write NewUnit.pas");
+    fs::write(
+        delphi_root.join("RtlUnit.pas"),
+        "unit RtlUnit;\ninterface\nimplementation\nend.\n",
+    )
+    .expect("write RtlUnit.pas");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&project_root)
+        .arg("--search-path")
+        .arg(&shared_root)
+        .arg("--delphi-path")
+        .arg(&delphi_root)
+        .arg(shared_root.join("NewUnit.pas"))
+        .arg("--introduced-scope")
+        .arg("project-only")
+        .output()
+        .expect("run fixdpr with project-only introduced dependencies");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let dpr = normalize_newlines(
+        fs::read_to_string(project_root.join("App.dpr")).expect("read updated dpr"),
+    );
+    assert_eq!(
+        dpr,
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  NewUnit in '..\\shared\\NewUnit.pas',\n  MidUnit in '..\\shared\\MidUnit.pas',\n  BaseUnit in '..\\shared\\BaseUnit.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_can_run_fix_dpr_on_updated_files() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let expected_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_expected");
+    let temp_root = temp_dir("fixdpr_e2e_add_then_fix_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let new_dependency = temp_root.join("common").join("NewUnit.pas");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(&new_dependency)
+        .arg("--ignore-path")
+        .arg(temp_root.join("ignored"))
+        .arg("--fix-updated-dprs")
+        .output()
+        .expect("run fixdpr add-dependency with follow-up fix mode");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Running fix-dpr pass on updated dpr files"),
+        "{stdout}"
+    );
+
+    let app1 = normalize_newlines(
+        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1"),
+    );
+    assert!(
+        app1.contains("UnitA in 'UnitA.pas'"),
+        "follow-up fix pass should add UnitA to app1:\n{app1}"
+    );
+    assert!(
+        app1.contains("NewUnit in '..\\common\\NewUnit.pas'"),
+        "app1 should still contain new dependency:\n{app1}"
+    );
+
+    let app2_actual = normalize_newlines(
+        fs::read_to_string(temp_root.join("app2").join("App2.dpr")).expect("read app2 actual"),
+    );
+    let app2_expected = normalize_newlines(
+        fs::read_to_string(expected_root.join("app2").join("App2.dpr"))
+            .expect("read app2 expected"),
+    );
+    assert_eq!(app2_actual, app2_expected, "app2 should remain unchanged");
+}
+
+#[test]
+fn end_to_end_fix_dpr_repairs_missing_chain_for_target_file() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_fix_dpr_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let target_dpr = temp_root.join("app1").join("App1.dpr");
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg(&target_dpr)
+        .arg("--ignore-path")
+        .arg(temp_root.join("ignored"))
+        .output()
+        .expect("run fixdpr fix-dpr mode");
+
+    assert!(
+        output.status.success(),
+        "stdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("dpr scanned: 1"), "{stdout}");
+
+    let app1 = normalize_newlines(
+        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read updated app1"),
+    );
+    assert!(app1.contains("UnitA in 'UnitA.pas'"), "{app1}");
+    assert!(
+        app1.contains("NewUnit in '..\\common\\NewUnit.pas'"),
+        "{app1}"
+    );
+
+    let app2 = normalize_newlines(
+        fs::read_to_string(temp_root.join("app2").join("App2.dpr")).expect("read app2"),
+    );
+    let app2_expected = normalize_newlines(
+        fs::read_to_string(fixture_root.join("app2").join("App2.dpr")).expect("read app2 expected"),
+    );
+    assert_eq!(
+        app2, app2_expected,
+        "non-target dpr should remain unchanged"
+    );
+}
+
+#[test]
+fn end_to_end_fix_dpr_reuses_persistent_unit_cache() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_cache_file_");
+    copy_dir(&fixture_root, &temp_root);
+
+    let target_dpr = temp_root.join("app2").join("App2.dpr");
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("fix-dpr")
+            .arg("--search-path")
+            .arg(&temp_root)
+            .arg(&target_dpr)
+            .args(extra)
+            .output()
+            .expect("run fixdpr fix-dpr mode");
+        assert!(
+            output.status.success(),
+            "stdout:\n{}\nstderr:\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let first = run(&[]);
+    let cache_path = temp_root.join(".fixdpr-cache");
+    assert!(cache_path.is_file(), "{first}");
+    assert!(first.contains("(0 reused, "), "{first}");
+
+    let second = run(&[]);
+    assert!(second.contains(" reused, 0 parsed)"), "{second}");
+
+    fs::remove_file(&cache_path).expect("remove cache file");
+    let uncached = run(&["--no-cache"]);
+    assert!(!uncached.contains("Cache file:"), "{uncached}");
+    assert!(!cache_path.exists());
+}
+
+#[test]
+fn end_to_end_cache_file_reparses_units_whose_include_changed() {
+    let root = temp_dir("fixdpr_e2e_cache_include_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  A in 'A.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "A.pas",
+        "unit A;\ninterface\nuses {$I deps.inc};\nimplementation\nend.\n",
+    );
+    write_file(&root, "deps.inc", "B");
+    write_file(&root, "B.pas", "unit B;\ninterface\nimplementation\nend.\n");
+    write_file(&root, "C.pas", "unit C;\ninterface\nimplementation\nend.\n");
+
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("fix-dpr")
+            .arg("--search-path")
+            .arg(&root)
+            .arg(root.join("App.dpr"))
+            .output()
+            .expect("run fixdpr fix-dpr mode");
+        assert!(
+            output.status.success(),
+            "stdout:\n{}\nstderr:\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let first = run();
+    assert!(first.contains("(0 reused, 3 parsed)"), "{first}");
+
+    write_file(&root, "deps.inc", "C");
+    let second = run();
+    assert!(second.contains("(2 reused, 1 parsed)"), "{second}");
+    assert_eq!(
+        fs::read_to_string(root.join("App.dpr")).expect("read dpr"),
+        "program App;\nuses\n  A in 'A.pas',\n  B in 'B.pas',\n  C in 'C.pas';\nbegin\nend.\n"
+    );
+
+    let third = run();
+    assert!(third.contains("(3 reused, 0 parsed)"), "{third}");
+}
+
+#[test]
+fn end_to_end_read_only_runs_leave_no_default_cache_file() {
+    let root = temp_dir("fixdpr_e2e_read_only_cache_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  A in 'A.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "A.pas",
+        "unit A;\ninterface\nuses B;\nimplementation\nend.\n",
+    );
+    write_file(&root, "B.pas", "unit B;\ninterface\nimplementation\nend.\n");
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .args(args)
+            .arg("--search-path")
+            .arg(&root)
+            .current_dir(&root)
+            .output()
+            .expect("run fixdpr");
+        assert!(
+            output
+                .status
+                .code()
+                .is_some_and(|code| code == 0 || code == 4),
+            "stdout:\n{}\nstderr:\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    run(&["who-uses", "B"]);
+    run(&["dependents", "B"]);
+    run(&["fanout"]);
+    run(&["impact", "B.pas"]);
+    run(&["fix-dpr", "App.dpr", "--dry-run"]);
+    run(&["fix-dpr", "App.dpr", "--emit-patch", "fix.patch"]);
+    assert!(!root.join(".fixdpr-cache").exists());
+    assert!(!root.join(".fixdpr-cache.tmp").exists());
+    assert!(!fs::read_to_string(root.join("App.dpr"))
+        .expect("read dpr")
+        .contains("B in"));
+
+    run(&["who-uses", "B", "--cache-file", "units.cache"]);
+    assert!(root.join("units.cache").is_file());
+}
+
+#[test]
+fn end_to_end_cache_subcommand_builds_reports_and_verifies() {
+    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let fixture_root = repo_root
+        .join("tests")
+        .join("fixtures")
+        .join("synthetic_repo");
+    let temp_root = temp_dir("fixdpr_e2e_cache_subcommand_");
+    copy_dir(&fixture_root, &temp_root);
+    let app1_before = fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read");
+
+    let run = |action: &str| {
+        Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("cache")
+            .arg(action)
+            .arg("--search-path")
+            .arg(&temp_root)
+            .arg("--show-warnings")
+            .output()
+            .expect("run fixdpr cache")
+    };
+
+    let build = run("build");
+    assert!(build.status.success());
+    let stdout = String::from_utf8_lossy(&build.stdout);
+    assert!(
+        stdout.contains("Cache ready (10 entries, 0 reused, 10 parsed)"),
+        "{stdout}"
+    );
+    assert_eq!(
+        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read"),
+        app1_before
+    );
+
+    let stats = run("stats");
+    assert!(stats.status.success());
+    let stdout = String::from_utf8_lossy(&stats.stdout);
+    assert!(stdout.contains("entries: 10"), "{stdout}");
+    assert!(stdout.contains("last run parsed: 10"), "{stdout}");
+
+    let verify = run("verify");
+    assert!(verify.status.success());
+    let stdout = String::from_utf8_lossy(&verify.stdout);
+    assert!(stdout.contains("entries checked: 10"), "{stdout}");
+    assert!(stdout.contains("entries drifted: 0"), "{stdout}");
+}
+
+#[test]
+fn end_to_end_add_dependency_append_inserts_before_semicolon() {
+    let root = temp_dir("fixdpr_e2e_append_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  Other in 'Other.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Other.pas",
+        "unit Other;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nuses NewDep;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "NewDep.pas",
+        "unit NewDep;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(root.join("NewUnit.pas"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--append")
+        .output()
+        .expect("run fixdpr add-dependency with --append");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let dpr = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+    assert_eq!(
+        dpr,
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  Other in 'Other.pas',\n  NewUnit in 'NewUnit.pas',\n  NewDep in 'NewDep.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_after_unit_groups_inserted_units() {
+    let root = temp_dir("fixdpr_e2e_after_unit_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  ThirdParty in 'ThirdParty.pas',\n  Other in 'Other.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "ThirdParty.pas",
+        "unit ThirdParty;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Other.pas",
+        "unit Other;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nuses NewDep;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "NewDep.pas",
+        "unit NewDep;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(root.join("NewUnit.pas"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--after-unit")
+        .arg("thirdparty")
+        .output()
+        .expect("run fixdpr add-dependency with --after-unit");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let dpr = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+    assert_eq!(
+        dpr,
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  ThirdParty in 'ThirdParty.pas',\n  NewUnit in 'NewUnit.pas',\n  NewDep in 'NewDep.pas',\n  Other in 'Other.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_keeps_inserts_out_of_conditional_regions() {
+    let root = temp_dir("fixdpr_e2e_guarded_introducer_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  Common in 'Common.pas',\n  {$IFDEF MSWINDOWS}\n  WinUnit in 'WinUnit.pas',\n  {$ENDIF}\n  Other in 'Other.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "Common.pas",
+        "unit Common;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "WinUnit.pas",
+        "unit WinUnit;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Other.pas",
+        "unit Other;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
+    );
+    let original = fs::read_to_string(root.join("App.dpr")).expect("read dpr");
+
+    let run = |insert_in_conditional: bool| {
+        fs::write(root.join("App.dpr"), &original).expect("reset dpr");
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .arg("add-dependency")
+            .arg(root.join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&root)
+            .arg("--show-infos");
+        if insert_in_conditional {
+            command.arg("--insert-in-conditional");
+        }
+        let output = command.output().expect("run fixdpr add-dependency");
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        assert!(
+            output.status.success(),
+            "stdout:\n{stdout}\nstderr:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let dpr = normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"));
+        (stdout, dpr)
+    };
+
+    let (stdout, dpr) = run(false);
+    assert_eq!(
+        dpr,
+        "program App;\nuses\n  Common in 'Common.pas',\n  {$IFDEF MSWINDOWS}\n  WinUnit in 'WinUnit.pas',\n  {$ENDIF}\n  Other in 'Other.pas',\n  NewUnit in 'NewUnit.pas';\nbegin\nend.\n"
+    );
+    assert!(
+        stdout.contains("WinUnit in ") && stdout.contains("is inside a conditional region"),
+        "{stdout}"
+    );
+
+    let (_, dpr) = run(true);
+    assert_eq!(
+        dpr,
+        "program App;\nuses\n  Common in 'Common.pas',\n  {$IFDEF MSWINDOWS}\n  WinUnit in 'WinUnit.pas',\n  NewUnit in 'NewUnit.pas',\n  {$ENDIF}\n  Other in 'Other.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_topo_order_inserts_dependencies_first() {
+    let root = temp_dir("fixdpr_e2e_topo_order_");
+    write_file(
+        &root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nuses Logger;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Logger.pas",
+        "unit Logger;\ninterface\nuses Config;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "Config.pas",
+        "unit Config;\ninterface\nimplementation\nend.\n",
+    );
+    let original = fs::read_to_string(root.join("App.dpr")).expect("read dpr");
+
+    let run = |topo_order: bool| {
+        fs::write(root.join("App.dpr"), &original).expect("reset dpr");
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fixdpr"));
+        command
+            .arg("add-dependency")
+            .arg(root.join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&root);
+        if topo_order {
+            command.arg("--topo-order");
+        }
+        let output = command.output().expect("run fixdpr add-dependency");
+        assert!(
+            output.status.success(),
+            "stderr:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        normalize_newlines(fs::read_to_string(root.join("App.dpr")).expect("read dpr"))
+    };
+
+    assert_eq!(
+        run(false),
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  NewUnit in 'NewUnit.pas',\n  Logger in 'Logger.pas',\n  Config in 'Config.pas';\nbegin\nend.\n"
+    );
+    assert_eq!(
+        run(true),
+        "program App;\nuses\n  UnitA in 'UnitA.pas',\n  NewUnit in 'NewUnit.pas',\n  Config in 'Config.pas',\n  Logger in 'Logger.pas';\nbegin\nend.\n"
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_direct_only_skips_transitive_dprs() {
+    let root = temp_dir("fixdpr_e2e_direct_only_");
+    write_file(
+        &root,
+        "Direct.dpr",
+        "program Direct;\nuses\n  UnitB in 'UnitB.pas';\nbegin\nend.\n",
+    );
+    let chain_dpr = "program Chain;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n";
+    write_file(&root, "Chain.dpr", chain_dpr);
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(root.join("NewUnit.pas"))
+        .arg("--search-path")
+        .arg(&root)
+        .arg("--direct-only")
+        .output()
+        .expect("run fixdpr add-dependency with --direct-only");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("dpr updated: 1"), "{stdout}");
+    let direct = normalize_newlines(fs::read_to_string(root.join("Direct.dpr")).expect("read"));
+    assert!(direct.contains("NewUnit in 'NewUnit.pas'"), "{direct}");
+    assert_eq!(
+        fs::read_to_string(root.join("Chain.dpr")).expect("read chain"),
+        chain_dpr
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_fix_pass_follows_dependency_outside_search_root() {
+    let root = temp_dir("fixdpr_e2e_fix_pass_outside_");
+    let project = root.join("project");
+    let shared = root.join("shared");
+    fs::create_dir_all(&project).expect("create project");
+    fs::create_dir_all(&shared).expect("create shared");
+    write_file(
+        &project,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &project,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &project,
+        "ProjHelper.pas",
+        "unit ProjHelper;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &shared,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nuses SharedHelper;\nimplementation\nuses ProjHelper;\nend.\n",
+    );
+    write_file(
+        &shared,
+        "SharedHelper.pas",
+        "unit SharedHelper;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(shared.join("NewUnit.pas"))
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--disable-introduced-dependencies")
+        .arg("--fix-updated-dprs")
+        .arg("--show-warnings")
+        .output()
+        .expect("run fixdpr add-dependency with --fix-updated-dprs");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        !stdout.contains("resolved outside known unit caches"),
+        "{stdout}"
+    );
+    let dpr = normalize_newlines(fs::read_to_string(project.join("App.dpr")).expect("read dpr"));
+    assert!(
+        dpr.contains("NewUnit in '..\\shared\\NewUnit.pas'"),
+        "{dpr}"
+    );
+    assert!(
+        dpr.contains("SharedHelper in '..\\shared\\SharedHelper.pas'"),
+        "{dpr}"
+    );
+    assert!(dpr.contains("ProjHelper in 'ProjHelper.pas'"), "{dpr}");
+}
+
+#[test]
+fn end_to_end_add_dependency_dpr_glob_limits_updated_dprs() {
+    let root = temp_dir("fixdpr_e2e_dpr_glob_");
+    let billing = root.join("apps").join("billing").join("main");
+    let hr = root.join("apps").join("hr");
+    fs::create_dir_all(&billing).expect("create billing");
+    fs::create_dir_all(&hr).expect("create hr");
+    write_file(
+        &root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
+    );
+    let hr_dpr = "program Hr;\nuses\n  UnitA in '..\\..\\UnitA.pas';\nbegin\nend.\n";
+    write_file(
+        &billing,
+        "Billing.dpr",
+        "program Billing;\nuses\n  UnitA in '..\\..\\..\\UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(&hr, "Hr.dpr", hr_dpr);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .current_dir(&root)
+        .arg("add-dependency")
+        .arg("NewUnit.pas")
+        .arg("--search-path")
+        .arg(".")
+        .arg("--dpr-glob")
+        .arg("apps/billing/**/*.dpr")
+        .arg("--dpr-glob")
+        .arg("apps/payroll/*.dpr")
+        .output()
+        .expect("run fixdpr add-dependency with --dpr-glob");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("dpr ignored: 0"), "{stdout}");
+    assert!(stdout.contains("dpr filtered: 1"), "{stdout}");
+    assert!(stdout.contains("dpr updated: 1"), "{stdout}");
+
+    let billing_dpr =
+        normalize_newlines(fs::read_to_string(billing.join("Billing.dpr")).expect("read billing"));
+    assert!(
+        billing_dpr.contains("NewUnit in '..\\..\\..\\NewUnit.pas'"),
+        "{billing_dpr}"
+    );
+    assert_eq!(
+        fs::read_to_string(hr.join("Hr.dpr")).expect("read hr"),
+        hr_dpr
+    );
+}
+
+#[test]
+fn end_to_end_add_dependency_scans_sibling_tree_of_new_dependency() {
+    let root = temp_dir("fixdpr_e2e_dependency_root_");
+    let project = root.join("project");
+    let shared = root.join("shared").join("src");
+    fs::create_dir_all(&project).expect("create project");
+    fs::create_dir_all(&shared).expect("create shared");
+    write_file(
+        &project,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &project,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses Shared;\nimplementation\nend.\n",
+    );
+    write_file(
+        &shared,
+        "Shared.pas",
+        "unit Shared;\ninterface\nuses SharedHelper;\nimplementation\nend.\n",
+    );
+    write_file(
+        &shared,
+        "SharedHelper.pas",
+        "unit SharedHelper;\ninterface\nimplementation\nend.\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("add-dependency")
+        .arg(shared.join("Shared.pas"))
+        .arg("--search-path")
+        .arg(&project)
+        .arg("--fix-updated-dprs")
+        .arg("--show-infos")
+        .arg("--show-warnings")
         .output()
-        .expect("run fixdpr with invalid delphi version");
+        .expect("run fixdpr add-dependency with a sibling dependency");
 
+    let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(
-        !output.status.success(),
-        "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&output.stdout),
+        output.status.success(),
+        "stdout:\n{stdout}\nstderr:\n{}",
         String::from_utf8_lossy(&output.stderr)
     );
+    assert!(
+        stdout.contains("because NEW_DEPENDENCY is outside every search root (2 .pas)"),
+        "{stdout}"
+    );
+    assert!(
+        !stdout.contains("resolved outside known unit caches"),
+        "{stdout}"
+    );
 
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    #[cfg(windows)]
+    let dpr = normalize_newlines(fs::read_to_string(project.join("App.dpr")).expect("read dpr"));
     assert!(
-        stderr.contains("--delphi-version not found in registry"),
-        "{stderr}"
+        dpr.contains("Shared in '..\\shared\\src\\Shared.pas'"),
+        "{dpr}"
     );
-    #[cfg(not(windows))]
     assert!(
-        stderr.contains("--delphi-version is only supported on Windows"),
-        "{stderr}"
+        dpr.contains("SharedHelper in '..\\shared\\src\\SharedHelper.pas'"),
+        "{dpr}"
     );
 }
 
 #[test]
-fn end_to_end_fix_dpr_delphi_version_reports_error_for_unknown_version() {
+fn end_to_end_add_dependency_skips_dprs_unchanged_since_identical_run() {
     let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let fixture_root = repo_root
         .join("tests")
         .join("fixtures")
         .join("synthetic_repo");
-    let temp_root = temp_dir("fixdpr_e2e_fix_dpr_delphi_version_unknown_");
+    let temp_root = temp_dir("fixdpr_e2e_dpr_hash_cache_");
     copy_dir(&fixture_root, &temp_root);
 
-    let target_dpr = temp_root.join("app1").join("App1.dpr");
-    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("fix-dpr")
-        .arg("--search-path")
-        .arg(&temp_root)
-        .arg(&target_dpr)
-        .arg("--delphi-version")
-        .arg("9999.9999")
-        .output()
-        .expect("run fixdpr fix-dpr with invalid delphi version");
+    let new_dependency = temp_root.join("common").join("NewUnit.pas");
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg("--search-path")
+            .arg(&temp_root)
+            .arg(&new_dependency)
+            .args(extra)
+            .output()
+            .expect("run fixdpr");
+        assert!(
+            output.status.success(),
+            "stdout:\n{}\nstderr:\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
 
-    assert!(
-        !output.status.success(),
-        "stdout:\n{}\nstderr:\n{}",
-        String::from_utf8_lossy(&output.stdout),
-        String::from_utf8_lossy(&output.stderr)
-    );
+    let first = run(&[]);
+    assert!(!first.contains("dpr unchanged (cached)"), "{first}");
 
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    #[cfg(windows)]
-    assert!(
-        stderr.contains("--delphi-version not found in registry"),
-        "{stderr}"
+    let second = run(&[]);
+    assert!(second.contains("dpr unchanged (cached): 5"), "{second}");
+    assert!(second.contains("dpr scanned: 5"), "{second}");
+    assert!(second.contains("dpr updated: 0"), "{second}");
+
+    let uncached = run(&["--no-cache"]);
+    assert!(!uncached.contains("dpr unchanged (cached)"), "{uncached}");
+
+    write_file(
+        &temp_root.join("common"),
+        "Extra.pas",
+        "unit Extra;\ninterface\nimplementation\nend.\n",
     );
-    #[cfg(not(windows))]
+    let after_unit_change = run(&[]);
     assert!(
-        stderr.contains("--delphi-version is only supported on Windows"),
-        "{stderr}"
+        !after_unit_change.contains("dpr unchanged (cached)"),
+        "{after_unit_change}"
     );
 }
 
 #[test]
-fn end_to_end_adds_introduced_dependencies_by_default() {
-    let root = temp_dir("fixdpr_e2e_introduced_default_");
-    let project_root = root.join("app");
-    let shared_root = root.join("shared");
-    create_introduced_dependency_fixture(&project_root, &shared_root);
+fn end_to_end_fix_dpr_uses_sections_interface_skips_implementation_dependencies() {
+    let temp_root = temp_dir("fixdpr_e2e_uses_sections_");
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses UnitB;\nimplementation\nuses UnitC;\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitC.pas",
+        "unit UnitC;\ninterface\nimplementation\nend.\n",
+    );
 
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("add-dependency")
+        .arg("fix-dpr")
+        .arg(temp_root.join("App.dpr"))
         .arg("--search-path")
-        .arg(&root)
-        .arg(shared_root.join("NewUnit.pas"))
+        .arg(&temp_root)
+        .arg("--uses-sections")
+        .arg("interface")
+        .arg("--no-cache")
         .output()
-        .expect("run fixdpr with introduced dependencies enabled");
-
+        .expect("run fixdpr fix-dpr mode");
     assert!(
         output.status.success(),
         "stdout:\n{}\nstderr:\n{}",
         String::from_utf8_lossy(&output.stdout),
         String::from_utf8_lossy(&output.stderr)
     );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Uses sections: interface"), "{stdout}");
 
-    let dpr = normalize_newlines(
-        fs::read_to_string(project_root.join("App.dpr")).expect("read updated dpr"),
+    let app = fs::read_to_string(temp_root.join("App.dpr")).expect("read app");
+    assert!(app.contains("UnitB in 'UnitB.pas'"), "{app}");
+    assert!(!app.contains("UnitC in "), "{app}");
+}
+
+#[test]
+fn end_to_end_add_dependency_closure_interface_skips_implementation_dependencies() {
+    let temp_root = temp_dir("fixdpr_e2e_closure_");
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
     );
-    assert!(
-        dpr.contains("NewUnit in '..\\shared\\NewUnit.pas'"),
-        "missing NewUnit entry:\n{dpr}"
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
     );
-    assert!(
-        dpr.contains("MidUnit in '..\\shared\\MidUnit.pas'"),
-        "missing MidUnit entry:\n{dpr}"
+    write_file(
+        &temp_root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nuses IntfDep;\nimplementation\nuses ImplDep;\nend.\n",
     );
-    assert!(
-        dpr.contains("BaseUnit in '..\\shared\\BaseUnit.pas'"),
-        "missing BaseUnit entry:\n{dpr}"
+    write_file(
+        &temp_root,
+        "IntfDep.pas",
+        "unit IntfDep;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "ImplDep.pas",
+        "unit ImplDep;\ninterface\nimplementation\nend.\n",
     );
-}
-
-#[test]
-fn end_to_end_disable_introduced_dependencies_flag_restores_single_insert_behavior() {
-    let root = temp_dir("fixdpr_e2e_introduced_disabled_");
-    let project_root = root.join("app");
-    let shared_root = root.join("shared");
-    create_introduced_dependency_fixture(&project_root, &shared_root);
 
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
         .arg("add-dependency")
+        .arg(temp_root.join("NewUnit.pas"))
         .arg("--search-path")
-        .arg(&root)
-        .arg(shared_root.join("NewUnit.pas"))
-        .arg("--disable-introduced-dependencies")
+        .arg(&temp_root)
+        .arg("--closure")
+        .arg("interface")
+        .arg("--no-cache")
         .output()
-        .expect("run fixdpr with introduced dependencies disabled");
-
+        .expect("run fixdpr add-dependency with --closure");
     assert!(
         output.status.success(),
         "stdout:\n{}\nstderr:\n{}",
@@ -857,134 +5069,212 @@ fn end_to_end_disable_introduced_dependencies_flag_restores_single_insert_behavi
         String::from_utf8_lossy(&output.stderr)
     );
 
-    let dpr = normalize_newlines(
-        fs::read_to_string(project_root.join("App.dpr")).expect("read updated dpr"),
+    let app = fs::read_to_string(temp_root.join("App.dpr")).expect("read app");
+    assert!(app.contains("NewUnit in 'NewUnit.pas'"), "{app}");
+    assert!(app.contains("IntfDep in 'IntfDep.pas'"), "{app}");
+    assert!(!app.contains("ImplDep in "), "{app}");
+}
+
+#[test]
+fn end_to_end_add_dependency_groups_and_suppresses_repeated_warnings() {
+    let temp_root = temp_dir("fixdpr_e2e_warning_dedupe_");
+    fs::create_dir_all(temp_root.join("a")).expect("create a");
+    fs::create_dir_all(temp_root.join("b")).expect("create b");
+    let shared = "unit Shared;\ninterface\nimplementation\nend.\n";
+    write_file(&temp_root.join("a"), "Shared.pas", shared);
+    write_file(&temp_root.join("b"), "Shared.pas", shared);
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses Shared, NewUnit;\nimplementation\nend.\n",
     );
-    assert!(
-        dpr.contains("NewUnit in '..\\shared\\NewUnit.pas'"),
-        "missing NewUnit entry:\n{dpr}"
+    write_file(
+        &temp_root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
     );
+
+    let run = |extra: &[&str]| {
+        for index in 1..=3 {
+            write_file(
+                &temp_root,
+                &format!("App{index}.dpr"),
+                &format!("program App{index};\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n"),
+            );
+        }
+        let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+            .arg("add-dependency")
+            .arg(temp_root.join("NewUnit.pas"))
+            .arg("--search-path")
+            .arg(&temp_root)
+            .arg("--show-warnings")
+            .arg("--no-cache")
+            .args(extra)
+            .output()
+            .expect("run fixdpr");
+        assert!(
+            output.status.success(),
+            "stdout:\n{}\nstderr:\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let grouped = run(&[]);
+    assert!(grouped.contains("Warnings: 3 (1 unique)"), "{grouped}");
+    assert_eq!(
+        grouped.matches("ambiguous unit Shared").count(),
+        1,
+        "{grouped}"
+    );
+    assert!(grouped.contains("(3 occurrences)"), "{grouped}");
+
+    let suppressed = run(&["--suppress", "AmbiguousUnit"]);
+    assert!(suppressed.contains("Warnings: 0"), "{suppressed}");
     assert!(
-        !dpr.contains("MidUnit in '..\\shared\\MidUnit.pas'"),
-        "MidUnit should not be inserted when disabled:\n{dpr}"
+        suppressed.contains("Suppressed warnings: 3"),
+        "{suppressed}"
     );
     assert!(
-        !dpr.contains("BaseUnit in '..\\shared\\BaseUnit.pas'"),
-        "BaseUnit should not be inserted when disabled:\n{dpr}"
+        !suppressed.contains("ambiguous unit Shared"),
+        "{suppressed}"
     );
+
+    let every = run(&["--no-dedupe"]);
+    assert!(every.contains("Warnings: 3\n"), "{every}");
+    assert_eq!(every.matches("ambiguous unit Shared").count(), 3, "{every}");
+    assert!(!every.contains("occurrences"), "{every}");
 }
 
 #[test]
-fn end_to_end_add_dependency_can_run_fix_dpr_on_updated_files() {
-    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    let fixture_root = repo_root
-        .join("tests")
-        .join("fixtures")
-        .join("synthetic_repo");
-    let expected_root = repo_root
-        .join("tests")
-        .join("fixtures")
-        .join("synthetic_expected");
-    let temp_root = temp_dir("fixdpr_e2e_add_then_fix_");
-    copy_dir(&fixture_root, &temp_root);
+fn end_to_end_fix_dpr_reports_warning_position_and_relative_paths() {
+    let temp_root = temp_dir("fixdpr_e2e_warning_position_");
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA;\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nimplementation\nend.\n",
+    );
 
-    let new_dependency = temp_root.join("common").join("NewUnit.pas");
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("add-dependency")
+        .arg("fix-dpr")
+        .arg(temp_root.join("App.dpr"))
         .arg("--search-path")
         .arg(&temp_root)
-        .arg(&new_dependency)
-        .arg("--ignore-path")
-        .arg(temp_root.join("ignored"))
-        .arg("--fix-updated-dprs")
+        .arg("--show-warnings")
+        .arg("--no-cache")
         .output()
-        .expect("run fixdpr add-dependency with follow-up fix mode");
-
+        .expect("run fixdpr fix-dpr mode");
     assert!(
         output.status.success(),
         "stdout:\n{}\nstderr:\n{}",
         String::from_utf8_lossy(&output.stdout),
         String::from_utf8_lossy(&output.stderr)
     );
-
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(
-        stdout.contains("Running fix-dpr pass on updated dpr files"),
+        stdout.contains("missing in-path for unit UnitA in App.dpr (resolved via scan)"),
         "{stdout}"
     );
+    assert!(stdout.contains("(App.dpr:3:3)"), "{stdout}");
 
-    let app1 = normalize_newlines(
-        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read app1"),
-    );
-    assert!(
-        app1.contains("UnitA in 'UnitA.pas'"),
-        "follow-up fix pass should add UnitA to app1:\n{app1}"
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA;\nbegin\nend.\n",
     );
+    let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
+        .arg("fix-dpr")
+        .arg(temp_root.join("App.dpr"))
+        .arg("--search-path")
+        .arg(&temp_root)
+        .arg("--show-warnings")
+        .arg("--no-cache")
+        .arg("--absolute-display-paths")
+        .output()
+        .expect("run fixdpr fix-dpr mode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let absolute_dpr = fs::canonicalize(temp_root.join("App.dpr")).expect("canonical dpr");
     assert!(
-        app1.contains("NewUnit in '..\\common\\NewUnit.pas'"),
-        "app1 should still contain new dependency:\n{app1}"
-    );
-
-    let app2_actual = normalize_newlines(
-        fs::read_to_string(temp_root.join("app2").join("App2.dpr")).expect("read app2 actual"),
-    );
-    let app2_expected = normalize_newlines(
-        fs::read_to_string(expected_root.join("app2").join("App2.dpr"))
-            .expect("read app2 expected"),
+        stdout.contains(&format!(
+            "missing in-path for unit UnitA in {}",
+            absolute_dpr.display()
+        )),
+        "{stdout}"
     );
-    assert_eq!(app2_actual, app2_expected, "app2 should remain unchanged");
 }
 
 #[test]
-fn end_to_end_fix_dpr_repairs_missing_chain_for_target_file() {
-    let repo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    let fixture_root = repo_root
-        .join("tests")
-        .join("fixtures")
-        .join("synthetic_repo");
-    let temp_root = temp_dir("fixdpr_e2e_fix_dpr_");
-    copy_dir(&fixture_root, &temp_root);
+fn end_to_end_add_dependency_show_details_lists_each_dpr() {
+    let temp_root = temp_dir("fixdpr_e2e_show_details_");
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "Other.dpr",
+        "program Other;\nuses\n  UnitC in 'UnitC.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "Skipped.dpr",
+        "program Skipped;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitC.pas",
+        "unit UnitC;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
+    );
 
-    let target_dpr = temp_root.join("app1").join("App1.dpr");
     let output = Command::new(env!("CARGO_BIN_EXE_fixdpr"))
-        .arg("fix-dpr")
+        .arg("add-dependency")
+        .arg(temp_root.join("NewUnit.pas"))
         .arg("--search-path")
         .arg(&temp_root)
-        .arg(&target_dpr)
-        .arg("--ignore-path")
-        .arg(temp_root.join("ignored"))
+        .arg("--ignore-dpr")
+        .arg(temp_root.join("Skipped.dpr"))
+        .arg("--no-cache")
+        .arg("--show-details")
         .output()
-        .expect("run fixdpr fix-dpr mode");
-
+        .expect("run fixdpr add-dependency mode");
     assert!(
         output.status.success(),
         "stdout:\n{}\nstderr:\n{}",
         String::from_utf8_lossy(&output.stdout),
         String::from_utf8_lossy(&output.stderr)
     );
-
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("dpr scanned: 1"), "{stdout}");
-
-    let app1 = normalize_newlines(
-        fs::read_to_string(temp_root.join("app1").join("App1.dpr")).expect("read updated app1"),
-    );
-    assert!(app1.contains("UnitA in 'UnitA.pas'"), "{app1}");
+    assert!(stdout.contains("Dpr details (3):"), "{stdout}");
     assert!(
-        app1.contains("NewUnit in '..\\common\\NewUnit.pas'"),
-        "{app1}"
-    );
-
-    let app2 = normalize_newlines(
-        fs::read_to_string(temp_root.join("app2").join("App2.dpr")).expect("read app2"),
+        stdout.contains("  App.dpr: updated (new dependency inserted)\n"),
+        "{stdout}"
     );
-    let app2_expected = normalize_newlines(
-        fs::read_to_string(fixture_root.join("app2").join("App2.dpr")).expect("read app2 expected"),
+    assert!(
+        stdout.contains("    inserted: NewUnit in 'NewUnit.pas' [project]\n"),
+        "{stdout}"
     );
-    assert_eq!(
-        app2, app2_expected,
-        "non-target dpr should remain unchanged"
+    assert!(
+        stdout.contains("  Other.dpr: unchanged (new dependency not needed)\n"),
+        "{stdout}"
     );
+    assert!(stdout.contains("  Skipped.dpr: ignored\n"), "{stdout}");
 }
 
 #[test]
@@ -1366,6 +5656,15 @@ fn normalize_newlines(contents: String) -> String {
     contents.replace("\r\n", "\n")
 }
 
+/// Drops the `,"elapsed_ms":{...}` member, whose values change between runs.
+fn strip_elapsed(json: &str) -> String {
+    let Some(start) = json.find(",\"elapsed_ms\":{") else {
+        panic!("missing elapsed_ms in {json}");
+    };
+    let end = start + json[start..].find('}').expect("elapsed_ms end") + 1;
+    format!("{}{}", &json[..start], &json[end..])
+}
+
 fn create_introduced_dependency_fixture(project_root: &Path, shared_root: &Path) {
     fs::create_dir_all(project_root).expect("create project root");
     fs::create_dir_all(shared_root).expect("create shared root");
@@ -0,0 +1,10 @@
+unit UnitA;
+
+interface
+
+uses
+  KeepUnit;
+
+implementation
+
+end.
@@ -0,0 +1,7 @@
+unit KeepUnit;
+
+interface
+
+implementation
+
+end.
@@ -0,0 +1,7 @@
+unit LeafOnly;
+
+interface
+
+implementation
+
+end.
@@ -0,0 +1,10 @@
+unit OldUnit;
+
+interface
+
+uses
+  LeafOnly;
+
+implementation
+
+end.
@@ -0,0 +1,5 @@
+unit UnitA;
+interface
+uses UnitC;
+implementation
+end.
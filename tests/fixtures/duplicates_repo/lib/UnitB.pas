@@ -0,0 +1,4 @@
+unit UnitB;
+interface
+implementation
+end.
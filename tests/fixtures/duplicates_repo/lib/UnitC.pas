@@ -0,0 +1,4 @@
+unit UnitC;
+interface
+implementation
+end.
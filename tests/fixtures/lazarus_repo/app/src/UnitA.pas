@@ -0,0 +1,12 @@
+unit UnitA;
+
+{$mode objfpc}{$H+}
+
+interface
+
+uses
+  Classes, NewUnit;
+
+implementation
+
+end.
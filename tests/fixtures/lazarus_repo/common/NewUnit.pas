@@ -0,0 +1,12 @@
+unit NewUnit;
+
+{$mode objfpc}{$H+}
+
+interface
+
+uses
+  SysUtils, UnitHelper;
+
+implementation
+
+end.
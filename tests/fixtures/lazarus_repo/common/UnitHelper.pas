@@ -0,0 +1,9 @@
+unit UnitHelper;
+
+{$mode objfpc}{$H+}
+
+interface
+
+implementation
+
+end.
@@ -0,0 +1,7 @@
+unit NewUnit;
+
+interface
+
+implementation
+
+end.
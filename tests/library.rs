@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fixdpr::conditionals::Assumptions;
+use fixdpr::diagnostic::{Diagnostic, DiagnosticCode};
+use fixdpr::dpr_edit::{self, DprDetail, DprStatus};
+use fixdpr::events::{EventSink, ScanProgress};
+use fixdpr::warnings::Warnings;
+use fixdpr::{fs_walk, unit_cache};
+
+#[test]
+fn library_add_dependency_updates_dpr_without_printing() {
+    let temp_root = temp_dir("fixdpr_lib_add_dependency_");
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA in 'UnitA.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nuses NewUnit;\nimplementation\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "NewUnit.pas",
+        "unit NewUnit;\ninterface\nimplementation\nend.\n",
+    );
+
+    let root = fs_walk::canonicalize_root(&temp_root);
+    let scan = fs_walk::scan_files(
+        std::slice::from_ref(&root),
+        &fs_walk::IgnoreMatcher::default(),
+    )
+    .expect("scan roots");
+    assert_eq!(scan.dpr_files.len(), 1);
+    assert_eq!(scan.pas_files.len(), 2);
+
+    let mut warnings = Warnings::new();
+    let mut cache =
+        unit_cache::build_unit_cache(&scan.pas_files, &mut warnings).expect("build unit cache");
+    let new_unit = unit_cache::load_unit_file(&root.join("NewUnit.pas"), &mut warnings)
+        .expect("read new unit")
+        .expect("new unit name");
+    let summary = dpr_edit::update_dpr_files(
+        &scan.dpr_files,
+        &mut cache,
+        None,
+        &new_unit,
+        true,
+        &Assumptions::default(),
+    )
+    .expect("update dpr files");
+
+    assert_eq!(summary.scanned, 1);
+    assert_eq!(summary.updated, 1);
+    assert_eq!(summary.details.len(), 1);
+    assert_eq!(summary.details[0].status, DprStatus::Updated);
+    let contents = fs::read_to_string(root.join("App.dpr")).expect("read dpr");
+    assert!(
+        contents.contains("UnitA in 'UnitA.pas',\n  NewUnit in 'NewUnit.pas';"),
+        "{contents}"
+    );
+}
+
+#[test]
+fn library_fix_dpr_returns_diagnostics_for_caller_to_render() {
+    let temp_root = temp_dir("fixdpr_lib_fix_dpr_");
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA;\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nimplementation\nend.\n",
+    );
+
+    let root = fs_walk::canonicalize_root(&temp_root);
+    let mut warnings = Warnings::new();
+    let cache = unit_cache::build_unit_cache(&[root.join("UnitA.pas")], &mut warnings)
+        .expect("build unit cache");
+    let summary =
+        dpr_edit::fix_dpr_file(&root.join("App.dpr"), &cache, None, &Assumptions::default())
+            .expect("fix dpr");
+
+    assert_eq!(summary.updated, 0);
+    let diagnostic = summary
+        .warnings
+        .iter()
+        .find(|diagnostic| diagnostic.code == DiagnosticCode::MissingInPath)
+        .expect("missing in-path diagnostic");
+    assert_eq!(diagnostic.line_col(), Some((3, 3)));
+}
+
+#[derive(Default)]
+struct RecordingEvents {
+    events: RefCell<Vec<String>>,
+}
+
+impl EventSink for RecordingEvents {
+    fn on_scan_progress(&self, progress: ScanProgress) {
+        if let ScanProgress::WalkFinished {
+            pas_files,
+            dpr_files,
+        } = progress
+        {
+            self.push(format!("walked {pas_files} pas, {dpr_files} dpr"));
+        }
+    }
+
+    fn on_dpr_started(&self, index: usize, total: usize, path: &Path) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.push(format!("started {}/{total} {name}", index + 1));
+    }
+
+    fn on_dpr_finished(&self, detail: &DprDetail) {
+        self.push(format!("finished {}", detail.status.label()));
+    }
+
+    fn on_warning(&self, warning: &Diagnostic) {
+        self.push(format!("warning {}", warning.code.name()));
+    }
+}
+
+impl RecordingEvents {
+    fn push(&self, event: String) {
+        self.events.borrow_mut().push(event);
+    }
+}
+
+#[test]
+fn library_event_sink_receives_scan_and_dpr_events() {
+    let temp_root = temp_dir("fixdpr_lib_events_");
+    write_file(
+        &temp_root,
+        "App.dpr",
+        "program App;\nuses\n  UnitA,\n  UnitB in 'UnitB.pas';\nbegin\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitA.pas",
+        "unit UnitA;\ninterface\nimplementation\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitB.pas",
+        "unit UnitB;\ninterface\nuses UnitC;\nimplementation\nend.\n",
+    );
+    write_file(
+        &temp_root,
+        "UnitC.pas",
+        "unit UnitC;\ninterface\nimplementation\nend.\n",
+    );
+
+    let root = fs_walk::canonicalize_root(&temp_root);
+    let events = Rc::new(RecordingEvents::default());
+    let scan = fs_walk::scan_files_with_events(
+        std::slice::from_ref(&root),
+        &fs_walk::IgnoreMatcher::default(),
+        events.as_ref(),
+    )
+    .expect("scan roots");
+    let mut warnings = Warnings::new();
+    let cache =
+        unit_cache::build_unit_cache(&scan.pas_files, &mut warnings).expect("build unit cache");
+    let options = dpr_edit::FixOptions::builder()
+        .edit(dpr_edit::EditOptions::builder().events(events.clone()))
+        .build();
+    dpr_edit::fix_dpr_file_with(
+        &root.join("App.dpr"),
+        &cache,
+        None,
+        &Assumptions::default(),
+        &options,
+    )
+    .expect("fix dpr");
+
+    assert_eq!(
+        *events.events.borrow(),
+        vec![
+            "walked 3 pas, 1 dpr",
+            "started 1/1 App.dpr",
+            "warning MissingInPath",
+            "finished updated",
+        ]
+    );
+}
+
+fn temp_dir(prefix: &str) -> PathBuf {
+    let mut root = env::temp_dir();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    root.push(format!("{prefix}{nanos}"));
+    fs::create_dir_all(&root).expect("create temp dir");
+    root
+}
+
+fn write_file(root: &Path, name: &str, contents: &str) {
+    let path = root.join(name);
+    fs::write(path, contents).expect("write file");
+}